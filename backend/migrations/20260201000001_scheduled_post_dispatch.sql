@@ -0,0 +1,11 @@
+-- Scheduled post dispatcher
+-- Migration: scheduled_post_dispatch
+
+-- Track when a scheduled post was handled and which post it produced
+ALTER TABLE scheduled_posts ADD COLUMN IF NOT EXISTS processed_at TIMESTAMPTZ;
+ALTER TABLE scheduled_posts ADD COLUMN IF NOT EXISTS post_id UUID REFERENCES posts(id) ON DELETE SET NULL;
+ALTER TABLE scheduled_posts ADD COLUMN IF NOT EXISTS error_code VARCHAR(64);
+
+-- Claimed rows ('processing') are released again if a replica dies mid-dispatch
+CREATE INDEX IF NOT EXISTS idx_scheduled_posts_processing
+    ON scheduled_posts(updated_at) WHERE state = 'processing';
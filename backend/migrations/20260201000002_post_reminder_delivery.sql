@@ -0,0 +1,9 @@
+-- Post reminder delivery
+-- Migration: post_reminder_delivery
+
+-- Delivered reminders are kept for a while so they can be snoozed
+ALTER TABLE post_reminders ADD COLUMN IF NOT EXISTS delivered_at TIMESTAMPTZ;
+ALTER TABLE post_reminders ADD COLUMN IF NOT EXISTS reminder_post_id UUID REFERENCES posts(id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_post_reminders_due
+    ON post_reminders(target_at) WHERE delivered_at IS NULL;
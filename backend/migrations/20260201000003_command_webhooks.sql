@@ -0,0 +1,12 @@
+-- Delayed response URLs handed out to custom slash commands
+CREATE TABLE command_webhooks (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    command_id UUID NOT NULL REFERENCES slash_commands(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    channel_id UUID NOT NULL REFERENCES channels(id) ON DELETE CASCADE,
+    root_id UUID REFERENCES posts(id) ON DELETE SET NULL,
+    use_count INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_command_webhooks_created ON command_webhooks(created_at);
@@ -0,0 +1,18 @@
+-- Email notifications for missed mentions and direct messages
+-- Migration: email_notifications
+
+-- One row per post a user should hear about by email. Rows are kept after
+-- processing so the same post is never emailed twice.
+CREATE TABLE IF NOT EXISTS email_notifications (
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    post_id UUID NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+    channel_id UUID NOT NULL REFERENCES channels(id) ON DELETE CASCADE,
+    reason VARCHAR(16) NOT NULL, -- 'mention', 'direct'
+    status VARCHAR(16) NOT NULL DEFAULT 'pending', -- 'pending', 'sent', 'skipped'
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    processed_at TIMESTAMPTZ,
+    PRIMARY KEY (user_id, post_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_email_notifications_pending
+    ON email_notifications(user_id, created_at) WHERE status = 'pending';
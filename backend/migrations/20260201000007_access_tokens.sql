@@ -0,0 +1,23 @@
+-- Bot tokens and personal access tokens
+-- Migration: access_tokens
+
+-- Store bot tokens as SHA-256 hashes instead of in plain text
+ALTER TABLE bot_tokens ADD COLUMN IF NOT EXISTS token_hash VARCHAR(64);
+UPDATE bot_tokens SET token_hash = encode(sha256(convert_to(token, 'UTF8')), 'hex')
+WHERE token_hash IS NULL;
+ALTER TABLE bot_tokens ALTER COLUMN token_hash SET NOT NULL;
+ALTER TABLE bot_tokens DROP COLUMN IF EXISTS token;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_bot_tokens_token_hash ON bot_tokens(token_hash);
+
+-- Long-lived tokens users create to call the API from scripts
+CREATE TABLE IF NOT EXISTS user_access_tokens (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    description VARCHAR(255),
+    is_active BOOLEAN NOT NULL DEFAULT TRUE,
+    last_used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_access_tokens_user ON user_access_tokens(user_id);
@@ -0,0 +1,52 @@
+-- Permissions enforced across the API
+-- Migration: permissions
+
+INSERT INTO permissions (id, description, category) VALUES
+    ('channel.manage', 'Change channel name, purpose and header', 'channels'),
+    ('post.pin', 'Pin and unpin posts', 'messaging'),
+    ('team.manage_members', 'Add and remove team members', 'teams'),
+    ('command.create', 'Create slash commands', 'integrations'),
+    ('command.manage', 'Manage other users slash commands', 'integrations')
+ON CONFLICT (id) DO NOTHING;
+
+-- team_admin and channel_admin are scheme roles: users hold them through an
+-- admin team or channel membership, on top of their system role. Members keep
+-- what they could already do before these checks were enforced.
+INSERT INTO role_permissions (role, permission_id) VALUES
+    ('system_admin', 'channel.manage'),
+    ('system_admin', 'post.pin'),
+    ('system_admin', 'team.manage_members'),
+    ('system_admin', 'command.create'),
+    ('system_admin', 'command.manage'),
+    ('org_admin', 'channel.manage'),
+    ('org_admin', 'post.pin'),
+    ('org_admin', 'post.delete_others'),
+    ('org_admin', 'team.manage_members'),
+    ('org_admin', 'command.create'),
+    ('org_admin', 'command.manage'),
+    ('team_admin', 'channel.delete'),
+    ('team_admin', 'channel.manage'),
+    ('team_admin', 'post.delete_others'),
+    ('team_admin', 'post.pin'),
+    ('team_admin', 'team.manage'),
+    ('team_admin', 'team.manage_members'),
+    ('team_admin', 'webhook.manage'),
+    ('team_admin', 'command.create'),
+    ('team_admin', 'command.manage'),
+    ('channel_admin', 'channel.delete'),
+    ('channel_admin', 'channel.manage'),
+    ('channel_admin', 'channel.manage_members'),
+    ('member', 'channel.create'),
+    ('member', 'post.pin'),
+    ('member', 'team.create'),
+    ('member', 'webhook.create'),
+    ('member', 'command.create'),
+    ('member', 'bot.create'),
+    ('bot', 'channel.create'),
+    ('bot', 'post.create'),
+    ('bot', 'post.edit_own'),
+    ('bot', 'post.delete_own'),
+    ('bot', 'post.pin'),
+    ('guest', 'post.edit_own'),
+    ('guest', 'post.delete_own')
+ON CONFLICT DO NOTHING;
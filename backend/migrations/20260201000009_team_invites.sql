@@ -0,0 +1,25 @@
+-- Team invitations
+-- Migration: team_invites
+
+-- Shareable invite link ID, regenerated to revoke old links
+ALTER TABLE teams ADD COLUMN IF NOT EXISTS invite_id VARCHAR(32) NOT NULL
+    DEFAULT replace(uuid_generate_v4()::text, '-', '');
+CREATE UNIQUE INDEX IF NOT EXISTS idx_teams_invite_id ON teams(invite_id);
+
+-- Comma separated email domains allowed to join, empty for any
+ALTER TABLE teams ADD COLUMN IF NOT EXISTS allowed_domains TEXT NOT NULL DEFAULT '';
+
+-- Single-use invitations emailed to people who may not have an account yet.
+-- Only the SHA-256 hash of the token is stored.
+CREATE TABLE IF NOT EXISTS team_invitations (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    email VARCHAR(255) NOT NULL,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    invited_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    used_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_team_invitations_team ON team_invitations(team_id);
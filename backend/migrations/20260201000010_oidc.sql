@@ -0,0 +1,40 @@
+-- OpenID Connect login and SSO identity linking
+-- Migration: oidc
+
+-- Which ID token or userinfo claims fill in the local profile
+ALTER TABLE sso_configs ADD COLUMN IF NOT EXISTS username_claim VARCHAR(64) NOT NULL DEFAULT 'preferred_username';
+ALTER TABLE sso_configs ADD COLUMN IF NOT EXISTS display_name_claim VARCHAR(64) NOT NULL DEFAULT 'name';
+ALTER TABLE sso_configs ADD COLUMN IF NOT EXISTS email_claim VARCHAR(64) NOT NULL DEFAULT 'email';
+-- Link first-time SSO logins to the account with the same email when the
+-- provider says the address is verified
+ALTER TABLE sso_configs ADD COLUMN IF NOT EXISTS link_by_email BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- External identities linked to local accounts, one per user and provider
+CREATE TABLE IF NOT EXISTS user_identities (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    sso_config_id UUID NOT NULL REFERENCES sso_configs(id) ON DELETE CASCADE,
+    subject VARCHAR(255) NOT NULL,
+    email VARCHAR(255),
+    last_login_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+
+    CONSTRAINT user_identities_subject_unique UNIQUE (sso_config_id, subject),
+    CONSTRAINT user_identities_user_unique UNIQUE (sso_config_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_user_identities_user ON user_identities(user_id);
+
+-- Logins waiting for the provider's callback. Only the SHA-256 hash of the
+-- state parameter is stored.
+CREATE TABLE IF NOT EXISTS sso_login_states (
+    state_hash VARCHAR(64) PRIMARY KEY,
+    sso_config_id UUID NOT NULL REFERENCES sso_configs(id) ON DELETE CASCADE,
+    nonce VARCHAR(64) NOT NULL,
+    code_verifier VARCHAR(128),
+    redirect_to TEXT,
+    -- Set when a signed-in user is linking an identity to their account
+    link_user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,7 @@
+-- SAML 2.0 service provider
+-- Migration: saml
+
+-- Key and certificate that sign our AuthnRequests and logout messages. The
+-- identity provider's endpoints and certificates come from idp_metadata_url.
+ALTER TABLE sso_configs ADD COLUMN IF NOT EXISTS sp_private_key_encrypted TEXT;
+ALTER TABLE sso_configs ADD COLUMN IF NOT EXISTS sp_certificate TEXT;
@@ -0,0 +1,29 @@
+-- LDAP / Active Directory authentication and directory sync
+-- Migration: ldap
+
+-- Local accounts that sign in against a directory entry. ldap_id holds the
+-- entry's stable id attribute, so renamed or moved entries keep their account.
+CREATE TABLE IF NOT EXISTS ldap_users (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    ldap_id VARCHAR(255) NOT NULL UNIQUE,
+    dn TEXT NOT NULL,
+    last_synced_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- One row per directory sync, reported through the admin API
+CREATE TABLE IF NOT EXISTS ldap_sync_runs (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    status VARCHAR(16) NOT NULL DEFAULT 'running',
+    -- NULL for runs started by the background job
+    triggered_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    users_synced INT NOT NULL DEFAULT 0,
+    users_deactivated INT NOT NULL DEFAULT 0,
+    memberships_added INT NOT NULL DEFAULT 0,
+    memberships_removed INT NOT NULL DEFAULT 0,
+    error TEXT,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_ldap_sync_runs_started ON ldap_sync_runs(started_at DESC);
@@ -0,0 +1,4 @@
+-- API rate limiting settings
+-- Migration: rate_limiting
+
+ALTER TABLE server_config ADD COLUMN IF NOT EXISTS rate_limiting JSONB NOT NULL DEFAULT '{}';
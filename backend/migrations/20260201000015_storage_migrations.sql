@@ -0,0 +1,23 @@
+-- Moving stored files between storage backends
+-- Migration: storage_migrations
+
+-- One row per admin-started migration, reported through the admin API.
+-- updated_at moves with every batch, so an abandoned run can be told apart
+-- from a long one.
+CREATE TABLE IF NOT EXISTS storage_migrations (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    source VARCHAR(32) NOT NULL,
+    target VARCHAR(32) NOT NULL,
+    status VARCHAR(16) NOT NULL DEFAULT 'running',
+    triggered_by UUID REFERENCES users(id) ON DELETE SET NULL,
+    files_total INT NOT NULL DEFAULT 0,
+    files_copied INT NOT NULL DEFAULT 0,
+    files_failed INT NOT NULL DEFAULT 0,
+    error TEXT,
+    started_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    finished_at TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS idx_storage_migrations_started ON storage_migrations(started_at DESC);
+CREATE INDEX IF NOT EXISTS idx_files_backend ON files(backend);
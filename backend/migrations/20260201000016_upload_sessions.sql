@@ -0,0 +1,29 @@
+-- Resumable uploads
+-- Migration: upload_sessions
+
+-- A file being sent over one or more requests. Everything needed to carry
+-- on is saved after each request: the storage backend's multipart upload,
+-- the parts written so far, bytes too few to write as a part yet, and the
+-- SHA-256 state.
+CREATE TABLE IF NOT EXISTS upload_sessions (
+    id UUID PRIMARY KEY DEFAULT uuid_generate_v4(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    channel_id UUID REFERENCES channels(id) ON DELETE CASCADE,
+    filename VARCHAR(255) NOT NULL,
+    mime_type VARCHAR(128) NOT NULL,
+    key VARCHAR(512) NOT NULL UNIQUE,
+    backend VARCHAR(32) NOT NULL,
+    file_size BIGINT NOT NULL,
+    file_offset BIGINT NOT NULL DEFAULT 0,
+    storage_upload_id TEXT,
+    part_tags TEXT[] NOT NULL DEFAULT '{}',
+    pending BYTEA NOT NULL DEFAULT '',
+    hash_state BYTEA NOT NULL,
+    -- Set while a request is appending, so two cannot write at once
+    locked_until TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS idx_upload_sessions_user ON upload_sessions(user_id);
+CREATE INDEX IF NOT EXISTS idx_upload_sessions_updated ON upload_sessions(updated_at);
@@ -0,0 +1,16 @@
+-- Media processing for uploaded files
+-- Migration: media_processing
+
+-- Images and video posters get a large preview next to the thumbnail, and a
+-- tiny JPEG clients blur while the rest loads.
+ALTER TABLE files ADD COLUMN IF NOT EXISTS preview_key VARCHAR(512);
+ALTER TABLE files ADD COLUMN IF NOT EXISTS mini_preview BYTEA;
+
+-- NULL for files with nothing to process, otherwise 'pending',
+-- 'processing', 'processed' or 'failed'. media_claimed_at tells a worker
+-- lost to a restart apart from a slow one.
+ALTER TABLE files ADD COLUMN IF NOT EXISTS media_status VARCHAR(16);
+ALTER TABLE files ADD COLUMN IF NOT EXISTS media_claimed_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_files_media_status ON files(media_status)
+    WHERE media_status IN ('pending', 'processing');
@@ -0,0 +1,16 @@
+-- File search
+-- Migration: file_search
+
+-- Posts only listed their files in posts.file_ids; link the files back so
+-- they can be found by the post and channel they were shared in.
+UPDATE files f SET post_id = p.id, channel_id = p.channel_id
+FROM posts p
+WHERE f.id = ANY(p.file_ids) AND f.post_id IS NULL;
+
+-- Text read from documents, searched together with the file name
+ALTER TABLE files ADD COLUMN IF NOT EXISTS extracted_text TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_files_search ON files USING GIN(
+    to_tsvector('english', translate(name, '._-', '   ') || ' ' || COALESCE(extracted_text, ''))
+);
+CREATE INDEX IF NOT EXISTS idx_files_created_at ON files(created_at DESC);
@@ -0,0 +1,15 @@
+-- Malware scanning of uploaded files
+-- Migration: file_scanning
+
+ALTER TABLE server_config ADD COLUMN IF NOT EXISTS file_scanning JSONB NOT NULL DEFAULT '{}';
+
+-- NULL for files stored while scanning was off, otherwise 'clean',
+-- 'infected' or 'failed'. Infected files are quarantined and files the
+-- scanner could not check are held back the same way; neither is served.
+-- scan_result names the threat found or the scanner's error.
+ALTER TABLE files ADD COLUMN IF NOT EXISTS scan_status VARCHAR(16);
+ALTER TABLE files ADD COLUMN IF NOT EXISTS scan_result TEXT;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS scanned_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS idx_files_scan_status ON files(scan_status)
+    WHERE scan_status IN ('infected', 'failed');
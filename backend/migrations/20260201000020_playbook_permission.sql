@@ -0,0 +1,11 @@
+-- Permission to manage other users' playbooks
+-- Migration: playbook_permission
+
+INSERT INTO permissions (id, description, category) VALUES
+    ('playbook.manage', 'Manage other users playbooks', 'playbooks')
+ON CONFLICT (id) DO NOTHING;
+
+INSERT INTO role_permissions (role, permission_id) VALUES
+    ('system_admin', 'playbook.manage'),
+    ('team_admin', 'playbook.manage')
+ON CONFLICT DO NOTHING;
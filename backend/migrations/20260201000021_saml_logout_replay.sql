@@ -0,0 +1,13 @@
+-- Replay protection for SAML single logout
+-- Migration: saml_logout_replay
+
+-- IDs of logout requests already acted on, kept until the requests would be
+-- too old to accept anyway
+CREATE TABLE IF NOT EXISTS saml_logout_requests (
+    sso_config_id UUID NOT NULL REFERENCES sso_configs(id) ON DELETE CASCADE,
+    request_id TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    PRIMARY KEY (sso_config_id, request_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_saml_logout_requests_expires ON saml_logout_requests(expires_at);
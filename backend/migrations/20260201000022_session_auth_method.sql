@@ -0,0 +1,7 @@
+-- How each session was signed in to
+-- Migration: session_auth_method
+
+-- 'password' or 'sso'. Only sessions started through single sign-on are
+-- exempt from enforced MFA; sessions from before this column count as
+-- password sessions, so SSO users without MFA sign in again through SSO.
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS auth_method VARCHAR(16) NOT NULL DEFAULT 'password';
@@ -0,0 +1,8 @@
+-- File name search
+-- Migration: file_name_search
+
+-- File names are searched for any part of them, which only a trigram index
+-- can answer without reading every file.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS idx_files_name_trgm ON files USING GIN(name gin_trgm_ops);
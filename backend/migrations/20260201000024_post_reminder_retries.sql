@@ -0,0 +1,8 @@
+-- Post reminder retries
+-- Migration: post_reminder_retries
+
+-- Reminders whose delivery failed wait longer after each attempt, and are
+-- given up on after a few, so they cannot hold back the reminders due after
+-- them.
+ALTER TABLE post_reminders ADD COLUMN IF NOT EXISTS attempts INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE post_reminders ADD COLUMN IF NOT EXISTS next_attempt_at TIMESTAMPTZ;
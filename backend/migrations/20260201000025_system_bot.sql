@@ -0,0 +1,24 @@
+-- System bot
+-- Migration: system_bot
+
+-- The system bot is marked rather than found by its email, so no account
+-- registered with that address can become it.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS is_system_bot BOOLEAN NOT NULL DEFAULT FALSE;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_system_bot ON users(is_system_bot) WHERE is_system_bot;
+
+-- A bot made before this migration has no password; an account that only
+-- had the address keeps its own
+UPDATE users SET is_system_bot = TRUE
+WHERE id = (
+    SELECT id FROM users
+    WHERE email = 'system-bot@bot.rustchat.local'
+      AND is_bot AND password_hash = 'BOT_NO_PASSWORD'
+    ORDER BY created_at
+    LIMIT 1
+);
+
+-- Create it now so nobody can take its name
+INSERT INTO users (username, email, password_hash, is_bot, is_system_bot, role, display_name)
+SELECT 'system-bot', 'system-bot@bot.rustchat.local', 'BOT_NO_PASSWORD', TRUE, TRUE, 'member', 'System'
+WHERE NOT EXISTS (SELECT 1 FROM users WHERE is_system_bot)
+ON CONFLICT DO NOTHING;
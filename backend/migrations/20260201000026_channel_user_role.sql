@@ -0,0 +1,18 @@
+-- Channel user role
+-- Migration: channel_user_role
+
+INSERT INTO permissions (id, description, category) VALUES
+    ('channel.manage_roles', 'Change the roles of channel members', 'channels')
+ON CONFLICT (id) DO NOTHING;
+
+-- channel_user is the scheme role of every channel member. As on Mattermost,
+-- and as before permissions were enforced, members may add and remove people
+-- and edit the channel's name, purpose and header, but not change roles.
+INSERT INTO role_permissions (role, permission_id) VALUES
+    ('system_admin', 'channel.manage_roles'),
+    ('org_admin', 'channel.manage_roles'),
+    ('team_admin', 'channel.manage_roles'),
+    ('channel_admin', 'channel.manage_roles'),
+    ('channel_user', 'channel.manage'),
+    ('channel_user', 'channel.manage_members')
+ON CONFLICT DO NOTHING;
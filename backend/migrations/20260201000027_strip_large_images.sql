@@ -0,0 +1,11 @@
+-- Strip large images
+-- Migration: strip_large_images
+
+-- Images over 50 MB used to be left unprocessed and so kept their metadata.
+-- Queue them so the media processing job strips them; they are held back
+-- until it has.
+UPDATE files SET media_status = 'pending'
+WHERE media_status IS NULL
+  AND mime_type IN ('image/jpeg', 'image/jpg', 'image/png', 'image/webp', 'image/gif')
+  AND size > 50 * 1024 * 1024
+  AND (scan_status IS NULL OR scan_status = 'clean');
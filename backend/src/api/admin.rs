@@ -18,18 +18,27 @@ use crate::models::{
     CreateChannel,
     CreateRetentionPolicy,
     CreateSsoConfig,
+    EmailConfig,
+    LdapSyncRun,
     MiroTalkConfig,
     Permission,
     RetentionPolicy,
     ServerConfig,
     ServerConfigResponse,
-    // SiteConfig, AuthConfig, IntegrationsConfig, ComplianceConfig, EmailConfig,
+    SiteConfig,
+    // AuthConfig, IntegrationsConfig, ComplianceConfig,
     SsoConfig,
+    StartStorageMigration,
+    StorageMigration,
     TeamMember,
     TeamMemberResponse,
     UpdateChannel,
+    MASKED_PASSWORD,
 };
+use crate::services::email_templates::{self, Branding};
+use crate::services::guests;
 use crate::services::mirotalk::{MiroTalkClient, MiroTalkStats};
+use crate::services::virus_scan::{self, Clamd};
 use sqlx::FromRow;
 
 /// Build admin routes
@@ -73,6 +82,13 @@ pub fn router() -> Router<AppState> {
             "/admin/users/{id}/reactivate",
             axum::routing::post(reactivate_user),
         )
+        .route("/admin/users/{id}/promote", axum::routing::post(promote_user))
+        .route("/admin/users/{id}/demote", axum::routing::post(demote_user))
+        .route(
+            "/admin/users/{id}/mfa/reset",
+            axum::routing::post(reset_user_mfa),
+        )
+        .route("/admin/users/{id}/unlock", axum::routing::post(unlock_user))
         // Teams & Channels management
         .route("/admin/teams", get(list_admin_teams))
         .route(
@@ -107,6 +123,18 @@ pub fn router() -> Router<AppState> {
             "/admin/integrations/mirotalk/test",
             axum::routing::post(test_mirotalk_connection),
         )
+        // Email
+        .route("/admin/email/test", axum::routing::post(test_email))
+        // LDAP
+        .route(
+            "/admin/ldap/sync",
+            get(list_ldap_sync_runs).post(start_ldap_sync),
+        )
+        // Storage
+        .route(
+            "/admin/storage/migrations",
+            get(list_storage_migrations).post(start_storage_migration),
+        )
 }
 
 /// Check if user is admin
@@ -164,17 +192,19 @@ async fn add_team_member(
     .fetch_one(&state.db)
     .await?;
 
-    // Also add user to all public channels in the team
+    // Also add user to all public channels in the team, except guests
     sqlx::query(
         r#"
         INSERT INTO channel_members (channel_id, user_id)
         SELECT c.id, $1 FROM channels c
-        WHERE c.team_id = $2 AND c.channel_type = 'public'::channel_type
+        WHERE c.team_id = $2 AND c.type = 'public'::channel_type
+          AND NOT EXISTS (SELECT 1 FROM users WHERE id = $1 AND role = $3)
         ON CONFLICT (channel_id, user_id) DO NOTHING
         "#,
     )
     .bind(payload.user_id)
     .bind(id)
+    .bind(guests::GUEST_ROLE)
     .execute(&state.db)
     .await?;
 
@@ -368,21 +398,71 @@ async fn create_sso_config(
         ));
     }
 
-    let scopes = input.scopes.unwrap_or_else(|| {
+    let default_role = input.default_role.unwrap_or_else(|| "member".to_string());
+    if default_role != "member" && default_role != "guest" {
+        return Err(AppError::Validation(
+            "Default role must be 'member' or 'guest'".to_string(),
+        ));
+    }
+
+    let mut scopes = input.scopes.unwrap_or_else(|| {
         vec![
             "openid".to_string(),
             "profile".to_string(),
             "email".to_string(),
         ]
     });
+    if input.provider == "oidc" && !scopes.iter().any(|s| s == "openid") {
+        scopes.insert(0, "openid".to_string());
+    }
+
+    if input.provider == "saml" {
+        if input.idp_metadata_url.as_deref().unwrap_or_default().is_empty() {
+            return Err(AppError::Validation(
+                "SAML providers need an IdP metadata URL".to_string(),
+            ));
+        }
+        if let Some(certificate) = input.sp_certificate.as_deref().filter(|c| !c.is_empty()) {
+            crate::services::xmldsig::certificate_key(certificate)
+                .map_err(|_| AppError::Validation("Invalid SP certificate".to_string()))?;
+        }
+    }
+
+    // A missing secret or key keeps the stored one
+    let client_secret = input
+        .client_secret
+        .filter(|s| !s.is_empty())
+        .map(|s| crate::crypto::encrypt(&s, &state.encryption_key));
+    let sp_private_key = match input.sp_private_key.filter(|k| !k.is_empty()) {
+        Some(pem) => {
+            crate::services::saml::parse_private_key(&pem)?;
+            Some(crate::crypto::encrypt(&pem, &state.encryption_key))
+        }
+        None => None,
+    };
 
     let config: SsoConfig = sqlx::query_as(
         r#"
-        INSERT INTO sso_configs (org_id, provider, display_name, issuer_url, client_id, client_secret_encrypted, scopes)
-        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        INSERT INTO sso_configs (
+            org_id, provider, display_name, issuer_url, client_id, client_secret_encrypted, scopes,
+            auto_provision, default_role, username_claim, display_name_claim, email_claim, link_by_email,
+            is_active, idp_metadata_url, idp_entity_id, sp_private_key_encrypted, sp_certificate
+        )
+        VALUES (
+            $1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, COALESCE($14, FALSE),
+            $15, $16, $17, $18
+        )
         ON CONFLICT (org_id) DO UPDATE SET
-            provider = $2, display_name = $3, issuer_url = $4, 
-            client_id = $5, client_secret_encrypted = $6, scopes = $7
+            provider = $2, display_name = $3, issuer_url = $4,
+            client_id = $5,
+            client_secret_encrypted = COALESCE($6, sso_configs.client_secret_encrypted),
+            scopes = $7, auto_provision = $8, default_role = $9,
+            username_claim = $10, display_name_claim = $11, email_claim = $12,
+            link_by_email = $13, is_active = COALESCE($14, sso_configs.is_active),
+            idp_metadata_url = $15, idp_entity_id = $16,
+            sp_private_key_encrypted = COALESCE($17, sso_configs.sp_private_key_encrypted),
+            sp_certificate = COALESCE($18, sso_configs.sp_certificate),
+            updated_at = NOW()
         RETURNING *
         "#,
     )
@@ -391,8 +471,19 @@ async fn create_sso_config(
     .bind(&input.display_name)
     .bind(&input.issuer_url)
     .bind(&input.client_id)
-    .bind(&input.client_secret) // Should be encrypted in production
+    .bind(&client_secret)
     .bind(&scopes)
+    .bind(input.auto_provision.unwrap_or(true))
+    .bind(&default_role)
+    .bind(input.username_claim.as_deref().unwrap_or("preferred_username"))
+    .bind(input.display_name_claim.as_deref().unwrap_or("name"))
+    .bind(input.email_claim.as_deref().unwrap_or("email"))
+    .bind(input.link_by_email.unwrap_or(false))
+    .bind(input.is_active)
+    .bind(&input.idp_metadata_url)
+    .bind(&input.idp_entity_id)
+    .bind(&sp_private_key)
+    .bind(input.sp_certificate.as_deref().filter(|c| !c.is_empty()))
     .fetch_one(&state.db)
     .await?;
 
@@ -650,6 +741,7 @@ async fn update_role_permissions(
     }
 
     tx.commit().await?;
+    state.permissions.invalidate(&role).await;
 
     Ok(Json(valid_ids))
 }
@@ -701,6 +793,68 @@ async fn get_config(
     Ok(Json(config.into()))
 }
 
+/// Encrypt a newly entered secret before it is stored
+///
+/// The masked placeholder (or a missing value) keeps `stored` and an empty
+/// value clears it.
+fn replace_secret(state: &AppState, entered: Option<&str>, stored: String) -> String {
+    match entered.unwrap_or(MASKED_PASSWORD) {
+        MASKED_PASSWORD => stored,
+        "" => String::new(),
+        secret => crate::crypto::encrypt(secret, &state.encryption_key),
+    }
+}
+
+/// Encrypt a newly entered SMTP password before it is stored
+async fn prepare_email_config(
+    state: &AppState,
+    mut body: serde_json::Value,
+) -> ApiResult<serde_json::Value> {
+    let Some(fields) = body.as_object_mut() else {
+        return Err(AppError::BadRequest("Email config must be an object".to_string()));
+    };
+
+    let current: sqlx::types::Json<EmailConfig> =
+        sqlx::query_scalar("SELECT email FROM server_config WHERE id = 'default'")
+            .fetch_one(&state.db)
+            .await?;
+    let password = fields
+        .get("smtp_password_encrypted")
+        .and_then(|v| v.as_str());
+    let stored = replace_secret(state, password, current.0.smtp_password_encrypted);
+    fields.insert(
+        "smtp_password_encrypted".to_string(),
+        serde_json::Value::String(stored),
+    );
+
+    Ok(body)
+}
+
+/// Encrypt a newly entered LDAP bind password before it is stored
+async fn prepare_auth_config(
+    state: &AppState,
+    mut body: serde_json::Value,
+) -> ApiResult<serde_json::Value> {
+    let Some(fields) = body.as_object_mut() else {
+        return Err(AppError::BadRequest(
+            "Authentication config must be an object".to_string(),
+        ));
+    };
+    let Some(ldap) = fields.get_mut("ldap").and_then(|v| v.as_object_mut()) else {
+        return Ok(body);
+    };
+
+    let current = crate::services::auth_config::get_password_rules(&state.db).await?;
+    let password = ldap.get("bind_password_encrypted").and_then(|v| v.as_str());
+    let stored = replace_secret(state, password, current.ldap.bind_password_encrypted);
+    ldap.insert(
+        "bind_password_encrypted".to_string(),
+        serde_json::Value::String(stored),
+    );
+
+    Ok(body)
+}
+
 async fn update_config(
     State(state): State<AppState>,
     auth: AuthUser,
@@ -715,6 +869,8 @@ async fn update_config(
         "integrations" => "integrations",
         "compliance" => "compliance",
         "email" => "email",
+        "rate_limiting" => "rate_limiting",
+        "file_scanning" => "file_scanning",
         "experimental" => "experimental",
         _ => {
             return Err(AppError::BadRequest(format!(
@@ -729,12 +885,38 @@ async fn update_config(
         column, column
     );
 
-    let result: (sqlx::types::Json<serde_json::Value>,) = sqlx::query_as(&query)
+    let body = match column {
+        "email" => prepare_email_config(&state, body).await?,
+        "authentication" => prepare_auth_config(&state, body).await?,
+        _ => body,
+    };
+
+    let mut result: (sqlx::types::Json<serde_json::Value>,) = sqlx::query_as(&query)
         .bind(sqlx::types::Json(&body))
         .bind(auth.user_id)
         .fetch_one(&state.db)
         .await?;
 
+    match column {
+        "rate_limiting" => state.rate_limiter.invalidate().await,
+        "authentication" => state.mfa_policy.invalidate().await,
+        _ => {}
+    }
+
+    let saved = &mut result.0 .0;
+    let password = match column {
+        "email" => saved.get_mut("smtp_password_encrypted"),
+        "authentication" => saved
+            .get_mut("ldap")
+            .and_then(|ldap| ldap.get_mut("bind_password_encrypted")),
+        _ => None,
+    };
+    if let Some(password) = password {
+        if password.as_str().is_some_and(|p| !p.is_empty()) {
+            *password = serde_json::Value::String(MASKED_PASSWORD.to_string());
+        }
+    }
+
     // Broadcast config update to all connected users
     let event = crate::realtime::events::WsEnvelope::event(
         crate::realtime::events::EventType::ConfigUpdated,
@@ -749,6 +931,103 @@ async fn update_config(
     Ok(Json(result.0 .0))
 }
 
+#[derive(Debug, Deserialize)]
+struct TestEmailRequest {
+    to: String,
+}
+
+/// Send a test email with the saved SMTP settings
+async fn test_email(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(input): Json<TestEmailRequest>,
+) -> ApiResult<Json<serde_json::Value>> {
+    require_admin(&auth)?;
+
+    let to = input.to.trim();
+    if to.is_empty() {
+        return Err(AppError::BadRequest("Recipient address is required".to_string()));
+    }
+
+    let (site, config): (sqlx::types::Json<SiteConfig>, sqlx::types::Json<EmailConfig>) =
+        sqlx::query_as("SELECT site, email FROM server_config WHERE id = 'default'")
+            .fetch_one(&state.db)
+            .await?;
+    let email = email_templates::test_email(&Branding::new(&site, &config));
+    crate::services::email::send_html_email(
+        &state,
+        &config,
+        to,
+        &email.subject,
+        &email.html,
+        &email.text,
+    )
+    .await?;
+
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+// ============ LDAP ============
+
+/// Recent directory syncs, newest first
+async fn list_ldap_sync_runs(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> ApiResult<Json<Vec<LdapSyncRun>>> {
+    require_admin(&auth)?;
+
+    let runs = sqlx::query_as("SELECT * FROM ldap_sync_runs ORDER BY started_at DESC LIMIT 50")
+        .fetch_all(&state.db)
+        .await?;
+
+    Ok(Json(runs))
+}
+
+/// Sync the directory now instead of waiting for the job
+async fn start_ldap_sync(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> ApiResult<Json<LdapSyncRun>> {
+    require_admin(&auth)?;
+
+    let run = crate::jobs::ldap_sync::run_ldap_sync(&state, Some(auth.user_id)).await?;
+
+    Ok(Json(run))
+}
+
+/// Recent storage migrations, newest first
+async fn list_storage_migrations(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> ApiResult<Json<Vec<StorageMigration>>> {
+    require_admin(&auth)?;
+
+    let runs = sqlx::query_as("SELECT * FROM storage_migrations ORDER BY started_at DESC LIMIT 50")
+        .fetch_all(&state.db)
+        .await?;
+
+    Ok(Json(runs))
+}
+
+/// Start copying every file from one storage backend to another
+async fn start_storage_migration(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(input): Json<StartStorageMigration>,
+) -> ApiResult<Json<StorageMigration>> {
+    require_admin(&auth)?;
+
+    let run = crate::jobs::storage_migration::start_storage_migration(
+        &state,
+        &input.source,
+        &input.target,
+        Some(auth.user_id),
+    )
+    .await?;
+
+    Ok(Json(run))
+}
+
 // ============ User Management ============
 
 #[derive(Debug, serde::Deserialize)]
@@ -890,6 +1169,7 @@ async fn deactivate_user(
         .bind(id)
         .execute(&state.db)
         .await?;
+    crate::services::sessions::revoke_all_sessions(&state, id).await?;
 
     Ok(Json(serde_json::json!({"status": "deactivated"})))
 }
@@ -909,6 +1189,52 @@ async fn reactivate_user(
     Ok(Json(serde_json::json!({"status": "reactivated"})))
 }
 
+/// Turn a guest into a regular member
+async fn promote_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<crate::models::User>> {
+    require_admin(&auth)?;
+
+    Ok(Json(guests::set_guest(&state, id, false).await?))
+}
+
+/// Turn a member into a guest
+async fn demote_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<crate::models::User>> {
+    require_admin(&auth)?;
+
+    Ok(Json(guests::set_guest(&state, id, true).await?))
+}
+
+/// Remove a user's MFA enrollment, for users who lost their authenticator
+async fn reset_user_mfa(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    require_admin(&auth)?;
+
+    crate::services::mfa::reset(&state, id).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// Lift a lockout after too many failed logins
+async fn unlock_user(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    require_admin(&auth)?;
+
+    crate::services::login_lockout::unlock_user(&state, id, auth.user_id).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
 // ============ Stats & Health ============
 
 #[derive(Debug, serde::Serialize)]
@@ -966,6 +1292,7 @@ pub struct HealthStatus {
     pub database: DatabaseHealth,
     pub storage: StorageHealth,
     pub websocket: WebSocketHealth,
+    pub file_scanning: FileScanningHealth,
     pub version: String,
     pub uptime_seconds: u64,
 }
@@ -988,6 +1315,16 @@ pub struct WebSocketHealth {
     pub active_connections: u64,
 }
 
+#[derive(Debug, serde::Serialize)]
+pub struct FileScanningHealth {
+    pub enabled: bool,
+    pub connected: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
 async fn get_health(
     State(state): State<AppState>,
     auth: AuthUser,
@@ -999,8 +1336,34 @@ async fn get_health(
     let db_ok = sqlx::query("SELECT 1").execute(&state.db).await.is_ok();
     let db_latency = start.elapsed().as_millis() as u64;
 
+    // Check the malware scanner, if files are scanned
+    let scanning = virus_scan::config(&state.db).await.unwrap_or_default();
+    let file_scanning = if scanning.enable {
+        match Clamd::new(&scanning).version().await {
+            Ok(version) => FileScanningHealth {
+                enabled: true,
+                connected: true,
+                version: Some(version),
+                error: None,
+            },
+            Err(e) => FileScanningHealth {
+                enabled: true,
+                connected: false,
+                version: None,
+                error: Some(e.to_string()),
+            },
+        }
+    } else {
+        FileScanningHealth {
+            enabled: false,
+            connected: false,
+            version: None,
+            error: None,
+        }
+    };
+
     Ok(Json(HealthStatus {
-        status: if db_ok {
+        status: if db_ok && (!file_scanning.enabled || file_scanning.connected) {
             "healthy".to_string()
         } else {
             "degraded".to_string()
@@ -1016,6 +1379,7 @@ async fn get_health(
         websocket: WebSocketHealth {
             active_connections: state.ws_hub.count_connections().await as u64,
         },
+        file_scanning,
         version: env!("CARGO_PKG_VERSION").to_string(),
         uptime_seconds: state.start_time.elapsed().as_secs(),
     }))
@@ -1,15 +1,23 @@
 //! Auth API endpoints
 
 use axum::{
-    extract::State,
-    routing::{get, post},
+    extract::{Path, State},
+    http::HeaderMap,
+    routing::{delete, get, post},
     Json, Router,
 };
+use uuid::Uuid;
 
 use super::AppState;
-use crate::auth::{create_token, hash_password, verify_password, AuthUser};
+use crate::auth::{hash_password, verify_dummy_password, verify_password, AuthUser};
 use crate::error::{ApiResult, AppError};
-use crate::models::{AuthResponse, CreateUser, LoginRequest, User, UserResponse};
+use crate::models::{
+    AuthResponse, CreateUser, LoginRequest, MfaCode, MfaRecoveryCodes, RegisterResponse,
+    ResetPassword, SendAccountEmail, Session, User, UserResponse, VerifyEmail,
+};
+use crate::services::{account_tokens, guests, ldap, login_lockout, mfa, sso, team_invites};
+use crate::services::client_address::ClientIp;
+use crate::services::sessions::{self, SessionMetadata};
 
 /// Build auth routes
 pub fn router() -> Router<AppState> {
@@ -17,22 +25,37 @@ pub fn router() -> Router<AppState> {
         .route("/register", post(register))
         .route("/login", post(login))
         .route("/me", get(me))
+        .route("/logout", post(logout))
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/{id}", delete(revoke_session))
         .route("/policy", get(get_auth_policy))
+        .route("/password/reset/send", post(send_password_reset))
+        .route("/password/reset", post(reset_password))
+        .route("/email/verify/send", post(send_email_verification))
+        .route("/email/verify", post(verify_email))
+        .route("/mfa/generate", post(generate_mfa_secret))
+        .route("/mfa/activate", post(activate_mfa))
+        .route("/mfa/deactivate", post(deactivate_mfa))
+        .route("/mfa/recovery-codes", post(regenerate_recovery_codes))
 }
 
 /// Get current authentication policy
 async fn get_auth_policy(
     State(state): State<AppState>,
 ) -> ApiResult<Json<crate::models::AuthConfig>> {
-    let config = crate::services::auth_config::get_password_rules(&state.db).await?;
+    let mut config = crate::services::auth_config::get_password_rules(&state.db).await?;
+    // Anyone can read the policy, the directory details are for admins
+    config.ldap = crate::models::LdapConfig::default();
     Ok(Json(config))
 }
 
 /// Register a new user
 async fn register(
     State(state): State<AppState>,
-    Json(input): Json<CreateUser>,
-) -> ApiResult<Json<AuthResponse>> {
+    headers: HeaderMap,
+    ClientIp(client_ip): ClientIp,
+    Json(mut input): Json<CreateUser>,
+) -> ApiResult<Json<RegisterResponse>> {
     // Validate input
     if input.username.len() < 3 {
         return Err(AppError::Validation(
@@ -48,6 +71,33 @@ async fn register(
         return Err(AppError::Validation("Invalid email format".to_string()));
     }
 
+    // Password accounts could not sign in
+    if config.require_sso {
+        return Err(AppError::Forbidden(
+            "Sign up with single sign-on instead".to_string(),
+        ));
+    }
+
+    // Invited people may sign up even when open registration is off
+    let invite = match team_invites::Invite::from_params(
+        input.invite_token.take(),
+        input.invite_id.take(),
+    ) {
+        Some(invite) => {
+            let pending = team_invites::resolve(&state.db, &invite).await?;
+            team_invites::ensure_domain_allowed(&pending.team, &input.email)?;
+            Some(pending)
+        }
+        None if !config.allow_registration => {
+            return Err(AppError::Forbidden("Registration is disabled".to_string()));
+        }
+        None => None,
+    };
+    // An emailed invitation already proved the address works
+    let email_verified = invite
+        .as_ref()
+        .is_some_and(|invite| invite.sent_to(&input.email));
+
     // Check if email already exists
     let existing: Option<User> = sqlx::query_as("SELECT * FROM users WHERE email = $1")
         .bind(&input.email)
@@ -74,8 +124,8 @@ async fn register(
     // Insert user
     let user: User = sqlx::query_as(
         r#"
-        INSERT INTO users (username, email, password_hash, display_name, org_id, role)
-        VALUES ($1, $2, $3, $4, $5, 'member')
+        INSERT INTO users (username, email, password_hash, display_name, org_id, role, email_verified)
+        VALUES ($1, $2, $3, $4, $5, 'member', $6)
         RETURNING *
         "#,
     )
@@ -84,45 +134,56 @@ async fn register(
     .bind(&password_hash)
     .bind(&input.display_name)
     .bind(input.org_id)
+    .bind(email_verified)
     .fetch_one(&state.db)
     .await?;
 
-    // Generate token
-    let token = create_token(
-        user.id,
-        &user.email,
-        &user.role,
-        user.org_id,
-        &state.jwt_secret,
-        state.jwt_expiry_hours,
-    )?;
+    if let Some(invite) = invite {
+        team_invites::accept(&state, invite, &user).await?;
+    }
 
-    Ok(Json(AuthResponse {
+    if config.require_email_verification && !user.email_verified {
+        if let Err(e) = account_tokens::send_email_verification(&state, &user).await {
+            tracing::warn!("Failed to send verification email to user {}: {}", user.id, e);
+        }
+        return Ok(Json(RegisterResponse::VerificationRequired {
+            user: UserResponse::from(user),
+            verification_required: true,
+        }));
+    }
+
+    let metadata = SessionMetadata::from_request(&headers, client_ip);
+    let token = sessions::create_session(&state, &user, metadata).await?;
+
+    Ok(Json(RegisterResponse::Authenticated(AuthResponse {
         token,
         token_type: "Bearer".to_string(),
         expires_in: state.jwt_expiry_hours * 3600,
         user: UserResponse::from(user),
-    }))
+    })))
 }
 
 /// Login with email and password
 async fn login(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    ClientIp(client_ip): ClientIp,
     Json(input): Json<LoginRequest>,
 ) -> ApiResult<Json<AuthResponse>> {
-    // Find user by email
-    let user: User = sqlx::query_as("SELECT * FROM users WHERE email = $1 AND is_active = true")
-        .bind(&input.email)
-        .fetch_optional(&state.db)
-        .await?
-        .ok_or_else(|| AppError::Unauthorized("Invalid email or password".to_string()))?;
-
-    // Verify password
-    if !verify_password(&input.password, &user.password_hash)? {
-        return Err(AppError::Unauthorized(
-            "Invalid email or password".to_string(),
-        ));
-    }
+    let metadata = SessionMetadata::from_request(&headers, client_ip);
+    let attempt =
+        login_lockout::LoginAttempt::start(&state, &input.email, metadata.ip_address.as_deref())
+            .await?;
+    let user = match check_credentials(&state, &input).await {
+        Ok(user) => user,
+        Err(e) => {
+            if matches!(e, AppError::Unauthorized(_)) {
+                attempt.failed(&state).await;
+            }
+            return Err(e);
+        }
+    };
+    attempt.succeeded(&state).await;
 
     // Update last login
     sqlx::query("UPDATE users SET last_login_at = NOW() WHERE id = $1")
@@ -130,15 +191,7 @@ async fn login(
         .execute(&state.db)
         .await?;
 
-    // Generate token
-    let token = create_token(
-        user.id,
-        &user.email,
-        &user.role,
-        user.org_id,
-        &state.jwt_secret,
-        state.jwt_expiry_hours,
-    )?;
+    let token = sessions::create_session(&state, &user, metadata).await?;
 
     Ok(Json(AuthResponse {
         token,
@@ -148,6 +201,42 @@ async fn login(
     }))
 }
 
+/// Check the password, and the MFA code if the account needs one
+async fn check_credentials(state: &AppState, input: &LoginRequest) -> ApiResult<User> {
+    let user = match ldap::authenticate(state, &input.email, &input.password).await? {
+        Some(user) => user,
+        None => {
+            // Find user by email
+            let user: Option<User> =
+                sqlx::query_as("SELECT * FROM users WHERE email = $1 AND is_active = true")
+                    .bind(&input.email)
+                    .fetch_optional(&state.db)
+                    .await?;
+            let Some(user) = user else {
+                verify_dummy_password(&input.password);
+                return Err(AppError::Unauthorized(
+                    "Invalid email or password".to_string(),
+                ));
+            };
+
+            // Verify password
+            if !verify_password(&input.password, &user.password_hash)? {
+                return Err(AppError::Unauthorized(
+                    "Invalid email or password".to_string(),
+                ));
+            }
+
+            sso::check_password_login(&state.db, &user).await?;
+            account_tokens::check_email_verified(&state.db, &user).await?;
+            guests::check_guest_login(&state.db, &user).await?;
+            user
+        }
+    };
+    mfa::check_login(state, &user, input.mfa_token.as_deref()).await?;
+
+    Ok(user)
+}
+
 /// Get current authenticated user
 async fn me(State(state): State<AppState>, auth: AuthUser) -> ApiResult<Json<UserResponse>> {
     let user: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
@@ -157,3 +246,108 @@ async fn me(State(state): State<AppState>, auth: AuthUser) -> ApiResult<Json<Use
 
     Ok(Json(UserResponse::from(user)))
 }
+
+/// End the current session
+async fn logout(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> ApiResult<Json<serde_json::Value>> {
+    match sessions::revoke_session(&state, auth.user_id, auth.session_id).await {
+        // Access tokens are not sessions and stay valid until revoked
+        Ok(()) | Err(AppError::NotFound(_)) => {}
+        Err(e) => return Err(e),
+    }
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// List the current user's active sessions
+async fn list_sessions(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> ApiResult<Json<Vec<Session>>> {
+    Ok(Json(sessions::list_sessions(&state, auth.user_id).await?))
+}
+
+/// Revoke one of the current user's sessions
+async fn revoke_session(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    sessions::revoke_session(&state, auth.user_id, id).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// Email a password reset link
+async fn send_password_reset(
+    State(state): State<AppState>,
+    Json(input): Json<SendAccountEmail>,
+) -> ApiResult<Json<serde_json::Value>> {
+    account_tokens::send_password_reset(&state, &input.email).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// Set a new password with a reset token
+async fn reset_password(
+    State(state): State<AppState>,
+    Json(input): Json<ResetPassword>,
+) -> ApiResult<Json<serde_json::Value>> {
+    account_tokens::reset_password(&state, &input.token, &input.new_password).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// Email a new verification link
+async fn send_email_verification(
+    State(state): State<AppState>,
+    Json(input): Json<SendAccountEmail>,
+) -> ApiResult<Json<serde_json::Value>> {
+    account_tokens::resend_email_verification(&state, &input.email).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// Confirm an email address with a verification token
+async fn verify_email(
+    State(state): State<AppState>,
+    Json(input): Json<VerifyEmail>,
+) -> ApiResult<Json<serde_json::Value>> {
+    account_tokens::verify_email(&state, &input.token).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// Start MFA enrollment with a new TOTP secret
+async fn generate_mfa_secret(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> ApiResult<Json<mfa::MfaSecret>> {
+    Ok(Json(mfa::generate_secret(&state, auth.user_id).await?))
+}
+
+/// Turn MFA on with a code from the authenticator
+async fn activate_mfa(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(input): Json<MfaCode>,
+) -> ApiResult<Json<MfaRecoveryCodes>> {
+    let recovery_codes = mfa::activate(&state, auth.user_id, &input.code).await?;
+    Ok(Json(MfaRecoveryCodes { recovery_codes }))
+}
+
+/// Turn MFA off
+async fn deactivate_mfa(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(input): Json<MfaCode>,
+) -> ApiResult<Json<serde_json::Value>> {
+    mfa::deactivate(&state, auth.user_id, &input.code).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// Replace the MFA recovery codes
+async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(input): Json<MfaCode>,
+) -> ApiResult<Json<MfaRecoveryCodes>> {
+    let recovery_codes = mfa::regenerate_recovery_codes(&state, auth.user_id, &input.code).await?;
+    Ok(Json(MfaRecoveryCodes { recovery_codes }))
+}
@@ -13,6 +13,8 @@ use crate::auth::AuthUser;
 use crate::error::{ApiResult, AppError};
 use crate::models::{Channel, ChannelMember, CreateChannel, UpdateChannel};
 use crate::realtime::events::{EventType, WsBroadcast, WsEnvelope};
+use crate::services::guests;
+use crate::services::permissions::{ensure_team_member, Permission, Principal, Scope};
 
 /// Build channels routes
 pub fn router() -> Router<AppState> {
@@ -71,6 +73,11 @@ async fn list_channels(
     let available_to_join = query.available_to_join.unwrap_or(false);
 
     if available_to_join {
+        // Guests cannot join channels on their own
+        if guests::is_guest(&auth.role) {
+            return Ok(Json(Vec::new()));
+        }
+
         // First check if user is a member of the team
         let team_member =
             sqlx::query("SELECT 1 FROM team_members WHERE team_id = $1 AND user_id = $2")
@@ -148,6 +155,8 @@ async fn create_channel(
             AppError::Validation("target_user_id is required for direct messages".to_string())
         })?;
 
+        guests::ensure_can_message(&state.db, auth.user_id, &[target_id]).await?;
+
         // Deterministic name: sorted user IDs
         let mut ids = vec![auth.user_id, target_id];
         ids.sort();
@@ -246,6 +255,8 @@ async fn create_channel(
     if member.is_none() {
         return Err(AppError::Forbidden("Not a member of this team".to_string()));
     }
+    auth.require(&state, Permission::ChannelCreate, Scope::Team(input.team_id))
+        .await?;
 
     // Create channel
     let channel: Channel = sqlx::query_as(
@@ -328,20 +339,8 @@ async fn update_channel(
     Path(id): Path<Uuid>,
     Json(input): Json<UpdateChannel>,
 ) -> ApiResult<Json<Channel>> {
-    // Check admin membership
-    let member: ChannelMember =
-        sqlx::query_as("SELECT * FROM channel_members WHERE channel_id = $1 AND user_id = $2")
-            .bind(id)
-            .bind(auth.user_id)
-            .fetch_optional(&state.db)
-            .await?
-            .ok_or_else(|| AppError::Forbidden("Not a member of this channel".to_string()))?;
-
-    if member.role != "admin" && auth.role != "system_admin" {
-        return Err(AppError::Forbidden(
-            "Not an admin of this channel".to_string(),
-        ));
-    }
+    auth.require(&state, Permission::ChannelManage, Scope::Channel(id))
+        .await?;
 
     // Update fields
     if let Some(ref display_name) = input.display_name {
@@ -380,20 +379,8 @@ async fn archive_channel(
     auth: AuthUser,
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<Channel>> {
-    // Check admin
-    let member: ChannelMember =
-        sqlx::query_as("SELECT * FROM channel_members WHERE channel_id = $1 AND user_id = $2")
-            .bind(id)
-            .bind(auth.user_id)
-            .fetch_optional(&state.db)
-            .await?
-            .ok_or_else(|| AppError::Forbidden("Not a member of this channel".to_string()))?;
-
-    if member.role != "admin" && auth.role != "system_admin" {
-        return Err(AppError::Forbidden(
-            "Not an admin of this channel".to_string(),
-        ));
-    }
+    auth.require(&state, Permission::ChannelDelete, Scope::Channel(id))
+        .await?;
 
     let channel: Channel =
         sqlx::query_as("UPDATE channels SET is_archived = true WHERE id = $1 RETURNING *")
@@ -443,58 +430,52 @@ async fn add_member(
     Json(input): Json<AddMemberRequest>,
 ) -> ApiResult<Json<ChannelMember>> {
     // Check permissions
+    let channel: Channel = sqlx::query_as("SELECT * FROM channels WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Channel not found".to_string()))?;
+    if matches!(
+        channel.channel_type,
+        crate::models::ChannelType::Public | crate::models::ChannelType::Private
+    ) {
+        ensure_team_member(&state, input.user_id, channel.team_id).await?;
+    }
     if auth.user_id == input.user_id {
         // User joining themselves
-        let channel: Channel = sqlx::query_as("SELECT * FROM channels WHERE id = $1")
-            .bind(id)
-            .fetch_one(&state.db)
-            .await?;
-
-        if channel.channel_type != crate::models::ChannelType::Public {
-            let member: ChannelMember = sqlx::query_as(
-                "SELECT * FROM channel_members WHERE channel_id = $1 AND user_id = $2",
-            )
-            .bind(id)
-            .bind(auth.user_id)
-            .fetch_optional(&state.db)
-            .await?
-            .ok_or_else(|| AppError::Forbidden("Not a member of this channel".to_string()))?;
-
-            if member.role != "admin" && auth.role != "system_admin" {
-                return Err(AppError::Forbidden(
-                    "Cannot join private channel without invite".to_string(),
-                ));
-            }
-        }
-        // If public, allow proceed
-    } else {
-        // Adding someone else - require admin
-        let member: ChannelMember =
-            sqlx::query_as("SELECT * FROM channel_members WHERE channel_id = $1 AND user_id = $2")
-                .bind(id)
-                .bind(auth.user_id)
-                .fetch_optional(&state.db)
+        guests::ensure_not_guest(&auth.role)?;
+        if channel.channel_type != crate::models::ChannelType::Public
+            && !auth
+                .can(&state, Permission::ChannelManageMembers, Scope::Channel(id))
                 .await?
-                .ok_or_else(|| AppError::Forbidden("Not a member of this channel".to_string()))?;
-
-        if member.role != "admin" && auth.role != "system_admin" {
+        {
             return Err(AppError::Forbidden(
-                "Not an admin of this channel".to_string(),
+                "Cannot join private channel without invite".to_string(),
             ));
         }
+        // If public, allow proceed
+    } else {
+        auth.require(&state, Permission::ChannelManageMembers, Scope::Channel(id))
+            .await?;
+    }
+    // Only those who manage the channel's roles can set a member's role
+    if input.role.is_some() {
+        auth.require(&state, Permission::ChannelManageRoles, Scope::Channel(id))
+            .await?;
     }
 
     let new_member: ChannelMember = sqlx::query_as(
         r#"
         INSERT INTO channel_members (channel_id, user_id, role)
         VALUES ($1, $2, $3)
-        ON CONFLICT (channel_id, user_id) DO UPDATE SET role = $3
+        ON CONFLICT (channel_id, user_id) DO UPDATE SET role = COALESCE($4, channel_members.role)
         RETURNING *
         "#,
     )
     .bind(id)
     .bind(input.user_id)
     .bind(input.role.as_deref().unwrap_or("member"))
+    .bind(input.role.as_deref())
     .fetch_one(&state.db)
     .await?;
 
@@ -536,21 +517,14 @@ async fn remove_member(
     auth: AuthUser,
     Path((channel_id, user_id)): Path<(Uuid, Uuid)>,
 ) -> ApiResult<Json<serde_json::Value>> {
-    // Check admin membership (or user removing themselves)
+    // Users can always leave; removing others needs permission
     if auth.user_id != user_id {
-        let member: ChannelMember =
-            sqlx::query_as("SELECT * FROM channel_members WHERE channel_id = $1 AND user_id = $2")
-                .bind(channel_id)
-                .bind(auth.user_id)
-                .fetch_optional(&state.db)
-                .await?
-                .ok_or_else(|| AppError::Forbidden("Not a member of this channel".to_string()))?;
-
-        if member.role != "admin" && auth.role != "system_admin" {
-            return Err(AppError::Forbidden(
-                "Not an admin of this channel".to_string(),
-            ));
-        }
+        auth.require(
+            &state,
+            Permission::ChannelManageMembers,
+            Scope::Channel(channel_id),
+        )
+        .await?;
     }
 
     sqlx::query("DELETE FROM channel_members WHERE channel_id = $1 AND user_id = $2")
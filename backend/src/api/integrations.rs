@@ -13,11 +13,13 @@ use crate::auth::AuthUser;
 use crate::error::{ApiResult, AppError};
 use crate::models::{
     Bot, BotToken, CommandResponse, CreateBot, CreateIncomingWebhook, CreateOutgoingWebhook,
-    CreateSlashCommand, ExecuteCommand, IncomingWebhook, OutgoingWebhook, OutgoingWebhookPayload,
-    SlashCommand, WebhookPayload, MiroTalkConfig,
+    CreateSlashCommand, ExecuteCommand, IncomingWebhook, OutgoingWebhook, SlashCommand,
+    WebhookPayload, MiroTalkConfig,
 };
-use crate::mattermost_compat::id::encode_mm_id;
+use crate::mattermost_compat::id::{encode_mm_id, parse_mm_or_uuid};
 use crate::services::mirotalk::MiroTalkClient;
+use crate::services::permissions::{CanCreateBot, Permission, Principal, Require, Scope};
+use crate::services::{access_tokens, builtin_commands, permissions, slash_commands};
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine as _;
 use chrono::Utc;
@@ -52,6 +54,7 @@ pub fn router() -> Router<AppState> {
             get(get_incoming_webhook).delete(delete_incoming_webhook),
         )
         .route("/hooks/{token}", post(execute_incoming_webhook))
+        .route("/hooks/commands/{id}", post(execute_command_webhook))
         // Outgoing webhooks
         .route(
             "/hooks/outgoing",
@@ -81,6 +84,21 @@ pub fn router() -> Router<AppState> {
         .route("/bots/{bot_id}/tokens/{token_id}", delete(revoke_bot_token))
 }
 
+/// Let creators manage their own integrations, others need `permission`
+async fn ensure_can_manage(
+    state: &AppState,
+    auth: &AuthUser,
+    creator_id: Uuid,
+    permission: Permission,
+    scope: Scope,
+) -> ApiResult<()> {
+    if creator_id == auth.user_id {
+        return Ok(());
+    }
+
+    auth.require(state, permission, scope).await
+}
+
 #[derive(Debug, Clone)]
 pub struct CommandAuth {
     pub user_id: Uuid,
@@ -116,6 +134,9 @@ async fn create_incoming_webhook(
     Query(query): Query<TeamQuery>,
     Json(input): Json<CreateIncomingWebhook>,
 ) -> ApiResult<Json<IncomingWebhook>> {
+    auth.require(&state, Permission::WebhookCreate, Scope::Team(query.team_id))
+        .await?;
+
     let token = generate_token();
 
     let webhook: IncomingWebhook = sqlx::query_as(
@@ -162,11 +183,14 @@ async fn delete_incoming_webhook(
         .await?
         .ok_or_else(|| AppError::NotFound("Webhook not found".to_string()))?;
 
-    if webhook.creator_id != auth.user_id && auth.role != "system_admin" {
-        return Err(AppError::Forbidden(
-            "Cannot delete this webhook".to_string(),
-        ));
-    }
+    ensure_can_manage(
+        &state,
+        &auth,
+        webhook.creator_id,
+        Permission::WebhookManage,
+        Scope::Team(webhook.team_id),
+    )
+    .await?;
 
     sqlx::query("DELETE FROM incoming_webhooks WHERE id = $1")
         .bind(id)
@@ -229,6 +253,9 @@ async fn create_outgoing_webhook(
     Query(query): Query<TeamQuery>,
     Json(input): Json<CreateOutgoingWebhook>,
 ) -> ApiResult<Json<OutgoingWebhook>> {
+    auth.require(&state, Permission::WebhookCreate, Scope::Team(query.team_id))
+        .await?;
+
     if input.callback_urls.is_empty() {
         return Err(AppError::Validation(
             "At least one callback URL required".to_string(),
@@ -240,8 +267,8 @@ async fn create_outgoing_webhook(
     let webhook: OutgoingWebhook = sqlx::query_as(
         r#"
         INSERT INTO outgoing_webhooks 
-        (team_id, channel_id, creator_id, display_name, description, trigger_words, trigger_when, callback_urls, token)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        (team_id, channel_id, creator_id, display_name, description, trigger_words, trigger_when, callback_urls, content_type, token)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
         RETURNING *
         "#,
     )
@@ -253,6 +280,7 @@ async fn create_outgoing_webhook(
     .bind(&input.trigger_words)
     .bind(&input.trigger_when)
     .bind(&input.callback_urls)
+    .bind(&input.content_type)
     .bind(&token)
     .fetch_one(&state.db)
     .await?;
@@ -285,11 +313,14 @@ async fn delete_outgoing_webhook(
         .await?
         .ok_or_else(|| AppError::NotFound("Webhook not found".to_string()))?;
 
-    if webhook.creator_id != auth.user_id && auth.role != "system_admin" {
-        return Err(AppError::Forbidden(
-            "Cannot delete this webhook".to_string(),
-        ));
-    }
+    ensure_can_manage(
+        &state,
+        &auth,
+        webhook.creator_id,
+        Permission::WebhookManage,
+        Scope::Team(webhook.team_id),
+    )
+    .await?;
 
     sqlx::query("DELETE FROM outgoing_webhooks WHERE id = $1")
         .bind(id)
@@ -321,6 +352,9 @@ async fn create_slash_command(
     Query(query): Query<TeamQuery>,
     Json(input): Json<CreateSlashCommand>,
 ) -> ApiResult<Json<SlashCommand>> {
+    auth.require(&state, Permission::CommandCreate, Scope::Team(query.team_id))
+        .await?;
+
     if !input.trigger.starts_with('/') && input.trigger.len() < 2 {
         return Err(AppError::Validation("Invalid trigger format".to_string()));
     }
@@ -376,11 +410,14 @@ async fn delete_slash_command(
         .await?
         .ok_or_else(|| AppError::NotFound("Command not found".to_string()))?;
 
-    if command.creator_id != auth.user_id && auth.role != "system_admin" {
-        return Err(AppError::Forbidden(
-            "Cannot delete this command".to_string(),
-        ));
-    }
+    ensure_can_manage(
+        &state,
+        &auth,
+        command.creator_id,
+        Permission::CommandManage,
+        Scope::Team(command.team_id),
+    )
+    .await?;
 
     sqlx::query("DELETE FROM slash_commands WHERE id = $1")
         .bind(id)
@@ -415,121 +452,110 @@ pub async fn execute_command_internal(
     payload: ExecuteCommand,
 ) -> ApiResult<CommandResponse> {
     // 1. Parse trigger
-    let parts: Vec<&str> = payload.command.split_whitespace().collect();
-    if parts.is_empty() {
+    let command = payload.command.trim();
+    if command.is_empty() {
         return Err(AppError::BadRequest("Empty command".to_string()));
     }
 
-    let trigger = parts[0].trim_start_matches('/');
-    let args = if parts.len() > 1 {
-        parts[1..].join(" ")
-    } else {
-        String::new()
+    // Keep the arguments verbatim so multi-line messages survive
+    let (trigger, args) = match command.split_once(char::is_whitespace) {
+        Some((trigger, rest)) => (trigger, rest.trim().to_string()),
+        None => (command, String::new()),
     };
+    let trigger = trigger.trim_start_matches('/');
 
-    // 2. Handle built-in commands
-    match trigger {
-        "call" => {
-            let config: MiroTalkConfig = sqlx::query_as(
-                "SELECT * FROM mirotalk_config WHERE is_active = true",
-            )
-            .fetch_optional(&state.db)
-            .await?
-            .unwrap_or_else(|| MiroTalkConfig {
-                is_active: true,
-                mode: crate::models::MiroTalkMode::Disabled,
-                base_url: "".to_string(),
-                api_key_secret: "".to_string(),
-                default_room_prefix: None,
-                join_behavior: crate::models::JoinBehavior::NewTab,
-                updated_at: Utc::now(),
-                updated_by: None,
+    // 2. Handle /call
+    if trigger == "call" {
+        let config: MiroTalkConfig = sqlx::query_as(
+            "SELECT * FROM mirotalk_config WHERE is_active = true",
+        )
+        .fetch_optional(&state.db)
+        .await?
+        .unwrap_or_else(|| MiroTalkConfig {
+            is_active: true,
+            mode: crate::models::MiroTalkMode::Disabled,
+            base_url: "".to_string(),
+            api_key_secret: "".to_string(),
+            default_room_prefix: None,
+            join_behavior: crate::models::JoinBehavior::NewTab,
+            updated_at: Utc::now(),
+            updated_by: None,
+        });
+
+        if !config.is_enabled() {
+            return Ok(CommandResponse {
+                response_type: "ephemeral".to_string(),
+                text: "MiroTalk integration is not enabled".to_string(),
+                username: None,
+                icon_url: None,
+                goto_location: None,
+                attachments: None,
             });
+        }
 
-            if !config.is_enabled() {
-                return Ok(CommandResponse {
-                    response_type: "ephemeral".to_string(),
-                    text: "MiroTalk integration is not enabled".to_string(),
-                    username: None,
-                    icon_url: None,
-                    goto_location: None,
-                    attachments: None,
-                });
-            }
+        let user = sqlx::query_as::<_, crate::models::User>(
+            "SELECT * FROM users WHERE id = $1",
+        )
+        .bind(auth.user_id)
+        .fetch_one(&state.db)
+        .await?;
 
-            let user = sqlx::query_as::<_, crate::models::User>(
-                "SELECT * FROM users WHERE id = $1",
+        let display_name = user
+            .display_name
+            .clone()
+            .unwrap_or_else(|| user.username.clone());
+
+        if args == "end" || args == "stop" {
+            let existing: Option<crate::models::post::Post> = sqlx::query_as(
+                "SELECT * FROM posts WHERE channel_id = $1 AND props->>'type' = 'custom_calls' ORDER BY created_at DESC LIMIT 1",
             )
-            .bind(auth.user_id)
-            .fetch_one(&state.db)
+            .bind(payload.channel_id)
+            .fetch_optional(&state.db)
             .await?;
 
-            let display_name = user
-                .display_name
-                .clone()
-                .unwrap_or_else(|| user.username.clone());
-
-            if args == "end" || args == "stop" {
-                let existing: Option<crate::models::post::Post> = sqlx::query_as(
-                    "SELECT * FROM posts WHERE channel_id = $1 AND props->>'type' = 'custom_calls' ORDER BY created_at DESC LIMIT 1",
+            if let Some(post) = existing {
+                let mut props = post.props.as_object().cloned().unwrap_or_default();
+                props.insert("ended".to_string(), serde_json::Value::Bool(true));
+                props.insert("attachments".to_string(), serde_json::Value::Array(vec![]));
+
+                let updated: crate::models::post::PostResponse = sqlx::query_as(
+                    r#"
+                    WITH updated_post AS (
+                        UPDATE posts SET message = $1, props = $2, edited_at = NOW()
+                        WHERE id = $3
+                        RETURNING *
+                    )
+                    SELECT p.id, p.channel_id, p.user_id, p.root_post_id, p.message, p.props, p.file_ids,
+                           p.is_pinned, p.created_at, p.edited_at, p.deleted_at,
+                           p.reply_count::int8 as reply_count,
+                           p.last_reply_at, p.seq,
+                           u.username, u.avatar_url, u.email
+                    FROM updated_post p
+                    LEFT JOIN users u ON p.user_id = u.id
+                    "#,
                 )
-                .bind(payload.channel_id)
-                .fetch_optional(&state.db)
+                .bind(format!("Video call ended by @{}", user.username))
+                .bind(serde_json::Value::Object(props))
+                .bind(post.id)
+                .fetch_one(&state.db)
                 .await?;
 
-                if let Some(post) = existing {
-                    let mut props = post.props.as_object().cloned().unwrap_or_default();
-                    props.insert("ended".to_string(), serde_json::Value::Bool(true));
-                    props.insert("attachments".to_string(), serde_json::Value::Array(vec![]));
-
-                    let updated: crate::models::post::PostResponse = sqlx::query_as(
-                        r#"
-                        WITH updated_post AS (
-                            UPDATE posts SET message = $1, props = $2, edited_at = NOW()
-                            WHERE id = $3
-                            RETURNING *
-                        )
-                        SELECT p.id, p.channel_id, p.user_id, p.root_post_id, p.message, p.props, p.file_ids,
-                               p.is_pinned, p.created_at, p.edited_at, p.deleted_at,
-                               p.reply_count::int8 as reply_count,
-                               p.last_reply_at, p.seq,
-                               u.username, u.avatar_url, u.email
-                        FROM updated_post p
-                        LEFT JOIN users u ON p.user_id = u.id
-                        "#,
-                    )
-                    .bind(format!("Video call ended by @{}", user.username))
-                    .bind(serde_json::Value::Object(props))
-                    .bind(post.id)
-                    .fetch_one(&state.db)
-                    .await?;
-
-                    let broadcast = crate::realtime::WsEnvelope::event(
-                        crate::realtime::EventType::MessageUpdated,
-                        updated.clone(),
-                        Some(post.channel_id),
-                    )
-                    .with_broadcast(crate::realtime::WsBroadcast {
-                        channel_id: Some(post.channel_id),
-                        team_id: None,
-                        user_id: None,
-                        exclude_user_id: None,
-                    });
-                    state.ws_hub.broadcast(broadcast).await;
-
-                    return Ok(CommandResponse {
-                        response_type: "ephemeral".to_string(),
-                        text: "Call ended".to_string(),
-                        username: None,
-                        icon_url: None,
-                        goto_location: None,
-                        attachments: None,
-                    });
-                }
+                let broadcast = crate::realtime::WsEnvelope::event(
+                    crate::realtime::EventType::MessageUpdated,
+                    updated.clone(),
+                    Some(post.channel_id),
+                )
+                .with_broadcast(crate::realtime::WsBroadcast {
+                    channel_id: Some(post.channel_id),
+                    team_id: None,
+                    user_id: None,
+                    exclude_user_id: None,
+                });
+                state.ws_hub.broadcast(broadcast).await;
 
                 return Ok(CommandResponse {
                     response_type: "ephemeral".to_string(),
-                    text: "No active call found in this channel".to_string(),
+                    text: "Call ended".to_string(),
                     username: None,
                     icon_url: None,
                     goto_location: None,
@@ -537,206 +563,137 @@ pub async fn execute_command_internal(
                 });
             }
 
-            let channel_id_mm = encode_mm_id(payload.channel_id);
-            let salt = if config.api_key_secret.is_empty() {
-                "rustchat".to_string()
-            } else {
-                config.api_key_secret.clone()
-            };
-            let room_seed = format!("{}:{}", channel_id_mm, salt);
-            let room_id = URL_SAFE_NO_PAD.encode(room_seed.as_bytes());
-
-            let client = MiroTalkClient::new(config.clone(), state.http_client.clone())?;
-            let meeting_url = client
-                .create_meeting(&room_id, Some(&display_name), true, true)
-                .await?;
-
-            let mut join_url = match Url::parse(&meeting_url) {
-                Ok(url) => url,
-                Err(_) => {
-                    let mut base = Url::parse(&config.base_url)
-                        .map_err(|_| AppError::Config("Invalid MiroTalk base URL".to_string()))?;
-                    if let Ok(mut segments) = base.path_segments_mut() {
-                        segments.pop_if_empty();
-                        segments.push(meeting_url.trim_start_matches('/'));
-                    }
-                    base
-                }
-            };
-            if !join_url.query_pairs().any(|(k, _)| k == "name") {
-                join_url.query_pairs_mut().append_pair("name", &display_name);
-            }
-
-            let attachments = serde_json::json!([
-                {
-                    "color": "#166de0",
-                    "title": "Mirotalk Conference",
-                    "text": "The meeting is active. Tap to join.",
-                    "actions": [
-                        {
-                            "id": "join_call",
-                            "name": "Join Meeting",
-                            "type": "button",
-                            "style": "primary",
-                            "integration": {
-                                "url": join_url.to_string(),
-                                "context": { "action": "open_url" }
-                            }
-                        }
-                    ]
-                }
-            ]);
-
-            let props = serde_json::json!({
-                "type": "custom_calls",
-                "attachments": attachments,
-                "call": {
-                    "room_id": room_id
-                }
-            });
-
-            let create_post_input = crate::models::CreatePost {
-                message: format!("Video call started by @{}", user.username),
-                file_ids: vec![],
-                props: Some(props),
-                root_post_id: None,
-            };
-
-            let _ = crate::services::posts::create_post(
-                state,
-                auth.user_id,
-                payload.channel_id,
-                create_post_input,
-                None,
-            )
-            .await?;
-
-            return Ok(CommandResponse {
-                response_type: "ephemeral".to_string(),
-                text: "Call started".to_string(),
-                username: None,
-                icon_url: None,
-                goto_location: None,
-                attachments: None,
-            });
-        }
-        "echo" => {
             return Ok(CommandResponse {
                 response_type: "ephemeral".to_string(),
-                text: format!("Echo: {}", args),
+                text: "No active call found in this channel".to_string(),
                 username: None,
                 icon_url: None,
                 goto_location: None,
                 attachments: None,
             });
         }
-        "shrug" => {
-            return Ok(CommandResponse {
-                response_type: "in_channel".to_string(),
-                text: format!("{} ¯\\_(ツ)_/¯", args),
-                username: None,
-                icon_url: None,
-                goto_location: None,
-                attachments: None,
-            });
-        }
-        "invite" => {
-            // Mock invite
-            return Ok(CommandResponse {
-                response_type: "ephemeral".to_string(),
-                text: format!("Invitation sent to {}", args),
-                username: None,
-                icon_url: None,
-                goto_location: None,
-                attachments: None,
-            });
+
+        let channel_id_mm = encode_mm_id(payload.channel_id);
+        let salt = if config.api_key_secret.is_empty() {
+            "rustchat".to_string()
+        } else {
+            config.api_key_secret.clone()
+        };
+        let room_seed = format!("{}:{}", channel_id_mm, salt);
+        let room_id = URL_SAFE_NO_PAD.encode(room_seed.as_bytes());
+
+        let client = MiroTalkClient::new(config.clone(), state.http_client.clone())?;
+        let meeting_url = client
+            .create_meeting(&room_id, Some(&display_name), true, true)
+            .await?;
+
+        let mut join_url = match Url::parse(&meeting_url) {
+            Ok(url) => url,
+            Err(_) => {
+                let mut base = Url::parse(&config.base_url)
+                    .map_err(|_| AppError::Config("Invalid MiroTalk base URL".to_string()))?;
+                if let Ok(mut segments) = base.path_segments_mut() {
+                    segments.pop_if_empty();
+                    segments.push(meeting_url.trim_start_matches('/'));
+                }
+                base
+            }
+        };
+        if !join_url.query_pairs().any(|(k, _)| k == "name") {
+            join_url.query_pairs_mut().append_pair("name", &display_name);
         }
-        _ => {}
-    }
 
-    // 3. Look up custom slash commands
-    // We need team_id. If not provided in payload (it's optional), try to get from channel.
-    let team_id = if let Some(tid) = payload.team_id {
-        tid
-    } else {
-        let channel = sqlx::query!(
-            "SELECT team_id FROM channels WHERE id = $1",
-            payload.channel_id
+        let attachments = serde_json::json!([
+            {
+                "color": "#166de0",
+                "title": "Mirotalk Conference",
+                "text": "The meeting is active. Tap to join.",
+                "actions": [
+                    {
+                        "id": "join_call",
+                        "name": "Join Meeting",
+                        "type": "button",
+                        "style": "primary",
+                        "integration": {
+                            "url": join_url.to_string(),
+                            "context": { "action": "open_url" }
+                        }
+                    }
+                ]
+            }
+        ]);
+
+        let props = serde_json::json!({
+            "type": "custom_calls",
+            "attachments": attachments,
+            "call": {
+                "room_id": room_id
+            }
+        });
+
+        let create_post_input = crate::models::CreatePost {
+            message: format!("Video call started by @{}", user.username),
+            file_ids: vec![],
+            props: Some(props),
+            root_post_id: None,
+        };
+
+        let _ = crate::services::posts::create_post(
+            state,
+            auth.user_id,
+            payload.channel_id,
+            create_post_input,
+            None,
         )
+        .await?;
+
+        return Ok(CommandResponse {
+            response_type: "ephemeral".to_string(),
+            text: "Call started".to_string(),
+            username: None,
+            icon_url: None,
+            goto_location: None,
+            attachments: None,
+        });
+    }
+
+    // Commands run in the client's current team, which for direct messages
+    // need not be the channel's; other teams are only taken from members
+    let channel_team_id: Uuid = sqlx::query_scalar("SELECT team_id FROM channels WHERE id = $1")
+        .bind(payload.channel_id)
         .fetch_optional(&state.db)
         .await?
         .ok_or_else(|| AppError::NotFound("Channel not found".to_string()))?;
-        channel.team_id
+    let team_id = match payload.team_id {
+        Some(team_id) if team_id != channel_team_id => {
+            permissions::ensure_team_member(state, auth.user_id, team_id).await?;
+            team_id
+        }
+        _ => channel_team_id,
     };
 
-    let command = sqlx::query_as::<_, SlashCommand>(
-        "SELECT * FROM slash_commands WHERE team_id = $1 AND trigger = $2",
-    )
-    .bind(team_id)
-    .bind(trigger)
-    .fetch_optional(&state.db)
-    .await?;
-
-    if let Some(cmd) = command {
-        // Fetch username
-        let user_name = sqlx::query_scalar::<_, String>("SELECT username FROM users WHERE id = $1")
-            .bind(auth.user_id)
-            .fetch_one(&state.db)
-            .await
-            .unwrap_or_else(|_| "unknown".to_string());
-
-        // Fetch channel name
-        let channel_name =
-            sqlx::query_scalar::<_, String>("SELECT name FROM channels WHERE id = $1")
-                .bind(payload.channel_id)
-                .fetch_one(&state.db)
-                .await
-                .unwrap_or_else(|_| "unknown".to_string());
-
-        // Execute external command (HTTP POST)
-        let client = reqwest::Client::new();
+    let ctx = slash_commands::CommandContext {
+        user_id: auth.user_id,
+        channel_id: payload.channel_id,
+        root_id: payload.root_id,
+    };
 
-        let payload_out = OutgoingWebhookPayload {
-            token: cmd.token.clone(),
-            team_id: cmd.team_id,
-            channel_id: payload.channel_id,
-            channel_name,
-            user_id: auth.user_id,
-            user_name,
-            text: args,
-            trigger_word: trigger.to_string(),
-        };
+    // 3. Handle the rest of the built-in commands
+    let builtin_ctx = builtin_commands::BuiltinContext {
+        command: ctx.clone(),
+        team_id,
+        role: auth.role.clone(),
+    };
+    if let Some(response) =
+        builtin_commands::execute_builtin(state, &builtin_ctx, trigger, &args).await?
+    {
+        slash_commands::handle_command_response(state, &ctx, &response, None).await?;
+        return Ok(response);
+    }
 
-        let res = client
-            .post(&cmd.url)
-            .json(&payload_out)
-            .send()
-            .await
-            .map_err(|e| AppError::Internal(format!("Command execution failed: {}", e)))?;
-
-        if res.status().is_success() {
-            let resp_body: CommandResponse =
-                res.json::<CommandResponse>()
-                    .await
-                    .unwrap_or_else(|_| CommandResponse {
-                        response_type: "ephemeral".to_string(),
-                        text: "Command executed successfully (no response body)".to_string(),
-                        username: None,
-                        icon_url: None,
-                        goto_location: None,
-                        attachments: None,
-                    });
-                return Ok(resp_body);
-            } else {
-            return Ok(CommandResponse {
-                response_type: "ephemeral".to_string(),
-                text: format!("Command failed with status: {}", res.status()),
-                username: None,
-                icon_url: None,
-                goto_location: None,
-                attachments: None,
-            });
-        }
+    // 4. Look up custom slash commands
+    if let Some(cmd) = slash_commands::find_command(state, team_id, trigger).await? {
+        return slash_commands::execute_custom_command(state, &cmd, &ctx, &args).await;
     }
 
     Ok(CommandResponse {
@@ -749,10 +706,24 @@ pub async fn execute_command_internal(
     })
 }
 
+/// Receive a delayed response for a custom command execution
+async fn execute_command_webhook(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Json(response): Json<CommandResponse>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let hook_id = parse_mm_or_uuid(&id)
+        .ok_or_else(|| AppError::BadRequest("Invalid response URL".to_string()))?;
+
+    slash_commands::execute_command_webhook(&state, hook_id, response).await?;
+
+    Ok(Json(serde_json::json!({"status": "ok"})))
+}
+
 // ============ Bots ============
 
 async fn list_bots(State(state): State<AppState>, auth: AuthUser) -> ApiResult<Json<Vec<Bot>>> {
-    let bots: Vec<Bot> = if auth.role == "system_admin" {
+    let bots: Vec<Bot> = if auth.can(&state, Permission::BotManage, Scope::System).await? {
         sqlx::query_as("SELECT * FROM bots ORDER BY created_at DESC")
             .fetch_all(&state.db)
             .await?
@@ -768,7 +739,7 @@ async fn list_bots(State(state): State<AppState>, auth: AuthUser) -> ApiResult<J
 
 async fn create_bot(
     State(state): State<AppState>,
-    auth: AuthUser,
+    Require(auth, _): Require<CanCreateBot>,
     Json(input): Json<CreateBot>,
 ) -> ApiResult<Json<Bot>> {
     // Create a user account for the bot
@@ -832,9 +803,14 @@ async fn delete_bot(
         .await?
         .ok_or_else(|| AppError::NotFound("Bot not found".to_string()))?;
 
-    if bot.owner_id != auth.user_id && auth.role != "system_admin" {
-        return Err(AppError::Forbidden("Cannot delete this bot".to_string()));
-    }
+    ensure_can_manage(
+        &state,
+        &auth,
+        bot.owner_id,
+        Permission::BotManage,
+        Scope::System,
+    )
+    .await?;
 
     sqlx::query("DELETE FROM bots WHERE id = $1")
         .bind(id)
@@ -855,9 +831,14 @@ async fn list_bot_tokens(
         .await?
         .ok_or_else(|| AppError::NotFound("Bot not found".to_string()))?;
 
-    if bot.owner_id != auth.user_id && auth.role != "system_admin" {
-        return Err(AppError::Forbidden("Cannot access this bot".to_string()));
-    }
+    ensure_can_manage(
+        &state,
+        &auth,
+        bot.owner_id,
+        Permission::BotManage,
+        Scope::System,
+    )
+    .await?;
 
     let tokens: Vec<BotToken> =
         sqlx::query_as("SELECT * FROM bot_tokens WHERE bot_id = $1 ORDER BY created_at DESC")
@@ -885,25 +866,18 @@ async fn create_bot_token(
         .await?
         .ok_or_else(|| AppError::NotFound("Bot not found".to_string()))?;
 
-    if bot.owner_id != auth.user_id && auth.role != "system_admin" {
-        return Err(AppError::Forbidden("Cannot access this bot".to_string()));
-    }
-
-    let token = generate_token();
-
-    let bot_token: BotToken = sqlx::query_as(
-        r#"
-        INSERT INTO bot_tokens (bot_id, token, description)
-        VALUES ($1, $2, $3)
-        RETURNING *
-        "#,
+    ensure_can_manage(
+        &state,
+        &auth,
+        bot.owner_id,
+        Permission::BotManage,
+        Scope::System,
     )
-    .bind(id)
-    .bind(&token)
-    .bind(&input.description)
-    .fetch_one(&state.db)
     .await?;
 
+    let bot_token =
+        access_tokens::create_bot_token(&state.db, id, input.description.as_deref()).await?;
+
     Ok(Json(bot_token))
 }
 
@@ -918,15 +892,16 @@ async fn revoke_bot_token(
         .await?
         .ok_or_else(|| AppError::NotFound("Bot not found".to_string()))?;
 
-    if bot.owner_id != auth.user_id && auth.role != "system_admin" {
-        return Err(AppError::Forbidden("Cannot access this bot".to_string()));
-    }
+    ensure_can_manage(
+        &state,
+        &auth,
+        bot.owner_id,
+        Permission::BotManage,
+        Scope::System,
+    )
+    .await?;
 
-    sqlx::query("DELETE FROM bot_tokens WHERE id = $1 AND bot_id = $2")
-        .bind(token_id)
-        .bind(bot_id)
-        .execute(&state.db)
-        .await?;
+    access_tokens::revoke_bot_token(&state, bot.user_id, bot_id, token_id).await?;
 
     Ok(Json(serde_json::json!({"status": "revoked"})))
 }
@@ -12,19 +12,21 @@ mod integrations;
 mod oauth;
 mod playbooks;
 mod posts;
-mod preferences;
+pub(crate) mod preferences;
+mod saml;
 mod search;
 mod site;
+mod storage;
 mod teams;
 mod unreads;
 mod users;
-mod v4;
+pub(crate) mod v4;
 mod video;
 mod ws;
 
 use std::sync::Arc;
 
-use axum::{extract::DefaultBodyLimit, http::Method, Router};
+use axum::{extract::DefaultBodyLimit, http::Method, middleware, Router};
 use sqlx::PgPool;
 use tower_http::{
     compression::CompressionLayer,
@@ -33,7 +35,13 @@ use tower_http::{
 };
 
 use crate::realtime::WsHub;
-use crate::storage::S3Client;
+use crate::services::client_address::TrustedProxies;
+use crate::services::email::Mailer;
+use crate::services::media::MediaProcessor;
+use crate::services::mfa::MfaPolicy;
+use crate::services::permissions::PermissionCache;
+use crate::services::rate_limit::{self, RateLimiter};
+use crate::storage::Storage;
 
 /// Application state shared across handlers
 #[derive(Clone)]
@@ -42,32 +50,89 @@ pub struct AppState {
     pub redis: deadpool_redis::Pool,
     pub jwt_secret: String,
     pub jwt_expiry_hours: u64,
+    /// Key for secrets stored in the database, such as the SMTP password
+    pub encryption_key: String,
     pub ws_hub: Arc<WsHub>,
-    pub s3_client: S3Client,
+    pub storage: Storage,
     pub http_client: reqwest::Client,
+    pub mailer: Arc<Mailer>,
+    pub permissions: Arc<PermissionCache>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub mfa_policy: Arc<MfaPolicy>,
+    pub media: Arc<MediaProcessor>,
+    /// Reverse proxies whose forwarding headers name the client
+    pub trusted_proxies: Arc<TrustedProxies>,
     pub start_time: std::time::Instant,
 }
 
+impl AppState {
+    /// Create application state from its dependencies
+    pub fn new(
+        db: PgPool,
+        redis: deadpool_redis::Pool,
+        jwt_secret: String,
+        jwt_expiry_hours: u64,
+        encryption_key: String,
+        ws_hub: Arc<WsHub>,
+        storage: Storage,
+    ) -> Self {
+        Self {
+            db,
+            redis,
+            jwt_secret,
+            jwt_expiry_hours,
+            encryption_key,
+            ws_hub,
+            storage,
+            http_client: reqwest::Client::new(),
+            mailer: Arc::new(Mailer::new()),
+            permissions: Arc::new(PermissionCache::new()),
+            rate_limiter: Arc::new(RateLimiter::new()),
+            mfa_policy: Arc::new(MfaPolicy::new()),
+            media: Arc::new(MediaProcessor::default()),
+            trusted_proxies: Arc::new(TrustedProxies::default()),
+            start_time: std::time::Instant::now(),
+        }
+    }
+
+    /// Use `media` for processing uploaded images and videos
+    pub fn with_media(mut self, media: MediaProcessor) -> Self {
+        self.media = Arc::new(media);
+        self
+    }
+
+    /// Read client addresses from the forwarding headers of `proxies`
+    pub fn with_trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        self.trusted_proxies = Arc::new(proxies);
+        self
+    }
+}
+
 /// Build the main application router
 pub fn router(
     db: PgPool,
     redis: deadpool_redis::Pool,
     jwt_secret: String,
     jwt_expiry_hours: u64,
+    encryption_key: String,
     ws_hub: Arc<WsHub>,
-    s3_client: S3Client,
+    storage: Storage,
 ) -> Router {
-    let state = AppState {
+    router_with_state(AppState::new(
         db,
         redis,
         jwt_secret,
         jwt_expiry_hours,
+        encryption_key,
         ws_hub,
-        s3_client,
-        http_client: reqwest::Client::new(),
-        start_time: std::time::Instant::now(),
-    };
+        storage,
+    ))
+}
 
+/// Build the main application router around an existing state
+///
+/// Used when background jobs need to share the same state as the handlers.
+pub fn router_with_state(state: AppState) -> Router {
     // CORS configuration
     let cors = CorsLayer::new()
         .allow_origin(Any)
@@ -97,7 +162,9 @@ pub fn router(
         .merge(playbooks::router())
         .merge(calls::router())
         .merge(oauth::router())
+        .merge(saml::router())
         .merge(site::router())
+        .merge(storage::router())
         .nest("/video", video::router())
         .merge(ws::router());
 
@@ -106,6 +173,10 @@ pub fn router(
     Router::new()
         .nest("/api/v1", api_v1)
         .nest("/api/v4", api_v4)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::limit_requests,
+        ))
         .layer(CompressionLayer::new())
         .layer(TraceLayer::new_for_http())
         .layer(cors)
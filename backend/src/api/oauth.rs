@@ -2,37 +2,63 @@
 
 use axum::{
     extract::{Path, Query, State},
-    response::Redirect,
-    routing::get,
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Redirect, Response},
+    routing::{delete, get, post},
     Json, Router,
 };
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use super::AppState;
+use crate::auth::AuthUser;
+use crate::crypto::random_token;
 use crate::error::{ApiResult, AppError};
+use crate::models::{SiteConfig, SsoConfig, UserIdentity};
+use crate::services::client_address::ClientIp;
+use crate::services::oidc;
+use crate::services::sessions::SessionMetadata;
+use crate::services::sso::{self, LoginState};
+
+/// Cookie that ties the callback to the browser that started the login
+const STATE_COOKIE: &str = "rustchat_sso_state";
 
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/oauth2/{provider}/login", get(oauth_login))
         .route("/oauth2/{provider}/callback", get(oauth_callback))
+        .route("/oauth2/{provider}/link", post(link_identity))
         .route("/oauth2/providers", get(list_providers))
+        .route("/oauth2/identities", get(list_identities))
+        .route("/oauth2/identities/{id}", delete(unlink_identity))
 }
 
-/// List available OAuth providers
+/// List available SSO providers, OIDC and SAML
 async fn list_providers(State(state): State<AppState>) -> ApiResult<Json<Vec<OAuthProvider>>> {
-    // Query enabled SSO configs from DB
-    let configs: Vec<SsoConfigRow> =
-        sqlx::query_as("SELECT * FROM sso_configs WHERE is_active = true")
-            .fetch_all(&state.db)
-            .await?;
+    if sso::ensure_enabled(&state.db).await.is_err() {
+        return Ok(Json(Vec::new()));
+    }
+
+    let configs: Vec<SsoConfig> = sqlx::query_as(
+        "SELECT * FROM sso_configs WHERE is_active = true AND provider IN ('oidc', 'saml') ORDER BY created_at",
+    )
+    .fetch_all(&state.db)
+    .await?;
 
     let providers: Vec<OAuthProvider> = configs
         .into_iter()
-        .map(|c| OAuthProvider {
-            id: c.provider.clone(),
-            name: c.display_name.unwrap_or(c.provider),
-            icon_url: None,
+        .map(|c| {
+            let login_url = match c.provider.as_str() {
+                "saml" => format!("/api/v1/saml/{}/login", c.provider),
+                _ => format!("/api/v1/oauth2/{}/login", c.provider),
+            };
+            OAuthProvider {
+                id: c.provider.clone(),
+                protocol: c.provider.clone(),
+                name: c.display_name.unwrap_or(c.provider),
+                icon_url: None,
+                login_url,
+            }
         })
         .collect();
 
@@ -42,241 +68,286 @@ async fn list_providers(State(state): State<AppState>) -> ApiResult<Json<Vec<OAu
 #[derive(Debug, Serialize)]
 pub struct OAuthProvider {
     pub id: String,
+    /// `oidc` or `saml`
+    pub protocol: String,
     pub name: String,
     pub icon_url: Option<String>,
-}
-
-#[derive(Debug, sqlx::FromRow)]
-#[allow(dead_code)]
-struct SsoConfigRow {
-    id: Uuid,
-    org_id: Uuid,
-    provider: String,
-    display_name: Option<String>,
-    issuer_url: Option<String>,
-    client_id: String,
-    client_secret_encrypted: String,
-    is_active: bool,
+    /// Where the browser goes to start a login
+    pub login_url: String,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct OAuthLoginQuery {
-    pub redirect_uri: Option<String>,
+    pub redirect_to: Option<String>,
 }
 
-/// Initiate OAuth login - redirects to provider
-async fn oauth_login(
-    State(state): State<AppState>,
-    Path(provider): Path<String>,
-    Query(query): Query<OAuthLoginQuery>,
-) -> Result<Redirect, AppError> {
-    // Get SSO config for provider
-    let config: SsoConfigRow =
-        sqlx::query_as("SELECT * FROM sso_configs WHERE provider = $1 AND is_active = true")
-            .bind(&provider)
-            .fetch_optional(&state.db)
-            .await?
-            .ok_or_else(|| {
-                AppError::NotFound(format!(
-                    "OAuth provider '{}' not found or disabled",
-                    provider
-                ))
-            })?;
-
-    // Generate state parameter for CSRF protection
-    let oauth_state = Uuid::new_v4().to_string();
-    let _redirect_after = query.redirect_uri.unwrap_or_else(|| "/".to_string());
-
-    // Store state in Redis with short TTL (5 min)
-    // TODO: Implement Redis state storage
-    // For now, we'll use a simple in-memory approach via query params
-
-    // Build authorization URL
-    let issuer = config
-        .issuer_url
-        .unwrap_or_else(|| format!("https://{}.example.com", provider));
+/// Only local paths, so the login cannot bounce users to another site
+pub(super) fn local_path(path: Option<String>) -> Option<String> {
+    path.filter(|p| p.starts_with('/') && !p.starts_with("//") && !p.contains('\\'))
+}
+
+pub(super) async fn site_url(state: &AppState) -> ApiResult<String> {
+    let site: sqlx::types::Json<SiteConfig> =
+        sqlx::query_scalar("SELECT site FROM server_config WHERE id = 'default'")
+            .fetch_one(&state.db)
+            .await?;
 
-    let callback_url = format!(
+    let url = if site.site_url.trim().is_empty() {
+        std::env::var("RUSTCHAT_SITE_URL").unwrap_or_else(|_| "http://localhost:8080".to_string())
+    } else {
+        site.site_url.trim().to_string()
+    };
+
+    Ok(url.trim_end_matches('/').to_string())
+}
+
+async fn callback_url(state: &AppState, provider: &str) -> ApiResult<String> {
+    Ok(format!(
         "{}/api/v1/oauth2/{}/callback",
-        std::env::var("RUSTCHAT_SITE_URL").unwrap_or_else(|_| "http://localhost:8080".to_string()),
+        site_url(state).await?,
         provider
+    ))
+}
+
+fn state_cookie(value: &str, max_age: i64, secure: bool) -> HeaderValue {
+    let cookie = format!(
+        "{}={}; Path=/api/v1/oauth2; Max-Age={}; HttpOnly; SameSite=Lax{}",
+        STATE_COOKIE,
+        value,
+        max_age,
+        if secure { "; Secure" } else { "" }
     );
+    HeaderValue::from_str(&cookie).expect("cookie is ASCII")
+}
+
+pub(super) fn cookie_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
 
-    let auth_url = format!(
-        "{}/authorize?client_id={}&redirect_uri={}&response_type=code&scope=openid%20profile%20email&state={}",
-        issuer,
-        urlencoding::encode(&config.client_id),
-        urlencoding::encode(&callback_url),
-        oauth_state
+/// Store a new login and build the provider's authorization URL
+async fn start_login(
+    state: &AppState,
+    provider: &str,
+    redirect_to: Option<String>,
+    link_user_id: Option<Uuid>,
+) -> ApiResult<(String, HeaderValue)> {
+    sso::ensure_enabled(&state.db).await?;
+    let config = sso::find_config(&state.db, provider, "oidc").await?;
+    let issuer = config
+        .issuer_url
+        .as_deref()
+        .ok_or_else(|| AppError::Config("SSO provider has no issuer URL".to_string()))?;
+    let metadata = oidc::discover(&state.http_client, issuer).await?;
+
+    let login = LoginState {
+        sso_config_id: config.id,
+        nonce: random_token(),
+        code_verifier: Some(random_token()),
+        redirect_to: local_path(redirect_to),
+        link_user_id,
+    };
+    let oauth_state = sso::save_login_state(&state.db, &login).await?;
+
+    let redirect_uri = callback_url(state, provider).await?;
+    let url = oidc::authorization_url(
+        &metadata,
+        &config,
+        &redirect_uri,
+        &oauth_state,
+        &login.nonce,
+        login.code_verifier.as_deref().unwrap_or_default(),
+    )?;
+    let cookie = state_cookie(
+        &oauth_state,
+        sso::LOGIN_STATE_EXPIRY_MINUTES as i64 * 60,
+        redirect_uri.starts_with("https://"),
     );
 
-    Ok(Redirect::temporary(&auth_url))
+    Ok((url, cookie))
+}
+
+/// Initiate OAuth login - redirects to provider
+async fn oauth_login(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    Query(query): Query<OAuthLoginQuery>,
+) -> ApiResult<Response> {
+    let (url, cookie) = start_login(&state, &provider, query.redirect_to, None).await?;
+
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::temporary(&url)).into_response())
+}
+
+/// Start linking an SSO identity to the signed-in account
+///
+/// Returns the provider URL for the browser to open; the callback links the
+/// identity instead of signing in.
+async fn link_identity(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(provider): Path<String>,
+    Query(query): Query<OAuthLoginQuery>,
+) -> ApiResult<Response> {
+    let redirect_to = query
+        .redirect_to
+        .or_else(|| Some("/settings/profile".to_string()));
+    let (url, cookie) = start_login(&state, &provider, redirect_to, Some(auth.user_id)).await?;
+
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Json(serde_json::json!({ "authorization_url": url })),
+    )
+        .into_response())
 }
 
 #[derive(Debug, Deserialize)]
 pub struct OAuthCallbackQuery {
     pub code: Option<String>,
-    pub _state: Option<String>,
+    pub state: Option<String>,
     pub error: Option<String>,
     pub error_description: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct TokenResponse {
-    access_token: String,
-    token_type: String,
-    expires_in: Option<u64>,
-    id_token: Option<String>,
-    refresh_token: Option<String>,
-}
+/// Send the browser back to the login page with the reason an SSO login failed
+///
+/// Only errors meant for the user are shown; the rest are logged.
+pub(super) fn login_error(provider: &str, e: AppError) -> Redirect {
+    tracing::warn!("SSO login through {} failed: {}", provider, e);
+    let message = match &e {
+        AppError::BadRequest(m)
+        | AppError::Unauthorized(m)
+        | AppError::Forbidden(m)
+        | AppError::Conflict(m) => m.as_str(),
+        _ => "Single sign-on failed",
+    };
 
-#[derive(Debug, Serialize, Deserialize)]
-struct UserInfoResponse {
-    sub: String,
-    email: Option<String>,
-    name: Option<String>,
-    preferred_username: Option<String>,
-    picture: Option<String>,
+    // 303 so a failed SAML POST turns into a GET of the login page
+    Redirect::to(&format!("/login?error={}", urlencoding::encode(message)))
 }
 
 /// Handle OAuth callback from provider
 async fn oauth_callback(
     State(state): State<AppState>,
+    headers: HeaderMap,
+    ClientIp(client_ip): ClientIp,
     Path(provider): Path<String>,
     Query(query): Query<OAuthCallbackQuery>,
-) -> Result<Redirect, AppError> {
+) -> Response {
+    let clear_cookie = [(header::SET_COOKIE, state_cookie("", 0, false))];
+    let session = SessionMetadata::from_request(&headers, client_ip);
+    let redirect = match complete_login(&state, &headers, session, &provider, query).await {
+        Ok(redirect) => redirect,
+        Err(e) => login_error(&provider, e),
+    };
+
+    (clear_cookie, redirect).into_response()
+}
+
+async fn complete_login(
+    state: &AppState,
+    headers: &HeaderMap,
+    session: SessionMetadata,
+    provider: &str,
+    query: OAuthCallbackQuery,
+) -> ApiResult<Redirect> {
     // Check for errors from provider
     if let Some(error) = query.error {
-        let desc = query.error_description.unwrap_or_else(|| error.clone());
-        return Ok(Redirect::temporary(&format!(
-            "/login?error={}",
-            urlencoding::encode(&desc)
-        )));
+        let desc = query.error_description.unwrap_or(error);
+        return Err(AppError::Unauthorized(desc));
     }
 
+    let oauth_state = query
+        .state
+        .ok_or_else(|| AppError::BadRequest("Missing state".to_string()))?;
+    if cookie_value(headers, STATE_COOKIE) != Some(oauth_state.as_str()) {
+        return Err(AppError::BadRequest(
+            "Login was started in another browser".to_string(),
+        ));
+    }
+    let login = sso::take_login_state(&state.db, &oauth_state).await?;
     let code = query
         .code
         .ok_or_else(|| AppError::BadRequest("Missing authorization code".to_string()))?;
 
-    // Get SSO config
-    let config: SsoConfigRow =
-        sqlx::query_as("SELECT * FROM sso_configs WHERE provider = $1 AND is_active = true")
-            .bind(&provider)
-            .fetch_optional(&state.db)
-            .await?
-            .ok_or_else(|| {
-                AppError::NotFound(format!("OAuth provider '{}' not found", provider))
-            })?;
-
+    let config = sso::find_config(&state.db, provider, "oidc").await?;
+    if config.id != login.sso_config_id {
+        return Err(AppError::BadRequest(
+            "Login was started with another provider".to_string(),
+        ));
+    }
     let issuer = config
         .issuer_url
-        .unwrap_or_else(|| format!("https://{}.example.com", provider));
-
-    let callback_url = format!(
-        "{}/api/v1/oauth2/{}/callback",
-        std::env::var("RUSTCHAT_SITE_URL").unwrap_or_else(|_| "http://localhost:8080".to_string()),
-        provider
-    );
+        .as_deref()
+        .ok_or_else(|| AppError::Config("SSO provider has no issuer URL".to_string()))?;
+    let client_id = config.client_id.as_deref().unwrap_or_default();
+    let client_secret = match config.client_secret_encrypted.as_deref() {
+        Some(secret) if !secret.is_empty() => crate::crypto::decrypt(secret, &state.encryption_key)
+            .map_err(|_| {
+                AppError::Config(
+                    "SSO client secret could not be decrypted, please save it again".to_string(),
+                )
+            })?,
+        _ => String::new(),
+    };
 
-    // Exchange code for tokens
-    let client = reqwest::Client::new();
-    let token_url = format!("{}/token", issuer);
-
-    let token_response = client
-        .post(&token_url)
-        .form(&[
-            ("grant_type", "authorization_code"),
-            ("code", &code),
-            ("redirect_uri", &callback_url),
-            ("client_id", &config.client_id),
-            ("client_secret", &config.client_secret_encrypted), // TODO: decrypt
-        ])
-        .send()
-        .await
-        .map_err(|e| AppError::Internal(format!("Token exchange failed: {}", e)))?;
-
-    if !token_response.status().is_success() {
-        let error_text = token_response.text().await.unwrap_or_default();
-        return Err(AppError::Internal(format!(
-            "Token exchange failed: {}",
-            error_text
-        )));
-    }
+    let metadata = oidc::discover(&state.http_client, issuer).await?;
+    let tokens = oidc::exchange_code(
+        &state.http_client,
+        &metadata,
+        client_id,
+        &client_secret,
+        &code,
+        &callback_url(state, provider).await?,
+        login.code_verifier.as_deref().unwrap_or_default(),
+    )
+    .await?;
+    let id_token = tokens
+        .id_token
+        .ok_or_else(|| AppError::ExternalService("Provider returned no ID token".to_string()))?;
+    let mut claims = oidc::validate_id_token(
+        &state.http_client,
+        &metadata,
+        client_id,
+        &id_token,
+        &login.nonce,
+    )
+    .await?;
+    oidc::merge_userinfo(
+        &state.http_client,
+        &metadata,
+        &tokens.access_token,
+        &mut claims,
+    )
+    .await?;
+    let identity = oidc::map_claims(&config, &claims)?;
+
+    let user = sso::resolve_user(state, &config, &identity, login.link_user_id).await?;
+    let target = sso::finish_login(state, session, &login, &user).await?;
+
+    Ok(Redirect::temporary(&target))
+}
 
-    let tokens: TokenResponse = token_response
-        .json()
-        .await
-        .map_err(|e| AppError::Internal(format!("Failed to parse token response: {}", e)))?;
-
-    // Get user info
-    let userinfo_url = format!("{}/userinfo", issuer);
-    let userinfo_response: reqwest::Response = client
-        .get(&userinfo_url)
-        .bearer_auth(&tokens.access_token)
-        .send()
-        .await
-        .map_err(|e| AppError::Internal(format!("Userinfo request failed: {}", e)))?;
-
-    let userinfo: UserInfoResponse = userinfo_response
-        .json::<UserInfoResponse>()
-        .await
-        .map_err(|e| AppError::Internal(format!("Failed to parse userinfo: {}", e)))?;
-
-    // Find or create user
-    let email = userinfo
-        .email
-        .ok_or_else(|| AppError::BadRequest("Email not provided by OAuth provider".to_string()))?;
-
-    let user: Option<crate::models::User> = sqlx::query_as("SELECT * FROM users WHERE email = $1")
-        .bind(&email)
-        .fetch_optional(&state.db)
-        .await?;
-
-    let user = match user {
-        Some(u) => u,
-        None => {
-            // Create new user from OAuth info
-            let username = userinfo
-                .preferred_username
-                .or(userinfo.name.clone())
-                .unwrap_or_else(|| email.split('@').next().unwrap_or("user").to_string());
-
-            sqlx::query_as(
-                r#"
-                INSERT INTO users (username, email, display_name, role, is_active, auth_provider)
-                VALUES ($1, $2, $3, 'member', true, $4)
-                ON CONFLICT (email) DO UPDATE SET last_login_at = NOW()
-                RETURNING *
-                "#,
-            )
-            .bind(&username)
-            .bind(&email)
-            .bind(&userinfo.name)
-            .bind(&provider)
-            .fetch_one(&state.db)
-            .await?
-        }
-    };
+/// List the SSO identities linked to the current user
+async fn list_identities(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> ApiResult<Json<Vec<UserIdentity>>> {
+    Ok(Json(sso::list_identities(&state.db, auth.user_id).await?))
+}
 
-    // Update last login
-    sqlx::query("UPDATE users SET last_login_at = NOW() WHERE id = $1")
-        .bind(user.id)
-        .execute(&state.db)
-        .await?;
-
-    // Generate JWT token
-    let token = crate::auth::create_token(
-        user.id,
-        &user.email,
-        &user.role,
-        user.org_id,
-        &state.jwt_secret,
-        state.jwt_expiry_hours,
-    )?;
+/// Unlink one of the current user's SSO identities
+async fn unlink_identity(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> ApiResult<Json<serde_json::Value>> {
+    sso::unlink_identity(&state.db, auth.user_id, id).await?;
 
-    // Redirect to frontend with token
-    Ok(Redirect::temporary(&format!(
-        "/oauth/callback?token={}",
-        token
-    )))
+    Ok(Json(serde_json::json!({"status": "unlinked"})))
 }
@@ -15,6 +15,7 @@ use crate::models::{
     PlaybookChecklist, PlaybookFull, PlaybookRun, PlaybookTask, RunProgress, RunStatusUpdate,
     RunTask, RunWithTasks, StartRun, UpdatePlaybook, UpdateRun, UpdateRunTask,
 };
+use crate::services::permissions::{Permission, Principal, Scope};
 
 #[derive(serde::Deserialize)]
 pub struct TeamQuery {
@@ -171,10 +172,13 @@ async fn update_playbook(
         .await?
         .ok_or_else(|| AppError::NotFound("Playbook not found".to_string()))?;
 
-    if current.created_by != auth.user_id && auth.role != "system_admin" {
-        return Err(AppError::Forbidden(
-            "Only the creator can edit this playbook".to_string(),
-        ));
+    if current.created_by != auth.user_id {
+        auth.require(
+            &state,
+            Permission::PlaybookManage,
+            Scope::Team(current.team_id),
+        )
+        .await?;
     }
 
     let playbook = sqlx::query_as::<_, Playbook>(
@@ -212,15 +216,16 @@ async fn delete_playbook(
     Path(id): Path<Uuid>,
 ) -> ApiResult<Json<serde_json::Value>> {
     // Check ownership
-    let current = sqlx::query!("SELECT created_by FROM playbooks WHERE id = $1", id)
-        .fetch_optional(&state.db)
-        .await?
-        .ok_or_else(|| AppError::NotFound("Playbook not found".to_string()))?;
-
-    if current.created_by != auth.user_id && auth.role != "system_admin" {
-        return Err(AppError::Forbidden(
-            "Only the creator can archive this playbook".to_string(),
-        ));
+    let (created_by, team_id): (Uuid, Uuid) =
+        sqlx::query_as("SELECT created_by, team_id FROM playbooks WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&state.db)
+            .await?
+            .ok_or_else(|| AppError::NotFound("Playbook not found".to_string()))?;
+
+    if created_by != auth.user_id {
+        auth.require(&state, Permission::PlaybookManage, Scope::Team(team_id))
+            .await?;
     }
 
     sqlx::query("UPDATE playbooks SET is_archived = true WHERE id = $1")
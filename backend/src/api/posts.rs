@@ -15,6 +15,7 @@ use crate::error::{ApiResult, AppError};
 use crate::models::{
     ChannelMember, CreatePost, CreateReaction, Post, PostResponse, Reaction, UpdatePost,
 };
+use crate::services::permissions::{Permission, Principal, Scope};
 
 /// Build posts routes
 pub fn router() -> Router<AppState> {
@@ -234,16 +235,27 @@ async fn update_post(
     Path(id): Path<Uuid>,
     Json(input): Json<UpdatePost>,
 ) -> ApiResult<Json<Post>> {
-    let post: Post = sqlx::query_as("SELECT * FROM posts WHERE id = $1 AND deleted_at IS NULL")
-        .bind(id)
-        .fetch_optional(&state.db)
-        .await?
-        .ok_or_else(|| AppError::NotFound("Post not found".to_string()))?;
+    let post: Post = sqlx::query_as(
+        r#"
+        SELECT id, channel_id, user_id, root_post_id, message, props, file_ids,
+               is_pinned, created_at, edited_at, deleted_at,
+               reply_count::int8 as reply_count,
+               last_reply_at, seq
+        FROM posts WHERE id = $1 AND deleted_at IS NULL
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Post not found".to_string()))?;
 
-    // Only author can edit
-    if post.user_id != auth.user_id && auth.role != "system_admin" {
-        return Err(AppError::Forbidden("Cannot edit this post".to_string()));
-    }
+    let permission = if post.user_id == auth.user_id {
+        Permission::PostEditOwn
+    } else {
+        Permission::PostEditOthers
+    };
+    auth.require(&state, permission, Scope::Channel(post.channel_id))
+        .await?;
 
     let updated: Post = sqlx::query_as(
         r#"
@@ -301,10 +313,13 @@ async fn delete_post(
     .await?
     .ok_or_else(|| AppError::NotFound("Post not found".to_string()))?;
 
-    // Only author or admin can delete
-    if post.user_id != auth.user_id && auth.role != "system_admin" {
-        return Err(AppError::Forbidden("Cannot delete this post".to_string()));
-    }
+    let permission = if post.user_id == auth.user_id {
+        Permission::PostDeleteOwn
+    } else {
+        Permission::PostDeleteOthers
+    };
+    auth.require(&state, permission, Scope::Channel(post.channel_id))
+        .await?;
 
     sqlx::query("UPDATE posts SET deleted_at = NOW() WHERE id = $1")
         .bind(id)
@@ -495,6 +510,23 @@ async fn remove_reaction(
     Ok(Json(serde_json::json!({"status": "removed"})))
 }
 
+/// Check that the caller is in the channel and may pin its posts
+async fn ensure_can_pin(state: &AppState, auth: &AuthUser, channel_id: Uuid) -> ApiResult<()> {
+    let is_member: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM channel_members WHERE channel_id = $1 AND user_id = $2)",
+    )
+    .bind(channel_id)
+    .bind(auth.user_id)
+    .fetch_one(&state.db)
+    .await?;
+    if !is_member {
+        return Err(AppError::Forbidden("Not a member of this channel".to_string()));
+    }
+
+    auth.require(state, Permission::PostPin, Scope::Channel(channel_id))
+        .await
+}
+
 /// Pin a post
 async fn pin_post(
     State(state): State<AppState>,
@@ -515,18 +547,7 @@ async fn pin_post(
     .await?
     .ok_or_else(|| AppError::NotFound("Post not found".to_string()))?;
 
-    // Check admin membership
-    let member: ChannelMember =
-        sqlx::query_as("SELECT * FROM channel_members WHERE channel_id = $1 AND user_id = $2")
-            .bind(post.channel_id)
-            .bind(auth.user_id)
-            .fetch_optional(&state.db)
-            .await?
-            .ok_or_else(|| AppError::Forbidden("Not a member of this channel".to_string()))?;
-
-    if member.role != "admin" && auth.role != "system_admin" {
-        return Err(AppError::Forbidden("Only admins can pin posts".to_string()));
-    }
+    ensure_can_pin(&state, &auth, post.channel_id).await?;
 
     let pinned: Post = sqlx::query_as(
         r#"
@@ -582,20 +603,7 @@ async fn unpin_post(
     .await?
     .ok_or_else(|| AppError::NotFound("Post not found".to_string()))?;
 
-    // Check admin membership
-    let member: ChannelMember =
-        sqlx::query_as("SELECT * FROM channel_members WHERE channel_id = $1 AND user_id = $2")
-            .bind(post.channel_id)
-            .bind(auth.user_id)
-            .fetch_optional(&state.db)
-            .await?
-            .ok_or_else(|| AppError::Forbidden("Not a member of this channel".to_string()))?;
-
-    if member.role != "admin" && auth.role != "system_admin" {
-        return Err(AppError::Forbidden(
-            "Only admins can unpin posts".to_string(),
-        ));
-    }
+    ensure_can_pin(&state, &auth, post.channel_id).await?;
 
     let unpinned: Post = sqlx::query_as(
         r#"
@@ -226,6 +226,33 @@ async fn clear_my_status(
     Ok(Json(user_status))
 }
 
+/// Set a user's presence and broadcast the change
+pub(crate) async fn set_user_presence(
+    state: &AppState,
+    user_id: Uuid,
+    presence: &str,
+) -> ApiResult<()> {
+    sqlx::query("UPDATE users SET presence = $1, updated_at = NOW() WHERE id = $2")
+        .bind(presence)
+        .bind(user_id)
+        .execute(&state.db)
+        .await?;
+
+    state.ws_hub.set_presence(user_id, presence.to_string()).await;
+
+    let event = WsEnvelope::event(
+        EventType::UserPresence,
+        PresenceEvent {
+            user_id,
+            status: presence.to_string(),
+        },
+        None,
+    );
+    state.ws_hub.broadcast(event).await;
+
+    Ok(())
+}
+
 /// Get another user's status
 async fn get_user_status(
     State(state): State<AppState>,
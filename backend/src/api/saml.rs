@@ -0,0 +1,336 @@
+//! SAML 2.0 service provider handlers
+
+use axum::{
+    extract::{Form, Path, Query, RawQuery, State},
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::oauth::{cookie_value, local_path, login_error, site_url, OAuthLoginQuery};
+use super::AppState;
+use crate::auth::AuthUser;
+use crate::error::{ApiResult, AppError};
+use crate::models::SsoConfig;
+use crate::services::client_address::ClientIp;
+use crate::services::saml::{self, IdpMetadata, LogoutRequest, RedirectMessage, ServiceProvider};
+use crate::services::sessions::{self, SessionMetadata};
+use crate::services::sso::{self, LoginState};
+
+/// Cookie that ties the assertion to the browser that started the login
+const STATE_COOKIE: &str = "rustchat_saml_state";
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/saml/{provider}/metadata", get(sp_metadata))
+        .route("/saml/{provider}/login", get(saml_login))
+        .route("/saml/{provider}/link", post(link_identity))
+        .route("/saml/{provider}/acs", post(assertion_consumer))
+        .route("/saml/{provider}/slo", get(single_logout))
+        .route("/saml/{provider}/logout", post(logout))
+}
+
+/// Both sides of the exchange for an active SAML config
+async fn load(
+    state: &AppState,
+    provider: &str,
+) -> ApiResult<(SsoConfig, ServiceProvider, IdpMetadata)> {
+    let config = sso::find_config(&state.db, provider, "saml").await?;
+    let sp = ServiceProvider::new(&config, &site_url(state).await?, &state.encryption_key)?;
+    let metadata_url = config
+        .idp_metadata_url
+        .as_deref()
+        .ok_or_else(|| AppError::Config("SAML provider has no metadata URL".to_string()))?;
+    let idp = saml::fetch_metadata(
+        &state.http_client,
+        metadata_url,
+        config.idp_entity_id.as_deref(),
+    )
+    .await?;
+
+    Ok((config, sp, idp))
+}
+
+/// SP metadata for registering rustchat with the identity provider
+///
+/// Served for inactive configs too, since the provider usually has to be
+/// set up before the config is turned on.
+async fn sp_metadata(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+) -> ApiResult<Response> {
+    let config: SsoConfig = sqlx::query_as(
+        "SELECT * FROM sso_configs WHERE provider = 'saml' AND (provider = $1 OR id::text = $1) ORDER BY created_at LIMIT 1",
+    )
+    .bind(&provider)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::NotFound("SAML provider not found".to_string()))?;
+    let sp = ServiceProvider::new(&config, &site_url(&state).await?, &state.encryption_key)?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/samlmetadata+xml")],
+        sp.metadata(),
+    )
+        .into_response())
+}
+
+/// The state cookie for a login, or one that clears it when `max_age` is 0
+///
+/// The identity provider posts the response from its own site, so over
+/// HTTPS the cookie must be `SameSite=None` to come along. Plain HTTP
+/// cannot use that, and leaves the attribute to the browser's default.
+fn state_cookie(value: &str, max_age: i64, secure: bool) -> HeaderValue {
+    let cookie = format!(
+        "{}={}; Path=/api/v1/saml; Max-Age={}; HttpOnly{}",
+        STATE_COOKIE,
+        value,
+        max_age,
+        if secure {
+            "; SameSite=None; Secure"
+        } else {
+            ""
+        }
+    );
+    HeaderValue::from_str(&cookie).expect("cookie is ASCII")
+}
+
+/// Store a new login and build the AuthnRequest URL
+async fn start_login(
+    state: &AppState,
+    provider: &str,
+    redirect_to: Option<String>,
+    link_user_id: Option<Uuid>,
+) -> ApiResult<(String, HeaderValue)> {
+    sso::ensure_enabled(&state.db).await?;
+    let (config, sp, idp) = load(state, provider).await?;
+
+    let request_id = saml::new_request_id();
+    let relay_state = sso::save_login_state(
+        &state.db,
+        &LoginState {
+            sso_config_id: config.id,
+            nonce: request_id.clone(),
+            code_verifier: None,
+            redirect_to: local_path(redirect_to),
+            link_user_id,
+        },
+    )
+    .await?;
+
+    let url = sp.authn_request_url(&idp, &request_id, &relay_state)?;
+    let cookie = state_cookie(
+        &relay_state,
+        sso::LOGIN_STATE_EXPIRY_MINUTES as i64 * 60,
+        sp.acs_url.starts_with("https://"),
+    );
+
+    Ok((url, cookie))
+}
+
+/// Initiate SAML login - redirects to the identity provider
+async fn saml_login(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    Query(query): Query<OAuthLoginQuery>,
+) -> ApiResult<Response> {
+    let (url, cookie) = start_login(&state, &provider, query.redirect_to, None).await?;
+
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::temporary(&url)).into_response())
+}
+
+/// Start linking a SAML identity to the signed-in account
+async fn link_identity(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(provider): Path<String>,
+    Query(query): Query<OAuthLoginQuery>,
+) -> ApiResult<Response> {
+    let redirect_to = query
+        .redirect_to
+        .or_else(|| Some("/settings/profile".to_string()));
+    let (url, cookie) = start_login(&state, &provider, redirect_to, Some(auth.user_id)).await?;
+
+    Ok((
+        [(header::SET_COOKIE, cookie)],
+        Json(serde_json::json!({ "authorization_url": url })),
+    )
+        .into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AcsForm {
+    #[serde(rename = "SAMLResponse")]
+    pub saml_response: String,
+    #[serde(rename = "RelayState")]
+    pub relay_state: Option<String>,
+}
+
+/// Receive the identity provider's response over HTTP-POST
+async fn assertion_consumer(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ClientIp(client_ip): ClientIp,
+    Path(provider): Path<String>,
+    Form(form): Form<AcsForm>,
+) -> Response {
+    let clear_cookie = [(header::SET_COOKIE, state_cookie("", 0, false))];
+    let session = SessionMetadata::from_request(&headers, client_ip);
+    let redirect = match complete_login(&state, &headers, session, &provider, form).await {
+        // 303 so the browser follows with a GET
+        Ok(target) => Redirect::to(&target),
+        Err(e) => login_error(&provider, e),
+    };
+
+    (clear_cookie, redirect).into_response()
+}
+
+async fn complete_login(
+    state: &AppState,
+    headers: &HeaderMap,
+    session: SessionMetadata,
+    provider: &str,
+    form: AcsForm,
+) -> ApiResult<String> {
+    // The relay state is how a response finds the login that asked for it
+    let relay_state = form.relay_state.filter(|r| !r.is_empty()).ok_or_else(|| {
+        AppError::BadRequest(
+            "Logins started at the identity provider are not supported".to_string(),
+        )
+    })?;
+    if cookie_value(headers, STATE_COOKIE) != Some(relay_state.as_str()) {
+        return Err(AppError::BadRequest(
+            "Login was started in another browser".to_string(),
+        ));
+    }
+    let login = sso::take_login_state(&state.db, &relay_state).await?;
+
+    let (config, sp, idp) = load(state, provider).await?;
+    if config.id != login.sso_config_id {
+        return Err(AppError::BadRequest(
+            "Login was started with another provider".to_string(),
+        ));
+    }
+
+    let assertion = saml::validate_response(
+        &sp,
+        &idp,
+        &form.saml_response,
+        &login.nonce,
+        chrono::Utc::now(),
+    )?;
+    let identity = saml::map_attributes(&config, &assertion)?;
+    let user = sso::resolve_user(state, &config, &identity, login.link_user_id).await?;
+
+    sso::finish_login(state, session, &login, &user).await
+}
+
+/// Single logout endpoint for messages from the identity provider
+///
+/// A logout request ends every session of the user it names; a logout
+/// response completes one we started.
+async fn single_logout(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    RawQuery(query): RawQuery,
+) -> Redirect {
+    match handle_logout_message(&state, &provider, query.as_deref().unwrap_or_default()).await {
+        Ok(target) => Redirect::to(&target),
+        Err(e) => login_error(&provider, e),
+    }
+}
+
+async fn handle_logout_message(state: &AppState, provider: &str, query: &str) -> ApiResult<String> {
+    let (config, sp, idp) = load(state, provider).await?;
+
+    match saml::read_redirect_message(&idp, query)? {
+        RedirectMessage::Request { xml, relay_state } => {
+            let request = saml::parse_logout_request(&idp, &xml, chrono::Utc::now())?;
+            claim_logout_request(state, config.id, &request).await?;
+            let user_id: Option<Uuid> = sqlx::query_scalar(
+                "SELECT user_id FROM user_identities WHERE sso_config_id = $1 AND subject = $2",
+            )
+            .bind(config.id)
+            .bind(&request.name_id)
+            .fetch_optional(&state.db)
+            .await?;
+            if let Some(user_id) = user_id {
+                sessions::revoke_all_sessions(state, user_id).await?;
+            }
+
+            let response = sp.logout_response_url(&idp, &request.id, relay_state.as_deref())?;
+            Ok(response.unwrap_or_else(|| "/login".to_string()))
+        }
+        RedirectMessage::Response { xml } => {
+            saml::check_logout_response(&idp, &xml)?;
+            Ok("/login".to_string())
+        }
+    }
+}
+
+/// Record a logout request's ID, refusing one that was already acted on
+///
+/// IDs are kept until the request would be too old to accept anyway.
+async fn claim_logout_request(
+    state: &AppState,
+    sso_config_id: Uuid,
+    request: &LogoutRequest,
+) -> ApiResult<()> {
+    sqlx::query("DELETE FROM saml_logout_requests WHERE expires_at < NOW()")
+        .execute(&state.db)
+        .await?;
+
+    let claimed = sqlx::query(
+        r#"
+        INSERT INTO saml_logout_requests (sso_config_id, request_id, expires_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (sso_config_id, request_id) DO NOTHING
+        "#,
+    )
+    .bind(sso_config_id)
+    .bind(&request.id)
+    .bind(request.expires_at)
+    .execute(&state.db)
+    .await?
+    .rows_affected();
+    if claimed == 0 {
+        return Err(AppError::Unauthorized(
+            "Invalid SAML response: logout request was already used".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Sign out here and return where to end the session at the identity provider
+///
+/// `logout_url` is null when the provider has no single logout or the user
+/// has no identity from it.
+async fn logout(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(provider): Path<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+    match sessions::revoke_session(&state, auth.user_id, auth.session_id).await {
+        Ok(()) | Err(AppError::NotFound(_)) => {}
+        Err(e) => return Err(e),
+    }
+
+    let (config, sp, idp) = load(&state, &provider).await?;
+    let name_id: Option<String> = sqlx::query_scalar(
+        "SELECT subject FROM user_identities WHERE sso_config_id = $1 AND user_id = $2",
+    )
+    .bind(config.id)
+    .bind(auth.user_id)
+    .fetch_optional(&state.db)
+    .await?;
+    let logout_url = match name_id {
+        Some(name_id) => sp.logout_request_url(&idp, &name_id)?,
+        None => None,
+    };
+
+    Ok(Json(serde_json::json!({ "logout_url": logout_url })))
+}
@@ -0,0 +1,98 @@
+//! Signed links to locally stored files
+//!
+//! The local storage backend hands out links to these routes instead of
+//! presigned S3 URLs. The signature in the query is the only credential.
+
+use axum::{
+    body::Body,
+    extract::{DefaultBodyLimit, Path, Query, State},
+    http::{
+        header::{CACHE_CONTROL, CONTENT_SECURITY_POLICY, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS},
+        HeaderMap, StatusCode,
+    },
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use serde::Deserialize;
+use tokio_util::io::ReaderStream;
+
+use super::AppState;
+use crate::error::ApiResult;
+use crate::services::uploads;
+use crate::storage::{verify_url, ObjectWriter, LOCAL_BACKEND};
+
+/// Build storage routes
+pub fn router() -> Router<AppState> {
+    // Uploads are held to the site's file size limit instead
+    Router::new().route(
+        "/storage/{*key}",
+        get(download).put(upload).layer(DefaultBodyLimit::disable()),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct SignedLink {
+    expires: i64,
+    signature: String,
+}
+
+/// Serve a stored file, streamed from disk
+async fn download(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Query(link): Query<SignedLink>,
+) -> ApiResult<impl IntoResponse> {
+    verify_url(
+        &state.encryption_key,
+        "GET",
+        &key,
+        link.expires,
+        &link.signature,
+    )?;
+
+    let reader = state.storage.backend(LOCAL_BACKEND)?.reader(&key).await?;
+    let content_type = mime_guess::from_path(&key).first_or_octet_stream();
+
+    // Uploaded HTML must not run scripts on this origin
+    Ok((
+        [
+            (CONTENT_TYPE, content_type.to_string()),
+            (CACHE_CONTROL, "private, max-age=3600".to_string()),
+            (X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            (CONTENT_SECURITY_POLICY, "sandbox".to_string()),
+        ],
+        Body::from_stream(ReaderStream::new(reader)),
+    ))
+}
+
+/// Store a file uploaded to a presigned link
+///
+/// The body is streamed to disk and refused once it passes the site's file
+/// size limit.
+async fn upload(
+    State(state): State<AppState>,
+    Path(key): Path<String>,
+    Query(link): Query<SignedLink>,
+    headers: HeaderMap,
+    body: Body,
+) -> ApiResult<StatusCode> {
+    verify_url(
+        &state.encryption_key,
+        "PUT",
+        &key,
+        link.expires,
+        &link.signature,
+    )?;
+
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("application/octet-stream");
+    let limit = uploads::max_file_size(&state).await?;
+    let backend = state.storage.backend(LOCAL_BACKEND)?;
+    let writer = ObjectWriter::new(backend, &key, content_type, limit);
+    uploads::write_stream(writer, body.into_data_stream()).await?;
+
+    Ok(StatusCode::OK)
+}
@@ -12,6 +12,10 @@ use crate::{
     auth::middleware::AuthUser,
     error::AppError,
     models::team::{AddTeamMember, CreateTeam, Team, TeamMember, TeamMemberResponse},
+    models::User,
+    services::guests,
+    services::team_invites::{self, Invite},
+    services::permissions::{CanCreateTeam, Permission, Principal, Require, Scope},
 };
 
 pub fn router() -> Router<AppState> {
@@ -23,6 +27,10 @@ pub fn router() -> Router<AppState> {
         .route("/{id}/leave", post(leave_team))
         .route("/{id}/members", get(get_members).post(add_member))
         .route("/{id}/members/{user_id}", delete(remove_member))
+        .route("/{id}/invite", get(get_invite_id))
+        .route("/{id}/invite/regenerate", post(regenerate_invite_id))
+        .route("/{id}/invite/email", post(invite_by_email))
+        .route("/invite/accept", post(accept_invite))
         .route("/{team_id}/channels", get(list_team_channels))
 }
 
@@ -49,7 +57,7 @@ async fn list_teams(
 /// Create a new team
 async fn create_team(
     State(state): State<AppState>,
-    auth: AuthUser,
+    Require(auth, _): Require<CanCreateTeam>,
     Json(payload): Json<CreateTeam>,
 ) -> Result<Json<Team>, AppError> {
     let team_id = Uuid::new_v4();
@@ -129,9 +137,12 @@ async fn get_team(
 /// Delete a team
 async fn delete_team(
     State(state): State<AppState>,
-    _auth: AuthUser,
+    auth: AuthUser,
     Path(id): Path<Uuid>,
 ) -> Result<(), AppError> {
+    auth.require(&state, Permission::TeamManage, Scope::Team(id))
+        .await?;
+
     sqlx::query("DELETE FROM teams WHERE id = $1")
         .bind(id)
         .execute(&state.db)
@@ -170,20 +181,8 @@ async fn add_member(
     Path(id): Path<Uuid>,
     Json(payload): Json<AddTeamMember>,
 ) -> Result<Json<TeamMember>, AppError> {
-    // Permission check
-    if auth.role != "system_admin" && auth.role != "org_admin" {
-        let requester_role: Option<String> =
-            sqlx::query_scalar("SELECT role FROM team_members WHERE team_id = $1 AND user_id = $2")
-                .bind(id)
-                .bind(auth.user_id)
-                .fetch_optional(&state.db)
-                .await?;
-
-        match requester_role.as_deref() {
-            Some("admin") | Some("owner") => {} // Allow
-            _ => return Err(AppError::Forbidden("Only admins can add members".into())),
-        }
-    }
+    auth.require(&state, Permission::TeamManageMembers, Scope::Team(id))
+        .await?;
 
     let member = sqlx::query_as::<_, TeamMember>(
         r#"
@@ -198,17 +197,20 @@ async fn add_member(
     .fetch_one(&state.db)
     .await?;
 
-    // Also add user to all public channels in the team
+    // Also add user to all public channels in the team, unless they are a
+    // guest and only get the channels they are explicitly added to
     sqlx::query(
         r#"
         INSERT INTO channel_members (channel_id, user_id)
         SELECT c.id, $1 FROM channels c
-        WHERE c.team_id = $2 AND c.channel_type = 'public'::channel_type
+        WHERE c.team_id = $2 AND c.type = 'public'::channel_type
+          AND NOT EXISTS (SELECT 1 FROM users WHERE id = $1 AND role = $3)
         ON CONFLICT (channel_id, user_id) DO NOTHING
         "#,
     )
     .bind(payload.user_id)
     .bind(id)
+    .bind(guests::GUEST_ROLE)
     .execute(&state.db)
     .await?;
 
@@ -221,33 +223,23 @@ async fn remove_member(
     auth: AuthUser,
     Path((id, user_id)): Path<(Uuid, Uuid)>,
 ) -> Result<(), AppError> {
-    // Permission check
-    if auth.role != "system_admin" && auth.role != "org_admin" {
-        let requester_role: Option<String> =
-            sqlx::query_scalar("SELECT role FROM team_members WHERE team_id = $1 AND user_id = $2")
-                .bind(id)
-                .bind(auth.user_id)
-                .fetch_optional(&state.db)
-                .await?;
+    auth.require(&state, Permission::TeamManageMembers, Scope::Team(id))
+        .await?;
 
-        match requester_role.as_deref() {
-            Some("admin") | Some("owner") => {
-                // Check target role
-                let target_role: Option<String> = sqlx::query_scalar(
-                    "SELECT role FROM team_members WHERE team_id = $1 AND user_id = $2",
-                )
+    // Team admins cannot remove each other, only a system-wide grant can
+    if !auth
+        .can(&state, Permission::TeamManageMembers, Scope::System)
+        .await?
+    {
+        let target_role: Option<String> =
+            sqlx::query_scalar("SELECT role FROM team_members WHERE team_id = $1 AND user_id = $2")
                 .bind(id)
                 .bind(user_id)
                 .fetch_optional(&state.db)
                 .await?;
 
-                if let Some(target) = target_role {
-                    if target == "admin" || target == "owner" {
-                        return Err(AppError::Forbidden("Cannot remove other admins".into()));
-                    }
-                }
-            }
-            _ => return Err(AppError::Forbidden("Only admins can remove members".into())),
+        if matches!(target_role.as_deref(), Some("admin") | Some("owner")) {
+            return Err(AppError::Forbidden("Cannot remove other admins".into()));
         }
     }
 
@@ -285,8 +277,12 @@ async fn list_team_channels(
 /// List all public teams that user can join
 async fn list_public_teams(
     State(state): State<AppState>,
-    _auth: AuthUser,
+    auth: AuthUser,
 ) -> Result<Json<Vec<Team>>, AppError> {
+    if guests::is_guest(&auth.role) {
+        return Ok(Json(Vec::new()));
+    }
+
     // Get all public teams, marking which ones user is already a member of
     let teams = sqlx::query_as::<_, Team>(
         r#"
@@ -307,6 +303,8 @@ async fn join_team(
     auth: AuthUser,
     Path(id): Path<Uuid>,
 ) -> Result<Json<TeamMember>, AppError> {
+    guests::ensure_not_guest(&auth.role)?;
+
     // Check if team exists and is public
     let team = sqlx::query_as::<_, Team>("SELECT * FROM teams WHERE id = $1")
         .bind(id)
@@ -350,7 +348,7 @@ async fn join_team(
         r#"
         INSERT INTO channel_members (channel_id, user_id)
         SELECT c.id, $1 FROM channels c
-        WHERE c.team_id = $2 AND c.channel_type = 'public'::channel_type
+        WHERE c.team_id = $2 AND c.type = 'public'::channel_type
         ON CONFLICT (channel_id, user_id) DO NOTHING
         "#,
     )
@@ -400,6 +398,7 @@ pub struct UpdateTeam {
     pub description: Option<String>,
     pub is_public: Option<bool>,
     pub allow_open_invite: Option<bool>,
+    pub allowed_domains: Option<String>,
 }
 
 /// Update a team
@@ -409,22 +408,8 @@ async fn update_team(
     Path(id): Path<Uuid>,
     Json(payload): Json<UpdateTeam>,
 ) -> Result<Json<Team>, AppError> {
-    // Check if user is admin of the team
-    let member: Option<TeamMember> =
-        sqlx::query_as("SELECT * FROM team_members WHERE team_id = $1 AND user_id = $2")
-            .bind(id)
-            .bind(auth.user_id)
-            .fetch_optional(&state.db)
-            .await?;
-
-    match member {
-        Some(m) if m.role == "admin" || m.role == "owner" => {}
-        _ => {
-            return Err(AppError::Forbidden(
-                "Only team admins can update team settings".into(),
-            ))
-        }
-    }
+    auth.require(&state, Permission::TeamManage, Scope::Team(id))
+        .await?;
 
     let team = sqlx::query_as::<_, Team>(
         r#"
@@ -434,8 +419,9 @@ async fn update_team(
             description = COALESCE($3, description),
             is_public = COALESCE($4, is_public),
             allow_open_invite = COALESCE($5, allow_open_invite),
+            allowed_domains = COALESCE($6, allowed_domains),
             updated_at = NOW()
-        WHERE id = $6
+        WHERE id = $7
         RETURNING *
         "#,
     )
@@ -444,9 +430,89 @@ async fn update_team(
     .bind(payload.description)
     .bind(payload.is_public)
     .bind(payload.allow_open_invite)
+    .bind(payload.allowed_domains.as_deref().map(team_invites::normalize_domains))
     .bind(id)
     .fetch_one(&state.db)
     .await?;
 
     Ok(Json(team))
 }
+
+/// Get the team's invite link ID
+async fn get_invite_id(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    auth.require(&state, Permission::TeamManageMembers, Scope::Team(id))
+        .await?;
+
+    let invite_id: String = sqlx::query_scalar("SELECT invite_id FROM teams WHERE id = $1")
+        .bind(id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Team not found".into()))?;
+
+    Ok(Json(serde_json::json!({ "invite_id": invite_id })))
+}
+
+/// Replace the team's invite link ID, revoking the old link
+async fn regenerate_invite_id(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    auth.require(&state, Permission::TeamManage, Scope::Team(id))
+        .await?;
+
+    let invite_id = team_invites::regenerate_invite_id(&state.db, id).await?;
+
+    Ok(Json(serde_json::json!({ "invite_id": invite_id })))
+}
+
+/// DTO for inviting people by email
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct InviteByEmail {
+    pub emails: Vec<String>,
+}
+
+/// Email invitations to join the team
+async fn invite_by_email(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<InviteByEmail>,
+) -> Result<Json<serde_json::Value>, AppError> {
+    auth.require(&state, Permission::TeamManageMembers, Scope::Team(id))
+        .await?;
+
+    team_invites::send_email_invites(&state, id, auth.user_id, &payload.emails).await?;
+
+    Ok(Json(serde_json::json!({"status": "sent"})))
+}
+
+/// DTO for accepting an invite, with the emailed token or the link's invite ID
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct AcceptInvite {
+    pub token: Option<String>,
+    pub invite_id: Option<String>,
+}
+
+/// Join a team through an invitation
+async fn accept_invite(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(payload): Json<AcceptInvite>,
+) -> Result<Json<TeamMember>, AppError> {
+    let invite = Invite::from_params(payload.token, payload.invite_id)
+        .ok_or_else(|| AppError::BadRequest("Missing invite token or invite_id".into()))?;
+    let pending = team_invites::resolve(&state.db, &invite).await?;
+    let user: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+        .bind(auth.user_id)
+        .fetch_one(&state.db)
+        .await?;
+
+    let member = team_invites::accept(&state, pending, &user).await?;
+
+    Ok(Json(member))
+}
@@ -10,6 +10,7 @@ use super::AppState;
 use crate::auth::{hash_password, AuthUser};
 use crate::error::{ApiResult, AppError};
 use crate::models::{ChangePassword, UpdateUser, User, UserResponse};
+use crate::services::permissions::{Permission, Principal, Scope};
 
 /// Build users routes
 pub fn router() -> Router<AppState> {
@@ -38,8 +39,8 @@ async fn list_users(
     let offset = ((page - 1) * per_page) as i64;
     let search_term = query.q.map(|s| format!("%{}%", s));
 
-    let users: Vec<User> = if auth.role == "system_admin" {
-        // System admin can see all users
+    let users: Vec<User> = if auth.can(&state, Permission::UserManage, Scope::System).await? {
+        // User managers can see all users
         if let Some(term) = search_term {
             sqlx::query_as(
                 "SELECT * FROM users WHERE username ILIKE $1 OR display_name ILIKE $1 ORDER BY created_at DESC LIMIT $2 OFFSET $3",
@@ -97,10 +98,10 @@ async fn get_user(
         .await?
         .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
 
-    // Check access: same user, same org, or system admin
+    // Check access: same user, same org, or a user manager
     let can_view = auth.user_id == user.id
-        || auth.role == "system_admin"
-        || (auth.org_id.is_some() && auth.org_id == user.org_id);
+        || (auth.org_id.is_some() && auth.org_id == user.org_id)
+        || auth.can(&state, Permission::UserManage, Scope::System).await?;
 
     if !can_view {
         return Err(AppError::Forbidden("Cannot view this user".to_string()));
@@ -116,8 +117,8 @@ async fn update_user(
     Path(id): Path<Uuid>,
     Json(input): Json<UpdateUser>,
 ) -> ApiResult<Json<UserResponse>> {
-    // Only the user themselves or an admin can update
-    if auth.user_id != id && auth.role != "system_admin" && auth.role != "org_admin" {
+    // Only the user themselves or a user manager can update
+    if auth.user_id != id && !auth.can(&state, Permission::UserManage, Scope::System).await? {
         return Err(AppError::Forbidden("Cannot update this user".to_string()));
     }
 
@@ -13,6 +13,7 @@ use crate::api::v4::extractors::MmAuthUser;
 use crate::error::{ApiResult};
 use crate::mattermost_compat::{id::{encode_mm_id}, models as mm};
 use crate::models::{Bot};
+use crate::services::permissions::{CanCreateBot, Require};
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
@@ -36,7 +37,7 @@ fn default_per_page() -> i64 {
 
 pub async fn create_bot(
     State(state): State<AppState>,
-    auth: MmAuthUser,
+    Require(auth, _): Require<CanCreateBot, MmAuthUser>,
     Json(input): Json<CreateBotRequest>,
 ) -> ApiResult<Json<mm::Bot>> {
     // 1. Create a user for the bot
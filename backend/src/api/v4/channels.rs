@@ -17,6 +17,8 @@ use crate::error::ApiResult;
 use crate::mattermost_compat::{id::{encode_mm_id, parse_mm_or_uuid}, models as mm};
 use crate::models::post::PostResponse;
 use crate::models::Channel;
+use crate::services::guests;
+use crate::services::permissions::{ensure_team_member, Permission, Principal, Scope};
 
 pub fn router() -> Router<AppState> {
     Router::new()
@@ -105,6 +107,12 @@ async fn view_channel(
             exclude_user_id: None,
         });
         state.ws_hub.broadcast(broadcast).await;
+
+        crate::services::push_notifications::spawn_clear_notifications(
+            &state,
+            auth.user_id,
+            channel_id,
+        );
     }
 
     Ok(Json(serde_json::json!({"status": "OK"})))
@@ -400,6 +408,7 @@ async fn create_direct_channel(
     }
 
     let other_id = if ids[0] == auth.user_id { ids[1] } else { ids[0] };
+    guests::ensure_can_message(&state.db, auth.user_id, &[other_id]).await?;
 
     let channel = create_direct_channel_internal(&state, auth.user_id, other_id).await?;
     Ok(Json(channel.into()))
@@ -468,6 +477,7 @@ async fn create_group_channel(
         .iter()
         .filter_map(|id| parse_mm_or_uuid(id))
         .collect();
+    guests::ensure_can_message(&state.db, auth.user_id, &uuids).await?;
 
     let channel = create_group_channel_internal(&state, auth.user_id, uuids).await?;
     Ok(Json(channel.into()))
@@ -570,6 +580,8 @@ async fn create_channel(
     if !is_member {
         return Err(crate::error::AppError::Forbidden("Not a member of this team".to_string()));
     }
+    auth.require(&state, Permission::ChannelCreate, Scope::Team(team_id))
+        .await?;
 
     // Map MM channel type to RustChat type
     let channel_type = match input.channel_type.as_str() {
@@ -630,14 +642,8 @@ async fn update_channel(
     let channel_id = parse_mm_or_uuid(&channel_id)
         .ok_or_else(|| crate::error::AppError::BadRequest("Invalid channel_id".to_string()))?;
 
-    // Verify membership
-    let _membership: crate::models::ChannelMember =
-        sqlx::query_as("SELECT * FROM channel_members WHERE channel_id = $1 AND user_id = $2")
-            .bind(channel_id)
-            .bind(auth.user_id)
-            .fetch_optional(&state.db)
-            .await?
-            .ok_or_else(|| crate::error::AppError::Forbidden("Not a member of this channel".to_string()))?;
+    auth.require(&state, Permission::ChannelManage, Scope::Channel(channel_id))
+        .await?;
 
     let input: UpdateChannelRequest = parse_body(&headers, &body, "Invalid channel update")?;
 
@@ -674,14 +680,8 @@ async fn delete_channel(
     let channel_id = parse_mm_or_uuid(&channel_id)
         .ok_or_else(|| crate::error::AppError::BadRequest("Invalid channel_id".to_string()))?;
 
-    // Verify membership (should be admin but simplified for now)
-    let _membership: crate::models::ChannelMember =
-        sqlx::query_as("SELECT * FROM channel_members WHERE channel_id = $1 AND user_id = $2")
-            .bind(channel_id)
-            .bind(auth.user_id)
-            .fetch_optional(&state.db)
-            .await?
-            .ok_or_else(|| crate::error::AppError::Forbidden("Not a member of this channel".to_string()))?;
+    auth.require(&state, Permission::ChannelDelete, Scope::Channel(channel_id))
+        .await?;
 
     // Soft delete the channel
     sqlx::query("UPDATE channels SET deleted_at = NOW() WHERE id = $1")
@@ -770,6 +770,8 @@ async fn pin_post(
             .fetch_optional(&state.db)
             .await?
             .ok_or_else(|| crate::error::AppError::Forbidden("Not a member of this channel".to_string()))?;
+    auth.require(&state, Permission::PostPin, Scope::Channel(channel_id))
+        .await?;
 
     // Pin the post
     sqlx::query("UPDATE posts SET is_pinned = true WHERE id = $1 AND channel_id = $2")
@@ -800,6 +802,8 @@ async fn unpin_post(
             .fetch_optional(&state.db)
             .await?
             .ok_or_else(|| crate::error::AppError::Forbidden("Not a member of this channel".to_string()))?;
+    auth.require(&state, Permission::PostPin, Scope::Channel(channel_id))
+        .await?;
 
     // Unpin the post
     sqlx::query("UPDATE posts SET is_pinned = false WHERE id = $1 AND channel_id = $2")
@@ -831,14 +835,26 @@ async fn add_channel_member(
     let user_id = parse_mm_or_uuid(&input.user_id)
         .ok_or_else(|| crate::error::AppError::BadRequest("Invalid user_id".to_string()))?;
 
-    // Verify caller is member of the channel
-    let _caller_member: crate::models::ChannelMember =
-        sqlx::query_as("SELECT * FROM channel_members WHERE channel_id = $1 AND user_id = $2")
-            .bind(channel_id)
-            .bind(auth.user_id)
-            .fetch_optional(&state.db)
-            .await?
-            .ok_or_else(|| crate::error::AppError::Forbidden("Not a member of this channel".to_string()))?;
+    // Anyone on the team may join a public channel; adding others needs
+    // permission
+    let channel: Channel = sqlx::query_as("SELECT * FROM channels WHERE id = $1")
+        .bind(channel_id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or_else(|| crate::error::AppError::NotFound("Channel not found".to_string()))?;
+    if matches!(
+        channel.channel_type,
+        crate::models::ChannelType::Public | crate::models::ChannelType::Private
+    ) {
+        ensure_team_member(&state, user_id, channel.team_id).await?;
+    }
+    let self_join = user_id == auth.user_id
+        && channel.channel_type == crate::models::ChannelType::Public
+        && !guests::is_guest(&auth.role);
+    if !self_join {
+        auth.require(&state, Permission::ChannelManageMembers, Scope::Channel(channel_id))
+            .await?;
+    }
 
     // Add the user
     sqlx::query(
@@ -890,15 +906,10 @@ async fn remove_channel_member(
     let user_id = parse_mm_or_uuid(&path.user_id)
         .ok_or_else(|| crate::error::AppError::BadRequest("Invalid user_id".to_string()))?;
 
-    // Verify caller is member of the channel (or is the user being removed)
+    // Users can always leave; removing others needs permission
     if auth.user_id != user_id {
-        let _caller_member: crate::models::ChannelMember =
-            sqlx::query_as("SELECT * FROM channel_members WHERE channel_id = $1 AND user_id = $2")
-                .bind(channel_id)
-                .bind(auth.user_id)
-                .fetch_optional(&state.db)
-                .await?
-                .ok_or_else(|| crate::error::AppError::Forbidden("Not a member of this channel".to_string()))?;
+        auth.require(&state, Permission::ChannelManageMembers, Scope::Channel(channel_id))
+            .await?;
     }
 
     // Remove the user
@@ -1095,21 +1106,30 @@ async fn get_posts(
 
 async fn search_channels_compat(
     State(state): State<AppState>,
-    _auth: MmAuthUser,
+    auth: MmAuthUser,
     Json(input): Json<HashMap<String, String>>,
 ) -> ApiResult<Json<Vec<mm::Channel>>> {
     let term = input.get("term").cloned().unwrap_or_default();
     let team_id_str = input.get("team_id").cloned();
-    
-    let mut sql = "SELECT * FROM channels WHERE name ILIKE $1".to_string();
+
+    // Guests only find the channels they are in
+    let mut sql = r#"
+        SELECT c.* FROM channels c
+        WHERE c.name ILIKE $1
+          AND ((c.type = 'public' AND NOT $3)
+               OR EXISTS (SELECT 1 FROM channel_members cm WHERE cm.channel_id = c.id AND cm.user_id = $2))
+    "#
+    .to_string();
     if let Some(tid_str) = team_id_str {
         if let Some(tid) = parse_mm_or_uuid(&tid_str) {
-            sql.push_str(&format!(" AND team_id = '{}'", tid));
+            sql.push_str(&format!(" AND c.team_id = '{}'", tid));
         }
     }
-    
+
     let channels: Vec<Channel> = sqlx::query_as(&sql)
         .bind(format!("%{}%", term))
+        .bind(auth.user_id)
+        .bind(guests::is_guest(&auth.role))
         .fetch_all(&state.db)
         .await?;
 
@@ -10,8 +10,10 @@ use crate::api::AppState;
 use crate::api::integrations::{execute_command_internal, CommandAuth};
 use crate::api::v4::extractors::MmAuthUser;
 use crate::error::{ApiResult, AppError};
-use crate::mattermost_compat::id::parse_mm_or_uuid;
-use crate::models::{CommandResponse, ExecuteCommand};
+use crate::mattermost_compat::{id::parse_mm_or_uuid, models as mm};
+use crate::models::{CommandResponse, ExecuteCommand, SlashCommand};
+use crate::services::builtin_commands::BUILTIN_COMMANDS;
+use crate::services::permissions::{Permission, Principal, Scope};
 
 pub fn router() -> Router<AppState> {
     Router::new()
@@ -26,6 +28,8 @@ pub fn router() -> Router<AppState> {
 #[derive(Deserialize)]
 struct CommandsQuery {
     team_id: Option<String>,
+    #[serde(default)]
+    custom_only: bool,
 }
 
 #[derive(Deserialize)]
@@ -33,6 +37,7 @@ struct ExecuteCommandRequest {
     command: String,
     channel_id: String,
     team_id: Option<String>,
+    root_id: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -47,20 +52,72 @@ struct TeamPath {
     team_id: String,
 }
 
-async fn list_commands(Query(_query): Query<CommandsQuery>) -> ApiResult<Json<Vec<serde_json::Value>>> {
-    let commands = vec![serde_json::json!({
-        "id": "builtin-call",
-        "trigger": "call",
-        "display_name": "Call",
-        "description": "Start a Mirotalk call",
-        "auto_complete": true,
-        "auto_complete_desc": "Start a Mirotalk call",
-        "auto_complete_hint": "[end]",
-    })];
+async fn list_commands(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Query(query): Query<CommandsQuery>,
+) -> ApiResult<Json<Vec<serde_json::Value>>> {
+    let mut commands = Vec::new();
+    if !query.custom_only {
+        commands.extend(BUILTIN_COMMANDS.iter().map(|command| {
+            serde_json::json!({
+                "id": format!("builtin-{}", command.trigger),
+                "trigger": command.trigger,
+                "display_name": command.display_name,
+                "description": command.description,
+                "auto_complete": true,
+                "auto_complete_desc": command.description,
+                "auto_complete_hint": command.hint,
+            })
+        }));
+    }
+
+    let Some(team_id) = query.team_id.as_deref() else {
+        return Ok(Json(commands));
+    };
+    let team_id = parse_mm_or_uuid(team_id)
+        .ok_or_else(|| AppError::BadRequest("Invalid team_id".to_string()))?;
+
+    let is_member: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM team_members WHERE team_id = $1 AND user_id = $2)",
+    )
+    .bind(team_id)
+    .bind(auth.user_id)
+    .fetch_one(&state.db)
+    .await?;
+    if !is_member {
+        auth.require(&state, Permission::CommandManage, Scope::Team(team_id))
+            .await?;
+    }
+
+    let custom = team_commands(&state, team_id).await?;
+    let manages_all = auth
+        .can(&state, Permission::CommandManage, Scope::Team(team_id))
+        .await?;
+    for command in custom {
+        // Only the creator and command managers may see the verification token
+        let can_manage = command.creator_id == auth.user_id || manages_all;
+        let mut command: mm::Command = command.into();
+        if !can_manage {
+            command.token = String::new();
+        }
+        commands.push(serde_json::to_value(command).unwrap_or_default());
+    }
 
     Ok(Json(commands))
 }
 
+async fn team_commands(state: &AppState, team_id: uuid::Uuid) -> ApiResult<Vec<SlashCommand>> {
+    let commands = sqlx::query_as(
+        "SELECT * FROM slash_commands WHERE team_id = $1 AND is_active = true ORDER BY trigger",
+    )
+    .bind(team_id)
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(commands)
+}
+
 async fn execute_command(
     State(state): State<AppState>,
     auth: MmAuthUser,
@@ -91,6 +148,7 @@ async fn execute_command(
             command: payload.command,
             channel_id,
             team_id,
+            root_id: payload.root_id.as_deref().and_then(parse_mm_or_uuid),
         },
     )
     .await?;
@@ -120,22 +178,39 @@ fn parse_body<T: serde::de::DeserializeOwned>(
 }
 
 async fn autocomplete_suggestions(
-    Path(_team): Path<TeamPath>,
+    State(state): State<AppState>,
+    Path(team): Path<TeamPath>,
     Query(query): Query<AutocompleteQuery>,
     _auth: MmAuthUser,
 ) -> ApiResult<Json<serde_json::Value>> {
-    let user_input = query.user_input.trim();
-
-    let suggestions = if user_input.starts_with("/call") {
-        vec![serde_json::json!({
-            "complete": "/call",
-            "suggestion": "/call",
-            "hint": "[end]",
-            "description": "Start a Mirotalk call",
-        })]
-    } else {
-        vec![]
-    };
+    let typed = query.user_input.trim().trim_start_matches('/');
+
+    let mut suggestions: Vec<serde_json::Value> = BUILTIN_COMMANDS
+        .iter()
+        .filter(|command| command.trigger.starts_with(typed))
+        .map(|command| {
+            serde_json::json!({
+                "complete": format!("/{}", command.trigger),
+                "suggestion": format!("/{}", command.trigger),
+                "hint": command.hint,
+                "description": command.description,
+            })
+        })
+        .collect();
+
+    if let Some(team_id) = parse_mm_or_uuid(&team.team_id) {
+        for command in team_commands(&state, team_id).await? {
+            if command.auto_complete && command.trigger.starts_with(typed) {
+                suggestions.push(serde_json::json!({
+                    "complete": format!("/{}", command.trigger),
+                    "suggestion": format!("/{}", command.trigger),
+                    "hint": command.hint.unwrap_or_default(),
+                    "description": command.description.unwrap_or_default(),
+                    "icon_data": command.icon_url.unwrap_or_default(),
+                }));
+            }
+        }
+    }
 
     Ok(Json(serde_json::json!({
         "suggestions": suggestions,
@@ -2,7 +2,7 @@ use crate::api::AppState;
 use crate::error::ApiResult;
 use crate::mattermost_compat::models as mm;
 use crate::mattermost_compat::{id::encode_mm_id, MM_VERSION};
-use crate::models::server_config::SiteConfig;
+use crate::models::server_config::{EmailConfig, SiteConfig};
 use axum::{extract::{Query, State}, response::IntoResponse, routing::get, Json, Router};
 use serde::Deserialize;
 use sha2::{Digest, Sha256};
@@ -23,24 +23,25 @@ pub async fn get_client_config(
     State(state): State<AppState>,
     Query(query): Query<LicenseQuery>,
 ) -> ApiResult<impl IntoResponse> {
-    let site = sqlx::query_as::<_, (sqlx::types::Json<SiteConfig>,)>(
-        "SELECT site FROM server_config WHERE id = 'default'",
+    let (site, email) = sqlx::query_as::<_, (sqlx::types::Json<SiteConfig>, sqlx::types::Json<EmailConfig>)>(
+        "SELECT site, email FROM server_config WHERE id = 'default'",
     )
     .fetch_optional(&state.db)
     .await
     .ok()
     .flatten()
-    .map(|row| row.0 .0)
+    .map(|row| (row.0 .0, row.1 .0))
     .unwrap_or_default();
+    let enable_push = email.send_push_notifications && !email.push_notification_server.is_empty();
 
     let body = if matches!(query.format.as_deref(), Some("old")) {
         let diagnostic_id = diagnostic_id(&site);
-        legacy_config(&site, &diagnostic_id)
+        legacy_config(&site, &diagnostic_id, enable_push)
     } else {
         serde_json::to_value(mm::Config {
             site_url: site.site_url.clone(),
             version: MM_VERSION.to_string(),
-            enable_push_notifications: "false".to_string(),
+            enable_push_notifications: enable_push.to_string(),
             diagnostic_id: "00000000-0000-0000-0000-000000000000".to_string(),
         })
         .map_err(|e| crate::error::AppError::Internal(e.to_string()))?
@@ -70,7 +71,7 @@ pub async fn get_client_license(
     Ok(Json(body))
 }
 
-fn legacy_config(site: &SiteConfig, diagnostic_id: &str) -> serde_json::Value {
+fn legacy_config(site: &SiteConfig, diagnostic_id: &str, enable_push: bool) -> serde_json::Value {
     use serde_json::{Map, Value};
 
     let mut map = Map::new();
@@ -103,11 +104,12 @@ fn legacy_config(site: &SiteConfig, diagnostic_id: &str) -> serde_json::Value {
     // Add essential fields for mobile
     insert(&mut map, "EnableMobileFileDownload", "true");
     insert(&mut map, "EnableMobileFileUpload", "true");
+    insert(&mut map, "SendPushNotifications", if enable_push { "true" } else { "false" });
 
     Value::Object(map)
 }
 
-fn diagnostic_id(site: &SiteConfig) -> String {
+pub fn diagnostic_id(site: &SiteConfig) -> String {
     let seed = if !site.site_url.is_empty() {
         site.site_url.as_bytes()
     } else if !site.site_name.is_empty() {
@@ -8,12 +8,13 @@ use axum::{
 use uuid::Uuid;
 
 use crate::api::AppState;
-use crate::auth::middleware::FromRef;
-use crate::auth::{validate_token, Claims};
+use crate::auth::middleware::{request_path, FromRef};
+use crate::auth::Claims;
 use crate::error::AppError;
 
 pub struct MmAuthUser {
     pub user_id: Uuid,
+    pub session_id: Uuid,
     #[allow(dead_code)]
     pub email: String,
     #[allow(dead_code)]
@@ -26,6 +27,7 @@ impl From<Claims> for MmAuthUser {
     fn from(claims: Claims) -> Self {
         Self {
             user_id: claims.sub,
+            session_id: claims.jti,
             email: claims.email,
             role: claims.role,
             org_id: claims.org_id,
@@ -65,8 +67,14 @@ where
             ));
         };
 
-        let token_data = validate_token(token, &app_state.jwt_secret)?;
+        let session = crate::services::sessions::authenticate_request(
+            &app_state,
+            &mut parts.extensions,
+            token,
+        )
+        .await?;
+        crate::services::mfa::check_enrollment(&app_state, &session, request_path(parts)).await?;
 
-        Ok(MmAuthUser::from(token_data.claims))
+        Ok(MmAuthUser::from(session.claims))
     }
 }
@@ -1,29 +1,33 @@
 use axum::{
-    extract::{Multipart, Path, State},
+    extract::{DefaultBodyLimit, Multipart, Path, Query, State},
     response::IntoResponse,
     routing::{get, post},
     Json, Router,
 };
-use image::{GenericImageView, ImageFormat};
-use std::io::Cursor;
+use serde::Deserialize;
 use uuid::Uuid;
-use chrono::Utc;
-use sha2::{Digest, Sha256};
 
 use super::extractors::MmAuthUser;
 use crate::api::AppState;
 use crate::error::{ApiResult, AppError};
-use crate::mattermost_compat::{id::{encode_mm_id, parse_mm_or_uuid}, models as mm};
+use crate::mattermost_compat::{id::parse_mm_or_uuid, models as mm};
 use crate::models::FileInfo;
+use crate::services::file_search::{self, FileSearch};
+use crate::services::{media, uploads, virus_scan};
 
 pub fn router() -> Router<AppState> {
     Router::new()
-        .route("/files", post(upload_file))
+        .route(
+            "/files",
+            post(upload_file).layer(DefaultBodyLimit::disable()),
+        )
         .route("/files/{file_id}", get(get_file))
         .route("/files/{file_id}/info", get(get_file_info))
         .route("/files/{file_id}/thumbnail", get(get_thumbnail))
         .route("/files/{file_id}/preview", get(get_preview))
         .route("/files/{file_id}/link", get(get_link))
+        .route("/teams/{team_id}/files/search", post(search_files))
+        .route("/channels/{channel_id}/files", get(get_channel_files))
 }
 
 async fn upload_file(
@@ -33,14 +37,7 @@ async fn upload_file(
 ) -> ApiResult<Json<serde_json::Value>> {
     let mut channel_id: Option<Uuid> = None;
     let mut client_ids: Vec<String> = Vec::new();
-
-    struct PendingFile {
-        filename: String,
-        content_type: String,
-        data: Vec<u8>,
-    }
-
-    let mut pending_files: Vec<PendingFile> = Vec::new();
+    let mut files: Vec<FileInfo> = Vec::new();
 
     while let Some(field) = multipart
         .next_field()
@@ -63,112 +60,41 @@ async fn upload_file(
                 .content_type()
                 .unwrap_or("application/octet-stream")
                 .to_string();
-            let data = field
-                .bytes()
-                .await
-                .map_err(|e| AppError::BadRequest(format!("Read error: {}", e)))?
-                .to_vec();
-
-            pending_files.push(PendingFile {
-                filename,
-                content_type,
-                data,
-            });
-        }
-    }
 
-    let mut file_infos: Vec<mm::FileInfo> = Vec::new();
-
-    for file in pending_files {
-        let file_id = Uuid::new_v4();
-        let extension = file.filename.rsplit('.').next().unwrap_or("").to_string();
-        let key = format!("files/{}/{}.{}", auth.user_id, file_id, extension);
-
-        let mut hasher = Sha256::new();
-        hasher.update(&file.data);
-        let hash = hex::encode(hasher.finalize());
-        let size = file.data.len() as i64;
-
-        state.s3_client.upload(&key, file.data.clone(), &file.content_type).await?;
-
-        // Image processing (Blocking offloaded)
-        let (width, height, thumbnail_data) = if file.content_type.starts_with("image/") {
-            let data_clone = file.data.clone();
-
-            tokio::task::spawn_blocking(move || {
-                if let Ok(img) = image::load_from_memory(&data_clone) {
-                    let (w, h) = img.dimensions();
-                    let w_out = Some(w as i32);
-                    let h_out = Some(h as i32);
-
-                    let thumb_data = if w > 400 || h > 400 {
-                         let thumb = img.thumbnail(400, 400);
-                         let mut buf = Vec::new();
-                         if thumb.write_to(&mut Cursor::new(&mut buf), ImageFormat::WebP).is_ok() {
-                             Some(buf)
-                         } else {
-                             None
-                         }
-                    } else {
-                        None
-                    };
-                    (w_out, h_out, thumb_data)
-                } else {
-                    (None, None, None)
-                }
-            }).await.unwrap_or((None, None, None))
-        } else {
-             (None, None, None)
-        };
-
-        let mut thumbnail_key = None;
-        if let Some(t_data) = thumbnail_data {
-             let t_key = format!("thumbnails/{}/{}.webp", auth.user_id, file_id);
-             if state.s3_client.upload(&t_key, t_data, "image/webp").await.is_ok() {
-                 thumbnail_key = Some(t_key);
-             }
+            // Streamed to storage, never held in memory whole
+            let file = uploads::store_stream(
+                &state,
+                auth.user_id,
+                channel_id,
+                &filename,
+                &content_type,
+                field,
+            )
+            .await?;
+            files.push(file);
         }
+    }
 
-        let has_thumbnail = thumbnail_key.is_some();
-
-        let _file_info: FileInfo = sqlx::query_as(
-            r#"
-            INSERT INTO files (id, uploader_id, channel_id, name, key, mime_type, size, sha256, width, height, has_thumbnail, thumbnail_key)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)
-            RETURNING *
-            "#,
-        )
-        .bind(file_id)
-        .bind(auth.user_id)
-        .bind(channel_id)
-        .bind(&file.filename)
-        .bind(&key)
-        .bind(&file.content_type)
-        .bind(size)
-        .bind(&hash)
-        .bind(width)
-        .bind(height)
-        .bind(has_thumbnail)
-        .bind(thumbnail_key)
-        .fetch_one(&state.db)
-        .await?;
-
-        file_infos.push(mm::FileInfo {
-            id: encode_mm_id(file_id),
-            user_id: encode_mm_id(auth.user_id),
-            create_at: Utc::now().timestamp_millis(),
-            update_at: Utc::now().timestamp_millis(),
-            delete_at: 0,
-            name: file.filename,
-            extension,
-            size,
-            mime_type: file.content_type,
-            width: width.unwrap_or(0),
-            height: height.unwrap_or(0),
-            has_preview_image: has_thumbnail,
-        });
+    // The channel may come after the files it belongs to
+    if let Some(channel_id) = channel_id.filter(|_| files.iter().any(|f| f.channel_id.is_none())) {
+        let ids: Vec<Uuid> = files
+            .iter_mut()
+            .filter(|file| file.channel_id.is_none())
+            .map(|file| {
+                file.channel_id = Some(channel_id);
+                file.id
+            })
+            .collect();
+        sqlx::query("UPDATE files SET channel_id = $1 WHERE id = ANY($2)")
+            .bind(channel_id)
+            .bind(&ids)
+            .execute(&state.db)
+            .await?;
     }
 
+    // Dimensions and previews are filled in once processing is done
+    let file_infos: Vec<mm::FileInfo> = files.into_iter().map(Into::into).collect();
+
     Ok(Json(serde_json::json!({
         "file_infos": file_infos,
         "client_ids": client_ids
@@ -187,12 +113,18 @@ async fn get_file(
         .fetch_optional(&state.db)
         .await?
         .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    virus_scan::ensure_servable(&file)?;
+    media::ensure_servable(&file)?;
 
     // In a real MM server, this returns the raw bytes.
     // For now, we redirect to S3 presigned URL or proxy it.
     // Mobile client usually handles redirects.
 
-    let url = state.s3_client.presigned_download_url(&file.key, 3600).await?;
+    let url = state
+        .storage
+        .backend(&file.backend)?
+        .presigned_download_url(&file.key, 3600)
+        .await?;
 
     // Redirect to S3
     Ok(axum::response::Redirect::temporary(&url))
@@ -212,23 +144,7 @@ async fn get_file_info(
         .await?
         .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
 
-    // Get file extension from name
-    let extension = file.name.rsplit('.').next().unwrap_or("").to_string();
-
-    Ok(Json(mm::FileInfo {
-        id: encode_mm_id(file.id),
-        user_id: encode_mm_id(file.uploader_id),
-        create_at: file.created_at.timestamp_millis(),
-        update_at: file.created_at.timestamp_millis(),
-        delete_at: 0,
-        name: file.name,
-        extension,
-        size: file.size,
-        mime_type: file.mime_type,
-        width: file.width.unwrap_or(0),
-        height: file.height.unwrap_or(0),
-        has_preview_image: file.has_thumbnail,
-    }))
+    Ok(Json(file.into()))
 }
 
 async fn get_thumbnail(
@@ -243,10 +159,15 @@ async fn get_thumbnail(
         .fetch_optional(&state.db)
         .await?
         .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    virus_scan::ensure_servable(&file)?;
 
     if file.has_thumbnail {
         if let Some(key) = file.thumbnail_key {
-            let url = state.s3_client.presigned_download_url(&key, 3600).await?;
+            let url = state
+                .storage
+                .backend(&file.backend)?
+                .presigned_download_url(&key, 3600)
+                .await?;
             return Ok(axum::response::Redirect::temporary(&url));
         }
     }
@@ -268,17 +189,20 @@ async fn get_preview(
         .fetch_optional(&state.db)
         .await?
         .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
-
-    if file.mime_type.starts_with("image/") {
-        if file.has_thumbnail {
-            if let Some(key) = file.thumbnail_key.as_ref() {
-                let url = state.s3_client.presigned_download_url(key, 3600).await?;
-                return Ok(axum::response::Redirect::temporary(&url));
-            }
-        }
-    }
-
-    let url = state.s3_client.presigned_download_url(&file.key, 3600).await?;
+    virus_scan::ensure_servable(&file)?;
+    media::ensure_servable(&file)?;
+
+    // Images small enough to show as they are have no separate preview
+    let key = match (&file.preview_key, file.has_thumbnail) {
+        (Some(key), _) => key,
+        (None, true) => &file.key,
+        (None, false) => return Err(AppError::NotFound("Preview not found".to_string())),
+    };
+    let url = state
+        .storage
+        .backend(&file.backend)?
+        .presigned_download_url(key, 3600)
+        .await?;
     Ok(axum::response::Redirect::temporary(&url))
 }
 
@@ -294,8 +218,91 @@ async fn get_link(
         .fetch_optional(&state.db)
         .await?
         .ok_or_else(|| AppError::NotFound("File not found".to_string()))?;
+    virus_scan::ensure_servable(&file)?;
+    media::ensure_servable(&file)?;
 
-    let url = state.s3_client.presigned_download_url(&file.key, 3600).await?;
+    let url = state
+        .storage
+        .backend(&file.backend)?
+        .presigned_download_url(&file.key, 3600)
+        .await?;
 
     Ok(Json(serde_json::json!({"link": url})))
 }
+
+fn default_per_page() -> i64 {
+    60
+}
+
+#[derive(Deserialize)]
+struct FileSearchRequest {
+    terms: String,
+    /// Seconds east of UTC that dates in the terms are in
+    #[serde(default)]
+    time_zone_offset: i32,
+    #[serde(default)]
+    page: i64,
+    #[serde(default = "default_per_page")]
+    per_page: i64,
+}
+
+/// POST /teams/{team_id}/files/search - Search files shared in a team
+async fn search_files(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(team_id): Path<String>,
+    Json(input): Json<FileSearchRequest>,
+) -> ApiResult<Json<mm::FileInfoList>> {
+    let team_id = parse_mm_or_uuid(&team_id)
+        .ok_or_else(|| AppError::BadRequest("Invalid team_id".to_string()))?;
+    let search = FileSearch::parse(&input.terms, input.time_zone_offset)?;
+
+    let files = file_search::search_files(
+        &state.db,
+        auth.user_id,
+        team_id,
+        &search,
+        input.page.max(0),
+        input.per_page.clamp(1, 200),
+    )
+    .await?;
+
+    Ok(Json(files.into()))
+}
+
+#[derive(Deserialize)]
+struct FilesQuery {
+    #[serde(default)]
+    page: i64,
+    #[serde(default = "default_per_page")]
+    per_page: i64,
+}
+
+/// GET /channels/{channel_id}/files - Files shared in a channel
+async fn get_channel_files(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(channel_id): Path<String>,
+    Query(query): Query<FilesQuery>,
+) -> ApiResult<Json<mm::FileInfoList>> {
+    let channel_id = parse_mm_or_uuid(&channel_id)
+        .ok_or_else(|| AppError::BadRequest("Invalid channel_id".to_string()))?;
+
+    let _membership: crate::models::ChannelMember =
+        sqlx::query_as("SELECT * FROM channel_members WHERE channel_id = $1 AND user_id = $2")
+            .bind(channel_id)
+            .bind(auth.user_id)
+            .fetch_optional(&state.db)
+            .await?
+            .ok_or_else(|| AppError::Forbidden("Not a member of this channel".to_string()))?;
+
+    let files = file_search::channel_files(
+        &state.db,
+        channel_id,
+        query.page.max(0),
+        query.per_page.clamp(1, 200),
+    )
+    .await?;
+
+    Ok(Json(files.into()))
+}
@@ -14,6 +14,7 @@ use crate::api::v4::extractors::MmAuthUser;
 use crate::error::{ApiResult, AppError};
 use crate::mattermost_compat::{id::{encode_mm_id, parse_mm_or_uuid}, models as mm};
 use crate::models::{IncomingWebhook, OutgoingWebhook};
+use crate::services::permissions::{Permission, Principal, Scope};
 use uuid::Uuid;
 
 #[derive(serde::Deserialize)]
@@ -62,6 +63,8 @@ pub async fn create_incoming_hook(
         .bind(channel_id)
         .fetch_one(&state.db)
         .await?;
+    auth.require(&state, Permission::WebhookCreate, Scope::Team(team_id))
+        .await?;
 
     let hook: IncomingWebhook = sqlx::query_as(
         r#"
@@ -113,6 +116,8 @@ pub async fn create_outgoing_hook(
         .ok_or_else(|| AppError::Validation("Invalid team_id".to_string()))?;
 
     let channel_id = input.channel_id.and_then(|id| parse_mm_or_uuid(&id));
+    auth.require(&state, Permission::WebhookCreate, Scope::Team(team_id))
+        .await?;
 
     let hook: OutgoingWebhook = sqlx::query_as(
         r#"
@@ -127,7 +132,7 @@ pub async fn create_outgoing_hook(
     .bind(&input.display_name)
     .bind(&input.description)
     .bind(&input.trigger_words)
-    .bind("first_word") // Simplified mapping for trigger_when
+    .bind(trigger_when_name(input.trigger_when))
     .bind(&input.callback_urls)
     .bind(&input.content_type)
     .bind(Uuid::new_v4().to_string())
@@ -183,10 +188,25 @@ fn map_outgoing_hook(h: OutgoingWebhook) -> mm::OutgoingWebhook {
         channel_id: h.channel_id.map(encode_mm_id).unwrap_or_default(),
         team_id: encode_mm_id(h.team_id),
         trigger_words: h.trigger_words,
-        trigger_when: 0,
+        trigger_when: trigger_when_code(&h.trigger_when),
         callback_urls: h.callback_urls,
         display_name: h.display_name.unwrap_or_default(),
         description: h.description.unwrap_or_default(),
         content_type: h.content_type.unwrap_or_default(),
     }
 }
+
+/// Mattermost encodes trigger_when as 0 (first word matches) or 1 (first word starts with)
+fn trigger_when_name(code: i32) -> &'static str {
+    match code {
+        1 => "starts_with",
+        _ => "first_word",
+    }
+}
+
+fn trigger_when_code(name: &str) -> i32 {
+    match name {
+        "starts_with" | "any" => 1,
+        _ => 0,
+    }
+}
@@ -18,6 +18,7 @@ pub mod posts;
 pub mod system;
 pub mod teams;
 pub mod threads;
+pub mod uploads;
 pub mod users;
 
 pub fn router() -> Router<AppState> {
@@ -31,6 +32,7 @@ pub fn router() -> Router<AppState> {
         .merge(categories::router())
         .merge(posts::router())
         .merge(files::router())
+        .merge(uploads::router())
         .merge(system::router())
         .merge(threads::router())
         .merge(config_client::router())
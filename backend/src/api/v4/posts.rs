@@ -12,8 +12,10 @@ use super::extractors::MmAuthUser;
 use crate::api::AppState;
 use crate::error::{ApiResult, AppError};
 use crate::mattermost_compat::{id::{encode_mm_id, parse_mm_or_uuid}, models as mm};
-use crate::models::CreatePost;
+use crate::jobs::scheduled_posts;
+use crate::models::{CreatePost, ScheduledPost};
 use crate::realtime::{EventType, WsBroadcast, WsEnvelope};
+use crate::services::permissions::{Permission, Principal, Scope};
 use crate::services::posts;
 
 pub fn router() -> Router<AppState> {
@@ -31,8 +33,23 @@ pub fn router() -> Router<AppState> {
         .route("/posts/{post_id}/thread", get(get_post_thread))
         .route("/posts/ephemeral", post(create_ephemeral_post))
         .route("/posts/schedule", post(create_scheduled_post))
+        .route(
+            "/posts/schedule/{scheduled_post_id}",
+            put(update_scheduled_post).delete(delete_scheduled_post),
+        )
+        .route(
+            "/posts/schedule/{scheduled_post_id}/send_now",
+            post(send_scheduled_post_now),
+        )
         .route("/posts/scheduled/team/{team_id}", get(list_scheduled_posts))
-        .route("/users/{user_id}/posts/{post_id}/reminder", post(set_post_reminder))
+        .route(
+            "/users/{user_id}/posts/{post_id}/reminder",
+            post(set_post_reminder).delete(delete_post_reminder),
+        )
+        .route(
+            "/users/{user_id}/posts/{post_id}/reminder/snooze",
+            post(snooze_post_reminder),
+        )
 }
 
 #[derive(Debug, Deserialize)]
@@ -247,9 +264,13 @@ async fn delete_post(
         .fetch_one(&state.db)
         .await?;
 
-    if post.user_id != auth.user_id {
-        return Err(AppError::Forbidden("Cannot delete others' posts".to_string()));
-    }
+    let permission = if post.user_id == auth.user_id {
+        Permission::PostDeleteOwn
+    } else {
+        Permission::PostDeleteOthers
+    };
+    auth.require(&state, permission, Scope::Channel(post.channel_id))
+        .await?;
 
     let deleted_post: crate::models::post::PostResponse = sqlx::query_as(
         r#"
@@ -304,9 +325,13 @@ async fn patch_post(
         .fetch_one(&state.db)
         .await?;
 
-    if post.user_id != auth.user_id {
-        return Err(AppError::Forbidden("Cannot edit others' posts".to_string()));
-    }
+    let permission = if post.user_id == auth.user_id {
+        Permission::PostEditOwn
+    } else {
+        Permission::PostEditOthers
+    };
+    auth.require(&state, permission, Scope::Channel(post.channel_id))
+        .await?;
 
     let updated: crate::models::post::PostResponse = sqlx::query_as(
         r#"
@@ -515,12 +540,12 @@ async fn list_scheduled_posts(
     let team_id = parse_mm_or_uuid(&team_id_str)
         .ok_or_else(|| AppError::Validation("Invalid team_id".to_string()))?;
 
-    let rows: Vec<(Uuid, Uuid, Uuid, Option<Uuid>, String, serde_json::Value, Vec<Uuid>, chrono::DateTime<chrono::Utc>, chrono::DateTime<chrono::Utc>, chrono::DateTime<chrono::Utc>)> = sqlx::query_as(
+    let rows: Vec<ScheduledPost> = sqlx::query_as(
         r#"
-        SELECT id, user_id, channel_id, root_id, message, props, file_ids, scheduled_at, created_at, updated_at
-        FROM scheduled_posts
+        SELECT * FROM scheduled_posts
         WHERE user_id = $1 AND channel_id IN (SELECT id FROM channels WHERE team_id = $2)
-        AND state = 'pending'
+        AND state IN ('pending', 'failed')
+        ORDER BY scheduled_at
         "#
     )
     .bind(auth.user_id)
@@ -528,20 +553,7 @@ async fn list_scheduled_posts(
     .fetch_all(&state.db)
     .await?;
 
-    let posts = rows.into_iter().map(|r| mm::ScheduledPost {
-        id: encode_mm_id(r.0),
-        user_id: encode_mm_id(r.1),
-        channel_id: encode_mm_id(r.2),
-        root_id: r.3.map(encode_mm_id).unwrap_or_default(),
-        message: r.4,
-        props: r.5,
-        file_ids: r.6.into_iter().map(encode_mm_id).collect(),
-        scheduled_at: r.7.timestamp_millis(),
-        create_at: r.8.timestamp_millis(),
-        update_at: r.9.timestamp_millis(),
-    }).collect();
-
-    Ok(Json(posts))
+    Ok(Json(rows.into_iter().map(Into::into).collect()))
 }
 
 async fn create_scheduled_post(
@@ -560,14 +572,13 @@ async fn create_scheduled_post(
     };
 
     let file_ids = input.file_ids.iter().filter_map(|id| parse_mm_or_uuid(id)).collect::<Vec<_>>();
-    let scheduled_at = chrono::DateTime::from_timestamp_millis(input.scheduled_at)
-        .ok_or_else(|| AppError::Validation("Invalid scheduled_at".to_string()))?;
+    let scheduled_at = parse_scheduled_at(input.scheduled_at)?;
 
-    let row: (Uuid, chrono::DateTime<chrono::Utc>, chrono::DateTime<chrono::Utc>) = sqlx::query_as(
+    let scheduled: ScheduledPost = sqlx::query_as(
         r#"
         INSERT INTO scheduled_posts (user_id, channel_id, root_id, message, props, file_ids, scheduled_at)
         VALUES ($1, $2, $3, $4, $5, $6, $7)
-        RETURNING id, created_at, updated_at
+        RETURNING *
         "#
     )
     .bind(auth.user_id)
@@ -580,18 +591,107 @@ async fn create_scheduled_post(
     .fetch_one(&state.db)
     .await?;
 
-    Ok(Json(mm::ScheduledPost {
-        id: encode_mm_id(row.0),
-        user_id: encode_mm_id(auth.user_id),
-        channel_id: input.channel_id,
-        root_id: input.root_id,
-        message: input.message,
-        props: input.props,
-        file_ids: input.file_ids,
-        scheduled_at: input.scheduled_at,
-        create_at: row.1.timestamp_millis(),
-        update_at: row.2.timestamp_millis(),
-    }))
+    Ok(Json(scheduled.into()))
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateScheduledPostRequest {
+    pub message: String,
+    #[serde(default)]
+    pub props: serde_json::Value,
+    #[serde(default)]
+    pub file_ids: Vec<String>,
+    pub scheduled_at: i64,
+}
+
+async fn update_scheduled_post(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(scheduled_post_id): Path<String>,
+    Json(input): Json<UpdateScheduledPostRequest>,
+) -> ApiResult<Json<mm::ScheduledPost>> {
+    let id = parse_mm_or_uuid(&scheduled_post_id)
+        .ok_or_else(|| AppError::Validation("Invalid scheduled_post_id".to_string()))?;
+
+    let file_ids = input.file_ids.iter().filter_map(|id| parse_mm_or_uuid(id)).collect::<Vec<_>>();
+    let scheduled_at = parse_scheduled_at(input.scheduled_at)?;
+
+    // Failed posts can be edited and rescheduled, which puts them back in the queue
+    let scheduled: ScheduledPost = sqlx::query_as(
+        r#"
+        UPDATE scheduled_posts
+        SET message = $3, props = $4, file_ids = $5, scheduled_at = $6,
+            state = 'pending', error_code = NULL, error_message = NULL, processed_at = NULL
+        WHERE id = $1 AND user_id = $2 AND state IN ('pending', 'failed')
+        RETURNING *
+        "#
+    )
+    .bind(id)
+    .bind(auth.user_id)
+    .bind(&input.message)
+    .bind(&input.props)
+    .bind(&file_ids)
+    .bind(scheduled_at)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Scheduled post not found".to_string()))?;
+
+    Ok(Json(scheduled.into()))
+}
+
+async fn delete_scheduled_post(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(scheduled_post_id): Path<String>,
+) -> ApiResult<Json<mm::ScheduledPost>> {
+    let id = parse_mm_or_uuid(&scheduled_post_id)
+        .ok_or_else(|| AppError::Validation("Invalid scheduled_post_id".to_string()))?;
+
+    let scheduled: ScheduledPost = sqlx::query_as(
+        r#"
+        UPDATE scheduled_posts SET state = 'cancelled'
+        WHERE id = $1 AND user_id = $2 AND state IN ('pending', 'failed')
+        RETURNING *
+        "#
+    )
+    .bind(id)
+    .bind(auth.user_id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Scheduled post not found".to_string()))?;
+
+    Ok(Json(scheduled.into()))
+}
+
+async fn send_scheduled_post_now(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(scheduled_post_id): Path<String>,
+) -> ApiResult<Json<mm::Post>> {
+    let id = parse_mm_or_uuid(&scheduled_post_id)
+        .ok_or_else(|| AppError::Validation("Invalid scheduled_post_id".to_string()))?;
+
+    // Claiming the row first keeps the dispatcher from publishing it a second time
+    let scheduled = scheduled_posts::claim_post(&state.db, id, auth.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Scheduled post not found".to_string()))?;
+
+    let post = scheduled_posts::publish_scheduled_post(&state, scheduled).await?;
+
+    Ok(Json(post.into()))
+}
+
+fn parse_scheduled_at(scheduled_at: i64) -> ApiResult<chrono::DateTime<chrono::Utc>> {
+    let scheduled_at = chrono::DateTime::from_timestamp_millis(scheduled_at)
+        .ok_or_else(|| AppError::Validation("Invalid scheduled_at".to_string()))?;
+
+    if scheduled_at <= chrono::Utc::now() {
+        return Err(AppError::Validation(
+            "scheduled_at must be in the future".to_string(),
+        ));
+    }
+
+    Ok(scheduled_at)
 }
 
 #[derive(serde::Deserialize)]
@@ -655,24 +755,31 @@ async fn create_ephemeral_post(
 
 #[derive(serde::Deserialize)]
 pub struct PostReminderRequest {
+    #[serde(alias = "target_time")]
     pub target_at: i64,
 }
 
+fn reminder_target(user_id_str: &str, post_id_str: &str, auth: &MmAuthUser) -> ApiResult<Uuid> {
+    if user_id_str != "me" {
+        let target_user_id = parse_mm_or_uuid(user_id_str)
+            .ok_or_else(|| AppError::Validation("Invalid user_id".to_string()))?;
+
+        if target_user_id != auth.user_id {
+            return Err(AppError::Forbidden("Cannot set reminder for others".to_string()));
+        }
+    }
+
+    parse_mm_or_uuid(post_id_str)
+        .ok_or_else(|| AppError::Validation("Invalid post_id".to_string()))
+}
+
 async fn set_post_reminder(
     State(state): State<AppState>,
     auth: MmAuthUser,
     Path((user_id_str, post_id_str)): Path<(String, String)>,
     Json(input): Json<PostReminderRequest>,
 ) -> ApiResult<impl axum::response::IntoResponse> {
-    let target_user_id = parse_mm_or_uuid(&user_id_str)
-        .ok_or_else(|| AppError::Validation("Invalid user_id".to_string()))?;
-
-    if target_user_id != auth.user_id && user_id_str != "me" {
-        return Err(AppError::Forbidden("Cannot set reminder for others".to_string()));
-    }
-
-    let post_id = parse_mm_or_uuid(&post_id_str)
-        .ok_or_else(|| AppError::Validation("Invalid post_id".to_string()))?;
+    let post_id = reminder_target(&user_id_str, &post_id_str, &auth)?;
 
     let target_at = chrono::DateTime::from_timestamp_millis(input.target_at)
         .ok_or_else(|| AppError::Validation("Invalid target_at".to_string()))?;
@@ -681,7 +788,9 @@ async fn set_post_reminder(
         r#"
         INSERT INTO post_reminders (user_id, post_id, target_at)
         VALUES ($1, $2, $3)
-        ON CONFLICT (user_id, post_id) DO UPDATE SET target_at = $3
+        ON CONFLICT (user_id, post_id)
+        DO UPDATE SET target_at = $3, delivered_at = NULL, reminder_post_id = NULL,
+                      attempts = 0, next_attempt_at = NULL
         "#
     )
     .bind(auth.user_id)
@@ -693,3 +802,54 @@ async fn set_post_reminder(
     Ok(Json(serde_json::json!({"status": "OK"})))
 }
 
+async fn delete_post_reminder(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path((user_id_str, post_id_str)): Path<(String, String)>,
+) -> ApiResult<impl axum::response::IntoResponse> {
+    let post_id = reminder_target(&user_id_str, &post_id_str, &auth)?;
+
+    sqlx::query("DELETE FROM post_reminders WHERE user_id = $1 AND post_id = $2")
+        .bind(auth.user_id)
+        .bind(post_id)
+        .execute(&state.db)
+        .await?;
+
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+#[derive(serde::Deserialize)]
+pub struct SnoozeReminderRequest {
+    #[serde(default = "default_snooze_minutes")]
+    pub minutes: i64,
+}
+
+fn default_snooze_minutes() -> i64 {
+    15
+}
+
+async fn snooze_post_reminder(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path((user_id_str, post_id_str)): Path<(String, String)>,
+    Json(input): Json<SnoozeReminderRequest>,
+) -> ApiResult<impl axum::response::IntoResponse> {
+    let post_id = reminder_target(&user_id_str, &post_id_str, &auth)?;
+
+    if input.minutes <= 0 {
+        return Err(AppError::Validation("minutes must be positive".to_string()));
+    }
+
+    let target_at = chrono::Utc::now() + chrono::Duration::minutes(input.minutes);
+    let found = crate::jobs::post_reminders::snooze_reminder(&state.db, auth.user_id, post_id, target_at)
+        .await?;
+
+    if !found {
+        return Err(AppError::NotFound("Reminder not found".to_string()));
+    }
+
+    Ok(Json(serde_json::json!({
+        "status": "OK",
+        "target_time": target_at.timestamp_millis()
+    })))
+}
@@ -12,6 +12,7 @@ pub fn router() -> Router<AppState> {
         .route("/caches/invalidate", post(invalidate_caches))
         .route("/logs", post(post_logs))
         .route("/database/recycle", post(recycle_database))
+        .route("/notifications/ack", post(ack_notification))
 }
 
 #[derive(Serialize)]
@@ -122,3 +123,32 @@ pub async fn post_logs(
     }
     Ok(Json(serde_json::json!({"status": "OK"})))
 }
+
+/// Receipt for a delivered push notification
+///
+/// For id-loaded notifications the device gets the full message content in
+/// return, since the push itself only carried identifiers.
+pub async fn ack_notification(
+    State(state): State<AppState>,
+    auth: crate::api::v4::extractors::MmAuthUser,
+    Json(input): Json<crate::models::PushNotificationAck>,
+) -> ApiResult<Json<serde_json::Value>> {
+    if !input.is_id_loaded || input.notification_type != "message" {
+        return Ok(Json(serde_json::json!({"status": "OK"})));
+    }
+
+    let post_id = input
+        .post_id
+        .as_deref()
+        .and_then(crate::mattermost_compat::id::parse_mm_or_uuid)
+        .ok_or_else(|| crate::error::AppError::BadRequest("Invalid post_id".to_string()))?;
+
+    let notification =
+        crate::services::push_notifications::id_loaded_notification(&state, auth.user_id, post_id)
+            .await?;
+
+    Ok(Json(
+        serde_json::to_value(notification)
+            .map_err(|e| crate::error::AppError::Internal(e.to_string()))?,
+    ))
+}
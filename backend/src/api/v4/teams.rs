@@ -1,6 +1,6 @@
 use axum::{
     body::Bytes,
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::IntoResponse,
     routing::{get, post},
     Json, Router,
@@ -13,6 +13,9 @@ use crate::api::AppState;
 use crate::error::ApiResult;
 use crate::mattermost_compat::{id::{encode_mm_id, parse_mm_or_uuid}, models as mm};
 use crate::models::{Team, Channel};
+use crate::services::guests;
+use crate::services::permissions::{Permission, Principal, Scope};
+use crate::services::team_invites::{self, Invite};
 
 pub fn router() -> Router<AppState> {
     Router::new()
@@ -23,6 +26,13 @@ pub fn router() -> Router<AppState> {
         .route("/teams/{team_id}/channels", get(get_team_channels))
         .route("/teams/{team_id}/channels/search", post(search_channels))
         .route("/teams/search", post(search_teams))
+        .route("/teams/invite/{invite_id}", get(get_invite_info))
+        .route("/teams/members/invite", post(add_user_to_team_from_invite))
+        .route("/teams/{team_id}/invite/email", post(invite_users_by_email))
+        .route(
+            "/teams/{team_id}/regenerate_invite_id",
+            post(regenerate_invite_id),
+        )
 }
 
 async fn get_teams(
@@ -47,7 +57,7 @@ async fn get_teams(
 
 async fn get_team(
     State(state): State<AppState>,
-    _auth: MmAuthUser,
+    auth: MmAuthUser,
     Path(team_id): Path<String>,
 ) -> ApiResult<Json<mm::Team>> {
     let team_id = parse_mm_or_uuid(&team_id)
@@ -57,7 +67,17 @@ async fn get_team(
         .fetch_one(&state.db)
         .await?;
 
-    Ok(Json(team.into()))
+    // Only people who can add members get to see the invite link
+    let invite_id = team.invite_id.clone();
+    let mut mm_team: mm::Team = team.into();
+    if auth
+        .can(&state, Permission::TeamManageMembers, Scope::Team(team_id))
+        .await?
+    {
+        mm_team.invite_id = invite_id;
+    }
+
+    Ok(Json(mm_team))
 }
 
 async fn get_team_channels(
@@ -169,7 +189,8 @@ async fn search_channels(
     let input: SearchChannelsRequest = parse_body(&headers, &body, "Invalid search request")?;
     let search_term = format!("%{}%", input.term.to_lowercase());
 
-    // Search public channels and private channels the user is a member of
+    // Search public channels and private channels the user is a member of;
+    // guests only find the channels they are in
     let channels: Vec<Channel> = sqlx::query_as(
         r#"
         SELECT DISTINCT c.* FROM channels c
@@ -177,7 +198,7 @@ async fn search_channels(
         WHERE c.team_id = $1
           AND c.deleted_at IS NULL
           AND (LOWER(c.name) LIKE $3 OR LOWER(c.display_name) LIKE $3)
-          AND (c.type = 'public' OR cm.user_id IS NOT NULL)
+          AND ((c.type = 'public' AND NOT $4) OR cm.user_id IS NOT NULL)
         ORDER BY c.display_name ASC
         LIMIT 50
         "#,
@@ -185,6 +206,7 @@ async fn search_channels(
     .bind(team_id)
     .bind(auth.user_id)
     .bind(&search_term)
+    .bind(guests::is_guest(&auth.role))
     .fetch_all(&state.db)
     .await?;
 
@@ -209,3 +231,84 @@ async fn search_teams(
 
     Ok(Json(teams.into_iter().map(|t| t.into()).collect()))
 }
+
+/// GET /teams/invite/{invite_id} - Team shown on an invite link's signup page
+async fn get_invite_info(
+    State(state): State<AppState>,
+    Path(invite_id): Path<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let invite = team_invites::resolve(&state.db, &Invite::Link(invite_id)).await?;
+    let team = invite.team;
+
+    Ok(Json(serde_json::json!({
+        "id": encode_mm_id(team.id),
+        "name": team.name,
+        "display_name": team.display_name.unwrap_or_default(),
+        "description": team.description.unwrap_or_default(),
+    })))
+}
+
+#[derive(Deserialize)]
+struct InviteQuery {
+    token: Option<String>,
+    invite_id: Option<String>,
+}
+
+/// POST /teams/members/invite - Join a team with an emailed token or invite ID
+async fn add_user_to_team_from_invite(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Query(query): Query<InviteQuery>,
+) -> ApiResult<Json<mm::TeamMember>> {
+    let invite = Invite::from_params(query.token, query.invite_id).ok_or_else(|| {
+        crate::error::AppError::BadRequest("Missing token or invite_id".to_string())
+    })?;
+    let pending = team_invites::resolve(&state.db, &invite).await?;
+    let user: crate::models::User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+        .bind(auth.user_id)
+        .fetch_one(&state.db)
+        .await?;
+
+    let member = team_invites::accept(&state, pending, &user).await?;
+
+    Ok(Json(member.into()))
+}
+
+/// POST /teams/{team_id}/invite/email - Email invitations to a list of addresses
+async fn invite_users_by_email(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(team_id): Path<String>,
+    Json(emails): Json<Vec<String>>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let team_id = parse_mm_or_uuid(&team_id)
+        .ok_or_else(|| crate::error::AppError::BadRequest("Invalid team_id".to_string()))?;
+    auth.require(&state, Permission::TeamManageMembers, Scope::Team(team_id))
+        .await?;
+
+    team_invites::send_email_invites(&state, team_id, auth.user_id, &emails).await?;
+
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// POST /teams/{team_id}/regenerate_invite_id
+async fn regenerate_invite_id(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(team_id): Path<String>,
+) -> ApiResult<Json<mm::Team>> {
+    let team_id = parse_mm_or_uuid(&team_id)
+        .ok_or_else(|| crate::error::AppError::BadRequest("Invalid team_id".to_string()))?;
+    auth.require(&state, Permission::TeamManage, Scope::Team(team_id))
+        .await?;
+
+    let invite_id = team_invites::regenerate_invite_id(&state.db, team_id).await?;
+    let team: Team = sqlx::query_as("SELECT * FROM teams WHERE id = $1")
+        .bind(team_id)
+        .fetch_one(&state.db)
+        .await?;
+    let mut mm_team: mm::Team = team.into();
+    mm_team.invite_id = invite_id;
+
+    Ok(Json(mm_team))
+}
@@ -0,0 +1,117 @@
+//! Resumable uploads
+//!
+//! Mattermost's upload sessions: a client declares a file, then sends its
+//! bytes over one or more requests, checking the session's `file_offset` to
+//! resume after a dropped connection.
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header::CONTENT_TYPE, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+
+use super::extractors::MmAuthUser;
+use super::users::resolve_user_id;
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::mattermost_compat::{id::parse_mm_or_uuid, models as mm};
+use crate::services::permissions::{Permission, Principal, Scope};
+use crate::services::uploads;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/uploads", post(create_upload))
+        .route("/uploads/{upload_id}", get(get_upload).post(upload_data))
+        .route("/users/{user_id}/uploads", get(get_user_uploads))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateUploadRequest {
+    #[serde(default)]
+    channel_id: String,
+    filename: String,
+    file_size: i64,
+}
+
+/// POST /uploads - Start an upload session
+async fn create_upload(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Json(input): Json<CreateUploadRequest>,
+) -> ApiResult<(StatusCode, Json<mm::UploadSession>)> {
+    let channel_id = parse_mm_or_uuid(&input.channel_id)
+        .ok_or_else(|| AppError::BadRequest("Invalid channel_id".to_string()))?;
+    auth.require(&state, Permission::PostCreate, Scope::Channel(channel_id))
+        .await?;
+
+    let session = uploads::create_session(
+        &state,
+        auth.user_id,
+        Some(channel_id),
+        &input.filename,
+        input.file_size,
+    )
+    .await?;
+
+    Ok((StatusCode::CREATED, Json(session.into())))
+}
+
+/// GET /uploads/{upload_id} - Get an upload session
+async fn get_upload(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(upload_id): Path<String>,
+) -> ApiResult<Json<mm::UploadSession>> {
+    let upload_id = parse_mm_or_uuid(&upload_id)
+        .ok_or_else(|| AppError::BadRequest("Invalid upload_id".to_string()))?;
+    let session = uploads::get_session(&state, auth.user_id, upload_id).await?;
+
+    Ok(Json(session.into()))
+}
+
+/// POST /uploads/{upload_id} - Append the request body to an upload
+///
+/// Answers 201 with the file once it is complete, and 204 before that. The
+/// body is streamed, and the session's size is its limit.
+async fn upload_data(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> ApiResult<Response> {
+    let upload_id = parse_mm_or_uuid(&upload_id)
+        .ok_or_else(|| AppError::BadRequest("Invalid upload_id".to_string()))?;
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    if content_type.starts_with("multipart/") {
+        return Err(AppError::BadRequest(
+            "Upload data must be sent as the raw request body".to_string(),
+        ));
+    }
+
+    let file = uploads::append(&state, auth.user_id, upload_id, body.into_data_stream()).await?;
+
+    Ok(match file {
+        Some(file) => (StatusCode::CREATED, Json(mm::FileInfo::from(file))).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    })
+}
+
+/// GET /users/{user_id}/uploads - List a user's unfinished uploads
+async fn get_user_uploads(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(user_id): Path<String>,
+) -> ApiResult<Json<Vec<mm::UploadSession>>> {
+    let user_id = resolve_user_id(&user_id, &auth)?;
+    let sessions = uploads::list_sessions(&state, user_id).await?;
+
+    Ok(Json(sessions.into_iter().map(Into::into).collect()))
+}
@@ -13,10 +13,19 @@ use uuid::Uuid;
 
 use super::extractors::MmAuthUser;
 use crate::api::AppState;
-use crate::auth::{create_token, verify_password};
+use crate::auth::{verify_dummy_password, verify_password};
 use crate::error::{ApiResult, AppError};
 use crate::mattermost_compat::{id::{encode_mm_id, parse_mm_or_uuid}, models as mm};
-use crate::models::{channel::Channel, channel::ChannelMember, Team, TeamMember, User};
+use crate::models::{
+    channel::Channel, channel::ChannelMember, CreateUserAccessToken, ResetPassword, RevokeSession,
+    SendAccountEmail, Team, TeamMember, User, UserAccessToken, UserAccessTokenAction, VerifyEmail,
+};
+use crate::services::{access_tokens, account_tokens, guests, ldap, login_lockout, mfa, sso};
+use crate::services::permissions::{
+    ensure_team_member, CanManageUsers, Permission, Principal, Require, Scope,
+};
+use crate::services::client_address::ClientIp;
+use crate::services::sessions::{self, SessionMetadata};
 
 pub fn router() -> Router<AppState> {
     Router::new()
@@ -60,8 +69,24 @@ pub fn router() -> Router<AppState> {
         .route("/users/{user_id}/image", get(get_user_image).post(upload_user_image))
         .route("/roles/names", post(get_roles_by_names))
         .route("/users/notifications", get(get_notifications).put(update_notifications))
-        .route("/users/me/sessions", get(get_sessions))
+        .route("/users/{user_id}/sessions", get(get_sessions))
+        .route("/users/{user_id}/sessions/revoke", post(revoke_session))
+        .route("/users/{user_id}/sessions/revoke/all", post(revoke_all_sessions))
+        .route("/users/{user_id}/promote", post(promote_user))
+        .route("/users/{user_id}/demote", post(demote_user))
+        .route("/users/{user_id}/tokens", get(get_user_tokens).post(create_user_token))
+        .route("/users/tokens/{token_id}", get(get_user_token))
+        .route("/users/tokens/revoke", post(revoke_user_token))
+        .route("/users/tokens/disable", post(disable_user_token))
+        .route("/users/tokens/enable", post(enable_user_token))
         .route("/users/logout", get(logout).post(logout))
+        .route("/users/password/reset/send", post(send_password_reset))
+        .route("/users/password/reset", post(reset_password))
+        .route("/users/email/verify/send", post(send_verification_email))
+        .route("/users/email/verify", post(verify_email))
+        .route("/users/mfa", post(check_mfa))
+        .route("/users/{user_id}/mfa", put(update_mfa))
+        .route("/users/{user_id}/mfa/generate", post(generate_mfa_secret))
         .route("/users/autocomplete", get(autocomplete_users))
         .route("/users/search", post(search_users))
         .route("/custom_profile_attributes/fields", get(get_custom_profile_attributes))
@@ -263,22 +288,6 @@ async fn ensure_team_exists(state: &AppState, team_id: Uuid) -> ApiResult<()> {
     Ok(())
 }
 
-async fn ensure_team_member(state: &AppState, user_id: Uuid, team_id: Uuid) -> ApiResult<()> {
-    let is_member: bool = sqlx::query_scalar(
-        "SELECT EXISTS(SELECT 1 FROM team_members WHERE user_id = $1 AND team_id = $2)",
-    )
-    .bind(user_id)
-    .bind(team_id)
-    .fetch_one(&state.db)
-    .await?;
-
-    if !is_member {
-        return Err(AppError::Forbidden("User is not a member of the team".to_string()));
-    }
-
-    Ok(())
-}
-
 fn build_default_categories(
     user_id: Uuid,
     team_id: Uuid,
@@ -601,36 +610,40 @@ struct LoginRequest {
     #[serde(default)]
     email: Option<String>,
     password: String,
-    #[allow(dead_code)]
     device_id: Option<String>,
+    /// MFA code
+    #[serde(default, alias = "mfa_token")]
+    token: Option<String>,
 }
 
 async fn login(
     State(state): State<AppState>,
     headers: HeaderMap,
+    ClientIp(client_ip): ClientIp,
     body: Bytes,
 ) -> ApiResult<impl IntoResponse> {
-    let input = parse_login_request(&headers, &body)?;
+    let mut input = parse_login_request(&headers, &body)?;
     let login_id = input
         .login_id
-        .or(input.email)
+        .take()
+        .or(input.email.take())
         .ok_or_else(|| AppError::BadRequest("Missing login_id".to_string()))?;
 
-    let user: Option<User> = sqlx::query_as(
-        "SELECT * FROM users WHERE (email = $1 OR username = $1) AND is_active = true",
-    )
-    .bind(&login_id)
-    .fetch_optional(&state.db)
-    .await?;
-
-    let user =
-        user.ok_or_else(|| AppError::Unauthorized("Invalid login credentials".to_string()))?;
-
-    if !verify_password(&input.password, &user.password_hash)? {
-        return Err(AppError::Unauthorized(
-            "Invalid login credentials".to_string(),
-        ));
-    }
+    let metadata =
+        SessionMetadata::from_request(&headers, client_ip).with_device_id(input.device_id.take());
+    let attempt =
+        login_lockout::LoginAttempt::start(&state, &login_id, metadata.ip_address.as_deref())
+            .await?;
+    let user = match check_credentials(&state, &login_id, &input).await {
+        Ok(user) => user,
+        Err(e) => {
+            if matches!(e, AppError::Unauthorized(_)) {
+                attempt.failed(&state).await;
+            }
+            return Err(e);
+        }
+    };
+    attempt.succeeded(&state).await;
 
     // Update last login
     sqlx::query("UPDATE users SET last_login_at = NOW() WHERE id = $1")
@@ -638,15 +651,7 @@ async fn login(
         .execute(&state.db)
         .await?;
 
-    // Generate token
-    let token = create_token(
-        user.id,
-        &user.email,
-        &user.role,
-        user.org_id,
-        &state.jwt_secret,
-        state.jwt_expiry_hours,
-    )?;
+    let token = sessions::create_session(&state, &user, metadata).await?;
 
     let mm_user: mm::User = user.into();
 
@@ -661,6 +666,160 @@ async fn login(
     Ok((headers, Json(mm_user)))
 }
 
+/// Check the password, and the MFA code if the account needs one
+async fn check_credentials(
+    state: &AppState,
+    login_id: &str,
+    input: &LoginRequest,
+) -> ApiResult<User> {
+    let user = match ldap::authenticate(state, login_id, &input.password).await? {
+        Some(user) => user,
+        None => {
+            let user: Option<User> = sqlx::query_as(
+                "SELECT * FROM users WHERE (email = $1 OR username = $1) AND is_active = true",
+            )
+            .bind(login_id)
+            .fetch_optional(&state.db)
+            .await?;
+
+            let Some(user) = user else {
+                verify_dummy_password(&input.password);
+                return Err(AppError::Unauthorized(
+                    "Invalid login credentials".to_string(),
+                ));
+            };
+
+            if !verify_password(&input.password, &user.password_hash)? {
+                return Err(AppError::Unauthorized(
+                    "Invalid login credentials".to_string(),
+                ));
+            }
+
+            sso::check_password_login(&state.db, &user).await?;
+            account_tokens::check_email_verified(&state.db, &user).await?;
+            guests::check_guest_login(&state.db, &user).await?;
+            user
+        }
+    };
+    mfa::check_login(state, &user, input.token.as_deref()).await?;
+
+    Ok(user)
+}
+
+/// POST /users/password/reset/send - Email a password reset link
+async fn send_password_reset(
+    State(state): State<AppState>,
+    Json(input): Json<SendAccountEmail>,
+) -> ApiResult<Json<serde_json::Value>> {
+    account_tokens::send_password_reset(&state, &input.email).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// POST /users/password/reset - Set a new password with a reset token
+async fn reset_password(
+    State(state): State<AppState>,
+    Json(input): Json<ResetPassword>,
+) -> ApiResult<Json<serde_json::Value>> {
+    account_tokens::reset_password(&state, &input.token, &input.new_password).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// POST /users/email/verify/send - Email a new verification link
+async fn send_verification_email(
+    State(state): State<AppState>,
+    Json(input): Json<SendAccountEmail>,
+) -> ApiResult<Json<serde_json::Value>> {
+    account_tokens::resend_email_verification(&state, &input.email).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// POST /users/email/verify - Confirm an email address
+async fn verify_email(
+    State(state): State<AppState>,
+    Json(input): Json<VerifyEmail>,
+) -> ApiResult<Json<serde_json::Value>> {
+    account_tokens::verify_email(&state, &input.token).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+#[derive(Deserialize)]
+struct CheckMfa {
+    login_id: String,
+}
+
+/// POST /users/mfa - Whether logging in needs an MFA code
+async fn check_mfa(
+    State(state): State<AppState>,
+    Json(input): Json<CheckMfa>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let required = mfa::login_requires_mfa(&state.db, &input.login_id).await?;
+    Ok(Json(serde_json::json!({"mfa_required": required})))
+}
+
+/// POST /users/{user_id}/mfa/generate - Start enrolling with a new TOTP secret
+async fn generate_mfa_secret(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(user_id): Path<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+    if user_id != "me" && parse_mm_or_uuid(&user_id) != Some(auth.user_id) {
+        return Err(AppError::Forbidden(
+            "Cannot generate an MFA secret for another user".to_string(),
+        ));
+    }
+
+    let secret = mfa::generate_secret(&state, auth.user_id).await?;
+    Ok(Json(serde_json::json!({
+        "secret": secret.secret,
+        "qr_code": secret.qr_code,
+    })))
+}
+
+#[derive(Deserialize)]
+struct UpdateMfa {
+    activate: bool,
+    #[serde(default)]
+    code: String,
+}
+
+/// PUT /users/{user_id}/mfa - Activate or deactivate MFA
+///
+/// Users switch their own MFA with a code. User managers can turn it off for
+/// others without one.
+async fn update_mfa(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(user_id): Path<String>,
+    Json(input): Json<UpdateMfa>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let user_id = if user_id == "me" {
+        auth.user_id
+    } else {
+        parse_mm_or_uuid(&user_id)
+            .ok_or_else(|| AppError::BadRequest("Invalid user ID".to_string()))?
+    };
+
+    if user_id == auth.user_id {
+        if input.activate {
+            mfa::activate(&state, user_id, &input.code).await?;
+        } else {
+            mfa::deactivate(&state, user_id, &input.code).await?;
+        }
+    } else {
+        let can_manage = auth
+            .can(&state, Permission::UserManage, Scope::System)
+            .await?;
+        if input.activate || !can_manage {
+            return Err(AppError::Forbidden(
+                "Cannot manage another user's MFA".to_string(),
+            ));
+        }
+        mfa::reset(&state, user_id).await?;
+    }
+
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
 fn parse_login_request(headers: &HeaderMap, body: &Bytes) -> ApiResult<LoginRequest> {
     let content_type = headers
         .get(axum::http::header::CONTENT_TYPE)
@@ -892,6 +1051,10 @@ async fn my_team_channels_not_members(
     let team_id = parse_mm_or_uuid(&team_id)
         .ok_or_else(|| AppError::BadRequest("Invalid team_id".to_string()))?;
 
+    if guests::is_guest(&auth.role) {
+        return Ok(Json(Vec::new()));
+    }
+
     let page = query.page.unwrap_or(0).max(0);
     let per_page = query.per_page.unwrap_or(60).clamp(1, 200);
     let offset = page * per_page;
@@ -946,9 +1109,8 @@ fn normalize_notify_props(value: serde_json::Value) -> serde_json::Value {
 #[derive(Deserialize)]
 struct AttachDeviceRequest {
     device_id: String,
-    #[allow(dead_code)]
-    token: String,
-    #[allow(dead_code)]
+    #[serde(default)]
+    token: Option<String>,
     platform: Option<String>,
 }
 
@@ -959,6 +1121,17 @@ async fn attach_device(
     body: Bytes,
 ) -> ApiResult<impl IntoResponse> {
     let input: AttachDeviceRequest = parse_body(&headers, &body, "Invalid device body")?;
+
+    // Mattermost apps send "<platform>:<push token>" as the device id
+    let token = input.token.filter(|t| !t.is_empty());
+    let (platform, token) = match (token, input.device_id.split_once(':')) {
+        (Some(token), _) => (input.platform, token),
+        (None, Some((platform, token))) => (Some(platform.to_string()), token.to_string()),
+        (None, None) => {
+            return Err(AppError::BadRequest("Missing device token".to_string()));
+        }
+    };
+
     sqlx::query(
         r#"
         INSERT INTO user_devices (user_id, device_id, token, platform)
@@ -968,11 +1141,12 @@ async fn attach_device(
         "#,
     )
     .bind(auth.user_id)
-    .bind(input.device_id)
-    .bind(input.token)
-    .bind(input.platform.unwrap_or_else(|| "unknown".to_string()))
+    .bind(&input.device_id)
+    .bind(token)
+    .bind(platform.unwrap_or_else(|| "unknown".to_string()))
     .execute(&state.db)
     .await?;
+    sessions::set_device_id(&state, auth.session_id, &input.device_id).await?;
 
     Ok(Json(serde_json::json!({"status": "OK"})))
 }
@@ -1091,11 +1265,221 @@ async fn update_notifications(
     Ok(Json(serde_json::json!({"status": "OK"})))
 }
 
-async fn get_sessions() -> ApiResult<Json<Vec<serde_json::Value>>> {
-    Ok(Json(vec![]))
+/// Resolve `user_id` for session endpoints, which only user managers may use on others
+async fn resolve_session_user(state: &AppState, user_id: &str, auth: &MmAuthUser) -> ApiResult<Uuid> {
+    if user_id == "me" {
+        return Ok(auth.user_id);
+    }
+
+    let user_id = parse_mm_or_uuid(user_id)
+        .ok_or_else(|| AppError::BadRequest("Invalid user ID".to_string()))?;
+    if user_id != auth.user_id && !auth.can(state, Permission::UserManage, Scope::System).await? {
+        return Err(AppError::Forbidden(
+            "Cannot manage another user's sessions".to_string(),
+        ));
+    }
+
+    Ok(user_id)
+}
+
+/// GET /users/{user_id}/sessions - List active sessions
+async fn get_sessions(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(user_id): Path<String>,
+) -> ApiResult<Json<Vec<mm::Session>>> {
+    let user_id = resolve_session_user(&state, &user_id, &auth).await?;
+    let sessions = sessions::list_sessions(&state, user_id).await?;
+
+    Ok(Json(sessions.into_iter().map(Into::into).collect()))
+}
+
+/// POST /users/{user_id}/sessions/revoke - Revoke one session
+async fn revoke_session(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(user_id): Path<String>,
+    Json(input): Json<RevokeSession>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let user_id = resolve_session_user(&state, &user_id, &auth).await?;
+    let session_id = parse_mm_or_uuid(&input.session_id)
+        .ok_or_else(|| AppError::BadRequest("Invalid session_id".to_string()))?;
+
+    sessions::revoke_session(&state, user_id, session_id).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// POST /users/{user_id}/sessions/revoke/all - Revoke every session
+async fn revoke_all_sessions(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(user_id): Path<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let user_id = resolve_session_user(&state, &user_id, &auth).await?;
+
+    sessions::revoke_all_sessions(&state, user_id).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
 }
 
-async fn logout() -> ApiResult<Json<serde_json::Value>> {
+/// POST /users/{user_id}/promote - Turn a guest into a regular user
+async fn promote_user(
+    State(state): State<AppState>,
+    Require(_, _): Require<CanManageUsers, MmAuthUser>,
+    Path(user_id): Path<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let user_id = parse_mm_or_uuid(&user_id)
+        .ok_or_else(|| AppError::BadRequest("Invalid user ID".to_string()))?;
+
+    guests::set_guest(&state, user_id, false).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// POST /users/{user_id}/demote - Turn a user into a guest
+async fn demote_user(
+    State(state): State<AppState>,
+    Require(_, _): Require<CanManageUsers, MmAuthUser>,
+    Path(user_id): Path<String>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let user_id = parse_mm_or_uuid(&user_id)
+        .ok_or_else(|| AppError::BadRequest("Invalid user ID".to_string()))?;
+
+    guests::set_guest(&state, user_id, true).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// Check that the caller may manage `user_id`'s access tokens
+///
+/// Users manage their own tokens, bot owners those of their bots, and user
+/// managers everyone's.
+async fn authorize_token_user(state: &AppState, auth: &MmAuthUser, user_id: Uuid) -> ApiResult<()> {
+    if user_id == auth.user_id || auth.can(state, Permission::UserManage, Scope::System).await? {
+        return Ok(());
+    }
+
+    let owns_bot: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM bots WHERE user_id = $1 AND owner_id = $2)",
+    )
+    .bind(user_id)
+    .bind(auth.user_id)
+    .fetch_one(&state.db)
+    .await?;
+    if !owns_bot {
+        return Err(AppError::Forbidden(
+            "Cannot manage another user's tokens".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Load the token named in a request and check the caller may manage it
+async fn resolve_token(
+    state: &AppState,
+    auth: &MmAuthUser,
+    token_id: &str,
+) -> ApiResult<UserAccessToken> {
+    access_tokens::ensure_enabled(&state.db).await?;
+    let token_id = parse_mm_or_uuid(token_id)
+        .ok_or_else(|| AppError::BadRequest("Invalid token_id".to_string()))?;
+    let token = access_tokens::get_user_token(&state.db, token_id).await?;
+    authorize_token_user(state, auth, token.user_id).await?;
+
+    Ok(token)
+}
+
+/// POST /users/{user_id}/tokens - Create a personal access token
+async fn create_user_token(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(user_id): Path<String>,
+    Json(input): Json<CreateUserAccessToken>,
+) -> ApiResult<Json<mm::UserAccessToken>> {
+    access_tokens::ensure_enabled(&state.db).await?;
+    let user_id = if user_id == "me" {
+        auth.user_id
+    } else {
+        parse_mm_or_uuid(&user_id)
+            .ok_or_else(|| AppError::BadRequest("Invalid user ID".to_string()))?
+    };
+    authorize_token_user(&state, &auth, user_id).await?;
+
+    let token =
+        access_tokens::create_user_token(&state.db, user_id, input.description.as_deref()).await?;
+    Ok(Json(token.into()))
+}
+
+/// GET /users/{user_id}/tokens - List a user's personal access tokens
+async fn get_user_tokens(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(user_id): Path<String>,
+) -> ApiResult<Json<Vec<mm::UserAccessToken>>> {
+    access_tokens::ensure_enabled(&state.db).await?;
+    let user_id = if user_id == "me" {
+        auth.user_id
+    } else {
+        parse_mm_or_uuid(&user_id)
+            .ok_or_else(|| AppError::BadRequest("Invalid user ID".to_string()))?
+    };
+    authorize_token_user(&state, &auth, user_id).await?;
+
+    let tokens = access_tokens::list_user_tokens(&state.db, user_id).await?;
+    Ok(Json(tokens.into_iter().map(Into::into).collect()))
+}
+
+/// GET /users/tokens/{token_id} - Get a personal access token
+async fn get_user_token(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Path(token_id): Path<String>,
+) -> ApiResult<Json<mm::UserAccessToken>> {
+    let token = resolve_token(&state, &auth, &token_id).await?;
+    Ok(Json(token.into()))
+}
+
+/// POST /users/tokens/revoke - Delete a personal access token
+async fn revoke_user_token(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Json(input): Json<UserAccessTokenAction>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let token = resolve_token(&state, &auth, &input.token_id).await?;
+    access_tokens::revoke_user_token(&state, &token).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// POST /users/tokens/disable - Stop a personal access token from working
+async fn disable_user_token(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Json(input): Json<UserAccessTokenAction>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let token = resolve_token(&state, &auth, &input.token_id).await?;
+    access_tokens::set_user_token_active(&state, &token, false).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// POST /users/tokens/enable - Re-enable a disabled personal access token
+async fn enable_user_token(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+    Json(input): Json<UserAccessTokenAction>,
+) -> ApiResult<Json<serde_json::Value>> {
+    let token = resolve_token(&state, &auth, &input.token_id).await?;
+    access_tokens::set_user_token_active(&state, &token, true).await?;
+    Ok(Json(serde_json::json!({"status": "OK"})))
+}
+
+/// POST /users/logout - End the current session
+async fn logout(
+    State(state): State<AppState>,
+    auth: MmAuthUser,
+) -> ApiResult<Json<serde_json::Value>> {
+    match sessions::revoke_session(&state, auth.user_id, auth.session_id).await {
+        // Access tokens are not sessions and stay valid until revoked
+        Ok(()) | Err(AppError::NotFound(_)) => {}
+        Err(e) => return Err(e),
+    }
     Ok(Json(serde_json::json!({"status": "OK"})))
 }
 
@@ -1683,9 +2067,13 @@ async fn upload_user_image(
                 continue;
             }
 
-            // Upload to S3
+            // Upload to storage
             let key = format!("avatars/{}.png", user_uuid);
-            state.s3_client.upload(&key, data, &content_type).await?;
+            state
+                .storage
+                .current()
+                .upload(&key, data, &content_type)
+                .await?;
 
             // Update user avatar_url
             let avatar_url = format!("/api/v4/users/{}/image", encode_mm_id(user_uuid));
@@ -15,9 +15,10 @@ use tokio::time::interval;
 use chrono;
 
 use crate::api::AppState;
-use crate::auth::validate_token;
+use crate::auth::Claims;
 use crate::mattermost_compat::{id::{encode_mm_id, parse_mm_or_uuid}, models as mm};
 use crate::realtime::{TypingEvent, WsEnvelope};
+use crate::services::sessions;
 
 #[derive(Debug, Deserialize)]
 pub struct WsQuery {
@@ -67,19 +68,19 @@ pub async fn handle_websocket(
         }
     }
 
-    let user_id = if let Some(ref t) = token {
-        validate_token(t, &state.jwt_secret).ok().map(|c| c.claims.sub)
+    let claims = if let Some(ref t) = token {
+        sessions::authenticate(&state, t).await.ok()
     } else {
         None
     };
 
-    ws.on_upgrade(move |socket| websocket_loop(socket, state, user_id, seq_start))
+    ws.on_upgrade(move |socket| websocket_loop(socket, state, claims, seq_start))
 }
 
 async fn websocket_loop(
     socket: WebSocket,
     state: AppState,
-    mut user_id: Option<Uuid>,
+    mut claims: Option<Claims>,
     seq_start: i64,
 ) {
     let (mut sender, mut receiver) = socket.split();
@@ -87,14 +88,14 @@ async fn websocket_loop(
     let connection_id = encode_mm_id(Uuid::new_v4());
 
     // 1. Wait for authentication if not already authenticated via handshake
-    if user_id.is_none() {
+    if claims.is_none() {
         while let Some(msg) = receiver.next().await {
             if let Ok(Message::Text(text)) = msg {
                 if let Ok(value) = serde_json::from_str::<serde_json::Value>(&text) {
                     if value["action"] == "authentication_challenge" {
                         if let Some(token) = value["data"]["token"].as_str() {
-                            if let Ok(claims) = validate_token(token, &state.jwt_secret) {
-                                user_id = Some(claims.claims.sub);
+                            if let Ok(authenticated) = sessions::authenticate(&state, token).await {
+                                claims = Some(authenticated);
 
                                 // Send OK response
                                 let resp = json!({
@@ -113,8 +114,8 @@ async fn websocket_loop(
         }
     }
 
-    let user_id = match user_id {
-        Some(uid) => uid,
+    let (user_id, session_id) = match claims {
+        Some(claims) => (claims.sub, claims.jti),
         None => return, // Failed to auth
     };
 
@@ -157,7 +158,10 @@ async fn websocket_loop(
         Err(_) => "Unknown".to_string(),
     };
 
-    let (connection_id, rx) = state.ws_hub.add_connection(user_id, username.clone()).await;
+    let (connection_id, rx) = state
+        .ws_hub
+        .add_connection(user_id, session_id, username.clone())
+        .await;
 
     // Subscribe to teams and channels
     let teams = sqlx::query_scalar::<_, Uuid>("SELECT team_id FROM team_members WHERE user_id = $1")
@@ -219,6 +223,8 @@ async fn websocket_loop(
                             }
                         }
                     } else {
+                        // Disconnected by the server, e.g. the session was revoked
+                        let _ = sender_sink.send(Message::Close(None)).await;
                         break;
                     }
                 }
@@ -241,9 +247,11 @@ async fn websocket_loop(
         }
     });
 
+    let mut sender_task = sender_task;
+    let mut receive_task = receive_task;
     tokio::select! {
-        _ = sender_task => {},
-        _ = receive_task => {},
+        _ = &mut sender_task => receive_task.abort(),
+        _ = &mut receive_task => sender_task.abort(),
     }
 
     state.ws_hub.remove_connection(user_id, connection_id).await;
@@ -277,6 +285,21 @@ fn map_envelope_to_mm(env: &WsEnvelope, seq: i64) -> Option<mm::WebSocketMessage
                 None
             }
         }
+        "ephemeral_message" => {
+            let mm_post = serde_json::from_value::<mm::Post>(env.data.clone()).ok().or_else(|| {
+                serde_json::from_value::<crate::models::post::PostResponse>(env.data.clone())
+                    .ok()
+                    .map(Into::into)
+            })?;
+            let post_json = serde_json::to_string(&mm_post).unwrap_or_default();
+
+            Some(mm::WebSocketMessage {
+                seq: Some(seq),
+                event: "ephemeral_message".to_string(),
+                data: json!({ "post": post_json }),
+                broadcast: map_broadcast(env.broadcast.as_ref()),
+            })
+        }
         "user_typing" => {
             if let Ok(typing) = serde_json::from_value::<TypingEvent>(env.data.clone()) {
                 let parent_id = typing
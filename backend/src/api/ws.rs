@@ -15,7 +15,7 @@ use serde::Deserialize;
 // use std::sync::Arc;
 
 use super::AppState;
-use crate::auth::validate_token;
+use crate::services::sessions;
 use crate::realtime::{
     ClientEnvelope, EventType, PresenceEvent, TypingCommandData, TypingEvent, WsBroadcast,
     WsEnvelope,
@@ -79,8 +79,8 @@ async fn ws_handler(
     tracing::info!("WS Handshake - Token present: {}, Protocol: {:?}", !token.is_empty(), requested_protocol);
 
     // Validate token
-    let claims = match validate_token(&token, &state.jwt_secret) {
-        Ok(data) => data.claims,
+    let claims = match sessions::authenticate(&state, &token).await {
+        Ok(claims) => claims,
         Err(_) => {
             tracing::warn!("WS Handshake failed: Invalid token");
             return Response::builder()
@@ -91,6 +91,7 @@ async fn ws_handler(
     };
 
     let user_id = claims.sub;
+    let session_id = claims.jti;
     let max_connections = get_max_simultaneous_connections(&state).await;
     let current_connections = state.ws_hub.user_connection_count(user_id).await;
     if current_connections >= max_connections {
@@ -108,7 +109,7 @@ async fn ws_handler(
         Err(_) => "Unknown".to_string(),
     };
 
-    let mut response = ws.on_upgrade(move |socket| handle_socket(socket, user_id, username, state));
+    let mut response = ws.on_upgrade(move |socket| handle_socket(socket, user_id, session_id, username, state));
 
     // Spec compliance: if client requested a protocol, we MUST return it
     if let Some(p) = requested_protocol {
@@ -121,11 +122,20 @@ async fn ws_handler(
 }
 
 /// Handle WebSocket connection
-async fn handle_socket(socket: WebSocket, user_id: uuid::Uuid, username: String, state: AppState) {
+async fn handle_socket(
+    socket: WebSocket,
+    user_id: uuid::Uuid,
+    session_id: uuid::Uuid,
+    username: String,
+    state: AppState,
+) {
     let (mut sender, mut receiver) = socket.split();
 
     // Add connection to hub
-    let (connection_id, mut rx) = state.ws_hub.add_connection(user_id, username.clone()).await;
+    let (connection_id, mut rx) = state
+        .ws_hub
+        .add_connection(user_id, session_id, username.clone())
+        .await;
 
     // Fetch user's teams and subscribe
     let teams =
@@ -165,9 +175,11 @@ async fn handle_socket(socket: WebSocket, user_id: uuid::Uuid, username: String,
     let send_task = tokio::spawn(async move {
         while let Ok(msg) = rx.recv().await {
             if sender.send(Message::Text(msg.into())).await.is_err() {
-                break;
+                return;
             }
         }
+        // Disconnected by the server, e.g. the session was revoked
+        let _ = sender.send(Message::Close(None)).await;
     });
 
     // Handle incoming messages from client
@@ -382,9 +394,11 @@ async fn handle_socket(socket: WebSocket, user_id: uuid::Uuid, username: String,
     });
 
     // Wait for either task to complete
+    let mut send_task = send_task;
+    let mut receive_task = receive_task;
     tokio::select! {
-        _ = send_task => {},
-        _ = receive_task => {},
+        _ = &mut send_task => receive_task.abort(),
+        _ = &mut receive_task => send_task.abort(),
     }
 
     // Cleanup
@@ -12,6 +12,8 @@ use crate::error::AppError;
 pub struct Claims {
     /// Subject (user ID)
     pub sub: Uuid,
+    /// Token ID, which is the ID of the session it belongs to
+    pub jti: Uuid,
     /// User email
     pub email: String,
     /// User role
@@ -28,6 +30,7 @@ impl Claims {
     /// Create new claims for a user
     pub fn new(
         user_id: Uuid,
+        session_id: Uuid,
         email: String,
         role: String,
         org_id: Option<Uuid>,
@@ -38,6 +41,7 @@ impl Claims {
 
         Self {
             sub: user_id,
+            jti: session_id,
             email,
             role,
             org_id,
@@ -50,6 +54,7 @@ impl Claims {
 /// Create a JWT token for a user
 pub fn create_token(
     user_id: Uuid,
+    session_id: Uuid,
     email: &str,
     role: &str,
     org_id: Option<Uuid>,
@@ -58,6 +63,7 @@ pub fn create_token(
 ) -> Result<String, AppError> {
     let claims = Claims::new(
         user_id,
+        session_id,
         email.to_string(),
         role.to_string(),
         org_id,
@@ -89,12 +95,23 @@ mod tests {
     #[test]
     fn test_create_and_validate_token() {
         let user_id = Uuid::new_v4();
+        let session_id = Uuid::new_v4();
         let secret = "test-secret-key";
 
-        let token = create_token(user_id, "test@example.com", "member", None, secret, 24).unwrap();
+        let token = create_token(
+            user_id,
+            session_id,
+            "test@example.com",
+            "member",
+            None,
+            secret,
+            24,
+        )
+        .unwrap();
 
         let decoded = validate_token(&token, secret).unwrap();
         assert_eq!(decoded.claims.sub, user_id);
+        assert_eq!(decoded.claims.jti, session_id);
         assert_eq!(decoded.claims.email, "test@example.com");
     }
 
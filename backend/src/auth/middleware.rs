@@ -1,12 +1,12 @@
 //! Auth middleware and extractors
 
 use axum::{
-    extract::FromRequestParts,
+    extract::{FromRequestParts, OriginalUri},
     http::{header::AUTHORIZATION, request::Parts},
 };
 use uuid::Uuid;
 
-use super::jwt::{validate_token, Claims};
+use super::jwt::Claims;
 use crate::api::AppState;
 use crate::error::AppError;
 
@@ -14,6 +14,7 @@ use crate::error::AppError;
 #[derive(Debug, Clone)]
 pub struct AuthUser {
     pub user_id: Uuid,
+    pub session_id: Uuid,
     pub email: String,
     pub role: String,
     pub org_id: Option<Uuid>,
@@ -23,6 +24,7 @@ impl From<Claims> for AuthUser {
     fn from(claims: Claims) -> Self {
         Self {
             user_id: claims.sub,
+            session_id: claims.jti,
             email: claims.email,
             role: claims.role,
             org_id: claims.org_id,
@@ -52,13 +54,27 @@ where
             .strip_prefix("Bearer ")
             .ok_or_else(|| AppError::Unauthorized("Invalid authorization format".to_string()))?;
 
-        // Validate token
-        let token_data = validate_token(token, &app_state.jwt_secret)?;
+        // Validate token and session
+        let session = crate::services::sessions::authenticate_request(
+            &app_state,
+            &mut parts.extensions,
+            token,
+        )
+        .await?;
+        crate::services::mfa::check_enrollment(&app_state, &session, request_path(parts)).await?;
 
-        Ok(AuthUser::from(token_data.claims))
+        Ok(AuthUser::from(session.claims))
     }
 }
 
+/// Full path of the request, before any nesting stripped a prefix
+pub(crate) fn request_path(parts: &Parts) -> &str {
+    parts
+        .extensions
+        .get::<OriginalUri>()
+        .map_or(parts.uri.path(), |uri| uri.path())
+}
+
 /// Helper trait to extract AppState from state
 pub trait FromRef<T> {
     fn from_ref(input: &T) -> Self;
@@ -1,5 +1,7 @@
 //! Password hashing with argon2
 
+use std::sync::LazyLock;
+
 use argon2::{
     password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
@@ -7,6 +9,16 @@ use argon2::{
 
 use crate::error::AppError;
 
+/// Stored instead of a hash for accounts that cannot sign in with a password
+pub const NO_PASSWORD: &str = "NO_PASSWORD";
+
+/// Whether `hash` is a real password hash rather than a placeholder such as
+/// [`NO_PASSWORD`]
+pub fn has_password(hash: &str) -> bool {
+    // PHC strings always start with the algorithm identifier
+    hash.starts_with('$')
+}
+
 /// Hash a password using argon2
 pub fn hash_password(password: &str) -> Result<String, AppError> {
     let salt = SaltString::generate(&mut OsRng);
@@ -20,6 +32,10 @@ pub fn hash_password(password: &str) -> Result<String, AppError> {
 
 /// Verify a password against a hash
 pub fn verify_password(password: &str, hash: &str) -> Result<bool, AppError> {
+    if !has_password(hash) {
+        return Ok(false);
+    }
+
     let parsed_hash = PasswordHash::new(hash)
         .map_err(|e| AppError::Internal(format!("Invalid password hash: {}", e)))?;
 
@@ -28,6 +44,16 @@ pub fn verify_password(password: &str, hash: &str) -> Result<bool, AppError> {
         .is_ok())
 }
 
+/// Take as long as [`verify_password`] on a real hash
+///
+/// For logins naming an unknown account, so response times do not reveal
+/// which accounts exist.
+pub fn verify_dummy_password(password: &str) {
+    static DUMMY_HASH: LazyLock<String> =
+        LazyLock::new(|| hash_password("dummy password").unwrap_or_default());
+    let _ = verify_password(password, &DUMMY_HASH);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -54,4 +80,11 @@ mod tests {
         assert!(verify_password(password, &hash1).unwrap());
         assert!(verify_password(password, &hash2).unwrap());
     }
+
+    #[test]
+    fn placeholders_never_verify() {
+        assert!(!has_password(NO_PASSWORD));
+        assert!(!verify_password("", NO_PASSWORD).unwrap());
+        assert!(!verify_password("BOT_NO_PASSWORD", "BOT_NO_PASSWORD").unwrap());
+    }
 }
@@ -60,6 +60,36 @@ pub struct Config {
     #[serde(default = "default_s3_region")]
     pub s3_region: String,
 
+    /// Where new files are stored: "s3" or "local"
+    #[serde(default = "default_storage_backend")]
+    pub storage_backend: String,
+
+    /// Directory for the local storage backend
+    #[serde(default = "default_local_storage_path")]
+    pub local_storage_path: String,
+
+    /// Base URL of the server in links to locally stored files
+    /// (relative links when unset)
+    #[serde(default)]
+    pub local_storage_url: Option<String>,
+
+    /// Files processed for previews at the same time
+    #[serde(default = "default_media_workers")]
+    pub media_workers: usize,
+
+    /// ffmpeg binary for video poster frames (no posters when unset)
+    #[serde(default)]
+    pub ffmpeg_path: Option<String>,
+
+    /// Extract the text of uploaded documents so file search can match it
+    #[serde(default = "default_true")]
+    pub extract_file_content: bool,
+
+    /// Comma-separated addresses and CIDR ranges of reverse proxies whose
+    /// `X-Forwarded-For` and `X-Real-IP` headers are trusted
+    #[serde(default)]
+    pub trusted_proxies: String,
+
     /// Initial admin email
     #[serde(default)]
     pub admin_user: Option<String>,
@@ -97,6 +127,22 @@ fn default_s3_region() -> String {
     "us-east-1".to_string()
 }
 
+fn default_storage_backend() -> String {
+    "s3".to_string()
+}
+
+fn default_local_storage_path() -> String {
+    "./data/files".to_string()
+}
+
+fn default_media_workers() -> usize {
+    2
+}
+
+fn default_true() -> bool {
+    true
+}
+
 impl Config {
     /// Load configuration from environment variables
     pub fn load() -> anyhow::Result<Self> {
@@ -1,6 +1,8 @@
 //! Cryptography utilities
 use crate::error::AppError;
+use argon2::password_hash::rand_core::{OsRng, RngCore};
 use magic_crypt::{new_magic_crypt, MagicCryptTrait};
+use sha2::{Digest, Sha256};
 
 /// Encrypts a plaintext string using AES-GCM
 pub fn encrypt(plaintext: &str, key: &str) -> String {
@@ -14,3 +16,40 @@ pub fn decrypt(ciphertext: &str, key: &str) -> Result<String, AppError> {
     mc.decrypt_base64_to_string(ciphertext)
         .map_err(|e| AppError::Internal(format!("Decryption failed: {}", e)))
 }
+
+/// Generates a random 256-bit token, hex encoded
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+/// Hashes a token for storage with SHA-256, hex encoded
+///
+/// Tokens are random, so a fast unsalted hash is enough to keep a database
+/// leak from exposing usable tokens.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_hash_is_hex_sha256() {
+        let hash = hash_token("abc");
+        assert_eq!(64, hash.len());
+        assert_eq!(
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            hash
+        );
+    }
+
+    #[test]
+    fn random_tokens_differ() {
+        let token = random_token();
+        assert_eq!(64, token.len());
+        assert_ne!(token, random_token());
+    }
+}
@@ -0,0 +1,311 @@
+//! Email notification delivery job
+//!
+//! This module provides a background task that sends the posts queued in
+//! `email_notifications` to their recipients. A user's queued posts go out
+//! together as one email once the oldest has waited for the user's
+//! `email_interval` (immediately, every 15 minutes or hourly). Posts read in
+//! the meantime are dropped from the email.
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::error::ApiResult;
+use crate::mattermost_compat::id::encode_mm_id;
+use crate::models::{EmailConfig, SiteConfig};
+use crate::services::email_notifications::{email_interval_secs, EMAIL_INTERVAL_IMMEDIATE_SECS};
+use crate::services::email_templates::{self, Branding, NotificationPost};
+
+/// How often the job looks for due notifications
+const POLL_INTERVAL_SECS: u64 = 30;
+
+/// Processed notifications older than this are deleted
+const PROCESSED_RETENTION_DAYS: i32 = 30;
+
+/// A user with queued notifications
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct PendingUser {
+    user_id: Uuid,
+    oldest: DateTime<Utc>,
+    email_interval: Option<String>,
+}
+
+/// A queued post that is still unread
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct UnreadPost {
+    post_id: Uuid,
+    message: String,
+    created_at: DateTime<Utc>,
+    sender: String,
+    channel_type: String,
+    channel_display_name: Option<String>,
+    channel_name: String,
+    team_name: String,
+}
+
+/// Statistics from a delivery run
+#[derive(Debug, Default)]
+pub struct EmailNotificationStats {
+    pub emails_sent: u64,
+    pub posts_sent: u64,
+    pub posts_skipped: u64,
+    pub failed: u64,
+    pub purged: u64,
+}
+
+/// Users whose oldest queued post has waited for their email interval
+async fn find_due_users(db: &PgPool, now: DateTime<Utc>) -> Result<Vec<Uuid>, sqlx::Error> {
+    let pending: Vec<PendingUser> = sqlx::query_as(
+        r#"
+        SELECT n.user_id, MIN(n.created_at) AS oldest, mp.value AS email_interval
+        FROM email_notifications n
+        LEFT JOIN mattermost_preferences mp
+               ON mp.user_id = n.user_id AND mp.category = 'notifications'
+              AND mp.name = 'email_interval'
+        WHERE n.status = 'pending'
+        GROUP BY n.user_id, mp.value
+        "#,
+    )
+    .fetch_all(db)
+    .await?;
+
+    Ok(pending
+        .into_iter()
+        .filter(|p| match email_interval_secs(p.email_interval.as_deref()) {
+            None => true, // turned off since queueing; drop below
+            Some(secs) if secs <= EMAIL_INTERVAL_IMMEDIATE_SECS => true,
+            Some(secs) => p.oldest + Duration::seconds(secs) <= now,
+        })
+        .map(|p| p.user_id)
+        .collect())
+}
+
+/// Claim a user's queued notifications by marking them sent
+///
+/// Claiming and marking happen in one statement so that concurrent replicas
+/// never email the same post twice.
+async fn claim_notifications(db: &PgPool, user_id: Uuid) -> Result<Vec<Uuid>, sqlx::Error> {
+    sqlx::query_scalar(
+        r#"
+        UPDATE email_notifications SET status = 'sent', processed_at = NOW()
+        WHERE user_id = $1 AND status = 'pending'
+        RETURNING post_id
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await
+}
+
+async fn set_status(
+    db: &PgPool,
+    user_id: Uuid,
+    post_ids: &[Uuid],
+    status: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE email_notifications SET status = $3, processed_at = NOW() WHERE user_id = $1 AND post_id = ANY($2)",
+    )
+    .bind(user_id)
+    .bind(post_ids)
+    .bind(status)
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Email a user the claimed posts they have not read yet
+///
+/// Returns the number of posts included in the email.
+async fn deliver_notifications(
+    state: &AppState,
+    config: &EmailConfig,
+    branding: &Branding,
+    user_id: Uuid,
+    post_ids: &[Uuid],
+) -> ApiResult<usize> {
+    let (email, email_interval): (String, Option<String>) = sqlx::query_as(
+        r#"
+        SELECT u.email, mp.value
+        FROM users u
+        LEFT JOIN mattermost_preferences mp
+               ON mp.user_id = u.id AND mp.category = 'notifications'
+              AND mp.name = 'email_interval'
+        WHERE u.id = $1
+        "#,
+    )
+    .bind(user_id)
+    .fetch_one(&state.db)
+    .await?;
+
+    let posts: Vec<UnreadPost> = if email_interval_secs(email_interval.as_deref()).is_some() {
+        sqlx::query_as(
+            r#"
+            SELECT p.id AS post_id, p.message, p.created_at, u.username AS sender,
+                   c.type::text AS channel_type, c.display_name AS channel_display_name,
+                   c.name AS channel_name, t.name AS team_name
+            FROM posts p
+            JOIN users u ON u.id = p.user_id
+            JOIN channels c ON c.id = p.channel_id
+            JOIN teams t ON t.id = c.team_id
+            JOIN channel_members cm ON cm.channel_id = p.channel_id AND cm.user_id = $1
+            LEFT JOIN channel_reads cr ON cr.channel_id = p.channel_id AND cr.user_id = $1
+            WHERE p.id = ANY($2)
+              AND p.deleted_at IS NULL
+              AND (cm.last_viewed_at IS NULL OR cm.last_viewed_at < p.created_at)
+              AND p.seq > COALESCE(cr.last_read_message_id, 0)
+            ORDER BY p.created_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(post_ids)
+        .fetch_all(&state.db)
+        .await?
+    } else {
+        Vec::new()
+    };
+
+    let unread: Vec<Uuid> = posts.iter().map(|p| p.post_id).collect();
+    let skipped: Vec<Uuid> = post_ids
+        .iter()
+        .filter(|id| !unread.contains(id))
+        .copied()
+        .collect();
+    if !skipped.is_empty() {
+        set_status(&state.db, user_id, &skipped, "skipped").await?;
+    }
+    if posts.is_empty() {
+        return Ok(0);
+    }
+
+    let items: Vec<NotificationPost> = posts
+        .into_iter()
+        .map(|p| NotificationPost {
+            channel: (p.channel_type != "direct").then(|| {
+                p.channel_display_name
+                    .filter(|n| !n.is_empty())
+                    .unwrap_or(p.channel_name)
+            }),
+            sender: p.sender,
+            message: p.message,
+            created_at: p.created_at,
+            permalink: format!(
+                "{}/{}/pl/{}",
+                branding.site_url,
+                p.team_name,
+                encode_mm_id(p.post_id)
+            ),
+        })
+        .collect();
+    let rendered = email_templates::notification_digest(branding, &items);
+
+    if let Err(e) = crate::services::email::send_html_email(
+        state,
+        config,
+        &email,
+        &rendered.subject,
+        &rendered.html,
+        &rendered.text,
+    )
+    .await
+    {
+        // Leave them queued for the next run
+        set_status(&state.db, user_id, &unread, "pending").await?;
+        return Err(e);
+    }
+
+    Ok(items.len())
+}
+
+/// Delete processed notifications past retention
+pub async fn purge_processed_notifications(db: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM email_notifications
+        WHERE status <> 'pending'
+          AND processed_at < NOW() - make_interval(days => $1)
+        "#,
+    )
+    .bind(PROCESSED_RETENTION_DAYS)
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Run a single delivery pass
+pub async fn run_email_notifications(
+    state: &AppState,
+) -> Result<EmailNotificationStats, sqlx::Error> {
+    let mut stats = EmailNotificationStats::default();
+
+    let row: Option<(sqlx::types::Json<SiteConfig>, sqlx::types::Json<EmailConfig>)> =
+        sqlx::query_as("SELECT site, email FROM server_config WHERE id = 'default'")
+            .fetch_optional(&state.db)
+            .await?;
+    let Some((site, config)) = row else {
+        return Ok(stats);
+    };
+    // Keep notifications queued until SMTP is set up
+    if config.smtp_host.is_empty() {
+        return Ok(stats);
+    }
+    let branding = Branding::new(&site, &config);
+
+    for user_id in find_due_users(&state.db, Utc::now()).await? {
+        let post_ids = claim_notifications(&state.db, user_id).await?;
+        if post_ids.is_empty() {
+            continue;
+        }
+
+        match deliver_notifications(state, &config, &branding, user_id, &post_ids).await {
+            Ok(0) => stats.posts_skipped += post_ids.len() as u64,
+            Ok(sent) => {
+                stats.emails_sent += 1;
+                stats.posts_sent += sent as u64;
+                stats.posts_skipped += (post_ids.len() - sent) as u64;
+            }
+            Err(e) => {
+                warn!("Failed to email notifications to user {}: {}", user_id, e);
+                stats.failed += 1;
+            }
+        }
+    }
+
+    stats.purged = purge_processed_notifications(&state.db).await?;
+
+    Ok(stats)
+}
+
+/// Spawn the email notification job as a background task
+pub fn spawn_email_notification_job(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(POLL_INTERVAL_SECS));
+
+        loop {
+            interval.tick().await;
+
+            match run_email_notifications(&state).await {
+                Ok(stats) => {
+                    if stats.emails_sent > 0 || stats.failed > 0 {
+                        info!(
+                            "Email notifications: {} emails with {} posts sent, {} failed",
+                            stats.emails_sent, stats.posts_sent, stats.failed
+                        );
+                    }
+                }
+                Err(e) => {
+                    error!("Email notification delivery failed: {}", e);
+                }
+            }
+        }
+    });
+
+    info!(
+        "Email notification job scheduled (runs every {}s)",
+        POLL_INTERVAL_SECS
+    );
+}
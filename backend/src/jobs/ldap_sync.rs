@@ -0,0 +1,215 @@
+//! LDAP directory sync
+//!
+//! This module provides a background task that compares the directory with
+//! the accounts that sign in through it. Profiles are refreshed, accounts
+//! whose entry is gone or no longer matches the user filter are deactivated
+//! and signed out, and group mappings are applied. Entries that never signed
+//! in are not imported. Every run is recorded in `ldap_sync_runs`, and only
+//! one runs at a time across replicas.
+
+use std::collections::HashMap;
+
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::models::{LdapConfig, LdapSyncRun};
+use crate::services::ldap::{self, Directory};
+use crate::services::sessions;
+
+/// How often the job checks whether a sync is due
+const POLL_INTERVAL_SECS: u64 = 60;
+
+/// Runs still marked running after this long are considered abandoned
+const STALE_RUN_MINUTES: i32 = 30;
+
+/// Statistics from a sync run
+#[derive(Debug, Default)]
+pub struct SyncStats {
+    pub users_synced: u64,
+    pub users_deactivated: u64,
+    pub memberships_added: u64,
+    pub memberships_removed: u64,
+}
+
+/// Sync the directory now and return the finished run
+///
+/// `triggered_by` is the admin who asked for the run. Fails with a conflict
+/// while another run is in progress.
+pub async fn run_ldap_sync(state: &AppState, triggered_by: Option<Uuid>) -> ApiResult<LdapSyncRun> {
+    let config = crate::services::auth_config::get_password_rules(&state.db).await?;
+    if !config.enable_ldap {
+        return Err(AppError::BadRequest("LDAP is not enabled".to_string()));
+    }
+
+    let run: LdapSyncRun = sqlx::query_as(
+        r#"
+        INSERT INTO ldap_sync_runs (triggered_by)
+        SELECT $1
+        WHERE NOT EXISTS (
+            SELECT 1 FROM ldap_sync_runs
+            WHERE status = 'running' AND started_at > NOW() - make_interval(mins => $2)
+        )
+        RETURNING *
+        "#,
+    )
+    .bind(triggered_by)
+    .bind(STALE_RUN_MINUTES)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::Conflict("A directory sync is already running".to_string()))?;
+
+    let result = sync_directory(state, &config.ldap).await;
+    let (status, stats, error) = match result {
+        Ok(stats) => ("success", stats, None),
+        Err(e) => ("failed", SyncStats::default(), Some(e.to_string())),
+    };
+
+    let run = sqlx::query_as(
+        r#"
+        UPDATE ldap_sync_runs SET
+            status = $2, users_synced = $3, users_deactivated = $4,
+            memberships_added = $5, memberships_removed = $6, error = $7,
+            finished_at = NOW()
+        WHERE id = $1
+        RETURNING *
+        "#,
+    )
+    .bind(run.id)
+    .bind(status)
+    .bind(stats.users_synced as i32)
+    .bind(stats.users_deactivated as i32)
+    .bind(stats.memberships_added as i32)
+    .bind(stats.memberships_removed as i32)
+    .bind(error)
+    .fetch_one(&state.db)
+    .await?;
+
+    Ok(run)
+}
+
+async fn sync_directory(state: &AppState, config: &LdapConfig) -> ApiResult<SyncStats> {
+    let mut directory = Directory::connect(config, &state.encryption_key).await?;
+    let result = sync_with(state, &mut directory, config).await;
+    directory.close().await;
+
+    result
+}
+
+async fn sync_with(
+    state: &AppState,
+    directory: &mut Directory,
+    config: &LdapConfig,
+) -> ApiResult<SyncStats> {
+    let mut stats = SyncStats::default();
+    let entries = directory.all_users().await?;
+    let local: Vec<(Uuid, String, bool)> = sqlx::query_as(
+        "SELECT l.user_id, l.ldap_id, u.is_active FROM ldap_users l JOIN users u ON u.id = l.user_id",
+    )
+    .fetch_all(&state.db)
+    .await?;
+
+    // A wrong base DN or filter must not deactivate everybody
+    if entries.is_empty() && !local.is_empty() {
+        return Err(AppError::ExternalService(
+            "The directory returned no users, check the base DN and user filter".to_string(),
+        ));
+    }
+
+    let entries: HashMap<&str, &ldap::DirectoryUser> =
+        entries.iter().map(|e| (e.id.as_str(), e)).collect();
+    for (user_id, ldap_id, is_active) in local {
+        match entries.get(ldap_id.as_str()) {
+            Some(entry) if entry.email.is_some() => {
+                ldap::upsert_user(&state.db, entry).await?;
+                stats.users_synced += 1;
+            }
+            Some(_) => {}
+            None if is_active => {
+                sqlx::query("UPDATE users SET is_active = false WHERE id = $1")
+                    .bind(user_id)
+                    .execute(&state.db)
+                    .await?;
+                sessions::revoke_all_sessions(state, user_id).await?;
+                stats.users_deactivated += 1;
+            }
+            None => {}
+        }
+    }
+
+    let changes =
+        ldap::sync_memberships(&state.db, directory, &config.group_mappings, None).await?;
+    stats.memberships_added = changes.added;
+    stats.memberships_removed = changes.removed;
+
+    Ok(stats)
+}
+
+/// Whether the configured interval has passed since the last run started
+async fn sync_due(state: &AppState) -> ApiResult<bool> {
+    let config = crate::services::auth_config::get_password_rules(&state.db).await?;
+    if !config.enable_ldap || config.ldap.sync_interval_minutes <= 0 {
+        return Ok(false);
+    }
+
+    let due = sqlx::query_scalar(
+        r#"
+        SELECT NOT EXISTS (
+            SELECT 1 FROM ldap_sync_runs
+            WHERE started_at > NOW() - make_interval(mins => $1)
+        )
+        "#,
+    )
+    .bind(config.ldap.sync_interval_minutes)
+    .fetch_one(&state.db)
+    .await?;
+
+    Ok(due)
+}
+
+/// Spawn the LDAP sync job as a background task
+pub fn spawn_ldap_sync_job(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_secs(POLL_INTERVAL_SECS));
+
+        loop {
+            interval.tick().await;
+
+            match sync_due(&state).await {
+                Ok(true) => {}
+                Ok(false) => continue,
+                Err(e) => {
+                    error!("LDAP sync check failed: {}", e);
+                    continue;
+                }
+            }
+
+            match run_ldap_sync(&state, None).await {
+                Ok(run) if run.status == "success" => {
+                    info!(
+                        "LDAP sync: {} users synced, {} deactivated, {} memberships added, {} removed",
+                        run.users_synced,
+                        run.users_deactivated,
+                        run.memberships_added,
+                        run.memberships_removed
+                    );
+                }
+                Ok(run) => {
+                    error!("LDAP sync failed: {}", run.error.unwrap_or_default());
+                }
+                // Another replica got there first
+                Err(AppError::Conflict(_)) => {}
+                Err(e) => {
+                    error!("LDAP sync failed: {}", e);
+                }
+            }
+        }
+    });
+
+    info!(
+        "LDAP sync job scheduled (checks every {}s)",
+        POLL_INTERVAL_SECS
+    );
+}
@@ -0,0 +1,33 @@
+//! Media processing recovery job
+//!
+//! Uploads queue their own media processing. This module provides a
+//! background task that queues again the files whose processing never
+//! finished, because the server restarted first.
+
+use tracing::{error, info};
+
+use crate::api::AppState;
+use crate::services::media::requeue_stale;
+
+/// How often the job looks for files left behind
+const POLL_INTERVAL_SECS: u64 = 300;
+
+/// Spawn the media processing recovery job as a background task
+pub fn spawn_media_processing_job(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_secs(POLL_INTERVAL_SECS));
+
+        loop {
+            interval.tick().await;
+
+            match requeue_stale(&state).await {
+                Ok(0) => {}
+                Ok(queued) => info!("Queued {} files left unprocessed", queued),
+                Err(e) => error!("Media processing recovery failed: {}", e),
+            }
+        }
+    });
+
+    info!("Media processing recovery job scheduled");
+}
@@ -1,5 +1,18 @@
 //! Background jobs module
 
+pub mod email_notifications;
+pub mod ldap_sync;
+pub mod media_processing;
+pub mod post_reminders;
 pub mod retention;
+pub mod scheduled_posts;
+pub mod storage_migration;
+pub mod upload_sessions;
 
+pub use email_notifications::spawn_email_notification_job;
+pub use ldap_sync::spawn_ldap_sync_job;
+pub use media_processing::spawn_media_processing_job;
+pub use post_reminders::spawn_post_reminder_job;
 pub use retention::spawn_retention_job;
+pub use scheduled_posts::spawn_scheduled_post_job;
+pub use upload_sessions::spawn_upload_session_job;
@@ -0,0 +1,299 @@
+//! Post reminder delivery job
+//!
+//! This module provides a background task that delivers due rows from
+//! `post_reminders` as a direct message from the system bot, using
+//! Mattermost's `system_post_reminder` post type. Delivered rows are kept
+//! (with `delivered_at` set) so they can be snoozed, and are purged after a
+//! week. A reminder whose delivery fails is released to be tried again,
+//! waiting twice as long after each attempt, and is dropped once its post is
+//! gone or it has failed [`MAX_ATTEMPTS`] times.
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::mattermost_compat::id::encode_mm_id;
+use crate::models::{CreatePost, PostResponse};
+use crate::services::posts::get_or_create_system_bot;
+
+/// How often the job looks for due reminders
+const POLL_INTERVAL_SECS: u64 = 15;
+
+/// Maximum number of reminders claimed per tick
+const BATCH_SIZE: i64 = 50;
+
+/// Failed deliveries of a reminder before it is given up on
+const MAX_ATTEMPTS: i32 = 8;
+
+/// Wait before retrying a reminder that failed once, doubled per attempt
+const RETRY_BASE_SECS: f64 = 60.0;
+
+/// Delivered reminders older than this are deleted
+const DELIVERED_RETENTION_DAYS: i32 = 7;
+
+/// Post type Mattermost clients render as a reminder
+pub const REMINDER_POST_TYPE: &str = "system_post_reminder";
+
+/// A reminder claimed for delivery
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct DueReminder {
+    pub user_id: Uuid,
+    pub post_id: Uuid,
+    pub target_at: DateTime<Utc>,
+}
+
+/// Statistics from a delivery run
+#[derive(Debug, Default)]
+pub struct ReminderStats {
+    pub delivered: u64,
+    pub failed: u64,
+    pub purged: u64,
+}
+
+/// Claim up to `limit` due reminders by marking them delivered
+///
+/// Claiming and marking happen in one statement so that concurrent replicas
+/// never deliver the same reminder twice.
+pub async fn claim_due_reminders(db: &PgPool, limit: i64) -> Result<Vec<DueReminder>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        UPDATE post_reminders SET delivered_at = NOW()
+        WHERE (user_id, post_id) IN (
+            SELECT user_id, post_id FROM post_reminders
+            WHERE delivered_at IS NULL AND target_at <= NOW()
+              AND (next_attempt_at IS NULL OR next_attempt_at <= NOW())
+            ORDER BY target_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING user_id, post_id, target_at
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(db)
+    .await
+}
+
+/// Release a claimed reminder whose delivery failed, so a later run retries
+/// it, returning how often it has failed
+async fn release_reminder(db: &PgPool, reminder: &DueReminder) -> Result<i32, sqlx::Error> {
+    let attempts: Option<i32> = sqlx::query_scalar(
+        r#"
+        UPDATE post_reminders SET
+            delivered_at = NULL,
+            attempts = attempts + 1,
+            next_attempt_at = NOW() + make_interval(secs => $3 * power(2, attempts))
+        WHERE user_id = $1 AND post_id = $2 AND reminder_post_id IS NULL
+        RETURNING attempts
+        "#,
+    )
+    .bind(reminder.user_id)
+    .bind(reminder.post_id)
+    .bind(RETRY_BASE_SECS)
+    .fetch_optional(db)
+    .await?;
+
+    Ok(attempts.unwrap_or(0))
+}
+
+/// Drop a reminder that can never be delivered
+async fn drop_reminder(db: &PgPool, reminder: &DueReminder) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM post_reminders WHERE user_id = $1 AND post_id = $2")
+        .bind(reminder.user_id)
+        .bind(reminder.post_id)
+        .execute(db)
+        .await?;
+
+    Ok(())
+}
+
+/// Deliver a single reminder as a system DM to its owner
+pub async fn deliver_reminder(state: &AppState, reminder: &DueReminder) -> ApiResult<PostResponse> {
+    #[derive(sqlx::FromRow)]
+    struct ReminderContext {
+        author_username: String,
+        team_name: String,
+    }
+
+    let context: ReminderContext = sqlx::query_as(
+        r#"
+        SELECT u.username AS author_username, t.name AS team_name
+        FROM posts p
+        JOIN users u ON u.id = p.user_id
+        JOIN channels c ON c.id = p.channel_id
+        JOIN teams t ON t.id = c.team_id
+        WHERE p.id = $1 AND p.deleted_at IS NULL
+        "#,
+    )
+    .bind(reminder.post_id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Reminder post not found".to_string()))?;
+
+    let site_url: Option<String> =
+        sqlx::query_scalar("SELECT site->>'site_url' FROM server_config WHERE id = 'default'")
+            .fetch_optional(&state.db)
+            .await?
+            .flatten();
+
+    let bot_id = get_or_create_system_bot(state).await?;
+    let dm = crate::api::v4::channels::create_direct_channel_internal(state, reminder.user_id, bot_id)
+        .await?;
+
+    let post_id = encode_mm_id(reminder.post_id);
+    let permalink = format!(
+        "{}/{}/pl/{}",
+        site_url.unwrap_or_default().trim_end_matches('/'),
+        context.team_name,
+        post_id
+    );
+
+    let input = CreatePost {
+        message: format!(
+            "Hi there, here's your reminder about this message from @{}: {}",
+            context.author_username, permalink
+        ),
+        root_post_id: None,
+        props: Some(serde_json::json!({
+            "type": REMINDER_POST_TYPE,
+            "team_name": context.team_name,
+            "post_id": post_id,
+            "username": context.author_username,
+            "target_time": reminder.target_at.timestamp_millis(),
+        })),
+        file_ids: vec![],
+    };
+
+    let post = crate::services::posts::create_post(state, bot_id, dm.id, input, None).await?;
+
+    sqlx::query(
+        "UPDATE post_reminders SET reminder_post_id = $3 WHERE user_id = $1 AND post_id = $2",
+    )
+    .bind(reminder.user_id)
+    .bind(reminder.post_id)
+    .bind(post.id)
+    .execute(&state.db)
+    .await?;
+
+    Ok(post)
+}
+
+/// Re-arm a reminder so it fires again at `target_at`
+pub async fn snooze_reminder(
+    db: &PgPool,
+    user_id: Uuid,
+    post_id: Uuid,
+    target_at: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE post_reminders
+        SET target_at = $3, delivered_at = NULL, reminder_post_id = NULL,
+            attempts = 0, next_attempt_at = NULL
+        WHERE user_id = $1 AND post_id = $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(post_id)
+    .bind(target_at)
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Delete delivered reminders that are no longer snoozable
+pub async fn purge_delivered_reminders(db: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM post_reminders
+        WHERE delivered_at IS NOT NULL
+          AND delivered_at < NOW() - make_interval(days => $1)
+        "#,
+    )
+    .bind(DELIVERED_RETENTION_DAYS)
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Run a single delivery pass
+pub async fn run_post_reminders(state: &AppState) -> Result<ReminderStats, sqlx::Error> {
+    let mut stats = ReminderStats::default();
+
+    loop {
+        let batch = claim_due_reminders(&state.db, BATCH_SIZE).await?;
+        let batch_len = batch.len() as i64;
+
+        for reminder in &batch {
+            match deliver_reminder(state, reminder).await {
+                Ok(_) => stats.delivered += 1,
+                Err(AppError::NotFound(_)) => {
+                    warn!(
+                        "Dropping reminder for post {} to user {}: post not found",
+                        reminder.post_id, reminder.user_id
+                    );
+                    drop_reminder(&state.db, reminder).await?;
+                    stats.failed += 1;
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to deliver reminder for post {} to user {}: {}",
+                        reminder.post_id, reminder.user_id, e
+                    );
+                    if release_reminder(&state.db, reminder).await? >= MAX_ATTEMPTS {
+                        error!(
+                            "Giving up on reminder for post {} to user {} after {} attempts",
+                            reminder.post_id, reminder.user_id, MAX_ATTEMPTS
+                        );
+                        drop_reminder(&state.db, reminder).await?;
+                    }
+                    stats.failed += 1;
+                }
+            }
+        }
+
+        // Released reminders wait for their next attempt, so are not reclaimed
+        if batch_len < BATCH_SIZE {
+            break;
+        }
+    }
+
+    stats.purged = purge_delivered_reminders(&state.db).await?;
+
+    Ok(stats)
+}
+
+/// Spawn the reminder delivery job as a background task
+pub fn spawn_post_reminder_job(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(POLL_INTERVAL_SECS));
+
+        loop {
+            interval.tick().await;
+
+            match run_post_reminders(&state).await {
+                Ok(stats) => {
+                    if stats.delivered > 0 || stats.failed > 0 {
+                        info!(
+                            "Post reminders: {} delivered, {} failed",
+                            stats.delivered, stats.failed
+                        );
+                    }
+                }
+                Err(e) => {
+                    error!("Post reminder delivery failed: {}", e);
+                }
+            }
+        }
+    });
+
+    info!(
+        "Post reminder job scheduled (runs every {}s)",
+        POLL_INTERVAL_SECS
+    );
+}
@@ -0,0 +1,232 @@
+//! Scheduled post dispatcher
+//!
+//! This module provides a background task that publishes rows from
+//! `scheduled_posts` once their `scheduled_at` time has passed. Due rows are
+//! claimed with `FOR UPDATE SKIP LOCKED`, so several replicas can run the
+//! job at the same time without publishing a post twice.
+
+use sqlx::PgPool;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::models::{CreatePost, PostResponse, ScheduledPost};
+
+/// How often the dispatcher looks for due posts
+const POLL_INTERVAL_SECS: u64 = 15;
+
+/// Maximum number of rows claimed per tick
+const BATCH_SIZE: i64 = 50;
+
+/// Claims older than this are considered abandoned and released again
+const STALE_CLAIM_MINUTES: i32 = 5;
+
+/// Statistics from a dispatch run
+#[derive(Debug, Default)]
+pub struct DispatchStats {
+    pub sent: u64,
+    pub failed: u64,
+    pub released: u64,
+}
+
+/// Claim up to `limit` due scheduled posts by moving them to `processing`
+pub async fn claim_due_posts(db: &PgPool, limit: i64) -> Result<Vec<ScheduledPost>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        UPDATE scheduled_posts SET state = 'processing'
+        WHERE id IN (
+            SELECT id FROM scheduled_posts
+            WHERE state = 'pending' AND scheduled_at <= NOW()
+            ORDER BY scheduled_at
+            LIMIT $1
+            FOR UPDATE SKIP LOCKED
+        )
+        RETURNING *
+        "#,
+    )
+    .bind(limit)
+    .fetch_all(db)
+    .await
+}
+
+/// Claim a single pending scheduled post owned by `user_id`, regardless of its schedule
+pub async fn claim_post(
+    db: &PgPool,
+    id: Uuid,
+    user_id: Uuid,
+) -> Result<Option<ScheduledPost>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        UPDATE scheduled_posts SET state = 'processing'
+        WHERE id = $1 AND user_id = $2 AND state = 'pending'
+        RETURNING *
+        "#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .fetch_optional(db)
+    .await
+}
+
+/// Return claims abandoned by a crashed replica to the pending queue
+pub async fn release_stale_claims(db: &PgPool) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE scheduled_posts SET state = 'pending'
+        WHERE state = 'processing'
+          AND updated_at < NOW() - make_interval(mins => $1)
+        "#,
+    )
+    .bind(STALE_CLAIM_MINUTES)
+    .execute(db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Publish a claimed scheduled post and record the outcome on its row
+pub async fn publish_scheduled_post(
+    state: &AppState,
+    scheduled: ScheduledPost,
+) -> ApiResult<PostResponse> {
+    let input = CreatePost {
+        message: scheduled.message,
+        root_post_id: scheduled.root_id,
+        props: scheduled.props,
+        file_ids: scheduled.file_ids.unwrap_or_default(),
+    };
+
+    let result =
+        crate::services::posts::create_post(state, scheduled.user_id, scheduled.channel_id, input, None)
+            .await;
+
+    match &result {
+        Ok(post) => {
+            sqlx::query(
+                r#"
+                UPDATE scheduled_posts
+                SET state = 'sent', post_id = $2, processed_at = NOW(),
+                    error_code = NULL, error_message = NULL
+                WHERE id = $1
+                "#,
+            )
+            .bind(scheduled.id)
+            .bind(post.id)
+            .execute(&state.db)
+            .await?;
+        }
+        Err(e) => {
+            sqlx::query(
+                r#"
+                UPDATE scheduled_posts
+                SET state = 'failed', processed_at = NOW(), error_code = $2, error_message = $3
+                WHERE id = $1
+                "#,
+            )
+            .bind(scheduled.id)
+            .bind(error_code(e))
+            .bind(e.to_string())
+            .execute(&state.db)
+            .await?;
+        }
+    }
+
+    result
+}
+
+/// Map a publishing error to a Mattermost scheduled post error code
+fn error_code(error: &AppError) -> &'static str {
+    match error {
+        AppError::Forbidden(_) => "no_channel_permission",
+        AppError::BadRequest(_) => "thread_deleted",
+        AppError::Validation(_) => "invalid_post",
+        AppError::NotFound(_) => "channel_not_found",
+        _ => "unknown",
+    }
+}
+
+/// Run a single dispatch pass
+pub async fn run_scheduled_posts_dispatch(state: &AppState) -> Result<DispatchStats, sqlx::Error> {
+    let mut stats = DispatchStats {
+        released: release_stale_claims(&state.db).await?,
+        ..Default::default()
+    };
+
+    if stats.released > 0 {
+        warn!(
+            "Scheduled posts: released {} abandoned claims",
+            stats.released
+        );
+    }
+
+    loop {
+        let batch = claim_due_posts(&state.db, BATCH_SIZE).await?;
+        let batch_len = batch.len() as i64;
+
+        for scheduled in batch {
+            let id = scheduled.id;
+            match publish_scheduled_post(state, scheduled).await {
+                Ok(_) => stats.sent += 1,
+                Err(e) => {
+                    warn!("Scheduled post {} failed: {}", id, e);
+                    stats.failed += 1;
+                }
+            }
+        }
+
+        if batch_len < BATCH_SIZE {
+            break;
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Spawn the scheduled post dispatcher as a background task
+pub fn spawn_scheduled_post_job(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(POLL_INTERVAL_SECS));
+
+        loop {
+            interval.tick().await;
+
+            match run_scheduled_posts_dispatch(&state).await {
+                Ok(stats) => {
+                    if stats.sent > 0 || stats.failed > 0 {
+                        info!(
+                            "Scheduled posts dispatched: {} sent, {} failed",
+                            stats.sent, stats.failed
+                        );
+                    }
+                }
+                Err(e) => {
+                    error!("Scheduled post dispatch failed: {}", e);
+                }
+            }
+        }
+    });
+
+    info!(
+        "Scheduled post job scheduled (runs every {}s)",
+        POLL_INTERVAL_SECS
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_code_mapping() {
+        assert_eq!(
+            error_code(&AppError::Forbidden("Not a member".to_string())),
+            "no_channel_permission"
+        );
+        assert_eq!(
+            error_code(&AppError::BadRequest("Invalid root post".to_string())),
+            "thread_deleted"
+        );
+        assert_eq!(error_code(&AppError::Internal("boom".to_string())), "unknown");
+    }
+}
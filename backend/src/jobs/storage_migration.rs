@@ -0,0 +1,216 @@
+//! Storage backend migration
+//!
+//! This module copies every file stored in one backend to another, for
+//! example after switching `RUSTCHAT_STORAGE_BACKEND` from S3 to local disk.
+//! Each file's `backend` column is switched once its objects are copied, so
+//! files stay readable throughout. Objects are streamed across a part at a
+//! time and left in the source backend.
+//! Every run is recorded in `storage_migrations`, and only one runs at a time
+//! across replicas.
+
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::models::StorageMigration;
+use crate::storage::ObjectWriter;
+
+/// Files copied between progress updates
+const BATCH_SIZE: i64 = 100;
+
+/// Runs whose progress has not moved for this long are considered abandoned
+const STALE_RUN_MINUTES: i32 = 30;
+
+/// A file's objects to copy
+#[derive(Debug, sqlx::FromRow)]
+struct StoredFile {
+    id: Uuid,
+    key: String,
+    thumbnail_key: Option<String>,
+    preview_key: Option<String>,
+    mime_type: String,
+}
+
+/// Start copying every file in `source` to `target` in the background
+///
+/// Returns the new run, whose progress is reported by
+/// `GET /admin/storage/migrations`. Fails with a conflict while another
+/// migration is in progress.
+pub async fn start_storage_migration(
+    state: &AppState,
+    source: &str,
+    target: &str,
+    triggered_by: Option<Uuid>,
+) -> ApiResult<StorageMigration> {
+    if source == target {
+        return Err(AppError::BadRequest(
+            "Source and target backends must differ".to_string(),
+        ));
+    }
+    for name in [source, target] {
+        state
+            .storage
+            .backend(name)
+            .map_err(|_| AppError::BadRequest(format!("Unknown storage backend: {}", name)))?;
+    }
+
+    let run: StorageMigration = sqlx::query_as(
+        r#"
+        INSERT INTO storage_migrations (source, target, triggered_by, files_total)
+        SELECT $1, $2, $3, (SELECT COUNT(*)::INT FROM files WHERE backend = $1)
+        WHERE NOT EXISTS (
+            SELECT 1 FROM storage_migrations
+            WHERE status = 'running' AND updated_at > NOW() - make_interval(mins => $4)
+        )
+        RETURNING *
+        "#,
+    )
+    .bind(source)
+    .bind(target)
+    .bind(triggered_by)
+    .bind(STALE_RUN_MINUTES)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::Conflict("A storage migration is already running".to_string()))?;
+
+    info!(
+        migration_id = %run.id,
+        source = %run.source,
+        target = %run.target,
+        files = run.files_total,
+        "Storage migration started"
+    );
+
+    let state = state.clone();
+    let id = run.id;
+    let (source, target) = (run.source.clone(), run.target.clone());
+    tokio::spawn(async move {
+        let result = migrate_files(&state, id, &source, &target).await;
+        if let Err(e) = finish(&state, id, result).await {
+            error!(migration_id = %id, error = %e, "Failed to record storage migration result");
+        }
+    });
+
+    Ok(run)
+}
+
+/// Copy the files in batches, returning how many could not be copied
+async fn migrate_files(state: &AppState, id: Uuid, source: &str, target: &str) -> ApiResult<i32> {
+    let from = state.storage.backend(source)?;
+    let to = state.storage.backend(target)?;
+    let mut failed = 0;
+    let mut last_id = Uuid::nil();
+
+    loop {
+        let files: Vec<StoredFile> = sqlx::query_as(
+            r#"
+            SELECT id, key, thumbnail_key, preview_key, mime_type FROM files
+            WHERE backend = $1 AND id > $2
+            ORDER BY id
+            LIMIT $3
+            "#,
+        )
+        .bind(source)
+        .bind(last_id)
+        .bind(BATCH_SIZE)
+        .fetch_all(&state.db)
+        .await?;
+
+        let Some(batch_last) = files.last() else {
+            break;
+        };
+        last_id = batch_last.id;
+
+        let mut copied = 0;
+        let mut batch_failed = 0;
+        for file in files {
+            let file_id = file.id;
+            let derived = file
+                .thumbnail_key
+                .into_iter()
+                .chain(file.preview_key)
+                .map(|key| {
+                    let content_type = mime_guess::from_path(&key).first_or_octet_stream();
+                    (key, content_type.to_string())
+                });
+            let mut result = Ok(());
+            for (key, content_type) in std::iter::once((file.key, file.mime_type)).chain(derived) {
+                result = match from.reader(&key).await {
+                    Ok(mut reader) => ObjectWriter::new(to, &key, &content_type, u64::MAX)
+                        .copy_from(&mut reader)
+                        .await
+                        .map(|_| ()),
+                    Err(e) => Err(e),
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+
+            if let Err(e) = result {
+                warn!(migration_id = %id, file_id = %file_id, error = %e, "Failed to migrate file");
+                batch_failed += 1;
+                continue;
+            }
+
+            // A file deleted or moved meanwhile is simply not counted
+            let moved = sqlx::query("UPDATE files SET backend = $2 WHERE id = $1 AND backend = $3")
+                .bind(file_id)
+                .bind(target)
+                .bind(source)
+                .execute(&state.db)
+                .await?;
+            copied += moved.rows_affected() as i32;
+        }
+
+        failed += batch_failed;
+        sqlx::query(
+            r#"
+            UPDATE storage_migrations SET
+                files_copied = files_copied + $2, files_failed = files_failed + $3,
+                updated_at = NOW()
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .bind(copied)
+        .bind(batch_failed)
+        .execute(&state.db)
+        .await?;
+    }
+
+    Ok(failed)
+}
+
+/// Record how a run ended
+async fn finish(state: &AppState, id: Uuid, result: ApiResult<i32>) -> ApiResult<()> {
+    let (status, error) = match result {
+        Ok(0) => ("success", None),
+        Ok(failed) => (
+            "failed",
+            Some(format!("{} files could not be copied", failed)),
+        ),
+        Err(e) => ("failed", Some(e.to_string())),
+    };
+
+    sqlx::query(
+        r#"
+        UPDATE storage_migrations SET
+            status = $2, error = $3, updated_at = NOW(), finished_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .bind(status)
+    .bind(&error)
+    .execute(&state.db)
+    .await?;
+
+    match error {
+        None => info!(migration_id = %id, "Storage migration finished"),
+        Some(e) => warn!(migration_id = %id, error = %e, "Storage migration failed"),
+    }
+
+    Ok(())
+}
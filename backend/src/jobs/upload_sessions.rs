@@ -0,0 +1,36 @@
+//! Upload session cleanup job
+//!
+//! This module provides a background task that discards upload sessions
+//! nobody has appended to for a week, along with the parts they already
+//! stored.
+
+use tracing::{error, info};
+
+use crate::api::AppState;
+use crate::services::uploads::{expire_sessions, SESSION_EXPIRY_DAYS};
+
+/// How often the job looks for abandoned sessions
+const POLL_INTERVAL_SECS: u64 = 3600;
+
+/// Spawn the upload session cleanup job as a background task
+pub fn spawn_upload_session_job(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_secs(POLL_INTERVAL_SECS));
+
+        loop {
+            interval.tick().await;
+
+            match expire_sessions(&state).await {
+                Ok(0) => {}
+                Ok(expired) => info!("Discarded {} abandoned upload sessions", expired),
+                Err(e) => error!("Upload session cleanup failed: {}", e),
+            }
+        }
+    });
+
+    info!(
+        "Upload session cleanup job scheduled (sessions expire after {} days)",
+        SESSION_EXPIRY_DAYS
+    );
+}
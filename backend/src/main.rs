@@ -1,4 +1,12 @@
-use rustchat::{api, config::Config, db, realtime::WsHub, storage::S3Client, telemetry};
+use rustchat::{
+    api,
+    config::Config,
+    db,
+    realtime::WsHub,
+    services::{client_address::TrustedProxies, media::MediaProcessor},
+    storage::Storage,
+    telemetry,
+};
 use std::net::SocketAddr;
 use tracing::info;
 
@@ -59,29 +67,37 @@ async fn main() -> anyhow::Result<()> {
     let redis_pool = redis_cfg.create_pool(Some(deadpool_redis::Runtime::Tokio1))?;
     info!("Redis pool initialized");
 
-    // Create S3 client
-    let s3_client = S3Client::new(
-        config.s3_endpoint.clone(),
-        config.s3_public_endpoint.clone(),
-        config.s3_bucket.clone(),
-        config.s3_access_key.clone(),
-        config.s3_secret_key.clone(),
-        config.s3_region.clone(),
-    );
-    info!("S3 client initialized");
+    // Set up file storage
+    let storage = Storage::from_config(&config)?;
+    info!("File storage initialized ({})", storage.current().name());
 
-    // Spawn background jobs
-    rustchat::jobs::spawn_retention_job(db_pool.clone());
-
-    // Build application router
-    let app = api::router(
+    // Build shared application state
+    let state = api::AppState::new(
         db_pool.clone(),
         redis_pool,
         config.jwt_secret.clone(),
         config.jwt_expiry_hours,
+        config.encryption_key.clone(),
         ws_hub,
-        s3_client,
-    );
+        storage,
+    )
+    .with_media(
+        MediaProcessor::new(config.media_workers, config.ffmpeg_path.clone())
+            .with_content_extraction(config.extract_file_content),
+    )
+    .with_trusted_proxies(TrustedProxies::parse(&config.trusted_proxies)?);
+
+    // Spawn background jobs
+    rustchat::jobs::spawn_retention_job(db_pool.clone());
+    rustchat::jobs::spawn_scheduled_post_job(state.clone());
+    rustchat::jobs::spawn_post_reminder_job(state.clone());
+    rustchat::jobs::spawn_email_notification_job(state.clone());
+    rustchat::jobs::spawn_ldap_sync_job(state.clone());
+    rustchat::jobs::spawn_upload_session_job(state.clone());
+    rustchat::jobs::spawn_media_processing_job(state.clone());
+
+    // Build application router
+    let app = api::router_with_state(state);
 
     // Start server
     let addr: SocketAddr = format!("{}:{}", config.server_host, config.server_port)
@@ -91,7 +107,11 @@ async fn main() -> anyhow::Result<()> {
     info!("Listening on http://{}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -1,11 +1,16 @@
 use super::{id::encode_mm_id, models as mm};
 use crate::models::{
     channel::{Channel, ChannelMember, ChannelType},
-    post::{Post, PostResponse},
+    post::{Post, PostResponse, ScheduledPost},
     team::{Team, TeamMember},
     user::User,
-    file::FileInfo,
+    file::{FileInfo, UploadSession},
+    integration::SlashCommand,
+    session::Session,
+    access_token::UserAccessToken,
 };
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use serde_json::json;
 
 impl From<User> for mm::User {
@@ -20,7 +25,7 @@ impl From<User> for mm::User {
             last_name: "".to_string(),
             nickname: user.display_name.unwrap_or_default(),
             email: user.email,
-            email_verified: true,
+            email_verified: user.email_verified,
             auth_service: "".to_string(),
             roles: map_role(&user.role),
             locale: "en".to_string(),
@@ -29,7 +34,7 @@ impl From<User> for mm::User {
             last_password_update: 0,
             last_picture_update: 0,
             failed_attempts: 0,
-            mfa_active: false,
+            mfa_active: user.mfa_active,
             timezone: json!({ "automaticTimezone": "UTC", "manualTimezone": "UTC", "useAutomaticTimezone": "true" }),
         }
     }
@@ -38,6 +43,7 @@ impl From<User> for mm::User {
 fn map_role(role: &str) -> String {
     match role {
         "system_admin" => "system_admin system_user".to_string(),
+        "guest" => "system_guest".to_string(),
         _ => "system_user".to_string(),
     }
 }
@@ -59,7 +65,8 @@ impl From<Team> for mm::Team {
                 "I".to_string()
             },
             company_name: "".to_string(),
-            allowed_domains: "".to_string(),
+            allowed_domains: team.allowed_domains,
+            // Filled in by handlers for callers allowed to invite
             invite_id: "".to_string(),
             allow_open_invite: team.allow_open_invite,
         }
@@ -97,6 +104,16 @@ impl From<Channel> for mm::Channel {
     }
 }
 
+/// Mattermost keeps the post type in a column; we keep it in `props.type`
+fn post_type_from_props(props: &serde_json::Value) -> String {
+    props
+        .get("type")
+        .and_then(|t| t.as_str())
+        .filter(|t| t.starts_with("system_") || t.starts_with("custom_"))
+        .unwrap_or_default()
+        .to_string()
+}
+
 impl From<Post> for mm::Post {
     fn from(post: Post) -> Self {
         mm::Post {
@@ -109,8 +126,8 @@ impl From<Post> for mm::Post {
             channel_id: encode_mm_id(post.channel_id),
             root_id: post.root_post_id.map(encode_mm_id).unwrap_or_default(),
             original_id: "".to_string(),
+            post_type: post_type_from_props(&post.props),
             message: post.message,
-            post_type: "".to_string(),
             props: post.props,
             hashtags: "".to_string(),
             file_ids: post.file_ids.iter().map(|id| encode_mm_id(*id)).collect(),
@@ -132,8 +149,8 @@ impl From<PostResponse> for mm::Post {
             channel_id: encode_mm_id(post.channel_id),
             root_id: post.root_post_id.map(encode_mm_id).unwrap_or_default(),
             original_id: "".to_string(),
+            post_type: post_type_from_props(&post.props),
             message: post.message,
-            post_type: "".to_string(),
             props: post.props,
             hashtags: "".to_string(),
             file_ids: post.file_ids.iter().map(|id| encode_mm_id(*id)).collect(),
@@ -180,16 +197,138 @@ impl From<FileInfo> for mm::FileInfo {
         mm::FileInfo {
             id: encode_mm_id(f.id),
             user_id: encode_mm_id(f.uploader_id),
+            post_id: f.post_id.map(encode_mm_id).unwrap_or_default(),
+            channel_id: f.channel_id.map(encode_mm_id).unwrap_or_default(),
             create_at: f.created_at.timestamp_millis(),
             update_at: f.created_at.timestamp_millis(),
             delete_at: 0,
             name: f.name.clone(),
-            extension: f.name.rsplit('.').next().unwrap_or_default().to_string(),
+            extension: f
+                .name
+                .rsplit_once('.')
+                .map(|(_, ext)| ext.to_lowercase())
+                .unwrap_or_default(),
             size: f.size,
             mime_type: f.mime_type,
             width: f.width.unwrap_or(0),
             height: f.height.unwrap_or(0),
             has_preview_image: f.has_thumbnail,
+            mini_preview: f.mini_preview.map(|data| STANDARD.encode(data)),
+        }
+    }
+}
+
+impl From<Vec<FileInfo>> for mm::FileInfoList {
+    fn from(files: Vec<FileInfo>) -> Self {
+        let mut order = Vec::with_capacity(files.len());
+        let mut file_infos = std::collections::HashMap::with_capacity(files.len());
+        for file in files {
+            let info: mm::FileInfo = file.into();
+            order.push(info.id.clone());
+            file_infos.insert(info.id.clone(), info);
+        }
+
+        mm::FileInfoList {
+            order,
+            file_infos,
+            next_file_info_id: "".to_string(),
+            prev_file_info_id: "".to_string(),
+        }
+    }
+}
+
+impl From<UploadSession> for mm::UploadSession {
+    fn from(s: UploadSession) -> Self {
+        mm::UploadSession {
+            id: encode_mm_id(s.id),
+            upload_type: "attachment".to_string(),
+            create_at: s.created_at.timestamp_millis(),
+            user_id: encode_mm_id(s.user_id),
+            channel_id: s.channel_id.map(encode_mm_id).unwrap_or_default(),
+            filename: s.filename,
+            file_size: s.file_size,
+            file_offset: s.file_offset,
+        }
+    }
+}
+
+impl From<ScheduledPost> for mm::ScheduledPost {
+    fn from(p: ScheduledPost) -> Self {
+        mm::ScheduledPost {
+            id: encode_mm_id(p.id),
+            user_id: encode_mm_id(p.user_id),
+            channel_id: encode_mm_id(p.channel_id),
+            root_id: p.root_id.map(encode_mm_id).unwrap_or_default(),
+            message: p.message,
+            props: p.props.unwrap_or_else(|| json!({})),
+            file_ids: p
+                .file_ids
+                .unwrap_or_default()
+                .into_iter()
+                .map(encode_mm_id)
+                .collect(),
+            scheduled_at: p.scheduled_at.timestamp_millis(),
+            create_at: p.created_at.timestamp_millis(),
+            update_at: p.updated_at.timestamp_millis(),
+            processed_at: p.processed_at.map(|t| t.timestamp_millis()).unwrap_or(0),
+            error_code: p.error_code.unwrap_or_default(),
+        }
+    }
+}
+
+impl From<Session> for mm::Session {
+    fn from(s: Session) -> Self {
+        mm::Session {
+            id: encode_mm_id(s.id),
+            // Tokens are never sent back to clients
+            token: String::new(),
+            create_at: s.created_at.timestamp_millis(),
+            expires_at: s.expires_at.timestamp_millis(),
+            last_activity_at: s.last_activity_at.timestamp_millis(),
+            user_id: encode_mm_id(s.user_id),
+            device_id: s.device_id.unwrap_or_default(),
+            roles: String::new(),
+            is_oauth: false,
+            props: json!({
+                "user_agent": s.user_agent.unwrap_or_default(),
+                "ip_address": s.ip_address.unwrap_or_default(),
+            }),
+        }
+    }
+}
+
+impl From<UserAccessToken> for mm::UserAccessToken {
+    fn from(t: UserAccessToken) -> Self {
+        mm::UserAccessToken {
+            id: encode_mm_id(t.id),
+            token: t.token.unwrap_or_default(),
+            user_id: encode_mm_id(t.user_id),
+            description: t.description.unwrap_or_default(),
+            is_active: t.is_active,
+        }
+    }
+}
+
+impl From<SlashCommand> for mm::Command {
+    fn from(c: SlashCommand) -> Self {
+        mm::Command {
+            id: encode_mm_id(c.id),
+            token: c.token,
+            create_at: c.created_at.timestamp_millis(),
+            update_at: c.updated_at.timestamp_millis(),
+            delete_at: 0,
+            creator_id: encode_mm_id(c.creator_id),
+            team_id: encode_mm_id(c.team_id),
+            trigger: c.trigger,
+            method: c.method,
+            username: String::new(),
+            icon_url: c.icon_url.unwrap_or_default(),
+            auto_complete: c.auto_complete,
+            auto_complete_desc: c.description.clone().unwrap_or_default(),
+            auto_complete_hint: c.hint.unwrap_or_default(),
+            display_name: c.display_name.unwrap_or_default(),
+            description: c.description.unwrap_or_default(),
+            url: c.url,
         }
     }
 }
@@ -220,17 +359,25 @@ mod tests {
             status_emoji: None,
             status_expires_at: None,
             custom_status: None,
+            email_verified: true,
+            mfa_active: false,
             last_login_at: None,
             created_at: now,
             updated_at: now,
             password_updated_at: now,
         };
 
-        let mm_u: mm::User = u.into();
+        let mm_u: mm::User = u.clone().into();
         assert_eq!(mm_u.id, encode_mm_id(user_id));
         assert_eq!(mm_u.username, "testuser");
         assert_eq!(mm_u.email, "test@example.com");
         assert_eq!(mm_u.roles, "system_user");
+
+        let guest = User {
+            role: "guest".to_string(),
+            ..u
+        };
+        assert_eq!(mm::User::from(guest).roles, "system_guest");
     }
 
     #[test]
@@ -179,6 +179,10 @@ pub struct Reaction {
 pub struct FileInfo {
     pub id: String,
     pub user_id: String,
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    pub post_id: String,
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    pub channel_id: String,
     pub create_at: i64,
     pub update_at: i64,
     pub delete_at: i64,
@@ -189,6 +193,17 @@ pub struct FileInfo {
     pub width: i32,
     pub height: i32,
     pub has_preview_image: bool,
+    /// Base64 of a tiny JPEG to blur while the preview loads
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub mini_preview: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileInfoList {
+    pub order: Vec<String>,
+    pub file_infos: std::collections::HashMap<String, FileInfo>,
+    pub next_file_info_id: String,
+    pub prev_file_info_id: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -312,6 +327,27 @@ pub struct OutgoingWebhook {
     pub content_type: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Command {
+    pub id: String,
+    pub token: String,
+    pub create_at: i64,
+    pub update_at: i64,
+    pub delete_at: i64,
+    pub creator_id: String,
+    pub team_id: String,
+    pub trigger: String,
+    pub method: String,
+    pub username: String,
+    pub icon_url: String,
+    pub auto_complete: bool,
+    pub auto_complete_desc: String,
+    pub auto_complete_hint: String,
+    pub display_name: String,
+    pub description: String,
+    pub url: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Bot {
     pub user_id: String,
@@ -336,6 +372,8 @@ pub struct ScheduledPost {
     pub scheduled_at: i64,
     pub create_at: i64,
     pub update_at: i64,
+    pub processed_at: i64,
+    pub error_code: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
@@ -358,3 +396,40 @@ pub struct PluginStatus {
     pub state: i32,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    pub id: String,
+    pub token: String,
+    pub create_at: i64,
+    pub expires_at: i64,
+    pub last_activity_at: i64,
+    pub user_id: String,
+    pub device_id: String,
+    pub roles: String,
+    pub is_oauth: bool,
+    pub props: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserAccessToken {
+    pub id: String,
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    pub token: String,
+    pub user_id: String,
+    pub description: String,
+    pub is_active: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UploadSession {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub upload_type: String,
+    pub create_at: i64,
+    pub user_id: String,
+    #[serde(skip_serializing_if = "String::is_empty", default)]
+    pub channel_id: String,
+    pub filename: String,
+    pub file_size: i64,
+    pub file_offset: i64,
+}
@@ -0,0 +1,36 @@
+//! Personal access token models
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A long-lived token a user created to call the API
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct UserAccessToken {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    pub description: Option<String>,
+    pub is_active: bool,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    /// The token itself, only returned when it is created
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+/// DTO for creating a personal access token
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateUserAccessToken {
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// DTO for revoking, disabling or enabling a personal access token
+#[derive(Debug, Clone, Deserialize)]
+pub struct UserAccessTokenAction {
+    pub token_id: String,
+}
@@ -34,13 +34,48 @@ pub struct SsoConfig {
     pub scopes: Vec<String>,
     pub idp_metadata_url: Option<String>,
     pub idp_entity_id: Option<String>,
+    #[serde(skip_serializing)]
+    pub sp_private_key_encrypted: Option<String>,
+    pub sp_certificate: Option<String>,
     pub is_active: bool,
     pub auto_provision: bool,
     pub default_role: Option<String>,
+    pub username_claim: String,
+    pub display_name_claim: String,
+    pub email_claim: String,
+    pub link_by_email: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// External SSO identity linked to a local user
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct UserIdentity {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub sso_config_id: Uuid,
+    pub subject: String,
+    pub email: Option<String>,
+    pub last_login_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Result of one LDAP directory sync
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct LdapSyncRun {
+    pub id: Uuid,
+    /// One of "running", "success" or "failed"
+    pub status: String,
+    pub triggered_by: Option<Uuid>,
+    pub users_synced: i32,
+    pub users_deactivated: i32,
+    pub memberships_added: i32,
+    pub memberships_removed: i32,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
 /// Retention policy
 #[derive(Debug, Clone, Serialize, FromRow)]
 pub struct RetentionPolicy {
@@ -82,6 +117,18 @@ pub struct CreateSsoConfig {
     pub client_id: Option<String>,
     pub client_secret: Option<String>,
     pub scopes: Option<Vec<String>>,
+    pub idp_metadata_url: Option<String>,
+    pub idp_entity_id: Option<String>,
+    /// PEM key that signs AuthnRequests and logout messages
+    pub sp_private_key: Option<String>,
+    pub sp_certificate: Option<String>,
+    pub auto_provision: Option<bool>,
+    pub default_role: Option<String>,
+    pub username_claim: Option<String>,
+    pub display_name_claim: Option<String>,
+    pub email_claim: Option<String>,
+    pub link_by_email: Option<bool>,
+    pub is_active: Option<bool>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
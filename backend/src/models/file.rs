@@ -21,10 +21,35 @@ pub struct FileInfo {
     pub height: Option<i32>,
     pub has_thumbnail: bool,
     pub thumbnail_key: Option<String>,
+    pub preview_key: Option<String>,
+    /// Tiny JPEG clients blur while the preview loads
+    #[serde(skip_serializing)]
+    pub mini_preview: Option<Vec<u8>>,
+    /// One of "pending", "processing", "processed" or "failed", if the file
+    /// has media to process
+    pub media_status: Option<String>,
+    #[serde(skip_serializing)]
+    pub media_claimed_at: Option<DateTime<Utc>>,
+    /// Text read from a document, for search
+    #[serde(skip_serializing)]
+    pub extracted_text: Option<String>,
+    /// One of "clean", "infected" or "failed", if the file was scanned
+    pub scan_status: Option<String>,
+    /// The threat found, or why the file could not be scanned
+    pub scan_result: Option<String>,
+    pub scanned_at: Option<DateTime<Utc>>,
     pub sha256: Option<String>,
     pub created_at: DateTime<Utc>,
 }
 
+impl FileInfo {
+    /// Whether the file is held back from download after a scan found a
+    /// threat or could not check it
+    pub fn is_quarantined(&self) -> bool {
+        matches!(self.scan_status.as_deref(), Some("infected" | "failed"))
+    }
+}
+
 /// Response for file upload
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileUploadResponse {
@@ -44,3 +69,50 @@ pub struct PresignedUploadUrl {
     pub file_key: String,
     pub expires_in: u64,
 }
+
+/// Admin-started copy of every file from one storage backend to another
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct StorageMigration {
+    pub id: Uuid,
+    pub source: String,
+    pub target: String,
+    /// One of "running", "success" or "failed"
+    pub status: String,
+    pub triggered_by: Option<Uuid>,
+    pub files_total: i32,
+    pub files_copied: i32,
+    pub files_failed: i32,
+    pub error: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+/// Request to start a storage migration
+#[derive(Debug, Clone, Deserialize)]
+pub struct StartStorageMigration {
+    pub source: String,
+    pub target: String,
+}
+
+/// A file being uploaded over one or more requests
+#[derive(Debug, Clone, FromRow)]
+pub struct UploadSession {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub channel_id: Option<Uuid>,
+    pub filename: String,
+    pub mime_type: String,
+    pub key: String,
+    pub backend: String,
+    pub file_size: i64,
+    /// Bytes received so far
+    pub file_offset: i64,
+    pub storage_upload_id: Option<String>,
+    pub part_tags: Vec<String>,
+    pub pending: Vec<u8>,
+    pub hash_state: Vec<u8>,
+    pub locked_until: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
@@ -78,7 +78,12 @@ pub struct Bot {
 pub struct BotToken {
     pub id: Uuid,
     pub bot_id: Uuid,
-    pub token: String,
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    /// The token itself, only returned when it is created
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
     pub description: Option<String>,
     pub is_active: bool,
     pub last_used_at: Option<DateTime<Utc>>,
@@ -102,12 +107,18 @@ pub struct CreateOutgoingWebhook {
     #[serde(default = "default_trigger_when")]
     pub trigger_when: String,
     pub callback_urls: Vec<String>,
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
 }
 
 fn default_trigger_when() -> String {
     "first_word".to_string()
 }
 
+fn default_content_type() -> String {
+    "application/json".to_string()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateSlashCommand {
     pub trigger: String,
@@ -140,17 +151,42 @@ pub struct WebhookPayload {
     pub props: serde_json::Value,
 }
 
-/// Outgoing webhook response
+/// Outgoing webhook request sent to callback URLs (Mattermost-compatible)
+///
+/// IDs are Mattermost-encoded and every field is a scalar so the payload can
+/// be sent either as JSON or as a form.
 #[derive(Debug, Clone, Serialize)]
 pub struct OutgoingWebhookPayload {
     pub token: String,
-    pub team_id: Uuid,
-    pub channel_id: Uuid,
+    pub team_id: String,
+    pub team_domain: String,
+    pub channel_id: String,
     pub channel_name: String,
-    pub user_id: Uuid,
+    pub timestamp: i64,
+    pub user_id: String,
     pub user_name: String,
+    pub post_id: String,
     pub text: String,
     pub trigger_word: String,
+    pub file_ids: String,
+}
+
+/// Response returned by an outgoing webhook callback
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct OutgoingWebhookResponse {
+    #[serde(default)]
+    pub text: Option<String>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub icon_url: Option<String>,
+    #[serde(default)]
+    pub props: Option<serde_json::Value>,
+    #[serde(default)]
+    pub attachments: Option<serde_json::Value>,
+    /// "comment" replies in the thread of the triggering post
+    #[serde(default)]
+    pub response_type: Option<String>,
 }
 
 /// Command execution request
@@ -159,15 +195,79 @@ pub struct ExecuteCommand {
     pub command: String,
     pub channel_id: Uuid,
     pub team_id: Option<Uuid>,
+    /// Thread the command was typed in, if any
+    #[serde(default)]
+    pub root_id: Option<Uuid>,
 }
 
 /// Command execution response
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CommandResponse {
+    #[serde(default = "default_response_type")]
     pub response_type: String, // "in_channel" or "ephemeral"
+    #[serde(default)]
     pub text: String,
+    #[serde(default)]
     pub username: Option<String>,
+    #[serde(default)]
     pub icon_url: Option<String>,
+    #[serde(default)]
     pub goto_location: Option<String>,
+    #[serde(default)]
     pub attachments: Option<serde_json::Value>,
 }
+
+fn default_response_type() -> String {
+    "ephemeral".to_string()
+}
+
+impl CommandResponse {
+    /// Response shown only to the user who ran the command
+    pub fn ephemeral(text: impl Into<String>) -> Self {
+        Self {
+            response_type: "ephemeral".to_string(),
+            text: text.into(),
+            username: None,
+            icon_url: None,
+            goto_location: None,
+            attachments: None,
+        }
+    }
+
+    /// Response posted in the channel as the user who ran the command
+    pub fn in_channel(text: impl Into<String>) -> Self {
+        Self {
+            response_type: "in_channel".to_string(),
+            ..Self::ephemeral(text)
+        }
+    }
+}
+
+/// Request sent to a custom slash command URL (Mattermost-compatible)
+///
+/// Sent as a form body for POST commands and as query parameters for GET.
+#[derive(Debug, Clone, Serialize)]
+pub struct SlashCommandPayload {
+    pub token: String,
+    pub team_id: String,
+    pub team_domain: String,
+    pub channel_id: String,
+    pub channel_name: String,
+    pub user_id: String,
+    pub user_name: String,
+    pub command: String,
+    pub text: String,
+    pub response_url: String,
+}
+
+/// Delayed response handle issued with each custom command execution
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct CommandWebhook {
+    pub id: Uuid,
+    pub command_id: Uuid,
+    pub user_id: Uuid,
+    pub channel_id: Uuid,
+    pub root_id: Option<Uuid>,
+    pub use_count: i32,
+    pub created_at: DateTime<Utc>,
+}
@@ -2,6 +2,7 @@
 //!
 //! Provides database entities and DTOs.
 
+pub mod access_token;
 pub mod call;
 pub mod channel;
 pub mod channel_category;
@@ -13,10 +14,13 @@ pub mod organization;
 pub mod playbook;
 pub mod post;
 pub mod preferences;
+pub mod push_notification;
 pub mod server_config;
+pub mod session;
 pub mod team;
 pub mod user;
 
+pub use access_token::*;
 pub use call::*;
 pub use channel::*;
 pub use channel_category::*;
@@ -28,6 +32,8 @@ pub use organization::*;
 pub use playbook::*;
 pub use post::*;
 pub use preferences::*;
+pub use push_notification::*;
 pub use server_config::*;
+pub use session::*;
 pub use team::*;
 pub use user::*;
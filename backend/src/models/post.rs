@@ -107,3 +107,23 @@ pub struct PostResponse {
     #[sqlx(default)]
     pub seq: i64,
 }
+
+/// Scheduled post entity
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct ScheduledPost {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub channel_id: Uuid,
+    pub root_id: Option<Uuid>,
+    pub message: String,
+    pub props: Option<serde_json::Value>,
+    pub file_ids: Option<Vec<Uuid>>,
+    pub scheduled_at: DateTime<Utc>,
+    pub state: String, // pending, processing, sent, failed, cancelled
+    pub error_code: Option<String>,
+    pub error_message: Option<String>,
+    pub processed_at: Option<DateTime<Utc>>,
+    pub post_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
@@ -0,0 +1,98 @@
+//! Push notification models
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Mobile device registered for push notifications
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct UserDevice {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub device_id: String,
+    pub token: Option<String>,
+    pub platform: Option<String>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+/// Notification sent to the push proxy (Mattermost wire format)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PushNotification {
+    #[serde(default)]
+    pub ack_id: String,
+    #[serde(default)]
+    pub platform: String,
+    #[serde(default)]
+    pub server_id: String,
+    #[serde(default)]
+    pub device_id: String,
+    #[serde(default)]
+    pub post_id: String,
+    #[serde(default)]
+    pub category: String,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub badge: i64,
+    #[serde(default)]
+    pub cont_ava: i32,
+    #[serde(default)]
+    pub team_id: String,
+    #[serde(default)]
+    pub channel_id: String,
+    #[serde(default)]
+    pub root_id: String,
+    #[serde(default)]
+    pub channel_name: String,
+    #[serde(rename = "type", default)]
+    pub push_type: String,
+    #[serde(default)]
+    pub sender_id: String,
+    #[serde(default)]
+    pub sender_name: String,
+    #[serde(default)]
+    pub override_username: String,
+    #[serde(default)]
+    pub override_icon_url: String,
+    #[serde(default)]
+    pub from_webhook: String,
+    #[serde(default)]
+    pub version: String,
+    #[serde(default)]
+    pub is_crt_enabled: bool,
+    #[serde(default)]
+    pub is_id_loaded: bool,
+    #[serde(default)]
+    pub post_type: String,
+    #[serde(default)]
+    pub channel_type: String,
+}
+
+/// Answer of the push proxy
+#[derive(Debug, Clone, Deserialize)]
+pub struct PushResponse {
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Receipt sent by a device for a delivered notification
+#[derive(Debug, Clone, Deserialize)]
+pub struct PushNotificationAck {
+    pub id: String,
+    #[serde(default)]
+    pub platform: String,
+    #[serde(default)]
+    pub server_id: String,
+    #[serde(rename = "type", default)]
+    pub notification_type: String,
+    #[serde(default)]
+    pub post_id: Option<String>,
+    #[serde(default)]
+    pub is_id_loaded: bool,
+    #[serde(default)]
+    pub received_at: i64,
+}
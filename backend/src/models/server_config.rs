@@ -14,6 +14,8 @@ pub struct ServerConfig {
     pub integrations: sqlx::types::Json<IntegrationsConfig>,
     pub compliance: sqlx::types::Json<ComplianceConfig>,
     pub email: sqlx::types::Json<EmailConfig>,
+    pub rate_limiting: sqlx::types::Json<RateLimitConfig>,
+    pub file_scanning: sqlx::types::Json<FileScanningConfig>,
     pub experimental: sqlx::types::Json<serde_json::Value>,
     pub updated_at: DateTime<Utc>,
     pub updated_by: Option<Uuid>,
@@ -77,6 +79,33 @@ pub struct AuthConfig {
     pub password_require_symbol: bool,
     #[serde(default = "default_session_length")]
     pub session_length_hours: i32,
+    /// Block login until the user has confirmed their email address
+    #[serde(default)]
+    pub require_email_verification: bool,
+    /// Let users create personal access tokens for API access
+    #[serde(default)]
+    pub enable_personal_access_tokens: bool,
+    /// Allow guest accounts that only see the channels they are added to
+    #[serde(default)]
+    pub enable_guest_accounts: bool,
+    /// Failed logins after which an account is locked out, 0 for no limit
+    #[serde(default = "default_maximum_login_attempts")]
+    pub maximum_login_attempts: i32,
+    /// Failed logins from one client address after which it is locked out,
+    /// 0 for no limit
+    #[serde(default = "default_maximum_login_attempts_per_ip")]
+    pub maximum_login_attempts_per_ip: i32,
+    /// How long a lockout lasts after the last failed login
+    #[serde(default = "default_login_lockout_minutes")]
+    pub login_lockout_minutes: i32,
+    /// Require every user to set up TOTP before they can use the API
+    #[serde(default)]
+    pub enforce_mfa: bool,
+    /// Check passwords against an LDAP / Active Directory server
+    #[serde(default)]
+    pub enable_ldap: bool,
+    #[serde(default)]
+    pub ldap: LdapConfig,
 }
 
 fn default_true() -> bool {
@@ -88,6 +117,15 @@ fn default_password_min_length() -> i32 {
 fn default_session_length() -> i32 {
     24
 }
+fn default_maximum_login_attempts() -> i32 {
+    10
+}
+fn default_maximum_login_attempts_per_ip() -> i32 {
+    50
+}
+fn default_login_lockout_minutes() -> i32 {
+    15
+}
 
 impl Default for AuthConfig {
     fn default() -> Self {
@@ -101,10 +139,123 @@ impl Default for AuthConfig {
             password_require_number: true,
             password_require_symbol: false,
             session_length_hours: 24,
+            require_email_verification: false,
+            enable_personal_access_tokens: false,
+            enable_guest_accounts: false,
+            maximum_login_attempts: 10,
+            maximum_login_attempts_per_ip: 50,
+            login_lockout_minutes: 15,
+            enforce_mfa: false,
+            enable_ldap: false,
+            ldap: LdapConfig::default(),
+        }
+    }
+}
+
+impl AuthConfig {
+    /// Copy with the stored LDAP bind password replaced by [`MASKED_PASSWORD`]
+    pub fn masked(mut self) -> Self {
+        if !self.ldap.bind_password_encrypted.is_empty() {
+            self.ldap.bind_password_encrypted = MASKED_PASSWORD.to_string();
         }
+        self
     }
 }
 
+/// LDAP / Active Directory connection and attribute mapping
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LdapConfig {
+    /// `ldap://` or `ldaps://` URL of the directory server
+    #[serde(default)]
+    pub server_url: String,
+    /// Upgrade `ldap://` connections with STARTTLS
+    #[serde(default)]
+    pub start_tls: bool,
+    /// Account used to search the directory, anonymous when empty
+    #[serde(default)]
+    pub bind_dn: String,
+    #[serde(default)]
+    pub bind_password_encrypted: String,
+    /// Where users and groups are searched
+    #[serde(default)]
+    pub base_dn: String,
+    /// Entries that may sign in, e.g. `(objectClass=person)`
+    #[serde(default = "default_ldap_user_filter")]
+    pub user_filter: String,
+    /// Attribute that never changes for an entry, e.g. `entryUUID` or `objectGUID`
+    #[serde(default = "default_ldap_id_attribute")]
+    pub id_attribute: String,
+    #[serde(default = "default_ldap_username_attribute")]
+    pub username_attribute: String,
+    #[serde(default = "default_ldap_email_attribute")]
+    pub email_attribute: String,
+    #[serde(default = "default_ldap_display_name_attribute")]
+    pub display_name_attribute: String,
+    /// Group attribute listing the DNs of its members
+    #[serde(default = "default_ldap_group_member_attribute")]
+    pub group_member_attribute: String,
+    /// Directory groups whose members are kept in a team or channel
+    #[serde(default)]
+    pub group_mappings: Vec<LdapGroupMapping>,
+    /// Minutes between directory syncs, 0 to only sync on demand
+    #[serde(default = "default_ldap_sync_interval")]
+    pub sync_interval_minutes: i32,
+}
+
+fn default_ldap_user_filter() -> String {
+    "(objectClass=person)".to_string()
+}
+fn default_ldap_id_attribute() -> String {
+    "entryUUID".to_string()
+}
+fn default_ldap_username_attribute() -> String {
+    "uid".to_string()
+}
+fn default_ldap_email_attribute() -> String {
+    "mail".to_string()
+}
+fn default_ldap_display_name_attribute() -> String {
+    "cn".to_string()
+}
+fn default_ldap_group_member_attribute() -> String {
+    "member".to_string()
+}
+fn default_ldap_sync_interval() -> i32 {
+    60
+}
+
+impl Default for LdapConfig {
+    fn default() -> Self {
+        Self {
+            server_url: String::new(),
+            start_tls: false,
+            bind_dn: String::new(),
+            bind_password_encrypted: String::new(),
+            base_dn: String::new(),
+            user_filter: default_ldap_user_filter(),
+            id_attribute: default_ldap_id_attribute(),
+            username_attribute: default_ldap_username_attribute(),
+            email_attribute: default_ldap_email_attribute(),
+            display_name_attribute: default_ldap_display_name_attribute(),
+            group_member_attribute: default_ldap_group_member_attribute(),
+            group_mappings: Vec::new(),
+            sync_interval_minutes: default_ldap_sync_interval(),
+        }
+    }
+}
+
+/// Directory group whose members are kept in a team, or in one of its channels
+///
+/// Membership of directory users in the mapped team or channel follows the
+/// group; local accounts are left alone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LdapGroupMapping {
+    pub group_dn: String,
+    pub team_id: Uuid,
+    #[serde(default)]
+    pub channel_id: Option<Uuid>,
+}
+
 /// Integrations configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IntegrationsConfig {
@@ -161,16 +312,177 @@ pub struct EmailConfig {
     pub smtp_password_encrypted: String,
     #[serde(default = "default_true")]
     pub smtp_tls: bool,
+    /// Overrides `smtp_tls` when set
+    #[serde(default)]
+    pub smtp_security: Option<SmtpSecurity>,
     #[serde(default)]
     pub from_address: String,
     #[serde(default = "default_site_name")]
     pub from_name: String,
+    #[serde(default)]
+    pub send_push_notifications: bool,
+    #[serde(default)]
+    pub push_notification_server: String,
+    /// One of "full", "generic", "generic_no_channel" or "id_loaded"
+    #[serde(default = "default_push_contents")]
+    pub push_notification_contents: String,
 }
 
 fn default_smtp_port() -> i32 {
     587
 }
 
+/// How the SMTP connection is secured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// TLS from the start of the connection (usually port 465)
+    Tls,
+    /// Plain connection upgraded with STARTTLS (usually port 587)
+    Starttls,
+    /// No encryption, for local relays only
+    None,
+}
+
+impl EmailConfig {
+    /// Connection security, derived from `smtp_tls` for older configs
+    pub fn security(&self) -> SmtpSecurity {
+        match (self.smtp_security, self.smtp_tls, self.smtp_port) {
+            (Some(security), _, _) => security,
+            (None, false, _) => SmtpSecurity::None,
+            (None, true, 465) => SmtpSecurity::Tls,
+            (None, true, _) => SmtpSecurity::Starttls,
+        }
+    }
+
+    /// Copy with the stored password replaced by [`MASKED_PASSWORD`]
+    pub fn masked(mut self) -> Self {
+        if !self.smtp_password_encrypted.is_empty() {
+            self.smtp_password_encrypted = MASKED_PASSWORD.to_string();
+        }
+        self
+    }
+}
+
+/// API rate limits
+///
+/// Requests are limited per user, per webhook token, and per client address
+/// for anonymous requests. System admins are never limited.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default)]
+    pub enable: bool,
+    /// Any API request not covered by a more specific quota
+    #[serde(default = "default_api_quota")]
+    pub api: RateLimitQuota,
+    /// Login, registration, password reset and email verification
+    #[serde(default = "default_auth_quota")]
+    pub auth: RateLimitQuota,
+    /// Incoming webhook and slash command calls
+    #[serde(default = "default_webhooks_quota")]
+    pub webhooks: RateLimitQuota,
+    /// File uploads, including upload sessions and the data sent to them
+    #[serde(default = "default_file_upload_quota")]
+    pub file_upload: RateLimitQuota,
+    #[serde(default = "default_search_quota")]
+    pub search: RateLimitQuota,
+}
+
+/// Token bucket refilled at `per_minute` and holding at most `burst` requests
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RateLimitQuota {
+    /// 0 for no limit
+    pub per_minute: i32,
+    pub burst: i32,
+}
+
+fn default_api_quota() -> RateLimitQuota {
+    RateLimitQuota {
+        per_minute: 600,
+        burst: 100,
+    }
+}
+fn default_auth_quota() -> RateLimitQuota {
+    RateLimitQuota {
+        per_minute: 20,
+        burst: 10,
+    }
+}
+fn default_webhooks_quota() -> RateLimitQuota {
+    RateLimitQuota {
+        per_minute: 300,
+        burst: 60,
+    }
+}
+fn default_file_upload_quota() -> RateLimitQuota {
+    RateLimitQuota {
+        per_minute: 60,
+        burst: 20,
+    }
+}
+fn default_search_quota() -> RateLimitQuota {
+    RateLimitQuota {
+        per_minute: 60,
+        burst: 20,
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            api: default_api_quota(),
+            auth: default_auth_quota(),
+            webhooks: default_webhooks_quota(),
+            file_upload: default_file_upload_quota(),
+            search: default_search_quota(),
+        }
+    }
+}
+
+/// Malware scanning of uploads with ClamAV
+///
+/// Uploads are streamed to clamd before they are recorded. Infected files
+/// are quarantined: kept for review but never served. Files clamd could not
+/// scan are held back the same way.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileScanningConfig {
+    #[serde(default)]
+    pub enable: bool,
+    /// `host:port` of clamd, or the path of its Unix socket
+    #[serde(default = "default_clamd_address")]
+    pub clamd_address: String,
+    /// How long a scan may take before the file is held back
+    #[serde(default = "default_scan_timeout")]
+    pub timeout_seconds: i32,
+}
+
+fn default_clamd_address() -> String {
+    "localhost:3310".to_string()
+}
+fn default_scan_timeout() -> i32 {
+    60
+}
+
+impl Default for FileScanningConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            clamd_address: default_clamd_address(),
+            timeout_seconds: default_scan_timeout(),
+        }
+    }
+}
+
+/// Placeholder sent to clients instead of a stored password
+///
+/// Saving the placeholder back keeps the stored password.
+pub const MASKED_PASSWORD: &str = "********************************";
+
+fn default_push_contents() -> String {
+    "full".to_string()
+}
+
 /// DTO for updating a specific config category
 #[derive(Debug, Clone, Deserialize)]
 #[serde(untagged)]
@@ -180,6 +492,8 @@ pub enum ConfigUpdate {
     Integrations(IntegrationsConfig),
     Compliance(ComplianceConfig),
     Email(EmailConfig),
+    RateLimiting(RateLimitConfig),
+    FileScanning(FileScanningConfig),
     Experimental(serde_json::Value),
 }
 
@@ -191,6 +505,8 @@ pub struct ServerConfigResponse {
     pub integrations: IntegrationsConfig,
     pub compliance: ComplianceConfig,
     pub email: EmailConfig,
+    pub rate_limiting: RateLimitConfig,
+    pub file_scanning: FileScanningConfig,
     pub experimental: serde_json::Value,
 }
 
@@ -198,10 +514,12 @@ impl From<ServerConfig> for ServerConfigResponse {
     fn from(config: ServerConfig) -> Self {
         Self {
             site: config.site.0,
-            authentication: config.authentication.0,
+            authentication: config.authentication.0.masked(),
             integrations: config.integrations.0,
             compliance: config.compliance.0,
-            email: config.email.0,
+            email: config.email.0.masked(),
+            rate_limiting: config.rate_limiting.0,
+            file_scanning: config.file_scanning.0,
             experimental: config.experimental.0,
         }
     }
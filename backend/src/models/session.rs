@@ -0,0 +1,28 @@
+//! Session models
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// A logged-in client, identified by its token's `jti` claim
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub device_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    /// "password" or "sso"
+    pub auth_method: String,
+    pub created_at: DateTime<Utc>,
+    pub last_activity_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// DTO for revoking a single session
+#[derive(Debug, Clone, Deserialize)]
+pub struct RevokeSession {
+    pub session_id: String,
+}
@@ -17,6 +17,12 @@ pub struct Team {
     pub is_public: bool,
     #[serde(default)]
     pub allow_open_invite: bool,
+    /// ID for the team's invite link; only shown to members who can invite
+    #[serde(default, skip_serializing)]
+    pub invite_id: String,
+    /// Comma separated email domains allowed to join, empty for any
+    #[serde(default)]
+    pub allowed_domains: String,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -42,6 +42,10 @@ pub struct User {
     pub status_expires_at: Option<DateTime<Utc>>,
     #[sqlx(default)]
     pub custom_status: Option<serde_json::Value>,
+    #[sqlx(default)]
+    pub email_verified: bool,
+    #[sqlx(default)]
+    pub mfa_active: bool,
     pub last_login_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -63,6 +67,8 @@ pub struct UserResponse {
     pub status_emoji: Option<String>,
     pub status_expires_at: Option<DateTime<Utc>>,
     pub custom_status: Option<serde_json::Value>,
+    pub email_verified: bool,
+    pub mfa_active: bool,
     pub created_at: DateTime<Utc>,
 }
 
@@ -82,6 +88,8 @@ impl From<User> for UserResponse {
             status_emoji: user.status_emoji,
             status_expires_at: user.status_expires_at,
             custom_status: user.custom_status,
+            email_verified: user.email_verified,
+            mfa_active: user.mfa_active,
             created_at: user.created_at,
         }
     }
@@ -95,6 +103,12 @@ pub struct CreateUser {
     pub password: String,
     pub display_name: Option<String>,
     pub org_id: Option<Uuid>,
+    /// Token from an emailed team invitation
+    #[serde(default)]
+    pub invite_token: Option<String>,
+    /// Invite ID from a team's invite link
+    #[serde(default)]
+    pub invite_id: Option<String>,
 }
 
 /// DTO for updating a user
@@ -112,10 +126,46 @@ pub struct ChangePassword {
     pub new_password: String,
 }
 
+/// DTO for requesting a password reset or verification email
+#[derive(Debug, Clone, Deserialize)]
+pub struct SendAccountEmail {
+    pub email: String,
+}
+
+/// DTO for completing a password reset
+#[derive(Debug, Clone, Deserialize)]
+pub struct ResetPassword {
+    /// Mattermost clients send the token as `code`
+    #[serde(alias = "code")]
+    pub token: String,
+    pub new_password: String,
+}
+
+/// DTO for confirming an email address
+#[derive(Debug, Clone, Deserialize)]
+pub struct VerifyEmail {
+    pub token: String,
+}
+
+/// DTO carrying a TOTP or recovery code
+#[derive(Debug, Clone, Deserialize)]
+pub struct MfaCode {
+    pub code: String,
+}
+
+/// One-time MFA recovery codes, only ever returned when they are created
+#[derive(Debug, Clone, Serialize)]
+pub struct MfaRecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct LoginRequest {
     pub email: String,
     pub password: String,
+    /// TOTP or recovery code, required once MFA is active
+    #[serde(default)]
+    pub mfa_token: Option<String>,
 }
 
 /// Response after successful login
@@ -126,3 +176,17 @@ pub struct AuthResponse {
     pub expires_in: u64,
     pub user: UserResponse,
 }
+
+/// Response after registration
+///
+/// When email verification is required the user has to verify before they
+/// can log in, so no token is issued.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum RegisterResponse {
+    Authenticated(AuthResponse),
+    VerificationRequired {
+        user: UserResponse,
+        verification_required: bool,
+    },
+}
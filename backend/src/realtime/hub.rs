@@ -27,6 +27,8 @@ pub struct WsHub {
     presence: RwLock<HashMap<Uuid, String>>,
     /// Usernames cache
     usernames: RwLock<HashMap<Uuid, String>>,
+    /// Session each connection authenticated with
+    connection_sessions: RwLock<HashMap<Uuid, Uuid>>, // connection_id -> session_id
 }
 
 impl WsHub {
@@ -37,6 +39,7 @@ impl WsHub {
             team_subscriptions: RwLock::new(HashMap::new()),
             presence: RwLock::new(HashMap::new()),
             usernames: RwLock::new(HashMap::new()),
+            connection_sessions: RwLock::new(HashMap::new()),
         })
     }
 
@@ -44,11 +47,17 @@ impl WsHub {
     pub async fn add_connection(
         &self,
         user_id: Uuid,
+        session_id: Uuid,
         username: String,
     ) -> (Uuid, broadcast::Receiver<String>) {
         let (tx, rx) = broadcast::channel(100);
         let connection_id = Uuid::new_v4();
 
+        self.connection_sessions
+            .write()
+            .await
+            .insert(connection_id, session_id);
+
         let mut connections = self.connections.write().await;
         connections
             .entry(user_id)
@@ -66,16 +75,18 @@ impl WsHub {
 
     /// Remove a connection
     pub async fn remove_connection(&self, user_id: Uuid, connection_id: Uuid) {
+        self.connection_sessions.write().await.remove(&connection_id);
+
         let mut connections = self.connections.write().await;
-        let mut should_clear_presence = false;
 
         if let Some(user_connections) = connections.get_mut(&user_id) {
             user_connections.remove(&connection_id);
             if user_connections.is_empty() {
                 connections.remove(&user_id);
-                should_clear_presence = true;
             }
         }
+        // Also true when the user was disconnected by the server
+        let should_clear_presence = !connections.contains_key(&user_id);
 
         drop(connections);
 
@@ -92,6 +103,36 @@ impl WsHub {
         // For accurate tracking, we might want to maintain a reverse map user_id -> [channels/teams].
     }
 
+    /// Close every connection of a user
+    ///
+    /// Dropping the senders ends the connections' forwarding loops, which
+    /// then close the sockets and clean up.
+    pub async fn disconnect_user(&self, user_id: Uuid) {
+        self.connections.write().await.remove(&user_id);
+    }
+
+    /// Close the connections that authenticated with `session_id`
+    pub async fn disconnect_session(&self, user_id: Uuid, session_id: Uuid) {
+        let connection_ids: Vec<Uuid> = self
+            .connection_sessions
+            .read()
+            .await
+            .iter()
+            .filter(|(_, s)| **s == session_id)
+            .map(|(c, _)| *c)
+            .collect();
+
+        let mut connections = self.connections.write().await;
+        if let Some(user_connections) = connections.get_mut(&user_id) {
+            for connection_id in &connection_ids {
+                user_connections.remove(connection_id);
+            }
+            if user_connections.is_empty() {
+                connections.remove(&user_id);
+            }
+        }
+    }
+
     /// Subscribe user to a channel
     pub async fn subscribe_channel(&self, user_id: Uuid, channel_id: Uuid) {
         let mut subs = self.channel_subscriptions.write().await;
@@ -238,6 +279,7 @@ impl Default for WsHub {
             team_subscriptions: RwLock::new(HashMap::new()),
             presence: RwLock::new(HashMap::new()),
             usernames: RwLock::new(HashMap::new()),
+            connection_sessions: RwLock::new(HashMap::new()),
         }
     }
 }
@@ -0,0 +1,245 @@
+//! Bot tokens and personal access tokens
+//!
+//! Both are long-lived random strings rather than JWTs, and only their
+//! SHA-256 hashes are stored. They are accepted wherever a session token is;
+//! the token's ID takes the place of the session ID in its claims. Personal
+//! access tokens only work while `enable_personal_access_tokens` is on, bot
+//! tokens work as long as the bot is active.
+
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::auth::Claims;
+use crate::crypto::{hash_token, random_token};
+use crate::error::{ApiResult, AppError};
+use crate::models::{BotToken, UserAccessToken};
+use crate::services::sessions::SessionCheck;
+
+/// Last-used times are only written when older than this
+const LAST_USED_RESOLUTION_SECS: i64 = 60;
+
+/// Whether `token` is an access token rather than a session JWT
+pub fn is_access_token(token: &str) -> bool {
+    // JWTs always contain dots, access tokens are plain hex
+    !token.contains('.')
+}
+
+#[derive(FromRow)]
+struct TokenOwner {
+    token_id: Uuid,
+    is_bot_token: bool,
+    last_used_at: Option<DateTime<Utc>>,
+    user_id: Uuid,
+    email: String,
+    role: String,
+    org_id: Option<Uuid>,
+    mfa_exempt: bool,
+}
+
+/// Look up the owner of an access token
+pub async fn authenticate(state: &AppState, token: &str) -> ApiResult<SessionCheck> {
+    let owner: TokenOwner = sqlx::query_as(
+        r#"
+        SELECT t.id AS token_id, false AS is_bot_token, t.last_used_at,
+               u.id AS user_id, u.email, u.role, u.org_id,
+               u.mfa_active OR u.is_bot AS mfa_exempt
+        FROM user_access_tokens t
+        JOIN users u ON u.id = t.user_id
+        WHERE t.token_hash = $1 AND t.is_active = true AND u.is_active = true
+        UNION ALL
+        SELECT t.id, true, t.last_used_at, u.id, u.email, u.role, u.org_id, true
+        FROM bot_tokens t
+        JOIN bots b ON b.id = t.bot_id
+        JOIN users u ON u.id = b.user_id
+        WHERE t.token_hash = $1 AND t.is_active = true
+          AND b.is_active = true AND u.is_active = true
+        "#,
+    )
+    .bind(hash_token(token.trim()))
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::Unauthorized("Invalid or revoked token".to_string()))?;
+
+    if !owner.is_bot_token {
+        let config = crate::services::auth_config::get_password_rules(&state.db).await?;
+        if !config.enable_personal_access_tokens {
+            return Err(AppError::Unauthorized(
+                "Personal access tokens are disabled".to_string(),
+            ));
+        }
+    }
+
+    let stale = owner
+        .last_used_at
+        .is_none_or(|t| Utc::now() - t > Duration::seconds(LAST_USED_RESOLUTION_SECS));
+    if stale {
+        let query = if owner.is_bot_token {
+            "UPDATE bot_tokens SET last_used_at = NOW() WHERE id = $1"
+        } else {
+            "UPDATE user_access_tokens SET last_used_at = NOW() WHERE id = $1"
+        };
+        sqlx::query(query)
+            .bind(owner.token_id)
+            .execute(&state.db)
+            .await?;
+    }
+
+    Ok(SessionCheck {
+        claims: Claims::new(
+            owner.user_id,
+            owner.token_id,
+            owner.email,
+            owner.role,
+            owner.org_id,
+            state.jwt_expiry_hours,
+        ),
+        mfa_exempt: owner.mfa_exempt,
+    })
+}
+
+/// Fail unless personal access tokens are turned on
+pub async fn ensure_enabled(db: &PgPool) -> ApiResult<()> {
+    let config = crate::services::auth_config::get_password_rules(db).await?;
+    if !config.enable_personal_access_tokens {
+        return Err(AppError::Forbidden(
+            "Personal access tokens are disabled".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Create a personal access token; the returned row carries the token
+pub async fn create_user_token(
+    db: &PgPool,
+    user_id: Uuid,
+    description: Option<&str>,
+) -> ApiResult<UserAccessToken> {
+    let token = random_token();
+    let mut row: UserAccessToken = sqlx::query_as(
+        r#"
+        INSERT INTO user_access_tokens (user_id, token_hash, description)
+        VALUES ($1, $2, $3)
+        RETURNING *
+        "#,
+    )
+    .bind(user_id)
+    .bind(hash_token(&token))
+    .bind(description)
+    .fetch_one(db)
+    .await?;
+
+    row.token = Some(token);
+    Ok(row)
+}
+
+pub async fn list_user_tokens(db: &PgPool, user_id: Uuid) -> ApiResult<Vec<UserAccessToken>> {
+    let tokens = sqlx::query_as(
+        "SELECT * FROM user_access_tokens WHERE user_id = $1 ORDER BY created_at DESC",
+    )
+    .bind(user_id)
+    .fetch_all(db)
+    .await?;
+
+    Ok(tokens)
+}
+
+pub async fn get_user_token(db: &PgPool, token_id: Uuid) -> ApiResult<UserAccessToken> {
+    sqlx::query_as("SELECT * FROM user_access_tokens WHERE id = $1")
+        .bind(token_id)
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Token not found".to_string()))
+}
+
+/// Disable or re-enable a personal access token
+pub async fn set_user_token_active(
+    state: &AppState,
+    token: &UserAccessToken,
+    active: bool,
+) -> ApiResult<()> {
+    sqlx::query("UPDATE user_access_tokens SET is_active = $2 WHERE id = $1")
+        .bind(token.id)
+        .bind(active)
+        .execute(&state.db)
+        .await?;
+
+    if !active {
+        state
+            .ws_hub
+            .disconnect_session(token.user_id, token.id)
+            .await;
+    }
+
+    Ok(())
+}
+
+/// Delete a personal access token
+pub async fn revoke_user_token(state: &AppState, token: &UserAccessToken) -> ApiResult<()> {
+    sqlx::query("DELETE FROM user_access_tokens WHERE id = $1")
+        .bind(token.id)
+        .execute(&state.db)
+        .await?;
+    state
+        .ws_hub
+        .disconnect_session(token.user_id, token.id)
+        .await;
+
+    Ok(())
+}
+
+/// Create a bot token; the returned row carries the token
+pub async fn create_bot_token(
+    db: &PgPool,
+    bot_id: Uuid,
+    description: Option<&str>,
+) -> ApiResult<BotToken> {
+    let token = random_token();
+    let mut row: BotToken = sqlx::query_as(
+        r#"
+        INSERT INTO bot_tokens (bot_id, token_hash, description)
+        VALUES ($1, $2, $3)
+        RETURNING *
+        "#,
+    )
+    .bind(bot_id)
+    .bind(hash_token(&token))
+    .bind(description)
+    .fetch_one(db)
+    .await?;
+
+    row.token = Some(token);
+    Ok(row)
+}
+
+/// Delete a bot token and close connections made with it
+pub async fn revoke_bot_token(
+    state: &AppState,
+    bot_user_id: Uuid,
+    bot_id: Uuid,
+    token_id: Uuid,
+) -> ApiResult<()> {
+    sqlx::query("DELETE FROM bot_tokens WHERE id = $1 AND bot_id = $2")
+        .bind(token_id)
+        .bind(bot_id)
+        .execute(&state.db)
+        .await?;
+    state.ws_hub.disconnect_session(bot_user_id, token_id).await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn access_tokens_are_told_apart_from_jwts() {
+        assert!(is_access_token(&random_token()));
+        assert!(!is_access_token(
+            "eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiIxIn0.c2ln"
+        ));
+    }
+}
@@ -0,0 +1,272 @@
+//! Password reset and email verification
+//!
+//! Both flows email the user a link with a random token. Only the token's
+//! SHA-256 hash is stored, each token works once, and issuing a new token
+//! replaces any unused one for the same purpose.
+
+use sqlx::PgPool;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::auth::hash_password;
+use crate::crypto::{hash_token, random_token};
+use crate::error::{ApiResult, AppError};
+use crate::models::{EmailConfig, SiteConfig, User};
+use crate::services::email_templates::{self, Branding};
+
+/// How long a password reset link stays valid
+pub const PASSWORD_RESET_EXPIRY_HOURS: i64 = 1;
+
+/// How long an email verification link stays valid
+pub const EMAIL_VERIFICATION_EXPIRY_HOURS: i64 = 48;
+
+/// What a token may be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenPurpose {
+    PasswordReset,
+    EmailVerification,
+}
+
+impl TokenPurpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::PasswordReset => "password_reset",
+            Self::EmailVerification => "email_verification",
+        }
+    }
+
+    fn expiry_hours(self) -> i64 {
+        match self {
+            Self::PasswordReset => PASSWORD_RESET_EXPIRY_HOURS,
+            Self::EmailVerification => EMAIL_VERIFICATION_EXPIRY_HOURS,
+        }
+    }
+}
+
+/// Create a token for `user_id`, replacing any unused one
+async fn issue_token(db: &PgPool, user_id: Uuid, purpose: TokenPurpose) -> ApiResult<String> {
+    let token = random_token();
+
+    let mut tx = db.begin().await?;
+    sqlx::query(
+        "DELETE FROM account_tokens WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(purpose.as_str())
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO account_tokens (user_id, purpose, token_hash, expires_at)
+        VALUES ($1, $2, $3, NOW() + make_interval(hours => $4))
+        "#,
+    )
+    .bind(user_id)
+    .bind(purpose.as_str())
+    .bind(hash_token(&token))
+    .bind(purpose.expiry_hours() as i32)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(token)
+}
+
+/// Mark a token used and return its user
+///
+/// Fails if the token is unknown, expired or was already used.
+async fn consume_token(db: &PgPool, token: &str, purpose: TokenPurpose) -> ApiResult<Uuid> {
+    sqlx::query_scalar(
+        r#"
+        UPDATE account_tokens SET used_at = NOW()
+        WHERE token_hash = $1 AND purpose = $2
+          AND used_at IS NULL AND expires_at > NOW()
+        RETURNING user_id
+        "#,
+    )
+    .bind(hash_token(token.trim()))
+    .bind(purpose.as_str())
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| AppError::BadRequest("Invalid or expired token".to_string()))
+}
+
+/// Load the settings needed to send account emails
+pub async fn email_settings(db: &PgPool) -> ApiResult<(EmailConfig, Branding)> {
+    let (site, config): (
+        sqlx::types::Json<SiteConfig>,
+        sqlx::types::Json<EmailConfig>,
+    ) = sqlx::query_as("SELECT site, email FROM server_config WHERE id = 'default'")
+        .fetch_one(db)
+        .await?;
+
+    if config.smtp_host.is_empty() {
+        return Err(AppError::Config("Email is not configured".to_string()));
+    }
+    let branding = Branding::new(&site, &config);
+    if branding.site_url.is_empty() {
+        return Err(AppError::Config(
+            "Site URL must be set to send account emails".to_string(),
+        ));
+    }
+
+    Ok((config.0, branding))
+}
+
+async fn find_active_user(db: &PgPool, email: &str) -> ApiResult<Option<User>> {
+    let user = sqlx::query_as(
+        r#"
+        SELECT * FROM users
+        WHERE lower(email) = lower($1) AND is_active = true AND is_bot = false
+        "#,
+    )
+    .bind(email.trim())
+    .fetch_optional(db)
+    .await?;
+
+    Ok(user)
+}
+
+/// Email a password reset link to the account with this address
+///
+/// Known and unknown addresses get the same answer, so the endpoint does not
+/// reveal which addresses have accounts: settings are checked before the
+/// lookup, and the lookup, token and email happen in the background so the
+/// response does not wait on work only done for known addresses.
+pub async fn send_password_reset(state: &AppState, email: &str) -> ApiResult<()> {
+    let (config, branding) = email_settings(&state.db).await?;
+
+    let state = state.clone();
+    let email = email.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = email_password_reset(&state, &config, &branding, &email).await {
+            warn!(error = %e, "Failed to send password reset email");
+        }
+    });
+
+    Ok(())
+}
+
+async fn email_password_reset(
+    state: &AppState,
+    config: &EmailConfig,
+    branding: &Branding,
+    email: &str,
+) -> ApiResult<()> {
+    let Some(user) = find_active_user(&state.db, email).await? else {
+        info!("Password reset requested for unknown address");
+        return Ok(());
+    };
+
+    let token = issue_token(&state.db, user.id, TokenPurpose::PasswordReset).await?;
+    let link = format!(
+        "{}/reset_password_complete?token={}",
+        branding.site_url, token
+    );
+    let rendered = email_templates::password_reset(branding, &link, PASSWORD_RESET_EXPIRY_HOURS);
+
+    crate::services::email::send_html_email(
+        state,
+        config,
+        &user.email,
+        &rendered.subject,
+        &rendered.html,
+        &rendered.text,
+    )
+    .await
+}
+
+/// Set a new password using a reset token
+pub async fn reset_password(state: &AppState, token: &str, new_password: &str) -> ApiResult<()> {
+    // Check the password first so a rejected one does not use up the token
+    let rules = crate::services::auth_config::get_password_rules(&state.db).await?;
+    crate::services::auth_config::validate_password(new_password, &rules)?;
+
+    let user_id = consume_token(&state.db, token, TokenPurpose::PasswordReset).await?;
+    let password_hash = hash_password(new_password)?;
+
+    // Receiving the link proves the address belongs to the user
+    sqlx::query(
+        "UPDATE users SET password_hash = $2, email_verified = true, updated_at = NOW() WHERE id = $1",
+    )
+    .bind(user_id)
+    .bind(&password_hash)
+    .execute(&state.db)
+    .await?;
+    sqlx::query(
+        "DELETE FROM account_tokens WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL",
+    )
+    .bind(user_id)
+    .bind(TokenPurpose::PasswordReset.as_str())
+    .execute(&state.db)
+    .await?;
+
+    // Whoever knew the old password is logged out
+    crate::services::sessions::revoke_all_sessions(state, user_id).await?;
+
+    Ok(())
+}
+
+/// Email a verification link to `user`
+pub async fn send_email_verification(state: &AppState, user: &User) -> ApiResult<()> {
+    let (config, branding) = email_settings(&state.db).await?;
+
+    let token = issue_token(&state.db, user.id, TokenPurpose::EmailVerification).await?;
+    let link = format!(
+        "{}/do_verify_email?token={}&email={}",
+        branding.site_url,
+        token,
+        urlencoding::encode(&user.email)
+    );
+    let rendered =
+        email_templates::email_verification(&branding, &link, EMAIL_VERIFICATION_EXPIRY_HOURS);
+
+    crate::services::email::send_html_email(
+        state,
+        &config,
+        &user.email,
+        &rendered.subject,
+        &rendered.html,
+        &rendered.text,
+    )
+    .await
+}
+
+/// Send a new verification link to the unverified account with this address
+///
+/// Like [`send_password_reset`], unknown addresses are ignored.
+pub async fn resend_email_verification(state: &AppState, email: &str) -> ApiResult<()> {
+    match find_active_user(&state.db, email).await? {
+        Some(user) if !user.email_verified => send_email_verification(state, &user).await,
+        _ => Ok(()),
+    }
+}
+
+/// Mark the token's user as verified
+pub async fn verify_email(state: &AppState, token: &str) -> ApiResult<()> {
+    let user_id = consume_token(&state.db, token, TokenPurpose::EmailVerification).await?;
+
+    sqlx::query("UPDATE users SET email_verified = true, updated_at = NOW() WHERE id = $1")
+        .bind(user_id)
+        .execute(&state.db)
+        .await?;
+
+    Ok(())
+}
+
+/// Reject logins from unverified users when verification is required
+pub async fn check_email_verified(db: &PgPool, user: &User) -> ApiResult<()> {
+    if user.email_verified {
+        return Ok(());
+    }
+
+    let config = crate::services::auth_config::get_password_rules(db).await?;
+    if config.require_email_verification {
+        return Err(AppError::Unauthorized(
+            "Email address has not been verified".to_string(),
+        ));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,774 @@
+//! Built-in slash commands
+//!
+//! Mattermost's standard commands implemented on top of the channel,
+//! membership, status and post services. `/call` is handled by the
+//! integrations API; it is listed here so autocomplete knows about it.
+
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::models::{Channel, ChannelType, CommandResponse, CreatePost};
+use crate::services::guests;
+use crate::services::permissions::{Permission, Principal, Scope};
+use crate::services::posts::{create_post, create_system_message};
+use crate::services::slash_commands::{site_url, CommandContext};
+
+/// Autocomplete metadata for a built-in command
+#[derive(Debug, Clone, Copy)]
+pub struct BuiltinCommand {
+    pub trigger: &'static str,
+    pub display_name: &'static str,
+    pub description: &'static str,
+    pub hint: &'static str,
+}
+
+pub const BUILTIN_COMMANDS: &[BuiltinCommand] = &[
+    BuiltinCommand {
+        trigger: "away",
+        display_name: "Away",
+        description: "Set your status away",
+        hint: "",
+    },
+    BuiltinCommand {
+        trigger: "call",
+        display_name: "Call",
+        description: "Start a Mirotalk call",
+        hint: "[end]",
+    },
+    BuiltinCommand {
+        trigger: "dnd",
+        display_name: "Do Not Disturb",
+        description: "Do not disturb disables desktop and mobile push notifications",
+        hint: "",
+    },
+    BuiltinCommand {
+        trigger: "echo",
+        display_name: "Echo",
+        description: "Echo back text from your account",
+        hint: "[message]",
+    },
+    BuiltinCommand {
+        trigger: "header",
+        display_name: "Edit Channel Header",
+        description: "Edit the channel header",
+        hint: "[text]",
+    },
+    BuiltinCommand {
+        trigger: "invite",
+        display_name: "Invite",
+        description: "Invite a user to a channel",
+        hint: "@[username] ~[channel]",
+    },
+    BuiltinCommand {
+        trigger: "join",
+        display_name: "Join",
+        description: "Join the open channel",
+        hint: "~[channel]",
+    },
+    BuiltinCommand {
+        trigger: "kick",
+        display_name: "Kick",
+        description: "Remove a member from the channel",
+        hint: "@[username]",
+    },
+    BuiltinCommand {
+        trigger: "leave",
+        display_name: "Leave",
+        description: "Leave the current channel",
+        hint: "",
+    },
+    BuiltinCommand {
+        trigger: "me",
+        display_name: "Me",
+        description: "Do an action",
+        hint: "[message]",
+    },
+    BuiltinCommand {
+        trigger: "msg",
+        display_name: "Message",
+        description: "Send Direct Message to a user",
+        hint: "@[username] [message]",
+    },
+    BuiltinCommand {
+        trigger: "mute",
+        display_name: "Mute",
+        description: "Turns off notifications for the current channel or the [channel] specified",
+        hint: "~[channel]",
+    },
+    BuiltinCommand {
+        trigger: "offline",
+        display_name: "Offline",
+        description: "Set your status offline",
+        hint: "",
+    },
+    BuiltinCommand {
+        trigger: "online",
+        display_name: "Online",
+        description: "Set your status online",
+        hint: "",
+    },
+    BuiltinCommand {
+        trigger: "remove",
+        display_name: "Remove",
+        description: "Remove a member from the channel",
+        hint: "@[username]",
+    },
+    BuiltinCommand {
+        trigger: "search",
+        display_name: "Search",
+        description: "Search text in messages",
+        hint: "[text]",
+    },
+    BuiltinCommand {
+        trigger: "shrug",
+        display_name: "Shrug",
+        description: "Adds ¯\\_(ツ)_/¯ to your message",
+        hint: "[message]",
+    },
+];
+
+/// Markdown-escaped shrug, rendered as ¯\_(ツ)_/¯
+const SHRUG: &str = "¯\\\\\\_(ツ)\\_/¯";
+
+/// Maximum number of results listed by `/search`
+const SEARCH_RESULT_LIMIT: i64 = 10;
+
+/// Who ran a built-in command and where
+#[derive(Debug, Clone)]
+pub struct BuiltinContext {
+    pub command: CommandContext,
+    pub team_id: Uuid,
+    /// System role of the caller
+    pub role: String,
+}
+
+impl Principal for BuiltinContext {
+    fn user_id(&self) -> Uuid {
+        self.command.user_id
+    }
+
+    fn role(&self) -> &str {
+        &self.role
+    }
+}
+
+/// Check `permission` for the caller, answering with `refusal` if it is
+/// missing
+async fn require(
+    state: &AppState,
+    ctx: &BuiltinContext,
+    permission: Permission,
+    scope: Scope,
+    refusal: &str,
+) -> ApiResult<Option<CommandResponse>> {
+    match ctx.require(state, permission, scope).await {
+        Ok(()) => Ok(None),
+        Err(AppError::Forbidden(_)) => Ok(Some(CommandResponse::ephemeral(refusal))),
+        Err(e) => Err(e),
+    }
+}
+
+/// Run a built-in command, or return `None` if `trigger` is not one
+pub async fn execute_builtin(
+    state: &AppState,
+    ctx: &BuiltinContext,
+    trigger: &str,
+    args: &str,
+) -> ApiResult<Option<CommandResponse>> {
+    let response = match trigger {
+        "echo" => {
+            if args.is_empty() {
+                CommandResponse::ephemeral("A message must be provided with the /echo command.")
+            } else {
+                CommandResponse::in_channel(args)
+            }
+        }
+        "me" => {
+            if args.is_empty() {
+                CommandResponse::ephemeral("A message must be provided with the /me command.")
+            } else {
+                CommandResponse::in_channel(format!("*{}*", args))
+            }
+        }
+        "shrug" => {
+            if args.is_empty() {
+                CommandResponse::in_channel(SHRUG)
+            } else {
+                CommandResponse::in_channel(format!("{} {}", args, SHRUG))
+            }
+        }
+        "away" | "online" | "dnd" | "offline" => set_presence(state, ctx, trigger).await?,
+        "join" => join(state, ctx, args).await?,
+        "leave" => leave(state, ctx).await?,
+        "msg" => msg(state, ctx, args).await?,
+        "mute" => mute(state, ctx, args).await?,
+        "header" => header(state, ctx, args).await?,
+        "invite" => invite(state, ctx, args).await?,
+        "kick" | "remove" => kick(state, ctx, args).await?,
+        "search" => search(state, ctx, args).await?,
+        _ => return Ok(None),
+    };
+
+    Ok(Some(response))
+}
+
+async fn set_presence(
+    state: &AppState,
+    ctx: &BuiltinContext,
+    presence: &str,
+) -> ApiResult<CommandResponse> {
+    crate::api::preferences::set_user_presence(state, ctx.command.user_id, presence).await?;
+
+    let text = match presence {
+        "away" => "You are now away",
+        "dnd" => "Do Not Disturb is enabled. You will not receive desktop or mobile push notifications until Do Not Disturb is turned off.",
+        "offline" => "You are now offline",
+        _ => "You are now online",
+    };
+    Ok(CommandResponse::ephemeral(text))
+}
+
+async fn join(state: &AppState, ctx: &BuiltinContext, args: &str) -> ApiResult<CommandResponse> {
+    let Some(name) = args.split_whitespace().next() else {
+        return Ok(CommandResponse::ephemeral("Usage: /join ~channel"));
+    };
+
+    let channel = match find_team_channel(state, ctx.team_id, name).await? {
+        Some(channel) => channel,
+        None => return Ok(not_found_channel(name)),
+    };
+    let is_member = channel_role(state, channel.id, ctx.command.user_id).await?.is_some();
+
+    // Private channels stay invisible to non-members, and so does every
+    // channel a guest was not added to or of a team the caller is not on
+    if !is_member
+        && (channel.channel_type != ChannelType::Public
+            || guests::user_is_guest(&state.db, ctx.command.user_id).await?
+            || !is_team_member(state, channel.team_id, ctx.command.user_id).await?)
+    {
+        return Ok(not_found_channel(name));
+    }
+
+    if !is_member {
+        add_member(state, channel.id, ctx.command.user_id).await?;
+        let username = username(state, ctx.command.user_id).await?;
+        create_system_message(
+            state,
+            channel.id,
+            format!("@{} has joined the channel.", username),
+            None,
+        )
+        .await?;
+    }
+
+    let mut response = CommandResponse::ephemeral(String::new());
+    response.goto_location = Some(format!(
+        "{}/{}/channels/{}",
+        site_url(state).await?,
+        team_name(state, ctx.team_id).await?,
+        channel.name
+    ));
+    Ok(response)
+}
+
+async fn leave(state: &AppState, ctx: &BuiltinContext) -> ApiResult<CommandResponse> {
+    let channel = get_channel(state, ctx.command.channel_id).await?;
+    if matches!(channel.channel_type, ChannelType::Direct | ChannelType::Group) {
+        return Ok(CommandResponse::ephemeral(
+            "You can't leave a direct message channel.",
+        ));
+    }
+
+    remove_member(state, channel.id, ctx.command.user_id).await?;
+    let username = username(state, ctx.command.user_id).await?;
+    create_system_message(
+        state,
+        channel.id,
+        format!("@{} has left the channel.", username),
+        Some(serde_json::json!({ "type": "system_leave_channel", "username": username })),
+    )
+    .await?;
+
+    let mut response = CommandResponse::ephemeral(String::new());
+    response.goto_location = Some(format!(
+        "{}/{}",
+        site_url(state).await?,
+        team_name(state, channel.team_id).await?
+    ));
+    Ok(response)
+}
+
+async fn msg(state: &AppState, ctx: &BuiltinContext, args: &str) -> ApiResult<CommandResponse> {
+    let (target, message) = split_first(args);
+    if target.is_empty() {
+        return Ok(CommandResponse::ephemeral("Usage: /msg @username [message]"));
+    }
+
+    let Some((target_id, target_name)) = find_user(state, target).await? else {
+        return Ok(not_found_user(target));
+    };
+    guests::ensure_can_message(&state.db, ctx.command.user_id, &[target_id]).await?;
+
+    let dm = crate::api::v4::channels::create_direct_channel_internal(
+        state,
+        ctx.command.user_id,
+        target_id,
+    )
+    .await?;
+
+    if !message.is_empty() {
+        let input = CreatePost {
+            message: message.to_string(),
+            root_post_id: None,
+            props: None,
+            file_ids: vec![],
+        };
+        create_post(state, ctx.command.user_id, dm.id, input, None).await?;
+    }
+
+    let mut response = CommandResponse::ephemeral(String::new());
+    response.goto_location = Some(format!(
+        "{}/{}/messages/@{}",
+        site_url(state).await?,
+        team_name(state, ctx.team_id).await?,
+        target_name
+    ));
+    Ok(response)
+}
+
+async fn mute(state: &AppState, ctx: &BuiltinContext, args: &str) -> ApiResult<CommandResponse> {
+    let channel = match args.split_whitespace().next() {
+        Some(name) => match find_team_channel(state, ctx.team_id, name).await? {
+            Some(channel) => channel,
+            None => return Ok(not_found_channel(name)),
+        },
+        None => get_channel(state, ctx.command.channel_id).await?,
+    };
+
+    if channel_role(state, channel.id, ctx.command.user_id).await?.is_none() {
+        return Ok(CommandResponse::ephemeral(
+            "You must be a member of a channel to mute it.",
+        ));
+    }
+
+    let is_muted: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM channel_notification_settings
+            WHERE user_id = $1 AND channel_id = $2 AND is_muted = true
+        )
+        "#,
+    )
+    .bind(ctx.command.user_id)
+    .bind(channel.id)
+    .fetch_one(&state.db)
+    .await?;
+    let mute = !is_muted;
+
+    sqlx::query(
+        r#"
+        INSERT INTO channel_notification_settings (user_id, channel_id, notify_level, is_muted)
+        VALUES ($1, $2, 'default', $3)
+        ON CONFLICT (user_id, channel_id) DO UPDATE SET
+            is_muted = $3,
+            mute_until = NULL,
+            updated_at = NOW()
+        "#,
+    )
+    .bind(ctx.command.user_id)
+    .bind(channel.id)
+    .bind(mute)
+    .execute(&state.db)
+    .await?;
+
+    // Mattermost clients read the mute state from the member's notify props
+    sqlx::query(
+        r#"
+        UPDATE channel_members
+        SET notify_props = COALESCE(notify_props, '{}'::jsonb) || jsonb_build_object('mark_unread', $3::text)
+        WHERE channel_id = $1 AND user_id = $2
+        "#,
+    )
+    .bind(channel.id)
+    .bind(ctx.command.user_id)
+    .bind(if mute { "mention" } else { "all" })
+    .execute(&state.db)
+    .await?;
+
+    let text = if mute {
+        format!(
+            "You will not receive notifications for ~{} until channel mute is turned off.",
+            channel.name
+        )
+    } else {
+        format!("~{} is no longer muted.", channel.name)
+    };
+    Ok(CommandResponse::ephemeral(text))
+}
+
+async fn header(state: &AppState, ctx: &BuiltinContext, args: &str) -> ApiResult<CommandResponse> {
+    if args.is_empty() {
+        return Ok(CommandResponse::ephemeral("Usage: /header [text]"));
+    }
+
+    let channel = get_channel(state, ctx.command.channel_id).await?;
+    if let Some(refusal) = require(
+        state,
+        ctx,
+        Permission::ChannelManage,
+        Scope::Channel(channel.id),
+        "You don't have permission to edit the header of this channel.",
+    )
+    .await?
+    {
+        return Ok(refusal);
+    }
+
+    sqlx::query("UPDATE channels SET header = $1, updated_at = NOW() WHERE id = $2")
+        .bind(args)
+        .bind(channel.id)
+        .execute(&state.db)
+        .await?;
+
+    let username = username(state, ctx.command.user_id).await?;
+    let old_header = channel.header.unwrap_or_default();
+    let message = if old_header.is_empty() {
+        format!("@{} updated the channel header to: {}", username, args)
+    } else {
+        format!(
+            "@{} updated the channel header from: {} to: {}",
+            username, old_header, args
+        )
+    };
+    create_system_message(
+        state,
+        channel.id,
+        message,
+        Some(serde_json::json!({
+            "type": "system_header_change",
+            "username": username,
+            "old_header": old_header,
+            "new_header": args,
+        })),
+    )
+    .await?;
+
+    Ok(CommandResponse::ephemeral(String::new()))
+}
+
+async fn invite(state: &AppState, ctx: &BuiltinContext, args: &str) -> ApiResult<CommandResponse> {
+    let (target, rest) = split_first(args);
+    if target.is_empty() {
+        return Ok(CommandResponse::ephemeral("Usage: /invite @username [~channel]"));
+    }
+
+    let Some((target_id, target_name)) = find_user(state, target).await? else {
+        return Ok(not_found_user(target));
+    };
+
+    let channel = match rest.split_whitespace().next() {
+        Some(name) => match find_team_channel(state, ctx.team_id, name).await? {
+            Some(channel) => channel,
+            None => return Ok(not_found_channel(name)),
+        },
+        None => get_channel(state, ctx.command.channel_id).await?,
+    };
+
+    if matches!(channel.channel_type, ChannelType::Direct | ChannelType::Group) {
+        return Ok(CommandResponse::ephemeral(
+            "You can't add someone to a direct message channel.",
+        ));
+    }
+    if channel_role(state, channel.id, ctx.command.user_id).await?.is_none() {
+        return Ok(not_found_channel(&channel.name));
+    }
+    if let Some(refusal) = require(
+        state,
+        ctx,
+        Permission::ChannelManageMembers,
+        Scope::Channel(channel.id),
+        "You don't have permission to add members to this channel.",
+    )
+    .await?
+    {
+        return Ok(refusal);
+    }
+    if channel_role(state, channel.id, target_id).await?.is_some() {
+        return Ok(CommandResponse::ephemeral(format!(
+            "@{} is already in the channel.",
+            target_name
+        )));
+    }
+
+    if !is_team_member(state, channel.team_id, target_id).await? {
+        return Ok(CommandResponse::ephemeral(format!(
+            "@{} isn't a member of the team.",
+            target_name
+        )));
+    }
+
+    add_member(state, channel.id, target_id).await?;
+    let username = username(state, ctx.command.user_id).await?;
+    create_system_message(
+        state,
+        channel.id,
+        format!("@{} added to the channel by @{}.", target_name, username),
+        Some(serde_json::json!({
+            "type": "system_add_to_channel",
+            "username": username,
+            "addedUsername": target_name,
+        })),
+    )
+    .await?;
+
+    Ok(CommandResponse::ephemeral(format!(
+        "@{} added to ~{}.",
+        target_name, channel.name
+    )))
+}
+
+async fn kick(state: &AppState, ctx: &BuiltinContext, args: &str) -> ApiResult<CommandResponse> {
+    let (target, _) = split_first(args);
+    if target.is_empty() {
+        return Ok(CommandResponse::ephemeral("Usage: /kick @username"));
+    }
+
+    let Some((target_id, target_name)) = find_user(state, target).await? else {
+        return Ok(not_found_user(target));
+    };
+
+    let channel = get_channel(state, ctx.command.channel_id).await?;
+    if matches!(channel.channel_type, ChannelType::Direct | ChannelType::Group) {
+        return Ok(CommandResponse::ephemeral(
+            "You can't remove someone from a direct message channel.",
+        ));
+    }
+
+    if target_id != ctx.command.user_id {
+        if let Some(refusal) = require(
+            state,
+            ctx,
+            Permission::ChannelManageMembers,
+            Scope::Channel(channel.id),
+            "You don't have permission to remove members from this channel.",
+        )
+        .await?
+        {
+            return Ok(refusal);
+        }
+    }
+
+    if channel_role(state, channel.id, target_id).await?.is_none() {
+        return Ok(CommandResponse::ephemeral(format!(
+            "@{} is not a member of this channel.",
+            target_name
+        )));
+    }
+
+    remove_member(state, channel.id, target_id).await?;
+    create_system_message(
+        state,
+        channel.id,
+        format!("@{} was removed from the channel.", target_name),
+        Some(serde_json::json!({
+            "type": "system_remove_from_channel",
+            "removedUsername": target_name,
+        })),
+    )
+    .await?;
+
+    Ok(CommandResponse::ephemeral(String::new()))
+}
+
+async fn search(state: &AppState, ctx: &BuiltinContext, args: &str) -> ApiResult<CommandResponse> {
+    if args.is_empty() {
+        return Ok(CommandResponse::ephemeral("Usage: /search [text]"));
+    }
+
+    let results: Vec<(String, String, String)> = sqlx::query_as(
+        r#"
+        SELECT u.username, c.name, p.message
+        FROM posts p
+        INNER JOIN channel_members cm ON cm.channel_id = p.channel_id AND cm.user_id = $1
+        INNER JOIN channels c ON c.id = p.channel_id
+        INNER JOIN users u ON u.id = p.user_id
+        WHERE p.deleted_at IS NULL
+          AND c.team_id = $2
+          AND to_tsvector('english', p.message) @@ plainto_tsquery('english', $3)
+        ORDER BY ts_rank(to_tsvector('english', p.message), plainto_tsquery('english', $3)) DESC, p.created_at DESC
+        LIMIT $4
+        "#,
+    )
+    .bind(ctx.command.user_id)
+    .bind(ctx.team_id)
+    .bind(args)
+    .bind(SEARCH_RESULT_LIMIT)
+    .fetch_all(&state.db)
+    .await?;
+
+    if results.is_empty() {
+        return Ok(CommandResponse::ephemeral(format!(
+            "No results found for \"{}\".",
+            args
+        )));
+    }
+
+    let mut text = format!("Search results for \"{}\":", args);
+    for (username, channel_name, message) in results {
+        let mut snippet: String = message.chars().take(100).collect();
+        if snippet.len() < message.len() {
+            snippet.push('…');
+        }
+        text.push_str(&format!(
+            "\n- @{} in ~{}: {}",
+            username,
+            channel_name,
+            snippet.replace('\n', " ")
+        ));
+    }
+    Ok(CommandResponse::ephemeral(text))
+}
+
+/// Split off the first whitespace-separated token
+fn split_first(args: &str) -> (&str, &str) {
+    match args.split_once(char::is_whitespace) {
+        Some((first, rest)) => (first, rest.trim()),
+        None => (args, ""),
+    }
+}
+
+fn not_found_channel(name: &str) -> CommandResponse {
+    CommandResponse::ephemeral(format!(
+        "Could not find the channel {}.",
+        name.trim_start_matches('~')
+    ))
+}
+
+fn not_found_user(name: &str) -> CommandResponse {
+    CommandResponse::ephemeral(format!(
+        "We couldn't find the user {}.",
+        name.trim_start_matches('@')
+    ))
+}
+
+async fn find_user(state: &AppState, name: &str) -> ApiResult<Option<(Uuid, String)>> {
+    let user = sqlx::query_as(
+        "SELECT id, username FROM users WHERE LOWER(username) = LOWER($1) AND is_active = true",
+    )
+    .bind(name.trim_start_matches('@'))
+    .fetch_optional(&state.db)
+    .await?;
+
+    Ok(user)
+}
+
+async fn find_team_channel(
+    state: &AppState,
+    team_id: Uuid,
+    name: &str,
+) -> ApiResult<Option<Channel>> {
+    let channel = sqlx::query_as(
+        "SELECT * FROM channels WHERE team_id = $1 AND name = $2 AND is_archived = false",
+    )
+    .bind(team_id)
+    .bind(name.trim_start_matches('~').to_lowercase())
+    .fetch_optional(&state.db)
+    .await?;
+
+    Ok(channel)
+}
+
+async fn get_channel(state: &AppState, channel_id: Uuid) -> ApiResult<Channel> {
+    let channel = sqlx::query_as("SELECT * FROM channels WHERE id = $1")
+        .bind(channel_id)
+        .fetch_one(&state.db)
+        .await?;
+
+    Ok(channel)
+}
+
+async fn channel_role(state: &AppState, channel_id: Uuid, user_id: Uuid) -> ApiResult<Option<String>> {
+    let role = sqlx::query_scalar(
+        "SELECT role FROM channel_members WHERE channel_id = $1 AND user_id = $2",
+    )
+    .bind(channel_id)
+    .bind(user_id)
+    .fetch_optional(&state.db)
+    .await?;
+
+    Ok(role)
+}
+
+async fn is_team_member(state: &AppState, team_id: Uuid, user_id: Uuid) -> ApiResult<bool> {
+    let is_member = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM team_members WHERE team_id = $1 AND user_id = $2)",
+    )
+    .bind(team_id)
+    .bind(user_id)
+    .fetch_one(&state.db)
+    .await?;
+
+    Ok(is_member)
+}
+
+async fn add_member(state: &AppState, channel_id: Uuid, user_id: Uuid) -> ApiResult<()> {
+    sqlx::query(
+        "INSERT INTO channel_members (channel_id, user_id, role) VALUES ($1, $2, 'member') ON CONFLICT DO NOTHING",
+    )
+    .bind(channel_id)
+    .bind(user_id)
+    .execute(&state.db)
+    .await?;
+
+    Ok(())
+}
+
+async fn remove_member(state: &AppState, channel_id: Uuid, user_id: Uuid) -> ApiResult<()> {
+    sqlx::query("DELETE FROM channel_members WHERE channel_id = $1 AND user_id = $2")
+        .bind(channel_id)
+        .bind(user_id)
+        .execute(&state.db)
+        .await?;
+
+    Ok(())
+}
+
+async fn username(state: &AppState, user_id: Uuid) -> ApiResult<String> {
+    let username = sqlx::query_scalar("SELECT username FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&state.db)
+        .await?;
+
+    Ok(username)
+}
+
+async fn team_name(state: &AppState, team_id: Uuid) -> ApiResult<String> {
+    let name = sqlx::query_scalar("SELECT name FROM teams WHERE id = $1")
+        .bind(team_id)
+        .fetch_one(&state.db)
+        .await?;
+
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_first_separates_target_from_message() {
+        assert_eq!(("@bob", "hello there"), split_first("@bob hello there"));
+        assert_eq!(("@bob", ""), split_first("@bob"));
+        assert_eq!(("", ""), split_first(""));
+    }
+
+    #[test]
+    fn builtin_triggers_are_unique_and_sorted() {
+        let triggers: Vec<&str> = BUILTIN_COMMANDS.iter().map(|c| c.trigger).collect();
+        let mut sorted = triggers.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted, triggers);
+    }
+}
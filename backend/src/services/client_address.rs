@@ -0,0 +1,213 @@
+//! Client addresses
+//!
+//! A request's address is the peer of its connection. Behind a reverse proxy
+//! that is the proxy, so when the peer is one of the configured trusted
+//! proxies the client is read from `X-Forwarded-For` instead: the right-most
+//! entry that is not itself a trusted proxy, or `X-Real-IP` when there is no
+//! such header. Any client can set those headers, so they are ignored when
+//! the peer is not trusted.
+
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+
+use crate::api::AppState;
+use crate::auth::middleware::FromRef;
+
+/// Addresses and CIDR ranges of the reverse proxies in front of the server
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    /// Parse a comma-separated list such as `10.0.0.0/8, ::1`
+    pub fn parse(list: &str) -> anyhow::Result<Self> {
+        let mut networks = Vec::new();
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (address, prefix) = match entry.split_once('/') {
+                Some((address, prefix)) => (address, Some(prefix)),
+                None => (entry, None),
+            };
+            let address: IpAddr = address
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid trusted proxy address: {}", entry))?;
+            let max_prefix = if address.is_ipv4() { 32 } else { 128 };
+            let prefix = match prefix {
+                Some(prefix) => prefix
+                    .parse::<u8>()
+                    .ok()
+                    .filter(|p| *p <= max_prefix)
+                    .ok_or_else(|| anyhow::anyhow!("Invalid trusted proxy range: {}", entry))?,
+                None => max_prefix,
+            };
+            networks.push((address, prefix));
+        }
+
+        Ok(Self { networks })
+    }
+
+    /// Whether `ip` is one of the trusted proxies
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.networks.iter().any(|(network, prefix)| match (network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - *prefix as u32).unwrap_or(0);
+                u32::from(*network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - *prefix as u32).unwrap_or(0);
+                u128::from(*network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        })
+    }
+
+    /// The client behind a request that arrived from `peer`
+    pub fn client_ip(&self, peer: Option<IpAddr>, headers: &HeaderMap) -> Option<IpAddr> {
+        let peer = peer?.to_canonical();
+        if !self.contains(peer) {
+            return Some(peer);
+        }
+
+        let header = |name: &str| {
+            headers
+                .get_all(name)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(','))
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+                .collect::<Vec<_>>()
+        };
+        let forwarded = header("x-forwarded-for");
+        if !forwarded.is_empty() {
+            // Each proxy appends the address it received the request from, so
+            // only the entries added by trusted proxies can be believed
+            let mut client = peer;
+            for entry in forwarded.iter().rev() {
+                match entry.parse::<IpAddr>() {
+                    Ok(ip) => {
+                        client = ip.to_canonical();
+                        if !self.contains(client) {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+            return Some(client);
+        }
+
+        header("x-real-ip")
+            .first()
+            .and_then(|v| v.parse::<IpAddr>().ok())
+            .map(|ip| ip.to_canonical())
+            .or(Some(peer))
+    }
+}
+
+/// Extractor for the client address of a request
+///
+/// `None` only when the server was not started with connection info.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let state = AppState::from_ref(state);
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip());
+
+        Ok(Self(state.trusted_proxies.client_ip(peer, &parts.headers)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn parses_addresses_and_ranges() {
+        let proxies = TrustedProxies::parse(" 10.0.0.0/8, 192.168.1.5 ,fd00::/8").unwrap();
+        assert!(proxies.contains("10.20.30.40".parse().unwrap()));
+        assert!(proxies.contains("192.168.1.5".parse().unwrap()));
+        assert!(!proxies.contains("192.168.1.6".parse().unwrap()));
+        assert!(proxies.contains("fd12::1".parse().unwrap()));
+        assert!(proxies.contains("::ffff:10.1.1.1".parse().unwrap()));
+        assert!(!proxies.contains("2001:db8::1".parse().unwrap()));
+
+        assert!(TrustedProxies::parse("").unwrap().networks.is_empty());
+        assert!(TrustedProxies::parse("0.0.0.0/0").is_ok());
+        assert!(TrustedProxies::parse("10.0.0.0/33").is_err());
+        assert!(TrustedProxies::parse("proxy.internal").is_err());
+    }
+
+    #[test]
+    fn headers_from_untrusted_peers_are_ignored() {
+        let spoofed = headers(&[
+            ("x-forwarded-for", "203.0.113.7"),
+            ("x-real-ip", "203.0.113.8"),
+        ]);
+        let none = TrustedProxies::default();
+        assert_eq!(ip("198.51.100.1"), none.client_ip(ip("198.51.100.1"), &spoofed));
+        assert_eq!(None, none.client_ip(None, &spoofed));
+
+        let proxies = TrustedProxies::parse("10.0.0.0/8").unwrap();
+        assert_eq!(
+            ip("198.51.100.1"),
+            proxies.client_ip(ip("198.51.100.1"), &spoofed)
+        );
+    }
+
+    #[test]
+    fn trusted_proxies_name_the_client() {
+        let proxies = TrustedProxies::parse("10.0.0.0/8").unwrap();
+        let peer = ip("10.0.0.1");
+
+        // The left-most entry is whatever the client sent
+        let forwarded = headers(&[("x-forwarded-for", "1.2.3.4, 203.0.113.7, 10.0.0.2")]);
+        assert_eq!(ip("203.0.113.7"), proxies.client_ip(peer, &forwarded));
+
+        let forwarded = headers(&[
+            ("x-forwarded-for", "1.2.3.4"),
+            ("x-forwarded-for", "203.0.113.7"),
+        ]);
+        assert_eq!(ip("203.0.113.7"), proxies.client_ip(peer, &forwarded));
+
+        let forwarded = headers(&[("x-forwarded-for", "10.0.0.3, 10.0.0.2")]);
+        assert_eq!(ip("10.0.0.3"), proxies.client_ip(peer, &forwarded));
+
+        let forwarded = headers(&[("x-forwarded-for", "garbage, 10.0.0.2")]);
+        assert_eq!(ip("10.0.0.2"), proxies.client_ip(peer, &forwarded));
+
+        let real_ip = headers(&[("x-real-ip", "203.0.113.9")]);
+        assert_eq!(ip("203.0.113.9"), proxies.client_ip(peer, &real_ip));
+
+        assert_eq!(peer, proxies.client_ip(peer, &HeaderMap::new()));
+    }
+}
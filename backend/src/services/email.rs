@@ -1,60 +1,189 @@
 //! Email service using lettre
 //!
-//! Handles sending emails via SMTP based on server configuration.
+//! Handles sending emails via SMTP based on server configuration. The SMTP
+//! password is stored encrypted with the server's encryption key and is only
+//! decrypted to build the transport. The transport keeps a pool of open
+//! connections and is reused until the SMTP settings change.
+
+use std::time::Duration;
 
 use lettre::{
-    transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport, Message,
-    Tokio1Executor,
+    message::{MessageBuilder, MultiPart},
+    transport::smtp::{authentication::Credentials, PoolConfig},
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
 };
+use tokio::sync::Mutex;
 use tracing::{error, info};
 
-use crate::models::server_config::EmailConfig;
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::models::server_config::{EmailConfig, SmtpSecurity};
+
+/// Maximum time to wait on the SMTP server
+const SMTP_TIMEOUT_SECS: u64 = 10;
+
+/// Maximum number of open SMTP connections
+const SMTP_POOL_MAX_SIZE: u32 = 4;
+
+type Transport = AsyncSmtpTransport<Tokio1Executor>;
+
+/// Settings a transport was built from
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TransportKey {
+    host: String,
+    port: i32,
+    username: String,
+    password_encrypted: String,
+    security: SmtpSecurity,
+}
+
+impl From<&EmailConfig> for TransportKey {
+    fn from(config: &EmailConfig) -> Self {
+        Self {
+            host: config.smtp_host.clone(),
+            port: config.smtp_port,
+            username: config.smtp_username.clone(),
+            password_encrypted: config.smtp_password_encrypted.clone(),
+            security: config.security(),
+        }
+    }
+}
+
+/// Pooled SMTP transport shared by everything that sends email
+#[derive(Default)]
+pub struct Mailer {
+    current: Mutex<Option<(TransportKey, Transport)>>,
+}
+
+impl Mailer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Transport for `config`, rebuilt only when the settings changed
+    async fn transport(&self, config: &EmailConfig, encryption_key: &str) -> ApiResult<Transport> {
+        let key = TransportKey::from(config);
+        let mut current = self.current.lock().await;
+        if let Some((current_key, transport)) = current.as_ref() {
+            if *current_key == key {
+                return Ok(transport.clone());
+            }
+        }
+
+        let transport = build_transport(config, encryption_key)?;
+        *current = Some((key, transport.clone()));
+        Ok(transport)
+    }
+}
+
+fn build_transport(config: &EmailConfig, encryption_key: &str) -> ApiResult<Transport> {
+    if config.smtp_host.is_empty() {
+        return Err(AppError::Config("SMTP host not configured".to_string()));
+    }
+    let port = u16::try_from(config.smtp_port)
+        .map_err(|_| AppError::Config(format!("Invalid SMTP port: {}", config.smtp_port)))?;
+
+    let builder = match config.security() {
+        SmtpSecurity::Tls => Transport::relay(&config.smtp_host),
+        SmtpSecurity::Starttls => Transport::starttls_relay(&config.smtp_host),
+        SmtpSecurity::None => Ok(Transport::builder_dangerous(&config.smtp_host)),
+    }
+    .map_err(|e| AppError::Config(format!("Failed to create transport: {}", e)))?;
+
+    let builder = if config.smtp_username.is_empty() {
+        builder
+    } else {
+        let password = if config.smtp_password_encrypted.is_empty() {
+            String::new()
+        } else {
+            crate::crypto::decrypt(&config.smtp_password_encrypted, encryption_key).map_err(
+                |_| {
+                    AppError::Config(
+                        "SMTP password could not be decrypted, please save it again".to_string(),
+                    )
+                },
+            )?
+        };
+        builder.credentials(Credentials::new(config.smtp_username.clone(), password))
+    };
+
+    Ok(builder
+        .port(port)
+        .timeout(Some(Duration::from_secs(SMTP_TIMEOUT_SECS)))
+        .pool_config(PoolConfig::new().max_size(SMTP_POOL_MAX_SIZE))
+        .build())
+}
 
 /// Send an email using the provided configuration
 pub async fn send_email(
+    state: &AppState,
     config: &EmailConfig,
     to_address: &str,
     subject: &str,
     body: &str,
-) -> Result<(), String> {
-    if config.smtp_host.is_empty() {
-        return Err("SMTP host not configured".to_string());
-    }
+) -> ApiResult<()> {
+    let email = message_builder(config, to_address, subject)?
+        .body(body.to_string())
+        .map_err(|e| AppError::Internal(format!("Failed to build email: {}", e)))?;
+
+    deliver(state, config, to_address, email).await
+}
+
+/// Send an HTML email with a plain text alternative
+pub async fn send_html_email(
+    state: &AppState,
+    config: &EmailConfig,
+    to_address: &str,
+    subject: &str,
+    html: &str,
+    text: &str,
+) -> ApiResult<()> {
+    let email = message_builder(config, to_address, subject)?
+        .multipart(MultiPart::alternative_plain_html(
+            text.to_string(),
+            html.to_string(),
+        ))
+        .map_err(|e| AppError::Internal(format!("Failed to build email: {}", e)))?;
 
-    let email = Message::builder()
+    deliver(state, config, to_address, email).await
+}
+
+fn message_builder(
+    config: &EmailConfig,
+    to_address: &str,
+    subject: &str,
+) -> ApiResult<MessageBuilder> {
+    Ok(Message::builder()
         .from(
             format!("{} <{}>", config.from_name, config.from_address)
                 .parse()
-                .map_err(|e| format!("Invalid from address: {}", e))?,
+                .map_err(|e| AppError::Config(format!("Invalid from address: {}", e)))?,
         )
         .to(to_address
             .parse()
-            .map_err(|e| format!("Invalid to address: {}", e))?)
-        .subject(subject)
-        .body(body.to_string())
-        .map_err(|e| format!("Failed to build email: {}", e))?;
-
-    let creds = Credentials::new(
-        config.smtp_username.clone(),
-        config.smtp_password_encrypted.clone(), // In a real app, decrypt this first
-    );
-
-    let mailer: AsyncSmtpTransport<Tokio1Executor> =
-        AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host)
-            .map_err(|e| format!("Failed to create transport: {}", e))?
-            .credentials(creds)
-            .port(config.smtp_port as u16)
-            .build();
-
-    // Use Result here to capture sending error
-    match mailer.send(email).await {
+            .map_err(|e| AppError::BadRequest(format!("Invalid to address: {}", e)))?)
+        .subject(subject))
+}
+
+async fn deliver(
+    state: &AppState,
+    config: &EmailConfig,
+    to_address: &str,
+    email: Message,
+) -> ApiResult<()> {
+    let transport = state
+        .mailer
+        .transport(config, &state.encryption_key)
+        .await?;
+
+    match transport.send(email).await {
         Ok(_) => {
             info!("Email sent to {}", to_address);
             Ok(())
         }
         Err(e) => {
             error!("Failed to send email to {}: {}", to_address, e);
-            Err(format!("Failed to send email: {}", e))
+            Err(AppError::ExternalService(format!("Failed to send email: {}", e)))
         }
     }
 }
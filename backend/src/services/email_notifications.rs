@@ -0,0 +1,193 @@
+//! Email notifications for missed mentions and direct messages
+//!
+//! When a post mentions a member who is not active in a client, or is a
+//! direct message to them, it is queued in `email_notifications`. The email
+//! notification job later sends each user's queued posts as one email, either
+//! right away or batched according to their `email_interval` preference.
+
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::error::ApiResult;
+use crate::models::PostResponse;
+use crate::services::push_notifications::{mentions_channel, mentions_user};
+
+/// Mattermost's "immediately" email interval, in seconds
+pub const EMAIL_INTERVAL_IMMEDIATE_SECS: i64 = 30;
+
+/// Batching interval used when a user has not chosen one
+pub const DEFAULT_EMAIL_INTERVAL_SECS: i64 = EMAIL_INTERVAL_IMMEDIATE_SECS;
+
+/// Channel member who may be emailed about a post
+#[derive(Debug, sqlx::FromRow)]
+struct Recipient {
+    user_id: Uuid,
+    username: String,
+    user_email: String,
+    channel_email: Option<String>,
+    presence: String,
+    is_muted: bool,
+    mention_keywords: Vec<String>,
+    email_interval: Option<String>,
+}
+
+/// Parse the `notifications`/`email_interval` preference into seconds
+///
+/// Returns `None` when the user turned email notifications off.
+pub fn email_interval_secs(value: Option<&str>) -> Option<i64> {
+    match value.map(str::trim) {
+        None | Some("") => Some(DEFAULT_EMAIL_INTERVAL_SECS),
+        Some(v) => match v.parse::<i64>() {
+            Ok(0) => None,
+            Ok(secs) if secs > 0 => Some(secs),
+            _ => Some(DEFAULT_EMAIL_INTERVAL_SECS),
+        },
+    }
+}
+
+/// Queue email notifications for a new post in the background
+pub fn spawn_queue_notifications(state: &AppState, post: &PostResponse) {
+    if is_system_post(post) {
+        return;
+    }
+
+    let state = state.clone();
+    let post = post.clone();
+    tokio::spawn(async move {
+        if let Err(e) = queue_notifications(&state, &post).await {
+            warn!("Queueing email notifications for post {} failed: {}", post.id, e);
+        }
+    });
+}
+
+/// Queue the post for every member who should be emailed about it
+///
+/// Returns the number of users the post was queued for.
+pub async fn queue_notifications(state: &AppState, post: &PostResponse) -> ApiResult<u64> {
+    if is_system_post(post) {
+        return Ok(0);
+    }
+
+    let channel_type: String = sqlx::query_scalar("SELECT type::text FROM channels WHERE id = $1")
+        .bind(post.channel_id)
+        .fetch_one(&state.db)
+        .await?;
+    let is_dm = channel_type == "direct";
+    let mentions_channel = mentions_channel(&post.message);
+
+    let recipients: Vec<Recipient> = sqlx::query_as(
+        r#"
+        SELECT cm.user_id, u.username, u.presence,
+               COALESCE(up.notify_email, 'mention') AS user_email,
+               cm.notify_props->>'email' AS channel_email,
+               (COALESCE(cns.is_muted, false)
+                    AND (cns.mute_until IS NULL OR cns.mute_until > NOW()))
+                   OR COALESCE(cm.notify_props->>'mark_unread', '') = 'mention' AS is_muted,
+               COALESCE(up.mention_keywords, '{}') AS mention_keywords,
+               mp.value AS email_interval
+        FROM channel_members cm
+        JOIN users u ON u.id = cm.user_id
+        LEFT JOIN user_preferences up ON up.user_id = cm.user_id
+        LEFT JOIN channel_notification_settings cns
+               ON cns.user_id = cm.user_id AND cns.channel_id = cm.channel_id
+        LEFT JOIN mattermost_preferences mp
+               ON mp.user_id = cm.user_id AND mp.category = 'notifications'
+              AND mp.name = 'email_interval'
+        WHERE cm.channel_id = $1
+          AND cm.user_id <> $2
+          AND u.is_active = true
+          AND u.is_bot = false
+          AND u.email <> ''
+        "#,
+    )
+    .bind(post.channel_id)
+    .bind(post.user_id)
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut queued = 0;
+    for recipient in recipients {
+        if state.ws_hub.user_connection_count(recipient.user_id).await > 0
+            && !matches!(recipient.presence.as_str(), "away" | "offline")
+        {
+            continue;
+        }
+
+        let Some(reason) = email_reason(&recipient, post, is_dm, mentions_channel) else {
+            continue;
+        };
+
+        let result = sqlx::query(
+            r#"
+            INSERT INTO email_notifications (user_id, post_id, channel_id, reason)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, post_id) DO NOTHING
+            "#,
+        )
+        .bind(recipient.user_id)
+        .bind(post.id)
+        .bind(post.channel_id)
+        .bind(reason)
+        .execute(&state.db)
+        .await?;
+        queued += result.rows_affected();
+    }
+
+    Ok(queued)
+}
+
+/// Decide whether `recipient` should be emailed about the post
+///
+/// The channel's `email` notify prop wins over the user's preference.
+fn email_reason(
+    recipient: &Recipient,
+    post: &PostResponse,
+    is_dm: bool,
+    mentions_channel: bool,
+) -> Option<&'static str> {
+    if recipient.is_muted || email_interval_secs(recipient.email_interval.as_deref()).is_none() {
+        return None;
+    }
+
+    let enabled = match recipient.channel_email.as_deref() {
+        Some("true" | "all" | "mention") => true,
+        Some("false" | "none") => false,
+        _ => recipient.user_email != "none",
+    };
+    if !enabled {
+        return None;
+    }
+
+    if is_dm {
+        Some("direct")
+    } else if mentions_channel
+        || mentions_user(&post.message, &recipient.username, &recipient.mention_keywords)
+    {
+        Some("mention")
+    } else {
+        None
+    }
+}
+
+fn is_system_post(post: &PostResponse) -> bool {
+    post.props
+        .get("type")
+        .and_then(|v| v.as_str())
+        .is_some_and(|t| t.starts_with("system_"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_email_interval() {
+        assert_eq!(Some(DEFAULT_EMAIL_INTERVAL_SECS), email_interval_secs(None));
+        assert_eq!(Some(30), email_interval_secs(Some("30")));
+        assert_eq!(Some(900), email_interval_secs(Some("900")));
+        assert_eq!(Some(3600), email_interval_secs(Some("3600")));
+        assert_eq!(None, email_interval_secs(Some("0")));
+        assert_eq!(Some(DEFAULT_EMAIL_INTERVAL_SECS), email_interval_secs(Some("soon")));
+    }
+}
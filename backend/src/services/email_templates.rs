@@ -0,0 +1,319 @@
+//! HTML email templates
+//!
+//! All emails share one layout: the site name as a header, the content, and
+//! a footer linking back to the site. Values interpolated into the HTML are
+//! escaped here, so callers pass plain text.
+
+use chrono::{DateTime, Utc};
+
+use crate::models::{EmailConfig, SiteConfig};
+
+/// Site details shown in every email
+#[derive(Debug, Clone)]
+pub struct Branding {
+    pub site_name: String,
+    pub site_url: String,
+}
+
+impl Branding {
+    pub fn new(site: &SiteConfig, email: &EmailConfig) -> Self {
+        let site_name = if site.site_name.trim().is_empty() {
+            email.from_name.clone()
+        } else {
+            site.site_name.clone()
+        };
+
+        Self {
+            site_name,
+            site_url: site.site_url.trim().trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+/// A rendered email with its plain text alternative
+#[derive(Debug, Clone)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// One post listed in a notification email
+#[derive(Debug, Clone)]
+pub struct NotificationPost {
+    pub sender: String,
+    /// Channel display name, or `None` for direct messages
+    pub channel: Option<String>,
+    pub message: String,
+    pub created_at: DateTime<Utc>,
+    pub permalink: String,
+}
+
+/// Escape text for use in HTML content and attribute values
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Wrap already-escaped `content` in the common layout
+pub fn layout(branding: &Branding, title: &str, content: &str) -> String {
+    let site_link = if branding.site_url.is_empty() {
+        escape(&branding.site_name)
+    } else {
+        format!(
+            r#"<a href="{}" style="color:#1c58d9;text-decoration:none">{}</a>"#,
+            escape(&branding.site_url),
+            escape(&branding.site_name)
+        )
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+</head>
+<body style="margin:0;padding:0;background:#f4f4f6;font-family:-apple-system,BlinkMacSystemFont,'Segoe UI',Roboto,Helvetica,Arial,sans-serif;color:#3d3c40">
+<table role="presentation" width="100%" cellpadding="0" cellspacing="0" style="padding:24px 0">
+<tr><td align="center">
+<table role="presentation" width="600" cellpadding="0" cellspacing="0" style="max-width:600px;background:#ffffff;border-radius:8px;padding:32px">
+<tr><td style="font-size:20px;font-weight:600;padding-bottom:24px">{site_link}</td></tr>
+<tr><td style="font-size:18px;font-weight:600;padding-bottom:16px">{title}</td></tr>
+<tr><td style="font-size:14px;line-height:1.5">{content}</td></tr>
+<tr><td style="font-size:12px;color:#8d8c90;padding-top:32px">You are receiving this email because of your notification settings on {site_name}.</td></tr>
+</table>
+</td></tr>
+</table>
+</body>
+</html>
+"#,
+        title = escape(title),
+        site_link = site_link,
+        content = content,
+        site_name = escape(&branding.site_name),
+    )
+}
+
+/// Email sent from the admin console to check the SMTP settings
+pub fn test_email(branding: &Branding) -> RenderedEmail {
+    let text = format!(
+        "This is a test email from {}. Your email settings are working.",
+        branding.site_name
+    );
+
+    RenderedEmail {
+        subject: format!("[{}] Test email", branding.site_name),
+        html: layout(
+            branding,
+            "Test email",
+            &format!("<p>{}</p>", escape(&text)),
+        ),
+        text,
+    }
+}
+
+/// Email with a link to choose a new password
+pub fn password_reset(branding: &Branding, link: &str, expires_in_hours: i64) -> RenderedEmail {
+    let intro = format!(
+        "Someone asked to reset the password for your {} account.",
+        branding.site_name
+    );
+    let note = format!(
+        "The link expires in {} hours. If you did not ask for this, you can ignore this email.",
+        expires_in_hours
+    );
+
+    RenderedEmail {
+        subject: format!("[{}] Reset your password", branding.site_name),
+        html: layout(
+            branding,
+            "Reset your password",
+            &action_html(&intro, "Reset password", link, &note),
+        ),
+        text: format!("{}\n\nReset your password: {}\n\n{}\n", intro, link, note),
+    }
+}
+
+/// Email with a link to confirm the address belongs to the user
+pub fn email_verification(branding: &Branding, link: &str, expires_in_hours: i64) -> RenderedEmail {
+    let intro = format!(
+        "Please confirm this email address for your {} account.",
+        branding.site_name
+    );
+    let note = format!("The link expires in {} hours.", expires_in_hours);
+
+    RenderedEmail {
+        subject: format!("[{}] Verify your email address", branding.site_name),
+        html: layout(
+            branding,
+            "Verify your email address",
+            &action_html(&intro, "Verify email", link, &note),
+        ),
+        text: format!("{}\n\nVerify your email: {}\n\n{}\n", intro, link, note),
+    }
+}
+
+/// Email inviting someone to join a team
+pub fn team_invite(
+    branding: &Branding,
+    inviter: &str,
+    team_name: &str,
+    link: &str,
+    expires_in_hours: i64,
+) -> RenderedEmail {
+    let intro = format!(
+        "{} invited you to join the {} team on {}.",
+        inviter, team_name, branding.site_name
+    );
+    let note = format!(
+        "The invitation expires in {} hours. If you do not have an account yet, the link lets you create one.",
+        expires_in_hours
+    );
+
+    RenderedEmail {
+        subject: format!(
+            "[{}] {} invited you to join {}",
+            branding.site_name, inviter, team_name
+        ),
+        html: layout(
+            branding,
+            &format!("Join {}", team_name),
+            &action_html(&intro, "Join team", link, &note),
+        ),
+        text: format!("{}\n\nJoin the team: {}\n\n{}\n", intro, link, note),
+    }
+}
+
+/// A paragraph, a call-to-action button and a footnote
+fn action_html(intro: &str, label: &str, link: &str, note: &str) -> String {
+    format!(
+        r#"<p>{}</p>
+<p style="margin:24px 0"><a href="{}" style="background:#1c58d9;color:#ffffff;padding:10px 20px;border-radius:4px;text-decoration:none;font-weight:600">{}</a></p>
+<p style="font-size:12px;color:#8d8c90">{}</p>
+"#,
+        escape(intro),
+        escape(link),
+        escape(label),
+        escape(note),
+    )
+}
+
+/// Email listing missed mentions and direct messages
+pub fn notification_digest(branding: &Branding, posts: &[NotificationPost]) -> RenderedEmail {
+    let (subject, title) = match posts {
+        [post] if post.channel.is_none() => (
+            format!("[{}] New direct message from @{}", branding.site_name, post.sender),
+            format!("@{} sent you a message", post.sender),
+        ),
+        [post] => (
+            format!(
+                "[{}] Notification in {}",
+                branding.site_name,
+                post.channel.as_deref().unwrap_or_default()
+            ),
+            format!("@{} mentioned you", post.sender),
+        ),
+        _ => (
+            format!("[{}] You have {} new notifications", branding.site_name, posts.len()),
+            format!("You have {} new notifications", posts.len()),
+        ),
+    };
+
+    let mut html = String::new();
+    let mut text = format!("{}\n\n", title);
+    for post in posts {
+        let context = match &post.channel {
+            Some(channel) => format!("@{} in {}", post.sender, channel),
+            None => format!("@{} (direct message)", post.sender),
+        };
+        let time = post.created_at.format("%b %-d, %H:%M UTC").to_string();
+
+        html.push_str(&format!(
+            r#"<div style="border-left:3px solid #1c58d9;padding:8px 12px;margin-bottom:16px">
+<div style="font-weight:600">{}</div>
+<div style="font-size:12px;color:#8d8c90">{}</div>
+<div style="margin:8px 0">{}</div>
+<a href="{}" style="font-size:12px;color:#1c58d9">View message</a>
+</div>
+"#,
+            escape(&context),
+            escape(&time),
+            escape(&post.message).replace('\n', "<br>"),
+            escape(&post.permalink),
+        ));
+
+        text.push_str(&format!(
+            "{} - {}\n{}\n{}\n\n",
+            context, time, post.message, post.permalink
+        ));
+    }
+
+    RenderedEmail {
+        subject,
+        html: layout(branding, &title, &html),
+        text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn branding() -> Branding {
+        Branding {
+            site_name: "RustChat".to_string(),
+            site_url: "https://chat.example.com".to_string(),
+        }
+    }
+
+    fn post(channel: Option<&str>, message: &str) -> NotificationPost {
+        NotificationPost {
+            sender: "alice".to_string(),
+            channel: channel.map(str::to_string),
+            message: message.to_string(),
+            created_at: Utc::now(),
+            permalink: "https://chat.example.com/team/pl/abc".to_string(),
+        }
+    }
+
+    #[test]
+    fn escapes_message_content() {
+        let email = notification_digest(&branding(), &[post(Some("Town Square"), "<b>hi</b> & bye")]);
+        assert!(email.html.contains("&lt;b&gt;hi&lt;/b&gt; &amp; bye"));
+        assert!(!email.html.contains("<b>hi</b>"));
+        assert!(email.text.contains("<b>hi</b> & bye"));
+    }
+
+    #[test]
+    fn subject_depends_on_content() {
+        let dm = notification_digest(&branding(), &[post(None, "psst")]);
+        assert_eq!("[RustChat] New direct message from @alice", dm.subject);
+
+        let mention = notification_digest(&branding(), &[post(Some("Town Square"), "@bob")]);
+        assert_eq!("[RustChat] Notification in Town Square", mention.subject);
+
+        let digest = notification_digest(&branding(), &[post(None, "a"), post(Some("Dev"), "b")]);
+        assert_eq!("[RustChat] You have 2 new notifications", digest.subject);
+    }
+
+    #[test]
+    fn team_invite_escapes_team_name() {
+        let email = team_invite(&branding(), "alice", "R&D", "https://x/?t=1&a=2", 48);
+        assert_eq!("[RustChat] alice invited you to join R&D", email.subject);
+        assert!(email.html.contains("Join R&amp;D"));
+        assert!(email.html.contains("https://x/?t=1&amp;a=2"));
+        assert!(email.text.contains("https://x/?t=1&a=2"));
+    }
+}
@@ -0,0 +1,235 @@
+//! File search
+//!
+//! Files are found by name and by the text extracted from them, narrowed by
+//! Mattermost's search modifiers: `from:` an uploader, `in:` a channel,
+//! `ext:` an extension, and `before:`, `after:` or `on:` a date. Only files
+//! shared in posts of channels the searcher is a member of are found.
+
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, TimeZone, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::error::{ApiResult, AppError};
+use crate::models::FileInfo;
+
+/// A parsed file search
+#[derive(Debug, Default, PartialEq)]
+pub struct FileSearch {
+    /// Words to find in the name or contents
+    pub terms: String,
+    /// Usernames of uploaders
+    pub from: Vec<String>,
+    /// Channel names
+    pub channels: Vec<String>,
+    /// Lowercase extensions, without the dot
+    pub extensions: Vec<String>,
+    /// Files shared at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Files shared before this time
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl FileSearch {
+    /// Parse search terms, reading dates in a time zone `time_zone_offset`
+    /// seconds east of UTC
+    pub fn parse(input: &str, time_zone_offset: i32) -> ApiResult<Self> {
+        let zone = FixedOffset::east_opt(time_zone_offset)
+            .ok_or_else(|| AppError::Validation("Invalid time zone offset".to_string()))?;
+        let day_start = |date: &str| -> ApiResult<DateTime<Utc>> {
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| AppError::Validation(format!("Invalid date in search: {}", date)))?;
+            let start = zone
+                .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap_or_default())
+                .single()
+                .ok_or_else(|| AppError::Validation(format!("Invalid date in search: {}", date)))?;
+            Ok(start.with_timezone(&Utc))
+        };
+
+        let mut search = Self::default();
+        let mut words = Vec::new();
+        for word in input.split_whitespace() {
+            let Some((modifier, value)) = word.split_once(':').filter(|(_, v)| !v.is_empty())
+            else {
+                words.push(word.trim_matches('"'));
+                continue;
+            };
+            match modifier.to_lowercase().as_str() {
+                "from" => search
+                    .from
+                    .push(value.trim_start_matches('@').to_lowercase()),
+                "in" => search
+                    .channels
+                    .push(value.trim_start_matches('~').to_lowercase()),
+                "ext" => search
+                    .extensions
+                    .push(value.trim_start_matches('.').to_lowercase()),
+                "after" => search.since = Some(day_start(value)? + Duration::days(1)),
+                "before" => search.until = Some(day_start(value)?),
+                "on" => {
+                    let start = day_start(value)?;
+                    search.since = Some(start);
+                    search.until = Some(start + Duration::days(1));
+                }
+                _ => words.push(word.trim_matches('"')),
+            }
+        }
+        words.retain(|word| !word.is_empty());
+        search.terms = words.join(" ");
+
+        if search == Self::default() {
+            return Err(AppError::Validation(
+                "Search query cannot be empty".to_string(),
+            ));
+        }
+        Ok(search)
+    }
+}
+
+/// Files in a team matching `search` that `user_id` can see, newest first
+pub async fn search_files(
+    db: &PgPool,
+    user_id: Uuid,
+    team_id: Uuid,
+    search: &FileSearch,
+    page: i64,
+    per_page: i64,
+) -> ApiResult<Vec<FileInfo>> {
+    // Each match runs on its own index; OR-ing them would read every file
+    let files = sqlx::query_as(
+        r#"
+        SELECT f.* FROM files f
+        JOIN posts p ON p.id = f.post_id AND p.deleted_at IS NULL
+        JOIN channels c ON c.id = p.channel_id
+        JOIN channel_members cm ON cm.channel_id = c.id AND cm.user_id = $1
+        JOIN users u ON u.id = f.uploader_id
+        WHERE c.team_id = $2
+          AND COALESCE(f.scan_status, 'clean') = 'clean'
+          AND ($3 = ''
+               OR f.id IN (
+                   SELECT id FROM files WHERE name ILIKE $4 ESCAPE '\'
+                   UNION
+                   SELECT id FROM files
+                   WHERE to_tsvector('english', translate(name, '._-', '   ') || ' ' || COALESCE(extracted_text, ''))
+                         @@ plainto_tsquery('english', $3)))
+          AND (cardinality($5::text[]) = 0 OR LOWER(u.username) = ANY($5))
+          AND (cardinality($6::text[]) = 0 OR LOWER(c.name) = ANY($6))
+          AND (cardinality($7::text[]) = 0 OR LOWER(substring(f.name from '\.([^.]*)$')) = ANY($7))
+          AND ($8::timestamptz IS NULL OR f.created_at >= $8)
+          AND ($9::timestamptz IS NULL OR f.created_at < $9)
+        ORDER BY f.created_at DESC
+        LIMIT $10 OFFSET $11
+        "#,
+    )
+    .bind(user_id)
+    .bind(team_id)
+    .bind(&search.terms)
+    .bind(contains_pattern(&search.terms))
+    .bind(&search.from)
+    .bind(&search.channels)
+    .bind(&search.extensions)
+    .bind(search.since)
+    .bind(search.until)
+    .bind(per_page)
+    .bind(page * per_page)
+    .fetch_all(db)
+    .await?;
+
+    Ok(files)
+}
+
+/// An `ILIKE` pattern matching `terms` anywhere, with its wildcards taken
+/// literally
+fn contains_pattern(terms: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in terms.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// Files shared in a channel, newest first
+pub async fn channel_files(
+    db: &PgPool,
+    channel_id: Uuid,
+    page: i64,
+    per_page: i64,
+) -> ApiResult<Vec<FileInfo>> {
+    let files = sqlx::query_as(
+        r#"
+        SELECT f.* FROM files f
+        JOIN posts p ON p.id = f.post_id AND p.deleted_at IS NULL
+        WHERE p.channel_id = $1
+          AND COALESCE(f.scan_status, 'clean') = 'clean'
+        ORDER BY f.created_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+    )
+    .bind(channel_id)
+    .bind(per_page)
+    .bind(page * per_page)
+    .fetch_all(db)
+    .await?;
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modifiers_are_taken_out_of_the_terms() {
+        let search = FileSearch::parse(
+            "quarterly from:@Alice ext:.PDF in:~town-square \"report\"",
+            0,
+        )
+        .unwrap();
+
+        assert_eq!("quarterly report", search.terms);
+        assert_eq!(vec!["alice"], search.from);
+        assert_eq!(vec!["town-square"], search.channels);
+        assert_eq!(vec!["pdf"], search.extensions);
+        assert!(search.since.is_none() && search.until.is_none());
+    }
+
+    #[test]
+    fn dates_are_whole_days_in_the_searchers_time_zone() {
+        let search = FileSearch::parse("on:2026-03-10", 3600).unwrap();
+        assert_eq!(
+            Some(Utc.with_ymd_and_hms(2026, 3, 9, 23, 0, 0).unwrap()),
+            search.since
+        );
+        assert_eq!(
+            Some(Utc.with_ymd_and_hms(2026, 3, 10, 23, 0, 0).unwrap()),
+            search.until
+        );
+
+        // After a day means from the next one on
+        let search = FileSearch::parse("after:2026-03-10 before:2026-03-20", 0).unwrap();
+        assert_eq!(
+            Some(Utc.with_ymd_and_hms(2026, 3, 11, 0, 0, 0).unwrap()),
+            search.since
+        );
+        assert_eq!(
+            Some(Utc.with_ymd_and_hms(2026, 3, 20, 0, 0, 0).unwrap()),
+            search.until
+        );
+    }
+
+    #[test]
+    fn name_patterns_match_wildcards_literally() {
+        assert_eq!("%report%", contains_pattern("report"));
+        assert_eq!(r"%50\%\_off\\%", contains_pattern(r"50%_off\"));
+    }
+
+    #[test]
+    fn empty_and_malformed_searches_are_refused() {
+        assert!(FileSearch::parse("  ", 0).is_err());
+        assert!(FileSearch::parse("after:yesterday", 0).is_err());
+        assert_eq!("12:30", FileSearch::parse("12:30", 0).unwrap().terms);
+    }
+}
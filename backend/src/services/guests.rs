@@ -0,0 +1,133 @@
+//! Guest accounts
+//!
+//! Guests are users with the `guest` system role, meant for contractors and
+//! customers. They only see channels they were explicitly added to: they
+//! cannot browse or join public channels or open teams, and can only message
+//! people they share a channel with. What they may do inside their channels
+//! comes from the `guest` role's permissions. Guest accounts only work while
+//! `enable_guest_accounts` is on.
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::models::User;
+use crate::services::sessions;
+
+pub const GUEST_ROLE: &str = "guest";
+pub const MEMBER_ROLE: &str = "member";
+
+pub fn is_guest(role: &str) -> bool {
+    role == GUEST_ROLE
+}
+
+/// Like [`is_guest`], for callers that only know the user ID
+pub async fn user_is_guest(db: &PgPool, user_id: Uuid) -> ApiResult<bool> {
+    let guest = sqlx::query_scalar("SELECT role = $2 FROM users WHERE id = $1")
+        .bind(user_id)
+        .bind(GUEST_ROLE)
+        .fetch_optional(db)
+        .await?;
+
+    Ok(guest.unwrap_or(false))
+}
+
+async fn guests_enabled(db: &PgPool) -> ApiResult<bool> {
+    let config = crate::services::auth_config::get_password_rules(db).await?;
+    Ok(config.enable_guest_accounts)
+}
+
+/// Reject logins from guests while guest accounts are disabled
+pub async fn check_guest_login(db: &PgPool, user: &User) -> ApiResult<()> {
+    if is_guest(&user.role) && !guests_enabled(db).await? {
+        return Err(AppError::Unauthorized(
+            "Guest accounts are disabled".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fail for guests, who only get into teams and channels by being added
+pub fn ensure_not_guest(role: &str) -> ApiResult<()> {
+    if is_guest(role) {
+        return Err(AppError::Forbidden(
+            "Guests can only join channels they are added to".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Check that `user_id` may open a direct or group message with `others`
+///
+/// Whenever a guest is on either side, the two must already share a public
+/// or private channel.
+pub async fn ensure_can_message(db: &PgPool, user_id: Uuid, others: &[Uuid]) -> ApiResult<()> {
+    let blocked: Option<Uuid> = sqlx::query_scalar(
+        r#"
+        SELECT u.id FROM users u
+        WHERE u.id = ANY($2) AND u.id <> $1
+          AND (u.role = $3 OR EXISTS (SELECT 1 FROM users WHERE id = $1 AND role = $3))
+          AND NOT EXISTS (
+              SELECT 1 FROM channel_members a
+              JOIN channel_members b ON b.channel_id = a.channel_id
+              JOIN channels c ON c.id = a.channel_id
+              WHERE a.user_id = $1 AND b.user_id = u.id
+                AND c.type IN ('public', 'private')
+          )
+        LIMIT 1
+        "#,
+    )
+    .bind(user_id)
+    .bind(others)
+    .bind(GUEST_ROLE)
+    .fetch_optional(db)
+    .await?;
+
+    if blocked.is_some() {
+        return Err(AppError::Forbidden(
+            "Guests can only message people they share a channel with".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Turn a member into a guest, or a guest back into a member
+///
+/// The role is part of every session token, so the user's sessions are
+/// revoked and they sign in again with the new role.
+pub async fn set_guest(state: &AppState, user_id: Uuid, guest: bool) -> ApiResult<User> {
+    let (from, to) = if guest {
+        if !guests_enabled(&state.db).await? {
+            return Err(AppError::BadRequest(
+                "Guest accounts are disabled".to_string(),
+            ));
+        }
+        (MEMBER_ROLE, GUEST_ROLE)
+    } else {
+        (GUEST_ROLE, MEMBER_ROLE)
+    };
+
+    let user: User = sqlx::query_as(
+        "UPDATE users SET role = $3, updated_at = NOW() WHERE id = $1 AND role = $2 AND is_bot = false RETURNING *",
+    )
+    .bind(user_id)
+    .bind(from)
+    .bind(to)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| {
+        AppError::BadRequest(if guest {
+            "Only members can be demoted to guests".to_string()
+        } else {
+            "User is not a guest".to_string()
+        })
+    })?;
+
+    sessions::revoke_all_sessions(state, user_id).await?;
+
+    Ok(user)
+}
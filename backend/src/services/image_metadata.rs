@@ -0,0 +1,371 @@
+//! Image metadata removal
+//!
+//! EXIF and XMP metadata can say where and when a photo was taken and with
+//! what, so it is cut out of uploaded originals. Only the metadata segments
+//! and chunks are removed; the image data, ICC colour profiles and anything
+//! else is copied byte for byte. An EXIF orientation is put back on its own
+//! so viewers still turn the original upright.
+
+use image::error::{DecodingError, ImageFormatHint};
+use image::metadata::Orientation;
+use image::{ImageError, ImageFormat, ImageResult};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Prefix of the EXIF data in a JPEG APP1 segment
+const JPEG_EXIF_PREFIX: &[u8] = b"Exif\0\0";
+
+/// VP8X flags saying a WebP has EXIF and XMP chunks
+const WEBP_EXIF_FLAG: u8 = 0x08;
+const WEBP_XMP_FLAG: u8 = 0x04;
+
+/// `data` without its metadata, or `None` when it carries none or is not a
+/// JPEG, PNG or WebP
+pub fn strip(
+    data: &[u8],
+    format: ImageFormat,
+    orientation: Orientation,
+) -> ImageResult<Option<Vec<u8>>> {
+    let orientation = (orientation != Orientation::NoTransforms).then(|| exif(orientation));
+    let stripped = match format {
+        ImageFormat::Jpeg => strip_jpeg(data, orientation.as_deref()),
+        ImageFormat::Png => strip_png(data, orientation.as_deref()),
+        ImageFormat::WebP => strip_webp(data, orientation.as_deref()),
+        _ => return Ok(None),
+    };
+
+    stripped.ok_or_else(|| malformed(format))
+}
+
+fn malformed(format: ImageFormat) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Exact(format),
+        "malformed metadata",
+    ))
+}
+
+/// A big-endian TIFF structure holding only the orientation tag
+fn exif(orientation: Orientation) -> Vec<u8> {
+    let mut tiff = b"MM\0\x2a\0\0\0\x08".to_vec();
+    tiff.extend_from_slice(&1u16.to_be_bytes());
+    // Tag 0x0112, type SHORT, one value, padded to four bytes
+    tiff.extend_from_slice(&[0x01, 0x12, 0x00, 0x03, 0, 0, 0, 1]);
+    tiff.extend_from_slice(&[0, orientation.to_exif(), 0, 0]);
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+    tiff
+}
+
+/// Drops APP1 (EXIF and XMP) and APP13 (IPTC) segments
+fn strip_jpeg(data: &[u8], orientation: Option<&[u8]>) -> Option<Option<Vec<u8>>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut out = data[..2].to_vec();
+    let mut orientation = orientation;
+    let mut changed = false;
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        match marker {
+            // Fill bytes before a marker
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // Markers without a length
+            0x01 | 0xD0..=0xD7 => {
+                out.extend_from_slice(&data[pos..pos + 2]);
+                pos += 2;
+                continue;
+            }
+            // The entropy-coded image data follows, up to the end
+            0xDA | 0xD9 => {
+                out.extend_from_slice(&data[pos..]);
+                break;
+            }
+            _ => {}
+        }
+
+        let length = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        let segment = data.get(pos..pos + 2 + length)?;
+        if marker == 0xE1 || marker == 0xED {
+            changed = true;
+            if let Some(tiff) = orientation.take() {
+                let length = (2 + JPEG_EXIF_PREFIX.len() + tiff.len()) as u16;
+                out.extend_from_slice(&[0xFF, 0xE1]);
+                out.extend_from_slice(&length.to_be_bytes());
+                out.extend_from_slice(JPEG_EXIF_PREFIX);
+                out.extend_from_slice(tiff);
+            }
+        } else {
+            out.extend_from_slice(segment);
+        }
+        pos += segment.len();
+    }
+
+    Some(changed.then_some(out))
+}
+
+/// Drops eXIf chunks and text chunks holding XMP or raw EXIF and IPTC
+/// profiles
+fn strip_png(data: &[u8], orientation: Option<&[u8]>) -> Option<Option<Vec<u8>>> {
+    if !data.starts_with(PNG_SIGNATURE) {
+        return None;
+    }
+    let mut out = PNG_SIGNATURE.to_vec();
+    let mut orientation = orientation;
+    let mut changed = false;
+    let mut pos = PNG_SIGNATURE.len();
+    while pos < data.len() {
+        let length = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let chunk = data.get(pos..pos + 12 + length)?;
+        let kind = &chunk[4..8];
+        let body = &chunk[8..8 + length];
+        let metadata = match kind {
+            b"eXIf" => true,
+            b"tEXt" | b"zTXt" | b"iTXt" => {
+                body.starts_with(b"XML:com.adobe.xmp\0") || body.starts_with(b"Raw profile type")
+            }
+            _ => false,
+        };
+        if metadata {
+            changed = true;
+            if let Some(tiff) = orientation.filter(|_| kind == b"eXIf") {
+                orientation = None;
+                let mut crc = crc32fast::Hasher::new();
+                crc.update(b"eXIf");
+                crc.update(tiff);
+                out.extend_from_slice(&(tiff.len() as u32).to_be_bytes());
+                out.extend_from_slice(b"eXIf");
+                out.extend_from_slice(tiff);
+                out.extend_from_slice(&crc.finalize().to_be_bytes());
+            }
+        } else {
+            out.extend_from_slice(chunk);
+        }
+        pos += chunk.len();
+    }
+
+    Some(changed.then_some(out))
+}
+
+/// Drops EXIF and XMP chunks and clears their VP8X flags
+fn strip_webp(data: &[u8], orientation: Option<&[u8]>) -> Option<Option<Vec<u8>>> {
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return None;
+    }
+    let mut out = data[..12].to_vec();
+    let mut orientation = orientation;
+    let mut changed = false;
+    let mut has_exif = false;
+    let mut vp8x_flags = None;
+    let mut pos = 12;
+    while pos < data.len() {
+        let kind = data.get(pos..pos + 4)?;
+        let length = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        // Chunks are padded to an even length
+        let end = (pos + 8 + length + (length & 1)).min(data.len());
+        let chunk = data.get(pos..end)?;
+        match kind {
+            b"EXIF" | b"XMP " => {
+                changed = true;
+                if let Some(tiff) = orientation.filter(|_| kind == b"EXIF") {
+                    orientation = None;
+                    has_exif = true;
+                    out.extend_from_slice(b"EXIF");
+                    out.extend_from_slice(&(tiff.len() as u32).to_le_bytes());
+                    out.extend_from_slice(tiff);
+                    if tiff.len() % 2 == 1 {
+                        out.push(0);
+                    }
+                }
+            }
+            _ => {
+                if kind == b"VP8X" {
+                    vp8x_flags = Some(out.len() + 8);
+                }
+                out.extend_from_slice(chunk);
+            }
+        }
+        pos = end;
+    }
+    if !changed {
+        return Some(None);
+    }
+
+    if let Some(flags) = vp8x_flags {
+        let flag = out.get_mut(flags)?;
+        *flag &= !(WEBP_EXIF_FLAG | WEBP_XMP_FLAG);
+        if has_exif {
+            *flag |= WEBP_EXIF_FLAG;
+        }
+    }
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+    Some(Some(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, GenericImageView, Rgb, RgbImage};
+    use std::io::Cursor;
+
+    const GPS: &[u8] = b"GPSLatitude 51.5";
+
+    fn encode(format: ImageFormat) -> Vec<u8> {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(6, 4, Rgb([200, 10, 10])));
+        let mut data = Vec::new();
+        img.write_to(&mut Cursor::new(&mut data), format).unwrap();
+        data
+    }
+
+    fn png_chunk(kind: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(kind);
+        crc.update(body);
+        let mut chunk = (body.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(body);
+        chunk.extend_from_slice(&crc.finalize().to_be_bytes());
+        chunk
+    }
+
+    fn contains(data: &[u8], needle: &[u8]) -> bool {
+        data.windows(needle.len()).any(|w| w == needle)
+    }
+
+    fn orientation(data: &[u8]) -> Orientation {
+        let mut decoder = image::ImageReader::new(Cursor::new(data))
+            .with_guessed_format()
+            .unwrap()
+            .into_decoder()
+            .unwrap();
+        image::ImageDecoder::orientation(&mut decoder).unwrap()
+    }
+
+    #[test]
+    fn jpeg_metadata_is_removed_and_the_rest_kept() {
+        let plain = encode(ImageFormat::Jpeg);
+        assert_eq!(
+            None,
+            strip(&plain, ImageFormat::Jpeg, Orientation::NoTransforms).unwrap()
+        );
+
+        let mut exif = JPEG_EXIF_PREFIX.to_vec();
+        exif.extend_from_slice(GPS);
+        let icc = b"ICC_PROFILE\0\x01\x01profile";
+        let mut data = plain[..2].to_vec();
+        for (marker, body) in [(0xE1, &exif[..]), (0xE2, &icc[..]), (0xED, GPS)] {
+            data.extend_from_slice(&[0xFF, marker]);
+            data.extend_from_slice(&(body.len() as u16 + 2).to_be_bytes());
+            data.extend_from_slice(body);
+        }
+        data.extend_from_slice(&plain[2..]);
+
+        let stripped = strip(&data, ImageFormat::Jpeg, Orientation::NoTransforms)
+            .unwrap()
+            .unwrap();
+        assert!(!contains(&stripped, GPS));
+        assert!(contains(&stripped, icc));
+        assert_eq!(data.len() - stripped.len(), exif.len() + GPS.len() + 8);
+        assert_eq!(Orientation::NoTransforms, orientation(&stripped));
+
+        let rotated = strip(&data, ImageFormat::Jpeg, Orientation::Rotate90)
+            .unwrap()
+            .unwrap();
+        assert!(!contains(&rotated, GPS));
+        assert_eq!(Orientation::Rotate90, orientation(&rotated));
+        let img = image::load_from_memory(&rotated).unwrap();
+        assert_eq!((6, 4), img.dimensions());
+    }
+
+    #[test]
+    fn png_metadata_chunks_are_removed() {
+        let plain = encode(ImageFormat::Png);
+        assert_eq!(
+            None,
+            strip(&plain, ImageFormat::Png, Orientation::NoTransforms).unwrap()
+        );
+
+        let mut xmp = b"XML:com.adobe.xmp\0\0\0\0\0".to_vec();
+        xmp.extend_from_slice(GPS);
+        let comment = b"Comment\0kept";
+        // Metadata goes between the header and the image data
+        let header_end = PNG_SIGNATURE.len() + 25;
+        let mut data = plain[..header_end].to_vec();
+        data.extend(png_chunk(b"eXIf", &exif(Orientation::FlipHorizontal)));
+        data.extend(png_chunk(b"iTXt", &xmp));
+        data.extend(png_chunk(b"tEXt", comment));
+        data.extend_from_slice(&plain[header_end..]);
+
+        let stripped = strip(&data, ImageFormat::Png, Orientation::Rotate270)
+            .unwrap()
+            .unwrap();
+        assert!(!contains(&stripped, GPS));
+        assert!(contains(&stripped, comment));
+        assert_eq!(Orientation::Rotate270, orientation(&stripped));
+        image::load_from_memory(&stripped).unwrap();
+    }
+
+    #[test]
+    fn webp_metadata_chunks_are_removed() {
+        let plain = encode(ImageFormat::WebP);
+        assert_eq!(
+            None,
+            strip(&plain, ImageFormat::WebP, Orientation::NoTransforms).unwrap()
+        );
+
+        // An extended WebP around the same image, with EXIF and XMP
+        let mut vp8x = vec![WEBP_EXIF_FLAG | WEBP_XMP_FLAG, 0, 0, 0];
+        vp8x.extend_from_slice(&[5, 0, 0, 3, 0, 0]);
+        let mut exif_body = exif(Orientation::NoTransforms);
+        exif_body.extend_from_slice(GPS);
+        let mut data = b"RIFF\0\0\0\0WEBP".to_vec();
+        for (kind, body) in [
+            (&b"VP8X"[..], &vp8x[..]),
+            (b"EXIF", &exif_body),
+            (b"XMP ", GPS),
+        ] {
+            data.extend_from_slice(kind);
+            data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            data.extend_from_slice(body);
+        }
+        data.extend_from_slice(&plain[12..]);
+        let riff_size = (data.len() - 8) as u32;
+        data[4..8].copy_from_slice(&riff_size.to_le_bytes());
+
+        let stripped = strip(&data, ImageFormat::WebP, Orientation::NoTransforms)
+            .unwrap()
+            .unwrap();
+        assert!(!contains(&stripped, GPS));
+        assert_eq!(0, stripped[20] & (WEBP_EXIF_FLAG | WEBP_XMP_FLAG));
+        assert_eq!(
+            (stripped.len() - 8) as u32,
+            u32::from_le_bytes(stripped[4..8].try_into().unwrap())
+        );
+        let img = image::load_from_memory(&stripped).unwrap();
+        assert_eq!((6, 4), img.dimensions());
+
+        let rotated = strip(&data, ImageFormat::WebP, Orientation::Rotate180)
+            .unwrap()
+            .unwrap();
+        assert!(!contains(&rotated, GPS));
+        assert_eq!(
+            WEBP_EXIF_FLAG,
+            rotated[20] & (WEBP_EXIF_FLAG | WEBP_XMP_FLAG)
+        );
+        assert_eq!(Orientation::Rotate180, orientation(&rotated));
+    }
+
+    #[test]
+    fn truncated_images_are_refused() {
+        let data = encode(ImageFormat::Png);
+        assert!(strip(&data[..20], ImageFormat::Png, Orientation::NoTransforms).is_err());
+        assert!(strip(b"not a jpeg", ImageFormat::Jpeg, Orientation::NoTransforms).is_err());
+    }
+}
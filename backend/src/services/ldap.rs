@@ -0,0 +1,571 @@
+//! LDAP / Active Directory authentication
+//!
+//! While `enable_ldap` is on, logins that don't belong to a local account are
+//! checked against the directory: the service account searches for the entry
+//! by username or email, then the user's password is verified with a bind as
+//! that entry. The first successful login creates the local account, later
+//! ones refresh its email and display name. Directory accounts are tied to
+//! the entry's `id_attribute`, so renamed or moved entries keep their
+//! account, and they have no local password.
+//!
+//! Group mappings keep the members of a directory group in a team or
+//! channel. They are applied to a user when they sign in and to everyone by
+//! the sync job in `jobs::ldap_sync`.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use ldap3::{ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, LdapError, Scope, SearchEntry};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::auth::NO_PASSWORD;
+use crate::error::{ApiResult, AppError};
+use crate::models::{LdapConfig, LdapGroupMapping, User};
+use crate::services::{guests, sso};
+
+/// Connect and operation timeout for directory requests
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// LDAP result code for a wrong DN or password
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// A user entry from the directory, mapped to profile fields
+#[derive(Debug, Clone)]
+pub struct DirectoryUser {
+    /// Value of the config's `id_attribute`
+    pub id: String,
+    pub dn: String,
+    pub username: Option<String>,
+    pub email: Option<String>,
+    pub display_name: Option<String>,
+}
+
+/// What a directory group mapping changed
+#[derive(Debug, Default)]
+pub struct MembershipChanges {
+    pub added: u64,
+    pub removed: u64,
+}
+
+fn directory_error(e: LdapError) -> AppError {
+    AppError::ExternalService(format!("LDAP: {}", e))
+}
+
+fn invalid_credentials() -> AppError {
+    AppError::Unauthorized("Invalid login credentials".to_string())
+}
+
+/// Wrap a configured filter in parentheses if the admin left them off
+fn wrap_filter(filter: &str) -> String {
+    let filter = filter.trim();
+    if filter.is_empty() {
+        "(objectClass=*)".to_string()
+    } else if filter.starts_with('(') {
+        filter.to_string()
+    } else {
+        format!("({})", filter)
+    }
+}
+
+/// DNs compare case-insensitively and without spaces around separators
+pub fn normalize_dn(dn: &str) -> String {
+    dn.split(',')
+        .map(|rdn| rdn.split('=').map(str::trim).collect::<Vec<_>>().join("="))
+        .collect::<Vec<_>>()
+        .join(",")
+        .to_lowercase()
+}
+
+/// First value of an attribute, whatever case the server returned its name in
+fn attribute(entry: &SearchEntry, name: &str) -> Option<String> {
+    entry
+        .attrs
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .and_then(|(_, values)| values.first())
+        .filter(|value| !value.is_empty())
+        .cloned()
+}
+
+/// An entry's id, hex-encoded for binary ids such as `objectGUID`
+fn entry_id(entry: &SearchEntry, name: &str) -> Option<String> {
+    attribute(entry, name).or_else(|| {
+        entry
+            .bin_attrs
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .and_then(|(_, values)| values.first())
+            .map(hex::encode)
+    })
+}
+
+/// Open a connection to the configured server
+async fn connect(config: &LdapConfig) -> ApiResult<Ldap> {
+    if config.server_url.is_empty() {
+        return Err(AppError::Config("LDAP server URL is not set".to_string()));
+    }
+
+    let settings = LdapConnSettings::new()
+        .set_conn_timeout(TIMEOUT)
+        .set_starttls(config.start_tls);
+    let (conn, ldap) = LdapConnAsync::with_settings(settings, &config.server_url)
+        .await
+        .map_err(directory_error)?;
+    ldap3::drive!(conn);
+
+    Ok(ldap)
+}
+
+/// Connection bound as the service account, for searching the directory
+pub struct Directory {
+    ldap: Ldap,
+    config: LdapConfig,
+}
+
+impl Directory {
+    pub async fn connect(config: &LdapConfig, encryption_key: &str) -> ApiResult<Self> {
+        let mut ldap = connect(config).await?;
+
+        if !config.bind_dn.is_empty() {
+            let password = if config.bind_password_encrypted.is_empty() {
+                String::new()
+            } else {
+                crate::crypto::decrypt(&config.bind_password_encrypted, encryption_key)?
+            };
+            ldap.with_timeout(TIMEOUT)
+                .simple_bind(&config.bind_dn, &password)
+                .await
+                .and_then(|result| result.success())
+                .map_err(|e| {
+                    AppError::Config(format!("LDAP service account bind failed: {}", e))
+                })?;
+        }
+
+        Ok(Self {
+            ldap,
+            config: config.clone(),
+        })
+    }
+
+    async fn search(
+        &mut self,
+        base: &str,
+        scope: Scope,
+        filter: &str,
+        attrs: Vec<String>,
+    ) -> ApiResult<Vec<SearchEntry>> {
+        let (entries, _) = self
+            .ldap
+            .with_timeout(TIMEOUT)
+            .search(base, scope, filter, attrs)
+            .await
+            .and_then(|result| result.success())
+            .map_err(directory_error)?;
+
+        // Referrals to other servers are not followed
+        Ok(entries
+            .into_iter()
+            .filter(|entry| !entry.is_ref() && !entry.is_intermediate())
+            .map(SearchEntry::construct)
+            .collect())
+    }
+
+    fn user_attributes(&self) -> Vec<String> {
+        vec![
+            self.config.id_attribute.clone(),
+            self.config.username_attribute.clone(),
+            self.config.email_attribute.clone(),
+            self.config.display_name_attribute.clone(),
+        ]
+    }
+
+    fn to_user(&self, entry: &SearchEntry) -> Option<DirectoryUser> {
+        Some(DirectoryUser {
+            id: entry_id(entry, &self.config.id_attribute)?,
+            dn: entry.dn.clone(),
+            username: attribute(entry, &self.config.username_attribute),
+            email: attribute(entry, &self.config.email_attribute),
+            display_name: attribute(entry, &self.config.display_name_attribute),
+        })
+    }
+
+    /// Look up the entry a user signs in as, by username or email
+    pub async fn find_user(&mut self, login_id: &str) -> ApiResult<Option<DirectoryUser>> {
+        let login_id = ldap_escape(login_id);
+        let filter = format!(
+            "(&{}(|({}={})({}={})))",
+            wrap_filter(&self.config.user_filter),
+            self.config.username_attribute,
+            login_id,
+            self.config.email_attribute,
+            login_id
+        );
+        let base = self.config.base_dn.clone();
+        let attrs = self.user_attributes();
+        let entries = self.search(&base, Scope::Subtree, &filter, attrs).await?;
+
+        // An ambiguous login must not pick one of the entries at random
+        match entries.as_slice() {
+            [entry] => Ok(self.to_user(entry)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Every entry that matches the user filter
+    pub async fn all_users(&mut self) -> ApiResult<Vec<DirectoryUser>> {
+        let filter = wrap_filter(&self.config.user_filter);
+        let base = self.config.base_dn.clone();
+        let attrs = self.user_attributes();
+        let entries = self.search(&base, Scope::Subtree, &filter, attrs).await?;
+
+        Ok(entries.iter().filter_map(|e| self.to_user(e)).collect())
+    }
+
+    /// Normalized DNs of a group's members
+    pub async fn group_members(&mut self, group_dn: &str) -> ApiResult<HashSet<String>> {
+        let attribute_name = self.config.group_member_attribute.clone();
+        let entries = self
+            .search(
+                group_dn,
+                Scope::Base,
+                "(objectClass=*)",
+                vec![attribute_name.clone()],
+            )
+            .await?;
+
+        Ok(entries
+            .iter()
+            .flat_map(|entry| {
+                entry
+                    .attrs
+                    .iter()
+                    .filter(|(key, _)| key.eq_ignore_ascii_case(&attribute_name))
+                    .flat_map(|(_, values)| values.iter().map(|dn| normalize_dn(dn)))
+            })
+            .collect())
+    }
+
+    pub async fn close(mut self) {
+        let _ = self.ldap.unbind().await;
+    }
+}
+
+/// Check a password with a bind as the entry
+///
+/// Uses its own connection so the service account's binding is untouched.
+async fn check_password(config: &LdapConfig, dn: &str, password: &str) -> ApiResult<bool> {
+    // An empty password would be an unauthenticated bind, which servers accept
+    if password.is_empty() {
+        return Ok(false);
+    }
+
+    let mut ldap = connect(config).await?;
+    let result = ldap
+        .with_timeout(TIMEOUT)
+        .simple_bind(dn, password)
+        .await
+        .map_err(directory_error)?;
+    let _ = ldap.unbind().await;
+
+    match result.rc {
+        0 => Ok(true),
+        INVALID_CREDENTIALS => Ok(false),
+        _ => Err(directory_error(LdapError::from(result))),
+    }
+}
+
+/// Sign a user in through the directory
+///
+/// Returns `None` when LDAP is off or the login belongs to a local account,
+/// which is then checked against its stored password as usual.
+pub async fn authenticate(
+    state: &AppState,
+    login_id: &str,
+    password: &str,
+) -> ApiResult<Option<User>> {
+    let config = crate::services::auth_config::get_password_rules(&state.db).await?;
+    if !config.enable_ldap {
+        return Ok(None);
+    }
+
+    let local: Option<bool> = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM ldap_users l WHERE l.user_id = u.id)
+        FROM users u
+        WHERE lower(u.email) = lower($1) OR u.username = $1
+        LIMIT 1
+        "#,
+    )
+    .bind(login_id)
+    .fetch_optional(&state.db)
+    .await?;
+    if local == Some(false) {
+        return Ok(None);
+    }
+
+    let mut directory = Directory::connect(&config.ldap, &state.encryption_key).await?;
+    let Some(entry) = directory.find_user(login_id).await? else {
+        directory.close().await;
+        return Err(invalid_credentials());
+    };
+    if !check_password(&config.ldap, &entry.dn, password).await? {
+        directory.close().await;
+        return Err(invalid_credentials());
+    }
+
+    let user = upsert_user(&state.db, &entry).await;
+    let user = match user {
+        Ok(user) if user.is_active => {
+            let memberships = sync_memberships(
+                &state.db,
+                &mut directory,
+                &config.ldap.group_mappings,
+                Some(user.id),
+            )
+            .await;
+            memberships.map(|_| user)
+        }
+        Ok(_) => Err(AppError::Unauthorized("Account is deactivated".to_string())),
+        Err(e) => Err(e),
+    };
+    directory.close().await;
+    let user = user?;
+
+    guests::check_guest_login(&state.db, &user).await?;
+
+    Ok(Some(user))
+}
+
+/// Create or refresh the local account for a directory entry
+pub async fn upsert_user(db: &PgPool, entry: &DirectoryUser) -> ApiResult<User> {
+    let email = entry
+        .email
+        .as_deref()
+        .ok_or_else(|| AppError::Forbidden("Directory entry has no email address".to_string()))?;
+
+    let existing: Option<User> = sqlx::query_as(
+        "SELECT u.* FROM users u JOIN ldap_users l ON l.user_id = u.id WHERE l.ldap_id = $1",
+    )
+    .bind(&entry.id)
+    .fetch_optional(db)
+    .await?;
+
+    if let Some(user) = existing {
+        sqlx::query("UPDATE ldap_users SET dn = $2, last_synced_at = NOW() WHERE user_id = $1")
+            .bind(user.id)
+            .bind(&entry.dn)
+            .execute(db)
+            .await?;
+
+        // Usernames stay as they are so mentions keep working; an email that
+        // another account already uses is left for an admin to sort out
+        let user = sqlx::query_as(
+            r#"
+            UPDATE users SET
+                email = CASE WHEN EXISTS (
+                    SELECT 1 FROM users o WHERE lower(o.email) = lower($2) AND o.id <> $1
+                ) THEN email ELSE $2 END,
+                display_name = COALESCE($3, display_name)
+            WHERE id = $1
+            RETURNING *
+            "#,
+        )
+        .bind(user.id)
+        .bind(email)
+        .bind(&entry.display_name)
+        .fetch_one(db)
+        .await?;
+
+        return Ok(user);
+    }
+
+    let taken: bool =
+        sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE lower(email) = lower($1))")
+            .bind(email)
+            .fetch_one(db)
+            .await?;
+    if taken {
+        return Err(AppError::Conflict(
+            "An account with this email already exists. Ask an administrator to move it to the directory".to_string(),
+        ));
+    }
+
+    let wanted = entry
+        .username
+        .clone()
+        .unwrap_or_else(|| email.split('@').next().unwrap_or_default().to_string());
+    let username = sso::unique_username(db, &wanted).await?;
+
+    let mut tx = db.begin().await?;
+    let user: User = sqlx::query_as(
+        r#"
+        INSERT INTO users (username, email, password_hash, display_name, role, email_verified)
+        VALUES ($1, $2, $3, $4, $5, true)
+        RETURNING *
+        "#,
+    )
+    .bind(&username)
+    .bind(email)
+    .bind(NO_PASSWORD)
+    .bind(&entry.display_name)
+    .bind(guests::MEMBER_ROLE)
+    .fetch_one(&mut *tx)
+    .await?;
+    sqlx::query(
+        "INSERT INTO ldap_users (user_id, ldap_id, dn, last_synced_at) VALUES ($1, $2, $3, NOW())",
+    )
+    .bind(user.id)
+    .bind(&entry.id)
+    .bind(&entry.dn)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(user)
+}
+
+/// Apply group mappings to directory users, or to just `only_user`
+///
+/// Members of a mapped group are added to its team (and channel); directory
+/// users who left the group are removed from the channel, or from the team
+/// and its channels when the mapping has no channel.
+pub async fn sync_memberships(
+    db: &PgPool,
+    directory: &mut Directory,
+    mappings: &[LdapGroupMapping],
+    only_user: Option<Uuid>,
+) -> ApiResult<MembershipChanges> {
+    let mut changes = MembershipChanges::default();
+    if mappings.is_empty() {
+        return Ok(changes);
+    }
+
+    let users: Vec<(Uuid, String)> = sqlx::query_as(
+        r#"
+        SELECT l.user_id, l.dn FROM ldap_users l
+        JOIN users u ON u.id = l.user_id
+        WHERE u.is_active = true AND ($1::uuid IS NULL OR l.user_id = $1)
+        "#,
+    )
+    .bind(only_user)
+    .fetch_all(db)
+    .await?;
+
+    for mapping in mappings {
+        let members = directory.group_members(&mapping.group_dn).await?;
+        for (user_id, dn) in &users {
+            if members.contains(&normalize_dn(dn)) {
+                changes.added += add_member(db, mapping, *user_id).await?;
+            } else {
+                changes.removed += remove_member(db, mapping, *user_id).await?;
+            }
+        }
+    }
+
+    Ok(changes)
+}
+
+async fn add_member(db: &PgPool, mapping: &LdapGroupMapping, user_id: Uuid) -> ApiResult<u64> {
+    let mut tx = db.begin().await?;
+    let team = sqlx::query(
+        r#"
+        INSERT INTO team_members (team_id, user_id, role)
+        VALUES ($1, $2, 'member')
+        ON CONFLICT (team_id, user_id) DO NOTHING
+        "#,
+    )
+    .bind(mapping.team_id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    // New team members get the public channels, like any other join
+    if team.rows_affected() > 0 {
+        sqlx::query(
+            r#"
+            INSERT INTO channel_members (channel_id, user_id)
+            SELECT c.id, $1 FROM channels c
+            WHERE c.team_id = $2 AND c.type = 'public'::channel_type
+            ON CONFLICT (channel_id, user_id) DO NOTHING
+            "#,
+        )
+        .bind(user_id)
+        .bind(mapping.team_id)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    let channel = match mapping.channel_id {
+        Some(channel_id) => sqlx::query(
+            r#"
+            INSERT INTO channel_members (channel_id, user_id)
+            SELECT id, $2 FROM channels WHERE id = $1 AND team_id = $3
+            ON CONFLICT (channel_id, user_id) DO NOTHING
+            "#,
+        )
+        .bind(channel_id)
+        .bind(user_id)
+        .bind(mapping.team_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected(),
+        None => 0,
+    };
+    tx.commit().await?;
+
+    Ok(team.rows_affected() + channel)
+}
+
+async fn remove_member(db: &PgPool, mapping: &LdapGroupMapping, user_id: Uuid) -> ApiResult<u64> {
+    let removed = match mapping.channel_id {
+        Some(channel_id) => {
+            sqlx::query("DELETE FROM channel_members WHERE channel_id = $1 AND user_id = $2")
+                .bind(channel_id)
+                .bind(user_id)
+                .execute(db)
+                .await?
+        }
+        None => {
+            sqlx::query(
+                "DELETE FROM channel_members WHERE user_id = $1 AND channel_id IN (SELECT id FROM channels WHERE team_id = $2)",
+            )
+            .bind(user_id)
+            .bind(mapping.team_id)
+            .execute(db)
+            .await?;
+            sqlx::query("DELETE FROM team_members WHERE team_id = $1 AND user_id = $2")
+                .bind(mapping.team_id)
+                .bind(user_id)
+                .execute(db)
+                .await?
+        }
+    };
+
+    Ok(removed.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_get_parentheses() {
+        assert_eq!("(objectClass=person)", wrap_filter("objectClass=person"));
+        assert_eq!("(&(a=b)(c=d))", wrap_filter(" (&(a=b)(c=d)) "));
+        assert_eq!("(objectClass=*)", wrap_filter(""));
+    }
+
+    #[test]
+    fn dns_compare_loosely() {
+        assert_eq!(
+            normalize_dn("uid=jdoe,ou=People,dc=example,dc=com"),
+            normalize_dn("UID=jdoe, OU=people, DC=Example, DC=com")
+        );
+        assert_ne!(
+            normalize_dn("uid=jdoe,ou=people,dc=example,dc=com"),
+            normalize_dn("uid=jdoe2,ou=people,dc=example,dc=com")
+        );
+    }
+}
@@ -0,0 +1,205 @@
+//! Brute-force protection for logins
+//!
+//! Failed logins are counted in Redis, per account and per client address.
+//! Once either count reaches its limit from `AuthConfig`, further logins are
+//! refused until `login_lockout_minutes` have passed since the last failure
+//! or an admin unlocks the account. Logins naming an unknown account are
+//! counted and locked the same way, so a lockout says nothing about whether
+//! the account exists. Lockouts are written to the audit log. Without Redis
+//! nothing is counted and logins are not limited.
+
+use std::net::IpAddr;
+
+use deadpool_redis::redis::{self, AsyncCommands};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::models::AuthConfig;
+
+fn locked_out() -> AppError {
+    AppError::TooManyRequests("Too many failed login attempts, try again later".to_string())
+}
+
+/// What failed logins are counted against
+#[derive(Debug, Clone)]
+enum Subject {
+    User(Uuid),
+    /// A login ID that matches no local account
+    Login(String),
+    Ip(IpAddr),
+}
+
+impl Subject {
+    fn key(&self) -> String {
+        match self {
+            Self::User(id) => format!("login_failures:user:{}", id),
+            Self::Login(login_id) => format!("login_failures:login:{}", login_id),
+            Self::Ip(ip) => format!("login_failures:ip:{}", ip),
+        }
+    }
+}
+
+/// A login attempt that got past the lockout checks
+pub struct LoginAttempt {
+    account: Subject,
+    ip: Option<IpAddr>,
+    config: AuthConfig,
+}
+
+impl LoginAttempt {
+    /// Start an attempt to log in as `login_id`
+    ///
+    /// Fails while the account or the client address is locked out.
+    pub async fn start(state: &AppState, login_id: &str, ip: Option<&str>) -> ApiResult<Self> {
+        let config = crate::services::auth_config::get_password_rules(&state.db).await?;
+        // Any spelling of the address counts against the same account
+        let user_id: Option<Uuid> = sqlx::query_scalar(
+            "SELECT id FROM users WHERE lower(email) = lower($1) OR username = $1 LIMIT 1",
+        )
+        .bind(login_id.trim())
+        .fetch_optional(&state.db)
+        .await?;
+
+        let attempt = Self {
+            account: user_id.map_or_else(
+                || Subject::Login(login_id.trim().to_lowercase()),
+                Subject::User,
+            ),
+            ip: ip.and_then(|ip| ip.parse().ok()),
+            config,
+        };
+
+        let Ok(mut conn) = state.redis.get().await else {
+            return Ok(attempt);
+        };
+        for (subject, limit) in attempt.limits() {
+            let failures: redis::RedisResult<Option<i64>> = conn.get(subject.key()).await;
+            match failures {
+                Ok(Some(failures)) if failures >= limit => return Err(locked_out()),
+                Ok(_) => {}
+                Err(e) => warn!("Failed to read login failures: {}", e),
+            }
+        }
+
+        Ok(attempt)
+    }
+
+    /// Subjects with a lockout configured, and their limits
+    fn limits(&self) -> Vec<(Subject, i64)> {
+        let mut limits = Vec::new();
+        if self.config.maximum_login_attempts > 0 {
+            limits.push((
+                self.account.clone(),
+                self.config.maximum_login_attempts as i64,
+            ));
+        }
+        if let Some(ip) = self
+            .ip
+            .filter(|_| self.config.maximum_login_attempts_per_ip > 0)
+        {
+            limits.push((
+                Subject::Ip(ip),
+                self.config.maximum_login_attempts_per_ip as i64,
+            ));
+        }
+        limits
+    }
+
+    /// Count a failed attempt, locking out whatever reached its limit
+    pub async fn failed(&self, state: &AppState) {
+        let Ok(mut conn) = state.redis.get().await else {
+            return;
+        };
+        let lockout_secs = i64::from(self.config.login_lockout_minutes.max(1)) * 60;
+
+        for (subject, limit) in self.limits() {
+            let key = subject.key();
+            let result: redis::RedisResult<(i64, i64)> = redis::pipe()
+                .atomic()
+                .incr(&key, 1)
+                .expire(&key, lockout_secs)
+                .query_async(&mut conn)
+                .await;
+
+            match result {
+                // Only the failure that reached the limit is logged
+                Ok((failures, _)) if failures == limit => {
+                    if let Err(e) = self.record_lockout(state, &subject).await {
+                        warn!("Failed to audit login lockout: {}", e);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Failed to count login failure: {}", e),
+            }
+        }
+    }
+
+    /// Forget the account's failures after a successful login
+    ///
+    /// The address keeps its count, so one working account cannot be used
+    /// to keep guessing others.
+    pub async fn succeeded(&self, state: &AppState) {
+        if let Ok(mut conn) = state.redis.get().await {
+            let result: redis::RedisResult<()> = conn.del(self.account.key()).await;
+            if let Err(e) = result {
+                warn!("Failed to reset login failures: {}", e);
+            }
+        }
+    }
+
+    async fn record_lockout(&self, state: &AppState, subject: &Subject) -> ApiResult<()> {
+        let (action, target_type, target_id) = match subject {
+            Subject::User(id) => ("user.login_locked", "user", Some(*id)),
+            Subject::Login(_) => ("user.login_locked", "user", None),
+            Subject::Ip(_) => ("ip.login_locked", "ip", None),
+        };
+        let login_id = match &self.account {
+            Subject::Login(login_id) => Some(login_id.as_str()),
+            _ => None,
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO audit_logs (actor_ip, action, target_type, target_id, metadata)
+            VALUES ($1, $2, $3, $4, $5)
+            "#,
+        )
+        .bind(self.ip.map(|ip| ip.to_string()))
+        .bind(action)
+        .bind(target_type)
+        .bind(target_id)
+        .bind(serde_json::json!({
+            "login_id": login_id,
+            "lockout_minutes": self.config.login_lockout_minutes,
+        }))
+        .execute(&state.db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Lift a lockout of `user_id` on behalf of `admin_id`
+pub async fn unlock_user(state: &AppState, user_id: Uuid, admin_id: Uuid) -> ApiResult<()> {
+    let mut conn = state
+        .redis
+        .get()
+        .await
+        .map_err(|e| AppError::Internal(format!("Redis unavailable: {}", e)))?;
+    let _: () = conn.del(Subject::User(user_id).key()).await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO audit_logs (actor_user_id, action, target_type, target_id)
+        VALUES ($1, 'user.login_unlocked', 'user', $2)
+        "#,
+    )
+    .bind(admin_id)
+    .bind(user_id)
+    .execute(&state.db)
+    .await?;
+
+    Ok(())
+}
@@ -0,0 +1,597 @@
+//! Media processing
+//!
+//! Uploaded images get a thumbnail, a preview of at most [`PREVIEW_SIZE`]
+//! pixels and a tiny mini preview clients blur while the rest loads. Images
+//! are turned upright from their EXIF orientation first, and JPEG, PNG and
+//! WebP originals carrying metadata, which may say where a photo was taken,
+//! are stored again without it. Until then the originals are not served.
+//! Images too large to decode are only stripped. GIFs are previewed by their
+//! first frame.
+//! Videos get the same images from a poster frame when ffmpeg is configured.
+//! Documents have their text extracted for search, unless that is turned off.
+//!
+//! Processing runs in the background on a fixed number of workers, so
+//! uploads return as soon as the file is stored. `files.media_status` tracks
+//! each file, and the media processing job picks up files a restart left
+//! behind.
+
+use std::io::Cursor;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, GenericImageView, ImageDecoder, ImageFormat, ImageReader, ImageResult};
+use sha2::{Digest, Sha256};
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::models::FileInfo;
+use crate::services::image_metadata;
+use crate::services::text_extraction::{self, Document};
+
+/// Image types that can be decoded
+const IMAGE_TYPES: &[&str] = &[
+    "image/jpeg",
+    "image/jpg",
+    "image/png",
+    "image/webp",
+    "image/gif",
+];
+
+/// Images larger than this are only stripped, without dimensions or previews
+const MAX_IMAGE_BYTES: i64 = 50 * 1024 * 1024;
+
+/// Largest side of a thumbnail
+const THUMBNAIL_SIZE: u32 = 400;
+
+/// Largest side of a preview
+const PREVIEW_SIZE: u32 = 1920;
+
+/// Side of a mini preview
+const MINI_PREVIEW_SIZE: u32 = 16;
+
+const JPEG_QUALITY: u8 = 85;
+
+/// Where in a video its poster frame is taken, for videos this long
+const POSTER_OFFSET_SECS: &str = "1";
+
+/// How long ffmpeg may take to find a poster frame
+const FFMPEG_TIMEOUT_SECS: u64 = 60;
+
+/// Claims older than this are considered abandoned and released again
+const STALE_CLAIM_MINUTES: i32 = 10;
+
+/// Pending files are left to the workers they were queued for this long
+const REQUEUE_AFTER_MINUTES: i32 = 10;
+
+/// Maximum number of files requeued per run
+const REQUEUE_BATCH_SIZE: i64 = 100;
+
+/// Files processed at the same time unless configured otherwise
+pub const DEFAULT_MEDIA_WORKERS: usize = 2;
+
+/// Runs media processing in the background, a few files at a time
+pub struct MediaProcessor {
+    workers: Arc<Semaphore>,
+    ffmpeg_path: Option<String>,
+    extract_content: bool,
+}
+
+impl Default for MediaProcessor {
+    fn default() -> Self {
+        Self::new(DEFAULT_MEDIA_WORKERS, None)
+    }
+}
+
+impl MediaProcessor {
+    /// Process at most `workers` files at once, using ffmpeg for videos if given
+    pub fn new(workers: usize, ffmpeg_path: Option<String>) -> Self {
+        Self {
+            workers: Arc::new(Semaphore::new(workers.max(1))),
+            ffmpeg_path,
+            extract_content: true,
+        }
+    }
+
+    /// Whether to extract the text of documents
+    pub fn with_content_extraction(mut self, enabled: bool) -> Self {
+        self.extract_content = enabled;
+        self
+    }
+
+    /// Whether files of this name, type and size get previews, have their
+    /// metadata stripped or have their text extracted
+    pub fn handles(&self, name: &str, mime_type: &str, size: i64) -> bool {
+        if mime_type.starts_with("video/") {
+            return self.ffmpeg_path.is_some();
+        }
+        if IMAGE_TYPES.contains(&mime_type) {
+            return true;
+        }
+        self.extract_content
+            && size <= text_extraction::MAX_DOCUMENT_BYTES
+            && Document::of(name, mime_type).is_some()
+    }
+
+    /// Process a pending file once a worker is free
+    pub fn queue(&self, state: &AppState, file_id: Uuid) {
+        let state = state.clone();
+        let workers = self.workers.clone();
+        tokio::spawn(async move {
+            let Ok(_permit) = workers.acquire_owned().await else {
+                return;
+            };
+            if let Err(e) = process_file(&state, file_id).await {
+                warn!(file_id = %file_id, error = %e, "Media processing failed");
+            }
+        });
+    }
+}
+
+/// Refuse to serve an image original that may still carry its metadata
+///
+/// Images are only stripped once processed, and those that could not be
+/// processed never are.
+pub fn ensure_servable(file: &FileInfo) -> ApiResult<()> {
+    if !IMAGE_TYPES.contains(&file.mime_type.as_str()) {
+        return Ok(());
+    }
+    match file.media_status.as_deref() {
+        Some("pending") | Some("processing") => Err(AppError::Conflict(format!(
+            "{} is still being processed",
+            file.name
+        ))),
+        Some("failed") => Err(AppError::Forbidden(format!(
+            "{} could not be processed",
+            file.name
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Claim a pending file by moving it to `processing`
+async fn claim_file(db: &sqlx::PgPool, file_id: Uuid) -> Result<Option<FileInfo>, sqlx::Error> {
+    sqlx::query_as(
+        r#"
+        UPDATE files SET media_status = 'processing', media_claimed_at = NOW()
+        WHERE id = $1 AND media_status = 'pending'
+        RETURNING *
+        "#,
+    )
+    .bind(file_id)
+    .fetch_optional(db)
+    .await
+}
+
+/// Process a file, unless another worker already has
+async fn process_file(state: &AppState, file_id: Uuid) -> ApiResult<()> {
+    let Some(file) = claim_file(&state.db, file_id).await? else {
+        return Ok(());
+    };
+
+    let document = Document::of(&file.name, &file.mime_type)
+        .filter(|_| !IMAGE_TYPES.contains(&file.mime_type.as_str()));
+    let result = match (&state.media.ffmpeg_path, document) {
+        (Some(ffmpeg), _) if file.mime_type.starts_with("video/") => {
+            process_video(state, &file, ffmpeg).await
+        }
+        (_, Some(document)) => process_document(state, &file, document).await,
+        _ => process_image(state, &file).await,
+    };
+
+    if let Err(e) = result {
+        sqlx::query("UPDATE files SET media_status = 'failed' WHERE id = $1")
+            .bind(file.id)
+            .execute(&state.db)
+            .await?;
+        return Err(e);
+    }
+
+    Ok(())
+}
+
+async fn process_image(state: &AppState, file: &FileInfo) -> ApiResult<()> {
+    let data = state
+        .storage
+        .backend(&file.backend)?
+        .download(&file.key)
+        .await?;
+    if file.size > MAX_IMAGE_BYTES {
+        return strip_large_image(state, file, data).await;
+    }
+    let media = tokio::task::spawn_blocking(move || Media::from_image(&data))
+        .await
+        .map_err(|e| AppError::Internal(format!("Image processing failed: {}", e)))?
+        .map_err(|e| AppError::Internal(format!("Unreadable image: {}", e)))?;
+
+    save_media(state, file, media).await
+}
+
+/// Strip an image too large to decode, leaving it without previews
+async fn strip_large_image(state: &AppState, file: &FileInfo, data: Vec<u8>) -> ApiResult<()> {
+    let stripped = tokio::task::spawn_blocking(move || strip_undecoded(&data))
+        .await
+        .map_err(|e| AppError::Internal(format!("Image processing failed: {}", e)))?
+        .map_err(|e| AppError::Internal(format!("Unreadable image: {}", e)))?;
+    let (size, sha256) = store_stripped(state, file, stripped).await?;
+
+    sqlx::query(
+        "UPDATE files SET size = $2, sha256 = $3, media_status = 'processed' WHERE id = $1",
+    )
+    .bind(file.id)
+    .bind(size)
+    .bind(sha256)
+    .execute(&state.db)
+    .await?;
+
+    Ok(())
+}
+
+/// The original without its metadata, reading only the image's header
+fn strip_undecoded(data: &[u8]) -> ImageResult<Option<Vec<u8>>> {
+    let reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+    let Some(format) = reader.format() else {
+        return Ok(None);
+    };
+    let mut decoder = reader.into_decoder()?;
+    let orientation = orientation(&mut decoder)?;
+
+    image_metadata::strip(data, format, orientation)
+}
+
+async fn process_video(state: &AppState, file: &FileInfo, ffmpeg: &str) -> ApiResult<()> {
+    // ffmpeg needs to seek, so the video is copied to disk rather than piped
+    let path = std::env::temp_dir().join(format!("rustchat-media-{}", Uuid::new_v4()));
+    let result = async {
+        let mut reader = state
+            .storage
+            .backend(&file.backend)?
+            .reader(&file.key)
+            .await?;
+        let mut out = tokio::fs::File::create(&path).await.map_err(temp_error)?;
+        tokio::io::copy(&mut reader, &mut out)
+            .await
+            .map_err(temp_error)?;
+        poster_frame(ffmpeg, &path).await
+    }
+    .await;
+    let _ = tokio::fs::remove_file(&path).await;
+
+    let frame = result?;
+    let media = tokio::task::spawn_blocking(move || {
+        let img = image::load_from_memory_with_format(&frame, ImageFormat::Png)?;
+        Media::from_frame(img, false)
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("Poster processing failed: {}", e)))?
+    .map_err(|e| AppError::Internal(format!("Unreadable poster frame: {}", e)))?;
+
+    save_media(state, file, media).await
+}
+
+async fn process_document(state: &AppState, file: &FileInfo, document: Document) -> ApiResult<()> {
+    let data = state
+        .storage
+        .backend(&file.backend)?
+        .download(&file.key)
+        .await?;
+    let text = tokio::task::spawn_blocking(move || document.extract(&data))
+        .await
+        .map_err(|e| AppError::Internal(format!("Text extraction failed: {}", e)))??;
+
+    sqlx::query(
+        "UPDATE files SET extracted_text = $2, media_status = 'processed' WHERE id = $1",
+    )
+    .bind(file.id)
+    .bind(text)
+    .execute(&state.db)
+    .await?;
+
+    Ok(())
+}
+
+fn temp_error(e: std::io::Error) -> AppError {
+    AppError::Internal(format!("Failed to copy video for processing: {}", e))
+}
+
+/// A PNG of the frame [`POSTER_OFFSET_SECS`] in, or of the first frame of
+/// shorter videos
+async fn poster_frame(ffmpeg: &str, path: &Path) -> ApiResult<Vec<u8>> {
+    for offset in [POSTER_OFFSET_SECS, "0"] {
+        let output = Command::new(ffmpeg)
+            .args(["-v", "error", "-ss", offset, "-i"])
+            .arg(path)
+            .args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "png", "-"])
+            .stdin(Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let output = tokio::time::timeout(Duration::from_secs(FFMPEG_TIMEOUT_SECS), output)
+            .await
+            .map_err(|_| AppError::Internal("ffmpeg timed out".to_string()))?
+            .map_err(|e| AppError::Internal(format!("Failed to run ffmpeg: {}", e)))?;
+
+        if output.status.success() && !output.stdout.is_empty() {
+            return Ok(output.stdout);
+        }
+    }
+
+    Err(AppError::Internal(
+        "ffmpeg found no video frame".to_string(),
+    ))
+}
+
+/// Replace the original with its stripped copy, if any
+///
+/// Returns the size and hash of what is stored now.
+async fn store_stripped(
+    state: &AppState,
+    file: &FileInfo,
+    stripped: Option<Vec<u8>>,
+) -> ApiResult<(i64, Option<String>)> {
+    let Some(original) = stripped else {
+        return Ok((file.size, file.sha256.clone()));
+    };
+
+    let size = original.len() as i64;
+    let sha256 = hex::encode(Sha256::digest(&original));
+    state
+        .storage
+        .backend(&file.backend)?
+        .upload(&file.key, original, &file.mime_type)
+        .await?;
+
+    Ok((size, Some(sha256)))
+}
+
+/// Store what processing made and record it on the file
+async fn save_media(state: &AppState, file: &FileInfo, media: Media) -> ApiResult<()> {
+    let backend = state.storage.backend(&file.backend)?;
+    let (size, sha256) = store_stripped(state, file, media.stripped).await?;
+
+    let thumbnail_key = format!(
+        "thumbnails/{}/{}.{}",
+        file.uploader_id,
+        file.id,
+        media.thumbnail.extension()
+    );
+    backend
+        .upload(
+            &thumbnail_key,
+            media.thumbnail.data,
+            media.thumbnail.format.to_mime_type(),
+        )
+        .await?;
+
+    let mut preview_key = None;
+    if let Some(preview) = media.preview {
+        let key = format!(
+            "previews/{}/{}.{}",
+            file.uploader_id,
+            file.id,
+            preview.extension()
+        );
+        backend
+            .upload(&key, preview.data, preview.format.to_mime_type())
+            .await?;
+        preview_key = Some(key);
+    }
+
+    sqlx::query(
+        r#"
+        UPDATE files SET
+            size = $2, sha256 = $3, width = $4, height = $5, has_thumbnail = TRUE,
+            thumbnail_key = $6, preview_key = $7, mini_preview = $8,
+            media_status = 'processed'
+        WHERE id = $1
+        "#,
+    )
+    .bind(file.id)
+    .bind(size)
+    .bind(sha256)
+    .bind(media.width as i32)
+    .bind(media.height as i32)
+    .bind(thumbnail_key)
+    .bind(preview_key)
+    .bind(media.mini_preview)
+    .execute(&state.db)
+    .await?;
+
+    Ok(())
+}
+
+/// Queue pending files nobody is working on
+///
+/// Files whose worker was lost to a restart are released first.
+pub async fn requeue_stale(state: &AppState) -> ApiResult<u64> {
+    sqlx::query(
+        r#"
+        UPDATE files SET media_status = 'pending'
+        WHERE media_status = 'processing'
+          AND media_claimed_at < NOW() - make_interval(mins => $1)
+        "#,
+    )
+    .bind(STALE_CLAIM_MINUTES)
+    .execute(&state.db)
+    .await?;
+
+    let ids: Vec<Uuid> = sqlx::query_scalar(
+        r#"
+        SELECT id FROM files
+        WHERE media_status = 'pending'
+          AND COALESCE(media_claimed_at, created_at) < NOW() - make_interval(mins => $1)
+        ORDER BY created_at
+        LIMIT $2
+        "#,
+    )
+    .bind(REQUEUE_AFTER_MINUTES)
+    .bind(REQUEUE_BATCH_SIZE)
+    .fetch_all(&state.db)
+    .await?;
+
+    for id in &ids {
+        state.media.queue(state, *id);
+    }
+
+    Ok(ids.len() as u64)
+}
+
+/// An encoded image
+struct Encoded {
+    data: Vec<u8>,
+    format: ImageFormat,
+}
+
+impl Encoded {
+    /// JPEG, or PNG for images with transparency
+    fn new(img: &DynamicImage) -> ImageResult<Self> {
+        if img.color().has_alpha() {
+            Self::png(img)
+        } else {
+            Self::jpeg(img, JPEG_QUALITY)
+        }
+    }
+
+    fn jpeg(img: &DynamicImage, quality: u8) -> ImageResult<Self> {
+        let mut data = Vec::new();
+        let rgb = DynamicImage::ImageRgb8(img.to_rgb8());
+        rgb.write_with_encoder(JpegEncoder::new_with_quality(&mut data, quality))?;
+        Ok(Self {
+            data,
+            format: ImageFormat::Jpeg,
+        })
+    }
+
+    fn png(img: &DynamicImage) -> ImageResult<Self> {
+        let mut data = Vec::new();
+        img.write_to(&mut Cursor::new(&mut data), ImageFormat::Png)?;
+        Ok(Self {
+            data,
+            format: ImageFormat::Png,
+        })
+    }
+
+    fn extension(&self) -> &'static str {
+        self.format.extensions_str()[0]
+    }
+}
+
+/// How the EXIF metadata says to turn the image upright
+fn orientation(decoder: &mut impl ImageDecoder) -> ImageResult<Orientation> {
+    let exif = decoder.exif_metadata()?;
+    Ok(exif
+        .as_deref()
+        .and_then(Orientation::from_exif_chunk)
+        .unwrap_or(Orientation::NoTransforms))
+}
+
+/// Images made from an upload or a video's poster frame
+struct Media {
+    width: u32,
+    height: u32,
+    thumbnail: Encoded,
+    /// Only made when the original is too large or cannot be shown as it is
+    preview: Option<Encoded>,
+    mini_preview: Vec<u8>,
+    /// The original without its metadata, when it carried any
+    stripped: Option<Vec<u8>>,
+}
+
+impl Media {
+    fn from_image(data: &[u8]) -> ImageResult<Self> {
+        let reader = ImageReader::new(Cursor::new(data)).with_guessed_format()?;
+        let format = reader.format();
+        let mut decoder = reader.into_decoder()?;
+        let orientation = orientation(&mut decoder)?;
+
+        // GIFs decode to their first frame
+        let mut img = DynamicImage::from_decoder(decoder)?;
+        img.apply_orientation(orientation);
+
+        let stripped = match format {
+            Some(format) => image_metadata::strip(data, format, orientation)?,
+            None => None,
+        };
+
+        // Not every viewer turns an original by its orientation
+        let upright = orientation == Orientation::NoTransforms;
+        let still = format != Some(ImageFormat::Gif);
+        Self::from_frame(img, upright && still).map(|media| Self { stripped, ..media })
+    }
+
+    /// `original_fits` when the original shows as this frame does
+    fn from_frame(img: DynamicImage, original_fits: bool) -> ImageResult<Self> {
+        let (width, height) = img.dimensions();
+        let thumbnail = Encoded::new(&fit(&img, THUMBNAIL_SIZE))?;
+        let preview = if original_fits && width <= PREVIEW_SIZE && height <= PREVIEW_SIZE {
+            None
+        } else {
+            Some(Encoded::new(&fit(&img, PREVIEW_SIZE))?)
+        };
+        let mini = img.resize_to_fill(MINI_PREVIEW_SIZE, MINI_PREVIEW_SIZE, FilterType::Triangle);
+        let mini_preview = Encoded::jpeg(&mini, JPEG_QUALITY)?.data;
+
+        Ok(Self {
+            width,
+            height,
+            thumbnail,
+            preview,
+            mini_preview,
+            stripped: None,
+        })
+    }
+}
+
+/// The image scaled down to fit in a square of `size`, never up
+fn fit(img: &DynamicImage, size: u32) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    if width <= size && height <= size {
+        return img.clone();
+    }
+    img.resize(size, size, FilterType::Lanczos3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+    fn decode(encoded: &Encoded) -> DynamicImage {
+        image::load_from_memory_with_format(&encoded.data, encoded.format).unwrap()
+    }
+
+    #[test]
+    fn large_images_get_a_bounded_preview() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(3000, 1500, Rgb([10, 20, 30])));
+        let media = Media::from_frame(img, true).unwrap();
+
+        assert_eq!((3000, 1500), (media.width, media.height));
+        assert_eq!((400, 200), decode(&media.thumbnail).dimensions());
+        let preview = media.preview.unwrap();
+        assert_eq!(ImageFormat::Jpeg, preview.format);
+        assert_eq!((1920, 960), decode(&preview).dimensions());
+        let mini = image::load_from_memory_with_format(&media.mini_preview, ImageFormat::Jpeg);
+        assert_eq!((16, 16), mini.unwrap().dimensions());
+    }
+
+    #[test]
+    fn small_images_are_not_scaled_up() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(50, 80, Rgba([0, 0, 0, 0])));
+        let media = Media::from_frame(img.clone(), true).unwrap();
+
+        assert!(media.preview.is_none());
+        assert_eq!(ImageFormat::Png, media.thumbnail.format);
+        assert_eq!((50, 80), decode(&media.thumbnail).dimensions());
+
+        // A frame that is not the original still gets a preview
+        let media = Media::from_frame(img, false).unwrap();
+        assert_eq!((50, 80), decode(&media.preview.unwrap()).dimensions());
+    }
+}
@@ -0,0 +1,525 @@
+//! Multi-factor authentication with TOTP
+//!
+//! Users enroll by generating a secret, adding it to an authenticator app
+//! through the provisioning URI or QR code, and confirming with a valid code.
+//! Activation hands out one-time recovery codes, of which only the SHA-256
+//! hash is stored. Once active, every password login needs a current code or
+//! an unused recovery code, and each code is accepted only once. With
+//! `enforce_mfa` on, users who have not enrolled can only reach the
+//! endpoints they need to do so.
+
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha1::Sha1;
+use sqlx::PgPool;
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::crypto::{decrypt, encrypt, hash_token};
+use crate::error::{ApiResult, AppError};
+use crate::models::{SiteConfig, User};
+use crate::services::sessions::{self, SessionCheck};
+
+/// Digits in a code
+const DIGITS: u32 = 6;
+
+/// Seconds each code is valid for
+const PERIOD_SECS: u64 = 30;
+
+/// Codes from this many steps before or after now are accepted, for clock drift
+const ALLOWED_DRIFT_STEPS: u64 = 1;
+
+/// Length of a generated secret, as recommended for HMAC-SHA1
+const SECRET_BYTES: usize = 20;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// 80 random bits, so the unsalted hashes cannot be brute forced
+const RECOVERY_CODE_BYTES: usize = 10;
+
+/// How long the `enforce_mfa` setting is cached
+const POLICY_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// A new secret for an authenticator app
+#[derive(Debug, Clone, Serialize)]
+pub struct MfaSecret {
+    /// Base32 secret for manual entry
+    pub secret: String,
+    /// `otpauth://` provisioning URI
+    pub uri: String,
+    /// The URI as a base64 encoded PNG QR code
+    pub qr_code: String,
+}
+
+/// HOTP value for one counter, per RFC 4226
+fn hotp(key: &[u8], counter: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    value % 10u32.pow(DIGITS)
+}
+
+/// TOTP code for a time step, zero padded
+fn code_at(key: &[u8], step: u64) -> String {
+    format!("{:0width$}", hotp(key, step), width = DIGITS as usize)
+}
+
+fn current_step() -> u64 {
+    chrono::Utc::now().timestamp() as u64 / PERIOD_SECS
+}
+
+/// The time step near `now` that `code` belongs to
+fn matching_step(key: &[u8], code: &str, now: u64) -> Option<u64> {
+    if code.len() != DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    (now.saturating_sub(ALLOWED_DRIFT_STEPS)..=now + ALLOWED_DRIFT_STEPS)
+        .find(|step| code_at(key, *step) == code)
+}
+
+/// `otpauth://` URI that authenticator apps import
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        urlencoding::encode(issuer),
+        urlencoding::encode(account),
+        secret,
+        urlencoding::encode(issuer),
+        DIGITS,
+        PERIOD_SECS
+    )
+}
+
+fn qr_code_png(uri: &str) -> ApiResult<String> {
+    let image = qrcode::QrCode::new(uri.as_bytes())
+        .map_err(|e| AppError::Internal(format!("Failed to build QR code: {}", e)))?
+        .render::<image::Luma<u8>>()
+        .min_dimensions(200, 200)
+        .build();
+
+    let mut png = Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, image::ImageFormat::Png)
+        .map_err(|e| AppError::Internal(format!("Failed to encode QR code: {}", e)))?;
+
+    Ok(STANDARD.encode(png.into_inner()))
+}
+
+/// Strip the separators users type or paste along with a code
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_lowercase()
+}
+
+fn new_recovery_code() -> String {
+    let mut bytes = [0u8; RECOVERY_CODE_BYTES];
+    OsRng.fill_bytes(&mut bytes);
+    let code = BASE32_NOPAD.encode(&bytes).to_lowercase();
+
+    code.as_bytes()
+        .chunks(4)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Stored MFA state of a user
+struct Enrollment {
+    email: String,
+    secret: Option<Vec<u8>>,
+    active: bool,
+    last_step: Option<i64>,
+}
+
+async fn load_enrollment(state: &AppState, user_id: Uuid) -> ApiResult<Enrollment> {
+    let (email, secret, active, last_step): (String, Option<String>, bool, Option<i64>) =
+        sqlx::query_as(
+            "SELECT email, mfa_secret_encrypted, mfa_active, mfa_last_step FROM users WHERE id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("User not found".to_string()))?;
+
+    let secret = match secret {
+        Some(secret) => {
+            let secret = decrypt(&secret, &state.encryption_key)?;
+            Some(
+                BASE32_NOPAD
+                    .decode(secret.as_bytes())
+                    .map_err(|_| AppError::Internal("Stored MFA secret is invalid".to_string()))?,
+            )
+        }
+        None => None,
+    };
+
+    Ok(Enrollment {
+        email,
+        secret,
+        active,
+        last_step,
+    })
+}
+
+/// Create a new secret for `user_id`, replacing any unconfirmed one
+///
+/// Fails while MFA is active, so an authenticator in use is never replaced.
+pub async fn generate_secret(state: &AppState, user_id: Uuid) -> ApiResult<MfaSecret> {
+    let enrollment = load_enrollment(state, user_id).await?;
+    if enrollment.active {
+        return Err(AppError::BadRequest("MFA is already active".to_string()));
+    }
+
+    let mut key = [0u8; SECRET_BYTES];
+    OsRng.fill_bytes(&mut key);
+    let secret = BASE32_NOPAD.encode(&key);
+
+    sqlx::query(
+        "UPDATE users SET mfa_secret_encrypted = $2, mfa_last_step = NULL, updated_at = NOW() WHERE id = $1",
+    )
+    .bind(user_id)
+    .bind(encrypt(&secret, &state.encryption_key))
+    .execute(&state.db)
+    .await?;
+
+    let site: sqlx::types::Json<SiteConfig> =
+        sqlx::query_scalar("SELECT site FROM server_config WHERE id = 'default'")
+            .fetch_one(&state.db)
+            .await?;
+    let uri = provisioning_uri(&site.site_name, &enrollment.email, &secret);
+    let qr_code = qr_code_png(&uri)?;
+
+    Ok(MfaSecret {
+        secret,
+        uri,
+        qr_code,
+    })
+}
+
+/// Accept a TOTP code at most once
+async fn verify_totp(
+    db: &PgPool,
+    user_id: Uuid,
+    enrollment: &Enrollment,
+    code: &str,
+) -> ApiResult<bool> {
+    let Some(key) = enrollment.secret.as_deref() else {
+        return Ok(false);
+    };
+    let Some(step) = matching_step(key, code, current_step()) else {
+        return Ok(false);
+    };
+    if enrollment.last_step.is_some_and(|last| step as i64 <= last) {
+        return Ok(false);
+    }
+
+    // Guarded so two requests racing with the same code cannot both win
+    let accepted: Option<Uuid> = sqlx::query_scalar(
+        r#"
+        UPDATE users SET mfa_last_step = $2
+        WHERE id = $1 AND (mfa_last_step IS NULL OR mfa_last_step < $2)
+        RETURNING id
+        "#,
+    )
+    .bind(user_id)
+    .bind(step as i64)
+    .fetch_optional(db)
+    .await?;
+
+    Ok(accepted.is_some())
+}
+
+/// Use up one of the user's recovery codes
+async fn use_recovery_code(db: &PgPool, user_id: Uuid, code: &str) -> ApiResult<bool> {
+    let used: Option<Uuid> = sqlx::query_scalar(
+        r#"
+        UPDATE mfa_recovery_codes SET used_at = NOW()
+        WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+        RETURNING id
+        "#,
+    )
+    .bind(user_id)
+    .bind(hash_token(code))
+    .fetch_optional(db)
+    .await?;
+
+    Ok(used.is_some())
+}
+
+/// Check a TOTP or recovery code of a user with active MFA
+async fn verify_code(state: &AppState, user_id: Uuid, code: &str) -> ApiResult<bool> {
+    let enrollment = load_enrollment(state, user_id).await?;
+    if !enrollment.active {
+        return Ok(false);
+    }
+
+    let code = normalize_code(code);
+    if verify_totp(&state.db, user_id, &enrollment, &code).await? {
+        return Ok(true);
+    }
+    use_recovery_code(&state.db, user_id, &code).await
+}
+
+/// Replace the user's recovery codes and return the new ones
+async fn issue_recovery_codes(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    user_id: Uuid,
+) -> ApiResult<Vec<String>> {
+    sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| new_recovery_code())
+        .collect();
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|c| hash_token(&normalize_code(c)))
+        .collect();
+    sqlx::query(
+        "INSERT INTO mfa_recovery_codes (user_id, code_hash) SELECT $1, UNNEST($2::varchar[])",
+    )
+    .bind(user_id)
+    .bind(&hashes)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(codes)
+}
+
+/// Turn MFA on with a code from the newly set up authenticator
+///
+/// Returns the recovery codes, which are not shown again.
+pub async fn activate(state: &AppState, user_id: Uuid, code: &str) -> ApiResult<Vec<String>> {
+    let enrollment = load_enrollment(state, user_id).await?;
+    if enrollment.active {
+        return Err(AppError::BadRequest("MFA is already active".to_string()));
+    }
+    if enrollment.secret.is_none() {
+        return Err(AppError::BadRequest(
+            "Generate an MFA secret first".to_string(),
+        ));
+    }
+    if !verify_totp(&state.db, user_id, &enrollment, &normalize_code(code)).await? {
+        return Err(AppError::BadRequest("Invalid MFA code".to_string()));
+    }
+
+    let mut tx = state.db.begin().await?;
+    sqlx::query("UPDATE users SET mfa_active = true, updated_at = NOW() WHERE id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    let codes = issue_recovery_codes(&mut tx, user_id).await?;
+    tx.commit().await?;
+    sessions::forget_cached_sessions(state, user_id).await?;
+
+    Ok(codes)
+}
+
+/// Turn MFA off for a user after they confirmed with a code
+pub async fn deactivate(state: &AppState, user_id: Uuid, code: &str) -> ApiResult<()> {
+    if !verify_code(state, user_id, code).await? {
+        return Err(AppError::BadRequest("Invalid MFA code".to_string()));
+    }
+    reset(state, user_id).await
+}
+
+/// Remove a user's MFA enrollment without a code, for admins
+pub async fn reset(state: &AppState, user_id: Uuid) -> ApiResult<()> {
+    let mut tx = state.db.begin().await?;
+    let updated = sqlx::query(
+        r#"
+        UPDATE users SET mfa_active = false, mfa_secret_encrypted = NULL,
+            mfa_last_step = NULL, updated_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    if updated.rows_affected() == 0 {
+        return Err(AppError::NotFound("User not found".to_string()));
+    }
+    sqlx::query("DELETE FROM mfa_recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    sessions::forget_cached_sessions(state, user_id).await?;
+
+    Ok(())
+}
+
+/// Replace the recovery codes after the user confirmed with a code
+pub async fn regenerate_recovery_codes(
+    state: &AppState,
+    user_id: Uuid,
+    code: &str,
+) -> ApiResult<Vec<String>> {
+    if !verify_code(state, user_id, code).await? {
+        return Err(AppError::BadRequest("Invalid MFA code".to_string()));
+    }
+
+    let mut tx = state.db.begin().await?;
+    let codes = issue_recovery_codes(&mut tx, user_id).await?;
+    tx.commit().await?;
+
+    Ok(codes)
+}
+
+/// Require a valid code from users with active MFA
+///
+/// Call after the password was checked, so the MFA state of an account is
+/// not revealed to someone who does not know it.
+pub async fn check_login(state: &AppState, user: &User, token: Option<&str>) -> ApiResult<()> {
+    if !user.mfa_active {
+        return Ok(());
+    }
+
+    let token = token.map(str::trim).unwrap_or_default();
+    if token.is_empty() {
+        return Err(AppError::Unauthorized("MFA code required".to_string()));
+    }
+    if !verify_code(state, user.id, token).await? {
+        return Err(AppError::Unauthorized("Invalid MFA code".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Whether logging in as `login_id` needs a code
+pub async fn login_requires_mfa(db: &PgPool, login_id: &str) -> ApiResult<bool> {
+    let active = sqlx::query_scalar(
+        "SELECT mfa_active FROM users WHERE (email = $1 OR username = $1) AND is_active = true",
+    )
+    .bind(login_id)
+    .fetch_optional(db)
+    .await?;
+
+    Ok(active.unwrap_or(false))
+}
+
+/// Endpoints users can call before they enrolled
+fn allowed_before_enrollment(path: &str) -> bool {
+    matches!(
+        path,
+        "/api/v1/auth/me" | "/api/v1/auth/logout" | "/api/v4/users/me" | "/api/v4/users/logout"
+    ) || path.starts_with("/api/v1/auth/mfa/")
+        || (path.starts_with("/api/v4/users/")
+            && (path.ends_with("/mfa") || path.ends_with("/mfa/generate")))
+}
+
+/// Cache of the `enforce_mfa` setting
+#[derive(Default)]
+pub struct MfaPolicy {
+    enforced: RwLock<Option<(Instant, bool)>>,
+}
+
+impl MfaPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop the cached setting after it was edited
+    pub async fn invalidate(&self) {
+        *self.enforced.write().await = None;
+    }
+
+    async fn enforced(&self, db: &PgPool) -> ApiResult<bool> {
+        if let Some((loaded_at, enforced)) = *self.enforced.read().await {
+            if loaded_at.elapsed() < POLICY_CACHE_TTL {
+                return Ok(enforced);
+            }
+        }
+
+        let enforced: Option<bool> = sqlx::query_scalar(
+            "SELECT (authentication->>'enforce_mfa')::boolean FROM server_config WHERE id = 'default'",
+        )
+        .fetch_one(db)
+        .await?;
+        let enforced = enforced.unwrap_or(false);
+        *self.enforced.write().await = Some((Instant::now(), enforced));
+
+        Ok(enforced)
+    }
+}
+
+/// Block users without MFA while `enforce_mfa` is on, until they enroll
+///
+/// Bots cannot enroll and are exempt, as are sessions started through single
+/// sign-on, whose provider is responsible for the second factor. The same
+/// users signing in with a password are not. Whether a session is exempt
+/// comes with its cached check, see [`sessions::check`].
+pub async fn check_enrollment(
+    state: &AppState,
+    session: &SessionCheck,
+    path: &str,
+) -> ApiResult<()> {
+    if session.mfa_exempt || allowed_before_enrollment(path) {
+        return Ok(());
+    }
+
+    if state.mfa_policy.enforced(&state.db).await? {
+        return Err(AppError::Forbidden(
+            "Multi-factor authentication must be set up first".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238 appendix B, SHA-1, truncated to six digits
+    #[test]
+    fn totp_matches_rfc_vectors() {
+        let key = b"12345678901234567890";
+        assert_eq!("287082", code_at(key, 59 / PERIOD_SECS));
+        assert_eq!("081804", code_at(key, 1111111109 / PERIOD_SECS));
+        assert_eq!("050471", code_at(key, 1111111111 / PERIOD_SECS));
+        assert_eq!("005924", code_at(key, 1234567890 / PERIOD_SECS));
+        assert_eq!("279037", code_at(key, 2000000000 / PERIOD_SECS));
+    }
+
+    #[test]
+    fn codes_from_adjacent_steps_are_accepted() {
+        let key = b"12345678901234567890";
+        let now = 1000;
+        assert_eq!(Some(999), matching_step(key, &code_at(key, 999), now));
+        assert_eq!(Some(1001), matching_step(key, &code_at(key, 1001), now));
+        assert_eq!(None, matching_step(key, &code_at(key, 1003), now));
+        assert_eq!(None, matching_step(key, "12345", now));
+    }
+
+    #[test]
+    fn recovery_codes_are_normalized() {
+        let code = new_recovery_code();
+        assert_eq!(19, code.len());
+        assert_eq!(
+            normalize_code(&code),
+            normalize_code(&format!(" {} ", code.to_uppercase()))
+        );
+    }
+}
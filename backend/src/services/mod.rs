@@ -1,7 +1,34 @@
 //! Services module
 
+pub mod access_tokens;
+pub mod account_tokens;
 pub mod auth_config;
+pub mod builtin_commands;
+pub mod client_address;
 pub mod email;
+pub mod email_notifications;
+pub mod email_templates;
+pub mod file_search;
+pub mod guests;
+pub mod image_metadata;
+pub mod ldap;
+pub mod login_lockout;
+pub mod media;
+pub mod mfa;
 pub mod mirotalk;
+pub mod oidc;
+pub mod outgoing_webhooks;
+pub mod permissions;
 pub mod posts;
+pub mod push_notifications;
+pub mod rate_limit;
+pub mod saml;
+pub mod sessions;
+pub mod slash_commands;
+pub mod sso;
+pub mod team_invites;
+pub mod text_extraction;
 pub mod unreads;
+pub mod uploads;
+pub mod virus_scan;
+pub mod xmldsig;
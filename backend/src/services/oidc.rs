@@ -0,0 +1,364 @@
+//! OpenID Connect client
+//!
+//! Implements the authorization code flow with PKCE. Provider endpoints come
+//! from discovery on the config's `issuer_url`. ID tokens must be signed by a
+//! key from the provider's JWKS, be issued by that issuer for our client ID,
+//! and carry the nonce of the login that asked for them. Claims from the
+//! userinfo endpoint are added to the ID token's when the provider has one.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use jsonwebtoken::jwk::{AlgorithmParameters, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+
+use crate::error::{ApiResult, AppError};
+use crate::models::SsoConfig;
+use crate::services::sso::ExternalIdentity;
+
+/// Endpoints from the provider's discovery document
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: Option<String>,
+    pub jwks_uri: String,
+}
+
+/// Tokens returned by the token endpoint
+#[derive(Debug, Clone, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub id_token: Option<String>,
+}
+
+type Claims = Map<String, Value>;
+
+fn provider_error(what: &str, e: impl std::fmt::Display) -> AppError {
+    AppError::ExternalService(format!("{}: {}", what, e))
+}
+
+/// Fetch the provider's discovery document from `issuer_url`
+pub async fn discover(http: &reqwest::Client, issuer_url: &str) -> ApiResult<ProviderMetadata> {
+    let issuer_url = issuer_url.trim_end_matches('/');
+    let metadata: ProviderMetadata = http
+        .get(format!("{}/.well-known/openid-configuration", issuer_url))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| provider_error("OIDC discovery failed", e))?
+        .json()
+        .await
+        .map_err(|e| provider_error("Invalid OIDC discovery document", e))?;
+
+    if metadata.issuer.trim_end_matches('/') != issuer_url {
+        return Err(AppError::ExternalService(format!(
+            "OIDC discovery returned issuer {} for {}",
+            metadata.issuer, issuer_url
+        )));
+    }
+
+    Ok(metadata)
+}
+
+/// The S256 PKCE challenge for `verifier`
+pub fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+/// URL that starts a login at the provider
+pub fn authorization_url(
+    metadata: &ProviderMetadata,
+    config: &SsoConfig,
+    redirect_uri: &str,
+    state: &str,
+    nonce: &str,
+    code_verifier: &str,
+) -> ApiResult<String> {
+    let mut scopes = config.scopes.clone();
+    if !scopes.iter().any(|s| s == "openid") {
+        scopes.insert(0, "openid".to_string());
+    }
+
+    let mut url = url::Url::parse(&metadata.authorization_endpoint)
+        .map_err(|e| provider_error("Invalid authorization endpoint", e))?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", config.client_id.as_deref().unwrap_or_default())
+        .append_pair("redirect_uri", redirect_uri)
+        .append_pair("scope", &scopes.join(" "))
+        .append_pair("state", state)
+        .append_pair("nonce", nonce)
+        .append_pair("code_challenge", &pkce_challenge(code_verifier))
+        .append_pair("code_challenge_method", "S256");
+
+    Ok(url.into())
+}
+
+/// Exchange an authorization code for tokens
+pub async fn exchange_code(
+    http: &reqwest::Client,
+    metadata: &ProviderMetadata,
+    client_id: &str,
+    client_secret: &str,
+    code: &str,
+    redirect_uri: &str,
+    code_verifier: &str,
+) -> ApiResult<TokenResponse> {
+    let response = http
+        .post(&metadata.token_endpoint)
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", client_id),
+            ("client_secret", client_secret),
+            ("code_verifier", code_verifier),
+        ])
+        .send()
+        .await
+        .map_err(|e| provider_error("Token exchange failed", e))?;
+
+    if !response.status().is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(AppError::ExternalService(format!(
+            "Token exchange failed: {}",
+            body
+        )));
+    }
+
+    response
+        .json()
+        .await
+        .map_err(|e| provider_error("Invalid token response", e))
+}
+
+fn invalid_token(reason: impl std::fmt::Display) -> AppError {
+    AppError::Unauthorized(format!("Invalid ID token: {}", reason))
+}
+
+/// Check an ID token and return its claims
+pub async fn validate_id_token(
+    http: &reqwest::Client,
+    metadata: &ProviderMetadata,
+    client_id: &str,
+    id_token: &str,
+    nonce: &str,
+) -> ApiResult<Claims> {
+    let header = jsonwebtoken::decode_header(id_token).map_err(invalid_token)?;
+    // Symmetric algorithms would verify with a key the client also knows
+    if matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err(invalid_token("unsupported signing algorithm"));
+    }
+
+    let jwks: JwkSet = http
+        .get(&metadata.jwks_uri)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| provider_error("Failed to fetch JWKS", e))?
+        .json()
+        .await
+        .map_err(|e| provider_error("Invalid JWKS", e))?;
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or_else(|| invalid_token("signing key not found"))?;
+    if matches!(jwk.algorithm, AlgorithmParameters::OctetKey(_)) {
+        return Err(invalid_token("unsupported signing key"));
+    }
+    let key = DecodingKey::from_jwk(jwk).map_err(invalid_token)?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[client_id]);
+    validation.set_issuer(&[metadata.issuer.as_str()]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+    let claims = jsonwebtoken::decode::<Claims>(id_token, &key, &validation)
+        .map_err(invalid_token)?
+        .claims;
+
+    if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+        return Err(invalid_token("nonce mismatch"));
+    }
+
+    Ok(claims)
+}
+
+/// Add the userinfo endpoint's claims to the ID token's
+pub async fn merge_userinfo(
+    http: &reqwest::Client,
+    metadata: &ProviderMetadata,
+    access_token: &str,
+    claims: &mut Claims,
+) -> ApiResult<()> {
+    let Some(endpoint) = &metadata.userinfo_endpoint else {
+        return Ok(());
+    };
+
+    let userinfo: Claims = http
+        .get(endpoint)
+        .bearer_auth(access_token)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| provider_error("Userinfo request failed", e))?
+        .json()
+        .await
+        .map_err(|e| provider_error("Invalid userinfo response", e))?;
+
+    // Userinfo for another subject must be ignored
+    if userinfo.get("sub") != claims.get("sub") {
+        return Err(AppError::ExternalService(
+            "Userinfo subject does not match the ID token".to_string(),
+        ));
+    }
+    for (name, value) in userinfo {
+        claims.entry(name).or_insert(value);
+    }
+
+    Ok(())
+}
+
+fn string_claim(claims: &Claims, name: &str) -> Option<String> {
+    claims
+        .get(name)
+        .and_then(Value::as_str)
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+}
+
+/// Map claims to a profile using the config's claim names
+pub fn map_claims(config: &SsoConfig, claims: &Claims) -> ApiResult<ExternalIdentity> {
+    let subject = string_claim(claims, "sub").ok_or_else(|| invalid_token("missing sub"))?;
+    let email = string_claim(claims, &config.email_claim)
+        .filter(|email| email.contains('@'))
+        .ok_or_else(|| {
+            AppError::BadRequest(
+                "The identity provider did not return an email address".to_string(),
+            )
+        })?;
+    let display_name = string_claim(claims, &config.display_name_claim).or_else(|| {
+        let given = string_claim(claims, "given_name");
+        let family = string_claim(claims, "family_name");
+        match (given, family) {
+            (Some(given), Some(family)) => Some(format!("{} {}", given, family)),
+            (given, family) => given.or(family),
+        }
+    });
+    // Providers send booleans, some send strings
+    let email_verified = match claims.get("email_verified") {
+        Some(Value::Bool(verified)) => *verified,
+        Some(Value::String(verified)) => verified == "true",
+        _ => false,
+    };
+
+    Ok(ExternalIdentity {
+        subject,
+        email,
+        email_verified,
+        username: string_claim(claims, &config.username_claim),
+        display_name,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    fn config() -> SsoConfig {
+        SsoConfig {
+            id: Uuid::new_v4(),
+            org_id: Uuid::new_v4(),
+            provider: "oidc".to_string(),
+            display_name: None,
+            issuer_url: Some("https://idp.example.com".to_string()),
+            client_id: Some("rustchat".to_string()),
+            client_secret_encrypted: None,
+            scopes: vec!["profile".to_string(), "email".to_string()],
+            idp_metadata_url: None,
+            idp_entity_id: None,
+            sp_private_key_encrypted: None,
+            sp_certificate: None,
+            is_active: true,
+            auto_provision: true,
+            default_role: None,
+            username_claim: "preferred_username".to_string(),
+            display_name_claim: "name".to_string(),
+            email_claim: "email".to_string(),
+            link_by_email: false,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn claims(value: Value) -> Claims {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn pkce_challenge_matches_rfc_7636() {
+        assert_eq!(
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM",
+            pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk")
+        );
+    }
+
+    #[test]
+    fn authorization_url_always_asks_for_openid() {
+        let metadata = ProviderMetadata {
+            issuer: "https://idp.example.com".to_string(),
+            authorization_endpoint: "https://idp.example.com/authorize".to_string(),
+            token_endpoint: "https://idp.example.com/token".to_string(),
+            userinfo_endpoint: None,
+            jwks_uri: "https://idp.example.com/jwks".to_string(),
+        };
+        let url =
+            authorization_url(&metadata, &config(), "https://chat/cb", "s", "n", "v").unwrap();
+        let url = url::Url::parse(&url).unwrap();
+        let query: std::collections::HashMap<_, _> = url.query_pairs().collect();
+
+        assert_eq!("openid profile email", query["scope"]);
+        assert_eq!("S256", query["code_challenge_method"]);
+        assert_eq!(pkce_challenge("v"), query["code_challenge"]);
+    }
+
+    #[test]
+    fn claims_map_through_configured_names() {
+        let mut config = config();
+        config.username_claim = "upn".to_string();
+        let identity = map_claims(
+            &config,
+            &claims(json!({
+                "sub": "abc",
+                "email": "jane@example.com",
+                "email_verified": "true",
+                "upn": "jdoe",
+                "given_name": "Jane",
+                "family_name": "Doe"
+            })),
+        )
+        .unwrap();
+
+        assert_eq!("abc", identity.subject);
+        assert_eq!(Some("jdoe".to_string()), identity.username);
+        assert_eq!(Some("Jane Doe".to_string()), identity.display_name);
+        assert!(identity.email_verified);
+    }
+
+    #[test]
+    fn claims_without_email_are_rejected() {
+        assert!(map_claims(&config(), &claims(json!({ "sub": "abc" }))).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+//! Outgoing webhook execution
+//!
+//! When a post is created in a public channel, every active outgoing webhook
+//! of the channel's team whose trigger matches is called with a
+//! Mattermost-compatible payload. A callback may answer with a message, which
+//! is posted back to the channel on behalf of the webhook creator.
+
+use std::time::Duration;
+
+use sqlx::PgPool;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::error::ApiResult;
+use crate::mattermost_compat::id::encode_mm_id;
+use crate::models::{
+    CreatePost, IntegrationsConfig, OutgoingWebhook, OutgoingWebhookPayload,
+    OutgoingWebhookResponse, PostResponse,
+};
+
+/// Maximum time to wait for a callback URL to answer
+const CALLBACK_TIMEOUT_SECS: u64 = 10;
+
+/// Channel and team details included in the payload
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct PostContext {
+    team_id: Uuid,
+    team_name: String,
+    channel_name: String,
+}
+
+/// Evaluate outgoing webhooks for a new post in the background
+pub fn spawn_outgoing_webhooks(state: &AppState, post: &PostResponse) {
+    if !is_triggering_post(post) {
+        return;
+    }
+
+    let state = state.clone();
+    let post = post.clone();
+    tokio::spawn(async move {
+        if let Err(e) = execute_outgoing_webhooks(&state, &post).await {
+            warn!("Outgoing webhooks for post {} failed: {}", post.id, e);
+        }
+    });
+}
+
+/// Run every outgoing webhook matching `post` and wait for them to finish
+///
+/// Returns the number of webhooks that were triggered.
+pub async fn execute_outgoing_webhooks(state: &AppState, post: &PostResponse) -> ApiResult<usize> {
+    if !is_triggering_post(post) || !webhooks_enabled(&state.db).await? {
+        return Ok(0);
+    }
+
+    // Outgoing webhooks only fire in public channels
+    let context: Option<PostContext> = sqlx::query_as(
+        r#"
+        SELECT c.team_id, t.name AS team_name, c.name AS channel_name
+        FROM channels c
+        JOIN teams t ON t.id = c.team_id
+        WHERE c.id = $1 AND c.type = 'public'
+        "#,
+    )
+    .bind(post.channel_id)
+    .fetch_optional(&state.db)
+    .await?;
+
+    let Some(context) = context else {
+        return Ok(0);
+    };
+
+    let hooks: Vec<OutgoingWebhook> = sqlx::query_as(
+        r#"
+        SELECT * FROM outgoing_webhooks
+        WHERE team_id = $1 AND is_active = true
+          AND (channel_id IS NULL OR channel_id = $2)
+        "#,
+    )
+    .bind(context.team_id)
+    .bind(post.channel_id)
+    .fetch_all(&state.db)
+    .await?;
+
+    let mut triggered = 0;
+    for hook in hooks {
+        let Some(trigger_word) = match_trigger(&hook, &post.message) else {
+            continue;
+        };
+        triggered += 1;
+
+        let payload = build_payload(&hook, post, &context, trigger_word);
+        for url in &hook.callback_urls {
+            match call_webhook(state, &hook, url, &payload).await {
+                Ok(Some(response)) => {
+                    if let Err(e) = post_response(state, &hook, post, response).await {
+                        warn!("Failed to post response of outgoing webhook {}: {}", hook.id, e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("Outgoing webhook {} call to {} failed: {}", hook.id, url, e);
+                }
+            }
+        }
+    }
+
+    Ok(triggered)
+}
+
+/// Posts made by integrations and system messages never trigger webhooks
+fn is_triggering_post(post: &PostResponse) -> bool {
+    if post.props.get("from_webhook").and_then(|v| v.as_str()) == Some("true") {
+        return false;
+    }
+    if let Some(post_type) = post.props.get("type").and_then(|v| v.as_str()) {
+        if post_type.starts_with("system_") {
+            return false;
+        }
+    }
+    !post.message.trim().is_empty()
+}
+
+async fn webhooks_enabled(db: &PgPool) -> ApiResult<bool> {
+    let config: Option<sqlx::types::Json<IntegrationsConfig>> =
+        sqlx::query_scalar("SELECT integrations FROM server_config WHERE id = 'default'")
+            .fetch_optional(db)
+            .await?;
+
+    Ok(config.map(|c| c.0.enable_webhooks).unwrap_or(true))
+}
+
+/// Return the trigger word that matched, or `None` if the hook should not fire
+///
+/// A hook bound to a channel without trigger words fires on every post.
+fn match_trigger<'a>(hook: &'a OutgoingWebhook, message: &str) -> Option<&'a str> {
+    let triggers: Vec<&str> = hook
+        .trigger_words
+        .iter()
+        .map(|w| w.trim())
+        .filter(|w| !w.is_empty())
+        .collect();
+
+    if triggers.is_empty() {
+        return hook.channel_id.map(|_| "");
+    }
+
+    let first_word = message.split_whitespace().next()?;
+    match hook.trigger_when.as_str() {
+        "starts_with" | "any" => triggers.into_iter().find(|t| first_word.starts_with(t)),
+        _ => triggers.into_iter().find(|t| first_word == *t),
+    }
+}
+
+fn build_payload(
+    hook: &OutgoingWebhook,
+    post: &PostResponse,
+    context: &PostContext,
+    trigger_word: &str,
+) -> OutgoingWebhookPayload {
+    OutgoingWebhookPayload {
+        token: hook.token.clone(),
+        team_id: encode_mm_id(context.team_id),
+        team_domain: context.team_name.clone(),
+        channel_id: encode_mm_id(post.channel_id),
+        channel_name: context.channel_name.clone(),
+        timestamp: post.created_at.timestamp_millis(),
+        user_id: encode_mm_id(post.user_id),
+        user_name: post.username.clone().unwrap_or_default(),
+        post_id: encode_mm_id(post.id),
+        text: post.message.clone(),
+        trigger_word: trigger_word.to_string(),
+        file_ids: post
+            .file_ids
+            .iter()
+            .map(|id| encode_mm_id(*id))
+            .collect::<Vec<_>>()
+            .join(","),
+    }
+}
+
+/// Send the payload to one callback URL and parse its response, if any
+async fn call_webhook(
+    state: &AppState,
+    hook: &OutgoingWebhook,
+    url: &str,
+    payload: &OutgoingWebhookPayload,
+) -> Result<Option<OutgoingWebhookResponse>, reqwest::Error> {
+    let request = state
+        .http_client
+        .post(url)
+        .timeout(Duration::from_secs(CALLBACK_TIMEOUT_SECS));
+
+    let request = match hook.content_type.as_deref() {
+        Some("application/x-www-form-urlencoded") => request.form(payload),
+        _ => request.json(payload),
+    };
+
+    let body = request.send().await?.error_for_status()?.bytes().await?;
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(None);
+    }
+
+    // Callbacks are free to answer with anything; only JSON bodies are posted
+    Ok(serde_json::from_slice(&body).ok())
+}
+
+/// Post a callback response in the channel as the webhook creator
+async fn post_response(
+    state: &AppState,
+    hook: &OutgoingWebhook,
+    trigger: &PostResponse,
+    response: OutgoingWebhookResponse,
+) -> ApiResult<Option<PostResponse>> {
+    let text = response.text.unwrap_or_default();
+    if text.trim().is_empty() && response.attachments.is_none() {
+        return Ok(None);
+    }
+
+    let mut props = match response.props {
+        Some(serde_json::Value::Object(map)) => map,
+        _ => serde_json::Map::new(),
+    };
+    props.insert("from_webhook".to_string(), "true".into());
+    if let Some(username) = response.username.filter(|u| !u.is_empty()) {
+        props.insert("override_username".to_string(), username.into());
+    }
+    if let Some(icon_url) = response.icon_url.filter(|u| !u.is_empty()) {
+        props.insert("override_icon_url".to_string(), icon_url.into());
+    }
+    if let Some(attachments) = response.attachments {
+        props.insert("attachments".to_string(), attachments);
+    }
+
+    let root_post_id = match response.response_type.as_deref() {
+        Some("comment") => Some(trigger.root_post_id.unwrap_or(trigger.id)),
+        _ => None,
+    };
+
+    let input = CreatePost {
+        message: text,
+        root_post_id,
+        props: Some(serde_json::Value::Object(props)),
+        file_ids: vec![],
+    };
+
+    let post =
+        crate::services::posts::publish_post(state, hook.creator_id, trigger.channel_id, input, None)
+            .await?;
+
+    Ok(Some(post))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn hook(trigger_words: &[&str], trigger_when: &str, channel_id: Option<Uuid>) -> OutgoingWebhook {
+        OutgoingWebhook {
+            id: Uuid::new_v4(),
+            team_id: Uuid::new_v4(),
+            channel_id,
+            creator_id: Uuid::new_v4(),
+            display_name: None,
+            description: None,
+            trigger_words: trigger_words.iter().map(|w| w.to_string()).collect(),
+            trigger_when: trigger_when.to_string(),
+            callback_urls: vec![],
+            content_type: None,
+            token: String::new(),
+            is_active: true,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn first_word_requires_exact_match() {
+        let h = hook(&["deploy"], "first_word", None);
+        assert_eq!(Some("deploy"), match_trigger(&h, "deploy now"));
+        assert_eq!(None, match_trigger(&h, "deployment now"));
+        assert_eq!(None, match_trigger(&h, "please deploy"));
+    }
+
+    #[test]
+    fn starts_with_matches_prefix() {
+        let h = hook(&["dep"], "starts_with", None);
+        assert_eq!(Some("dep"), match_trigger(&h, "deployment now"));
+        assert_eq!(None, match_trigger(&h, "now deploy"));
+    }
+
+    #[test]
+    fn channel_hook_without_triggers_fires_on_everything() {
+        let h = hook(&[], "first_word", Some(Uuid::new_v4()));
+        assert_eq!(Some(""), match_trigger(&h, "anything"));
+
+        let team_wide = hook(&[], "first_word", None);
+        assert_eq!(None, match_trigger(&team_wide, "anything"));
+    }
+}
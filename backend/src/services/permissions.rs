@@ -0,0 +1,364 @@
+//! Role-based permission checks
+//!
+//! A user's permissions in a scope are the union of the permissions of every
+//! role they hold there: their system role, plus `team_admin` in teams where
+//! they are an admin or owner, plus `channel_user` in channels they belong
+//! to and `channel_admin` in channels they admin.
+//! Role permissions come from `role_permissions` and are cached per role for
+//! a short time; the admin role editor drops the cache entry when it saves.
+//! `system_admin` always has every permission so it cannot be locked out.
+
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::{extract::FromRequestParts, http::request::Parts};
+use tokio::sync::RwLock;
+use uuid::Uuid;
+
+use crate::api::v4::extractors::MmAuthUser;
+use crate::api::AppState;
+use crate::auth::middleware::FromRef;
+use crate::auth::AuthUser;
+use crate::error::{ApiResult, AppError};
+
+/// How long a role's permissions are cached
+const ROLE_CACHE_TTL: Duration = Duration::from_secs(60);
+
+pub const SYSTEM_ADMIN_ROLE: &str = "system_admin";
+pub const TEAM_ADMIN_ROLE: &str = "team_admin";
+pub const CHANNEL_USER_ROLE: &str = "channel_user";
+pub const CHANNEL_ADMIN_ROLE: &str = "channel_admin";
+
+/// Permissions that can be granted to roles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    ChannelCreate,
+    ChannelDelete,
+    ChannelManage,
+    ChannelManageMembers,
+    ChannelManageRoles,
+    PostCreate,
+    PostEditOwn,
+    PostEditOthers,
+    PostDeleteOwn,
+    PostDeleteOthers,
+    PostPin,
+    UserManage,
+    TeamCreate,
+    TeamManage,
+    TeamManageMembers,
+    WebhookCreate,
+    WebhookManage,
+    CommandCreate,
+    CommandManage,
+    BotCreate,
+    BotManage,
+    PlaybookManage,
+}
+
+impl Permission {
+    /// ID of the permission in the `permissions` table
+    pub fn id(self) -> &'static str {
+        match self {
+            Self::ChannelCreate => "channel.create",
+            Self::ChannelDelete => "channel.delete",
+            Self::ChannelManage => "channel.manage",
+            Self::ChannelManageMembers => "channel.manage_members",
+            Self::ChannelManageRoles => "channel.manage_roles",
+            Self::PostCreate => "post.create",
+            Self::PostEditOwn => "post.edit_own",
+            Self::PostEditOthers => "post.edit_others",
+            Self::PostDeleteOwn => "post.delete_own",
+            Self::PostDeleteOthers => "post.delete_others",
+            Self::PostPin => "post.pin",
+            Self::UserManage => "user.manage",
+            Self::TeamCreate => "team.create",
+            Self::TeamManage => "team.manage",
+            Self::TeamManageMembers => "team.manage_members",
+            Self::WebhookCreate => "webhook.create",
+            Self::WebhookManage => "webhook.manage",
+            Self::CommandCreate => "command.create",
+            Self::CommandManage => "command.manage",
+            Self::BotCreate => "bot.create",
+            Self::BotManage => "bot.manage",
+            Self::PlaybookManage => "playbook.manage",
+        }
+    }
+}
+
+/// Where a permission is checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    System,
+    Team(Uuid),
+    Channel(Uuid),
+}
+
+type RolePermissions = Arc<HashSet<String>>;
+
+/// Cache of each role's permission IDs
+#[derive(Default)]
+pub struct PermissionCache {
+    roles: RwLock<HashMap<String, (Instant, RolePermissions)>>,
+}
+
+impl PermissionCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop a role's cached permissions after they were edited
+    pub async fn invalidate(&self, role: &str) {
+        self.roles.write().await.remove(role);
+    }
+
+    async fn role_permissions(
+        &self,
+        db: &sqlx::PgPool,
+        role: &str,
+    ) -> ApiResult<RolePermissions> {
+        if let Some((loaded_at, permissions)) = self.roles.read().await.get(role) {
+            if loaded_at.elapsed() < ROLE_CACHE_TTL {
+                return Ok(permissions.clone());
+            }
+        }
+
+        let ids: Vec<String> =
+            sqlx::query_scalar("SELECT permission_id FROM role_permissions WHERE role = $1")
+                .bind(role)
+                .fetch_all(db)
+                .await?;
+        let permissions = Arc::new(ids.into_iter().collect::<HashSet<_>>());
+        self.roles
+            .write()
+            .await
+            .insert(role.to_string(), (Instant::now(), permissions.clone()));
+
+        Ok(permissions)
+    }
+}
+
+/// Roles `user_id` holds in `scope` besides their system role
+async fn scheme_roles(
+    state: &AppState,
+    user_id: Uuid,
+    scope: Scope,
+) -> ApiResult<Vec<&'static str>> {
+    let (team_role, channel_role): (Option<String>, Option<String>) = match scope {
+        Scope::System => return Ok(Vec::new()),
+        Scope::Team(team_id) => {
+            let team_role = sqlx::query_scalar(
+                "SELECT role FROM team_members WHERE team_id = $1 AND user_id = $2",
+            )
+            .bind(team_id)
+            .bind(user_id)
+            .fetch_optional(&state.db)
+            .await?;
+            (team_role, None)
+        }
+        Scope::Channel(channel_id) => sqlx::query_as(
+            r#"
+            SELECT tm.role, cm.role
+            FROM channels c
+            LEFT JOIN team_members tm ON tm.team_id = c.team_id AND tm.user_id = $2
+            LEFT JOIN channel_members cm ON cm.channel_id = c.id AND cm.user_id = $2
+            WHERE c.id = $1
+            "#,
+        )
+        .bind(channel_id)
+        .bind(user_id)
+        .fetch_optional(&state.db)
+        .await?
+        .unwrap_or((None, None)),
+    };
+
+    let mut roles = Vec::new();
+    if matches!(team_role.as_deref(), Some("admin") | Some("owner")) {
+        roles.push(TEAM_ADMIN_ROLE);
+    }
+    if channel_role.is_some() {
+        roles.push(CHANNEL_USER_ROLE);
+    }
+    if channel_role.as_deref() == Some("admin") {
+        roles.push(CHANNEL_ADMIN_ROLE);
+    }
+
+    Ok(roles)
+}
+
+/// Whether a user with `system_role` has `permission` in `scope`
+pub async fn has_permission(
+    state: &AppState,
+    user_id: Uuid,
+    system_role: &str,
+    permission: Permission,
+    scope: Scope,
+) -> ApiResult<bool> {
+    if system_role == SYSTEM_ADMIN_ROLE {
+        return Ok(true);
+    }
+
+    let cache = &state.permissions;
+    if cache
+        .role_permissions(&state.db, system_role)
+        .await?
+        .contains(permission.id())
+    {
+        return Ok(true);
+    }
+    for role in scheme_roles(state, user_id, scope).await? {
+        if cache
+            .role_permissions(&state.db, role)
+            .await?
+            .contains(permission.id())
+        {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Like [`has_permission`], for callers that only know the user ID
+pub async fn user_has_permission(
+    state: &AppState,
+    user_id: Uuid,
+    permission: Permission,
+    scope: Scope,
+) -> ApiResult<bool> {
+    let role: String = sqlx::query_scalar("SELECT role FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&state.db)
+        .await?;
+
+    has_permission(state, user_id, &role, permission, scope).await
+}
+
+/// Fail with 403 unless `user_id` belongs to the team
+pub async fn ensure_team_member(state: &AppState, user_id: Uuid, team_id: Uuid) -> ApiResult<()> {
+    let is_member: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM team_members WHERE user_id = $1 AND team_id = $2)",
+    )
+    .bind(user_id)
+    .bind(team_id)
+    .fetch_one(&state.db)
+    .await?;
+
+    if !is_member {
+        return Err(AppError::Forbidden("User is not a member of the team".to_string()));
+    }
+
+    Ok(())
+}
+
+fn denied(permission: Permission) -> AppError {
+    AppError::Forbidden(format!("Missing permission {}", permission.id()))
+}
+
+/// An authenticated caller whose permissions can be checked
+pub trait Principal {
+    fn user_id(&self) -> Uuid;
+    fn role(&self) -> &str;
+
+    /// Whether the caller has `permission` in `scope`
+    fn can(
+        &self,
+        state: &AppState,
+        permission: Permission,
+        scope: Scope,
+    ) -> impl std::future::Future<Output = ApiResult<bool>> + Send
+    where
+        Self: Sync,
+    {
+        async move { has_permission(state, self.user_id(), self.role(), permission, scope).await }
+    }
+
+    /// Fail with 403 unless the caller has `permission` in `scope`
+    fn require(
+        &self,
+        state: &AppState,
+        permission: Permission,
+        scope: Scope,
+    ) -> impl std::future::Future<Output = ApiResult<()>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            if self.can(state, permission, scope).await? {
+                Ok(())
+            } else {
+                Err(denied(permission))
+            }
+        }
+    }
+}
+
+impl Principal for AuthUser {
+    fn user_id(&self) -> Uuid {
+        self.user_id
+    }
+
+    fn role(&self) -> &str {
+        &self.role
+    }
+}
+
+impl Principal for MmAuthUser {
+    fn user_id(&self) -> Uuid {
+        self.user_id
+    }
+
+    fn role(&self) -> &str {
+        &self.role
+    }
+}
+
+/// A system-wide permission usable with [`Require`]
+pub trait SystemPermission {
+    const PERMISSION: Permission;
+}
+
+macro_rules! system_permissions {
+    ($($name:ident => $permission:ident),* $(,)?) => {
+        $(
+            pub struct $name;
+
+            impl SystemPermission for $name {
+                const PERMISSION: Permission = Permission::$permission;
+            }
+        )*
+    };
+}
+
+system_permissions! {
+    CanCreateTeam => TeamCreate,
+    CanCreateBot => BotCreate,
+    CanManageUsers => UserManage,
+}
+
+/// Extractor that authenticates the caller and checks a system permission
+///
+/// `Require<CanCreateTeam>` rejects callers without `team.create`; use
+/// `Require<CanCreateBot, MmAuthUser>` on Mattermost routes.
+pub struct Require<P, A = AuthUser>(pub A, pub PhantomData<P>);
+
+impl<S, P, A> FromRequestParts<S> for Require<P, A>
+where
+    S: Send + Sync,
+    AppState: FromRef<S>,
+    P: SystemPermission,
+    A: FromRequestParts<S, Rejection = AppError> + Principal + Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let auth = A::from_request_parts(parts, state).await?;
+        let app_state = AppState::from_ref(state);
+        auth.require(&app_state, P::PERMISSION, Scope::System)
+            .await?;
+
+        Ok(Self(auth, PhantomData))
+    }
+}
@@ -5,6 +5,8 @@ use crate::api::AppState;
 use crate::error::{ApiResult, AppError};
 use crate::models::{ChannelMember, CreatePost, FileUploadResponse, Post, PostResponse};
 use crate::realtime::{EventType, WsBroadcast, WsEnvelope};
+use crate::services::media;
+use crate::services::permissions::{self, Permission, Scope};
 
 #[derive(Debug, Default)]
 pub struct PostsQuery {
@@ -23,7 +25,16 @@ pub async fn create_post(
     input: CreatePost,
     client_msg_id: Option<String>,
 ) -> ApiResult<PostResponse> {
-    ensure_permission(state, user_id, "post.create").await?;
+    let allowed = permissions::user_has_permission(
+        state,
+        user_id,
+        Permission::PostCreate,
+        Scope::Channel(channel_id),
+    )
+    .await?;
+    if !allowed {
+        return Err(AppError::Forbidden("Insufficient permissions".to_string()));
+    }
 
     // Check membership
     let _: ChannelMember =
@@ -34,6 +45,20 @@ pub async fn create_post(
             .await?
             .ok_or_else(|| AppError::Forbidden("Not a member of this channel".to_string()))?;
 
+    publish_post(state, user_id, channel_id, input, client_msg_id).await
+}
+
+/// Publish a post without permission or membership checks
+///
+/// Used for posts made on behalf of integrations (webhook and command
+/// responses), whose author is not necessarily a member of the channel.
+pub async fn publish_post(
+    state: &AppState,
+    user_id: Uuid,
+    channel_id: Uuid,
+    input: CreatePost,
+    client_msg_id: Option<String>,
+) -> ApiResult<PostResponse> {
     // Validate message
     if input.message.trim().is_empty() && input.file_ids.is_empty() {
         return Err(AppError::Validation("Message cannot be empty".to_string()));
@@ -81,6 +106,22 @@ pub async fn create_post(
     .fetch_one(&state.db)
     .await?;
 
+    // Files belong to the first post of their uploader that shares them
+    if !post.file_ids.is_empty() {
+        sqlx::query(
+            r#"
+            UPDATE files SET post_id = $1, channel_id = $2
+            WHERE id = ANY($3) AND uploader_id = $4 AND post_id IS NULL
+            "#,
+        )
+        .bind(post.id)
+        .bind(channel_id)
+        .bind(&post.file_ids)
+        .bind(user_id)
+        .execute(&state.db)
+        .await?;
+    }
+
     // If this is a reply, update the root post
     if let Some(r_id) = root_post_id {
         sqlx::query(
@@ -218,32 +259,14 @@ pub async fn create_post(
         response.props = serde_json::Value::Object(props);
     }
 
-    Ok(response)
-}
-
-async fn ensure_permission(
-    state: &AppState,
-    user_id: Uuid,
-    permission: &str,
-) -> ApiResult<()> {
-    let role: String = sqlx::query_scalar("SELECT role FROM users WHERE id = $1")
-        .bind(user_id)
-        .fetch_one(&state.db)
-        .await?;
-
-    let allowed: bool = sqlx::query_scalar(
-        "SELECT EXISTS(SELECT 1 FROM role_permissions WHERE role = $1 AND permission_id = $2)",
-    )
-    .bind(&role)
-    .bind(permission)
-    .fetch_one(&state.db)
-    .await?;
+    // Fire outgoing webhooks without holding up the response
+    crate::services::outgoing_webhooks::spawn_outgoing_webhooks(state, &response);
 
-    if !allowed {
-        return Err(AppError::Forbidden("Insufficient permissions".to_string()));
-    }
+    // Notify members on their mobile devices
+    crate::services::push_notifications::spawn_post_notifications(state, &response);
+    crate::services::email_notifications::spawn_queue_notifications(state, &response);
 
-    Ok(())
+    Ok(response)
 }
 
 /// Helper to ensure all participants of a DM are members (resurrects DM)
@@ -312,28 +335,30 @@ pub async fn populate_files(state: &AppState, posts: &mut [PostResponse]) -> Api
         return Ok(());
     }
 
-    // 2. Fetch file infos
+    // 2. Fetch file infos, leaving out quarantined files
     // crate::models::FileInfo is needed, ensure it is pub
-    let files: Vec<crate::models::FileInfo> =
-        sqlx::query_as("SELECT * FROM files WHERE id = ANY($1)")
-            .bind(&all_file_ids)
-            .fetch_all(&state.db)
-            .await?;
+    let files: Vec<crate::models::FileInfo> = sqlx::query_as(
+        r#"
+        SELECT * FROM files
+        WHERE id = ANY($1) AND COALESCE(scan_status, 'clean') = 'clean'
+        "#,
+    )
+    .bind(&all_file_ids)
+    .fetch_all(&state.db)
+    .await?;
 
     // 3. Generate presigned URLs and map to posts
     let mut file_map = HashMap::new();
     for file in files {
-        let url = state
-            .s3_client
-            .presigned_download_url(&file.key, 3600)
-            .await?;
+        // Images still carrying their metadata are left out until processed
+        if media::ensure_servable(&file).is_err() {
+            continue;
+        }
+        let backend = state.storage.backend(&file.backend)?;
+        let url = backend.presigned_download_url(&file.key, 3600).await?;
         let thumbnail_url = if file.has_thumbnail {
             if let Some(t_key) = &file.thumbnail_key {
-                state
-                    .s3_client
-                    .presigned_download_url(t_key, 3600)
-                    .await
-                    .ok()
+                backend.presigned_download_url(t_key, 3600).await.ok()
             } else {
                 None
             }
@@ -366,6 +391,40 @@ pub async fn populate_files(state: &AppState, posts: &mut [PostResponse]) -> Api
     Ok(())
 }
 
+/// Username of the built-in system bot used for server-generated messages
+pub const SYSTEM_BOT_USERNAME: &str = "system-bot";
+
+/// Get the system bot user, creating it on first use
+///
+/// The bot is the user marked `is_system_bot`; accounts that merely share
+/// its name or email are never turned into it.
+pub async fn get_or_create_system_bot(state: &AppState) -> ApiResult<Uuid> {
+    let find = || sqlx::query_scalar("SELECT id FROM users WHERE is_system_bot");
+    if let Some(bot_id) = find().fetch_optional(&state.db).await? {
+        return Ok(bot_id);
+    }
+
+    sqlx::query(
+        r#"
+        INSERT INTO users (username, email, password_hash, is_bot, is_system_bot, role, display_name)
+        VALUES ($1, $2, 'BOT_NO_PASSWORD', true, true, 'member', 'System')
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(SYSTEM_BOT_USERNAME)
+    .bind(format!("{}@bot.rustchat.local", SYSTEM_BOT_USERNAME))
+    .execute(&state.db)
+    .await?;
+
+    // Another server may have created it first
+    find().fetch_optional(&state.db).await?.ok_or_else(|| {
+        AppError::Internal(format!(
+            "The system bot cannot be created: another account has the name {}",
+            SYSTEM_BOT_USERNAME
+        ))
+    })
+}
+
 /// Create a system message in a channel
 pub async fn create_system_message(
     state: &AppState,
@@ -374,11 +433,7 @@ pub async fn create_system_message(
     props: Option<serde_json::Value>,
 ) -> ApiResult<()> {
     // 1. Find bot user
-    let bot_user = sqlx::query!("SELECT id FROM users WHERE is_bot = true LIMIT 1")
-        .fetch_optional(&state.db)
-        .await?
-        .map(|r| r.id)
-        .unwrap_or_else(Uuid::nil);
+    let bot_user = get_or_create_system_bot(state).await?;
 
     // 2. Prepare props
     let mut final_props = props.unwrap_or_else(|| serde_json::json!({}));
@@ -452,6 +507,62 @@ pub async fn create_system_message(
     Ok(())
 }
 
+/// Send a post that only `user_id` sees in `channel_id`
+///
+/// Ephemeral posts are delivered over the websocket and never stored.
+pub async fn send_ephemeral_post(
+    state: &AppState,
+    user_id: Uuid,
+    channel_id: Uuid,
+    root_post_id: Option<Uuid>,
+    message: String,
+    props: Option<serde_json::Value>,
+) -> PostResponse {
+    let mut props = props.unwrap_or_else(|| serde_json::json!({}));
+    if let Some(obj) = props.as_object_mut() {
+        obj.insert(
+            "type".to_string(),
+            serde_json::Value::String("system_ephemeral".to_string()),
+        );
+    }
+
+    let response = PostResponse {
+        id: Uuid::new_v4(),
+        channel_id,
+        user_id,
+        root_post_id,
+        message,
+        props,
+        file_ids: vec![],
+        is_pinned: false,
+        created_at: chrono::Utc::now(),
+        edited_at: None,
+        deleted_at: None,
+        username: None,
+        avatar_url: None,
+        email: None,
+        reply_count: 0,
+        last_reply_at: None,
+        files: vec![],
+        reactions: vec![],
+        is_saved: false,
+        client_msg_id: None,
+        seq: 0,
+    };
+
+    let broadcast =
+        WsEnvelope::event(EventType::EphemeralMessage, response.clone(), Some(channel_id))
+            .with_broadcast(WsBroadcast {
+                channel_id: Some(channel_id),
+                team_id: None,
+                user_id: Some(user_id),
+                exclude_user_id: None,
+            });
+    state.ws_hub.broadcast(broadcast).await;
+
+    response
+}
+
 async fn check_playbook_triggers(
     state: &AppState,
     channel_id: Uuid,
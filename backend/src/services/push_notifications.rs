@@ -0,0 +1,583 @@
+//! Mobile push notifications
+//!
+//! When a post is created, channel members who are not active in a client
+//! get a notification on their registered devices, following their push
+//! preferences. Notifications are sent in Mattermost's format to a push
+//! proxy, which relays them to APNs/FCM. Reading a channel sends a `clear`
+//! notification so devices can dismiss what was delivered and update their
+//! badge.
+
+use std::time::Duration;
+
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::api::v4::config_client::diagnostic_id;
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::mattermost_compat::id::encode_mm_id;
+use crate::models::{
+    EmailConfig, PushNotification, PushResponse, PostResponse, SiteConfig, UserDevice,
+};
+
+/// Maximum time to wait for the push proxy to answer
+const PUSH_TIMEOUT_SECS: u64 = 10;
+
+/// Delivery attempts per notification before giving up
+const PUSH_MAX_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry, doubled for every further attempt
+const PUSH_RETRY_BASE_MS: u64 = 250;
+
+const PUSH_TYPE_MESSAGE: &str = "message";
+const PUSH_TYPE_CLEAR: &str = "clear";
+const PUSH_CATEGORY_CAN_REPLY: &str = "CAN_REPLY";
+const PUSH_MESSAGE_V2: &str = "v2";
+const ID_LOADED_MESSAGE: &str = "You've received a new message.";
+
+/// Push settings, available only when push notifications are enabled
+#[derive(Debug, Clone)]
+struct PushSettings {
+    server_url: String,
+    server_id: String,
+    contents: String,
+}
+
+/// Channel the notified post belongs to
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct ChannelInfo {
+    team_id: Uuid,
+    channel_type: String,
+    display_name: Option<String>,
+    name: String,
+}
+
+/// Channel member who may receive a notification
+#[derive(Debug, sqlx::FromRow)]
+struct Recipient {
+    user_id: Uuid,
+    username: String,
+    presence: String,
+    user_push: String,
+    channel_push: Option<String>,
+    notify_level: Option<String>,
+    is_muted: bool,
+    mention_keywords: Vec<String>,
+    follows_thread: bool,
+}
+
+/// Why a recipient is being notified
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reason {
+    DirectMessage,
+    Mention,
+    ThreadReply,
+    Message,
+}
+
+/// Send push notifications for a new post in the background
+pub fn spawn_post_notifications(state: &AppState, post: &PostResponse) {
+    if !is_notifying_post(post) {
+        return;
+    }
+
+    let state = state.clone();
+    let post = post.clone();
+    tokio::spawn(async move {
+        if let Err(e) = send_post_notifications(&state, &post).await {
+            warn!("Push notifications for post {} failed: {}", post.id, e);
+        }
+    });
+}
+
+/// Notify every offline or idle member who should hear about `post`
+///
+/// Returns the number of notifications accepted by the push proxy.
+pub async fn send_post_notifications(state: &AppState, post: &PostResponse) -> ApiResult<usize> {
+    if !is_notifying_post(post) {
+        return Ok(0);
+    }
+    let Some(settings) = push_settings(state).await? else {
+        return Ok(0);
+    };
+
+    let channel = channel_info(state, post.channel_id).await?;
+    let recipients = find_recipients(state, post).await?;
+    let is_dm = channel.channel_type == "direct";
+    let mentions_channel = mentions_channel(&post.message);
+
+    let mut sent = 0;
+    for recipient in recipients {
+        if state.ws_hub.user_connection_count(recipient.user_id).await > 0
+            && !matches!(recipient.presence.as_str(), "away" | "offline")
+        {
+            continue;
+        }
+
+        let Some(reason) = notify_reason(&recipient, post, is_dm, mentions_channel) else {
+            continue;
+        };
+
+        let mut msg = build_message(&settings, post, &channel, reason);
+        if settings.contents == "id_loaded" {
+            msg = id_loaded_message(msg);
+        }
+        sent += send_to_devices(state, &settings, recipient.user_id, msg).await?;
+    }
+
+    Ok(sent)
+}
+
+/// Tell a user's devices to dismiss notifications of a channel they have read
+pub fn spawn_clear_notifications(state: &AppState, user_id: Uuid, channel_id: Uuid) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        if let Err(e) = send_clear_notifications(&state, user_id, channel_id).await {
+            warn!("Clearing push notifications for user {} failed: {}", user_id, e);
+        }
+    });
+}
+
+/// Send a `clear` notification with the updated badge to a user's devices
+///
+/// Returns the number of notifications accepted by the push proxy.
+pub async fn send_clear_notifications(
+    state: &AppState,
+    user_id: Uuid,
+    channel_id: Uuid,
+) -> ApiResult<usize> {
+    let Some(settings) = push_settings(state).await? else {
+        return Ok(0);
+    };
+    let Some(team_id): Option<Uuid> =
+        sqlx::query_scalar("SELECT team_id FROM channels WHERE id = $1")
+            .bind(channel_id)
+            .fetch_optional(&state.db)
+            .await?
+    else {
+        return Ok(0);
+    };
+
+    let msg = PushNotification {
+        server_id: settings.server_id.clone(),
+        team_id: encode_mm_id(team_id),
+        channel_id: encode_mm_id(channel_id),
+        push_type: PUSH_TYPE_CLEAR.to_string(),
+        version: PUSH_MESSAGE_V2.to_string(),
+        cont_ava: 1,
+        ..Default::default()
+    };
+
+    send_to_devices(state, &settings, user_id, msg).await
+}
+
+/// Full notification content for a device acknowledging an id-loaded push
+pub async fn id_loaded_notification(
+    state: &AppState,
+    user_id: Uuid,
+    post_id: Uuid,
+) -> ApiResult<PushNotification> {
+    let settings = push_settings(state)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("Push notifications are disabled".to_string()))?;
+
+    let post: PostResponse = crate::services::posts::get_post_by_id(state, post_id).await?;
+    let is_member: bool = sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM channel_members WHERE channel_id = $1 AND user_id = $2)",
+    )
+    .bind(post.channel_id)
+    .bind(user_id)
+    .fetch_one(&state.db)
+    .await?;
+    if !is_member {
+        return Err(AppError::Forbidden("Not a member of this channel".to_string()));
+    }
+
+    let channel = channel_info(state, post.channel_id).await?;
+    let reason = if channel.channel_type == "direct" {
+        Reason::DirectMessage
+    } else {
+        Reason::Message
+    };
+
+    let full = PushSettings {
+        contents: "full".to_string(),
+        ..settings
+    };
+    let mut msg = build_message(&full, &post, &channel, reason);
+    msg.ack_id = encode_mm_id(Uuid::new_v4());
+    msg.badge = crate::services::unreads::total_unread_count(state, user_id).await?;
+    Ok(msg)
+}
+
+/// System messages and posts without text or files never notify
+fn is_notifying_post(post: &PostResponse) -> bool {
+    if let Some(post_type) = post.props.get("type").and_then(|v| v.as_str()) {
+        if post_type.starts_with("system_") {
+            return false;
+        }
+    }
+    !post.message.trim().is_empty() || !post.file_ids.is_empty()
+}
+
+async fn push_settings(state: &AppState) -> ApiResult<Option<PushSettings>> {
+    let row: Option<(sqlx::types::Json<SiteConfig>, sqlx::types::Json<EmailConfig>)> =
+        sqlx::query_as("SELECT site, email FROM server_config WHERE id = 'default'")
+            .fetch_optional(&state.db)
+            .await?;
+
+    let Some((site, email)) = row else {
+        return Ok(None);
+    };
+    let server_url = email.push_notification_server.trim().trim_end_matches('/');
+    if !email.send_push_notifications || server_url.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(PushSettings {
+        server_url: server_url.to_string(),
+        server_id: diagnostic_id(&site.0),
+        contents: email.0.push_notification_contents,
+    }))
+}
+
+async fn channel_info(state: &AppState, channel_id: Uuid) -> ApiResult<ChannelInfo> {
+    let channel = sqlx::query_as(
+        "SELECT team_id, type::text AS channel_type, display_name, name FROM channels WHERE id = $1",
+    )
+    .bind(channel_id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Channel not found".to_string()))?;
+
+    Ok(channel)
+}
+
+/// Channel members other than the author who have a device registered
+async fn find_recipients(state: &AppState, post: &PostResponse) -> ApiResult<Vec<Recipient>> {
+    let recipients = sqlx::query_as(
+        r#"
+        SELECT cm.user_id, u.username, u.presence,
+               COALESCE(up.notify_push, 'mention') AS user_push,
+               cm.notify_props->>'push' AS channel_push,
+               cns.notify_level,
+               (COALESCE(cns.is_muted, false)
+                    AND (cns.mute_until IS NULL OR cns.mute_until > NOW()))
+                   OR COALESCE(cm.notify_props->>'mark_unread', '') = 'mention' AS is_muted,
+               COALESCE(up.mention_keywords, '{}') AS mention_keywords,
+               CASE WHEN $3::uuid IS NULL THEN false
+                    ELSE COALESCE(
+                        (SELECT tm.following FROM thread_memberships tm
+                         WHERE tm.user_id = cm.user_id AND tm.post_id = $3),
+                        EXISTS(SELECT 1 FROM posts p
+                               WHERE (p.id = $3 OR p.root_post_id = $3)
+                                 AND p.user_id = cm.user_id))
+               END AS follows_thread
+        FROM channel_members cm
+        JOIN users u ON u.id = cm.user_id
+        LEFT JOIN user_preferences up ON up.user_id = cm.user_id
+        LEFT JOIN channel_notification_settings cns
+               ON cns.user_id = cm.user_id AND cns.channel_id = cm.channel_id
+        WHERE cm.channel_id = $1
+          AND cm.user_id <> $2
+          AND u.is_active = true
+          AND u.is_bot = false
+          AND u.presence <> 'dnd'
+          AND EXISTS(SELECT 1 FROM user_devices d
+                     WHERE d.user_id = cm.user_id AND COALESCE(d.token, '') <> '')
+        "#,
+    )
+    .bind(post.channel_id)
+    .bind(post.user_id)
+    .bind(post.root_post_id)
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(recipients)
+}
+
+/// Decide whether and why `recipient` should be notified
+///
+/// The channel-level setting wins over the user's global push preference.
+/// Direct messages always count as mentions.
+fn notify_reason(
+    recipient: &Recipient,
+    post: &PostResponse,
+    is_dm: bool,
+    mentions_channel: bool,
+) -> Option<Reason> {
+    if recipient.is_muted {
+        return None;
+    }
+
+    let level = [recipient.notify_level.as_deref(), recipient.channel_push.as_deref()]
+        .into_iter()
+        .flatten()
+        .find(|l| !l.is_empty() && *l != "default")
+        .unwrap_or(&recipient.user_push);
+
+    let reason = if is_dm {
+        Reason::DirectMessage
+    } else if mentions_channel
+        || mentions_user(&post.message, &recipient.username, &recipient.mention_keywords)
+    {
+        Reason::Mention
+    } else if recipient.follows_thread {
+        Reason::ThreadReply
+    } else {
+        Reason::Message
+    };
+
+    match level {
+        "none" => None,
+        "all" => Some(reason),
+        _ if reason == Reason::Message => None,
+        _ => Some(reason),
+    }
+}
+
+/// Whether the message mentions `username` or one of the user's keywords
+pub(crate) fn mentions_user(message: &str, username: &str, keywords: &[String]) -> bool {
+    mention_words(message).any(|word| {
+        word.strip_prefix('@')
+            .is_some_and(|name| name.eq_ignore_ascii_case(username))
+            || keywords
+                .iter()
+                .map(|k| k.trim())
+                .any(|k| !k.is_empty() && word.eq_ignore_ascii_case(k))
+    })
+}
+
+/// Whether the message contains `@channel` or `@all`
+pub(crate) fn mentions_channel(message: &str) -> bool {
+    mention_words(message).any(|word| {
+        word.eq_ignore_ascii_case("@channel") || word.eq_ignore_ascii_case("@all")
+    })
+}
+
+fn mention_words(message: &str) -> impl Iterator<Item = &str> {
+    message
+        .split(|c: char| !(c.is_alphanumeric() || matches!(c, '@' | '.' | '_' | '-')))
+        .map(|word| word.trim_end_matches(['.', '-', '_']))
+        .filter(|word| !word.is_empty())
+}
+
+fn build_message(
+    settings: &PushSettings,
+    post: &PostResponse,
+    channel: &ChannelInfo,
+    reason: Reason,
+) -> PushNotification {
+    let prop = |key: &str| {
+        post.props
+            .get(key)
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let override_username = prop("override_username");
+    let sender_name = if override_username.is_empty() {
+        post.username.clone().unwrap_or_default()
+    } else {
+        override_username.clone()
+    };
+
+    let channel_name = match (reason, settings.contents.as_str()) {
+        (_, "generic_no_channel") => String::new(),
+        (Reason::DirectMessage, _) => sender_name.clone(),
+        _ => channel
+            .display_name
+            .clone()
+            .filter(|n| !n.is_empty())
+            .unwrap_or_else(|| channel.name.clone()),
+    };
+
+    PushNotification {
+        server_id: settings.server_id.clone(),
+        post_id: encode_mm_id(post.id),
+        category: PUSH_CATEGORY_CAN_REPLY.to_string(),
+        message: message_text(settings, post, &sender_name, reason),
+        cont_ava: 1,
+        team_id: encode_mm_id(channel.team_id),
+        channel_id: encode_mm_id(post.channel_id),
+        root_id: post.root_post_id.map(encode_mm_id).unwrap_or_default(),
+        channel_name,
+        push_type: PUSH_TYPE_MESSAGE.to_string(),
+        sender_id: encode_mm_id(post.user_id),
+        sender_name,
+        override_username,
+        override_icon_url: prop("override_icon_url"),
+        from_webhook: prop("from_webhook"),
+        version: PUSH_MESSAGE_V2.to_string(),
+        post_type: prop("type"),
+        channel_type: channel_type_code(&channel.channel_type).to_string(),
+        ..Default::default()
+    }
+}
+
+fn message_text(
+    settings: &PushSettings,
+    post: &PostResponse,
+    sender_name: &str,
+    reason: Reason,
+) -> String {
+    if settings.contents == "full" {
+        if post.message.trim().is_empty() {
+            return format!("@{} attached a file.", sender_name);
+        }
+        return match reason {
+            Reason::DirectMessage => post.message.clone(),
+            _ => format!("@{}: {}", sender_name, post.message),
+        };
+    }
+
+    match reason {
+        Reason::DirectMessage => format!("@{} sent you a message.", sender_name),
+        Reason::Mention => format!("@{} mentioned you.", sender_name),
+        Reason::ThreadReply => format!("@{} replied to a thread.", sender_name),
+        Reason::Message => format!("@{} posted a message.", sender_name),
+    }
+}
+
+/// Strip everything but identifiers; the device fetches the content itself
+fn id_loaded_message(msg: PushNotification) -> PushNotification {
+    PushNotification {
+        server_id: msg.server_id,
+        post_id: msg.post_id,
+        category: msg.category,
+        message: ID_LOADED_MESSAGE.to_string(),
+        cont_ava: msg.cont_ava,
+        team_id: msg.team_id,
+        channel_id: msg.channel_id,
+        root_id: msg.root_id,
+        push_type: msg.push_type,
+        sender_id: msg.sender_id,
+        version: msg.version,
+        channel_type: msg.channel_type,
+        is_id_loaded: true,
+        ..Default::default()
+    }
+}
+
+fn channel_type_code(channel_type: &str) -> &'static str {
+    match channel_type {
+        "private" => "P",
+        "direct" => "D",
+        "group" => "G",
+        _ => "O",
+    }
+}
+
+/// Send `msg` to every device of the user, with the user's badge count
+async fn send_to_devices(
+    state: &AppState,
+    settings: &PushSettings,
+    user_id: Uuid,
+    msg: PushNotification,
+) -> ApiResult<usize> {
+    let devices: Vec<UserDevice> = sqlx::query_as(
+        "SELECT * FROM user_devices WHERE user_id = $1 AND COALESCE(token, '') <> ''",
+    )
+    .bind(user_id)
+    .fetch_all(&state.db)
+    .await?;
+    if devices.is_empty() {
+        return Ok(0);
+    }
+
+    let badge = crate::services::unreads::total_unread_count(state, user_id)
+        .await
+        .unwrap_or(0);
+
+    let mut sent = 0;
+    for device in devices {
+        let device_msg = PushNotification {
+            ack_id: encode_mm_id(Uuid::new_v4()),
+            platform: device.platform.clone().unwrap_or_default(),
+            device_id: device.token.clone().unwrap_or_default(),
+            badge,
+            ..msg.clone()
+        };
+
+        match send_to_proxy(state, settings, &device_msg).await {
+            Ok(response) if response.status == "REMOVE" => {
+                sqlx::query("DELETE FROM user_devices WHERE id = $1")
+                    .bind(device.id)
+                    .execute(&state.db)
+                    .await?;
+            }
+            Ok(response) if response.status == "FAIL" => {
+                warn!(
+                    "Push proxy rejected notification for device {}: {}",
+                    device.device_id,
+                    response.error.unwrap_or_default()
+                );
+            }
+            Ok(_) => sent += 1,
+            Err(e) => warn!("Push to device {} failed: {}", device.device_id, e),
+        }
+    }
+
+    Ok(sent)
+}
+
+/// POST the notification to the push proxy, retrying transient failures
+async fn send_to_proxy(
+    state: &AppState,
+    settings: &PushSettings,
+    msg: &PushNotification,
+) -> Result<PushResponse, reqwest::Error> {
+    let url = format!("{}/api/v1/send_push", settings.server_url);
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let result = state
+            .http_client
+            .post(&url)
+            .json(msg)
+            .timeout(Duration::from_secs(PUSH_TIMEOUT_SECS))
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+
+        let retryable = match &result {
+            Ok(_) => false,
+            Err(e) => e.status().is_none_or(|s| s.is_server_error()),
+        };
+        if !retryable || attempt >= PUSH_MAX_ATTEMPTS {
+            let res = result?;
+            // Older proxies answer with an empty body
+            let body = res.bytes().await?;
+            return Ok(serde_json::from_slice(&body).unwrap_or(PushResponse {
+                status: "OK".to_string(),
+                error: None,
+            }));
+        }
+
+        let delay = PUSH_RETRY_BASE_MS * 2u64.pow(attempt - 1);
+        tokio::time::sleep(Duration::from_millis(delay)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_user_and_keyword_mentions() {
+        let keywords = vec!["deploy".to_string()];
+        assert!(mentions_user("hey @Alice, look", "alice", &[]));
+        assert!(mentions_user("ping @alice.", "alice", &[]));
+        assert!(!mentions_user("hey @alicia", "alice", &[]));
+        assert!(!mentions_user("alice@example.com", "alice", &[]));
+        assert!(mentions_user("the Deploy failed", "bob", &keywords));
+    }
+
+    #[test]
+    fn detects_channel_mentions() {
+        assert!(mentions_channel("@channel standup now"));
+        assert!(mentions_channel("heads up @all!"));
+        assert!(!mentions_channel("#channel"));
+        assert!(!mentions_channel("@here is not pushed"));
+    }
+}
@@ -0,0 +1,405 @@
+//! API rate limiting
+//!
+//! Every API request takes a token from a bucket in Redis. Buckets are kept
+//! per route class (see [`RouteClass`]) and per caller: the user for
+//! authenticated requests, the token for webhook calls and the client address
+//! otherwise. Each class has its own quota in [`RateLimitConfig`]. Responses
+//! carry Mattermost's `X-Ratelimit-*` headers, and refused requests get a 429
+//! in the format of the API version they were made against. System admins
+//! are never limited, and without Redis nothing is. A caller's token is only
+//! checked once per request, as the auth extractors reuse the result.
+
+use std::net::SocketAddr;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::{
+        header::{HeaderName, AUTHORIZATION, RETRY_AFTER},
+        HeaderMap, HeaderValue, Method, StatusCode,
+    },
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use deadpool_redis::redis;
+use tokio::sync::RwLock;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::models::{RateLimitConfig, RateLimitQuota};
+use crate::services::permissions::SYSTEM_ADMIN_ROLE;
+use crate::services::sessions;
+
+/// How long the rate limit settings are cached
+const CONFIG_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Takes a token from the bucket in `KEYS[1]`
+///
+/// `ARGV` holds the burst size and the refill rate per minute. Returns
+/// whether a token was taken, the whole tokens left, and the milliseconds
+/// until the bucket is full and until the next token is available.
+static TOKEN_BUCKET: LazyLock<redis::Script> = LazyLock::new(|| {
+    redis::Script::new(
+        r#"
+        redis.replicate_commands()
+        local burst = tonumber(ARGV[1])
+        local per_ms = tonumber(ARGV[2]) / 60000
+        local time = redis.call('TIME')
+        local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+        local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated_at')
+        local tokens = tonumber(bucket[1]) or burst
+        local updated_at = tonumber(bucket[2]) or now
+        tokens = math.min(burst, tokens + math.max(0, now - updated_at) * per_ms)
+
+        local allowed = 0
+        local retry_in = 0
+        if tokens >= 1 then
+            tokens = tokens - 1
+            allowed = 1
+        else
+            retry_in = math.ceil((1 - tokens) / per_ms)
+        end
+        local full_in = math.ceil((burst - tokens) / per_ms)
+
+        redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated_at', now)
+        redis.call('PEXPIRE', KEYS[1], full_in + 1000)
+        return {allowed, math.floor(tokens), full_in, retry_in}
+        "#,
+    )
+});
+
+/// Kind of request, each with its own quota
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteClass {
+    Api,
+    Auth,
+    Webhooks,
+    FileUpload,
+    Search,
+}
+
+impl RouteClass {
+    /// Class of a request, or `None` for requests that are never limited
+    pub fn of(method: &Method, path: &str) -> Option<Self> {
+        let rest = path
+            .strip_prefix("/api/v1")
+            .or_else(|| path.strip_prefix("/api/v4"))?;
+
+        let class = match rest {
+            _ if rest.starts_with("/health") => return None,
+            // Signed storage links stand in for S3, which is not limited either
+            _ if rest.starts_with("/storage/") => return None,
+            "/auth/login" | "/auth/register" | "/users/login" | "/users/mfa" => Self::Auth,
+            _ if [
+                "/auth/password/",
+                "/auth/email/",
+                "/users/password/",
+                "/users/email/",
+            ]
+            .iter()
+            .any(|prefix| rest.starts_with(prefix)) =>
+            {
+                Self::Auth
+            }
+            _ if method == Method::POST && webhook_token(path).is_some() => Self::Webhooks,
+            "/files" | "/files/presign" | "/uploads" if method == Method::POST => Self::FileUpload,
+            // Data appended to an upload session
+            _ if method == Method::POST && rest.starts_with("/uploads/") => Self::FileUpload,
+            _ if rest.ends_with("/search") => Self::Search,
+            _ => Self::Api,
+        };
+
+        Some(class)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Api => "api",
+            Self::Auth => "auth",
+            Self::Webhooks => "webhooks",
+            Self::FileUpload => "file_upload",
+            Self::Search => "search",
+        }
+    }
+
+    fn quota(self, config: &RateLimitConfig) -> RateLimitQuota {
+        match self {
+            Self::Api => config.api,
+            Self::Auth => config.auth,
+            Self::Webhooks => config.webhooks,
+            Self::FileUpload => config.file_upload,
+            Self::Search => config.search,
+        }
+    }
+}
+
+/// Token of an incoming webhook or slash command call
+fn webhook_token(path: &str) -> Option<&str> {
+    let hook = path.strip_prefix("/api/v1/hooks/")?;
+    match hook.split_once('/') {
+        Some(("commands", id)) if !id.contains('/') => Some(id),
+        None if !matches!(hook, "incoming" | "outgoing" | "") => Some(hook),
+        _ => None,
+    }
+}
+
+/// Who a bucket belongs to
+enum Caller {
+    User(Uuid),
+    /// Hash of a webhook token
+    Token(String),
+    Ip(String),
+}
+
+impl Caller {
+    fn key(&self, class: RouteClass) -> String {
+        match self {
+            Self::User(id) => format!("rate_limit:{}:user:{}", class.name(), id),
+            Self::Token(hash) => format!("rate_limit:{}:token:{}", class.name(), hash),
+            Self::Ip(ip) => format!("rate_limit:{}:ip:{}", class.name(), ip),
+        }
+    }
+}
+
+/// Token sent with a request, in any of the forms the auth extractors accept
+fn request_token(headers: &HeaderMap) -> Option<&str> {
+    if let Some(value) = headers.get(AUTHORIZATION) {
+        let value = value.to_str().ok()?;
+        let token = value
+            .strip_prefix("Bearer ")
+            .or_else(|| value.strip_prefix("Token "))
+            .unwrap_or(value);
+        return Some(token.trim());
+    }
+    headers
+        .get(HeaderName::from_static("token"))
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+}
+
+/// Whose bucket a request takes from, or `None` if it is not limited
+///
+/// The address is the connection's peer, or the client named by a trusted
+/// proxy in front of the server.
+async fn caller(state: &AppState, class: RouteClass, request: &mut Request) -> Option<Caller> {
+    if class == RouteClass::Webhooks {
+        let token = webhook_token(request.uri().path())?;
+        return Some(Caller::Token(crate::crypto::hash_token(token)));
+    }
+
+    if let Some(token) = request_token(request.headers()).map(str::to_string) {
+        // The handler's extractor reuses the result. Invalid tokens are left
+        // to the handler and limited by address.
+        let session = sessions::authenticate_request(state, request.extensions_mut(), &token);
+        if let Ok(session) = session.await {
+            if session.claims.role == SYSTEM_ADMIN_ROLE {
+                return None;
+            }
+            return Some(Caller::User(session.claims.sub));
+        }
+    }
+
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip());
+    state
+        .trusted_proxies
+        .client_ip(peer, request.headers())
+        .map(|ip| Caller::Ip(ip.to_string()))
+}
+
+/// Outcome of taking a token from a bucket
+struct Decision {
+    allowed: bool,
+    limit: i32,
+    remaining: i64,
+    reset_ms: i64,
+    retry_ms: i64,
+}
+
+impl Decision {
+    fn set_headers(&self, headers: &mut HeaderMap) {
+        let seconds = |ms: i64| HeaderValue::from((ms + 999) / 1000);
+        headers.insert(
+            HeaderName::from_static("x-ratelimit-limit"),
+            HeaderValue::from(self.limit),
+        );
+        headers.insert(
+            HeaderName::from_static("x-ratelimit-remaining"),
+            HeaderValue::from(self.remaining),
+        );
+        headers.insert(
+            HeaderName::from_static("x-ratelimit-reset"),
+            seconds(self.reset_ms),
+        );
+        if !self.allowed {
+            headers.insert(RETRY_AFTER, seconds(self.retry_ms));
+        }
+    }
+}
+
+/// Cache of the rate limit settings
+#[derive(Default)]
+pub struct RateLimiter {
+    config: RwLock<Option<(Instant, RateLimitConfig)>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drop the cached settings after they were edited
+    pub async fn invalidate(&self) {
+        *self.config.write().await = None;
+    }
+
+    async fn config(&self, db: &sqlx::PgPool) -> ApiResult<RateLimitConfig> {
+        if let Some((loaded_at, config)) = self.config.read().await.as_ref() {
+            if loaded_at.elapsed() < CONFIG_CACHE_TTL {
+                return Ok(config.clone());
+            }
+        }
+
+        let config: sqlx::types::Json<RateLimitConfig> =
+            sqlx::query_scalar("SELECT rate_limiting FROM server_config WHERE id = 'default'")
+                .fetch_one(db)
+                .await?;
+        *self.config.write().await = Some((Instant::now(), config.0.clone()));
+
+        Ok(config.0)
+    }
+}
+
+/// Take a token from `caller`'s bucket, or `None` if Redis is unavailable
+async fn take(
+    state: &AppState,
+    class: RouteClass,
+    caller: &Caller,
+    quota: RateLimitQuota,
+) -> Option<Decision> {
+    let mut conn = state.redis.get().await.ok()?;
+    let result: redis::RedisResult<(i64, i64, i64, i64)> = TOKEN_BUCKET
+        .key(caller.key(class))
+        .arg(quota.burst.max(1))
+        .arg(quota.per_minute)
+        .invoke_async(&mut conn)
+        .await;
+
+    match result {
+        Ok((allowed, remaining, reset_ms, retry_ms)) => Some(Decision {
+            allowed: allowed == 1,
+            limit: quota.burst.max(1),
+            remaining,
+            reset_ms,
+            retry_ms,
+        }),
+        Err(e) => {
+            warn!("Failed to check rate limit: {}", e);
+            None
+        }
+    }
+}
+
+/// Middleware applying the configured rate limits
+pub async fn limit_requests(
+    State(state): State<AppState>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(class) = RouteClass::of(request.method(), request.uri().path()) else {
+        return next.run(request).await;
+    };
+    let config = match state.rate_limiter.config(&state.db).await {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("Failed to load rate limit settings: {}", e);
+            return next.run(request).await;
+        }
+    };
+    let quota = class.quota(&config);
+    if !config.enable || quota.per_minute <= 0 {
+        return next.run(request).await;
+    }
+
+    let Some(caller) = caller(&state, class, &mut request).await else {
+        return next.run(request).await;
+    };
+    let Some(decision) = take(&state, class, &caller, quota).await else {
+        return next.run(request).await;
+    };
+
+    let mut response = if decision.allowed {
+        next.run(request).await
+    } else if request.uri().path().starts_with("/api/v4") {
+        // What Mattermost clients get from the Mattermost server
+        (StatusCode::TOO_MANY_REQUESTS, "limit exceeded\n").into_response()
+    } else {
+        AppError::TooManyRequests("Rate limit exceeded, try again later".to_string())
+            .into_response()
+    };
+    decision.set_headers(response.headers_mut());
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_routes() {
+        let class = |method: Method, path: &str| RouteClass::of(&method, path);
+
+        assert_eq!(None, class(Method::GET, "/api/v1/health/live"));
+        assert_eq!(None, class(Method::GET, "/static/app.js"));
+        assert_eq!(None, class(Method::GET, "/api/v1/storage/files/a.png"));
+        assert_eq!(
+            Some(RouteClass::Auth),
+            class(Method::POST, "/api/v4/users/login")
+        );
+        assert_eq!(
+            Some(RouteClass::Auth),
+            class(Method::POST, "/api/v1/auth/password/reset/send")
+        );
+        assert_eq!(Some(RouteClass::Api), class(Method::GET, "/api/v1/auth/me"));
+        assert_eq!(
+            Some(RouteClass::Webhooks),
+            class(Method::POST, "/api/v1/hooks/abc123")
+        );
+        assert_eq!(
+            Some(RouteClass::Webhooks),
+            class(Method::POST, "/api/v1/hooks/commands/abc123")
+        );
+        assert_eq!(
+            Some(RouteClass::Api),
+            class(Method::POST, "/api/v1/hooks/incoming")
+        );
+        assert_eq!(
+            Some(RouteClass::FileUpload),
+            class(Method::POST, "/api/v4/files")
+        );
+        assert_eq!(Some(RouteClass::Api), class(Method::GET, "/api/v4/files"));
+        assert_eq!(
+            Some(RouteClass::FileUpload),
+            class(Method::POST, "/api/v4/uploads")
+        );
+        assert_eq!(
+            Some(RouteClass::FileUpload),
+            class(Method::POST, "/api/v4/uploads/abc123")
+        );
+        assert_eq!(
+            Some(RouteClass::Api),
+            class(Method::GET, "/api/v4/uploads/abc123")
+        );
+        assert_eq!(
+            Some(RouteClass::Search),
+            class(Method::POST, "/api/v4/teams/abc/posts/search")
+        );
+    }
+}
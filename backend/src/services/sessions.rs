@@ -0,0 +1,398 @@
+//! Server-side sessions
+//!
+//! Every token carries the ID of a row in `sessions` as its `jti` claim, and
+//! requests are only accepted while that session is neither expired nor
+//! revoked. Validity is cached in Redis for a short time so most requests do
+//! not touch the database; revoking writes a tombstone to the cache so the
+//! revocation takes effect immediately. Without Redis every check goes to
+//! the database. The cache entry also records whether the user may skip MFA
+//! enrollment, so [`mfa::check_enrollment`] needs no query of its own. Bot
+//! and personal access tokens are handed off to [`access_tokens`].
+//!
+//! [`mfa::check_enrollment`]: crate::services::mfa::check_enrollment
+
+use std::net::IpAddr;
+
+use axum::http::{header::USER_AGENT, Extensions, HeaderMap};
+use deadpool_redis::redis::{self, AsyncCommands};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::auth::{create_token, validate_token, Claims};
+use crate::error::{ApiResult, AppError};
+use crate::models::{Session, User};
+use crate::services::access_tokens;
+
+/// How long a session check is cached
+const SESSION_CACHE_TTL_SECS: u64 = 60;
+
+/// Revoked and expired sessions are kept this long for the session list
+const SESSION_RETENTION_DAYS: i32 = 7;
+
+const REVOKED: &str = "revoked";
+
+/// Cached for active sessions whose user may skip MFA enrollment
+const MFA_EXEMPT: &str = "mfa_exempt";
+
+fn cache_key(session_id: Uuid) -> String {
+    format!("session:{}", session_id)
+}
+
+/// How the user of a session signed in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AuthMethod {
+    /// Password, with the second factor when the user has one
+    #[default]
+    Password,
+    /// Single sign-on, whose provider is responsible for the second factor
+    Sso,
+}
+
+impl AuthMethod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Password => "password",
+            Self::Sso => "sso",
+        }
+    }
+}
+
+/// Client details recorded with a new session
+#[derive(Debug, Clone, Default)]
+pub struct SessionMetadata {
+    pub device_id: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub auth_method: AuthMethod,
+}
+
+impl SessionMetadata {
+    /// Record the client address and the user agent from the request headers
+    ///
+    /// `ip_address` is the [`ClientIp`](crate::services::client_address::ClientIp)
+    /// of the request.
+    pub fn from_request(headers: &HeaderMap, ip_address: Option<IpAddr>) -> Self {
+        Self {
+            device_id: None,
+            ip_address: ip_address.map(|ip| ip.to_string()),
+            user_agent: headers
+                .get(USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            auth_method: AuthMethod::default(),
+        }
+    }
+
+    pub fn with_device_id(mut self, device_id: Option<String>) -> Self {
+        self.device_id = device_id.filter(|d| !d.is_empty());
+        self
+    }
+
+    pub fn with_auth_method(mut self, auth_method: AuthMethod) -> Self {
+        self.auth_method = auth_method;
+        self
+    }
+}
+
+/// Start a session for `user` and return its token
+pub async fn create_session(
+    state: &AppState,
+    user: &User,
+    metadata: SessionMetadata,
+) -> ApiResult<String> {
+    sqlx::query(
+        r#"
+        DELETE FROM sessions
+        WHERE user_id = $1
+          AND COALESCE(revoked_at, expires_at) < NOW() - make_interval(days => $2)
+        "#,
+    )
+    .bind(user.id)
+    .bind(SESSION_RETENTION_DAYS)
+    .execute(&state.db)
+    .await?;
+
+    let session_id: Uuid = sqlx::query_scalar(
+        r#"
+        INSERT INTO sessions (user_id, device_id, ip_address, user_agent, auth_method, expires_at)
+        VALUES ($1, $2, $3, $4, $5, NOW() + make_interval(hours => $6))
+        RETURNING id
+        "#,
+    )
+    .bind(user.id)
+    .bind(&metadata.device_id)
+    .bind(&metadata.ip_address)
+    .bind(&metadata.user_agent)
+    .bind(metadata.auth_method.as_str())
+    .bind(state.jwt_expiry_hours as i32)
+    .fetch_one(&state.db)
+    .await?;
+
+    create_token(
+        user.id,
+        session_id,
+        &user.email,
+        &user.role,
+        user.org_id,
+        &state.jwt_secret,
+        state.jwt_expiry_hours,
+    )
+}
+
+/// A token that passed [`check`]
+#[derive(Debug, Clone)]
+pub struct SessionCheck {
+    pub claims: Claims,
+    /// Whether the user has MFA, is a bot, or signed in through single sign-on
+    pub mfa_exempt: bool,
+}
+
+/// Validate a token and check that its session is still active
+pub async fn authenticate(state: &AppState, token: &str) -> ApiResult<Claims> {
+    Ok(check(state, token).await?.claims)
+}
+
+/// [`authenticate`], also telling whether the user may skip MFA enrollment
+pub async fn check(state: &AppState, token: &str) -> ApiResult<SessionCheck> {
+    if access_tokens::is_access_token(token) {
+        return access_tokens::authenticate(state, token).await;
+    }
+
+    let claims = validate_token(token, &state.jwt_secret)?.claims;
+    let key = cache_key(claims.jti);
+
+    let mut conn = state.redis.get().await.ok();
+    if let Some(conn) = conn.as_mut() {
+        let cached: Option<String> = conn.get(&key).await.unwrap_or(None);
+        match cached.as_deref() {
+            Some(REVOKED) => return Err(session_ended()),
+            Some(value) => {
+                return Ok(SessionCheck {
+                    claims,
+                    mfa_exempt: value == MFA_EXEMPT,
+                })
+            }
+            None => {}
+        }
+    }
+
+    // Also records activity, at most once per cache period
+    let mfa_exempt: Option<bool> = sqlx::query_scalar(
+        r#"
+        UPDATE sessions s SET last_activity_at = NOW()
+        FROM users u
+        WHERE s.id = $1 AND s.user_id = $2 AND u.id = s.user_id
+          AND u.is_active = true
+          AND s.revoked_at IS NULL AND s.expires_at > NOW()
+        RETURNING u.mfa_active OR u.is_bot OR s.auth_method = 'sso'
+        "#,
+    )
+    .bind(claims.jti)
+    .bind(claims.sub)
+    .fetch_optional(&state.db)
+    .await?;
+    let Some(mfa_exempt) = mfa_exempt else {
+        return Err(session_ended());
+    };
+
+    if let Some(conn) = conn.as_mut() {
+        // NX so a concurrent revocation's tombstone is not overwritten
+        let result: redis::RedisResult<Option<String>> = redis::cmd("SET")
+            .arg(&key)
+            .arg(if mfa_exempt { MFA_EXEMPT } else { "active" })
+            .arg("EX")
+            .arg(SESSION_CACHE_TTL_SECS)
+            .arg("NX")
+            .query_async(conn)
+            .await;
+        if let Err(e) = result {
+            warn!("Failed to cache session {}: {}", claims.jti, e);
+        }
+    }
+
+    Ok(SessionCheck { claims, mfa_exempt })
+}
+
+/// A token already checked while handling a request
+#[derive(Clone)]
+struct Authenticated {
+    token: String,
+    check: SessionCheck,
+}
+
+/// [`check`], at most once per request
+///
+/// Successful checks are kept in the request's `extensions`, so the rate
+/// limiter and the auth extractors do not each look the token up.
+pub async fn authenticate_request(
+    state: &AppState,
+    extensions: &mut Extensions,
+    token: &str,
+) -> ApiResult<SessionCheck> {
+    if let Some(done) = extensions.get::<Authenticated>() {
+        if done.token == token {
+            return Ok(done.check.clone());
+        }
+    }
+
+    let check = check(state, token).await?;
+    extensions.insert(Authenticated {
+        token: token.to_string(),
+        check: check.clone(),
+    });
+
+    Ok(check)
+}
+
+/// Drop the cached checks of a user's sessions after their MFA changed
+pub async fn forget_cached_sessions(state: &AppState, user_id: Uuid) -> ApiResult<()> {
+    let session_ids: Vec<Uuid> =
+        sqlx::query_scalar("SELECT id FROM sessions WHERE user_id = $1 AND revoked_at IS NULL")
+            .bind(user_id)
+            .fetch_all(&state.db)
+            .await?;
+    if session_ids.is_empty() {
+        return Ok(());
+    }
+
+    if let Ok(mut conn) = state.redis.get().await {
+        let keys: Vec<String> = session_ids.into_iter().map(cache_key).collect();
+        let result: redis::RedisResult<()> = conn.del(keys).await;
+        if let Err(e) = result {
+            warn!("Failed to drop cached sessions of user {}: {}", user_id, e);
+        }
+    }
+
+    Ok(())
+}
+
+fn session_ended() -> AppError {
+    AppError::Unauthorized("Session expired or revoked".to_string())
+}
+
+/// Sessions of a user that can still be used, most recent first
+pub async fn list_sessions(state: &AppState, user_id: Uuid) -> ApiResult<Vec<Session>> {
+    let sessions = sqlx::query_as(
+        r#"
+        SELECT * FROM sessions
+        WHERE user_id = $1 AND revoked_at IS NULL AND expires_at > NOW()
+        ORDER BY last_activity_at DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(&state.db)
+    .await?;
+
+    Ok(sessions)
+}
+
+/// Revoke one session of a user
+pub async fn revoke_session(state: &AppState, user_id: Uuid, session_id: Uuid) -> ApiResult<()> {
+    let revoked: Vec<(Uuid, Option<String>)> = sqlx::query_as(
+        r#"
+        UPDATE sessions SET revoked_at = NOW()
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        RETURNING id, device_id
+        "#,
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .fetch_all(&state.db)
+    .await?;
+    if revoked.is_empty() {
+        return Err(AppError::NotFound("Session not found".to_string()));
+    }
+
+    finish_revocation(state, user_id, &revoked).await;
+    state.ws_hub.disconnect_session(user_id, session_id).await;
+
+    Ok(())
+}
+
+/// Revoke every session of a user and close their WebSocket connections
+///
+/// Returns the number of sessions revoked.
+pub async fn revoke_all_sessions(state: &AppState, user_id: Uuid) -> ApiResult<u64> {
+    let revoked: Vec<(Uuid, Option<String>)> = sqlx::query_as(
+        r#"
+        UPDATE sessions SET revoked_at = NOW()
+        WHERE user_id = $1 AND revoked_at IS NULL
+        RETURNING id, device_id
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(&state.db)
+    .await?;
+
+    finish_revocation(state, user_id, &revoked).await;
+    state.ws_hub.disconnect_user(user_id).await;
+
+    Ok(revoked.len() as u64)
+}
+
+/// Tombstone revoked sessions in the cache and forget their push devices
+async fn finish_revocation(state: &AppState, user_id: Uuid, revoked: &[(Uuid, Option<String>)]) {
+    if let Ok(mut conn) = state.redis.get().await {
+        for (session_id, _) in revoked {
+            let result: redis::RedisResult<()> = conn
+                .set_ex(cache_key(*session_id), REVOKED, SESSION_CACHE_TTL_SECS)
+                .await;
+            if let Err(e) = result {
+                warn!(
+                    "Failed to cache revocation of session {}: {}",
+                    session_id, e
+                );
+            }
+        }
+    }
+
+    let device_ids: Vec<&str> = revoked
+        .iter()
+        .filter_map(|(_, device_id)| device_id.as_deref())
+        .collect();
+    if !device_ids.is_empty() {
+        if let Err(e) =
+            sqlx::query("DELETE FROM user_devices WHERE user_id = $1 AND device_id = ANY($2)")
+                .bind(user_id)
+                .bind(&device_ids)
+                .execute(&state.db)
+                .await
+        {
+            warn!("Failed to remove devices of revoked sessions: {}", e);
+        }
+    }
+}
+
+/// Record the mobile device a session belongs to
+pub async fn set_device_id(state: &AppState, session_id: Uuid, device_id: &str) -> ApiResult<()> {
+    sqlx::query("UPDATE sessions SET device_id = $2 WHERE id = $1")
+        .bind(session_id)
+        .bind(device_id)
+        .execute(&state.db)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_takes_the_given_address() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "203.0.113.7, 10.0.0.1".parse().unwrap());
+        headers.insert(USER_AGENT, "Mattermost Mobile/2.0".parse().unwrap());
+
+        let metadata = SessionMetadata::from_request(&headers, "198.51.100.1".parse().ok());
+        assert_eq!(Some("198.51.100.1"), metadata.ip_address.as_deref());
+        assert_eq!(
+            Some("Mattermost Mobile/2.0"),
+            metadata.user_agent.as_deref()
+        );
+
+        let metadata = SessionMetadata::from_request(&headers, None);
+        assert_eq!(None, metadata.ip_address);
+    }
+}
@@ -0,0 +1,281 @@
+//! Custom slash command dispatch
+//!
+//! Team commands created through the integrations API are executed by calling
+//! their URL with Mattermost's command payload. Each execution hands out a
+//! `response_url` the integration can use to post delayed responses.
+
+use std::time::Duration;
+
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::mattermost_compat::id::encode_mm_id;
+use crate::models::{CommandResponse, CommandWebhook, CreatePost, SlashCommand, SlashCommandPayload};
+
+/// Maximum time to wait for a command URL to answer
+const COMMAND_TIMEOUT_SECS: u64 = 30;
+
+/// How long a response URL stays valid
+const RESPONSE_URL_TTL_MINUTES: i64 = 30;
+
+/// How many delayed responses a single execution may post
+const RESPONSE_URL_MAX_USES: i32 = 5;
+
+/// Where and by whom a command was executed
+#[derive(Debug, Clone)]
+pub struct CommandContext {
+    pub user_id: Uuid,
+    pub channel_id: Uuid,
+    pub root_id: Option<Uuid>,
+}
+
+/// Look up an active custom command by trigger
+pub async fn find_command(
+    state: &AppState,
+    team_id: Uuid,
+    trigger: &str,
+) -> ApiResult<Option<SlashCommand>> {
+    let command = sqlx::query_as(
+        "SELECT * FROM slash_commands WHERE team_id = $1 AND trigger = $2 AND is_active = true",
+    )
+    .bind(team_id)
+    .bind(trigger)
+    .fetch_optional(&state.db)
+    .await?;
+
+    Ok(command)
+}
+
+/// Call a custom command's URL and apply its response
+pub async fn execute_custom_command(
+    state: &AppState,
+    command: &SlashCommand,
+    ctx: &CommandContext,
+    text: &str,
+) -> ApiResult<CommandResponse> {
+    #[derive(sqlx::FromRow)]
+    struct Names {
+        user_name: String,
+        channel_name: String,
+        team_name: String,
+    }
+
+    let names: Names = sqlx::query_as(
+        r#"
+        SELECT u.username AS user_name, c.name AS channel_name, t.name AS team_name
+        FROM users u, channels c
+        JOIN teams t ON t.id = $3
+        WHERE u.id = $1 AND c.id = $2
+        "#,
+    )
+    .bind(ctx.user_id)
+    .bind(ctx.channel_id)
+    .bind(command.team_id)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Channel not found".to_string()))?;
+
+    purge_expired_command_webhooks(state).await?;
+
+    let hook_id: Uuid = sqlx::query_scalar(
+        r#"
+        INSERT INTO command_webhooks (command_id, user_id, channel_id, root_id)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id
+        "#,
+    )
+    .bind(command.id)
+    .bind(ctx.user_id)
+    .bind(ctx.channel_id)
+    .bind(ctx.root_id)
+    .fetch_one(&state.db)
+    .await?;
+
+    let payload = SlashCommandPayload {
+        token: command.token.clone(),
+        team_id: encode_mm_id(command.team_id),
+        team_domain: names.team_name,
+        channel_id: encode_mm_id(ctx.channel_id),
+        channel_name: names.channel_name,
+        user_id: encode_mm_id(ctx.user_id),
+        user_name: names.user_name,
+        command: format!("/{}", command.trigger),
+        text: text.to_string(),
+        response_url: response_url(state, hook_id).await?,
+    };
+
+    let response = call_command(state, command, &payload).await.map_err(|e| {
+        warn!("Slash command /{} failed: {}", command.trigger, e);
+        AppError::Internal(format!(
+            "Command with a trigger of '/{}' failed",
+            command.trigger
+        ))
+    })?;
+
+    handle_command_response(state, ctx, &response, command.icon_url.as_deref()).await?;
+
+    Ok(response)
+}
+
+/// Post a delayed response received on a command's response URL
+pub async fn execute_command_webhook(
+    state: &AppState,
+    hook_id: Uuid,
+    response: CommandResponse,
+) -> ApiResult<()> {
+    let hook: CommandWebhook = sqlx::query_as(
+        r#"
+        UPDATE command_webhooks SET use_count = use_count + 1
+        WHERE id = $1
+          AND use_count < $2
+          AND created_at > NOW() - make_interval(mins => $3)
+        RETURNING *
+        "#,
+    )
+    .bind(hook_id)
+    .bind(RESPONSE_URL_MAX_USES)
+    .bind(RESPONSE_URL_TTL_MINUTES as i32)
+    .fetch_optional(&state.db)
+    .await?
+    .ok_or_else(|| AppError::BadRequest("Invalid or expired response URL".to_string()))?;
+
+    let command: SlashCommand = sqlx::query_as("SELECT * FROM slash_commands WHERE id = $1")
+        .bind(hook.command_id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Command not found".to_string()))?;
+
+    let ctx = CommandContext {
+        user_id: hook.user_id,
+        channel_id: hook.channel_id,
+        root_id: hook.root_id,
+    };
+
+    handle_command_response(state, &ctx, &response, command.icon_url.as_deref()).await
+}
+
+/// Post an `in_channel` response or show an `ephemeral` one to the caller
+pub async fn handle_command_response(
+    state: &AppState,
+    ctx: &CommandContext,
+    response: &CommandResponse,
+    default_icon_url: Option<&str>,
+) -> ApiResult<()> {
+    if response.text.trim().is_empty() && response.attachments.is_none() {
+        return Ok(());
+    }
+
+    let mut props = serde_json::Map::new();
+    if let Some(attachments) = &response.attachments {
+        props.insert("attachments".to_string(), attachments.clone());
+    }
+    let username = response.username.as_ref().filter(|u| !u.is_empty());
+    let icon_url = response
+        .icon_url
+        .as_deref()
+        .or(default_icon_url)
+        .filter(|u| !u.is_empty());
+    if username.is_some() || icon_url.is_some() {
+        props.insert("from_webhook".to_string(), "true".into());
+    }
+    if let Some(username) = username {
+        props.insert("override_username".to_string(), username.clone().into());
+    }
+    if let Some(icon_url) = icon_url {
+        props.insert("override_icon_url".to_string(), icon_url.into());
+    }
+
+    if response.response_type == "in_channel" {
+        let input = CreatePost {
+            message: response.text.clone(),
+            root_post_id: ctx.root_id,
+            props: Some(serde_json::Value::Object(props)),
+            file_ids: vec![],
+        };
+        crate::services::posts::create_post(state, ctx.user_id, ctx.channel_id, input, None)
+            .await?;
+    } else {
+        crate::services::posts::send_ephemeral_post(
+            state,
+            ctx.user_id,
+            ctx.channel_id,
+            ctx.root_id,
+            response.text.clone(),
+            Some(serde_json::Value::Object(props)),
+        )
+        .await;
+    }
+
+    Ok(())
+}
+
+/// Delete response URLs that can no longer be used
+async fn purge_expired_command_webhooks(state: &AppState) -> ApiResult<u64> {
+    let result = sqlx::query(
+        "DELETE FROM command_webhooks WHERE created_at < NOW() - make_interval(mins => $1)",
+    )
+    .bind(RESPONSE_URL_TTL_MINUTES as i32)
+    .execute(&state.db)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Configured site URL without a trailing slash (empty when unset)
+pub async fn site_url(state: &AppState) -> ApiResult<String> {
+    let site_url: Option<String> =
+        sqlx::query_scalar("SELECT site->>'site_url' FROM server_config WHERE id = 'default'")
+            .fetch_optional(&state.db)
+            .await?
+            .flatten();
+
+    Ok(site_url.unwrap_or_default().trim_end_matches('/').to_string())
+}
+
+async fn response_url(state: &AppState, hook_id: Uuid) -> ApiResult<String> {
+    Ok(format!(
+        "{}/api/v1/hooks/commands/{}",
+        site_url(state).await?,
+        encode_mm_id(hook_id)
+    ))
+}
+
+/// Send the payload to the command URL and interpret its answer
+async fn call_command(
+    state: &AppState,
+    command: &SlashCommand,
+    payload: &SlashCommandPayload,
+) -> Result<CommandResponse, reqwest::Error> {
+    let request = if command.method.eq_ignore_ascii_case("GET") {
+        state.http_client.get(&command.url).query(payload)
+    } else {
+        state.http_client.post(&command.url).form(payload)
+    };
+
+    let res = request
+        .header(reqwest::header::ACCEPT, "application/json")
+        .header(
+            reqwest::header::AUTHORIZATION,
+            format!("Token {}", command.token),
+        )
+        .timeout(Duration::from_secs(COMMAND_TIMEOUT_SECS))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let is_json = res
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|ct| ct.starts_with("application/json"));
+    let body = res.text().await?;
+
+    // Plain text bodies are shown to the caller as-is
+    if is_json {
+        Ok(serde_json::from_str(&body).unwrap_or_else(|_| CommandResponse::ephemeral(String::new())))
+    } else {
+        Ok(CommandResponse::ephemeral(body))
+    }
+}
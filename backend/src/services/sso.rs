@@ -0,0 +1,394 @@
+//! Single sign-on accounts
+//!
+//! Shared by the SSO protocols. An external identity is linked to a local user
+//! by its SSO config and subject. On the first login the identity is linked
+//! to the signed-in user who started a link, to the account with the same
+//! verified email when the config allows it, or to a new user when
+//! `auto_provision` is on. Provisioned users have no password and get the
+//! config's `default_role`.
+//!
+//! Logins in flight keep their state, nonce and PKCE verifier in
+//! `sso_login_states` until the provider calls back.
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::auth::{has_password, NO_PASSWORD};
+use crate::crypto::{hash_token, random_token};
+use crate::error::{ApiResult, AppError};
+use crate::models::{SsoConfig, User, UserIdentity};
+use crate::services::sessions::{self, AuthMethod, SessionMetadata};
+use crate::services::{guests, permissions};
+
+/// How long a login may take at the provider
+pub const LOGIN_STATE_EXPIRY_MINUTES: i32 = 10;
+
+/// Profile of an external identity, after claim mapping
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExternalIdentity {
+    pub subject: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub username: Option<String>,
+    pub display_name: Option<String>,
+}
+
+/// A login waiting for the provider's callback
+///
+/// For SAML the nonce is the AuthnRequest ID.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct LoginState {
+    pub sso_config_id: Uuid,
+    pub nonce: String,
+    pub code_verifier: Option<String>,
+    pub redirect_to: Option<String>,
+    pub link_user_id: Option<Uuid>,
+}
+
+/// Load an active SSO config of `protocol` by its ID or provider name
+pub async fn find_config(db: &PgPool, provider: &str, protocol: &str) -> ApiResult<SsoConfig> {
+    let id = Uuid::parse_str(provider).ok();
+
+    sqlx::query_as(
+        r#"
+        SELECT * FROM sso_configs
+        WHERE is_active = true AND provider = $3 AND (id = $1 OR provider = $2)
+        ORDER BY created_at
+        LIMIT 1
+        "#,
+    )
+    .bind(id)
+    .bind(provider)
+    .bind(protocol)
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| AppError::NotFound(format!("SSO provider '{}' not found or disabled", provider)))
+}
+
+/// Fail unless single sign-on is turned on
+pub async fn ensure_enabled(db: &PgPool) -> ApiResult<()> {
+    let config = crate::services::auth_config::get_password_rules(db).await?;
+    if !config.enable_sso {
+        return Err(AppError::Forbidden(
+            "Single sign-on is disabled".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Reject password logins while `require_sso` is on
+///
+/// System admins keep password login so a broken provider cannot lock
+/// everyone out.
+pub async fn check_password_login(db: &PgPool, user: &User) -> ApiResult<()> {
+    if user.role == permissions::SYSTEM_ADMIN_ROLE {
+        return Ok(());
+    }
+
+    let config = crate::services::auth_config::get_password_rules(db).await?;
+    if config.require_sso {
+        return Err(AppError::Unauthorized(
+            "Password login is disabled, sign in with single sign-on".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Store a login and return the `state` parameter that identifies it
+pub async fn save_login_state(db: &PgPool, login: &LoginState) -> ApiResult<String> {
+    let state = random_token();
+
+    sqlx::query("DELETE FROM sso_login_states WHERE expires_at < NOW()")
+        .execute(db)
+        .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO sso_login_states
+            (state_hash, sso_config_id, nonce, code_verifier, redirect_to, link_user_id, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, NOW() + make_interval(mins => $7))
+        "#,
+    )
+    .bind(hash_token(&state))
+    .bind(login.sso_config_id)
+    .bind(&login.nonce)
+    .bind(&login.code_verifier)
+    .bind(&login.redirect_to)
+    .bind(login.link_user_id)
+    .bind(LOGIN_STATE_EXPIRY_MINUTES)
+    .execute(db)
+    .await?;
+
+    Ok(state)
+}
+
+/// Remove and return the login identified by `state`
+///
+/// Fails if the state is unknown, expired or was already used.
+pub async fn take_login_state(db: &PgPool, state: &str) -> ApiResult<LoginState> {
+    sqlx::query_as(
+        r#"
+        DELETE FROM sso_login_states
+        WHERE state_hash = $1 AND expires_at > NOW()
+        RETURNING sso_config_id, nonce, code_verifier, redirect_to, link_user_id
+        "#,
+    )
+    .bind(hash_token(state))
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| AppError::BadRequest("Invalid or expired login state".to_string()))
+}
+
+async fn link(
+    db: &PgPool,
+    config: &SsoConfig,
+    identity: &ExternalIdentity,
+    user_id: Uuid,
+) -> ApiResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO user_identities (user_id, sso_config_id, subject, email, last_login_at)
+        VALUES ($1, $2, $3, $4, NOW())
+        "#,
+    )
+    .bind(user_id)
+    .bind(config.id)
+    .bind(&identity.subject)
+    .bind(&identity.email)
+    .execute(db)
+    .await
+    .map_err(|e| match e {
+        sqlx::Error::Database(ref db_err) if db_err.is_unique_violation() => AppError::Conflict(
+            "Another identity from this provider is already linked to the account".to_string(),
+        ),
+        e => e.into(),
+    })?;
+
+    Ok(())
+}
+
+/// Turn a claimed username into a free, valid one
+pub async fn unique_username(db: &PgPool, wanted: &str) -> ApiResult<String> {
+    let mut base: String = wanted
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+        .take(56)
+        .collect();
+    if base.len() < 3 {
+        base = format!("user{}", base);
+    }
+
+    let mut candidate = base.clone();
+    for n in 1..100 {
+        let taken: bool =
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM users WHERE username = $1)")
+                .bind(&candidate)
+                .fetch_one(db)
+                .await?;
+        if !taken {
+            return Ok(candidate);
+        }
+        candidate = format!("{}{}", base, n + 1);
+    }
+
+    Ok(format!(
+        "{}-{}",
+        base,
+        &Uuid::new_v4().simple().to_string()[..6]
+    ))
+}
+
+async fn provision(
+    db: &PgPool,
+    config: &SsoConfig,
+    identity: &ExternalIdentity,
+) -> ApiResult<User> {
+    let wanted = identity.username.clone().unwrap_or_else(|| {
+        identity
+            .email
+            .split('@')
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    });
+    let username = unique_username(db, &wanted).await?;
+
+    let user: User = sqlx::query_as(
+        r#"
+        INSERT INTO users (username, email, password_hash, display_name, org_id, role, email_verified)
+        VALUES ($1, $2, $3, $4, $5, $6, true)
+        RETURNING *
+        "#,
+    )
+    .bind(&username)
+    .bind(&identity.email)
+    .bind(NO_PASSWORD)
+    .bind(&identity.display_name)
+    .bind(config.org_id)
+    .bind(config.default_role.as_deref().unwrap_or(guests::MEMBER_ROLE))
+    .fetch_one(db)
+    .await?;
+
+    link(db, config, identity, user.id).await?;
+
+    Ok(user)
+}
+
+/// Find or create the local user for an external identity
+///
+/// `link_user_id` is the signed-in user when the login was started to link
+/// the identity to their account.
+pub async fn resolve_user(
+    state: &AppState,
+    config: &SsoConfig,
+    identity: &ExternalIdentity,
+    link_user_id: Option<Uuid>,
+) -> ApiResult<User> {
+    let db = &state.db;
+    let linked: Option<User> = sqlx::query_as(
+        r#"
+        SELECT u.* FROM users u
+        JOIN user_identities i ON i.user_id = u.id
+        WHERE i.sso_config_id = $1 AND i.subject = $2
+        "#,
+    )
+    .bind(config.id)
+    .bind(&identity.subject)
+    .fetch_optional(db)
+    .await?;
+
+    let user = match (linked, link_user_id) {
+        (Some(user), Some(link_user_id)) if user.id != link_user_id => {
+            return Err(AppError::Conflict(
+                "This identity is already linked to another account".to_string(),
+            ));
+        }
+        (Some(user), _) => {
+            sqlx::query(
+                "UPDATE user_identities SET email = $3, last_login_at = NOW() WHERE sso_config_id = $1 AND subject = $2",
+            )
+            .bind(config.id)
+            .bind(&identity.subject)
+            .bind(&identity.email)
+            .execute(db)
+            .await?;
+            user
+        }
+        (None, Some(link_user_id)) => {
+            let user: User = sqlx::query_as("SELECT * FROM users WHERE id = $1")
+                .bind(link_user_id)
+                .fetch_one(db)
+                .await?;
+            link(db, config, identity, user.id).await?;
+            user
+        }
+        (None, None) => {
+            let existing: Option<User> =
+                sqlx::query_as("SELECT * FROM users WHERE lower(email) = lower($1)")
+                    .bind(&identity.email)
+                    .fetch_optional(db)
+                    .await?;
+
+            match existing {
+                Some(user) if config.link_by_email && identity.email_verified && !user.is_bot => {
+                    link(db, config, identity, user.id).await?;
+                    user
+                }
+                Some(_) => {
+                    return Err(AppError::Conflict(
+                        "An account with this email already exists. Sign in with your password and link your single sign-on account from your profile".to_string(),
+                    ));
+                }
+                None if config.auto_provision => provision(db, config, identity).await?,
+                None => {
+                    return Err(AppError::Forbidden(
+                        "No account is linked to this identity".to_string(),
+                    ));
+                }
+            }
+        }
+    };
+
+    if !user.is_active {
+        return Err(AppError::Unauthorized("Account is deactivated".to_string()));
+    }
+    guests::check_guest_login(db, &user).await?;
+
+    Ok(user)
+}
+
+/// Finish an SSO login for the user it resolved to
+///
+/// Returns where to send the browser: back to the page that started a link,
+/// or to the frontend's callback with a new session token.
+pub async fn finish_login(
+    state: &AppState,
+    session: SessionMetadata,
+    login: &LoginState,
+    user: &User,
+) -> ApiResult<String> {
+    if login.link_user_id.is_some() {
+        let to = login.redirect_to.as_deref().unwrap_or("/settings/profile");
+        return Ok(to.to_string());
+    }
+
+    sqlx::query("UPDATE users SET last_login_at = NOW() WHERE id = $1")
+        .bind(user.id)
+        .execute(&state.db)
+        .await?;
+    let session = session.with_auth_method(AuthMethod::Sso);
+    let token = sessions::create_session(state, user, session).await?;
+
+    let mut target = format!("/oauth/callback?token={}", token);
+    if let Some(to) = &login.redirect_to {
+        target.push_str(&format!("&redirect_to={}", urlencoding::encode(to)));
+    }
+
+    Ok(target)
+}
+
+/// List the identities linked to a user
+pub async fn list_identities(db: &PgPool, user_id: Uuid) -> ApiResult<Vec<UserIdentity>> {
+    let identities =
+        sqlx::query_as("SELECT * FROM user_identities WHERE user_id = $1 ORDER BY created_at")
+            .bind(user_id)
+            .fetch_all(db)
+            .await?;
+
+    Ok(identities)
+}
+
+/// Unlink one of a user's identities
+///
+/// The last identity of an account without a password stays, or the user
+/// could no longer sign in.
+pub async fn unlink_identity(db: &PgPool, user_id: Uuid, identity_id: Uuid) -> ApiResult<()> {
+    let (password_hash, identities): (String, i64) = sqlx::query_as(
+        r#"
+        SELECT u.password_hash, (SELECT COUNT(*) FROM user_identities WHERE user_id = u.id)
+        FROM users u WHERE u.id = $1
+        "#,
+    )
+    .bind(user_id)
+    .fetch_one(db)
+    .await?;
+    if !has_password(&password_hash) && identities <= 1 {
+        return Err(AppError::BadRequest(
+            "Set a password before unlinking your only sign-in method".to_string(),
+        ));
+    }
+
+    let removed = sqlx::query("DELETE FROM user_identities WHERE id = $1 AND user_id = $2")
+        .bind(identity_id)
+        .bind(user_id)
+        .execute(db)
+        .await?;
+    if removed.rows_affected() == 0 {
+        return Err(AppError::NotFound("Identity not found".to_string()));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,336 @@
+//! Team invitations
+//!
+//! People join a team through its invite link, which carries the team's
+//! `invite_id`, or through a single-use invitation emailed to them. Both work
+//! for people without an account yet: signing up with an invite skips the
+//! `allow_registration` check. Regenerating the invite ID revokes old links.
+//! Teams can limit who joins to a list of email domains. Guests never join
+//! through invites, they are added to their channels by an admin.
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::crypto::{hash_token, random_token};
+use crate::error::{ApiResult, AppError};
+use crate::models::{Team, TeamMember, User};
+use crate::services::{account_tokens, email_templates, guests};
+
+/// How long an emailed invitation stays valid
+pub const TEAM_INVITE_EXPIRY_HOURS: i64 = 48;
+
+/// Most addresses one request may invite
+pub const MAX_EMAIL_INVITES: usize = 20;
+
+/// How someone was invited to a team
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invite {
+    /// The team's shareable invite ID
+    Link(String),
+    /// Token from an emailed invitation
+    Token(String),
+}
+
+impl Invite {
+    /// Pick the invite out of optional request parameters, preferring the token
+    pub fn from_params(token: Option<String>, invite_id: Option<String>) -> Option<Self> {
+        let non_empty = |value: Option<String>| value.filter(|v| !v.trim().is_empty());
+
+        non_empty(token)
+            .map(Self::Token)
+            .or_else(|| non_empty(invite_id).map(Self::Link))
+    }
+}
+
+/// A checked invite that has not been used yet
+#[derive(Debug, Clone)]
+pub struct PendingInvite {
+    pub team: Team,
+    /// ID and address of the emailed invitation, if any
+    invitation: Option<(Uuid, String)>,
+}
+
+impl PendingInvite {
+    /// Whether this is an emailed invitation sent to `email`
+    pub fn sent_to(&self, email: &str) -> bool {
+        self.invitation
+            .as_ref()
+            .is_some_and(|(_, to)| to.eq_ignore_ascii_case(email.trim()))
+    }
+}
+
+/// A new random invite ID
+pub fn new_invite_id() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+fn domains(allowed_domains: &str) -> impl Iterator<Item = String> + '_ {
+    allowed_domains
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .map(|d| d.trim().trim_start_matches('@').to_lowercase())
+        .filter(|d| !d.is_empty())
+}
+
+/// Normalize a user-entered domain list to the stored form
+pub fn normalize_domains(allowed_domains: &str) -> String {
+    domains(allowed_domains).collect::<Vec<_>>().join(",")
+}
+
+/// Whether `email` may join a team with these allowed domains
+pub fn email_domain_allowed(allowed_domains: &str, email: &str) -> bool {
+    let mut allowed = domains(allowed_domains).peekable();
+    if allowed.peek().is_none() {
+        return true;
+    }
+    let Some((_, domain)) = email.trim().rsplit_once('@') else {
+        return false;
+    };
+    let domain = domain.to_lowercase();
+
+    allowed.any(|d| d == domain)
+}
+
+/// Fail unless `email` is in one of the team's allowed domains
+pub fn ensure_domain_allowed(team: &Team, email: &str) -> ApiResult<()> {
+    if !email_domain_allowed(&team.allowed_domains, email) {
+        return Err(AppError::Forbidden(format!(
+            "Email address {} is not in a domain allowed to join this team",
+            email
+        )));
+    }
+
+    Ok(())
+}
+
+async fn find_team(db: &PgPool, team_id: Uuid) -> ApiResult<Team> {
+    sqlx::query_as("SELECT * FROM teams WHERE id = $1")
+        .bind(team_id)
+        .fetch_optional(db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Team not found".to_string()))
+}
+
+/// Replace the team's invite ID, revoking links that use the old one
+pub async fn regenerate_invite_id(db: &PgPool, team_id: Uuid) -> ApiResult<String> {
+    sqlx::query_scalar(
+        "UPDATE teams SET invite_id = $2, updated_at = NOW() WHERE id = $1 RETURNING invite_id",
+    )
+    .bind(team_id)
+    .bind(new_invite_id())
+    .fetch_optional(db)
+    .await?
+    .ok_or_else(|| AppError::NotFound("Team not found".to_string()))
+}
+
+/// Email an invitation to join `team_id` to each address
+pub async fn send_email_invites(
+    state: &AppState,
+    team_id: Uuid,
+    inviter_id: Uuid,
+    emails: &[String],
+) -> ApiResult<()> {
+    if emails.is_empty() {
+        return Err(AppError::BadRequest("No email addresses given".to_string()));
+    }
+    if emails.len() > MAX_EMAIL_INVITES {
+        return Err(AppError::BadRequest(format!(
+            "Cannot invite more than {} addresses at once",
+            MAX_EMAIL_INVITES
+        )));
+    }
+
+    let team = find_team(&state.db, team_id).await?;
+    for email in emails {
+        if !email.contains('@') {
+            return Err(AppError::Validation(format!(
+                "Invalid email address: {}",
+                email
+            )));
+        }
+        ensure_domain_allowed(&team, email)?;
+    }
+
+    let (config, branding) = account_tokens::email_settings(&state.db).await?;
+    let inviter: String = sqlx::query_scalar(
+        "SELECT COALESCE(NULLIF(display_name, ''), username) FROM users WHERE id = $1",
+    )
+    .bind(inviter_id)
+    .fetch_one(&state.db)
+    .await?;
+    let team_name = team.display_name.as_deref().unwrap_or(&team.name);
+
+    for email in emails {
+        let token = random_token();
+        sqlx::query(
+            r#"
+            INSERT INTO team_invitations (team_id, email, token_hash, invited_by, expires_at)
+            VALUES ($1, $2, $3, $4, NOW() + make_interval(hours => $5))
+            "#,
+        )
+        .bind(team.id)
+        .bind(email.trim())
+        .bind(hash_token(&token))
+        .bind(inviter_id)
+        .bind(TEAM_INVITE_EXPIRY_HOURS as i32)
+        .execute(&state.db)
+        .await?;
+
+        let link = format!("{}/signup_user_complete/?t={}", branding.site_url, token);
+        let rendered = email_templates::team_invite(
+            &branding,
+            &inviter,
+            team_name,
+            &link,
+            TEAM_INVITE_EXPIRY_HOURS,
+        );
+        crate::services::email::send_html_email(
+            state,
+            &config,
+            email.trim(),
+            &rendered.subject,
+            &rendered.html,
+            &rendered.text,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Look up the team an invite is for
+///
+/// Fails if the invite ID is unknown or the token is unknown, expired or
+/// already used.
+pub async fn resolve(db: &PgPool, invite: &Invite) -> ApiResult<PendingInvite> {
+    let invalid = || AppError::BadRequest("Invalid or expired invite".to_string());
+
+    match invite {
+        Invite::Link(invite_id) => {
+            let team: Team = sqlx::query_as("SELECT * FROM teams WHERE invite_id = $1")
+                .bind(invite_id.trim())
+                .fetch_optional(db)
+                .await?
+                .ok_or_else(invalid)?;
+
+            Ok(PendingInvite {
+                team,
+                invitation: None,
+            })
+        }
+        Invite::Token(token) => {
+            let (id, team_id, email): (Uuid, Uuid, String) = sqlx::query_as(
+                r#"
+                SELECT id, team_id, email FROM team_invitations
+                WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+                "#,
+            )
+            .bind(hash_token(token.trim()))
+            .fetch_optional(db)
+            .await?
+            .ok_or_else(invalid)?;
+
+            Ok(PendingInvite {
+                team: find_team(db, team_id).await?,
+                invitation: Some((id, email)),
+            })
+        }
+    }
+}
+
+/// Add `user` to the invite's team, using up an emailed invitation
+///
+/// Users who are already members keep their membership and the invitation
+/// stays unused.
+pub async fn accept(state: &AppState, invite: PendingInvite, user: &User) -> ApiResult<TeamMember> {
+    guests::ensure_not_guest(&user.role)?;
+    ensure_domain_allowed(&invite.team, &user.email)?;
+
+    let existing: Option<TeamMember> =
+        sqlx::query_as("SELECT * FROM team_members WHERE team_id = $1 AND user_id = $2")
+            .bind(invite.team.id)
+            .bind(user.id)
+            .fetch_optional(&state.db)
+            .await?;
+    if let Some(member) = existing {
+        return Ok(member);
+    }
+
+    let mut tx = state.db.begin().await?;
+    if let Some((invitation_id, _)) = &invite.invitation {
+        let used = sqlx::query(
+            "UPDATE team_invitations SET used_at = NOW() WHERE id = $1 AND used_at IS NULL",
+        )
+        .bind(invitation_id)
+        .execute(&mut *tx)
+        .await?;
+        if used.rows_affected() == 0 {
+            return Err(AppError::BadRequest(
+                "Invalid or expired invite".to_string(),
+            ));
+        }
+    }
+
+    let member: TeamMember = sqlx::query_as(
+        r#"
+        INSERT INTO team_members (team_id, user_id, role)
+        VALUES ($1, $2, 'member')
+        RETURNING *
+        "#,
+    )
+    .bind(invite.team.id)
+    .bind(user.id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    // Also add the user to all public channels in the team
+    sqlx::query(
+        r#"
+        INSERT INTO channel_members (channel_id, user_id)
+        SELECT c.id, $1 FROM channels c
+        WHERE c.team_id = $2 AND c.type = 'public'::channel_type
+        ON CONFLICT (channel_id, user_id) DO NOTHING
+        "#,
+    )
+    .bind(user.id)
+    .bind(invite.team.id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(member)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_domain_list_allows_everyone() {
+        assert!(email_domain_allowed("", "bob@anywhere.org"));
+        assert!(email_domain_allowed(" , ", "bob@anywhere.org"));
+    }
+
+    #[test]
+    fn domains_match_exactly_and_ignore_case() {
+        let allowed = normalize_domains("Example.com, @partner.io");
+        assert_eq!("example.com,partner.io", allowed);
+        assert!(email_domain_allowed(&allowed, "alice@EXAMPLE.com"));
+        assert!(email_domain_allowed(&allowed, "carol@partner.io"));
+        assert!(!email_domain_allowed(&allowed, "mallory@evil-example.com"));
+        assert!(!email_domain_allowed(&allowed, "mallory@sub.example.com"));
+        assert!(!email_domain_allowed(&allowed, "not-an-email"));
+    }
+
+    #[test]
+    fn token_takes_precedence_over_invite_id() {
+        assert_eq!(
+            Some(Invite::Token("t".to_string())),
+            Invite::from_params(Some("t".to_string()), Some("i".to_string()))
+        );
+        assert_eq!(
+            Some(Invite::Link("i".to_string())),
+            Invite::from_params(Some(" ".to_string()), Some("i".to_string()))
+        );
+        assert_eq!(None, Invite::from_params(None, None));
+    }
+}
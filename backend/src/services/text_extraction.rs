@@ -0,0 +1,256 @@
+//! Text extraction from uploaded documents
+//!
+//! Plain text, PDFs, and the zipped XML of Office and OpenDocument files are
+//! read for their text, so file search finds documents by what they say as
+//! well as by name. Extraction runs as part of media processing.
+
+use std::io::{Cursor, Read};
+
+use roxmltree::Document as Xml;
+use zip::ZipArchive;
+
+use crate::error::AppError;
+
+/// Documents larger than this are searched by name only
+pub const MAX_DOCUMENT_BYTES: i64 = 50 * 1024 * 1024;
+
+/// Text kept per document; Postgres will not index much more
+const MAX_TEXT_BYTES: usize = 256 * 1024;
+
+/// Largest XML part read out of an archive, so a small zip cannot expand
+/// into an unbounded amount of memory
+const MAX_PART_BYTES: u64 = 32 * 1024 * 1024;
+
+/// Extensions of plain text files sent without a text type
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "csv", "tsv", "log", "json", "xml", "yaml", "yml",
+];
+
+/// Kinds of documents text can be read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Document {
+    Text,
+    Pdf,
+    Word,
+    PowerPoint,
+    Excel,
+    OpenDocument,
+}
+
+impl Document {
+    /// The kind of document a file is, by its type or else its extension
+    pub fn of(name: &str, mime_type: &str) -> Option<Self> {
+        let by_type = match mime_type {
+            "application/pdf" => Some(Self::Pdf),
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => {
+                Some(Self::Word)
+            }
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation" => {
+                Some(Self::PowerPoint)
+            }
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => {
+                Some(Self::Excel)
+            }
+            "application/vnd.oasis.opendocument.text"
+            | "application/vnd.oasis.opendocument.presentation"
+            | "application/vnd.oasis.opendocument.spreadsheet" => Some(Self::OpenDocument),
+            "application/json" | "application/xml" => Some(Self::Text),
+            _ if mime_type.starts_with("text/") => Some(Self::Text),
+            _ => None,
+        };
+        if by_type.is_some() {
+            return by_type;
+        }
+
+        let (_, extension) = name.rsplit_once('.')?;
+        match extension.to_lowercase().as_str() {
+            "pdf" => Some(Self::Pdf),
+            "docx" => Some(Self::Word),
+            "pptx" => Some(Self::PowerPoint),
+            "xlsx" => Some(Self::Excel),
+            "odt" | "odp" | "ods" => Some(Self::OpenDocument),
+            ext if TEXT_EXTENSIONS.contains(&ext) => Some(Self::Text),
+            _ => None,
+        }
+    }
+
+    /// The document's text, cut short if there is a lot of it
+    pub fn extract(self, data: &[u8]) -> Result<String, AppError> {
+        let text = match self {
+            Self::Text => String::from_utf8_lossy(data).into_owned(),
+            Self::Pdf => pdf_extract::extract_text_from_mem(data)
+                .map_err(|e| AppError::Internal(format!("Unreadable PDF: {}", e)))?,
+            Self::Word => {
+                let mut archive = open_archive(data)?;
+                xml_text(
+                    &read_part(&mut archive, "word/document.xml")?,
+                    Some("t"),
+                    &["p"],
+                )?
+            }
+            Self::PowerPoint => {
+                let mut archive = open_archive(data)?;
+                // Slides in order, not in the order the archive lists them
+                let mut slides: Vec<(u32, String)> = archive
+                    .file_names()
+                    .filter_map(|name| {
+                        let number = name
+                            .strip_prefix("ppt/slides/slide")?
+                            .strip_suffix(".xml")?
+                            .parse()
+                            .ok()?;
+                        Some((number, name.to_string()))
+                    })
+                    .collect();
+                slides.sort();
+
+                let mut text = String::new();
+                for (_, name) in slides {
+                    let slide = xml_text(&read_part(&mut archive, &name)?, Some("t"), &["p"])?;
+                    text.push_str(&slide);
+                    text.push('\n');
+                }
+                text
+            }
+            Self::Excel => {
+                // Cells with text keep it in the shared strings
+                let mut archive = open_archive(data)?;
+                match read_part(&mut archive, "xl/sharedStrings.xml") {
+                    Ok(xml) => xml_text(&xml, Some("t"), &["si"])?,
+                    Err(_) => String::new(),
+                }
+            }
+            Self::OpenDocument => {
+                let mut archive = open_archive(data)?;
+                xml_text(&read_part(&mut archive, "content.xml")?, None, &["p", "h"])?
+            }
+        };
+
+        Ok(truncate(text))
+    }
+}
+
+fn open_archive(data: &[u8]) -> Result<ZipArchive<Cursor<&[u8]>>, AppError> {
+    ZipArchive::new(Cursor::new(data))
+        .map_err(|e| AppError::Internal(format!("Unreadable document: {}", e)))
+}
+
+fn read_part(archive: &mut ZipArchive<Cursor<&[u8]>>, name: &str) -> Result<String, AppError> {
+    let part = archive
+        .by_name(name)
+        .map_err(|e| AppError::Internal(format!("Document has no {}: {}", name, e)))?;
+    let mut xml = String::new();
+    part.take(MAX_PART_BYTES)
+        .read_to_string(&mut xml)
+        .map_err(|e| AppError::Internal(format!("Unreadable {}: {}", name, e)))?;
+    Ok(xml)
+}
+
+/// Text of an XML part, from `text_element`s only if given, with a line
+/// break before each paragraph element
+fn xml_text(
+    xml: &str,
+    text_element: Option<&str>,
+    paragraphs: &[&str],
+) -> Result<String, AppError> {
+    let doc = Xml::parse(xml).map_err(|e| AppError::Internal(format!("Unreadable XML: {}", e)))?;
+
+    let mut text = String::new();
+    for node in doc.descendants() {
+        if node.is_element() && paragraphs.contains(&node.tag_name().name()) && !text.is_empty() {
+            text.push('\n');
+        }
+        if node.is_text() {
+            let parent = node.parent_element().map(|p| p.tag_name().name());
+            if text_element.is_none_or(|name| parent == Some(name)) {
+                text.push_str(node.text().unwrap_or_default());
+            }
+        }
+    }
+    Ok(text)
+}
+
+/// At most [`MAX_TEXT_BYTES`] of text, without the NUL characters Postgres
+/// refuses to store
+fn truncate(mut text: String) -> String {
+    text.retain(|c| c != '\0');
+    if text.len() > MAX_TEXT_BYTES {
+        let mut end = MAX_TEXT_BYTES;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn archive(parts: &[(&str, &str)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut zip = zip::ZipWriter::new(Cursor::new(&mut data));
+        for (name, content) in parts {
+            zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+        data
+    }
+
+    #[test]
+    fn documents_are_recognised_by_type_or_extension() {
+        assert_eq!(Some(Document::Pdf), Document::of("scan", "application/pdf"));
+        assert_eq!(Some(Document::Text), Document::of("a.bin", "text/csv"));
+        assert_eq!(
+            Some(Document::Word),
+            Document::of("Report.DOCX", "application/octet-stream")
+        );
+        assert_eq!(None, Document::of("photo.jpg", "image/jpeg"));
+        assert_eq!(None, Document::of("README", "application/octet-stream"));
+    }
+
+    #[test]
+    fn word_documents_keep_their_paragraphs() {
+        let data = archive(&[(
+            "word/document.xml",
+            r#"<w:document xmlns:w="urn:w"><w:body>
+                <w:p><w:r><w:t>Quarterly</w:t></w:r><w:r><w:t xml:space="preserve"> results</w:t></w:r></w:p>
+                <w:p><w:r><w:instrText>PAGE</w:instrText><w:t>Revenue grew</w:t></w:r></w:p>
+            </w:body></w:document>"#,
+        )]);
+
+        let text = Document::Word.extract(&data).unwrap();
+        assert_eq!("Quarterly results\nRevenue grew", text);
+    }
+
+    #[test]
+    fn slides_are_read_in_order() {
+        let slide = |text: &str| {
+            format!(
+                r#"<p:sld xmlns:p="urn:p" xmlns:a="urn:a"><a:p><a:t>{}</a:t></a:p></p:sld>"#,
+                text
+            )
+        };
+        let data = archive(&[
+            ("ppt/slides/slide10.xml", &slide("last")),
+            ("ppt/slides/slide2.xml", &slide("second")),
+            ("ppt/slides/slide1.xml", &slide("first")),
+        ]);
+
+        let text = Document::PowerPoint.extract(&data).unwrap();
+        assert_eq!("first\nsecond\nlast\n", text);
+    }
+
+    #[test]
+    fn long_text_is_cut_on_a_character_boundary() {
+        let text = "é".repeat(MAX_TEXT_BYTES);
+        let cut = Document::Text.extract(text.as_bytes()).unwrap();
+        assert_eq!(MAX_TEXT_BYTES, cut.len());
+
+        assert_eq!("ab", Document::Text.extract(b"a\0b").unwrap());
+    }
+}
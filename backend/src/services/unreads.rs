@@ -124,6 +124,9 @@ pub async fn mark_channel_as_read(
         state.ws_hub.broadcast(broadcast).await;
     }
 
+    // Dismiss the channel's notifications on the user's devices
+    crate::services::push_notifications::spawn_clear_notifications(state, user_id, channel_id);
+
     Ok(())
 }
 
@@ -192,6 +195,32 @@ pub async fn get_unread_overview(state: &AppState, user_id: Uuid) -> ApiResult<U
     })
 }
 
+/// Total number of unread posts for a user, e.g. for app icon badges
+///
+/// Falls back to counting from Postgres when Redis is unavailable.
+pub async fn total_unread_count(state: &AppState, user_id: Uuid) -> ApiResult<i64> {
+    if let Ok(overview) = get_unread_overview(state, user_id).await {
+        return Ok(overview.teams.iter().map(|t| t.unread_count).sum());
+    }
+
+    let count: i64 = sqlx::query_scalar(
+        r#"
+        SELECT COUNT(*)
+        FROM channel_members cm
+        LEFT JOIN channel_reads cr ON cr.user_id = cm.user_id AND cr.channel_id = cm.channel_id
+        JOIN posts p ON p.channel_id = cm.channel_id
+        WHERE cm.user_id = $1
+          AND p.seq > COALESCE(cr.last_read_message_id, 0)
+          AND p.deleted_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .fetch_one(&state.db)
+    .await?;
+
+    Ok(count)
+}
+
 /// Increment unread counts for a new message
 pub async fn increment_unreads(
     state: &AppState,
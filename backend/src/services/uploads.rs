@@ -0,0 +1,452 @@
+//! File uploads
+//!
+//! Files are streamed into storage as they arrive and hashed on the way, so
+//! the server never holds a whole file in memory. Anything larger than the
+//! site's `max_file_size_mb` is refused as soon as it passes the limit.
+//! Once stored, a file is scanned for malware if scanning is on.
+//!
+//! Upload sessions let clients send a file over several requests, so a
+//! dropped connection does not mean starting over. Each request appends to
+//! the session, and its progress is saved when the request ends, however it
+//! ends. The session's id becomes the file's id once the last byte arrives.
+
+use std::fmt::Display;
+
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::models::{FileInfo, SiteConfig, UploadSession};
+use crate::services::virus_scan;
+use crate::storage::{ObjectWriter, UploadHash, UploadProgress};
+
+/// How long an append holds its session without saving progress
+const APPEND_LOCK_MINUTES: i32 = 10;
+
+/// Progress is saved at least this often while appending
+const SAVE_INTERVAL_SECS: u64 = 30;
+
+/// Sessions left untouched for this long are discarded
+pub const SESSION_EXPIRY_DAYS: i32 = 7;
+
+/// The largest file the site accepts, in bytes
+pub async fn max_file_size(state: &AppState) -> ApiResult<u64> {
+    let site: sqlx::types::Json<SiteConfig> =
+        sqlx::query_scalar("SELECT site FROM server_config WHERE id = 'default'")
+            .fetch_one(&state.db)
+            .await?;
+
+    Ok(site.max_file_size_mb.max(0) as u64 * 1024 * 1024)
+}
+
+/// Storage key of an uploaded file
+pub fn file_key(user_id: Uuid, file_id: Uuid, filename: &str) -> String {
+    let extension = filename.rsplit('.').next().unwrap_or("");
+    format!("files/{}/{}.{}", user_id, file_id, extension)
+}
+
+/// Stream a file into storage and record it
+pub async fn store_stream<S, E>(
+    state: &AppState,
+    user_id: Uuid,
+    channel_id: Option<Uuid>,
+    filename: &str,
+    content_type: &str,
+    stream: S,
+) -> ApiResult<FileInfo>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send,
+    E: Display + Send,
+{
+    let limit = max_file_size(state).await?;
+    let file_id = Uuid::new_v4();
+    let key = file_key(user_id, file_id, filename);
+    let backend = state.storage.current();
+
+    let writer = ObjectWriter::new(backend, &key, content_type, limit);
+    let (size, sha256) = write_stream(writer, stream).await?;
+
+    record_file(
+        state,
+        file_id,
+        user_id,
+        channel_id,
+        filename,
+        content_type,
+        &key,
+        backend.name(),
+        size,
+        &sha256,
+    )
+    .await
+    .and_then(refuse_quarantined)
+}
+
+/// Write all of `stream` and store the object, returning its size and SHA-256
+///
+/// The upload is discarded if the stream breaks off or passes the writer's
+/// limit.
+pub async fn write_stream<S, E>(mut writer: ObjectWriter<'_>, stream: S) -> ApiResult<(u64, String)>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send,
+    E: Display + Send,
+{
+    let mut stream = std::pin::pin!(stream);
+    while let Some(chunk) = stream.next().await {
+        let result = match chunk {
+            Ok(chunk) => writer.write(&chunk).await,
+            Err(e) => Err(interrupted(e)),
+        };
+        if let Err(e) = result {
+            writer.abort().await;
+            return Err(e);
+        }
+    }
+
+    writer.finish().await
+}
+
+fn refuse_quarantined(file: FileInfo) -> ApiResult<FileInfo> {
+    virus_scan::ensure_servable(&file)?;
+    Ok(file)
+}
+
+fn interrupted(e: impl Display) -> AppError {
+    AppError::BadRequest(format!("Upload interrupted: {}", e))
+}
+
+/// Record a stored file, after scanning it if scanning is on, and queue it
+/// for media processing if it has any. Quarantined files are recorded but
+/// refused.
+#[allow(clippy::too_many_arguments)]
+async fn record_file(
+    state: &AppState,
+    file_id: Uuid,
+    user_id: Uuid,
+    channel_id: Option<Uuid>,
+    filename: &str,
+    content_type: &str,
+    key: &str,
+    backend: &str,
+    size: u64,
+    sha256: &str,
+) -> ApiResult<FileInfo> {
+    let scan = virus_scan::scan_stored(state, backend, key).await?;
+    let quarantined = scan.as_ref().is_some_and(|scan| scan.status != "clean");
+    let media_status = (!quarantined && state.media.handles(filename, content_type, size as i64))
+        .then_some("pending");
+    let file: FileInfo = sqlx::query_as(
+        r#"
+        INSERT INTO files
+            (id, uploader_id, channel_id, name, key, mime_type, size, sha256, backend, media_status,
+             scan_status, scan_result, scanned_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, CASE WHEN $11::VARCHAR IS NOT NULL THEN NOW() END)
+        RETURNING *
+        "#,
+    )
+    .bind(file_id)
+    .bind(user_id)
+    .bind(channel_id)
+    .bind(filename)
+    .bind(key)
+    .bind(content_type)
+    .bind(size as i64)
+    .bind(sha256)
+    .bind(backend)
+    .bind(media_status)
+    .bind(scan.as_ref().map(|scan| scan.status))
+    .bind(scan.and_then(|scan| scan.result))
+    .fetch_one(&state.db)
+    .await?;
+
+    if file.is_quarantined() {
+        virus_scan::audit_quarantine(&state.db, &file).await?;
+    }
+    if file.media_status.is_some() {
+        state.media.queue(state, file.id);
+    }
+
+    Ok(file)
+}
+
+/// Start a session for a file of `file_size` bytes
+pub async fn create_session(
+    state: &AppState,
+    user_id: Uuid,
+    channel_id: Option<Uuid>,
+    filename: &str,
+    file_size: i64,
+) -> ApiResult<UploadSession> {
+    let filename = filename.trim();
+    if filename.is_empty() || filename.len() > 255 {
+        return Err(AppError::BadRequest("Invalid filename".to_string()));
+    }
+    if file_size <= 0 {
+        return Err(AppError::BadRequest("Invalid file size".to_string()));
+    }
+    let limit = max_file_size(state).await?;
+    if file_size as u64 > limit {
+        return Err(AppError::PayloadTooLarge(format!(
+            "File is larger than {} bytes",
+            limit
+        )));
+    }
+
+    let id = Uuid::new_v4();
+    let mime_type = mime_guess::from_path(filename).first_or_octet_stream();
+    let session = sqlx::query_as(
+        r#"
+        INSERT INTO upload_sessions
+            (id, user_id, channel_id, filename, mime_type, key, backend, file_size, hash_state)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        RETURNING *
+        "#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .bind(channel_id)
+    .bind(filename)
+    .bind(mime_type.essence_str())
+    .bind(file_key(user_id, id, filename))
+    .bind(state.storage.current().name())
+    .bind(file_size)
+    .bind(UploadHash::default().to_bytes())
+    .fetch_one(&state.db)
+    .await?;
+
+    Ok(session)
+}
+
+/// One of the user's sessions
+pub async fn get_session(state: &AppState, user_id: Uuid, id: Uuid) -> ApiResult<UploadSession> {
+    sqlx::query_as("SELECT * FROM upload_sessions WHERE id = $1 AND user_id = $2")
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&state.db)
+        .await?
+        .ok_or_else(|| AppError::NotFound("Upload session not found".to_string()))
+}
+
+/// The user's unfinished sessions, oldest first
+pub async fn list_sessions(state: &AppState, user_id: Uuid) -> ApiResult<Vec<UploadSession>> {
+    let sessions =
+        sqlx::query_as("SELECT * FROM upload_sessions WHERE user_id = $1 ORDER BY created_at")
+            .bind(user_id)
+            .fetch_all(&state.db)
+            .await?;
+
+    Ok(sessions)
+}
+
+/// Append a request body to a session
+///
+/// Returns the file once the session has all of it. The append runs in its
+/// own task, so progress is saved even if the client goes away and the
+/// request handler is dropped.
+pub async fn append<S, E>(
+    state: &AppState,
+    user_id: Uuid,
+    id: Uuid,
+    stream: S,
+) -> ApiResult<Option<FileInfo>>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Display + Send + 'static,
+{
+    let session: UploadSession = match sqlx::query_as(
+        r#"
+        UPDATE upload_sessions SET locked_until = NOW() + make_interval(mins => $3)
+        WHERE id = $1 AND user_id = $2 AND (locked_until IS NULL OR locked_until < NOW())
+        RETURNING *
+        "#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .bind(APPEND_LOCK_MINUTES)
+    .fetch_optional(&state.db)
+    .await?
+    {
+        Some(session) => session,
+        None => {
+            get_session(state, user_id, id).await?;
+            return Err(AppError::Conflict(
+                "The upload is already being appended to".to_string(),
+            ));
+        }
+    };
+
+    let state = state.clone();
+    tokio::spawn(async move { append_locked(&state, session, stream).await })
+        .await
+        .map_err(|e| AppError::Internal(format!("Upload task failed: {}", e)))?
+}
+
+async fn append_locked<S, E>(
+    state: &AppState,
+    session: UploadSession,
+    stream: S,
+) -> ApiResult<Option<FileInfo>>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send,
+    E: Display + Send,
+{
+    let backend = state.storage.backend(&session.backend)?;
+    let hash = UploadHash::from_bytes(&session.hash_state)
+        .ok_or_else(|| AppError::Internal("Corrupt upload session".to_string()))?;
+    let progress = UploadProgress {
+        upload_id: session.storage_upload_id.clone(),
+        part_tags: session.part_tags.clone(),
+        pending: session.pending.clone(),
+        hash,
+        size: session.file_offset as u64,
+    };
+    let mut writer = ObjectWriter::resume(
+        backend,
+        &session.key,
+        &session.mime_type,
+        session.file_size as u64,
+        progress,
+    );
+
+    let mut saved_parts = session.part_tags.len();
+    let mut saved_at = tokio::time::Instant::now();
+    let mut stream = std::pin::pin!(stream);
+    let mut result = Ok(());
+    while let Some(chunk) = stream.next().await {
+        result = match chunk {
+            Ok(chunk) => writer.write(&chunk).await,
+            Err(e) => Err(interrupted(e)),
+        };
+        if result.is_err() {
+            break;
+        }
+
+        // Keep the lock, and lose little if the server goes down
+        let parts = writer.progress().part_tags.len();
+        if parts != saved_parts || saved_at.elapsed().as_secs() >= SAVE_INTERVAL_SECS {
+            result = save_progress(state, session.id, writer.progress(), true).await;
+            if result.is_err() {
+                break;
+            }
+            saved_parts = parts;
+            saved_at = tokio::time::Instant::now();
+        }
+    }
+
+    if let Err(e) = result {
+        if let Err(save_error) = save_progress(state, session.id, writer.progress(), false).await {
+            error!(upload_id = %session.id, error = %save_error, "Failed to save upload progress");
+        }
+        return Err(e);
+    }
+
+    if writer.size() < session.file_size as u64 {
+        let result = match writer.checkpoint().await {
+            Ok(progress) => save_progress(state, session.id, progress, false).await,
+            Err(e) => {
+                save_progress(state, session.id, writer.progress(), false).await?;
+                Err(e)
+            }
+        };
+        return result.map(|_| None);
+    }
+
+    // A failed finish is retried by appending nothing
+    save_progress(state, session.id, writer.progress(), true).await?;
+    let (size, sha256) = match writer.finish().await {
+        Ok(finished) => finished,
+        Err(e) => {
+            sqlx::query("UPDATE upload_sessions SET locked_until = NULL WHERE id = $1")
+                .bind(session.id)
+                .execute(&state.db)
+                .await?;
+            return Err(e);
+        }
+    };
+    let file = record_file(
+        state,
+        session.id,
+        session.user_id,
+        session.channel_id,
+        &session.filename,
+        &session.mime_type,
+        &session.key,
+        &session.backend,
+        size,
+        &sha256,
+    )
+    .await?;
+    sqlx::query("DELETE FROM upload_sessions WHERE id = $1")
+        .bind(session.id)
+        .execute(&state.db)
+        .await?;
+
+    refuse_quarantined(file).map(Some)
+}
+
+/// Save a session's progress, keeping or releasing its lock
+async fn save_progress(
+    state: &AppState,
+    id: Uuid,
+    progress: &UploadProgress,
+    keep_lock: bool,
+) -> ApiResult<()> {
+    sqlx::query(
+        r#"
+        UPDATE upload_sessions SET
+            file_offset = $2, storage_upload_id = $3, part_tags = $4, pending = $5,
+            hash_state = $6, updated_at = NOW(),
+            locked_until = CASE WHEN $7 THEN NOW() + make_interval(mins => $8) END
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .bind(progress.size as i64)
+    .bind(&progress.upload_id)
+    .bind(&progress.part_tags)
+    .bind(&progress.pending)
+    .bind(progress.hash.to_bytes())
+    .bind(keep_lock)
+    .bind(APPEND_LOCK_MINUTES)
+    .execute(&state.db)
+    .await?;
+
+    Ok(())
+}
+
+/// Discard sessions left untouched, with any parts already stored
+pub async fn expire_sessions(state: &AppState) -> ApiResult<u64> {
+    let expired: Vec<UploadSession> = sqlx::query_as(
+        r#"
+        DELETE FROM upload_sessions
+        WHERE updated_at < NOW() - make_interval(days => $1)
+          AND (locked_until IS NULL OR locked_until < NOW())
+        RETURNING *
+        "#,
+    )
+    .bind(SESSION_EXPIRY_DAYS)
+    .fetch_all(&state.db)
+    .await?;
+
+    for session in &expired {
+        let Some(upload_id) = &session.storage_upload_id else {
+            continue;
+        };
+        let result = match state.storage.backend(&session.backend) {
+            Ok(backend) => {
+                backend
+                    .abort_multipart_upload(&session.key, upload_id)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            warn!(upload_id = %session.id, error = %e, "Failed to discard expired upload");
+        }
+    }
+
+    Ok(expired.len() as u64)
+}
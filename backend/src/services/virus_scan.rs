@@ -0,0 +1,310 @@
+//! Malware scanning of uploads with ClamAV
+//!
+//! When scanning is on, each upload is streamed from storage to clamd with
+//! its `INSTREAM` command before the file is recorded. Files found infected
+//! are quarantined: the row is kept, marked and audited, but the file is
+//! never served. Files clamd could not check are held back the same way.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use sqlx::PgPool;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UnixStream};
+use tracing::warn;
+
+use crate::api::AppState;
+use crate::error::{ApiResult, AppError};
+use crate::models::{FileInfo, FileScanningConfig};
+
+/// Size of the chunks content is sent to clamd in
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Longest reply read from clamd
+const MAX_REPLY_BYTES: u64 = 4096;
+
+/// How long a health check waits for clamd
+const PING_TIMEOUT_SECS: u64 = 5;
+
+/// What clamd made of a file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    /// Infected with the named threat
+    Infected(String),
+}
+
+/// Where clamd listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Address {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl Address {
+    /// `unix:/path` or `/path` for a Unix socket, `tcp://host:port` or
+    /// `host:port` for TCP
+    fn parse(address: &str) -> Self {
+        let address = address.trim();
+        if let Some(path) = address.strip_prefix("unix:") {
+            return Self::Unix(path.into());
+        }
+        if address.starts_with('/') {
+            return Self::Unix(address.into());
+        }
+        Self::Tcp(
+            address
+                .strip_prefix("tcp://")
+                .unwrap_or(address)
+                .to_string(),
+        )
+    }
+}
+
+/// A clamd daemon
+pub struct Clamd {
+    address: Address,
+    timeout: Duration,
+}
+
+impl Clamd {
+    pub fn new(config: &FileScanningConfig) -> Self {
+        Self {
+            address: Address::parse(&config.clamd_address),
+            timeout: Duration::from_secs(config.timeout_seconds.max(1) as u64),
+        }
+    }
+
+    /// Scan content read to its end
+    pub async fn scan(&self, content: &mut (dyn AsyncRead + Send + Unpin)) -> ApiResult<Verdict> {
+        let reply = self
+            .command(b"zINSTREAM\0", Some(content), self.timeout)
+            .await?;
+        parse_scan_reply(&reply)
+    }
+
+    /// clamd's version, which shows it is reachable
+    pub async fn version(&self) -> ApiResult<String> {
+        self.command(b"zVERSION\0", None, Duration::from_secs(PING_TIMEOUT_SECS))
+            .await
+    }
+
+    async fn command(
+        &self,
+        command: &[u8],
+        content: Option<&mut (dyn AsyncRead + Send + Unpin)>,
+        timeout: Duration,
+    ) -> ApiResult<String> {
+        let exchange = async {
+            match &self.address {
+                Address::Tcp(address) => {
+                    exchange(TcpStream::connect(address).await?, command, content).await
+                }
+                Address::Unix(path) => {
+                    exchange(UnixStream::connect(path).await?, command, content).await
+                }
+            }
+        };
+
+        tokio::time::timeout(timeout, exchange)
+            .await
+            .map_err(|_| AppError::ExternalService("clamd timed out".to_string()))?
+            .map_err(|e| AppError::ExternalService(format!("clamd failed: {}", e)))
+    }
+}
+
+/// Send a command, and content in `INSTREAM` chunks if given, then read the
+/// reply clamd sends before closing the connection
+async fn exchange<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    command: &[u8],
+    content: Option<&mut (dyn AsyncRead + Send + Unpin)>,
+) -> std::io::Result<String> {
+    let sent = send(&mut stream, command, content).await;
+
+    // clamd replies and hangs up early when content is over its size limit,
+    // so a failed send may still have a reply waiting
+    let mut reply = Vec::new();
+    let received = (&mut stream)
+        .take(MAX_REPLY_BYTES)
+        .read_to_end(&mut reply)
+        .await;
+    if reply.is_empty() {
+        sent?;
+        received?;
+    }
+
+    Ok(String::from_utf8_lossy(&reply)
+        .trim_end_matches(['\0', '\n'])
+        .to_string())
+}
+
+async fn send<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    command: &[u8],
+    content: Option<&mut (dyn AsyncRead + Send + Unpin)>,
+) -> std::io::Result<()> {
+    stream.write_all(command).await?;
+    if let Some(content) = content {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        loop {
+            let read = content.read(&mut chunk).await?;
+            if read == 0 {
+                break;
+            }
+            stream.write_all(&(read as u32).to_be_bytes()).await?;
+            stream.write_all(&chunk[..read]).await?;
+        }
+        stream.write_all(&[0; 4]).await?;
+    }
+    stream.flush().await
+}
+
+/// `stream: OK`, `stream: <threat> FOUND` or `<error> ERROR`
+fn parse_scan_reply(reply: &str) -> ApiResult<Verdict> {
+    let result = reply.strip_prefix("stream:").unwrap_or(reply).trim();
+    if result == "OK" {
+        return Ok(Verdict::Clean);
+    }
+    if let Some(threat) = result.strip_suffix(" FOUND") {
+        return Ok(Verdict::Infected(threat.trim().to_string()));
+    }
+    let error = result.strip_suffix(" ERROR").unwrap_or(result);
+    Err(AppError::ExternalService(format!("clamd: {}", error)))
+}
+
+/// Scanning settings
+pub async fn config(db: &PgPool) -> ApiResult<FileScanningConfig> {
+    let config: sqlx::types::Json<FileScanningConfig> =
+        sqlx::query_scalar("SELECT file_scanning FROM server_config WHERE id = 'default'")
+            .fetch_one(db)
+            .await?;
+
+    Ok(config.0)
+}
+
+/// The outcome of a scan, as recorded on the file
+pub struct ScanOutcome {
+    /// "clean", "infected" or "failed"
+    pub status: &'static str,
+    /// The threat found, or why the scan failed
+    pub result: Option<String>,
+}
+
+/// Scan a stored object, if scanning is on
+pub async fn scan_stored(
+    state: &AppState,
+    backend: &str,
+    key: &str,
+) -> ApiResult<Option<ScanOutcome>> {
+    let config = config(&state.db).await?;
+    if !config.enable {
+        return Ok(None);
+    }
+
+    let clamd = Clamd::new(&config);
+    let verdict = async {
+        let mut reader = state.storage.backend(backend)?.reader(key).await?;
+        clamd.scan(&mut reader).await
+    }
+    .await;
+
+    Ok(Some(match verdict {
+        Ok(Verdict::Clean) => ScanOutcome {
+            status: "clean",
+            result: None,
+        },
+        Ok(Verdict::Infected(threat)) => ScanOutcome {
+            status: "infected",
+            result: Some(threat),
+        },
+        Err(e) => {
+            warn!(key, error = %e, "Failed to scan file");
+            ScanOutcome {
+                status: "failed",
+                result: Some(e.to_string()),
+            }
+        }
+    }))
+}
+
+/// Audit a file being quarantined
+pub async fn audit_quarantine(db: &PgPool, file: &FileInfo) -> ApiResult<()> {
+    let action = match file.scan_status.as_deref() {
+        Some("infected") => "file.quarantined",
+        _ => "file.scan_failed",
+    };
+    sqlx::query(
+        r#"
+        INSERT INTO audit_logs (actor_user_id, action, target_type, target_id, metadata)
+        VALUES ($1, $2, 'file', $3, $4)
+        "#,
+    )
+    .bind(file.uploader_id)
+    .bind(action)
+    .bind(file.id)
+    .bind(serde_json::json!({
+        "name": file.name,
+        "channel_id": file.channel_id,
+        "sha256": file.sha256,
+        "scan_result": file.scan_result,
+    }))
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Refuse to serve a quarantined file
+pub fn ensure_servable(file: &FileInfo) -> ApiResult<()> {
+    match file.scan_status.as_deref() {
+        Some("infected") => Err(AppError::Forbidden(format!(
+            "{} is quarantined: {} found",
+            file.name,
+            file.scan_result.as_deref().unwrap_or("malware")
+        ))),
+        Some("failed") => Err(AppError::Forbidden(format!(
+            "{} is quarantined: it could not be scanned for malware",
+            file.name
+        ))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_are_tcp_or_unix_sockets() {
+        assert_eq!(
+            Address::Tcp("clamav:3310".to_string()),
+            Address::parse("clamav:3310")
+        );
+        assert_eq!(
+            Address::Tcp("127.0.0.1:3310".to_string()),
+            Address::parse("tcp://127.0.0.1:3310")
+        );
+        assert_eq!(
+            Address::Unix("/run/clamav/clamd.ctl".into()),
+            Address::parse("unix:/run/clamav/clamd.ctl")
+        );
+        assert_eq!(
+            Address::Unix("/run/clamav/clamd.ctl".into()),
+            Address::parse(" /run/clamav/clamd.ctl")
+        );
+    }
+
+    #[test]
+    fn scan_replies_are_parsed() {
+        assert_eq!(Verdict::Clean, parse_scan_reply("stream: OK").unwrap());
+        assert_eq!(
+            Verdict::Infected("Win.Test.EICAR_HDB-1".to_string()),
+            parse_scan_reply("stream: Win.Test.EICAR_HDB-1 FOUND").unwrap()
+        );
+        assert!(matches!(
+            parse_scan_reply("INSTREAM size limit exceeded. ERROR"),
+            Err(AppError::ExternalService(e)) if e == "clamd: INSTREAM size limit exceeded."
+        ));
+    }
+}
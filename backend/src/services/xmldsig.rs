@@ -0,0 +1,404 @@
+//! XML signatures for SAML
+//!
+//! Implements the profile SAML identity providers use: an enveloped
+//! signature over one element, referenced by its `ID`, canonicalized with
+//! exclusive XML canonicalization and signed with RSA-SHA256. Anything else
+//! is rejected rather than half-verified.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use roxmltree::{Node, NodeId, NodeType};
+use rsa::pkcs1v15::{Signature, VerifyingKey};
+use rsa::pkcs8::DecodePublicKey;
+use rsa::signature::Verifier;
+use rsa::RsaPublicKey;
+use sha2::{Digest, Sha256};
+use x509_cert::der::{Decode, Encode};
+
+use crate::error::{ApiResult, AppError};
+
+pub const DSIG_NS: &str = "http://www.w3.org/2000/09/xmldsig#";
+pub const EXC_C14N: &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+pub const ENVELOPED_SIGNATURE: &str = "http://www.w3.org/2000/09/xmldsig#enveloped-signature";
+pub const RSA_SHA256: &str = "http://www.w3.org/2001/04/xmldsig-more#rsa-sha256";
+pub const SHA256: &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+
+fn invalid_signature(reason: impl std::fmt::Display) -> AppError {
+    AppError::Unauthorized(format!("Invalid XML signature: {}", reason))
+}
+
+/// Decode base64 that may be wrapped over several lines
+pub fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    let compact: String = value.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    STANDARD.decode(compact).ok()
+}
+
+/// Read the RSA public key of a base64 DER or PEM certificate
+pub fn certificate_key(certificate: &str) -> ApiResult<RsaPublicKey> {
+    let body: String = certificate
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let der = decode_base64(&body)
+        .ok_or_else(|| AppError::BadRequest("Certificate is not valid base64".to_string()))?;
+    let invalid =
+        |e: &dyn std::fmt::Display| AppError::BadRequest(format!("Invalid certificate: {}", e));
+
+    let certificate = x509_cert::Certificate::from_der(&der).map_err(|e| invalid(&e))?;
+    let spki = certificate
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(|e| invalid(&e))?;
+    RsaPublicKey::from_public_key_der(&spki).map_err(|e| invalid(&e))
+}
+
+/// The qualified name an element or attribute was written with
+fn source_qname(source: &str, start: usize) -> &str {
+    let rest = &source[start..];
+    let end = rest
+        .find(|c: char| c.is_whitespace() || c == '/' || c == '>')
+        .unwrap_or(rest.len());
+    &rest[..end]
+}
+
+fn prefix_of(qname: &str) -> &str {
+    qname
+        .split_once(':')
+        .map(|(prefix, _)| prefix)
+        .unwrap_or("")
+}
+
+fn escape_text(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn escape_attribute(value: &str, out: &mut String) {
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' => out.push_str("&#x9;"),
+            '\n' => out.push_str("&#xA;"),
+            '\r' => out.push_str("&#xD;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Exclusive XML canonicalization (without comments) of `node`'s subtree
+///
+/// `exclude` drops one descendant, which is how the enveloped signature
+/// transform removes the signature itself. `inclusive_prefixes` is the
+/// transform's `InclusiveNamespaces PrefixList`.
+pub fn canonicalize(node: Node, exclude: Option<NodeId>, inclusive_prefixes: &[&str]) -> String {
+    let mut out = String::new();
+    write_canonical(
+        node,
+        exclude,
+        inclusive_prefixes,
+        &BTreeMap::new(),
+        &mut out,
+    );
+    out
+}
+
+fn write_canonical(
+    node: Node,
+    exclude: Option<NodeId>,
+    inclusive_prefixes: &[&str],
+    rendered: &BTreeMap<String, String>,
+    out: &mut String,
+) {
+    match node.node_type() {
+        NodeType::Element => {}
+        NodeType::Text => {
+            escape_text(node.text().unwrap_or_default(), out);
+            return;
+        }
+        NodeType::PI => {
+            if let Some(pi) = node.pi() {
+                out.push_str("<?");
+                out.push_str(pi.target);
+                if let Some(value) = pi.value {
+                    out.push(' ');
+                    out.push_str(value);
+                }
+                out.push_str("?>");
+            }
+            return;
+        }
+        NodeType::Comment | NodeType::Root => return,
+    }
+
+    let source = node.document().input_text();
+    // Skip the '<'
+    let qname = source_qname(source, node.range().start + 1);
+
+    // Namespaces the element and its attributes use, plus the listed ones
+    let mut utilized: BTreeSet<&str> = BTreeSet::new();
+    utilized.insert(prefix_of(qname));
+    for attribute in node.attributes() {
+        let attribute_qname = &source[attribute.range_qname()];
+        if attribute_qname.contains(':') {
+            utilized.insert(prefix_of(attribute_qname));
+        }
+    }
+    for prefix in inclusive_prefixes {
+        let prefix = if *prefix == "#default" { "" } else { prefix };
+        let in_scope = if prefix.is_empty() {
+            node.default_namespace().is_some()
+        } else {
+            node.lookup_namespace_uri(Some(prefix)).is_some()
+        };
+        if in_scope {
+            utilized.insert(prefix);
+        }
+    }
+
+    let mut rendered = rendered.clone();
+    out.push('<');
+    out.push_str(qname);
+    // BTreeSet order puts the default namespace first, as c14n requires
+    for prefix in utilized {
+        if prefix == "xml" {
+            continue;
+        }
+        let uri = if prefix.is_empty() {
+            node.default_namespace().unwrap_or_default()
+        } else {
+            node.lookup_namespace_uri(Some(prefix)).unwrap_or_default()
+        };
+        let current = rendered.get(prefix).map(String::as_str).unwrap_or_default();
+        if current == uri {
+            continue;
+        }
+        if prefix.is_empty() {
+            out.push_str(" xmlns=\"");
+        } else {
+            out.push_str(" xmlns:");
+            out.push_str(prefix);
+            out.push_str("=\"");
+        }
+        escape_attribute(uri, out);
+        out.push('"');
+        rendered.insert(prefix.to_string(), uri.to_string());
+    }
+
+    let mut attributes: Vec<_> = node
+        .attributes()
+        .map(|a| {
+            (
+                a.namespace().unwrap_or_default(),
+                a.name(),
+                &source[a.range_qname()],
+                a.value(),
+            )
+        })
+        .collect();
+    attributes.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+    for (_, _, attribute_qname, value) in attributes {
+        out.push(' ');
+        out.push_str(attribute_qname);
+        out.push_str("=\"");
+        escape_attribute(value, out);
+        out.push('"');
+    }
+    out.push('>');
+
+    for child in node.children() {
+        if Some(child.id()) != exclude {
+            write_canonical(child, exclude, inclusive_prefixes, &rendered, out);
+        }
+    }
+
+    out.push_str("</");
+    out.push_str(qname);
+    out.push('>');
+}
+
+fn dsig_child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|c| c.has_tag_name((DSIG_NS, name)))
+}
+
+fn algorithm<'a>(node: Node<'a, '_>) -> &'a str {
+    node.attribute("Algorithm").unwrap_or_default()
+}
+
+/// The `InclusiveNamespaces PrefixList` of a canonicalization method
+fn prefix_list<'a>(method: Node<'a, '_>) -> Vec<&'a str> {
+    method
+        .children()
+        .find(|c| c.has_tag_name((EXC_C14N, "InclusiveNamespaces")))
+        .and_then(|n| n.attribute("PrefixList"))
+        .map(|list| list.split_whitespace().collect())
+        .unwrap_or_default()
+}
+
+/// Whether `element` carries its own enveloped signature
+pub fn is_signed(element: Node) -> bool {
+    dsig_child(element, "Signature").is_some()
+}
+
+/// Verify the enveloped signature of `element` against any of `keys`
+///
+/// The signature must be a direct child of `element` and its only reference
+/// must point at `element` by an `ID` nothing else in the document uses, so
+/// a signed element cannot be swapped for an unsigned one.
+pub fn verify_enveloped(element: Node, keys: &[RsaPublicKey]) -> ApiResult<()> {
+    let id = element
+        .attribute("ID")
+        .ok_or_else(|| invalid_signature("signed element has no ID"))?;
+    let same_id = element
+        .document()
+        .descendants()
+        .filter(|n| n.attribute("ID") == Some(id))
+        .count();
+    if same_id != 1 {
+        return Err(invalid_signature("duplicate ID"));
+    }
+
+    let signature =
+        dsig_child(element, "Signature").ok_or_else(|| invalid_signature("not signed"))?;
+    let signed_info =
+        dsig_child(signature, "SignedInfo").ok_or_else(|| invalid_signature("no SignedInfo"))?;
+    let c14n_method = dsig_child(signed_info, "CanonicalizationMethod")
+        .filter(|m| algorithm(*m) == EXC_C14N)
+        .ok_or_else(|| invalid_signature("unsupported canonicalization"))?;
+    if dsig_child(signed_info, "SignatureMethod").map(algorithm) != Some(RSA_SHA256) {
+        return Err(invalid_signature("unsupported signature algorithm"));
+    }
+
+    let mut references = signed_info
+        .children()
+        .filter(|c| c.has_tag_name((DSIG_NS, "Reference")));
+    let reference = match (references.next(), references.next()) {
+        (Some(reference), None) => reference,
+        _ => return Err(invalid_signature("expected exactly one reference")),
+    };
+    if reference.attribute("URI") != Some(format!("#{}", id).as_str()) {
+        return Err(invalid_signature(
+            "reference does not point at the signed element",
+        ));
+    }
+
+    let mut inclusive_prefixes = Vec::new();
+    if let Some(transforms) = dsig_child(reference, "Transforms") {
+        for transform in transforms.children().filter(|c| c.is_element()) {
+            match algorithm(transform) {
+                ENVELOPED_SIGNATURE => {}
+                EXC_C14N => inclusive_prefixes = prefix_list(transform),
+                other => {
+                    return Err(invalid_signature(format!(
+                        "unsupported transform {}",
+                        other
+                    )))
+                }
+            }
+        }
+    }
+    if dsig_child(reference, "DigestMethod").map(algorithm) != Some(SHA256) {
+        return Err(invalid_signature("unsupported digest algorithm"));
+    }
+    let expected_digest = dsig_child(reference, "DigestValue")
+        .and_then(|n| n.text())
+        .and_then(decode_base64)
+        .ok_or_else(|| invalid_signature("missing digest"))?;
+
+    let canonical = canonicalize(element, Some(signature.id()), &inclusive_prefixes);
+    if Sha256::digest(canonical.as_bytes()).as_slice() != expected_digest.as_slice() {
+        return Err(invalid_signature("digest mismatch"));
+    }
+
+    let signature_value = dsig_child(signature, "SignatureValue")
+        .and_then(|n| n.text())
+        .and_then(decode_base64)
+        .ok_or_else(|| invalid_signature("missing signature value"))?;
+    let signature_value =
+        Signature::try_from(signature_value.as_slice()).map_err(invalid_signature)?;
+    let signed_info = canonicalize(signed_info, None, &prefix_list(c14n_method));
+
+    let verified = keys.iter().any(|key| {
+        VerifyingKey::<Sha256>::new(key.clone())
+            .verify(signed_info.as_bytes(), &signature_value)
+            .is_ok()
+    });
+    if !verified {
+        return Err(invalid_signature(
+            "signature does not match the provider's certificate",
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c14n(xml: &str, path: &[&str]) -> String {
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let mut node = doc.root_element();
+        for name in path {
+            node = node
+                .children()
+                .find(|c| c.tag_name().name() == *name)
+                .unwrap();
+        }
+        canonicalize(node, None, &[])
+    }
+
+    #[test]
+    fn canonical_form_normalizes_syntax() {
+        let xml = "<doc b='2' a=\"1\" xmlns='urn:d'><e/>\r\n<f x=\"&#x9;&quot;\">&lt;&#62;</f><!-- c --></doc>";
+
+        assert_eq!(
+            "<doc xmlns=\"urn:d\" a=\"1\" b=\"2\"><e></e>\n<f x=\"&#x9;&quot;\">&lt;&gt;</f></doc>",
+            c14n(xml, &[])
+        );
+    }
+
+    #[test]
+    fn subtrees_only_declare_namespaces_they_use() {
+        let xml = r#"<a:root xmlns:a="urn:a" xmlns:b="urn:b" xmlns:unused="urn:u"><a:child b:attr="1" plain="2"><a:leaf/></a:child></a:root>"#;
+
+        assert_eq!(
+            r#"<a:child xmlns:a="urn:a" xmlns:b="urn:b" plain="2" b:attr="1"><a:leaf></a:leaf></a:child>"#,
+            c14n(xml, &["child"])
+        );
+    }
+
+    #[test]
+    fn inclusive_prefixes_are_declared_when_in_scope() {
+        let xml = r#"<r xmlns:xs="urn:xs"><v type="xs:string">x</v></r>"#;
+        let doc = roxmltree::Document::parse(xml).unwrap();
+        let v = doc.root_element().first_element_child().unwrap();
+
+        assert_eq!(
+            r#"<v xmlns:xs="urn:xs" type="xs:string">x</v>"#,
+            canonicalize(v, None, &["xs", "missing"])
+        );
+    }
+
+    #[test]
+    fn default_namespace_is_undeclared_when_left() {
+        let xml = r#"<r xmlns="urn:r"><inner xmlns=""><x/></inner></r>"#;
+
+        assert_eq!(
+            r#"<r xmlns="urn:r"><inner xmlns=""><x></x></inner></r>"#,
+            c14n(xml, &[])
+        );
+        assert_eq!("<inner><x></x></inner>", c14n(xml, &["inner"]));
+    }
+}
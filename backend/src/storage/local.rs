@@ -0,0 +1,305 @@
+//! Local filesystem storage
+//!
+//! Objects are files below a root directory, named by their key. Clients
+//! reach them through signed, expiring links served by rustchat itself
+//! under `/api/v1/storage/`.
+
+use std::path::{Component, Path, PathBuf};
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::error;
+use uuid::Uuid;
+
+use super::{FileBackend, ObjectReader};
+use crate::error::AppError;
+
+/// Name of the local backend in `files.backend`
+pub const LOCAL_BACKEND: &str = "local";
+
+/// Where the signed links point, below the server's base URL
+pub const LOCAL_STORAGE_PATH: &str = "/api/v1/storage/";
+
+/// Directory below the root holding the parts of unfinished uploads
+const UPLOADS_DIR: &str = ".uploads";
+
+/// Signature of a link allowing `method` on `key` until `expires`
+///
+/// `expires` is a Unix timestamp. `secret` is the server's encryption key.
+pub fn sign_url(secret: &str, method: &str, key: &str, expires: i64) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}\n{}\n{}", method, key, expires).as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Check a link made by [`sign_url`]
+pub fn verify_url(
+    secret: &str,
+    method: &str,
+    key: &str,
+    expires: i64,
+    signature: &str,
+) -> Result<(), AppError> {
+    let Ok(signature) = hex::decode(signature) else {
+        return Err(invalid_link());
+    };
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(format!("{}\n{}\n{}", method, key, expires).as_bytes());
+    mac.verify_slice(&signature).map_err(|_| invalid_link())?;
+
+    if expires < chrono::Utc::now().timestamp() {
+        return Err(AppError::Forbidden("Link expired".to_string()));
+    }
+
+    Ok(())
+}
+
+fn invalid_link() -> AppError {
+    AppError::Forbidden("Invalid link signature".to_string())
+}
+
+/// Storage in a directory on the server
+#[derive(Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+    base_url: String,
+    secret: String,
+}
+
+impl LocalStorage {
+    /// Store files below `root`, linking to them from `base_url`
+    ///
+    /// Links are relative to the server when `base_url` is empty. `secret`
+    /// signs them and must be the server's encryption key.
+    pub fn new(root: impl AsRef<Path>, base_url: &str, secret: &str) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            secret: secret.to_string(),
+        }
+    }
+
+    /// Path of the file holding `key`, refusing keys that leave the root
+    fn path(&self, key: &str) -> Result<PathBuf, AppError> {
+        let relative = Path::new(key);
+        let valid = relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+        if key.is_empty() || !valid {
+            return Err(AppError::BadRequest("Invalid storage key".to_string()));
+        }
+
+        Ok(self.root.join(relative))
+    }
+
+    /// Directory holding the parts of an unfinished upload
+    fn upload_dir(&self, upload_id: &str) -> Result<PathBuf, AppError> {
+        self.path(&format!("{}/{}", UPLOADS_DIR, upload_id))
+    }
+
+    fn signed_url(&self, method: &str, key: &str, expires_in_secs: u64) -> String {
+        let expires = chrono::Utc::now().timestamp() + expires_in_secs as i64;
+        let path = key
+            .split('/')
+            .map(|segment| urlencoding::encode(segment).into_owned())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        format!(
+            "{}{}{}?expires={}&signature={}",
+            self.base_url,
+            LOCAL_STORAGE_PATH,
+            path,
+            expires,
+            sign_url(&self.secret, method, key, expires)
+        )
+    }
+}
+
+fn io_error(action: &str, key: &str, e: std::io::Error) -> AppError {
+    if e.kind() == std::io::ErrorKind::NotFound {
+        return AppError::NotFound("File not found".to_string());
+    }
+    error!(error = ?e, key = %key, "Local storage {} failed", action);
+    AppError::Internal(format!("Local storage {} error: {}", action, e))
+}
+
+#[async_trait]
+impl FileBackend for LocalStorage {
+    fn name(&self) -> &'static str {
+        LOCAL_BACKEND
+    }
+
+    async fn upload(&self, key: &str, data: Vec<u8>, _content_type: &str) -> Result<(), AppError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| io_error("upload", key, e))?;
+        }
+
+        // Readers never see a partly written file
+        let partial = path.with_file_name(format!(".{}.partial", Uuid::new_v4()));
+        tokio::fs::write(&partial, data)
+            .await
+            .map_err(|e| io_error("upload", key, e))?;
+        if let Err(e) = tokio::fs::rename(&partial, &path).await {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(io_error("upload", key, e));
+        }
+
+        Ok(())
+    }
+
+    async fn download(&self, key: &str) -> Result<Vec<u8>, AppError> {
+        tokio::fs::read(self.path(key)?)
+            .await
+            .map_err(|e| io_error("download", key, e))
+    }
+
+    async fn reader(&self, key: &str) -> Result<ObjectReader, AppError> {
+        let file = tokio::fs::File::open(self.path(key)?)
+            .await
+            .map_err(|e| io_error("download", key, e))?;
+
+        Ok(Box::new(file))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(io_error("delete", key, e)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn presigned_download_url(
+        &self,
+        key: &str,
+        expires_in_secs: u64,
+    ) -> Result<String, AppError> {
+        self.path(key)?;
+        Ok(self.signed_url("GET", key, expires_in_secs))
+    }
+
+    async fn presigned_upload_url(
+        &self,
+        key: &str,
+        _content_type: &str,
+        expires_in_secs: u64,
+    ) -> Result<String, AppError> {
+        self.path(key)?;
+        Ok(self.signed_url("PUT", key, expires_in_secs))
+    }
+
+    fn min_part_size(&self) -> usize {
+        1
+    }
+
+    async fn create_multipart_upload(
+        &self,
+        key: &str,
+        _content_type: &str,
+    ) -> Result<String, AppError> {
+        let upload_id = Uuid::new_v4().simple().to_string();
+        tokio::fs::create_dir_all(self.upload_dir(&upload_id)?)
+            .await
+            .map_err(|e| io_error("upload", key, e))?;
+
+        Ok(upload_id)
+    }
+
+    /// Each part is its own file, so writing a part again replaces it
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        data: Vec<u8>,
+    ) -> Result<String, AppError> {
+        let path = self.upload_dir(upload_id)?.join(part_number.to_string());
+        tokio::fs::write(&path, data)
+            .await
+            .map_err(|e| io_error("upload", key, e))?;
+
+        Ok(part_number.to_string())
+    }
+
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_tags: &[String],
+    ) -> Result<(), AppError> {
+        let path = self.path(key)?;
+        let dir = self.upload_dir(upload_id)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| io_error("upload", key, e))?;
+        }
+
+        let partial = path.with_file_name(format!(".{}.partial", Uuid::new_v4()));
+        let result = async {
+            let mut out = tokio::fs::File::create(&partial).await?;
+            for tag in part_tags {
+                let mut part = tokio::fs::File::open(dir.join(tag)).await?;
+                tokio::io::copy(&mut part, &mut out).await?;
+            }
+            out.sync_all().await?;
+            tokio::fs::rename(&partial, &path).await
+        }
+        .await;
+        if let Err(e) = result {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(io_error("upload", key, e));
+        }
+
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        Ok(())
+    }
+
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<(), AppError> {
+        match tokio::fs::remove_dir_all(self.upload_dir(upload_id)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(io_error("abort", key, e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_links_cover_method_key_and_expiry() {
+        let expires = chrono::Utc::now().timestamp() + 60;
+        let signature = sign_url("secret", "GET", "files/a.png", expires);
+
+        assert!(verify_url("secret", "GET", "files/a.png", expires, &signature).is_ok());
+        assert!(verify_url("secret", "PUT", "files/a.png", expires, &signature).is_err());
+        assert!(verify_url("secret", "GET", "files/b.png", expires, &signature).is_err());
+        assert!(verify_url("secret", "GET", "files/a.png", expires + 1, &signature).is_err());
+        assert!(verify_url("other", "GET", "files/a.png", expires, &signature).is_err());
+
+        let expired = expires - 120;
+        let signature = sign_url("secret", "GET", "files/a.png", expired);
+        assert!(verify_url("secret", "GET", "files/a.png", expired, &signature).is_err());
+    }
+
+    #[test]
+    fn keys_stay_below_the_root() {
+        let storage = LocalStorage::new("/srv/files", "", "secret");
+
+        assert_eq!(
+            PathBuf::from("/srv/files/files/u/f.png"),
+            storage.path("files/u/f.png").unwrap()
+        );
+        assert!(storage.path("../etc/passwd").is_err());
+        assert!(storage.path("files/../../etc/passwd").is_err());
+        assert!(storage.path("/etc/passwd").is_err());
+        assert!(storage.path("").is_err());
+    }
+}
@@ -1,7 +1,160 @@
 //! Storage module for rustchat
 //!
-//! Provides S3-compatible storage backend for files.
+//! File contents live in a [`FileBackend`]: an S3-compatible bucket or a
+//! directory on the server. New files go to the backend chosen in [`Config`],
+//! and each file's `backend` column names the one holding it, so files stay
+//! readable after the setting changes and until they are migrated.
+//!
+//! Large files are written in parts through an [`ObjectWriter`], so they are
+//! never held in memory whole.
 
+mod local;
 mod s3;
+mod writer;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::io::AsyncRead;
 
+pub use local::*;
 pub use s3::*;
+pub use writer::*;
+
+use crate::config::Config;
+use crate::error::AppError;
+
+/// Contents of a stored object, read as they arrive
+pub type ObjectReader = Box<dyn AsyncRead + Send + Unpin>;
+
+/// A place file contents are stored
+#[async_trait]
+pub trait FileBackend: Send + Sync {
+    /// Name recorded in `files.backend`
+    fn name(&self) -> &'static str;
+
+    /// Store `data` under `key`, replacing any existing object
+    async fn upload(&self, key: &str, data: Vec<u8>, content_type: &str) -> Result<(), AppError>;
+
+    /// Read the object stored under `key`
+    async fn download(&self, key: &str) -> Result<Vec<u8>, AppError>;
+
+    /// Stream the object stored under `key`, for files too large to read whole
+    async fn reader(&self, key: &str) -> Result<ObjectReader, AppError>;
+
+    /// Remove the object stored under `key`, if there is one
+    async fn delete(&self, key: &str) -> Result<(), AppError>;
+
+    /// URL clients can download the object from until it expires
+    async fn presigned_download_url(
+        &self,
+        key: &str,
+        expires_in_secs: u64,
+    ) -> Result<String, AppError>;
+
+    /// URL clients can upload the object to until it expires
+    async fn presigned_upload_url(
+        &self,
+        key: &str,
+        content_type: &str,
+        expires_in_secs: u64,
+    ) -> Result<String, AppError>;
+
+    /// Smallest part a multipart upload accepts, except for the last one
+    fn min_part_size(&self) -> usize;
+
+    /// Start writing an object in parts, returning the upload's id
+    async fn create_multipart_upload(
+        &self,
+        key: &str,
+        content_type: &str,
+    ) -> Result<String, AppError>;
+
+    /// Write the next part of an upload, returning its tag
+    ///
+    /// Parts are numbered from 1 and written in order.
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        data: Vec<u8>,
+    ) -> Result<String, AppError>;
+
+    /// Assemble the written parts into the object under `key`
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_tags: &[String],
+    ) -> Result<(), AppError>;
+
+    /// Discard an unfinished upload and its parts
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<(), AppError>;
+}
+
+/// The configured file backends
+#[derive(Clone)]
+pub struct Storage {
+    current: Arc<dyn FileBackend>,
+    others: Vec<Arc<dyn FileBackend>>,
+}
+
+impl Storage {
+    /// Storage keeping new files in `current`
+    pub fn new(current: impl FileBackend + 'static) -> Self {
+        Self {
+            current: Arc::new(current),
+            others: Vec::new(),
+        }
+    }
+
+    /// Add a backend that existing files may be stored in
+    pub fn with_backend(mut self, backend: impl FileBackend + 'static) -> Self {
+        self.others.push(Arc::new(backend));
+        self
+    }
+
+    /// Set up every backend, keeping new files in the configured one
+    ///
+    /// Both backends are always available so files can be migrated between
+    /// them. Neither connects to anything until it is used.
+    pub fn from_config(config: &Config) -> Result<Self, AppError> {
+        let s3 = S3Client::new(
+            config.s3_endpoint.clone(),
+            config.s3_public_endpoint.clone(),
+            config.s3_bucket.clone(),
+            config.s3_access_key.clone(),
+            config.s3_secret_key.clone(),
+            config.s3_region.clone(),
+        );
+        let local = LocalStorage::new(
+            &config.local_storage_path,
+            config.local_storage_url.as_deref().unwrap_or_default(),
+            &config.encryption_key,
+        );
+
+        match config.storage_backend.as_str() {
+            S3_BACKEND => Ok(Self::new(s3).with_backend(local)),
+            LOCAL_BACKEND => Ok(Self::new(local).with_backend(s3)),
+            other => Err(AppError::Config(format!(
+                "Unknown storage backend: {}",
+                other
+            ))),
+        }
+    }
+
+    /// The backend new files are stored in
+    pub fn current(&self) -> &dyn FileBackend {
+        self.current.as_ref()
+    }
+
+    /// The backend with the given name
+    pub fn backend(&self, name: &str) -> Result<&dyn FileBackend, AppError> {
+        std::iter::once(&self.current)
+            .chain(&self.others)
+            .find(|backend| backend.name() == name)
+            .map(|backend| backend.as_ref())
+            .ok_or_else(|| AppError::Internal(format!("Storage backend {} is not set up", name)))
+    }
+}
@@ -1,24 +1,32 @@
 //! S3-compatible storage client
 
+use async_trait::async_trait;
 use aws_config::Region;
 use aws_sdk_s3::{
     config::{Credentials, SharedCredentialsProvider},
     presigning::PresigningConfig,
     primitives::ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart},
     Client, Config,
 };
 use std::time::Duration;
 use tracing::error;
 
+use super::{FileBackend, ObjectReader};
 use crate::error::AppError;
 
+/// Name of the S3 backend in `files.backend`
+pub const S3_BACKEND: &str = "s3";
+
+/// S3 rejects smaller parts, except for the last one
+const S3_MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
 /// S3 storage client
 #[derive(Clone)]
 pub struct S3Client {
     client: Client,
     bucket: String,
     endpoint: Option<String>,
-    public_endpoint: Option<String>,
     public_client: Option<Client>,
 }
 
@@ -80,18 +88,28 @@ impl S3Client {
             client,
             bucket,
             endpoint,
-            public_endpoint,
             public_client,
         }
     }
 
+    /// Get the public URL for a file (if bucket is public)
+    pub fn public_url(&self, key: &str) -> String {
+        if let Some(ref endpoint) = self.endpoint {
+            format!("{}/{}/{}", endpoint, self.bucket, key)
+        } else {
+            format!("https://{}.s3.amazonaws.com/{}", self.bucket, key)
+        }
+    }
+}
+
+#[async_trait]
+impl FileBackend for S3Client {
+    fn name(&self) -> &'static str {
+        S3_BACKEND
+    }
+
     /// Upload a file to S3
-    pub async fn upload(
-        &self,
-        key: &str,
-        data: Vec<u8>,
-        content_type: &str,
-    ) -> Result<(), AppError> {
+    async fn upload(&self, key: &str, data: Vec<u8>, content_type: &str) -> Result<(), AppError> {
         let body = ByteStream::from(data);
 
         self.client
@@ -111,7 +129,7 @@ impl S3Client {
     }
 
     /// Download a file from S3
-    pub async fn download(&self, key: &str) -> Result<Vec<u8>, AppError> {
+    async fn download(&self, key: &str) -> Result<Vec<u8>, AppError> {
         let response = self
             .client
             .get_object()
@@ -135,8 +153,25 @@ impl S3Client {
         Ok(data)
     }
 
+    /// Stream a file from S3
+    async fn reader(&self, key: &str) -> Result<ObjectReader, AppError> {
+        let response = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(error = ?e, bucket = %self.bucket, key = %key, "S3 download failed");
+                AppError::Internal(format!("S3 download error: {}", e))
+            })?;
+
+        Ok(Box::new(response.body.into_async_read()))
+    }
+
     /// Delete a file from S3
-    pub async fn delete(&self, key: &str) -> Result<(), AppError> {
+    async fn delete(&self, key: &str) -> Result<(), AppError> {
         self.client
             .delete_object()
             .bucket(&self.bucket)
@@ -152,7 +187,7 @@ impl S3Client {
     }
 
     /// Generate a presigned download URL
-    pub async fn presigned_download_url(
+    async fn presigned_download_url(
         &self,
         key: &str,
         expires_in_secs: u64,
@@ -178,7 +213,7 @@ impl S3Client {
     }
 
     /// Generate a presigned upload URL
-    pub async fn presigned_upload_url(
+    async fn presigned_upload_url(
         &self,
         key: &str,
         content_type: &str,
@@ -205,12 +240,114 @@ impl S3Client {
         Ok(presigned.uri().to_string())
     }
 
-    /// Get the public URL for a file (if bucket is public)
-    pub fn public_url(&self, key: &str) -> String {
-        if let Some(ref endpoint) = self.endpoint {
-            format!("{}/{}/{}", endpoint, self.bucket, key)
-        } else {
-            format!("https://{}.s3.amazonaws.com/{}", self.bucket, key)
-        }
+    fn min_part_size(&self) -> usize {
+        S3_MIN_PART_SIZE
+    }
+
+    /// Start an S3 multipart upload
+    async fn create_multipart_upload(
+        &self,
+        key: &str,
+        content_type: &str,
+    ) -> Result<String, AppError> {
+        let response = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(error = ?e, bucket = %self.bucket, key = %key, "S3 multipart upload start failed");
+                AppError::Internal(format!("S3 upload error: {}", e))
+            })?;
+
+        response
+            .upload_id
+            .ok_or_else(|| AppError::Internal("S3 returned no upload id".to_string()))
+    }
+
+    /// Upload one part of a multipart upload
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        data: Vec<u8>,
+    ) -> Result<String, AppError> {
+        let response = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(data))
+            .send()
+            .await
+            .map_err(|e| {
+                error!(error = ?e, bucket = %self.bucket, key = %key, part_number, "S3 part upload failed");
+                AppError::Internal(format!("S3 upload error: {}", e))
+            })?;
+
+        response
+            .e_tag
+            .ok_or_else(|| AppError::Internal("S3 returned no part ETag".to_string()))
+    }
+
+    /// Finish a multipart upload
+    async fn complete_multipart_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_tags: &[String],
+    ) -> Result<(), AppError> {
+        let parts = part_tags
+            .iter()
+            .zip(1..)
+            .map(|(tag, number)| {
+                CompletedPart::builder()
+                    .e_tag(tag)
+                    .part_number(number)
+                    .build()
+            })
+            .collect();
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| {
+                error!(error = ?e, bucket = %self.bucket, key = %key, "S3 multipart upload failed");
+                AppError::Internal(format!("S3 upload error: {}", e))
+            })?;
+
+        Ok(())
+    }
+
+    /// Abort a multipart upload, freeing its parts
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) -> Result<(), AppError> {
+        self.client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(error = ?e, bucket = %self.bucket, key = %key, "S3 multipart abort failed");
+                AppError::Internal(format!("S3 abort error: {}", e))
+            })?;
+
+        Ok(())
     }
 }
@@ -0,0 +1,317 @@
+//! Streaming object writes
+//!
+//! An [`ObjectWriter`] takes a file a chunk at a time and writes it to a
+//! backend in parts of at most [`PART_SIZE`], hashing it along the way. Its
+//! [`UploadProgress`] can be saved between requests, so an upload session can
+//! carry on where the previous request stopped.
+
+use sha2::compress256;
+use sha2::digest::generic_array::GenericArray;
+use tokio::io::AsyncReadExt;
+use tracing::warn;
+
+use super::{FileBackend, ObjectReader};
+use crate::error::AppError;
+
+/// Bytes held in memory before they are written as a part
+pub const PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// SHA-256 initial hash values
+const SHA256_INIT: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// SHA-256 whose state can be saved and resumed
+///
+/// `sha2::Sha256` cannot be serialized, so this keeps the raw state and
+/// feeds whole blocks to the same compression function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadHash {
+    state: [u32; 8],
+    len: u64,
+    block: Vec<u8>,
+}
+
+impl Default for UploadHash {
+    fn default() -> Self {
+        Self {
+            state: SHA256_INIT,
+            len: 0,
+            block: Vec::with_capacity(64),
+        }
+    }
+}
+
+impl UploadHash {
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+
+        if !self.block.is_empty() {
+            let take = (64 - self.block.len()).min(data.len());
+            self.block.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.block.len() < 64 {
+                return;
+            }
+            compress256(&mut self.state, &[*GenericArray::from_slice(&self.block)]);
+            self.block.clear();
+        }
+
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            compress256(&mut self.state, &[*GenericArray::from_slice(block)]);
+        }
+        self.block.extend_from_slice(blocks.remainder());
+    }
+
+    /// Hex digest of everything hashed so far
+    pub fn finalize(mut self) -> String {
+        let bits = self.len.wrapping_mul(8);
+        self.block.push(0x80);
+        if self.block.len() > 56 {
+            self.block.resize(64, 0);
+            compress256(&mut self.state, &[*GenericArray::from_slice(&self.block)]);
+            self.block.clear();
+        }
+        self.block.resize(56, 0);
+        self.block.extend_from_slice(&bits.to_be_bytes());
+        compress256(&mut self.state, &[*GenericArray::from_slice(&self.block)]);
+
+        self.state
+            .iter()
+            .map(|word| format!("{:08x}", word))
+            .collect()
+    }
+
+    /// The state as bytes, for [`UploadHash::from_bytes`]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(40 + self.block.len());
+        for word in self.state {
+            bytes.extend_from_slice(&word.to_be_bytes());
+        }
+        bytes.extend_from_slice(&self.len.to_be_bytes());
+        bytes.extend_from_slice(&self.block);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 40 || bytes.len() >= 40 + 64 {
+            return None;
+        }
+
+        let mut state = [0u32; 8];
+        for (word, chunk) in state.iter_mut().zip(bytes[..32].chunks_exact(4)) {
+            *word = u32::from_be_bytes(chunk.try_into().ok()?);
+        }
+        let len = u64::from_be_bytes(bytes[32..40].try_into().ok()?);
+        let block = bytes[40..].to_vec();
+        if len % 64 != block.len() as u64 {
+            return None;
+        }
+
+        Some(Self { state, len, block })
+    }
+}
+
+/// Where an unfinished upload stands
+#[derive(Debug, Clone, Default)]
+pub struct UploadProgress {
+    /// The backend's multipart upload, once the first part is written
+    pub upload_id: Option<String>,
+    /// Tags of the parts written so far, in order
+    pub part_tags: Vec<String>,
+    /// Bytes received but not yet written as a part
+    pub pending: Vec<u8>,
+    pub hash: UploadHash,
+    /// Bytes received, including `pending`
+    pub size: u64,
+}
+
+/// Writes one object a chunk at a time
+pub struct ObjectWriter<'a> {
+    backend: &'a dyn FileBackend,
+    key: String,
+    content_type: String,
+    limit: u64,
+    progress: UploadProgress,
+}
+
+impl<'a> ObjectWriter<'a> {
+    /// Write a new object of at most `limit` bytes
+    pub fn new(backend: &'a dyn FileBackend, key: &str, content_type: &str, limit: u64) -> Self {
+        Self::resume(backend, key, content_type, limit, UploadProgress::default())
+    }
+
+    /// Carry on with an upload from saved progress
+    pub fn resume(
+        backend: &'a dyn FileBackend,
+        key: &str,
+        content_type: &str,
+        limit: u64,
+        progress: UploadProgress,
+    ) -> Self {
+        Self {
+            backend,
+            key: key.to_string(),
+            content_type: content_type.to_string(),
+            limit,
+            progress,
+        }
+    }
+
+    /// Bytes received so far
+    pub fn size(&self) -> u64 {
+        self.progress.size
+    }
+
+    /// Add the next chunk, writing a part whenever enough is buffered
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), AppError> {
+        if self.progress.size + data.len() as u64 > self.limit {
+            return Err(AppError::PayloadTooLarge(format!(
+                "File is larger than {} bytes",
+                self.limit
+            )));
+        }
+
+        self.progress.hash.update(data);
+        self.progress.pending.extend_from_slice(data);
+        self.progress.size += data.len() as u64;
+
+        while self.progress.pending.len() >= PART_SIZE {
+            let rest = self.progress.pending.split_off(PART_SIZE);
+            let part = std::mem::replace(&mut self.progress.pending, rest);
+            self.write_part(part).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Write what the backend accepts as a part and return the progress
+    ///
+    /// Whatever is still pending must be saved with the progress.
+    pub async fn checkpoint(&mut self) -> Result<&UploadProgress, AppError> {
+        let pending = self.progress.pending.len();
+        if pending > 0 && pending >= self.backend.min_part_size() {
+            let part = std::mem::take(&mut self.progress.pending);
+            self.write_part(part).await?;
+        }
+
+        Ok(&self.progress)
+    }
+
+    /// Progress as it stands, without writing anything
+    pub fn progress(&self) -> &UploadProgress {
+        &self.progress
+    }
+
+    /// Store the object, returning its size and SHA-256
+    pub async fn finish(mut self) -> Result<(u64, String), AppError> {
+        let pending = std::mem::take(&mut self.progress.pending);
+        match self.progress.upload_id.clone() {
+            // Small files never start a multipart upload
+            None => {
+                self.backend
+                    .upload(&self.key, pending, &self.content_type)
+                    .await?
+            }
+            Some(upload_id) => {
+                if !pending.is_empty() {
+                    self.write_part(pending).await?;
+                }
+                self.backend
+                    .complete_multipart_upload(&self.key, &upload_id, &self.progress.part_tags)
+                    .await?;
+            }
+        }
+
+        Ok((self.progress.size, self.progress.hash.finalize()))
+    }
+
+    /// Write everything `reader` yields and store the object
+    ///
+    /// The upload is discarded if reading or writing fails.
+    pub async fn copy_from(mut self, reader: &mut ObjectReader) -> Result<(u64, String), AppError> {
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let result = match reader.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(read) => self.write(&buf[..read]).await,
+                Err(e) => Err(AppError::Internal(format!(
+                    "Failed to read {}: {}",
+                    self.key, e
+                ))),
+            };
+            if let Err(e) = result {
+                self.abort().await;
+                return Err(e);
+            }
+        }
+
+        self.finish().await
+    }
+
+    /// Discard the upload, logging rather than returning failures
+    pub async fn abort(self) {
+        if let Some(upload_id) = &self.progress.upload_id {
+            if let Err(e) = self
+                .backend
+                .abort_multipart_upload(&self.key, upload_id)
+                .await
+            {
+                warn!(key = %self.key, error = %e, "Failed to abort multipart upload");
+            }
+        }
+    }
+
+    async fn write_part(&mut self, data: Vec<u8>) -> Result<(), AppError> {
+        let upload_id = match &self.progress.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                let upload_id = self
+                    .backend
+                    .create_multipart_upload(&self.key, &self.content_type)
+                    .await?;
+                self.progress.upload_id = Some(upload_id.clone());
+                upload_id
+            }
+        };
+
+        let part_number = self.progress.part_tags.len() as i32 + 1;
+        let tag = self
+            .backend
+            .upload_part(&self.key, &upload_id, part_number, data)
+            .await?;
+        self.progress.part_tags.push(tag);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    #[test]
+    fn upload_hash_matches_sha256_across_resumes() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 251) as u8).collect();
+
+        for split in [0, 1, 55, 56, 63, 64, 65, 128, 500, 1000] {
+            let mut hash = UploadHash::default();
+            hash.update(&data[..split]);
+            let mut hash = UploadHash::from_bytes(&hash.to_bytes()).unwrap();
+            hash.update(&data[split..]);
+
+            assert_eq!(hex::encode(Sha256::digest(&data)), hash.finalize());
+        }
+
+        for len in [0, 55, 56, 64] {
+            let mut hash = UploadHash::default();
+            hash.update(&data[..len]);
+            assert_eq!(hex::encode(Sha256::digest(&data[..len])), hash.finalize());
+        }
+
+        assert!(UploadHash::from_bytes(&[0; 39]).is_none());
+        assert!(UploadHash::from_bytes(&[0; 41]).is_none());
+    }
+}
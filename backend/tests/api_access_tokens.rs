@@ -0,0 +1,172 @@
+use crate::common::{spawn_app, TestApp};
+use serde_json::json;
+
+mod common;
+
+async fn register(app: &TestApp, username: &str) -> String {
+    let res = app
+        .api_client
+        .post(format!("{}/api/v1/auth/register", &app.address))
+        .json(&json!({
+            "username": username,
+            "email": format!("{}@example.com", username),
+            "password": "Password123!",
+            "display_name": username
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+    let body: serde_json::Value = res.json().await.unwrap();
+    body["token"].as_str().unwrap().to_string()
+}
+
+async fn set_tokens_enabled(app: &TestApp, enabled: bool) {
+    sqlx::query(
+        r#"
+        UPDATE server_config
+        SET authentication = authentication || jsonb_build_object('enable_personal_access_tokens', $1::boolean)
+        WHERE id = 'default'
+        "#,
+    )
+    .bind(enabled)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn get(app: &TestApp, path: &str, token: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn post(
+    app: &TestApp,
+    path: &str,
+    token: &str,
+    body: serde_json::Value,
+) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}{}", &app.address, path))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn personal_access_tokens_authenticate_until_disabled() {
+    let app = spawn_app().await;
+    let alice = register(&app, "alice").await;
+    let bob = register(&app, "bob").await;
+
+    // Off by default
+    let res = post(&app, "/api/v4/users/me/tokens", &alice, json!({ "description": "ci" })).await;
+    assert_eq!(403, res.status().as_u16());
+
+    set_tokens_enabled(&app, true).await;
+    let res = post(&app, "/api/v4/users/me/tokens", &alice, json!({ "description": "ci" })).await;
+    assert_eq!(200, res.status().as_u16());
+    let created: serde_json::Value = res.json().await.unwrap();
+    let token = created["token"].as_str().unwrap().to_string();
+    let token_id = created["id"].as_str().unwrap().to_string();
+    let user_id = created["user_id"].as_str().unwrap().to_string();
+    assert_eq!("ci", created["description"]);
+
+    // The token works on both APIs
+    let res = get(&app, "/api/v4/users/me", &token).await;
+    assert_eq!(200, res.status().as_u16());
+    let me: serde_json::Value = res.json().await.unwrap();
+    assert_eq!("alice", me["username"]);
+    assert_eq!(200, get(&app, "/api/v1/auth/me", &token).await.status().as_u16());
+    let used: bool = sqlx::query_scalar(
+        "SELECT last_used_at IS NOT NULL FROM user_access_tokens WHERE user_id = (SELECT id FROM users WHERE username = 'alice')",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(used);
+
+    // Listing never returns the token itself
+    let res = get(&app, &format!("/api/v4/users/{}/tokens", user_id), &alice).await;
+    let tokens: Vec<serde_json::Value> = res.json().await.unwrap();
+    assert_eq!(1, tokens.len());
+    assert!(tokens[0].get("token").is_none());
+
+    // Other users cannot see or manage it
+    let res = get(&app, &format!("/api/v4/users/{}/tokens", user_id), &bob).await;
+    assert_eq!(403, res.status().as_u16());
+    let res = post(&app, "/api/v4/users/tokens/revoke", &bob, json!({ "token_id": token_id })).await;
+    assert_eq!(403, res.status().as_u16());
+
+    let res = post(&app, "/api/v4/users/tokens/disable", &alice, json!({ "token_id": token_id })).await;
+    assert_eq!(200, res.status().as_u16());
+    assert_eq!(401, get(&app, "/api/v4/users/me", &token).await.status().as_u16());
+    let res = post(&app, "/api/v4/users/tokens/enable", &alice, json!({ "token_id": token_id })).await;
+    assert_eq!(200, res.status().as_u16());
+    assert_eq!(200, get(&app, "/api/v4/users/me", &token).await.status().as_u16());
+
+    // Turning the feature off rejects existing tokens
+    set_tokens_enabled(&app, false).await;
+    assert_eq!(401, get(&app, "/api/v4/users/me", &token).await.status().as_u16());
+    set_tokens_enabled(&app, true).await;
+
+    let res = post(&app, "/api/v4/users/tokens/revoke", &alice, json!({ "token_id": token_id })).await;
+    assert_eq!(200, res.status().as_u16());
+    assert_eq!(401, get(&app, "/api/v4/users/me", &token).await.status().as_u16());
+}
+
+#[tokio::test]
+async fn bot_tokens_authenticate_as_the_bot() {
+    let app = spawn_app().await;
+    let owner = register(&app, "owner").await;
+
+    let res = post(&app, "/api/v1/bots", &owner, json!({ "display_name": "CI Bot" })).await;
+    assert_eq!(200, res.status().as_u16());
+    let bot: serde_json::Value = res.json().await.unwrap();
+    let bot_id = bot["id"].as_str().unwrap().to_string();
+
+    let res = post(
+        &app,
+        &format!("/api/v1/bots/{}/tokens", bot_id),
+        &owner,
+        json!({ "description": "deploys" }),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+    let created: serde_json::Value = res.json().await.unwrap();
+    let token = created["token"].as_str().unwrap().to_string();
+    let token_id = created["id"].as_str().unwrap().to_string();
+
+    // Only the hash is stored
+    let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM bot_tokens WHERE token_hash = $1")
+        .bind(&token)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(0, stored);
+    let res = get(&app, &format!("/api/v1/bots/{}/tokens", bot_id), &owner).await;
+    let tokens: Vec<serde_json::Value> = res.json().await.unwrap();
+    assert!(tokens[0].get("token").is_none());
+
+    // Bot tokens do not depend on the personal access token switch
+    let res = get(&app, "/api/v4/users/me", &token).await;
+    assert_eq!(200, res.status().as_u16());
+    let me: serde_json::Value = res.json().await.unwrap();
+    assert!(me["username"].as_str().unwrap().starts_with("bot_"));
+
+    let res = app
+        .api_client
+        .delete(format!("{}/api/v1/bots/{}/tokens/{}", &app.address, bot_id, token_id))
+        .header("Authorization", format!("Bearer {}", owner))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    assert_eq!(401, get(&app, "/api/v4/users/me", &token).await.status().as_u16());
+}
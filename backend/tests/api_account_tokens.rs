@@ -0,0 +1,270 @@
+use crate::common::{spawn_app, spawn_smtp_sink, SinkMessage, TestApp};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod common;
+
+async fn configure_email(app: &TestApp) -> Arc<Mutex<Vec<SinkMessage>>> {
+    let (smtp_port, sink) = spawn_smtp_sink().await;
+    sqlx::query(
+        r#"
+        UPDATE server_config
+        SET email = email || $1::jsonb,
+            site = site || '{"site_url": "https://chat.example.com"}'::jsonb
+        WHERE id = 'default'
+        "#,
+    )
+    .bind(json!({
+        "smtp_host": "127.0.0.1",
+        "smtp_port": smtp_port,
+        "smtp_security": "none",
+        "from_address": "noreply@example.com"
+    }))
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sink
+}
+
+/// Wait until the sink holds `count` emails
+///
+/// Password reset emails are sent after the request has been answered.
+async fn wait_for_mail(sink: &Arc<Mutex<Vec<SinkMessage>>>, count: usize) {
+    for _ in 0..50 {
+        if sink.lock().unwrap().len() >= count {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(count, sink.lock().unwrap().len());
+}
+
+/// Pull the token out of the link in an email
+fn token_from(message: &SinkMessage) -> String {
+    // Undo the quoted-printable soft line breaks and escaped '='
+    let data = message.data.replace("=\n", "").replace("=3D", "=");
+    let start = data.find("token=").expect("no link in email") + "token=".len();
+    data[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_hexdigit())
+        .collect()
+}
+
+async fn register(app: &TestApp, username: &str) -> serde_json::Value {
+    let res = app
+        .api_client
+        .post(format!("{}/api/v1/auth/register", &app.address))
+        .json(&json!({
+            "username": username,
+            "email": format!("{}@example.com", username),
+            "password": "Password123!",
+            "display_name": username
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    res.json().await.unwrap()
+}
+
+async fn login_status(app: &TestApp, email: &str, password: &str) -> u16 {
+    app.api_client
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&json!({ "email": email, "password": password }))
+        .send()
+        .await
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+async fn post(app: &TestApp, path: &str, body: serde_json::Value) -> u16 {
+    app.api_client
+        .post(format!("{}{}", &app.address, path))
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+#[tokio::test]
+async fn password_reset_tokens_are_single_use() {
+    let app = spawn_app().await;
+    let sink = configure_email(&app).await;
+    register(&app, "alice").await;
+
+    // Unknown addresses get the same answer but no email
+    assert_eq!(
+        200,
+        post(&app, "/api/v1/auth/password/reset/send", json!({ "email": "nobody@example.com" })).await
+    );
+
+    assert_eq!(
+        200,
+        post(&app, "/api/v1/auth/password/reset/send", json!({ "email": "alice@example.com" })).await
+    );
+    wait_for_mail(&sink, 1).await;
+    let token = {
+        let sink = sink.lock().unwrap();
+        assert_eq!(1, sink.len());
+        assert_eq!(vec!["alice@example.com".to_string()], sink[0].to);
+        assert!(sink[0].data.contains("Subject: [RustChat] Reset your password"));
+        token_from(&sink[0])
+    };
+    assert_eq!(64, token.len());
+
+    // Only the hash is stored
+    let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM account_tokens WHERE token_hash = $1")
+        .bind(&token)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(0, stored);
+
+    // A weak password is rejected without using up the token
+    assert_eq!(
+        422,
+        post(&app, "/api/v1/auth/password/reset", json!({ "token": token, "new_password": "weak" })).await
+    );
+    assert_eq!(
+        200,
+        post(
+            &app,
+            "/api/v4/users/password/reset",
+            json!({ "code": token, "new_password": "NewPassword456!" })
+        )
+        .await
+    );
+    assert_eq!(
+        400,
+        post(
+            &app,
+            "/api/v1/auth/password/reset",
+            json!({ "token": token, "new_password": "OtherPassword789!" })
+        )
+        .await
+    );
+
+    assert_eq!(401, login_status(&app, "alice@example.com", "Password123!").await);
+    assert_eq!(200, login_status(&app, "alice@example.com", "NewPassword456!").await);
+
+    // Expired tokens are rejected
+    assert_eq!(
+        200,
+        post(&app, "/api/v4/users/password/reset/send", json!({ "email": "alice@example.com" })).await
+    );
+    wait_for_mail(&sink, 2).await;
+    let token = token_from(&sink.lock().unwrap()[1]);
+    sqlx::query("UPDATE account_tokens SET expires_at = NOW() - INTERVAL '1 minute'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        400,
+        post(
+            &app,
+            "/api/v1/auth/password/reset",
+            json!({ "token": token, "new_password": "OtherPassword789!" })
+        )
+        .await
+    );
+}
+
+#[tokio::test]
+async fn password_reset_answers_alike_when_sending_fails() {
+    let app = spawn_app().await;
+    configure_email(&app).await;
+    register(&app, "bob").await;
+
+    // Nothing listens on the SMTP port
+    sqlx::query("UPDATE server_config SET email = email || '{\"smtp_port\": 1}'::jsonb")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    for email in ["bob@example.com", "nobody@example.com"] {
+        assert_eq!(
+            200,
+            post(&app, "/api/v4/users/password/reset/send", json!({ "email": email })).await
+        );
+    }
+
+    // Without email settings both are refused the same way
+    sqlx::query("UPDATE server_config SET email = email || '{\"smtp_host\": \"\"}'::jsonb")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let known = post(&app, "/api/v4/users/password/reset/send", json!({ "email": "bob@example.com" })).await;
+    let unknown =
+        post(&app, "/api/v4/users/password/reset/send", json!({ "email": "nobody@example.com" })).await;
+    assert_ne!(200, known);
+    assert_eq!(known, unknown);
+}
+
+#[tokio::test]
+async fn login_waits_for_email_verification_when_required() {
+    let app = spawn_app().await;
+    let sink = configure_email(&app).await;
+    sqlx::query(
+        r#"
+        UPDATE server_config
+        SET authentication = authentication || '{"require_email_verification": true}'::jsonb
+        WHERE id = 'default'
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let body = register(&app, "bob").await;
+    assert_eq!(true, body["verification_required"]);
+    assert!(body.get("token").is_none());
+    assert_eq!(false, body["user"]["email_verified"]);
+
+    assert_eq!(401, login_status(&app, "bob@example.com", "Password123!").await);
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/users/login", &app.address))
+        .json(&json!({ "login_id": "bob", "password": "Password123!" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(401, res.status().as_u16());
+
+    // Asking again replaces the first link
+    let first = {
+        let sink = sink.lock().unwrap();
+        assert_eq!(1, sink.len());
+        assert!(sink[0].data.contains("Subject: [RustChat] Verify your email address"));
+        token_from(&sink[0])
+    };
+    assert_eq!(
+        200,
+        post(&app, "/api/v4/users/email/verify/send", json!({ "email": "bob@example.com" })).await
+    );
+    let second = token_from(&sink.lock().unwrap()[1]);
+    assert_eq!(400, post(&app, "/api/v4/users/email/verify", json!({ "token": first })).await);
+    assert_eq!(200, post(&app, "/api/v4/users/email/verify", json!({ "token": second })).await);
+
+    assert_eq!(200, login_status(&app, "bob@example.com", "Password123!").await);
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/users/login", &app.address))
+        .json(&json!({ "login_id": "bob", "password": "Password123!" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    let user: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(true, user["email_verified"]);
+
+    // Verified users get no more verification emails
+    assert_eq!(
+        200,
+        post(&app, "/api/v1/auth/email/verify/send", json!({ "email": "bob@example.com" })).await
+    );
+    assert_eq!(2, sink.lock().unwrap().len());
+}
@@ -0,0 +1,262 @@
+use crate::common::{setup_channel_member, spawn_app, Fixture, TestApp};
+use rustchat::mattermost_compat::id::encode_mm_id;
+use serde_json::Value;
+use uuid::Uuid;
+
+mod common;
+
+async fn execute(
+    app: &TestApp,
+    fx: &Fixture,
+    channel_id: Uuid,
+    team_id: Uuid,
+    command: &str,
+) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/api/v4/commands/execute", &app.address))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .json(&serde_json::json!({
+            "command": command,
+            "channel_id": encode_mm_id(channel_id),
+            "team_id": encode_mm_id(team_id),
+        }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn run(app: &TestApp, fx: &Fixture, channel_id: Uuid, command: &str) -> Value {
+    let res = execute(app, fx, channel_id, fx.team_id, command).await;
+    assert_eq!(200, res.status().as_u16(), "{} failed", command);
+    res.json().await.unwrap()
+}
+
+async fn is_member(app: &TestApp, channel_id: Uuid, user_id: Uuid) -> bool {
+    sqlx::query_scalar(
+        "SELECT EXISTS(SELECT 1 FROM channel_members WHERE channel_id = $1 AND user_id = $2)",
+    )
+    .bind(channel_id)
+    .bind(user_id)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+/// Add bob to the fixture's team, but not its channel
+async fn add_bob(app: &TestApp, fx: &Fixture) -> Uuid {
+    let bob_id: Uuid = sqlx::query_scalar(
+        "INSERT INTO users (username, email, password_hash) VALUES ('bob', 'bob@example.com', 'x') RETURNING id",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    sqlx::query("INSERT INTO team_members (team_id, user_id) VALUES ($1, $2)")
+        .bind(fx.team_id)
+        .bind(bob_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    bob_id
+}
+
+async fn add_channel(app: &TestApp, team_id: Uuid, name: &str) -> Uuid {
+    sqlx::query_scalar(
+        "INSERT INTO channels (team_id, name, display_name, type) VALUES ($1, $2, $2, 'public') RETURNING id",
+    )
+    .bind(team_id)
+    .bind(name)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn echo_and_shrug_post_as_the_caller() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    let res = run(&app, &fx, fx.channel_id, "/echo hello\nworld").await;
+    assert_eq!("in_channel", res["response_type"]);
+    run(&app, &fx, fx.channel_id, "/shrug oh well").await;
+
+    let messages: Vec<String> =
+        sqlx::query_scalar("SELECT message FROM posts WHERE user_id = $1 ORDER BY created_at")
+            .bind(fx.user_id)
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!("hello\nworld", messages[0]);
+    assert!(messages[1].starts_with("oh well ¯"));
+}
+
+#[tokio::test]
+async fn away_updates_presence() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    run(&app, &fx, fx.channel_id, "/away").await;
+    let presence: String = sqlx::query_scalar("SELECT presence FROM users WHERE id = $1")
+        .bind(fx.user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!("away", presence);
+}
+
+#[tokio::test]
+async fn members_edit_the_header() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    run(&app, &fx, fx.channel_id, "/header Release day").await;
+    let header: Option<String> = sqlx::query_scalar("SELECT header FROM channels WHERE id = $1")
+        .bind(fx.channel_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(Some("Release day".to_string()), header);
+}
+
+#[tokio::test]
+async fn members_invite_and_kick() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let bob_id = add_bob(&app, &fx).await;
+
+    let res = run(&app, &fx, fx.channel_id, "/invite @bob").await;
+    assert_eq!("@bob added to ~test-channel.", res["text"]);
+    assert!(is_member(&app, fx.channel_id, bob_id).await);
+
+    run(&app, &fx, fx.channel_id, "/kick @bob").await;
+    assert!(!is_member(&app, fx.channel_id, bob_id).await);
+}
+
+#[tokio::test]
+async fn join_and_leave() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let other_id = add_channel(&app, fx.team_id, "other").await;
+
+    let res = run(&app, &fx, fx.channel_id, "/join ~other").await;
+    assert!(res["goto_location"]
+        .as_str()
+        .unwrap()
+        .ends_with("/test-team/channels/other"));
+    assert!(is_member(&app, other_id, fx.user_id).await);
+
+    run(&app, &fx, other_id, "/leave").await;
+    assert!(!is_member(&app, other_id, fx.user_id).await);
+}
+
+#[tokio::test]
+async fn join_is_refused_for_teams_the_caller_is_not_on() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    let org_id: Uuid = sqlx::query_scalar("SELECT org_id FROM teams WHERE id = $1")
+        .bind(fx.team_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    let other_team: Uuid = sqlx::query_scalar(
+        "INSERT INTO teams (org_id, name, display_name) VALUES ($1, 'other-team', 'Other Team') RETURNING id",
+    )
+    .bind(org_id)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    let secret_id = add_channel(&app, other_team, "secret").await;
+
+    // Naming another team
+    let res = execute(&app, &fx, fx.channel_id, other_team, "/join ~secret").await;
+    assert_eq!(403, res.status().as_u16());
+
+    // Sending the command from one of its channels
+    let res = execute(&app, &fx, secret_id, other_team, "/join ~secret").await;
+    assert_eq!(200, res.status().as_u16());
+    let res: Value = res.json().await.unwrap();
+    assert!(res["text"].as_str().unwrap().contains("find the channel"));
+
+    assert!(!is_member(&app, secret_id, fx.user_id).await);
+}
+
+#[tokio::test]
+async fn mute_toggles() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    run(&app, &fx, fx.channel_id, "/mute").await;
+    let mark_unread: Option<String> = sqlx::query_scalar(
+        "SELECT notify_props->>'mark_unread' FROM channel_members WHERE channel_id = $1 AND user_id = $2",
+    )
+    .bind(fx.channel_id)
+    .bind(fx.user_id)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(Some("mention".to_string()), mark_unread);
+
+    let res = run(&app, &fx, fx.channel_id, "/mute").await;
+    assert!(res["text"].as_str().unwrap().contains("no longer muted"));
+}
+
+#[tokio::test]
+async fn msg_opens_a_direct_message() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    add_bob(&app, &fx).await;
+
+    let res = run(&app, &fx, fx.channel_id, "/msg @bob psst").await;
+    assert!(res["goto_location"]
+        .as_str()
+        .unwrap()
+        .ends_with("/messages/@bob"));
+    let in_dm: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM posts p JOIN channels c ON c.id = p.channel_id
+            WHERE p.message = 'psst' AND c.type = 'direct'
+        )
+        "#,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(in_dm);
+}
+
+#[tokio::test]
+async fn search_lists_matching_posts() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    run(&app, &fx, fx.channel_id, "/echo hello world").await;
+    let res = run(&app, &fx, fx.channel_id, "/search world").await;
+    assert_eq!("ephemeral", res["response_type"]);
+    assert!(res["text"]
+        .as_str()
+        .unwrap()
+        .contains("@alice in ~test-channel"));
+}
+
+#[tokio::test]
+async fn autocomplete_knows_the_builtins() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    let suggestions: Value = app
+        .api_client
+        .get(format!(
+            "{}/api/v4/teams/{}/commands/autocomplete_suggestions?user_input=/jo",
+            &app.address,
+            encode_mm_id(fx.team_id)
+        ))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!("/join", suggestions["suggestions"][0]["complete"]);
+}
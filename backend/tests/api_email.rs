@@ -0,0 +1,180 @@
+use crate::common::{spawn_app, spawn_smtp_sink, TestApp};
+use base64::Engine;
+use serde_json::json;
+
+mod common;
+
+/// Register a user and return their token, optionally as a system admin
+async fn login(app: &TestApp, username: &str, admin: bool) -> String {
+    let email = format!("{}@example.com", username);
+    let res = app
+        .api_client
+        .post(format!("{}/api/v1/auth/register", &app.address))
+        .json(&json!({
+            "username": username,
+            "email": email,
+            "password": "Password123!",
+            "display_name": username
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+
+    if admin {
+        sqlx::query("UPDATE users SET role = 'system_admin' WHERE email = $1")
+            .bind(&email)
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    }
+
+    let res = app
+        .api_client
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&json!({ "email": email, "password": "Password123!" }))
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+    let body: serde_json::Value = res.json().await.unwrap();
+    body["token"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn smtp_password_is_encrypted_and_used_for_test_email() {
+    let app = spawn_app().await;
+    let token = login(&app, "admin", true).await;
+    let (smtp_port, sink) = spawn_smtp_sink().await;
+
+    let email_config = json!({
+        "smtp_host": "127.0.0.1",
+        "smtp_port": smtp_port,
+        "smtp_username": "mailer",
+        "smtp_password_encrypted": "s3cret-pass",
+        "smtp_security": "none",
+        "from_address": "noreply@example.com",
+        "from_name": "RustChat"
+    });
+    let res = app
+        .api_client
+        .patch(format!("{}/api/v1/admin/config/email", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&email_config)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!("********************************", body["smtp_password_encrypted"]);
+
+    // Stored encrypted, returned masked
+    let stored: String = sqlx::query_scalar(
+        "SELECT email->>'smtp_password_encrypted' FROM server_config WHERE id = 'default'",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(!stored.is_empty());
+    assert_ne!("s3cret-pass", stored);
+
+    let res = app
+        .api_client
+        .get(format!("{}/api/v1/admin/config", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .unwrap();
+    let config: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(
+        "********************************",
+        config["email"]["smtp_password_encrypted"]
+    );
+
+    // Saving the mask back keeps the stored password
+    let mut resaved = config["email"].clone();
+    resaved["from_name"] = json!("Chat");
+    let res = app
+        .api_client
+        .patch(format!("{}/api/v1/admin/config/email", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&resaved)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    let restored: String = sqlx::query_scalar(
+        "SELECT email->>'smtp_password_encrypted' FROM server_config WHERE id = 'default'",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(stored, restored);
+
+    let res = app
+        .api_client
+        .post(format!("{}/api/v1/admin/email/test", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&json!({ "to": "someone@example.com" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+
+    let sink = sink.lock().unwrap();
+    assert_eq!(1, sink.len());
+    assert_eq!(vec!["someone@example.com".to_string()], sink[0].to);
+    assert!(sink[0].data.contains("Test email"));
+
+    // The server authenticated with the decrypted password
+    let auth = sink[0].auth.as_deref().expect("client did not authenticate");
+    let credentials = base64::engine::general_purpose::STANDARD
+        .decode(auth.rsplit(' ').next().unwrap())
+        .unwrap();
+    assert_eq!(b"\0mailer\0s3cret-pass".to_vec(), credentials);
+}
+
+#[tokio::test]
+async fn test_email_requires_admin_and_reports_smtp_errors() {
+    let app = spawn_app().await;
+    let user_token = login(&app, "member", false).await;
+    let admin_token = login(&app, "admin", true).await;
+
+    let res = app
+        .api_client
+        .post(format!("{}/api/v1/admin/email/test", &app.address))
+        .header("Authorization", format!("Bearer {}", user_token))
+        .json(&json!({ "to": "someone@example.com" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(403, res.status().as_u16());
+
+    // Nothing listens on this port
+    let port = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().port()
+    };
+    sqlx::query("UPDATE server_config SET email = email || $1::jsonb WHERE id = 'default'")
+        .bind(json!({
+            "smtp_host": "127.0.0.1",
+            "smtp_port": port,
+            "smtp_security": "none",
+            "from_address": "noreply@example.com"
+        }))
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let res = app
+        .api_client
+        .post(format!("{}/api/v1/admin/email/test", &app.address))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .json(&json!({ "to": "someone@example.com" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(502, res.status().as_u16());
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!("EXTERNAL_SERVICE_ERROR", body["error"]["code"]);
+}
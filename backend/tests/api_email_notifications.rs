@@ -0,0 +1,143 @@
+use crate::common::{add_channel_member, setup_channel_member, spawn_app, spawn_smtp_sink, Fixture, TestApp};
+use rustchat::jobs::email_notifications::run_email_notifications;
+use rustchat::mattermost_compat::id::encode_mm_id;
+use std::time::Duration;
+use uuid::Uuid;
+
+mod common;
+
+async fn create_post(app: &TestApp, fx: &Fixture, message: &str) {
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/posts", &app.address))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .json(&serde_json::json!({
+            "channel_id": encode_mm_id(fx.channel_id),
+            "message": message
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+}
+
+/// Wait until `count` notifications are queued for the user
+async fn wait_for_queued(app: &TestApp, user_id: Uuid, count: i64) {
+    for _ in 0..50 {
+        let queued: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM email_notifications WHERE user_id = $1 AND status = 'pending'",
+        )
+        .bind(user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+        if queued >= count {
+            assert_eq!(count, queued);
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("notifications were not queued");
+}
+
+#[tokio::test]
+async fn missed_mentions_are_emailed_once_and_batched_by_interval() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob_token, bob_id) = add_channel_member(&app, &fx, "bob").await;
+    let (smtp_port, sink) = spawn_smtp_sink().await;
+
+    sqlx::query(
+        r#"
+        UPDATE server_config
+        SET email = email || $1::jsonb,
+            site = site || '{"site_url": "https://chat.example.com"}'::jsonb
+        WHERE id = 'default'
+        "#,
+    )
+    .bind(serde_json::json!({
+        "smtp_host": "127.0.0.1",
+        "smtp_port": smtp_port,
+        "smtp_tls": false,
+        "from_address": "noreply@example.com"
+    }))
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Plain posts are not emailed, mentions are sent right away by default
+    create_post(&app, &fx, "good morning").await;
+    create_post(&app, &fx, "hey @bob, standup?").await;
+    wait_for_queued(&app, bob_id, 1).await;
+
+    let stats = run_email_notifications(&app.state).await.unwrap();
+    assert_eq!(1, stats.emails_sent);
+    {
+        let sink = sink.lock().unwrap();
+        assert_eq!(1, sink.len());
+        assert_eq!(vec!["bob@example.com".to_string()], sink[0].to);
+        assert!(sink[0].data.contains("Subject: [RustChat] Notification in Test Channel"));
+        assert!(sink[0].data.contains("Content-Type: text/html"));
+        assert!(sink[0].data.contains("hey @bob, standup?"));
+    }
+
+    // Nothing is emailed twice
+    let stats = run_email_notifications(&app.state).await.unwrap();
+    assert_eq!(0, stats.emails_sent);
+
+    // With a 15 minute interval, mentions are collected into one digest
+    sqlx::query(
+        "INSERT INTO mattermost_preferences (user_id, category, name, value) VALUES ($1, 'notifications', 'email_interval', '900')",
+    )
+    .bind(bob_id)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    create_post(&app, &fx, "@bob first").await;
+    create_post(&app, &fx, "@bob second").await;
+    wait_for_queued(&app, bob_id, 2).await;
+
+    let stats = run_email_notifications(&app.state).await.unwrap();
+    assert_eq!(0, stats.emails_sent);
+
+    sqlx::query(
+        "UPDATE email_notifications SET created_at = NOW() - INTERVAL '16 minutes' WHERE user_id = $1",
+    )
+    .bind(bob_id)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let stats = run_email_notifications(&app.state).await.unwrap();
+    assert_eq!(1, stats.emails_sent);
+    assert_eq!(2, stats.posts_sent);
+    {
+        let sink = sink.lock().unwrap();
+        assert_eq!(2, sink.len());
+        assert!(sink[1].data.contains("Subject: [RustChat] You have 2 new notifications"));
+    }
+
+    // Mentions read before the digest goes out are dropped
+    create_post(&app, &fx, "@bob third").await;
+    wait_for_queued(&app, bob_id, 1).await;
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/channels/members/me/view", &app.address))
+        .header("Authorization", format!("Bearer {}", bob_token))
+        .json(&serde_json::json!({ "channel_id": encode_mm_id(fx.channel_id) }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    sqlx::query(
+        "UPDATE email_notifications SET created_at = NOW() - INTERVAL '16 minutes' WHERE user_id = $1",
+    )
+    .bind(bob_id)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let stats = run_email_notifications(&app.state).await.unwrap();
+    assert_eq!(0, stats.emails_sent);
+    assert_eq!(1, stats.posts_skipped);
+    assert_eq!(2, sink.lock().unwrap().len());
+}
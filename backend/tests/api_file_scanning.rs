@@ -0,0 +1,196 @@
+use std::sync::atomic::Ordering;
+
+use crate::common::mock_clamd::{spawn_mock_clamd, spawn_mock_clamd_unix, EICAR, VERSION};
+use crate::common::{setup_channel_member, spawn_app, Fixture, TestApp};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+mod common;
+
+async fn enable_scanning(app: &TestApp, address: &str) {
+    sqlx::query("UPDATE server_config SET file_scanning = $1 WHERE id = 'default'")
+        .bind(json!({ "enable": true, "clamd_address": address, "timeout_seconds": 5 }))
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+async fn upload(app: &TestApp, fx: &Fixture, name: &str, data: &[u8]) -> reqwest::Response {
+    let part = reqwest::multipart::Part::bytes(data.to_vec())
+        .file_name(name.to_string())
+        .mime_str("text/plain")
+        .unwrap();
+    let form = reqwest::multipart::Form::new()
+        .text("channel_id", fx.channel_id.to_string())
+        .part("files", part);
+
+    app.api_client
+        .post(format!("{}/api/v4/files", &app.address))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+/// The scan status and result of the only file named `name`
+async fn scan_of(app: &TestApp, name: &str) -> (Uuid, Option<String>, Option<String>) {
+    sqlx::query_as("SELECT id, scan_status, scan_result FROM files WHERE name = $1")
+        .bind(name)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+}
+
+async fn admin_token(app: &TestApp, fx: &Fixture, username: &str) -> String {
+    sqlx::query("UPDATE users SET role = 'system_admin' WHERE id = $1")
+        .bind(fx.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let body: Value = app
+        .api_client
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&json!({
+            "email": format!("{}@example.com", username),
+            "password": "Password123!",
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    body["token"].as_str().unwrap().to_string()
+}
+
+async fn health(app: &TestApp, token: &str) -> Value {
+    let res = app
+        .api_client
+        .get(format!("{}/api/v1/admin/health", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    res.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn infected_uploads_are_quarantined_and_audited() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "scan_user").await;
+    let clamd = spawn_mock_clamd().await;
+
+    // Files stored before scanning was turned on are left as they are
+    let res = upload(&app, &fx, "before.txt", b"unscanned").await;
+    assert_eq!(200, res.status().as_u16());
+    assert_eq!(None, scan_of(&app, "before.txt").await.1);
+
+    enable_scanning(&app, &clamd.address).await;
+
+    let res = upload(&app, &fx, "clean.txt", b"nothing to see").await;
+    assert_eq!(200, res.status().as_u16());
+    assert_eq!(
+        Some("clean".to_string()),
+        scan_of(&app, "clean.txt").await.1
+    );
+
+    let mut infected = b"prefix ".to_vec();
+    infected.extend_from_slice(EICAR);
+    let res = upload(&app, &fx, "eicar.txt", &infected).await;
+    assert_eq!(403, res.status().as_u16());
+    assert_eq!(2, clamd.scans.load(Ordering::SeqCst));
+
+    let (file_id, status, result) = scan_of(&app, "eicar.txt").await;
+    assert_eq!(Some("infected".to_string()), status);
+    assert_eq!(Some("Eicar-Signature".to_string()), result);
+
+    let (actor, metadata): (Option<Uuid>, Value) = sqlx::query_as(
+        "SELECT actor_user_id, metadata FROM audit_logs WHERE action = 'file.quarantined' AND target_id = $1",
+    )
+    .bind(file_id)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(Some(fx.user_id), actor);
+    assert_eq!("eicar.txt", metadata["name"]);
+    assert_eq!("Eicar-Signature", metadata["scan_result"]);
+
+    // Quarantined files are never served
+    for path in ["", "/thumbnail", "/preview", "/link"] {
+        let res = app
+            .api_client
+            .get(format!("{}/api/v4/files/{}{}", &app.address, file_id, path))
+            .header("Authorization", format!("Bearer {}", fx.token))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(403, res.status().as_u16(), "GET /files/{{id}}{}", path);
+    }
+    let res = app
+        .api_client
+        .get(format!(
+            "{}/api/v1/files/{}/download",
+            &app.address, file_id
+        ))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(403, res.status().as_u16());
+
+    // Presigned uploads would skip the scanner
+    let res = app
+        .api_client
+        .post(format!("{}/api/v1/files/presign", &app.address))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .json(&json!({ "filename": "direct.txt", "content_type": "text/plain" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn scanner_status_is_reported_and_unscannable_files_are_held_back() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "scan_admin").await;
+    let token = admin_token(&app, &fx, "scan_admin").await;
+
+    let body = health(&app, &token).await;
+    assert_eq!("healthy", body["status"]);
+    assert_eq!(false, body["file_scanning"]["enabled"]);
+
+    let socket = std::env::temp_dir().join(format!("clamd-{}.sock", Uuid::new_v4()));
+    let clamd = spawn_mock_clamd_unix(&socket).await;
+    enable_scanning(&app, &clamd.address).await;
+
+    let body = health(&app, &token).await;
+    assert_eq!("healthy", body["status"]);
+    assert_eq!(true, body["file_scanning"]["connected"]);
+    assert_eq!(VERSION, body["file_scanning"]["version"]);
+
+    let res = upload(&app, &fx, "socket.txt", b"scanned over a socket").await;
+    assert_eq!(200, res.status().as_u16());
+    assert_eq!(1, clamd.scans.load(Ordering::SeqCst));
+    std::fs::remove_file(&socket).unwrap();
+
+    // Nothing listens on the socket any more
+    let res = upload(&app, &fx, "unscannable.txt", b"held back").await;
+    assert_eq!(403, res.status().as_u16());
+    let (file_id, status, result) = scan_of(&app, "unscannable.txt").await;
+    assert_eq!(Some("failed".to_string()), status);
+    assert!(result.unwrap().contains("clamd"));
+    let action: String = sqlx::query_scalar("SELECT action FROM audit_logs WHERE target_id = $1")
+        .bind(file_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!("file.scan_failed", action);
+
+    let body = health(&app, &token).await;
+    assert_eq!("degraded", body["status"]);
+    assert_eq!(false, body["file_scanning"]["connected"]);
+    assert!(body["file_scanning"]["error"].is_string());
+}
@@ -0,0 +1,217 @@
+use std::time::Duration;
+
+use crate::common::{add_channel_member, setup_channel_member, spawn_app, Fixture, TestApp};
+use rustchat::mattermost_compat::id::parse_mm_or_uuid;
+use serde_json::{json, Value};
+
+mod common;
+
+async fn upload(app: &TestApp, token: &str, fx: &Fixture, name: &str, data: &[u8]) -> String {
+    let part = reqwest::multipart::Part::bytes(data.to_vec())
+        .file_name(name.to_string())
+        .mime_str("text/plain")
+        .unwrap();
+    let form = reqwest::multipart::Form::new()
+        .text("channel_id", fx.channel_id.to_string())
+        .part("files", part);
+
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/files", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    let body: Value = res.json().await.unwrap();
+    let file_id = body["file_infos"][0]["id"].as_str().unwrap().to_string();
+
+    // Wait for the text to be extracted
+    let id = parse_mm_or_uuid(&file_id).unwrap();
+    for _ in 0..100 {
+        let status: Option<String> =
+            sqlx::query_scalar("SELECT media_status FROM files WHERE id = $1")
+                .bind(id)
+                .fetch_one(&app.db_pool)
+                .await
+                .unwrap();
+        if status.as_deref() == Some("processed") {
+            return file_id;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Text of {} was not extracted", name);
+}
+
+async fn post_files(app: &TestApp, token: &str, fx: &Fixture, file_ids: &[&str]) {
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/posts", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&json!({
+            "channel_id": fx.channel_id.to_string(),
+            "message": "",
+            "file_ids": file_ids,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+}
+
+/// Names of the files a search finds, in order
+async fn search(app: &TestApp, token: &str, fx: &Fixture, terms: &str) -> Vec<String> {
+    let res = app
+        .api_client
+        .post(format!(
+            "{}/api/v4/teams/{}/files/search",
+            &app.address, fx.team_id
+        ))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&json!({ "terms": terms, "is_or_search": false }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    names(res.json().await.unwrap())
+}
+
+fn names(list: Value) -> Vec<String> {
+    list["order"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|id| {
+            list["file_infos"][id.as_str().unwrap()]["name"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn files_are_found_by_name_contents_and_modifiers() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, _) = add_channel_member(&app, &fx, "bob").await;
+
+    let budget = upload(
+        &app,
+        &fx.token,
+        &fx,
+        "budget_2026.csv",
+        b"quarterly,revenue\n1,2",
+    )
+    .await;
+    let notes = upload(&app, &bob, &fx, "notes.txt", b"Lunch menu for the offsite").await;
+    post_files(&app, &fx.token, &fx, &[&budget]).await;
+    post_files(&app, &bob, &fx, &[&notes]).await;
+    // Not shared in a post yet
+    upload(&app, &fx.token, &fx, "draft budget.txt", b"revenue").await;
+
+    assert_eq!(
+        vec!["budget_2026.csv"],
+        search(&app, &bob, &fx, "revenue").await
+    );
+    assert_eq!(
+        vec!["budget_2026.csv"],
+        search(&app, &bob, &fx, "budget").await
+    );
+    assert_eq!(
+        vec!["budget_2026.csv"],
+        search(&app, &bob, &fx, "dget_20").await
+    );
+    // Wildcards in the terms are matched literally
+    assert!(search(&app, &bob, &fx, "%").await.is_empty());
+    assert!(search(&app, &bob, &fx, "dget_%").await.is_empty());
+    assert_eq!(vec!["notes.txt"], search(&app, &bob, &fx, "lunches").await);
+    assert_eq!(vec!["notes.txt"], search(&app, &bob, &fx, "ext:txt").await);
+    assert_eq!(
+        vec!["budget_2026.csv"],
+        search(&app, &bob, &fx, "from:alice in:test-channel").await
+    );
+    assert_eq!(
+        vec!["notes.txt", "budget_2026.csv"],
+        search(&app, &bob, &fx, "after:2000-01-01").await
+    );
+    assert!(search(&app, &bob, &fx, "before:2000-01-01")
+        .await
+        .is_empty());
+
+    // Members of the team who are not in the channel find nothing
+    sqlx::query("DELETE FROM channel_members WHERE user_id <> $1")
+        .bind(fx.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(search(&app, &bob, &fx, "revenue").await.is_empty());
+
+    let res = app
+        .api_client
+        .post(format!(
+            "{}/api/v4/teams/{}/files/search",
+            &app.address, fx.team_id
+        ))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .json(&json!({ "terms": "   " }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(422, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn channel_files_are_listed_for_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "carol").await;
+    let (dave, dave_id) = add_channel_member(&app, &fx, "dave").await;
+
+    let first = upload(&app, &fx.token, &fx, "first.txt", b"one").await;
+    let second = upload(&app, &fx.token, &fx, "second.txt", b"two").await;
+    post_files(&app, &fx.token, &fx, &[&first]).await;
+    post_files(&app, &fx.token, &fx, &[&second]).await;
+
+    let url = format!("{}/api/v4/channels/{}/files", &app.address, fx.channel_id);
+    let list: Value = app
+        .api_client
+        .get(&url)
+        .header("Authorization", format!("Bearer {}", dave))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(vec!["second.txt", "first.txt"], names(list.clone()));
+    let info = &list["file_infos"][&first];
+    assert!(!info["post_id"].as_str().unwrap().is_empty());
+    assert!(!info["channel_id"].as_str().unwrap().is_empty());
+
+    let list: Value = app
+        .api_client
+        .get(format!("{}?page=1&per_page=1", url))
+        .header("Authorization", format!("Bearer {}", dave))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(vec!["first.txt"], names(list));
+
+    sqlx::query("DELETE FROM channel_members WHERE user_id = $1")
+        .bind(dave_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let res = app
+        .api_client
+        .get(&url)
+        .header("Authorization", format!("Bearer {}", dave))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(403, res.status().as_u16());
+}
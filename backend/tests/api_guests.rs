@@ -0,0 +1,242 @@
+use crate::common::{add_channel_member, setup_channel_member, spawn_app, TestApp};
+use serde_json::json;
+use uuid::Uuid;
+
+mod common;
+
+async fn send(
+    app: &TestApp,
+    method: reqwest::Method,
+    path: &str,
+    token: &str,
+    body: Option<serde_json::Value>,
+) -> reqwest::Response {
+    let mut req = app
+        .api_client
+        .request(method, format!("{}{}", &app.address, path))
+        .header("Authorization", format!("Bearer {}", token));
+    if let Some(body) = body {
+        req = req.json(&body);
+    }
+    req.send().await.unwrap()
+}
+
+async fn login(app: &TestApp, username: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&json!({
+            "email": format!("{}@example.com", username),
+            "password": "Password123!"
+        }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn login_token(app: &TestApp, username: &str) -> String {
+    let res = login(app, username).await;
+    assert_eq!(200, res.status().as_u16());
+    let body: serde_json::Value = res.json().await.unwrap();
+    body["token"].as_str().unwrap().to_string()
+}
+
+async fn set_guests_enabled(app: &TestApp, enabled: bool) {
+    sqlx::query(
+        r#"
+        UPDATE server_config
+        SET authentication = authentication || jsonb_build_object('enable_guest_accounts', $1::boolean)
+        WHERE id = 'default'
+        "#,
+    )
+    .bind(enabled)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn make_guest(app: &TestApp, user_id: Uuid, username: &str) -> String {
+    sqlx::query("UPDATE users SET role = 'guest' WHERE id = $1")
+        .bind(user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    login_token(app, username).await
+}
+
+#[tokio::test]
+async fn admins_demote_and_promote_guests() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (_, admin_id) = add_channel_member(&app, &fx, "admin").await;
+    sqlx::query("UPDATE users SET role = 'system_admin' WHERE id = $1")
+        .bind(admin_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let admin = login_token(&app, "admin").await;
+    let demote = format!("/api/v1/admin/users/{}/demote", fx.user_id);
+    let promote = format!("/api/v1/admin/users/{}/promote", fx.user_id);
+
+    // Off by default
+    let res = send(&app, reqwest::Method::POST, &demote, &admin, None).await;
+    assert_eq!(400, res.status().as_u16());
+
+    set_guests_enabled(&app, true).await;
+    let res = send(&app, reqwest::Method::POST, &demote, &fx.token, None).await;
+    assert_eq!(403, res.status().as_u16());
+    let res = send(&app, reqwest::Method::POST, &demote, &admin, None).await;
+    assert_eq!(200, res.status().as_u16());
+    let user: serde_json::Value = res.json().await.unwrap();
+    assert_eq!("guest", user["role"]);
+
+    // The old session carried the old role
+    let res = send(
+        &app,
+        reqwest::Method::GET,
+        "/api/v4/users/me",
+        &fx.token,
+        None,
+    )
+    .await;
+    assert_eq!(401, res.status().as_u16());
+    let guest = login_token(&app, "alice").await;
+    let res = send(&app, reqwest::Method::GET, "/api/v4/users/me", &guest, None).await;
+    let me: serde_json::Value = res.json().await.unwrap();
+    assert_eq!("system_guest", me["roles"]);
+
+    // Guests cannot sign in while the feature is off
+    set_guests_enabled(&app, false).await;
+    assert_eq!(401, login(&app, "alice").await.status().as_u16());
+    set_guests_enabled(&app, true).await;
+
+    let res = send(&app, reqwest::Method::POST, &promote, &admin, None).await;
+    assert_eq!(200, res.status().as_u16());
+    let res = send(&app, reqwest::Method::POST, &promote, &admin, None).await;
+    assert_eq!(400, res.status().as_u16());
+    let member = login_token(&app, "alice").await;
+    let res = send(
+        &app,
+        reqwest::Method::GET,
+        "/api/v4/users/me",
+        &member,
+        None,
+    )
+    .await;
+    let me: serde_json::Value = res.json().await.unwrap();
+    assert_eq!("system_user", me["roles"]);
+}
+
+#[tokio::test]
+async fn guests_only_see_channels_they_were_added_to() {
+    let app = spawn_app().await;
+    set_guests_enabled(&app, true).await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (_, guest_id) = add_channel_member(&app, &fx, "gus").await;
+    let guest = make_guest(&app, guest_id, "gus").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        "/api/v1/channels",
+        &fx.token,
+        Some(json!({ "team_id": fx.team_id, "name": "lounge", "display_name": "Lounge", "channel_type": "public" })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+    let lounge: serde_json::Value = res.json().await.unwrap();
+    let lounge_id = lounge["id"].as_str().unwrap();
+
+    let joinable = format!(
+        "/api/v1/channels?team_id={}&available_to_join=true",
+        fx.team_id
+    );
+    let res = send(&app, reqwest::Method::GET, &joinable, &guest, None).await;
+    let channels: Vec<serde_json::Value> = res.json().await.unwrap();
+    assert!(channels.is_empty());
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &format!("/api/v1/channels/{}/members", lounge_id),
+        &guest,
+        Some(json!({ "user_id": guest_id })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        "/api/v4/channels/search",
+        &guest,
+        Some(json!({ "term": "lounge" })),
+    )
+    .await;
+    let found: Vec<serde_json::Value> = res.json().await.unwrap();
+    assert!(found.is_empty());
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        "/api/v1/channels",
+        &guest,
+        Some(json!({ "team_id": fx.team_id, "name": "mine", "display_name": "Mine", "channel_type": "public" })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn guests_only_message_people_they_share_a_channel_with() {
+    let app = spawn_app().await;
+    set_guests_enabled(&app, true).await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (_, guest_id) = add_channel_member(&app, &fx, "gus").await;
+    let guest = make_guest(&app, guest_id, "gus").await;
+
+    // Olga is on the team but not in the guest's channel
+    let (olga, olga_id) = add_channel_member(&app, &fx, "olga").await;
+    sqlx::query("DELETE FROM channel_members WHERE user_id = $1")
+        .bind(olga_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let dm = |target: Uuid| {
+        json!({
+            "team_id": fx.team_id,
+            "name": "dm",
+            "channel_type": "direct",
+            "target_user_id": target
+        })
+    };
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        "/api/v1/channels",
+        &guest,
+        Some(dm(fx.user_id)),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        "/api/v1/channels",
+        &guest,
+        Some(dm(olga_id)),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+
+    // The restriction holds the other way round too
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        "/api/v4/channels/direct",
+        &olga,
+        Some(json!([olga_id.to_string(), guest_id.to_string()])),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+}
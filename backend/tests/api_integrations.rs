@@ -98,6 +98,7 @@ async fn test_slash_command_lifecycle() {
         command: "/echo Hello World".to_string(),
         channel_id: channel_uuid,
         team_id: Some(team.id),
+        root_id: None,
     };
 
     let echo_res = app
@@ -116,7 +117,8 @@ async fn test_slash_command_lifecycle() {
         .json()
         .await
         .expect("Failed to parse echo response");
-    assert_eq!(echo_body.text, "Echo: Hello World");
+    assert_eq!(echo_body.response_type, "in_channel");
+    assert_eq!(echo_body.text, "Hello World");
 
     // 5. Create Custom Slash Command
     let new_cmd = CreateSlashCommand {
@@ -153,6 +155,7 @@ async fn test_slash_command_lifecycle() {
         command: "/custom some args".to_string(),
         channel_id: channel_uuid,
         team_id: Some(team.id),
+        root_id: None,
     };
 
     let exec_res = app
@@ -0,0 +1,264 @@
+use crate::common::mock_ldap::{
+    group_dn, spawn_mock_ldap, user_dn, MockLdap, BASE_DN, BIND_DN, BIND_PASSWORD,
+};
+use crate::common::{setup_channel_member, spawn_app, Fixture, TestApp};
+use serde_json::{json, Value};
+
+mod common;
+
+/// Register a local user, optionally as a system admin, and return their token
+async fn local_login(app: &TestApp, username: &str, admin: bool) -> String {
+    let email = format!("{}@example.com", username);
+    app.api_client
+        .post(format!("{}/api/v1/auth/register", &app.address))
+        .json(&json!({
+            "username": username,
+            "email": email,
+            "password": "Password123!",
+            "display_name": username
+        }))
+        .send()
+        .await
+        .unwrap();
+    if admin {
+        sqlx::query("UPDATE users SET role = 'system_admin' WHERE email = $1")
+            .bind(&email)
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+    }
+
+    let res = login(app, &email, "Password123!").await;
+    assert_eq!(200, res.status().as_u16());
+    let body: Value = res.json().await.unwrap();
+    body["token"].as_str().unwrap().to_string()
+}
+
+async fn login(app: &TestApp, login_id: &str, password: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&json!({ "email": login_id, "password": password }))
+        .send()
+        .await
+        .unwrap()
+}
+
+fn ldap_settings(ldap: &MockLdap, fx: &Fixture, bind_password: &str) -> Value {
+    json!({
+        "server_url": ldap.url,
+        "bind_dn": BIND_DN,
+        "bind_password_encrypted": bind_password,
+        "base_dn": BASE_DN,
+        "group_mappings": [{
+            "group_dn": group_dn("engineering"),
+            "team_id": fx.team_id,
+            "channel_id": fx.channel_id
+        }]
+    })
+}
+
+/// Turn LDAP on without going through the admin API
+async fn configure_ldap(app: &TestApp, ldap: &MockLdap, fx: &Fixture) {
+    let password = rustchat::crypto::encrypt(BIND_PASSWORD, &app.state.encryption_key);
+    sqlx::query(
+        r#"
+        UPDATE server_config
+        SET authentication = authentication || jsonb_build_object('enable_ldap', true, 'ldap', $1::jsonb)
+        WHERE id = 'default'
+        "#,
+    )
+    .bind(ldap_settings(ldap, fx, &password))
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn is_member(app: &TestApp, table: &str, column: &str, id: uuid::Uuid, user: &Value) -> bool {
+    sqlx::query_scalar(&format!(
+        "SELECT EXISTS (SELECT 1 FROM {} WHERE {} = $1 AND user_id = $2::uuid)",
+        table, column
+    ))
+    .bind(id)
+    .bind(user["id"].as_str().unwrap())
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+async fn sync(app: &TestApp, token: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/api/v1/admin/ldap/sync", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn ldap_logins_provision_directory_accounts() {
+    let app = spawn_app().await;
+    let admin = local_login(&app, "ldap_admin", true).await;
+    let fx = setup_channel_member(&app, "ldap_owner").await;
+    let ldap = spawn_mock_ldap().await;
+    ldap.add_user("jdoe", "jane@corp.example.com", "Jane Doe", "ldap-pass");
+    ldap.add_group("engineering", &["jdoe"]);
+
+    // The bind password is stored encrypted and never sent back
+    let res = app
+        .api_client
+        .patch(format!(
+            "{}/api/v1/admin/config/authentication",
+            &app.address
+        ))
+        .header("Authorization", format!("Bearer {}", admin))
+        .json(&json!({
+            "enable_ldap": true,
+            "ldap": ldap_settings(&ldap, &fx, BIND_PASSWORD)
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    let saved: Value = res.json().await.unwrap();
+    assert_ne!(BIND_PASSWORD, saved["ldap"]["bind_password_encrypted"]);
+    let stored: String = sqlx::query_scalar(
+        "SELECT authentication->'ldap'->>'bind_password_encrypted' FROM server_config WHERE id = 'default'",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_ne!(BIND_PASSWORD, stored);
+
+    let policy: Value = app
+        .api_client
+        .get(format!("{}/api/v1/auth/policy", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(true, policy["enable_ldap"]);
+    assert_eq!("", policy["ldap"]["server_url"]);
+
+    assert_eq!(401, login(&app, "jdoe", "wrong").await.status().as_u16());
+    assert_eq!(401, login(&app, "jdoe", "").await.status().as_u16());
+    assert_eq!(
+        401,
+        login(&app, "nobody", "ldap-pass").await.status().as_u16()
+    );
+
+    let res = login(&app, "jdoe", "ldap-pass").await;
+    assert_eq!(200, res.status().as_u16());
+    let body: Value = res.json().await.unwrap();
+    let user = &body["user"];
+    assert_eq!("jdoe", user["username"]);
+    assert_eq!("jane@corp.example.com", user["email"]);
+    assert_eq!("Jane Doe", user["display_name"]);
+
+    // Group members land in the mapped team and channel
+    assert!(is_member(&app, "team_members", "team_id", fx.team_id, user).await);
+    assert!(is_member(&app, "channel_members", "channel_id", fx.channel_id, user).await);
+
+    // The v4 login signs in to the same account, by email too
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/users/login", &app.address))
+        .json(&json!({ "login_id": "jane@corp.example.com", "password": "ldap-pass" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    let mm_user: Value = res.json().await.unwrap();
+    assert_eq!("jdoe", mm_user["username"]);
+
+    // Local accounts keep their passwords
+    let res = login(&app, "ldap_admin@example.com", "Password123!").await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn ldap_sync_deactivates_removed_users() {
+    let app = spawn_app().await;
+    let admin = local_login(&app, "sync_admin", true).await;
+    let fx = setup_channel_member(&app, "sync_owner").await;
+    let ldap = spawn_mock_ldap().await;
+    ldap.add_user("jdoe", "jane@corp.example.com", "Jane Doe", "ldap-pass");
+    ldap.add_user("sam", "sam@corp.example.com", "Sam", "sam-pass");
+    ldap.add_group("engineering", &["jdoe", "sam"]);
+    configure_ldap(&app, &ldap, &fx).await;
+
+    let jane: Value = login(&app, "jdoe", "ldap-pass").await.json().await.unwrap();
+    let sam: Value = login(&app, "sam", "sam-pass").await.json().await.unwrap();
+    let sam_token = sam["token"].as_str().unwrap();
+
+    // Sam leaves the company, Jane leaves the group and gets a new name
+    ldap.remove(&user_dn("sam"));
+    ldap.remove(&group_dn("engineering"));
+    ldap.add_group("engineering", &[]);
+    for entry in ldap.directory.lock().unwrap().iter_mut() {
+        if entry.dn == user_dn("jdoe") {
+            entry.attrs.retain(|(name, _)| name != "cn");
+            entry
+                .attrs
+                .push(("cn".to_string(), vec!["Jane Smith".to_string()]));
+        }
+    }
+
+    assert_eq!(403, sync(&app, &fx.token).await.status().as_u16());
+    let res = sync(&app, &admin).await;
+    assert_eq!(200, res.status().as_u16());
+    let run: Value = res.json().await.unwrap();
+    assert_eq!("success", run["status"], "{}", run);
+    assert_eq!(1, run["users_synced"]);
+    assert_eq!(1, run["users_deactivated"]);
+    assert_eq!(1, run["memberships_removed"]);
+
+    let me = app
+        .api_client
+        .get(format!("{}/api/v1/auth/me", &app.address))
+        .header("Authorization", format!("Bearer {}", sam_token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(401, me.status().as_u16());
+    assert_eq!(401, login(&app, "sam", "sam-pass").await.status().as_u16());
+
+    let jane_user = &jane["user"];
+    assert!(is_member(&app, "team_members", "team_id", fx.team_id, jane_user).await);
+    assert!(
+        !is_member(
+            &app,
+            "channel_members",
+            "channel_id",
+            fx.channel_id,
+            jane_user
+        )
+        .await
+    );
+    let name: String = sqlx::query_scalar("SELECT display_name FROM users WHERE id = $1::uuid")
+        .bind(jane_user["id"].as_str().unwrap())
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!("Jane Smith", name);
+
+    // An empty directory looks like a misconfiguration, not a mass departure
+    ldap.remove(&user_dn("jdoe"));
+    let run: Value = sync(&app, &admin).await.json().await.unwrap();
+    assert_eq!("failed", run["status"]);
+    assert!(run["error"].as_str().unwrap().contains("returned no users"));
+
+    let runs: Value = app
+        .api_client
+        .get(format!("{}/api/v1/admin/ldap/sync", &app.address))
+        .header("Authorization", format!("Bearer {}", admin))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(2, runs.as_array().unwrap().len());
+    assert_eq!("failed", runs[0]["status"]);
+}
@@ -0,0 +1,247 @@
+use crate::common::{
+    client_from, random_loopback_ip, setup_channel_member, spawn_app, spawn_app_with, TestApp,
+};
+use rustchat::services::client_address::TrustedProxies;
+use serde_json::{json, Value};
+use std::net::IpAddr;
+use uuid::Uuid;
+
+mod common;
+
+/// Failed logins are counted in Redis, so these tests need a server
+async fn redis_available(app: &TestApp) -> bool {
+    if app.state.redis.get().await.is_ok() {
+        return true;
+    }
+    eprintln!("Redis is not available, skipping");
+    false
+}
+
+async fn set_limits(app: &TestApp, per_account: i32, per_ip: i32) {
+    sqlx::query(
+        r#"
+        UPDATE server_config
+        SET authentication = authentication || jsonb_build_object(
+            'maximum_login_attempts', $1::int,
+            'maximum_login_attempts_per_ip', $2::int,
+            'login_lockout_minutes', 15)
+        WHERE id = 'default'
+        "#,
+    )
+    .bind(per_account)
+    .bind(per_ip)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+/// Log in from the client address `ip`
+async fn login(app: &TestApp, email: &str, password: &str, ip: IpAddr) -> reqwest::Response {
+    client_from(ip)
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&json!({ "email": email, "password": password }))
+        .send()
+        .await
+        .unwrap()
+}
+
+/// A client address for a proxy to forward, which no other test run uses
+fn forwarded_ip() -> String {
+    std::net::Ipv6Addr::from(Uuid::new_v4().as_u128()).to_string()
+}
+
+/// Log in through a proxy that names `forwarded_for` as the client
+async fn login_forwarded(
+    app: &TestApp,
+    email: &str,
+    password: &str,
+    proxy: IpAddr,
+    forwarded_for: &str,
+) -> reqwest::Response {
+    client_from(proxy)
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .header("X-Forwarded-For", forwarded_for)
+        .json(&json!({ "email": email, "password": password }))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn repeated_failures_lock_the_account() {
+    let app = spawn_app().await;
+    if !redis_available(&app).await {
+        return;
+    }
+    let fx = setup_channel_member(&app, "lockout_user").await;
+    let admin = setup_channel_member(&app, "lockout_admin").await;
+    set_limits(&app, 3, 0).await;
+    let email = "lockout_user@example.com";
+
+    for _ in 0..2 {
+        let res = login(&app, email, "wrong", random_loopback_ip()).await;
+        assert_eq!(401, res.status().as_u16());
+    }
+    // The v4 login counts against the same account
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/users/login", &app.address))
+        .json(&json!({ "login_id": "lockout_user", "password": "wrong" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(401, res.status().as_u16());
+
+    // Even the right password is refused now
+    let res = login(&app, email, "Password123!", random_loopback_ip()).await;
+    assert_eq!(429, res.status().as_u16());
+    let locked: Value = res.json().await.unwrap();
+    let res = login(&app, &email.to_uppercase(), "wrong", random_loopback_ip()).await;
+    assert_eq!(429, res.status().as_u16());
+
+    // An unknown account locks out the same way
+    let nobody = format!("{}@example.com", Uuid::new_v4());
+    for _ in 0..3 {
+        let res = login(&app, &nobody, "wrong", random_loopback_ip()).await;
+        assert_eq!(401, res.status().as_u16());
+    }
+    let res = login(&app, &nobody, "wrong", random_loopback_ip()).await;
+    assert_eq!(429, res.status().as_u16());
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(locked, body);
+
+    let audited: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM audit_logs WHERE action = 'user.login_locked' AND target_id = $1",
+    )
+    .bind(fx.user_id)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(1, audited);
+
+    // Only admins can lift a lockout
+    let unlock = format!("{}/api/v1/admin/users/{}/unlock", &app.address, fx.user_id);
+    let res = app
+        .api_client
+        .post(&unlock)
+        .header("Authorization", format!("Bearer {}", admin.token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(403, res.status().as_u16());
+    sqlx::query("UPDATE users SET role = 'system_admin' WHERE id = $1")
+        .bind(admin.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let res = login(
+        &app,
+        "lockout_admin@example.com",
+        "Password123!",
+        random_loopback_ip(),
+    )
+    .await;
+    let body: Value = res.json().await.unwrap();
+    let res = app
+        .api_client
+        .post(&unlock)
+        .header(
+            "Authorization",
+            format!("Bearer {}", body["token"].as_str().unwrap()),
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+
+    let res = login(&app, email, "Password123!", random_loopback_ip()).await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn failures_from_one_address_lock_it_out() {
+    let app = spawn_app().await;
+    if !redis_available(&app).await {
+        return;
+    }
+    setup_channel_member(&app, "ip_victim").await;
+    set_limits(&app, 10, 3).await;
+    let ip = random_loopback_ip();
+
+    // Spread over accounts, so no single account reaches its limit
+    for _ in 0..3 {
+        let nobody = format!("{}@example.com", Uuid::new_v4());
+        let res = login(&app, &nobody, "wrong", ip).await;
+        assert_eq!(401, res.status().as_u16());
+    }
+
+    let res = login(&app, "ip_victim@example.com", "Password123!", ip).await;
+    assert_eq!(429, res.status().as_u16());
+    let res = login(
+        &app,
+        "ip_victim@example.com",
+        "Password123!",
+        random_loopback_ip(),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+
+    let audited: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM audit_logs WHERE action = 'ip.login_locked' AND actor_ip = $1",
+    )
+    .bind(ip.to_string())
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(1, audited);
+}
+
+#[tokio::test]
+async fn forwarding_headers_count_only_from_trusted_proxies() {
+    let proxy = random_loopback_ip();
+    let app = spawn_app_with(|state| {
+        state.with_trusted_proxies(TrustedProxies::parse(&proxy.to_string()).unwrap())
+    })
+    .await;
+    if !redis_available(&app).await {
+        return;
+    }
+    setup_channel_member(&app, "proxied_user").await;
+    set_limits(&app, 10, 2).await;
+    let email = "proxied_user@example.com";
+
+    // A client naming a new address on every attempt is still locked out
+    let client = random_loopback_ip();
+    for _ in 0..2 {
+        let nobody = format!("{}@example.com", Uuid::new_v4());
+        let spoofed = random_loopback_ip().to_string();
+        let res = login_forwarded(&app, &nobody, "wrong", client, &spoofed).await;
+        assert_eq!(401, res.status().as_u16());
+    }
+    let spoofed = random_loopback_ip().to_string();
+    let res = login_forwarded(&app, email, "Password123!", client, &spoofed).await;
+    assert_eq!(429, res.status().as_u16());
+
+    // Behind the trusted proxy, the client it names is what counts
+    let proxied = forwarded_ip();
+    for _ in 0..2 {
+        let nobody = format!("{}@example.com", Uuid::new_v4());
+        let forwarded = format!("{}, {}", forwarded_ip(), proxied);
+        let res = login_forwarded(&app, &nobody, "wrong", proxy, &forwarded).await;
+        assert_eq!(401, res.status().as_u16());
+    }
+    let res = login_forwarded(&app, email, "Password123!", proxy, &proxied).await;
+    assert_eq!(429, res.status().as_u16());
+    let other = forwarded_ip();
+    let res = login_forwarded(&app, email, "Password123!", proxy, &other).await;
+    assert_eq!(200, res.status().as_u16());
+
+    let sessions: Vec<String> = sqlx::query_scalar(
+        "SELECT ip_address FROM sessions s JOIN users u ON u.id = s.user_id WHERE u.email = $1 AND ip_address IS NOT NULL ORDER BY s.created_at",
+    )
+    .bind(email)
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(Some(&other), sessions.last());
+}
@@ -0,0 +1,314 @@
+use std::io::Cursor;
+use std::time::Duration;
+
+use crate::common::{setup_channel_member, spawn_app, Fixture, TestApp};
+use image::codecs::jpeg::JpegEncoder;
+use image::metadata::Orientation;
+use image::{
+    DynamicImage, GenericImageView, ImageDecoder, ImageFormat, ImageReader, Rgb, RgbImage,
+};
+use serde_json::Value;
+
+mod common;
+
+/// Where the test photos say they were taken
+const LOCATION: &[u8] = b"GPS 51.5074 N 0.1278 W";
+
+/// A JPEG whose EXIF says to turn it 90° clockwise, with `extra` bytes after
+/// the orientation
+fn rotated_jpeg(width: u32, height: u32, extra: &[u8]) -> Vec<u8> {
+    let img = RgbImage::from_fn(width, height, |x, _| Rgb([(x % 256) as u8, 100, 200]));
+    let mut jpeg = Vec::new();
+    DynamicImage::ImageRgb8(img)
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, 80))
+        .unwrap();
+
+    // Big-endian TIFF with one entry: Orientation (0x0112) = 6
+    let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08".to_vec();
+    exif.extend_from_slice(&[0, 1, 0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, 0, 0, 0, 0]);
+    exif.extend_from_slice(extra);
+    let mut segment = vec![0xff, 0xe1];
+    segment.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+    segment.extend_from_slice(&exif);
+
+    // Right after the start of image marker
+    jpeg.splice(2..2, segment);
+    jpeg
+}
+
+fn gif() -> Vec<u8> {
+    let img = RgbImage::from_pixel(30, 20, Rgb([255, 0, 0]));
+    let mut data = Vec::new();
+    DynamicImage::ImageRgb8(img)
+        .write_to(&mut Cursor::new(&mut data), ImageFormat::Gif)
+        .unwrap();
+    data
+}
+
+async fn upload(app: &TestApp, fx: &Fixture, name: &str, mime: &str, data: Vec<u8>) -> String {
+    let part = reqwest::multipart::Part::bytes(data)
+        .file_name(name.to_string())
+        .mime_str(mime)
+        .unwrap();
+    let form = reqwest::multipart::Form::new()
+        .text("channel_id", fx.channel_id.to_string())
+        .part("files", part);
+
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/files", &app.address))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    let body: Value = res.json().await.unwrap();
+    body["file_infos"][0]["id"].as_str().unwrap().to_string()
+}
+
+/// File info once processing is done
+async fn processed_info(app: &TestApp, fx: &Fixture, file_id: &str) -> Value {
+    for _ in 0..600 {
+        let info: Value = app
+            .api_client
+            .get(format!("{}/api/v4/files/{}/info", &app.address, file_id))
+            .header("Authorization", format!("Bearer {}", fx.token))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        if info["has_preview_image"] == true {
+            return info;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("File {} was not processed", file_id);
+}
+
+/// Follow a redirect to a stored image
+async fn fetch_image(app: &TestApp, fx: &Fixture, path: &str) -> Option<DynamicImage> {
+    let res = app
+        .api_client
+        .get(format!("{}/api/v4/files/{}", &app.address, path))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .send()
+        .await
+        .unwrap();
+    if !res.status().is_redirection() {
+        return None;
+    }
+    let location = res.headers()["location"].to_str().unwrap().to_string();
+    let data = app
+        .api_client
+        .get(format!("{}{}", &app.address, location))
+        .send()
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    Some(image::load_from_memory(&data).unwrap())
+}
+
+#[tokio::test]
+async fn images_are_turned_upright_and_stripped() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "media_user").await;
+
+    let file_id = upload(
+        &app,
+        &fx,
+        "photo.jpg",
+        "image/jpeg",
+        rotated_jpeg(2400, 1200, LOCATION),
+    )
+    .await;
+    let info = processed_info(&app, &fx, &file_id).await;
+    assert_eq!(1200, info["width"]);
+    assert_eq!(2400, info["height"]);
+    let mini = info["mini_preview"].as_str().unwrap();
+    assert!(!mini.is_empty());
+
+    // The original is stored as it was, keeping only its orientation
+    let original = fetch_image(&app, &fx, &file_id).await.unwrap();
+    assert_eq!((2400, 1200), original.dimensions());
+    let id = rustchat::mattermost_compat::id::parse_mm_or_uuid(&file_id).unwrap();
+    let (key, size, sha256): (String, i64, String) =
+        sqlx::query_as("SELECT key, size, sha256 FROM files WHERE id = $1")
+            .bind(id)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    let stored = app.state.storage.current().download(&key).await.unwrap();
+    assert_eq!(stored.len() as i64, size);
+    assert_eq!(
+        hex::encode(<sha2::Sha256 as sha2::Digest>::digest(&stored)),
+        sha256
+    );
+    assert_eq!(rotated_jpeg(2400, 1200, b""), stored.to_vec());
+    let mut decoder = ImageReader::new(Cursor::new(stored))
+        .with_guessed_format()
+        .unwrap()
+        .into_decoder()
+        .unwrap();
+    assert_eq!(Orientation::Rotate90, decoder.orientation().unwrap());
+
+    let thumbnail = fetch_image(&app, &fx, &format!("{}/thumbnail", file_id))
+        .await
+        .unwrap();
+    assert_eq!((200, 400), thumbnail.dimensions());
+    let preview = fetch_image(&app, &fx, &format!("{}/preview", file_id))
+        .await
+        .unwrap();
+    assert_eq!((960, 1920), preview.dimensions());
+}
+
+#[tokio::test]
+async fn gifs_are_previewed_by_their_first_frame() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "gif_user").await;
+
+    let file_id = upload(&app, &fx, "wave.gif", "image/gif", gif()).await;
+    let info = processed_info(&app, &fx, &file_id).await;
+    assert_eq!(30, info["width"]);
+    assert_eq!(20, info["height"]);
+
+    let preview = fetch_image(&app, &fx, &format!("{}/preview", file_id))
+        .await
+        .unwrap();
+    assert_eq!((30, 20), preview.dimensions());
+
+    // Other files have nothing to preview
+    let file_id = upload(&app, &fx, "notes.txt", "text/plain", b"hello".to_vec()).await;
+    assert!(fetch_image(&app, &fx, &format!("{}/preview", file_id))
+        .await
+        .is_none());
+}
+
+#[tokio::test]
+async fn images_are_not_served_before_they_are_stripped() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "unstripped_user").await;
+
+    let file_id = upload(
+        &app,
+        &fx,
+        "photo.jpg",
+        "image/jpeg",
+        rotated_jpeg(40, 20, LOCATION),
+    )
+    .await;
+    processed_info(&app, &fx, &file_id).await;
+    let id = rustchat::mattermost_compat::id::parse_mm_or_uuid(&file_id).unwrap();
+
+    let status = |path: String| {
+        let app = &app;
+        let token = fx.token.clone();
+        async move {
+            app.api_client
+                .get(format!("{}/api/v4/files/{}", &app.address, path))
+                .header("Authorization", format!("Bearer {}", token))
+                .send()
+                .await
+                .unwrap()
+                .status()
+                .as_u16()
+        }
+    };
+    assert_eq!(200, status(format!("{}/link", file_id)).await);
+
+    for (media_status, expected) in [("pending", 409), ("processing", 409), ("failed", 403)] {
+        sqlx::query("UPDATE files SET media_status = $2 WHERE id = $1")
+            .bind(id)
+            .bind(media_status)
+            .execute(&app.db_pool)
+            .await
+            .unwrap();
+        assert_eq!(expected, status(file_id.clone()).await);
+        assert_eq!(expected, status(format!("{}/link", file_id)).await);
+        assert_eq!(expected, status(format!("{}/preview", file_id)).await);
+    }
+
+    // Thumbnails are made without the metadata
+    assert_eq!(307, status(format!("{}/thumbnail", file_id)).await);
+}
+
+#[tokio::test]
+async fn large_images_are_stripped_without_previews() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "large_image_user").await;
+    sqlx::query(
+        "UPDATE server_config SET site = jsonb_set(site, '{max_file_size_mb}', '60') WHERE id = 'default'",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Comments pad it past the size images are decoded up to
+    let mut comment = vec![0xff, 0xfe, 0xff, 0xff];
+    comment.resize(4 + 0xfffd, b' ');
+    let mut jpeg = rotated_jpeg(40, 20, LOCATION);
+    jpeg.splice(2..2, comment.repeat(51 * 1024 * 1024 / comment.len() + 1));
+    let file_id = upload(&app, &fx, "huge.jpg", "image/jpeg", jpeg).await;
+    let id = rustchat::mattermost_compat::id::parse_mm_or_uuid(&file_id).unwrap();
+
+    let mut media_status = None;
+    for _ in 0..600 {
+        media_status = sqlx::query_scalar("SELECT media_status FROM files WHERE id = $1")
+            .bind(id)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+        if media_status.as_deref() != Some("pending")
+            && media_status.as_deref() != Some("processing")
+        {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(Some("processed".to_string()), media_status);
+
+    let res = app
+        .api_client
+        .get(format!("{}/api/v4/files/{}", &app.address, file_id))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_redirection());
+    let location = res.headers()["location"].to_str().unwrap().to_string();
+    let original = app
+        .api_client
+        .get(format!("{}{}", &app.address, location))
+        .send()
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    assert!(!original
+        .windows(LOCATION.len())
+        .any(|window| window == LOCATION));
+
+    // Still turned by viewers, though not decoded here
+    let mut decoder = ImageReader::new(Cursor::new(&original[..]))
+        .with_guessed_format()
+        .unwrap()
+        .into_decoder()
+        .unwrap();
+    let exif = decoder.exif_metadata().unwrap().unwrap();
+    assert_eq!(
+        Some(Orientation::Rotate90),
+        Orientation::from_exif_chunk(&exif)
+    );
+
+    let has_thumbnail: bool = sqlx::query_scalar("SELECT has_thumbnail FROM files WHERE id = $1")
+        .bind(id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(!has_thumbnail);
+}
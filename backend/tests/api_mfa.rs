@@ -0,0 +1,341 @@
+use crate::common::{setup_channel_member, spawn_app, TestApp};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha1::Sha1;
+
+mod common;
+
+/// TOTP code for the current time step plus `offset`
+fn totp(secret: &str, offset: i64) -> String {
+    let key = data_encoding::BASE32_NOPAD
+        .decode(secret.as_bytes())
+        .unwrap();
+    let step = (chrono::Utc::now().timestamp() / 30 + offset) as u64;
+
+    let mut mac = Hmac::<Sha1>::new_from_slice(&key).unwrap();
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[19] & 0x0f) as usize;
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    format!("{:06}", value % 1_000_000)
+}
+
+async fn login(app: &TestApp, email: &str, mfa_token: Option<&str>) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&json!({ "email": email, "password": "Password123!", "mfa_token": mfa_token }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn post(app: &TestApp, token: &str, path: &str, body: Value) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}{}", &app.address, path))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get(app: &TestApp, token: &str, path: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn mfa_enrollment_guards_logins() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "mfa_user").await;
+    let email = "mfa_user@example.com";
+
+    let res = post(&app, &fx.token, "/api/v1/auth/mfa/generate", json!({})).await;
+    assert_eq!(200, res.status().as_u16());
+    let generated: Value = res.json().await.unwrap();
+    let secret = generated["secret"].as_str().unwrap().to_string();
+    let uri = generated["uri"].as_str().unwrap();
+    assert!(uri.starts_with("otpauth://totp/"));
+    assert!(uri.contains(&format!("secret={}", secret)));
+    let png = base64::engine::general_purpose::STANDARD
+        .decode(generated["qr_code"].as_str().unwrap())
+        .unwrap();
+    assert_eq!(b"\x89PNG", &png[..4]);
+
+    // The secret only counts once it is confirmed
+    assert_eq!(200, login(&app, email, None).await.status().as_u16());
+
+    let res = post(
+        &app,
+        &fx.token,
+        "/api/v1/auth/mfa/activate",
+        json!({ "code": "000000" }),
+    )
+    .await;
+    assert_eq!(400, res.status().as_u16());
+    let first = totp(&secret, 0);
+    let res = post(
+        &app,
+        &fx.token,
+        "/api/v1/auth/mfa/activate",
+        json!({ "code": first }),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+    let body: Value = res.json().await.unwrap();
+    let recovery: Vec<String> = body["recovery_codes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c.as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(10, recovery.len());
+
+    let stored: String = sqlx::query_scalar("SELECT mfa_secret_encrypted FROM users WHERE id = $1")
+        .bind(fx.user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_ne!(secret, stored);
+
+    let me: Value = get(&app, &fx.token, "/api/v1/auth/me")
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(true, me["mfa_active"]);
+
+    assert_eq!(401, login(&app, email, None).await.status().as_u16());
+    assert_eq!(
+        401,
+        login(&app, email, Some("123456")).await.status().as_u16()
+    );
+
+    // Codes work once, the one used to activate included
+    assert_eq!(
+        401,
+        login(&app, email, Some(&first)).await.status().as_u16()
+    );
+    let next = totp(&secret, 1);
+    assert_eq!(200, login(&app, email, Some(&next)).await.status().as_u16());
+    assert_eq!(401, login(&app, email, Some(&next)).await.status().as_u16());
+
+    let code = recovery[0].to_uppercase();
+    assert_eq!(200, login(&app, email, Some(&code)).await.status().as_u16());
+    assert_eq!(401, login(&app, email, Some(&code)).await.status().as_u16());
+
+    // Mattermost clients ask first and send the code as `token`
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/users/mfa", &app.address))
+        .json(&json!({ "login_id": "mfa_user" }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = res.json().await.unwrap();
+    assert_eq!(true, body["mfa_required"]);
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/users/login", &app.address))
+        .json(&json!({ "login_id": "mfa_user", "password": "Password123!" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(401, res.status().as_u16());
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/users/login", &app.address))
+        .json(&json!({
+            "login_id": "mfa_user",
+            "password": "Password123!",
+            "token": recovery[1]
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    let mm_user: Value = res.json().await.unwrap();
+    assert_eq!(true, mm_user["mfa_active"]);
+
+    // Admins can reset a user who lost their authenticator
+    let admin = setup_channel_member(&app, "mfa_admin").await;
+    let reset = format!("/api/v1/admin/users/{}/mfa/reset", fx.user_id);
+    assert_eq!(
+        403,
+        post(&app, &admin.token, &reset, json!({}))
+            .await
+            .status()
+            .as_u16()
+    );
+    sqlx::query("UPDATE users SET role = 'system_admin' WHERE id = $1")
+        .bind(admin.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let res = login(&app, "mfa_admin@example.com", None).await;
+    let body: Value = res.json().await.unwrap();
+    let admin_token = body["token"].as_str().unwrap();
+    assert_eq!(
+        200,
+        post(&app, admin_token, &reset, json!({}))
+            .await
+            .status()
+            .as_u16()
+    );
+    assert_eq!(200, login(&app, email, None).await.status().as_u16());
+    let remaining: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM mfa_recovery_codes WHERE user_id = $1")
+            .bind(fx.user_id)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(0, remaining);
+}
+
+#[tokio::test]
+async fn enforced_mfa_blocks_until_enrolled() {
+    let app = spawn_app().await;
+    setup_channel_member(&app, "enforced").await;
+    sqlx::query(
+        r#"
+        UPDATE server_config
+        SET authentication = authentication || '{"enforce_mfa": true}'::jsonb
+        WHERE id = 'default'
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let res = login(&app, "enforced@example.com", None).await;
+    assert_eq!(200, res.status().as_u16());
+    let body: Value = res.json().await.unwrap();
+    let token = body["token"].as_str().unwrap();
+
+    assert_eq!(
+        403,
+        get(&app, token, "/api/v4/users/me/teams")
+            .await
+            .status()
+            .as_u16()
+    );
+    assert_eq!(
+        403,
+        get(&app, token, "/api/v1/teams").await.status().as_u16()
+    );
+    assert_eq!(
+        200,
+        get(&app, token, "/api/v4/users/me").await.status().as_u16()
+    );
+
+    // Enrolling through the Mattermost endpoints lifts the block
+    let res = post(&app, token, "/api/v4/users/me/mfa/generate", json!({})).await;
+    assert_eq!(200, res.status().as_u16());
+    let generated: Value = res.json().await.unwrap();
+    let secret = generated["secret"].as_str().unwrap();
+    assert!(!generated["qr_code"].as_str().unwrap().is_empty());
+    let code = totp(secret, 0);
+
+    let res = app
+        .api_client
+        .put(format!("{}/api/v4/users/me/mfa", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&json!({ "activate": true, "code": code }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+
+    assert_eq!(
+        200,
+        get(&app, token, "/api/v4/users/me/teams")
+            .await
+            .status()
+            .as_u16()
+    );
+    assert_eq!(
+        200,
+        get(&app, token, "/api/v1/teams").await.status().as_u16()
+    );
+
+    // Turning it off again needs an unused code
+    let res = app
+        .api_client
+        .put(format!("{}/api/v4/users/me/mfa", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&json!({ "activate": false, "code": code }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(400, res.status().as_u16());
+
+    // Once it is off the block is back, though the session check is cached
+    let res = app
+        .api_client
+        .put(format!("{}/api/v4/users/me/mfa", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&json!({ "activate": false, "code": totp(secret, 1) }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    assert_eq!(
+        403,
+        get(&app, token, "/api/v1/teams").await.status().as_u16()
+    );
+}
+
+#[tokio::test]
+async fn enforcing_mfa_takes_effect_at_once() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "unenrolled").await;
+    let admin = setup_channel_member(&app, "mfa_admin").await;
+    sqlx::query("UPDATE users SET role = 'system_admin' WHERE id = $1")
+        .bind(admin.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let res = login(&app, "mfa_admin@example.com", None).await;
+    let body: Value = res.json().await.unwrap();
+    let admin_token = body["token"].as_str().unwrap().to_string();
+
+    // Loads the setting while it is still off
+    assert_eq!(
+        200,
+        get(&app, &fx.token, "/api/v1/teams").await.status().as_u16()
+    );
+
+    let mut authentication: Value =
+        sqlx::query_scalar("SELECT authentication FROM server_config WHERE id = 'default'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    authentication["enforce_mfa"] = json!(true);
+    let res = app
+        .api_client
+        .patch(format!(
+            "{}/api/v1/admin/config/authentication",
+            &app.address
+        ))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .json(&authentication)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+
+    assert_eq!(
+        403,
+        get(&app, &fx.token, "/api/v1/teams").await.status().as_u16()
+    );
+}
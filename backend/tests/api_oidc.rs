@@ -0,0 +1,319 @@
+use crate::common::mock_idp::{spawn_mock_idp, MockIdp, CLIENT_ID, CLIENT_SECRET};
+use crate::common::{setup_channel_member, spawn_app, TestApp};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+mod common;
+
+/// Register the mock provider as the active OIDC config and turn SSO on
+async fn configure_sso(app: &TestApp, idp: &MockIdp, auto_provision: bool) -> Uuid {
+    let org_id: Uuid =
+        sqlx::query_scalar("INSERT INTO organizations (name) VALUES ('sso-org') RETURNING id")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    let secret = rustchat::crypto::encrypt(CLIENT_SECRET, &app.state.encryption_key);
+    let config_id: Uuid = sqlx::query_scalar(
+        r#"
+        INSERT INTO sso_configs
+            (org_id, provider, issuer_url, client_id, client_secret_encrypted, is_active, auto_provision)
+        VALUES ($1, 'oidc', $2, $3, $4, true, $5)
+        RETURNING id
+        "#,
+    )
+    .bind(org_id)
+    .bind(&idp.issuer)
+    .bind(CLIENT_ID)
+    .bind(secret)
+    .bind(auto_provision)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"
+        UPDATE server_config
+        SET authentication = authentication || '{"enable_sso": true}'::jsonb,
+            site = site || jsonb_build_object('site_url', $1::text)
+        WHERE id = 'default'
+        "#,
+    )
+    .bind(&app.address)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    config_id
+}
+
+fn location(response: &reqwest::Response) -> String {
+    response.headers()["location"].to_str().unwrap().to_string()
+}
+
+/// Follow a login from its authorization URL to the callback's redirect
+async fn finish_login(app: &TestApp, authorization_url: &str) -> String {
+    let authorize = app.api_client.get(authorization_url).send().await.unwrap();
+    assert!(authorize.status().is_redirection());
+    let callback = app
+        .api_client
+        .get(location(&authorize))
+        .send()
+        .await
+        .unwrap();
+    assert!(callback.status().is_redirection());
+    location(&callback)
+}
+
+/// Run a full SSO login and return where the browser lands
+async fn sso_login(app: &TestApp) -> String {
+    let login = app
+        .api_client
+        .get(format!("{}/api/v1/oauth2/oidc/login", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert!(login.status().is_redirection());
+    finish_login(app, &location(&login)).await
+}
+
+fn session_token(landing: &str) -> String {
+    assert!(
+        landing.starts_with("/oauth/callback?token="),
+        "unexpected redirect {}",
+        landing
+    );
+    landing["/oauth/callback?token=".len()..].to_string()
+}
+
+async fn me(app: &TestApp, token: &str) -> Value {
+    app.api_client
+        .get(format!("{}/api/v1/auth/me", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn oidc_login_provisions_and_reuses_the_account() {
+    let app = spawn_app().await;
+    let idp = spawn_mock_idp().await;
+    let config_id = configure_sso(&app, &idp, true).await;
+    idp.sign_in_as(json!({
+        "sub": "subject-1",
+        "email": "jane@corp.example.com",
+        "email_verified": true,
+        "preferred_username": "Jane.Doe",
+        "name": "Jane Doe"
+    }));
+
+    let providers: Value = app
+        .api_client
+        .get(format!("{}/api/v1/oauth2/providers", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!("oidc", providers[0]["id"]);
+
+    let token = session_token(&sso_login(&app).await);
+    let user = me(&app, &token).await;
+    assert_eq!("jane.doe", user["username"]);
+    assert_eq!("jane@corp.example.com", user["email"]);
+    assert_eq!("Jane Doe", user["display_name"]);
+
+    // The same subject signs in to the same account, even with a new email
+    idp.sign_in_as(json!({
+        "sub": "subject-1",
+        "email": "jane.doe@corp.example.com",
+        "preferred_username": "someone-else"
+    }));
+    let token = session_token(&sso_login(&app).await);
+    assert_eq!(user["id"], me(&app, &token).await["id"]);
+
+    let identities: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM user_identities WHERE sso_config_id = $1")
+            .bind(config_id)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(1, identities);
+
+    // Provisioned accounts have no password to sign in with
+    let password_login = app
+        .api_client
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&json!({ "email": "jane@corp.example.com", "password": "NO_PASSWORD" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(401, password_login.status().as_u16());
+}
+
+#[tokio::test]
+async fn oidc_callback_rejects_forged_logins() {
+    let app = spawn_app().await;
+    let idp = spawn_mock_idp().await;
+    configure_sso(&app, &idp, true).await;
+    idp.sign_in_as(json!({ "sub": "subject-2", "email": "sam@corp.example.com" }));
+
+    // A callback without the state cookie of the browser that started it
+    let login = app
+        .api_client
+        .get(format!("{}/api/v1/oauth2/oidc/login", &app.address))
+        .send()
+        .await
+        .unwrap();
+    let authorize = app.api_client.get(location(&login)).send().await.unwrap();
+    let callback = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+        .get(location(&authorize))
+        .send()
+        .await
+        .unwrap();
+    assert!(location(&callback).starts_with("/login?error="));
+
+    // ID tokens for another client
+    idp.state.lock().unwrap().audience = Some("another-client".to_string());
+    assert!(sso_login(&app)
+        .await
+        .starts_with("/login?error=Invalid%20ID%20token"));
+
+    // ID tokens replayed from another login
+    idp.state.lock().unwrap().audience = None;
+    idp.state.lock().unwrap().nonce = Some("stale-nonce".to_string());
+    assert!(sso_login(&app)
+        .await
+        .starts_with("/login?error=Invalid%20ID%20token"));
+
+    let users: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE email = 'sam@corp.example.com'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(0, users);
+}
+
+#[tokio::test]
+async fn existing_accounts_link_their_identity_explicitly() {
+    let app = spawn_app().await;
+    let idp = spawn_mock_idp().await;
+    configure_sso(&app, &idp, true).await;
+    let fx = setup_channel_member(&app, "oidc_linker").await;
+    idp.sign_in_as(json!({
+        "sub": "subject-3",
+        "email": "oidc_linker@example.com",
+        "email_verified": true
+    }));
+
+    // A matching email alone must not take over the account
+    let landing = sso_login(&app).await;
+    assert!(landing.starts_with("/login?error=An%20account%20with%20this%20email"));
+
+    // The signed-in owner links the identity instead
+    let link: Value = app
+        .api_client
+        .post(format!("{}/api/v1/oauth2/oidc/link", &app.address))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let landing = finish_login(&app, link["authorization_url"].as_str().unwrap()).await;
+    assert_eq!("/settings/profile", landing);
+
+    let token = session_token(&sso_login(&app).await);
+    assert_eq!(fx.user_id.to_string(), me(&app, &token).await["id"]);
+
+    let identities: Value = app
+        .api_client
+        .get(format!("{}/api/v1/oauth2/identities", &app.address))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!("subject-3", identities[0]["subject"]);
+
+    // The account still has its password, so the identity can go
+    let unlink = app
+        .api_client
+        .delete(format!(
+            "{}/api/v1/oauth2/identities/{}",
+            &app.address,
+            identities[0]["id"].as_str().unwrap()
+        ))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, unlink.status().as_u16());
+}
+
+#[tokio::test]
+async fn sso_policies_restrict_logins() {
+    let app = spawn_app().await;
+    let idp = spawn_mock_idp().await;
+    configure_sso(&app, &idp, false).await;
+    setup_channel_member(&app, "oidc_member").await;
+
+    // Without auto-provisioning unknown identities are turned away
+    idp.sign_in_as(json!({ "sub": "subject-4", "email": "new@corp.example.com" }));
+    assert!(sso_login(&app)
+        .await
+        .starts_with("/login?error=No%20account"));
+
+    sqlx::query(
+        r#"
+        UPDATE server_config
+        SET authentication = authentication || '{"require_sso": true}'::jsonb
+        WHERE id = 'default'
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let password_login = |username: &'static str| {
+        app.api_client
+            .post(format!("{}/api/v1/auth/login", &app.address))
+            .json(&json!({
+                "email": format!("{}@example.com", username),
+                "password": "Password123!"
+            }))
+            .send()
+    };
+    assert_eq!(
+        401,
+        password_login("oidc_member")
+            .await
+            .unwrap()
+            .status()
+            .as_u16()
+    );
+
+    // Admins keep password login in case the provider breaks
+    sqlx::query("UPDATE users SET role = 'system_admin' WHERE username = 'oidc_member'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(
+        200,
+        password_login("oidc_member")
+            .await
+            .unwrap()
+            .status()
+            .as_u16()
+    );
+}
@@ -0,0 +1,109 @@
+use crate::common::{setup_channel_member, spawn_app};
+use axum::{routing::post, Json, Router};
+use rustchat::mattermost_compat::id::encode_mm_id;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+
+mod common;
+
+/// Spawn a callback server that records payloads and answers with a threaded reply
+async fn spawn_callback() -> (String, Arc<Mutex<Vec<Value>>>) {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let recorder = received.clone();
+
+    let app = Router::new().route(
+        "/hook",
+        post(move |Json(payload): Json<Value>| {
+            let recorder = recorder.clone();
+            async move {
+                recorder.lock().unwrap().push(payload);
+                Json(serde_json::json!({
+                    "text": "pong",
+                    "username": "pinger",
+                    "response_type": "comment"
+                }))
+            }
+        }),
+    );
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    (format!("http://127.0.0.1:{}/hook", port), received)
+}
+
+#[tokio::test]
+async fn outgoing_webhook_fires_on_trigger_word_and_posts_reply() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "webhooker").await;
+    let auth = format!("Bearer {}", fx.token);
+    let (callback_url, received) = spawn_callback().await;
+
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/hooks/outgoing", &app.address))
+        .header("Authorization", &auth)
+        .json(&serde_json::json!({
+            "team_id": encode_mm_id(fx.team_id),
+            "display_name": "Pinger",
+            "description": "",
+            "trigger_words": ["ping"],
+            "trigger_when": 0,
+            "callback_urls": [callback_url],
+            "content_type": "application/json"
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+
+    for message in ["pinging nobody", "ping everyone"] {
+        let res = app
+            .api_client
+            .post(format!("{}/api/v4/posts", &app.address))
+            .header("Authorization", &auth)
+            .json(&serde_json::json!({
+                "channel_id": encode_mm_id(fx.channel_id),
+                "message": message
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(200, res.status().as_u16());
+    }
+
+    let trigger_id: Uuid =
+        sqlx::query_scalar("SELECT id FROM posts WHERE message = 'ping everyone'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+
+    let mut reply: Option<(Option<Uuid>, Value)> = None;
+    for _ in 0..50 {
+        reply = sqlx::query_as("SELECT root_post_id, props FROM posts WHERE message = 'pong'")
+            .fetch_optional(&app.db_pool)
+            .await
+            .unwrap();
+        if reply.is_some() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let (root_post_id, props) = reply.expect("Webhook reply was not posted");
+    assert_eq!(Some(trigger_id), root_post_id);
+    assert_eq!("true", props["from_webhook"]);
+    assert_eq!("pinger", props["override_username"]);
+
+    let received = received.lock().unwrap();
+    assert_eq!(1, received.len());
+    assert_eq!("ping", received[0]["trigger_word"]);
+    assert_eq!("ping everyone", received[0]["text"]);
+    assert_eq!(encode_mm_id(trigger_id), received[0]["post_id"]);
+    assert_eq!("webhooker", received[0]["user_name"]);
+}
@@ -0,0 +1,1085 @@
+use crate::common::{add_channel_member, setup_channel_member, spawn_app, TestApp};
+use serde_json::json;
+use uuid::Uuid;
+
+mod common;
+
+async fn send(
+    app: &TestApp,
+    method: reqwest::Method,
+    path: &str,
+    token: &str,
+    body: Option<serde_json::Value>,
+) -> reqwest::Response {
+    let mut req = app
+        .api_client
+        .request(method, format!("{}{}", &app.address, path))
+        .header("Authorization", format!("Bearer {}", token));
+    if let Some(body) = body {
+        req = req.json(&body);
+    }
+    req.send().await.unwrap()
+}
+
+async fn set_role(app: &TestApp, user_id: Uuid, role: &str) -> String {
+    sqlx::query("UPDATE users SET role = $2 WHERE id = $1")
+        .bind(user_id)
+        .bind(role)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let username: String = sqlx::query_scalar("SELECT username FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Log in again so the token carries the new role
+    let login: serde_json::Value = app
+        .api_client
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&json!({
+            "email": format!("{}@example.com", username),
+            "password": "Password123!"
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    login["token"].as_str().unwrap().to_string()
+}
+
+async fn create_post(app: &TestApp, channel_id: Uuid, token: &str) -> String {
+    let res = send(
+        app,
+        reqwest::Method::POST,
+        &format!("/api/v1/channels/{}/posts", channel_id),
+        token,
+        Some(json!({ "message": "hello" })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+    let post: serde_json::Value = res.json().await.unwrap();
+    post["id"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn channel_members_manage_their_channel() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, _) = add_channel_member(&app, &fx, "bob").await;
+    let (carol, carol_id) = add_channel_member(&app, &fx, "carol").await;
+    leave_channel(&app, fx.channel_id, carol_id).await;
+    let channel = format!("/api/v1/channels/{}", fx.channel_id);
+    let rename = json!({ "display_name": "Renamed" });
+
+    // Team members outside the channel cannot change it
+    let res = send(
+        &app,
+        reqwest::Method::PUT,
+        &channel,
+        &carol,
+        Some(rename.clone()),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert!(body["error"]["message"]
+        .as_str()
+        .unwrap()
+        .contains("channel.manage"));
+    let kick_alice = format!("{}/members/{}", channel, fx.user_id);
+    let res = send(&app, reqwest::Method::DELETE, &kick_alice, &carol, None).await;
+    assert_eq!(403, res.status().as_u16());
+
+    let res = send(&app, reqwest::Method::PUT, &channel, &bob, Some(rename)).await;
+    assert_eq!(200, res.status().as_u16());
+    let res = send(&app, reqwest::Method::DELETE, &kick_alice, &bob, None).await;
+    assert_eq!(200, res.status().as_u16());
+
+    // Deleting the channel is for its admins
+    let res = send(&app, reqwest::Method::DELETE, &channel, &bob, None).await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn channel_admins_manage_only_their_channel() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, bob_id) = add_channel_member(&app, &fx, "bob").await;
+    make_channel_admin(&app, fx.channel_id, bob_id).await;
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        "/api/v1/channels",
+        &fx.token,
+        Some(json!({ "team_id": fx.team_id, "name": "other", "display_name": "Other", "channel_type": "public" })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+    let other: serde_json::Value = res.json().await.unwrap();
+    let res = send(
+        &app,
+        reqwest::Method::DELETE,
+        &format!("/api/v1/channels/{}", other["id"].as_str().unwrap()),
+        &bob,
+        None,
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+
+    let res = send(
+        &app,
+        reqwest::Method::DELETE,
+        &format!("/api/v1/channels/{}", fx.channel_id),
+        &bob,
+        None,
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn team_admins_delete_other_users_posts() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, _) = add_channel_member(&app, &fx, "bob").await;
+    let post_id = create_post(&app, fx.channel_id, &bob).await;
+    let path = format!("/api/v1/posts/{}", post_id);
+
+    let res = send(&app, reqwest::Method::DELETE, &path, &fx.token, None).await;
+    assert_eq!(403, res.status().as_u16());
+
+    sqlx::query("UPDATE team_members SET role = 'admin' WHERE team_id = $1 AND user_id = $2")
+        .bind(fx.team_id)
+        .bind(fx.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let res = send(&app, reqwest::Method::DELETE, &path, &fx.token, None).await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn guests_cannot_create_channels_or_bots() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "guest").await;
+    sqlx::query(
+        "UPDATE server_config SET authentication = authentication || '{\"enable_guest_accounts\": true}' WHERE id = 'default'",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let token = set_role(&app, fx.user_id, "guest").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        "/api/v1/channels",
+        &token,
+        Some(json!({ "team_id": fx.team_id, "name": "nope", "display_name": "Nope", "channel_type": "public" })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        "/api/v4/bots",
+        &token,
+        Some(json!({ "username": "guestbot", "display_name": "Guest Bot" })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+
+    // Their own posts are still theirs to delete
+    let post_id = create_post(&app, fx.channel_id, &token).await;
+    let res = send(
+        &app,
+        reqwest::Method::DELETE,
+        &format!("/api/v1/posts/{}", post_id),
+        &token,
+        None,
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn role_editor_changes_apply_immediately() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (_, admin_id) = add_channel_member(&app, &fx, "admin").await;
+    let admin = set_role(&app, admin_id, "system_admin").await;
+
+    let post_id = create_post(&app, fx.channel_id, &fx.token).await;
+    let pin = format!("/api/v1/posts/{}/pin", post_id);
+    let res = send(&app, reqwest::Method::POST, &pin, &fx.token, None).await;
+    assert_eq!(200, res.status().as_u16());
+
+    let res = send(
+        &app,
+        reqwest::Method::PUT,
+        "/api/v1/admin/roles/member/permissions",
+        &admin,
+        Some(json!({ "permissions": ["post.create", "post.edit_own", "post.delete_own"] })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+
+    let res = send(&app, reqwest::Method::DELETE, &pin, &fx.token, None).await;
+    assert_eq!(403, res.status().as_u16());
+    // System admins keep every permission whatever the editor says
+    let res = send(&app, reqwest::Method::DELETE, &pin, &admin, None).await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+/// Give a role a permission, as the role editor would
+async fn grant(app: &TestApp, role: &str, permission: &str) {
+    sqlx::query(
+        "INSERT INTO role_permissions (role, permission_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+    )
+    .bind(role)
+    .bind(permission)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    app.state.permissions.invalidate(role).await;
+}
+
+/// Take a permission away from a role, as the role editor would
+async fn revoke(app: &TestApp, role: &str, permission: &str) {
+    sqlx::query("DELETE FROM role_permissions WHERE role = $1 AND permission_id = $2")
+        .bind(role)
+        .bind(permission)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.state.permissions.invalidate(role).await;
+}
+
+async fn make_team_admin(app: &TestApp, team_id: Uuid, user_id: Uuid) {
+    sqlx::query("UPDATE team_members SET role = 'admin' WHERE team_id = $1 AND user_id = $2")
+        .bind(team_id)
+        .bind(user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+async fn make_channel_admin(app: &TestApp, channel_id: Uuid, user_id: Uuid) {
+    sqlx::query("UPDATE channel_members SET role = 'admin' WHERE channel_id = $1 AND user_id = $2")
+        .bind(channel_id)
+        .bind(user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+async fn leave_channel(app: &TestApp, channel_id: Uuid, user_id: Uuid) {
+    sqlx::query("DELETE FROM channel_members WHERE channel_id = $1 AND user_id = $2")
+        .bind(channel_id)
+        .bind(user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+/// Create something and return its ID
+async fn create(app: &TestApp, path: &str, token: &str, body: serde_json::Value) -> String {
+    let res = send(app, reqwest::Method::POST, path, token, Some(body)).await;
+    assert_eq!(200, res.status().as_u16(), "POST {}", path);
+    let created: serde_json::Value = res.json().await.unwrap();
+    created["id"].as_str().unwrap().to_string()
+}
+
+fn new_channel(team_id: Uuid, name: &str) -> serde_json::Value {
+    json!({ "team_id": team_id, "name": name, "display_name": name, "channel_type": "public" })
+}
+
+// ============ Channels ============
+
+#[tokio::test]
+async fn channel_create_is_allowed_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        "/api/v1/channels",
+        &fx.token,
+        Some(new_channel(fx.team_id, "mine")),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn channel_create_is_denied_without_permission() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    revoke(&app, "member", "channel.create").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        "/api/v1/channels",
+        &fx.token,
+        Some(new_channel(fx.team_id, "mine")),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn channel_delete_is_allowed_to_channel_admins() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    make_channel_admin(&app, fx.channel_id, fx.user_id).await;
+
+    let path = format!("/api/v1/channels/{}", fx.channel_id);
+    let res = send(&app, reqwest::Method::DELETE, &path, &fx.token, None).await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn channel_delete_is_denied_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    let path = format!("/api/v1/channels/{}", fx.channel_id);
+    let res = send(&app, reqwest::Method::DELETE, &path, &fx.token, None).await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn channel_member_add_is_allowed_to_channel_admins() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (_, bob_id) = add_channel_member(&app, &fx, "bob").await;
+    let private = create(
+        &app,
+        "/api/v1/channels",
+        &fx.token,
+        json!({ "team_id": fx.team_id, "name": "private", "display_name": "Private", "channel_type": "private" }),
+    )
+    .await;
+    let private_id = Uuid::parse_str(&private).unwrap();
+    make_channel_admin(&app, private_id, fx.user_id).await;
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &format!("/api/v1/channels/{}/members", private_id),
+        &fx.token,
+        Some(json!({ "user_id": bob_id })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn channel_member_add_is_allowed_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, _) = add_channel_member(&app, &fx, "bob").await;
+    let (_, carol_id) = add_channel_member(&app, &fx, "carol").await;
+    leave_channel(&app, fx.channel_id, carol_id).await;
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &format!("/api/v1/channels/{}/members", fx.channel_id),
+        &bob,
+        Some(json!({ "user_id": carol_id })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn channel_member_add_is_denied_to_non_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, bob_id) = add_channel_member(&app, &fx, "bob").await;
+    let (_, carol_id) = add_channel_member(&app, &fx, "carol").await;
+    leave_channel(&app, fx.channel_id, bob_id).await;
+    leave_channel(&app, fx.channel_id, carol_id).await;
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &format!("/api/v1/channels/{}/members", fx.channel_id),
+        &bob,
+        Some(json!({ "user_id": carol_id })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn channel_roles_are_set_only_by_channel_admins() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, _) = add_channel_member(&app, &fx, "bob").await;
+    let (_, carol_id) = add_channel_member(&app, &fx, "carol").await;
+    make_channel_admin(&app, fx.channel_id, fx.user_id).await;
+    let members = format!("/api/v1/channels/{}/members", fx.channel_id);
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &members,
+        &bob,
+        Some(json!({ "user_id": carol_id, "role": "admin" })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+
+    // Adding an admin again leaves them an admin
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &members,
+        &bob,
+        Some(json!({ "user_id": fx.user_id })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!("admin", body["role"]);
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &members,
+        &fx.token,
+        Some(json!({ "user_id": carol_id, "role": "admin" })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn public_channels_are_joined_only_from_their_team() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, bob_id) = add_channel_member(&app, &fx, "bob").await;
+    let (mallory, mallory_id) = add_channel_member(&app, &fx, "mallory").await;
+    leave_channel(&app, fx.channel_id, bob_id).await;
+    leave_channel(&app, fx.channel_id, mallory_id).await;
+    sqlx::query("DELETE FROM team_members WHERE user_id = $1")
+        .bind(mallory_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let v1 = format!("/api/v1/channels/{}/members", fx.channel_id);
+    let v4 = format!("/api/v4/channels/{}/members", fx.channel_id);
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &v1,
+        &mallory,
+        Some(json!({ "user_id": mallory_id })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &v4,
+        &mallory,
+        Some(json!({ "user_id": mallory_id.to_string() })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+
+    // Nor can members bring in people from outside the team
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &v1,
+        &fx.token,
+        Some(json!({ "user_id": mallory_id })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &v4,
+        &bob,
+        Some(json!({ "user_id": bob_id.to_string() })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+// ============ Posts ============
+
+#[tokio::test]
+async fn post_create_is_allowed_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    create_post(&app, fx.channel_id, &fx.token).await;
+}
+
+#[tokio::test]
+async fn post_create_is_denied_without_permission() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    revoke(&app, "member", "post.create").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &format!("/api/v1/channels/{}/posts", fx.channel_id),
+        &fx.token,
+        Some(json!({ "message": "hello" })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn post_edit_own_is_allowed_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let post_id = create_post(&app, fx.channel_id, &fx.token).await;
+
+    let res = send(
+        &app,
+        reqwest::Method::PUT,
+        &format!("/api/v1/posts/{}", post_id),
+        &fx.token,
+        Some(json!({ "message": "edited" })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn post_edit_own_is_denied_without_permission() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let post_id = create_post(&app, fx.channel_id, &fx.token).await;
+    revoke(&app, "member", "post.edit_own").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::PUT,
+        &format!("/api/v1/posts/{}", post_id),
+        &fx.token,
+        Some(json!({ "message": "edited" })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn post_edit_others_is_allowed_when_granted() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, _) = add_channel_member(&app, &fx, "bob").await;
+    let post_id = create_post(&app, fx.channel_id, &bob).await;
+    grant(&app, "member", "post.edit_others").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::PUT,
+        &format!("/api/v1/posts/{}", post_id),
+        &fx.token,
+        Some(json!({ "message": "edited" })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn post_edit_others_is_denied_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, _) = add_channel_member(&app, &fx, "bob").await;
+    let post_id = create_post(&app, fx.channel_id, &bob).await;
+
+    let res = send(
+        &app,
+        reqwest::Method::PUT,
+        &format!("/api/v1/posts/{}", post_id),
+        &fx.token,
+        Some(json!({ "message": "edited" })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn post_delete_own_is_allowed_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let post_id = create_post(&app, fx.channel_id, &fx.token).await;
+
+    let path = format!("/api/v1/posts/{}", post_id);
+    let res = send(&app, reqwest::Method::DELETE, &path, &fx.token, None).await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn post_delete_own_is_denied_without_permission() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let post_id = create_post(&app, fx.channel_id, &fx.token).await;
+    revoke(&app, "member", "post.delete_own").await;
+
+    let path = format!("/api/v1/posts/{}", post_id);
+    let res = send(&app, reqwest::Method::DELETE, &path, &fx.token, None).await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn post_pin_and_unpin_are_allowed_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let post_id = create_post(&app, fx.channel_id, &fx.token).await;
+    let pin = format!("/api/v1/posts/{}/pin", post_id);
+
+    let res = send(&app, reqwest::Method::POST, &pin, &fx.token, None).await;
+    assert_eq!(200, res.status().as_u16());
+    let res = send(&app, reqwest::Method::DELETE, &pin, &fx.token, None).await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn post_pin_and_unpin_are_denied_without_permission() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let post_id = create_post(&app, fx.channel_id, &fx.token).await;
+    let pin = format!("/api/v1/posts/{}/pin", post_id);
+    let res = send(&app, reqwest::Method::POST, &pin, &fx.token, None).await;
+    assert_eq!(200, res.status().as_u16());
+    revoke(&app, "member", "post.pin").await;
+
+    let res = send(&app, reqwest::Method::DELETE, &pin, &fx.token, None).await;
+    assert_eq!(403, res.status().as_u16());
+    let res = send(&app, reqwest::Method::POST, &pin, &fx.token, None).await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+// ============ Users and teams ============
+
+#[tokio::test]
+async fn user_manage_is_allowed_when_granted() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, _) = add_channel_member(&app, &fx, "bob").await;
+    grant(&app, "member", "user.manage").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::PUT,
+        &format!("/api/v1/users/{}", fx.user_id),
+        &bob,
+        Some(json!({ "display_name": "Alice A." })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn user_manage_is_denied_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, _) = add_channel_member(&app, &fx, "bob").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::PUT,
+        &format!("/api/v1/users/{}", fx.user_id),
+        &bob,
+        Some(json!({ "display_name": "Alice A." })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn team_create_is_allowed_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        "/api/v1/teams",
+        &fx.token,
+        Some(json!({ "name": "second", "display_name": "Second" })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn team_create_is_denied_without_permission() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    revoke(&app, "member", "team.create").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        "/api/v1/teams",
+        &fx.token,
+        Some(json!({ "name": "second", "display_name": "Second" })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn team_manage_is_allowed_to_team_admins() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    make_team_admin(&app, fx.team_id, fx.user_id).await;
+
+    let res = send(
+        &app,
+        reqwest::Method::PUT,
+        &format!("/api/v1/teams/{}", fx.team_id),
+        &fx.token,
+        Some(json!({ "display_name": "Renamed" })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn team_manage_is_denied_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::PUT,
+        &format!("/api/v1/teams/{}", fx.team_id),
+        &fx.token,
+        Some(json!({ "display_name": "Renamed" })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn team_member_add_and_remove_are_allowed_to_team_admins() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (_, bob_id) = add_channel_member(&app, &fx, "bob").await;
+    make_team_admin(&app, fx.team_id, fx.user_id).await;
+    let members = format!("/api/v1/teams/{}/members", fx.team_id);
+
+    let path = format!("{}/{}", members, bob_id);
+    let res = send(&app, reqwest::Method::DELETE, &path, &fx.token, None).await;
+    assert_eq!(200, res.status().as_u16());
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &members,
+        &fx.token,
+        Some(json!({ "user_id": bob_id })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn team_member_add_and_remove_are_denied_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (_, bob_id) = add_channel_member(&app, &fx, "bob").await;
+    let members = format!("/api/v1/teams/{}/members", fx.team_id);
+
+    let path = format!("{}/{}", members, bob_id);
+    let res = send(&app, reqwest::Method::DELETE, &path, &fx.token, None).await;
+    assert_eq!(403, res.status().as_u16());
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &members,
+        &fx.token,
+        Some(json!({ "user_id": bob_id })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+// ============ Integrations ============
+
+#[tokio::test]
+async fn webhook_create_is_allowed_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    create(
+        &app,
+        &format!("/api/v1/hooks/incoming?team_id={}", fx.team_id),
+        &fx.token,
+        json!({ "channel_id": fx.channel_id }),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn webhook_create_is_denied_without_permission() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    revoke(&app, "member", "webhook.create").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &format!("/api/v1/hooks/incoming?team_id={}", fx.team_id),
+        &fx.token,
+        Some(json!({ "channel_id": fx.channel_id })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn webhook_manage_is_allowed_to_team_admins() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, bob_id) = add_channel_member(&app, &fx, "bob").await;
+    let hook_id = create(
+        &app,
+        &format!("/api/v1/hooks/incoming?team_id={}", fx.team_id),
+        &fx.token,
+        json!({ "channel_id": fx.channel_id }),
+    )
+    .await;
+    make_team_admin(&app, fx.team_id, bob_id).await;
+
+    let path = format!("/api/v1/hooks/incoming/{}", hook_id);
+    let res = send(&app, reqwest::Method::DELETE, &path, &bob, None).await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn webhook_manage_is_denied_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, _) = add_channel_member(&app, &fx, "bob").await;
+    let hook_id = create(
+        &app,
+        &format!("/api/v1/hooks/incoming?team_id={}", fx.team_id),
+        &fx.token,
+        json!({ "channel_id": fx.channel_id }),
+    )
+    .await;
+
+    let path = format!("/api/v1/hooks/incoming/{}", hook_id);
+    let res = send(&app, reqwest::Method::DELETE, &path, &bob, None).await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+fn new_command(trigger: &str) -> serde_json::Value {
+    json!({ "trigger": trigger, "url": "https://example.com/command" })
+}
+
+#[tokio::test]
+async fn command_create_is_allowed_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    create(
+        &app,
+        &format!("/api/v1/commands?team_id={}", fx.team_id),
+        &fx.token,
+        new_command("deploy"),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn command_create_is_denied_without_permission() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    revoke(&app, "member", "command.create").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &format!("/api/v1/commands?team_id={}", fx.team_id),
+        &fx.token,
+        Some(new_command("deploy")),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn command_manage_is_allowed_to_team_admins() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, bob_id) = add_channel_member(&app, &fx, "bob").await;
+    let command_id = create(
+        &app,
+        &format!("/api/v1/commands?team_id={}", fx.team_id),
+        &fx.token,
+        new_command("deploy"),
+    )
+    .await;
+    make_team_admin(&app, fx.team_id, bob_id).await;
+
+    let path = format!("/api/v1/commands/{}", command_id);
+    let res = send(&app, reqwest::Method::DELETE, &path, &bob, None).await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn command_manage_is_denied_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, _) = add_channel_member(&app, &fx, "bob").await;
+    let command_id = create(
+        &app,
+        &format!("/api/v1/commands?team_id={}", fx.team_id),
+        &fx.token,
+        new_command("deploy"),
+    )
+    .await;
+
+    let path = format!("/api/v1/commands/{}", command_id);
+    let res = send(&app, reqwest::Method::DELETE, &path, &bob, None).await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn bot_create_is_allowed_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+
+    create(
+        &app,
+        "/api/v1/bots",
+        &fx.token,
+        json!({ "display_name": "Helper" }),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn bot_create_is_denied_without_permission() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    revoke(&app, "member", "bot.create").await;
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        "/api/v1/bots",
+        &fx.token,
+        Some(json!({ "display_name": "Helper" })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn bot_manage_is_allowed_when_granted() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, _) = add_channel_member(&app, &fx, "bob").await;
+    let bot_id = create(
+        &app,
+        "/api/v1/bots",
+        &fx.token,
+        json!({ "display_name": "Helper" }),
+    )
+    .await;
+    grant(&app, "member", "bot.manage").await;
+
+    let path = format!("/api/v1/bots/{}", bot_id);
+    let res = send(&app, reqwest::Method::DELETE, &path, &bob, None).await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn bot_manage_is_denied_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, _) = add_channel_member(&app, &fx, "bob").await;
+    let bot_id = create(
+        &app,
+        "/api/v1/bots",
+        &fx.token,
+        json!({ "display_name": "Helper" }),
+    )
+    .await;
+
+    let path = format!("/api/v1/bots/{}", bot_id);
+    let res = send(&app, reqwest::Method::DELETE, &path, &bob, None).await;
+    assert_eq!(403, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn playbook_manage_is_allowed_to_team_admins() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, bob_id) = add_channel_member(&app, &fx, "bob").await;
+    let playbook_id = create(
+        &app,
+        &format!("/api/v1/playbooks?team_id={}", fx.team_id),
+        &fx.token,
+        json!({ "name": "Incident" }),
+    )
+    .await;
+    make_team_admin(&app, fx.team_id, bob_id).await;
+
+    let path = format!("/api/v1/playbooks/{}", playbook_id);
+    let res = send(
+        &app,
+        reqwest::Method::PUT,
+        &path,
+        &bob,
+        Some(json!({ "name": "Outage" })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+    let res = send(&app, reqwest::Method::DELETE, &path, &bob, None).await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn playbook_manage_is_denied_to_members() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob, _) = add_channel_member(&app, &fx, "bob").await;
+    let playbook_id = create(
+        &app,
+        &format!("/api/v1/playbooks?team_id={}", fx.team_id),
+        &fx.token,
+        json!({ "name": "Incident" }),
+    )
+    .await;
+
+    let path = format!("/api/v1/playbooks/{}", playbook_id);
+    let res = send(
+        &app,
+        reqwest::Method::PUT,
+        &path,
+        &bob,
+        Some(json!({ "name": "Outage" })),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+    let res = send(&app, reqwest::Method::DELETE, &path, &bob, None).await;
+    assert_eq!(403, res.status().as_u16());
+}
@@ -0,0 +1,276 @@
+use crate::common::{setup_channel_member, spawn_app};
+use rustchat::jobs::post_reminders::run_post_reminders;
+use rustchat::mattermost_compat::id::encode_mm_id;
+use rustchat::services::posts::get_or_create_system_bot;
+use serde_json::Value;
+use uuid::Uuid;
+
+mod common;
+
+#[tokio::test]
+async fn due_reminder_is_delivered_and_can_be_snoozed() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "forgetful").await;
+    let auth = format!("Bearer {}", fx.token);
+
+    let post_id = Uuid::new_v4();
+    sqlx::query("INSERT INTO posts (id, channel_id, user_id, message) VALUES ($1, $2, $3, 'remember me')")
+        .bind(post_id)
+        .bind(fx.channel_id)
+        .bind(fx.user_id)
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to insert post");
+
+    let target_time = chrono::Utc::now().timestamp_millis() + 60_000;
+    let res = app
+        .api_client
+        .post(format!(
+            "{}/api/v4/users/me/posts/{}/reminder",
+            &app.address,
+            encode_mm_id(post_id)
+        ))
+        .header("Authorization", &auth)
+        .json(&serde_json::json!({ "target_time": target_time }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+
+    // Not due yet
+    let stats = run_post_reminders(&app.state).await.unwrap();
+    assert_eq!(0, stats.delivered);
+
+    sqlx::query("UPDATE post_reminders SET target_at = NOW() - INTERVAL '1 second'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let stats = run_post_reminders(&app.state).await.unwrap();
+    assert_eq!(1, stats.delivered);
+
+    let (message, props): (String, Value) = sqlx::query_as(
+        r#"
+        SELECT p.message, p.props FROM posts p
+        JOIN users u ON u.id = p.user_id
+        WHERE u.username = 'system-bot'
+        "#,
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .expect("Reminder post not created");
+
+    assert!(message.contains(&encode_mm_id(post_id)));
+    assert_eq!("system_post_reminder", props["type"]);
+    assert_eq!("forgetful", props["username"]);
+
+    // The reminder lands in a DM the user belongs to
+    let in_dm: bool = sqlx::query_scalar(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM posts p
+            JOIN channels c ON c.id = p.channel_id
+            JOIN channel_members cm ON cm.channel_id = c.id
+            WHERE p.props->>'type' = 'system_post_reminder'
+              AND c.type = 'direct' AND cm.user_id = $1
+        )
+        "#,
+    )
+    .bind(fx.user_id)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(in_dm);
+
+    // Delivered reminders do not fire twice
+    let stats = run_post_reminders(&app.state).await.unwrap();
+    assert_eq!(0, stats.delivered);
+
+    let res = app
+        .api_client
+        .post(format!(
+            "{}/api/v4/users/me/posts/{}/reminder/snooze",
+            &app.address,
+            encode_mm_id(post_id)
+        ))
+        .header("Authorization", &auth)
+        .json(&serde_json::json!({ "minutes": 10 }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+
+    let pending: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM post_reminders WHERE delivered_at IS NULL")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(1, pending);
+
+    let res = app
+        .api_client
+        .delete(format!(
+            "{}/api/v4/users/me/posts/{}/reminder",
+            &app.address,
+            encode_mm_id(post_id)
+        ))
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+
+    let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM post_reminders")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(0, remaining);
+}
+
+#[tokio::test]
+async fn failed_reminders_are_retried_and_missing_posts_dropped() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "retried").await;
+
+    let post_id = Uuid::new_v4();
+    let gone_id = Uuid::new_v4();
+    for id in [post_id, gone_id] {
+        sqlx::query("INSERT INTO posts (id, channel_id, user_id, message) VALUES ($1, $2, $3, 'remember me')")
+            .bind(id)
+            .bind(fx.channel_id)
+            .bind(fx.user_id)
+            .execute(&app.db_pool)
+            .await
+            .expect("Failed to insert post");
+        sqlx::query(
+            "INSERT INTO post_reminders (user_id, post_id, target_at) VALUES ($1, $2, NOW() - INTERVAL '1 second')",
+        )
+        .bind(fx.user_id)
+        .bind(id)
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to insert reminder");
+    }
+    sqlx::query("UPDATE posts SET deleted_at = NOW() WHERE id = $1")
+        .bind(gone_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // Reminder posts cannot be created for now
+    sqlx::query(
+        "ALTER TABLE posts ADD CONSTRAINT no_reminders CHECK (props->>'type' IS DISTINCT FROM 'system_post_reminder') NOT VALID",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let stats = run_post_reminders(&app.state).await.unwrap();
+    assert_eq!((0, 2), (stats.delivered, stats.failed));
+
+    let pending: Vec<Uuid> =
+        sqlx::query_scalar("SELECT post_id FROM post_reminders WHERE delivered_at IS NULL")
+            .fetch_all(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(vec![post_id], pending);
+
+    let attempts: i32 = sqlx::query_scalar("SELECT attempts FROM post_reminders WHERE post_id = $1")
+        .bind(post_id)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(1, attempts);
+
+    sqlx::query("ALTER TABLE posts DROP CONSTRAINT no_reminders")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    // A failed reminder waits before it is tried again
+    let stats = run_post_reminders(&app.state).await.unwrap();
+    assert_eq!((0, 0), (stats.delivered, stats.failed));
+
+    sqlx::query("UPDATE post_reminders SET next_attempt_at = NOW() WHERE post_id = $1")
+        .bind(post_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let stats = run_post_reminders(&app.state).await.unwrap();
+    assert_eq!((1, 0), (stats.delivered, stats.failed));
+}
+
+#[tokio::test]
+async fn reminders_that_keep_failing_are_given_up_on() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "given_up").await;
+
+    let mut post_ids = Vec::new();
+    for attempts in [7, 0] {
+        let id = Uuid::new_v4();
+        sqlx::query("INSERT INTO posts (id, channel_id, user_id, message) VALUES ($1, $2, $3, 'remember me')")
+            .bind(id)
+            .bind(fx.channel_id)
+            .bind(fx.user_id)
+            .execute(&app.db_pool)
+            .await
+            .expect("Failed to insert post");
+        sqlx::query(
+            "INSERT INTO post_reminders (user_id, post_id, target_at, attempts) VALUES ($1, $2, NOW() - INTERVAL '1 second', $3)",
+        )
+        .bind(fx.user_id)
+        .bind(id)
+        .bind(attempts)
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to insert reminder");
+        post_ids.push(id);
+    }
+
+    sqlx::query(
+        "ALTER TABLE posts ADD CONSTRAINT no_reminders CHECK (props->>'type' IS DISTINCT FROM 'system_post_reminder') NOT VALID",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // Both are tried; the one on its last attempt is dropped
+    let stats = run_post_reminders(&app.state).await.unwrap();
+    assert_eq!((0, 2), (stats.delivered, stats.failed));
+
+    let remaining: Vec<Uuid> = sqlx::query_scalar("SELECT post_id FROM post_reminders")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(vec![post_ids[1]], remaining);
+}
+
+#[tokio::test]
+async fn accounts_sharing_the_bots_address_never_become_the_bot() {
+    let app = spawn_app().await;
+
+    // An ordinary account that has the address the bot would use
+    let squatter: Uuid = sqlx::query_scalar(
+        "UPDATE users SET is_system_bot = FALSE, is_bot = FALSE, username = 'squatter' WHERE is_system_bot RETURNING id",
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+
+    assert!(get_or_create_system_bot(&app.state).await.is_err());
+    let is_bot: bool = sqlx::query_scalar("SELECT is_bot FROM users WHERE id = $1")
+        .bind(squatter)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert!(!is_bot);
+
+    sqlx::query("UPDATE users SET email = 'squatter@example.com' WHERE id = $1")
+        .bind(squatter)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let bot_id = get_or_create_system_bot(&app.state).await.unwrap();
+    assert_ne!(squatter, bot_id);
+    assert_eq!(bot_id, get_or_create_system_bot(&app.state).await.unwrap());
+}
@@ -0,0 +1,180 @@
+use crate::common::{add_channel_member, setup_channel_member, spawn_app, Fixture, TestApp};
+use axum::{http::StatusCode, routing::post, Json, Router};
+use rustchat::mattermost_compat::id::encode_mm_id;
+use serde_json::Value;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod common;
+
+type Received = Arc<Mutex<Vec<Value>>>;
+
+/// Spawn a push proxy that fails its first request and records the others
+async fn spawn_push_proxy() -> (String, Received, Arc<AtomicUsize>) {
+    let received: Received = Arc::new(Mutex::new(Vec::new()));
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let recorder = received.clone();
+    let counter = attempts.clone();
+
+    let app = Router::new().route(
+        "/api/v1/send_push",
+        post(move |Json(payload): Json<Value>| {
+            let recorder = recorder.clone();
+            let counter = counter.clone();
+            async move {
+                if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return (StatusCode::SERVICE_UNAVAILABLE, Json(serde_json::json!({})));
+                }
+                recorder.lock().unwrap().push(payload);
+                (StatusCode::OK, Json(serde_json::json!({ "status": "OK" })))
+            }
+        }),
+    );
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    (format!("http://127.0.0.1:{}", port), received, attempts)
+}
+
+async fn create_post(app: &TestApp, fx: &Fixture, message: &str) {
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/posts", &app.address))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .json(&serde_json::json!({
+            "channel_id": encode_mm_id(fx.channel_id),
+            "message": message
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+}
+
+/// Wait until the proxy has received `count` notifications
+async fn wait_for(received: &Received, count: usize) -> Vec<Value> {
+    for _ in 0..50 {
+        if received.lock().unwrap().len() >= count {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    let received = received.lock().unwrap().clone();
+    assert_eq!(count, received.len(), "unexpected pushes: {:?}", received);
+    received
+}
+
+#[tokio::test]
+async fn posts_are_pushed_to_offline_members_through_the_proxy() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    let (bob_token, bob_id) = add_channel_member(&app, &fx, "bob").await;
+    let (proxy_url, received, attempts) = spawn_push_proxy().await;
+
+    let updated = sqlx::query(
+        "UPDATE server_config SET email = email || $1::jsonb WHERE id = 'default'",
+    )
+    .bind(serde_json::json!({
+        "send_push_notifications": true,
+        "push_notification_server": proxy_url,
+        "push_notification_contents": "full"
+    }))
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(1, updated.rows_affected());
+
+    // Mattermost apps register "<platform>:<token>" as their device id
+    let res = app
+        .api_client
+        .put(format!("{}/api/v4/users/sessions/device", &app.address))
+        .header("Authorization", format!("Bearer {}", bob_token))
+        .json(&serde_json::json!({ "device_id": "android_rn-v2:bob-device-token" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+
+    // Only mentions are pushed by default; the first delivery is retried
+    create_post(&app, &fx, "good morning").await;
+    create_post(&app, &fx, "hey @bob, standup?").await;
+    let pushes = wait_for(&received, 1).await;
+    assert_eq!(2, attempts.load(Ordering::SeqCst));
+    let push = &pushes[0];
+    assert_eq!("message", push["type"]);
+    assert_eq!("android_rn-v2", push["platform"]);
+    assert_eq!("bob-device-token", push["device_id"]);
+    assert_eq!("@alice: hey @bob, standup?", push["message"]);
+    assert_eq!("Test Channel", push["channel_name"]);
+    assert_eq!("alice", push["sender_name"]);
+    assert_eq!(encode_mm_id(fx.channel_id), push["channel_id"]);
+    assert_eq!("v2", push["version"]);
+    assert!(push["badge"].as_i64().unwrap() >= 1);
+
+    // A channel set to "all" pushes every post
+    sqlx::query(
+        "INSERT INTO channel_notification_settings (user_id, channel_id, notify_level) VALUES ($1, $2, 'all')",
+    )
+    .bind(bob_id)
+    .bind(fx.channel_id)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    create_post(&app, &fx, "lunch is here").await;
+    let pushes = wait_for(&received, 2).await;
+    assert_eq!("@alice: lunch is here", pushes[1]["message"]);
+
+    // Id-loaded pushes carry no content; the device fetches it on ack
+    sqlx::query(
+        "UPDATE server_config SET email = email || '{\"push_notification_contents\": \"id_loaded\"}'::jsonb WHERE id = 'default'",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    create_post(&app, &fx, "the secret is 42").await;
+    let pushes = wait_for(&received, 3).await;
+    let push = &pushes[2];
+    assert_eq!(true, push["is_id_loaded"]);
+    assert_eq!("", push["sender_name"]);
+    assert!(!push["message"].as_str().unwrap().contains("42"));
+
+    let ack: Value = app
+        .api_client
+        .post(format!("{}/api/v4/notifications/ack", &app.address))
+        .header("Authorization", format!("Bearer {}", bob_token))
+        .json(&serde_json::json!({
+            "id": push["ack_id"],
+            "platform": "android_rn-v2",
+            "type": "message",
+            "post_id": push["post_id"],
+            "is_id_loaded": true
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!("@alice: the secret is 42", ack["message"]);
+    assert_eq!("alice", ack["sender_name"]);
+
+    // Viewing the channel clears its notifications on the device
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/channels/members/me/view", &app.address))
+        .header("Authorization", format!("Bearer {}", bob_token))
+        .json(&serde_json::json!({ "channel_id": encode_mm_id(fx.channel_id) }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    let pushes = wait_for(&received, 4).await;
+    assert_eq!("clear", pushes[3]["type"]);
+    assert_eq!(encode_mm_id(fx.channel_id), pushes[3]["channel_id"]);
+    assert_eq!("bob-device-token", pushes[3]["device_id"]);
+}
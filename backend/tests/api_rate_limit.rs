@@ -0,0 +1,142 @@
+use crate::common::{client_from, random_loopback_ip, setup_channel_member, spawn_app, TestApp};
+use serde_json::{json, Value};
+use std::net::IpAddr;
+use uuid::Uuid;
+
+mod common;
+
+/// Request buckets live in Redis, so these tests need a server
+async fn redis_available(app: &TestApp) -> bool {
+    if app.state.redis.get().await.is_ok() {
+        return true;
+    }
+    eprintln!("Redis is not available, skipping");
+    false
+}
+
+async fn get(app: &TestApp, token: &str, path: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn login(app: &TestApp, ip: IpAddr) -> reqwest::Response {
+    client_from(ip)
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&json!({ "email": "nobody@example.com", "password": "wrong" }))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn call_webhook(app: &TestApp, token: &str) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/api/v1/hooks/{}", &app.address, token))
+        .json(&json!({ "text": "hello" }))
+        .send()
+        .await
+        .unwrap()
+}
+
+fn header<'a>(res: &'a reqwest::Response, name: &str) -> Option<&'a str> {
+    res.headers().get(name).map(|v| v.to_str().unwrap())
+}
+
+#[tokio::test]
+async fn quotas_apply_per_caller_and_route_class() {
+    let app = spawn_app().await;
+    if !redis_available(&app).await {
+        return;
+    }
+    let fx = setup_channel_member(&app, "limited_user").await;
+    let admin = setup_channel_member(&app, "limit_admin").await;
+    sqlx::query("UPDATE users SET role = 'system_admin' WHERE id = $1")
+        .bind(admin.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let res = app
+        .api_client
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&json!({ "email": "limit_admin@example.com", "password": "Password123!" }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = res.json().await.unwrap();
+    let admin_token = body["token"].as_str().unwrap().to_string();
+
+    let res = app
+        .api_client
+        .patch(format!("{}/api/v1/admin/config/rate_limiting", &app.address))
+        .header("Authorization", format!("Bearer {}", admin_token))
+        .json(&json!({
+            "enable": true,
+            "api": { "per_minute": 1, "burst": 3 },
+            "auth": { "per_minute": 1, "burst": 2 },
+            "webhooks": { "per_minute": 1, "burst": 2 }
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+
+    for remaining in ["2", "1", "0"] {
+        let res = get(&app, &fx.token, "/api/v1/auth/me").await;
+        assert_eq!(200, res.status().as_u16());
+        assert_eq!(Some("3"), header(&res, "x-ratelimit-limit"));
+        assert_eq!(Some(remaining), header(&res, "x-ratelimit-remaining"));
+        assert!(header(&res, "x-ratelimit-reset").is_some());
+    }
+    let res = get(&app, &fx.token, "/api/v1/auth/me").await;
+    assert_eq!(429, res.status().as_u16());
+    assert!(header(&res, "retry-after").is_some());
+    let body: Value = res.json().await.unwrap();
+    assert_eq!("TOO_MANY_REQUESTS", body["error"]["code"]);
+
+    // The Mattermost API shares the bucket and answers like Mattermost
+    let res = get(&app, &fx.token, "/api/v4/users/me").await;
+    assert_eq!(429, res.status().as_u16());
+    assert_eq!("limit exceeded\n", res.text().await.unwrap());
+
+    // System admins are never limited
+    for _ in 0..5 {
+        let res = get(&app, &admin_token, "/api/v1/auth/me").await;
+        assert_eq!(200, res.status().as_u16());
+        assert!(header(&res, "x-ratelimit-limit").is_none());
+    }
+
+    // Anonymous requests are limited by client address
+    let ip = random_loopback_ip();
+    for _ in 0..2 {
+        assert_eq!(401, login(&app, ip).await.status().as_u16());
+    }
+    let res = login(&app, ip).await;
+    assert_eq!(429, res.status().as_u16());
+    assert_eq!(Some("2"), header(&res, "x-ratelimit-limit"));
+    assert_eq!(
+        401,
+        login(&app, random_loopback_ip()).await.status().as_u16()
+    );
+    // Naming another client in a header does not get a new bucket
+    let res = client_from(ip)
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .header("X-Forwarded-For", random_loopback_ip().to_string())
+        .header("X-Real-IP", random_loopback_ip().to_string())
+        .json(&json!({ "email": "nobody@example.com", "password": "wrong" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(429, res.status().as_u16());
+
+    // Webhook calls are limited by token
+    let token = Uuid::new_v4().simple().to_string();
+    for _ in 0..2 {
+        assert_eq!(401, call_webhook(&app, &token).await.status().as_u16());
+    }
+    assert_eq!(429, call_webhook(&app, &token).await.status().as_u16());
+    let other = Uuid::new_v4().simple().to_string();
+    assert_eq!(401, call_webhook(&app, &other).await.status().as_u16());
+}
@@ -0,0 +1,351 @@
+use crate::common::mock_saml_idp::{
+    spawn_mock_saml_idp, MockSamlIdp, SamlResponse, SP_CERTIFICATE, SP_KEY,
+};
+use crate::common::{setup_channel_member, spawn_app, TestApp};
+use serde_json::Value;
+use uuid::Uuid;
+
+mod common;
+
+/// Register the mock provider as the active SAML config and turn SSO on
+async fn configure_saml(app: &TestApp, idp: &MockSamlIdp) -> Uuid {
+    let org_id: Uuid =
+        sqlx::query_scalar("INSERT INTO organizations (name) VALUES ('saml-org') RETURNING id")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    let config_id: Uuid = sqlx::query_scalar(
+        r#"
+        INSERT INTO sso_configs
+            (org_id, provider, display_name, idp_metadata_url, sp_private_key_encrypted,
+             sp_certificate, is_active, username_claim, display_name_claim, email_claim)
+        VALUES ($1, 'saml', 'Corp SSO', $2, $3, $4, true, 'uid', 'displayName', 'mail')
+        RETURNING id
+        "#,
+    )
+    .bind(org_id)
+    .bind(&idp.metadata_url)
+    .bind(rustchat::crypto::encrypt(SP_KEY, &app.state.encryption_key))
+    .bind(SP_CERTIFICATE)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"
+        UPDATE server_config
+        SET authentication = authentication || '{"enable_sso": true}'::jsonb,
+            site = site || jsonb_build_object('site_url', $1::text)
+        WHERE id = 'default'
+        "#,
+    )
+    .bind(&app.address)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    config_id
+}
+
+fn location(response: &reqwest::Response) -> String {
+    response.headers()["location"].to_str().unwrap().to_string()
+}
+
+/// Start a login and return the AuthnRequest URL
+async fn start_login(app: &TestApp) -> String {
+    let login = app
+        .api_client
+        .get(format!("{}/api/v1/saml/saml/login", &app.address))
+        .send()
+        .await
+        .unwrap();
+    assert!(login.status().is_redirection());
+    location(&login)
+}
+
+/// Post a response to the assertion consumer service and return where the
+/// browser lands
+async fn post_response(app: &TestApp, response: &SamlResponse) -> String {
+    let acs = app
+        .api_client
+        .post(&response.acs_url)
+        .form(&response.form())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(303, acs.status().as_u16());
+    location(&acs)
+}
+
+async fn sso_login(app: &TestApp, idp: &MockSamlIdp, name_id: &str, email: &str) -> String {
+    let response = idp.respond(
+        &start_login(app).await,
+        name_id,
+        &[
+            ("mail", email),
+            ("uid", "jdoe"),
+            ("displayName", "Jane Doe"),
+        ],
+    );
+    let landing = post_response(app, &response).await;
+    assert!(
+        landing.starts_with("/oauth/callback?token="),
+        "unexpected redirect {}",
+        landing
+    );
+    landing["/oauth/callback?token=".len()..].to_string()
+}
+
+async fn me(app: &TestApp, token: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/api/v1/auth/me", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn saml_metadata_and_provider_listing() {
+    let app = spawn_app().await;
+    let idp = spawn_mock_saml_idp().await;
+    configure_saml(&app, &idp).await;
+
+    let metadata = app
+        .api_client
+        .get(format!("{}/api/v1/saml/saml/metadata", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(metadata.contains(&format!(
+        "entityID=\"{}/api/v1/saml/saml/metadata\"",
+        app.address
+    )));
+    assert!(metadata.contains("AuthnRequestsSigned=\"true\""));
+    assert!(metadata.contains(&format!(
+        "Location=\"{}/api/v1/saml/saml/acs\"",
+        app.address
+    )));
+    assert!(metadata.contains("<ds:X509Certificate>"));
+
+    let providers: Value = app
+        .api_client
+        .get(format!("{}/api/v1/oauth2/providers", &app.address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!("saml", providers[0]["protocol"]);
+    assert_eq!("Corp SSO", providers[0]["name"]);
+    assert_eq!("/api/v1/saml/saml/login", providers[0]["login_url"]);
+}
+
+#[tokio::test]
+async fn saml_login_provisions_the_account_once() {
+    let app = spawn_app().await;
+    let idp = spawn_mock_saml_idp().await;
+    configure_saml(&app, &idp).await;
+
+    let authn_request = start_login(&app).await;
+    let response = idp.respond(
+        &authn_request,
+        "employee-1",
+        &[
+            ("mail", "jane@corp.example.com"),
+            ("uid", "jdoe"),
+            ("displayName", "Jane Doe"),
+        ],
+    );
+    let landing = post_response(&app, &response).await;
+    let token = &landing["/oauth/callback?token=".len()..];
+    let user: Value = me(&app, token).await.json().await.unwrap();
+    assert_eq!("jdoe", user["username"]);
+    assert_eq!("jane@corp.example.com", user["email"]);
+    assert_eq!("Jane Doe", user["display_name"]);
+
+    // A captured response cannot be posted again, even with the state cookie
+    let replay = app
+        .api_client
+        .post(&response.acs_url)
+        .header(
+            "Cookie",
+            format!("rustchat_saml_state={}", response.relay_state),
+        )
+        .form(&response.form())
+        .send()
+        .await
+        .unwrap();
+    assert!(location(&replay).starts_with("/login?error=Invalid%20or%20expired"));
+
+    // The NameID keeps mapping to the same account
+    let token = sso_login(&app, &idp, "employee-1", "jane.doe@corp.example.com").await;
+    let again: Value = me(&app, &token).await.json().await.unwrap();
+    assert_eq!(user["id"], again["id"]);
+}
+
+#[tokio::test]
+async fn saml_rejects_tampered_and_misaddressed_assertions() {
+    let app = spawn_app().await;
+    let idp = spawn_mock_saml_idp().await;
+    configure_saml(&app, &idp).await;
+    let attributes = [("mail", "mallory@corp.example.com")];
+
+    // Changing a signed attribute breaks the digest
+    let mut response = idp.respond(&start_login(&app).await, "employee-2", &attributes);
+    response.xml = response
+        .xml
+        .replace("mallory@corp.example.com", "ceo@corp.example.com");
+    assert!(post_response(&app, &response)
+        .await
+        .starts_with("/login?error=Invalid%20XML%20signature"));
+
+    // A second, unsigned assertion next to the signed one
+    let mut response = idp.respond(&start_login(&app).await, "employee-2", &attributes);
+    let injected = r#"<saml:Assertion ID="_evil"><saml:Issuer>x</saml:Issuer></saml:Assertion></samlp:Response>"#;
+    response.xml = response.xml.replace("</samlp:Response>", injected);
+    assert!(post_response(&app, &response)
+        .await
+        .contains("expected%20exactly%20one%20assertion"));
+
+    // Assertions for another service provider
+    idp.state.lock().unwrap().audience = Some("https://other.example.com".to_string());
+    let response = idp.respond(&start_login(&app).await, "employee-2", &attributes);
+    assert!(post_response(&app, &response)
+        .await
+        .contains("another%20audience"));
+
+    // A response carried to a browser other than the one that started the
+    // login, as a phished login link would be
+    let response = idp.respond(&start_login(&app).await, "employee-2", &attributes);
+    let other_browser = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let acs = other_browser
+        .post(&response.acs_url)
+        .form(&response.form())
+        .send()
+        .await
+        .unwrap();
+    assert!(location(&acs).contains("another%20browser"));
+
+    let users: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE email LIKE '%@corp.example.com'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(0, users);
+}
+
+#[tokio::test]
+async fn saml_single_logout_ends_sessions() {
+    let app = spawn_app().await;
+    let idp = spawn_mock_saml_idp().await;
+    configure_saml(&app, &idp).await;
+    let slo_url = format!("{}/api/v1/saml/saml/slo", app.address);
+
+    // Logout started at the identity provider
+    let token = sso_login(&app, &idp, "employee-3", "sam@corp.example.com").await;
+    let slo = app
+        .api_client
+        .get(idp.logout_request_url(&slo_url, "employee-3"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(303, slo.status().as_u16());
+    let response = idp.read_logout_response(&location(&slo));
+    assert!(response.contains("status:Success"));
+    assert!(location(&slo).contains("RelayState=idp-state"));
+    assert_eq!(401, me(&app, &token).await.status().as_u16());
+
+    // The same logout request is not acted on twice
+    let token = sso_login(&app, &idp, "employee-3", "sam@corp.example.com").await;
+    let replay = app.api_client.get(slo.url().as_str()).send().await.unwrap();
+    assert!(location(&replay).contains("already%20used"));
+    assert_eq!(200, me(&app, &token).await.status().as_u16());
+
+    // Logout started here
+    let token = sso_login(&app, &idp, "employee-3", "sam@corp.example.com").await;
+    let logout: Value = app
+        .api_client
+        .post(format!("{}/api/v1/saml/saml/logout", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let request = idp.read_logout_request(logout["logout_url"].as_str().unwrap());
+    assert!(request.contains(">employee-3</saml:NameID>"));
+    assert_eq!(401, me(&app, &token).await.status().as_u16());
+
+    // Unsigned logout requests are ignored
+    let token = sso_login(&app, &idp, "employee-3", "sam@corp.example.com").await;
+    let forged = idp.logout_request_url(&slo_url, "employee-3");
+    let forged = forged.split("&SigAlg=").next().unwrap();
+    app.api_client.get(forged).send().await.unwrap();
+    assert_eq!(200, me(&app, &token).await.status().as_u16());
+}
+
+#[tokio::test]
+async fn enforced_mfa_exempts_only_sso_sessions() {
+    let app = spawn_app().await;
+    let idp = spawn_mock_saml_idp().await;
+    configure_saml(&app, &idp).await;
+    sqlx::query(
+        r#"
+        UPDATE server_config
+        SET authentication = authentication || '{"enforce_mfa": true}'::jsonb
+        WHERE id = 'default'
+        "#,
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let teams = |token: String| {
+        let app = &app;
+        async move {
+            app.api_client
+                .get(format!("{}/api/v1/teams", &app.address))
+                .header("Authorization", format!("Bearer {}", token))
+                .send()
+                .await
+                .unwrap()
+                .status()
+                .as_u16()
+        }
+    };
+
+    let token = sso_login(&app, &idp, "employee-4", "pat@corp.example.com").await;
+    assert_eq!(200, teams(token).await);
+
+    // The same account signing in with a password needs its second factor
+    setup_channel_member(&app, "local").await;
+    sqlx::query(
+        "UPDATE users SET password_hash = (SELECT password_hash FROM users WHERE username = 'local') WHERE email = 'pat@corp.example.com'",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let login: Value = app
+        .api_client
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&serde_json::json!({
+            "email": "pat@corp.example.com",
+            "password": "Password123!"
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let token = login["token"].as_str().unwrap().to_string();
+    assert_eq!(403, teams(token).await);
+}
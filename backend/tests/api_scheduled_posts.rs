@@ -0,0 +1,188 @@
+use crate::common::{setup_channel_member, spawn_app, Fixture};
+use rustchat::jobs::scheduled_posts::run_scheduled_posts_dispatch;
+use rustchat::mattermost_compat::id::encode_mm_id;
+use serde_json::Value;
+use uuid::Uuid;
+
+mod common;
+
+async fn schedule(app: &common::TestApp, fx: &Fixture, message: &str) -> Value {
+    let scheduled_at = chrono::Utc::now().timestamp_millis() + 60_000;
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/posts/schedule", &app.address))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .json(&serde_json::json!({
+            "channel_id": encode_mm_id(fx.channel_id),
+            "message": message,
+            "scheduled_at": scheduled_at
+        }))
+        .send()
+        .await
+        .expect("Failed to schedule post");
+
+    assert_eq!(200, res.status().as_u16());
+    res.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn scheduled_post_is_published_when_due() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "scheduler").await;
+
+    let scheduled = schedule(&app, &fx, "hello from the past").await;
+    let scheduled_id = rustchat::mattermost_compat::id::parse_mm_or_uuid(
+        scheduled["id"].as_str().unwrap(),
+    )
+    .unwrap();
+
+    // Nothing is due yet
+    let stats = run_scheduled_posts_dispatch(&app.state).await.unwrap();
+    assert_eq!(0, stats.sent);
+
+    sqlx::query("UPDATE scheduled_posts SET scheduled_at = NOW() - INTERVAL '1 minute' WHERE id = $1")
+        .bind(scheduled_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let stats = run_scheduled_posts_dispatch(&app.state).await.unwrap();
+    assert_eq!(1, stats.sent);
+
+    let (state, post_id): (String, Option<Uuid>) =
+        sqlx::query_as("SELECT state, post_id FROM scheduled_posts WHERE id = $1")
+            .bind(scheduled_id)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!("sent", state);
+
+    let message: String = sqlx::query_scalar("SELECT message FROM posts WHERE id = $1")
+        .bind(post_id.unwrap())
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!("hello from the past", message);
+
+    // A second pass must not publish it again
+    let stats = run_scheduled_posts_dispatch(&app.state).await.unwrap();
+    assert_eq!(0, stats.sent);
+}
+
+#[tokio::test]
+async fn scheduled_post_fails_without_membership() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "scheduler").await;
+
+    let scheduled = schedule(&app, &fx, "nobody will see this").await;
+    let scheduled_id = rustchat::mattermost_compat::id::parse_mm_or_uuid(
+        scheduled["id"].as_str().unwrap(),
+    )
+    .unwrap();
+
+    sqlx::query("DELETE FROM channel_members WHERE channel_id = $1 AND user_id = $2")
+        .bind(fx.channel_id)
+        .bind(fx.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE scheduled_posts SET scheduled_at = NOW() WHERE id = $1")
+        .bind(scheduled_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let stats = run_scheduled_posts_dispatch(&app.state).await.unwrap();
+    assert_eq!(1, stats.failed);
+
+    let list: Vec<Value> = app
+        .api_client
+        .get(format!(
+            "{}/api/v4/posts/scheduled/team/{}",
+            &app.address,
+            encode_mm_id(fx.team_id)
+        ))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(1, list.len());
+    assert_eq!("no_channel_permission", list[0]["error_code"]);
+}
+
+#[tokio::test]
+async fn scheduled_post_can_be_updated_cancelled_and_sent_now() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "scheduler").await;
+    let auth = format!("Bearer {}", fx.token);
+
+    let first = schedule(&app, &fx, "first draft").await;
+    let first_id = first["id"].as_str().unwrap();
+
+    let new_time = chrono::Utc::now().timestamp_millis() + 3_600_000;
+    let updated: Value = app
+        .api_client
+        .put(format!("{}/api/v4/posts/schedule/{}", &app.address, first_id))
+        .header("Authorization", &auth)
+        .json(&serde_json::json!({
+            "message": "second draft",
+            "scheduled_at": new_time
+        }))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!("second draft", updated["message"]);
+    assert_eq!(new_time, updated["scheduled_at"].as_i64().unwrap());
+
+    let sent = app
+        .api_client
+        .post(format!(
+            "{}/api/v4/posts/schedule/{}/send_now",
+            &app.address, first_id
+        ))
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, sent.status().as_u16());
+    let post: Value = sent.json().await.unwrap();
+    assert_eq!("second draft", post["message"]);
+
+    // Already sent, so it can no longer be cancelled
+    let res = app
+        .api_client
+        .delete(format!("{}/api/v4/posts/schedule/{}", &app.address, first_id))
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(404, res.status().as_u16());
+
+    let second = schedule(&app, &fx, "never mind").await;
+    let res = app
+        .api_client
+        .delete(format!(
+            "{}/api/v4/posts/schedule/{}",
+            &app.address,
+            second["id"].as_str().unwrap()
+        ))
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+
+    let pending: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM scheduled_posts WHERE state = 'pending'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(0, pending);
+}
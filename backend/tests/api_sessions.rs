@@ -0,0 +1,227 @@
+use crate::common::{spawn_app, TestApp};
+use futures_util::StreamExt;
+use serde_json::json;
+use std::time::Duration;
+
+mod common;
+
+async fn register(app: &TestApp, username: &str) {
+    let res = app
+        .api_client
+        .post(format!("{}/api/v1/auth/register", &app.address))
+        .json(&json!({
+            "username": username,
+            "email": format!("{}@example.com", username),
+            "password": "Password123!",
+            "display_name": username
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert!(res.status().is_success());
+}
+
+/// Log in through the Mattermost API and return the token
+async fn login(app: &TestApp, username: &str, device_id: &str) -> String {
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/users/login", &app.address))
+        .header("User-Agent", "Mattermost Mobile/2.20")
+        .json(&json!({
+            "login_id": username,
+            "password": "Password123!",
+            "device_id": device_id
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    res.headers()["token"].to_str().unwrap().to_string()
+}
+
+async fn get(app: &TestApp, path: &str, token: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}{}", &app.address, path))
+        .header("Authorization", format!("Bearer {}", token))
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn post(app: &TestApp, path: &str, token: &str, body: serde_json::Value) -> u16 {
+    app.api_client
+        .post(format!("{}{}", &app.address, path))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+        .status()
+        .as_u16()
+}
+
+#[tokio::test]
+async fn sessions_are_listed_and_revoked() {
+    let app = spawn_app().await;
+    register(&app, "alice").await;
+    register(&app, "bob").await;
+    let phone = login(&app, "alice", "android_rn:phone-token").await;
+    let tablet = login(&app, "alice", "").await;
+    let bob = login(&app, "bob", "").await;
+
+    let res = get(&app, "/api/v4/users/me/sessions", &phone).await;
+    assert_eq!(200, res.status().as_u16());
+    let sessions: Vec<serde_json::Value> = res.json().await.unwrap();
+    // Registering started a session too
+    assert_eq!(3, sessions.len());
+    assert!(sessions.iter().all(|s| s["token"] == ""));
+    let phone_session = sessions
+        .iter()
+        .find(|s| s["device_id"] == "android_rn:phone-token")
+        .expect("phone session missing");
+    assert_eq!(
+        app.client_ip.to_string(),
+        phone_session["props"]["ip_address"]
+    );
+    assert_eq!(
+        "Mattermost Mobile/2.20",
+        phone_session["props"]["user_agent"]
+    );
+    let tablet_session = sessions
+        .iter()
+        .find(|s| s["device_id"] == "" && s["props"]["user_agent"] != "")
+        .unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let user_id = phone_session["user_id"].as_str().unwrap().to_string();
+
+    // Other users cannot see or revoke them
+    let res = get(&app, &format!("/api/v4/users/{}/sessions", user_id), &bob).await;
+    assert_eq!(403, res.status().as_u16());
+    assert_eq!(
+        403,
+        post(
+            &app,
+            &format!("/api/v4/users/{}/sessions/revoke/all", user_id),
+            &bob,
+            json!({})
+        )
+        .await
+    );
+
+    // Revoking a session rejects its token right away
+    assert_eq!(
+        200,
+        post(
+            &app,
+            "/api/v4/users/me/sessions/revoke",
+            &phone,
+            json!({ "session_id": tablet_session })
+        )
+        .await
+    );
+    assert_eq!(
+        401,
+        get(&app, "/api/v4/users/me", &tablet)
+            .await
+            .status()
+            .as_u16()
+    );
+    assert_eq!(
+        401,
+        get(&app, "/api/v1/auth/me", &tablet)
+            .await
+            .status()
+            .as_u16()
+    );
+    assert_eq!(
+        200,
+        get(&app, "/api/v4/users/me", &phone)
+            .await
+            .status()
+            .as_u16()
+    );
+
+    // Logging out ends the current session
+    assert_eq!(
+        200,
+        post(&app, "/api/v4/users/logout", &phone, json!({})).await
+    );
+    assert_eq!(
+        401,
+        get(&app, "/api/v4/users/me", &phone)
+            .await
+            .status()
+            .as_u16()
+    );
+    assert_eq!(
+        200,
+        get(&app, "/api/v4/users/me", &bob).await.status().as_u16()
+    );
+}
+
+#[tokio::test]
+async fn deactivating_a_user_ends_sessions_and_websockets() {
+    let app = spawn_app().await;
+    register(&app, "admin").await;
+    register(&app, "carol").await;
+    sqlx::query("UPDATE users SET role = 'system_admin' WHERE username = 'admin'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let admin = login(&app, "admin", "").await;
+    let carol = login(&app, "carol", "").await;
+    let carol_id: uuid::Uuid = sqlx::query_scalar("SELECT id FROM users WHERE username = 'carol'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+
+    let ws_url = format!(
+        "{}/api/v4/websocket?token={}",
+        app.address.replacen("http", "ws", 1),
+        carol
+    );
+    let (mut socket, _) = tokio_tungstenite::connect_async(ws_url).await.unwrap();
+    let hello = socket.next().await.unwrap().unwrap();
+    assert!(hello.to_text().unwrap().contains("hello"));
+    for _ in 0..50 {
+        if app.state.ws_hub.user_connection_count(carol_id).await > 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    let res = app
+        .api_client
+        .post(format!(
+            "{}/api/v1/admin/users/{}/deactivate",
+            &app.address, carol_id
+        ))
+        .header("Authorization", format!("Bearer {}", admin))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+
+    assert_eq!(
+        401,
+        get(&app, "/api/v4/users/me", &carol)
+            .await
+            .status()
+            .as_u16()
+    );
+
+    // The server closes the socket
+    let closed = tokio::time::timeout(Duration::from_secs(5), async {
+        while let Some(msg) = socket.next().await {
+            match msg {
+                Ok(m) if m.is_close() => return,
+                Ok(_) => continue,
+                Err(_) => return,
+            }
+        }
+    })
+    .await;
+    assert!(closed.is_ok(), "websocket was not closed");
+}
@@ -0,0 +1,186 @@
+use crate::common::{setup_channel_member, spawn_app};
+use axum::{
+    extract::{Form, Query},
+    routing::get,
+    Json, Router,
+};
+use rustchat::mattermost_compat::id::encode_mm_id;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+mod common;
+
+type Received = Arc<Mutex<Vec<HashMap<String, String>>>>;
+
+/// Spawn a command server: POST answers in channel, GET answers ephemerally
+async fn spawn_command_server() -> (String, Received) {
+    let received: Received = Arc::new(Mutex::new(Vec::new()));
+    let on_post = received.clone();
+    let on_get = received.clone();
+
+    let app = Router::new().route(
+        "/command",
+        get(move |Query(params): Query<HashMap<String, String>>| {
+            let on_get = on_get.clone();
+            async move {
+                on_get.lock().unwrap().push(params);
+                Json(serde_json::json!({ "text": "only you can see this" }))
+            }
+        })
+        .post(move |Form(params): Form<HashMap<String, String>>| {
+            let on_post = on_post.clone();
+            async move {
+                let text = format!("deploying {}", params["text"]);
+                on_post.lock().unwrap().push(params);
+                Json(serde_json::json!({
+                    "response_type": "in_channel",
+                    "text": text,
+                    "goto_location": "https://example.com/deploys"
+                }))
+            }
+        }),
+    );
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    (format!("http://127.0.0.1:{}/command", port), received)
+}
+
+#[tokio::test]
+async fn custom_slash_command_is_dispatched_and_accepts_delayed_responses() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "commander").await;
+    let auth = format!("Bearer {}", fx.token);
+    let (url, received) = spawn_command_server().await;
+
+    for (trigger, method) in [("deploy", "POST"), ("peek", "GET")] {
+        let res = app
+            .api_client
+            .post(format!("{}/api/v1/commands?team_id={}", &app.address, fx.team_id))
+            .header("Authorization", &auth)
+            .json(&serde_json::json!({ "trigger": trigger, "url": url, "method": method }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(200, res.status().as_u16());
+    }
+
+    // The team's commands are listed alongside the built-ins
+    let commands: Vec<Value> = app
+        .api_client
+        .get(format!(
+            "{}/api/v4/commands?team_id={}",
+            &app.address,
+            encode_mm_id(fx.team_id)
+        ))
+        .header("Authorization", &auth)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let triggers: Vec<&str> = commands
+        .iter()
+        .filter_map(|c| c["trigger"].as_str())
+        .collect();
+    assert!(triggers.contains(&"deploy"));
+    assert!(triggers.contains(&"peek"));
+
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/commands/execute", &app.address))
+        .header("Authorization", &auth)
+        .json(&serde_json::json!({
+            "command": "/deploy production",
+            "channel_id": encode_mm_id(fx.channel_id),
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    let body: Value = res.json().await.unwrap();
+    assert_eq!("in_channel", body["response_type"]);
+    assert_eq!("https://example.com/deploys", body["goto_location"]);
+
+    let response_url = {
+        let received = received.lock().unwrap();
+        assert_eq!(1, received.len());
+        let params = &received[0];
+        assert_eq!("/deploy", params["command"]);
+        assert_eq!("production", params["text"]);
+        assert_eq!("commander", params["user_name"]);
+        assert_eq!(encode_mm_id(fx.channel_id), params["channel_id"]);
+        assert!(!params["token"].is_empty());
+        params["response_url"].clone()
+    };
+
+    // In-channel responses are posted as the caller
+    let posted: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM posts WHERE message = 'deploying production' AND user_id = $1",
+    )
+    .bind(fx.user_id)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(1, posted);
+
+    // GET commands receive the payload as query parameters
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/commands/execute", &app.address))
+        .header("Authorization", &auth)
+        .json(&serde_json::json!({
+            "command": "/peek",
+            "channel_id": encode_mm_id(fx.channel_id),
+        }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = res.json().await.unwrap();
+    assert_eq!("ephemeral", body["response_type"]);
+    assert_eq!("/peek", received.lock().unwrap()[1]["command"]);
+
+    // Delayed response through the response URL
+    let res = app
+        .api_client
+        .post(format!("{}{}", &app.address, response_url))
+        .json(&serde_json::json!({
+            "response_type": "in_channel",
+            "text": "deploy finished"
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+
+    let finished: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM posts WHERE message = 'deploy finished'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(1, finished);
+
+    // Response URLs stop working after a few uses
+    for _ in 0..4 {
+        app.api_client
+            .post(format!("{}{}", &app.address, response_url))
+            .json(&serde_json::json!({ "text": "progress" }))
+            .send()
+            .await
+            .unwrap();
+    }
+    let res = app
+        .api_client
+        .post(format!("{}{}", &app.address, response_url))
+        .json(&serde_json::json!({ "text": "one too many" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(400, res.status().as_u16());
+}
@@ -0,0 +1,227 @@
+use std::time::Duration;
+
+use crate::common::{setup_channel_member, spawn_app, Fixture, TestApp};
+use serde_json::{json, Value};
+
+mod common;
+
+async fn upload(app: &TestApp, fx: &Fixture, name: &str, data: &[u8]) -> String {
+    let part = reqwest::multipart::Part::bytes(data.to_vec())
+        .file_name(name.to_string())
+        .mime_str("text/html")
+        .unwrap();
+    let form = reqwest::multipart::Form::new().part("file", part);
+
+    let res = app
+        .api_client
+        .post(format!(
+            "{}/api/v1/files?channel_id={}",
+            &app.address, fx.channel_id
+        ))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    let body: Value = res.json().await.unwrap();
+    body["id"].as_str().unwrap().to_string()
+}
+
+async fn file_backend(app: &TestApp, file_id: &str) -> String {
+    let res = app
+        .api_client
+        .get(format!("{}/api/v1/files/{}", &app.address, file_id))
+        .header("Authorization", format!("Bearer {}", app_token(app).await))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = res.json().await.unwrap();
+    body["backend"].as_str().unwrap().to_string()
+}
+
+async fn app_token(app: &TestApp) -> String {
+    let res = app
+        .api_client
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&json!({ "email": "storage_user@example.com", "password": "Password123!" }))
+        .send()
+        .await
+        .unwrap();
+    let body: Value = res.json().await.unwrap();
+    body["token"].as_str().unwrap().to_string()
+}
+
+async fn start_migration(
+    app: &TestApp,
+    token: &str,
+    source: &str,
+    target: &str,
+) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/api/v1/admin/storage/migrations", &app.address))
+        .header("Authorization", format!("Bearer {}", token))
+        .json(&json!({ "source": source, "target": target }))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn local_files_are_served_through_signed_links() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "storage_user").await;
+
+    let file_id = upload(&app, &fx, "page.html", b"<script>alert(1)</script>").await;
+    assert_eq!("local", file_backend(&app, &file_id).await);
+
+    let res = app
+        .api_client
+        .get(format!(
+            "{}/api/v1/files/{}/download",
+            &app.address, file_id
+        ))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    let body: Value = res.json().await.unwrap();
+    let url = body["url"].as_str().unwrap().to_string();
+    assert!(url.starts_with("/api/v1/storage/"));
+
+    // The link is the only credential, and uploads cannot script the origin
+    let res = app
+        .api_client
+        .get(format!("{}{}", &app.address, url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    assert_eq!(
+        Some("sandbox"),
+        res.headers()
+            .get("content-security-policy")
+            .map(|v| v.to_str().unwrap())
+    );
+    assert_eq!("<script>alert(1)</script>", res.text().await.unwrap());
+
+    let tampered = url.replace("/storage/files/", "/storage/files/other/");
+    let res = app
+        .api_client
+        .get(format!("{}{}", &app.address, tampered))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(403, res.status().as_u16());
+
+    // Presigned uploads accept exactly the signed key
+    let expires = chrono::Utc::now().timestamp() + 60;
+    let signature =
+        rustchat::storage::sign_url(&app.state.encryption_key, "PUT", "uploads/a.txt", expires);
+    let res = app
+        .api_client
+        .put(format!(
+            "{}/api/v1/storage/uploads/a.txt?expires={}&signature={}",
+            &app.address, expires, signature
+        ))
+        .body("uploaded")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    let res = app
+        .api_client
+        .put(format!(
+            "{}/api/v1/storage/uploads/b.txt?expires={}&signature={}",
+            &app.address, expires, signature
+        ))
+        .body("uploaded")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(403, res.status().as_u16());
+
+    // They are held to the site's file size limit
+    sqlx::query(
+        "UPDATE server_config SET site = jsonb_set(site, '{max_file_size_mb}', '1') WHERE id = 'default'",
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let signature =
+        rustchat::storage::sign_url(&app.state.encryption_key, "PUT", "uploads/big.bin", expires);
+    let put_big = |size: usize| {
+        app.api_client
+            .put(format!(
+                "{}/api/v1/storage/uploads/big.bin?expires={}&signature={}",
+                &app.address, expires, signature
+            ))
+            .body(vec![0u8; size])
+            .send()
+    };
+    assert_eq!(
+        413,
+        put_big(1024 * 1024 + 1).await.unwrap().status().as_u16()
+    );
+    assert_eq!(200, put_big(1024 * 1024).await.unwrap().status().as_u16());
+}
+
+#[tokio::test]
+async fn admins_migrate_files_between_backends() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "storage_user").await;
+    let file_id = upload(&app, &fx, "notes.html", b"notes").await;
+
+    let res = start_migration(&app, &fx.token, "local", "s3").await;
+    assert_eq!(403, res.status().as_u16());
+
+    sqlx::query("UPDATE users SET role = 'system_admin' WHERE id = $1")
+        .bind(fx.user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let token = app_token(&app).await;
+
+    let res = start_migration(&app, &token, "local", "local").await;
+    assert_eq!(400, res.status().as_u16());
+    let res = start_migration(&app, &token, "local", "ftp").await;
+    assert_eq!(400, res.status().as_u16());
+
+    let res = start_migration(&app, &token, "local", "s3").await;
+    assert_eq!(200, res.status().as_u16());
+    let run: Value = res.json().await.unwrap();
+    assert_eq!("running", run["status"]);
+    assert_eq!(1, run["files_total"]);
+
+    let mut run = run;
+    for _ in 0..100 {
+        let res = app
+            .api_client
+            .get(format!("{}/api/v1/admin/storage/migrations", &app.address))
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await
+            .unwrap();
+        let runs: Value = res.json().await.unwrap();
+        run = runs[0].clone();
+        if run["status"] != "running" {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+
+    // S3 may not be reachable here; either way the file's backend must match
+    match run["status"].as_str().unwrap() {
+        "success" => {
+            assert_eq!(1, run["files_copied"]);
+            assert_eq!("s3", file_backend(&app, &file_id).await);
+        }
+        "failed" => {
+            assert_eq!(1, run["files_failed"]);
+            assert_eq!("local", file_backend(&app, &file_id).await);
+        }
+        status => panic!("migration did not finish: {}", status),
+    }
+    assert!(run["finished_at"].is_string());
+}
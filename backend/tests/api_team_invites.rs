@@ -0,0 +1,280 @@
+use crate::common::{setup_channel_member, spawn_app, spawn_smtp_sink, SinkMessage, TestApp};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+mod common;
+
+async fn send(
+    app: &TestApp,
+    method: reqwest::Method,
+    path: &str,
+    token: &str,
+    body: Option<serde_json::Value>,
+) -> reqwest::Response {
+    let mut req = app
+        .api_client
+        .request(method, format!("{}{}", &app.address, path))
+        .header("Authorization", format!("Bearer {}", token));
+    if let Some(body) = body {
+        req = req.json(&body);
+    }
+    req.send().await.unwrap()
+}
+
+async fn configure_email(app: &TestApp) -> Arc<Mutex<Vec<SinkMessage>>> {
+    let (smtp_port, sink) = spawn_smtp_sink().await;
+    sqlx::query(
+        r#"
+        UPDATE server_config
+        SET email = email || $1::jsonb,
+            site = site || '{"site_url": "https://chat.example.com"}'::jsonb
+        WHERE id = 'default'
+        "#,
+    )
+    .bind(json!({
+        "smtp_host": "127.0.0.1",
+        "smtp_port": smtp_port,
+        "smtp_security": "none",
+        "from_address": "noreply@example.com"
+    }))
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    sink
+}
+
+async fn set_registration_open(app: &TestApp, open: bool) {
+    sqlx::query(
+        r#"
+        UPDATE server_config
+        SET authentication = authentication || jsonb_build_object('allow_registration', $1::boolean)
+        WHERE id = 'default'
+        "#,
+    )
+    .bind(open)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn make_team_admin(app: &TestApp, team_id: Uuid, user_id: Uuid) {
+    sqlx::query("UPDATE team_members SET role = 'admin' WHERE team_id = $1 AND user_id = $2")
+        .bind(team_id)
+        .bind(user_id)
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+}
+
+async fn register(
+    app: &TestApp,
+    username: &str,
+    email: &str,
+    extra: serde_json::Value,
+) -> reqwest::Response {
+    let mut body = json!({
+        "username": username,
+        "email": email,
+        "password": "Password123!",
+        "display_name": username
+    });
+    body.as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    app.api_client
+        .post(format!("{}/api/v1/auth/register", &app.address))
+        .json(&body)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn is_member(app: &TestApp, team_id: Uuid, username: &str) -> bool {
+    sqlx::query_scalar(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM team_members tm JOIN users u ON u.id = tm.user_id
+            WHERE tm.team_id = $1 AND u.username = $2
+        )
+        "#,
+    )
+    .bind(team_id)
+    .bind(username)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+}
+
+/// Pull the invite token out of the link in an email
+fn token_from(message: &SinkMessage) -> String {
+    // Undo the quoted-printable soft line breaks and escaped '='
+    let data = message.data.replace("=\n", "").replace("=3D", "=");
+    let start = data.find("?t=").expect("no link in email") + "?t=".len();
+    data[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_hexdigit())
+        .collect()
+}
+
+#[tokio::test]
+async fn emailed_invites_sign_people_up_when_registration_is_closed() {
+    let app = spawn_app().await;
+    let sink = configure_email(&app).await;
+    let fx = setup_channel_member(&app, "alice").await;
+    set_registration_open(&app, false).await;
+
+    let res = register(&app, "mallory", "mallory@example.com", json!({})).await;
+    assert_eq!(403, res.status().as_u16());
+
+    let invite = format!("/api/v1/teams/{}/invite/email", fx.team_id);
+    let emails = json!({ "emails": ["carol@example.com"] });
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &invite,
+        &fx.token,
+        Some(emails.clone()),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+
+    make_team_admin(&app, fx.team_id, fx.user_id).await;
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &invite,
+        &fx.token,
+        Some(emails),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+    let token = {
+        let sink = sink.lock().unwrap();
+        assert_eq!(1, sink.len());
+        assert_eq!(vec!["carol@example.com".to_string()], sink[0].to);
+        assert!(sink[0]
+            .data
+            .contains("Subject: [RustChat] alice invited you to join Test Team"));
+        token_from(&sink[0])
+    };
+
+    let res = register(
+        &app,
+        "carol",
+        "carol@example.com",
+        json!({ "invite_token": token }),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert!(body["token"].is_string());
+    assert!(is_member(&app, fx.team_id, "carol").await);
+    // Public channels come with the team
+    let in_channel: bool = sqlx::query_scalar(
+        "SELECT EXISTS (SELECT 1 FROM channel_members cm JOIN users u ON u.id = cm.user_id WHERE cm.channel_id = $1 AND u.username = 'carol')",
+    )
+    .bind(fx.channel_id)
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert!(in_channel);
+
+    // Each invitation works once
+    let res = register(
+        &app,
+        "carl",
+        "carl@example.com",
+        json!({ "invite_token": token }),
+    )
+    .await;
+    assert_eq!(400, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn invite_links_respect_allowed_domains_and_regeneration() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "alice").await;
+    make_team_admin(&app, fx.team_id, fx.user_id).await;
+    let team = format!("/api/v1/teams/{}", fx.team_id);
+
+    let res = send(
+        &app,
+        reqwest::Method::GET,
+        &format!("{}/invite", team),
+        &fx.token,
+        None,
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+    let body: serde_json::Value = res.json().await.unwrap();
+    let invite_id = body["invite_id"].as_str().unwrap().to_string();
+
+    // The invite ID is not part of the team itself
+    let res = send(&app, reqwest::Method::GET, &team, &fx.token, None).await;
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert!(body.get("invite_id").is_none());
+
+    let res = send(
+        &app,
+        reqwest::Method::PUT,
+        &team,
+        &fx.token,
+        Some(json!({ "allowed_domains": "Example.com" })),
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!("example.com", body["allowed_domains"]);
+
+    let res = app
+        .api_client
+        .get(format!(
+            "{}/api/v4/teams/invite/{}",
+            &app.address, invite_id
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(200, res.status().as_u16());
+    let info: serde_json::Value = res.json().await.unwrap();
+    assert_eq!("test-team", info["name"]);
+
+    let res = register(
+        &app,
+        "olga",
+        "olga@other.org",
+        json!({ "invite_id": invite_id }),
+    )
+    .await;
+    assert_eq!(403, res.status().as_u16());
+
+    // Existing users join through the v4 endpoint
+    let res = register(&app, "dave", "dave@example.com", json!({})).await;
+    let body: serde_json::Value = res.json().await.unwrap();
+    let dave = body["token"].as_str().unwrap().to_string();
+    let join = format!("/api/v4/teams/members/invite?invite_id={}", invite_id);
+    let res = send(&app, reqwest::Method::POST, &join, &dave, None).await;
+    assert_eq!(200, res.status().as_u16());
+    let member: serde_json::Value = res.json().await.unwrap();
+    assert!(member["team_id"].is_string());
+    assert!(is_member(&app, fx.team_id, "dave").await);
+
+    let res = send(
+        &app,
+        reqwest::Method::POST,
+        &format!("{}/invite/regenerate", team),
+        &fx.token,
+        None,
+    )
+    .await;
+    assert_eq!(200, res.status().as_u16());
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_ne!(invite_id, body["invite_id"].as_str().unwrap());
+
+    let res = register(&app, "erin", "erin@example.com", json!({})).await;
+    let body: serde_json::Value = res.json().await.unwrap();
+    let erin = body["token"].as_str().unwrap().to_string();
+    let res = send(&app, reqwest::Method::POST, &join, &erin, None).await;
+    assert_eq!(400, res.status().as_u16());
+}
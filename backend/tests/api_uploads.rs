@@ -0,0 +1,209 @@
+use crate::common::{setup_channel_member, spawn_app, Fixture, TestApp};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+mod common;
+
+/// Larger than one part, so uploads are written in several
+fn test_data(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 % 251) as u8).collect()
+}
+
+async fn set_max_file_size_mb(app: &TestApp, mb: i32) {
+    sqlx::query(
+        "UPDATE server_config SET site = jsonb_set(site, '{max_file_size_mb}', to_jsonb($1)) WHERE id = 'default'",
+    )
+    .bind(mb)
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn upload_files(app: &TestApp, fx: &Fixture, data: Vec<u8>) -> reqwest::Response {
+    let part = reqwest::multipart::Part::bytes(data)
+        .file_name("data.bin")
+        .mime_str("application/octet-stream")
+        .unwrap();
+    let form = reqwest::multipart::Form::new()
+        .text("channel_id", fx.channel_id.to_string())
+        .part("files", part);
+
+    app.api_client
+        .post(format!("{}/api/v4/files", &app.address))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn stored_file(app: &TestApp, id: Uuid) -> (i64, String, Option<Uuid>, Vec<u8>) {
+    let (size, sha256, channel_id, key, backend): (i64, String, Option<Uuid>, String, String) =
+        sqlx::query_as("SELECT size, sha256, channel_id, key, backend FROM files WHERE id = $1")
+            .bind(id)
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    let data = app
+        .state
+        .storage
+        .backend(&backend)
+        .unwrap()
+        .download(&key)
+        .await
+        .unwrap();
+    (size, sha256, channel_id, data)
+}
+
+async fn append(app: &TestApp, fx: &Fixture, upload_id: &str, data: Vec<u8>) -> reqwest::Response {
+    app.api_client
+        .post(format!("{}/api/v4/uploads/{}", &app.address, upload_id))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .body(data)
+        .send()
+        .await
+        .unwrap()
+}
+
+async fn get_upload(app: &TestApp, fx: &Fixture, upload_id: &str) -> reqwest::Response {
+    app.api_client
+        .get(format!("{}/api/v4/uploads/{}", &app.address, upload_id))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn large_files_are_streamed_and_limited() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "stream_user").await;
+
+    let data = test_data(9 * 1024 * 1024 + 123);
+    let res = upload_files(&app, &fx, data.clone()).await;
+    assert_eq!(200, res.status().as_u16());
+    let body: Value = res.json().await.unwrap();
+    let info = &body["file_infos"][0];
+    assert_eq!(data.len() as i64, info["size"]);
+
+    let id =
+        rustchat::mattermost_compat::id::parse_mm_or_uuid(info["id"].as_str().unwrap()).unwrap();
+    let (size, sha256, channel_id, stored) = stored_file(&app, id).await;
+    assert_eq!(data.len() as i64, size);
+    assert_eq!(hex::encode(Sha256::digest(&data)), sha256);
+    assert_eq!(Some(fx.channel_id), channel_id);
+    assert!(stored == data);
+
+    set_max_file_size_mb(&app, 1).await;
+    let res = upload_files(&app, &fx, test_data(1024 * 1024 + 1)).await;
+    assert_eq!(413, res.status().as_u16());
+    let res = upload_files(&app, &fx, test_data(1024 * 1024)).await;
+    assert_eq!(200, res.status().as_u16());
+}
+
+#[tokio::test]
+async fn upload_sessions_resume_until_complete() {
+    let app = spawn_app().await;
+    let fx = setup_channel_member(&app, "session_user").await;
+    let data = test_data(10 * 1024 * 1024);
+
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/uploads", &app.address))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .json(&json!({
+            "channel_id": fx.channel_id.to_string(),
+            "filename": "video.mp4",
+            "file_size": data.len(),
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(201, res.status().as_u16());
+    let session: Value = res.json().await.unwrap();
+    let upload_id = session["id"].as_str().unwrap().to_string();
+    assert_eq!("attachment", session["type"]);
+    assert_eq!(0, session["file_offset"]);
+
+    // Sent in uneven pieces, as a client resuming after drops would
+    let mut offset = 0;
+    for end in [3, 1000, 9 * 1024 * 1024] {
+        let res = append(&app, &fx, &upload_id, data[offset..end].to_vec()).await;
+        assert_eq!(204, res.status().as_u16());
+        offset = end;
+
+        let session: Value = get_upload(&app, &fx, &upload_id)
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(offset as i64, session["file_offset"]);
+    }
+
+    let res = app
+        .api_client
+        .get(format!("{}/api/v4/users/me/uploads", &app.address))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .send()
+        .await
+        .unwrap();
+    let sessions: Value = res.json().await.unwrap();
+    assert_eq!(upload_id, sessions[0]["id"].as_str().unwrap());
+
+    // Nobody else can see or append to the session
+    let other = setup_channel_member(&app, "other_session_user").await;
+    assert_eq!(
+        404,
+        get_upload(&app, &other, &upload_id).await.status().as_u16()
+    );
+    let res = append(&app, &other, &upload_id, vec![0]).await;
+    assert_eq!(404, res.status().as_u16());
+
+    // More than the declared size is refused, keeping what fit
+    let mut too_much = data[offset..].to_vec();
+    too_much.push(0);
+    let res = append(&app, &fx, &upload_id, too_much).await;
+    assert_eq!(413, res.status().as_u16());
+    let session: Value = get_upload(&app, &fx, &upload_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    let offset = session["file_offset"].as_i64().unwrap() as usize;
+    assert!(offset < data.len());
+
+    let res = append(&app, &fx, &upload_id, data[offset..].to_vec()).await;
+    assert_eq!(201, res.status().as_u16());
+    let info: Value = res.json().await.unwrap();
+    assert_eq!(upload_id, info["id"].as_str().unwrap());
+    assert_eq!(data.len() as i64, info["size"]);
+    assert_eq!("mp4", info["extension"]);
+
+    let id = rustchat::mattermost_compat::id::parse_mm_or_uuid(&upload_id).unwrap();
+    let (_, sha256, channel_id, stored) = stored_file(&app, id).await;
+    assert_eq!(hex::encode(Sha256::digest(&data)), sha256);
+    assert_eq!(Some(fx.channel_id), channel_id);
+    assert!(stored == data);
+
+    assert_eq!(
+        404,
+        get_upload(&app, &fx, &upload_id).await.status().as_u16()
+    );
+
+    // Sessions are checked against the size limit up front
+    set_max_file_size_mb(&app, 1).await;
+    let res = app
+        .api_client
+        .post(format!("{}/api/v4/uploads", &app.address))
+        .header("Authorization", format!("Bearer {}", fx.token))
+        .json(&json!({
+            "channel_id": fx.channel_id.to_string(),
+            "filename": "big.bin",
+            "file_size": 2 * 1024 * 1024,
+        }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(413, res.status().as_u16());
+}
@@ -2,7 +2,11 @@ use axum::{
     body::Body,
     http::{Request, StatusCode},
 };
-use rustchat::{api::router, realtime::WsHub, storage::S3Client};
+use rustchat::{
+    api::router,
+    realtime::WsHub,
+    storage::{S3Client, Storage},
+};
 use sqlx::postgres::PgPoolOptions;
 use tower::ServiceExt;
 
@@ -33,8 +37,9 @@ async fn config_client_returns_diagnostic_id() {
         redis,
         "secret".to_string(),
         1,
+        "encryption-key".to_string(),
         ws_hub,
-        s3_client
+        Storage::new(s3_client)
     );
 
     // 3. Make request
@@ -88,8 +93,9 @@ async fn license_client_returns_boolean() {
         redis,
         "secret".to_string(),
         1,
+        "encryption-key".to_string(),
         ws_hub,
-        s3_client
+        Storage::new(s3_client)
     );
 
     // 3. Make request
@@ -0,0 +1,104 @@
+//! A minimal clamd for malware scanning tests
+//!
+//! Answers `zVERSION` and `zINSTREAM` over TCP or a Unix socket, finding
+//! the EICAR test signature in any content that contains it.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UnixListener};
+
+/// The standard antivirus test file
+pub const EICAR: &[u8] = br"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+pub const VERSION: &str = "ClamAV 1.4.1/27500/Mock";
+
+pub struct MockClamd {
+    /// Address to put in the scanning config
+    pub address: String,
+    /// How many streams were scanned
+    pub scans: Arc<AtomicUsize>,
+}
+
+pub async fn spawn_mock_clamd() -> MockClamd {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let scans = Arc::new(AtomicUsize::new(0));
+
+    let shared = scans.clone();
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                return;
+            };
+            tokio::spawn(serve(stream, shared.clone()));
+        }
+    });
+
+    MockClamd { address, scans }
+}
+
+pub async fn spawn_mock_clamd_unix(path: &Path) -> MockClamd {
+    let listener = UnixListener::bind(path).unwrap();
+    let scans = Arc::new(AtomicUsize::new(0));
+
+    let shared = scans.clone();
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                return;
+            };
+            tokio::spawn(serve(stream, shared.clone()));
+        }
+    });
+
+    MockClamd {
+        address: format!("unix:{}", path.display()),
+        scans,
+    }
+}
+
+async fn serve<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, scans: Arc<AtomicUsize>) {
+    let mut command = Vec::new();
+    loop {
+        let Ok(byte) = stream.read_u8().await else {
+            return;
+        };
+        if byte == 0 {
+            break;
+        }
+        command.push(byte);
+    }
+
+    let reply = match command.as_slice() {
+        b"zVERSION" => VERSION.to_string(),
+        b"zINSTREAM" => {
+            let mut content = Vec::new();
+            loop {
+                let Ok(len) = stream.read_u32().await else {
+                    return;
+                };
+                if len == 0 {
+                    break;
+                }
+                let mut chunk = vec![0; len as usize];
+                if stream.read_exact(&mut chunk).await.is_err() {
+                    return;
+                }
+                content.extend_from_slice(&chunk);
+            }
+            scans.fetch_add(1, Ordering::SeqCst);
+            if content.windows(EICAR.len()).any(|window| window == EICAR) {
+                "stream: Eicar-Signature FOUND".to_string()
+            } else {
+                "stream: OK".to_string()
+            }
+        }
+        _ => "UNKNOWN COMMAND".to_string(),
+    };
+
+    let _ = stream.write_all(reply.as_bytes()).await;
+    let _ = stream.write_all(b"\0").await;
+}
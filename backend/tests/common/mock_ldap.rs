@@ -0,0 +1,339 @@
+//! A minimal LDAP server for directory login and sync tests
+//!
+//! Speaks just enough of the protocol for the backend: simple binds,
+//! searches with and/or/not/equality/presence filters, and unbind. Only the
+//! service account may search.
+
+use std::sync::{Arc, Mutex};
+
+use ldap3::asn1::{parse_tag, StructureTag, TagClass, PL};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use uuid::Uuid;
+
+pub const BASE_DN: &str = "dc=example,dc=com";
+pub const BIND_DN: &str = "cn=admin,dc=example,dc=com";
+pub const BIND_PASSWORD: &str = "directory-admin";
+
+const SUCCESS: u8 = 0;
+const NO_SUCH_OBJECT: u8 = 32;
+const INVALID_CREDENTIALS: u8 = 49;
+const INSUFFICIENT_ACCESS: u8 = 50;
+
+#[derive(Debug, Clone)]
+pub struct LdapEntry {
+    pub dn: String,
+    pub attrs: Vec<(String, Vec<String>)>,
+    pub password: Option<String>,
+}
+
+impl LdapEntry {
+    fn values(&self, name: &str) -> Option<&Vec<String>> {
+        self.attrs
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, values)| values)
+    }
+}
+
+pub struct MockLdap {
+    pub url: String,
+    pub directory: Arc<Mutex<Vec<LdapEntry>>>,
+}
+
+pub fn user_dn(uid: &str) -> String {
+    format!("uid={},ou=people,{}", uid, BASE_DN)
+}
+
+pub fn group_dn(cn: &str) -> String {
+    format!("cn={},ou=groups,{}", cn, BASE_DN)
+}
+
+impl MockLdap {
+    /// Add a person entry and return its `entryUUID`
+    pub fn add_user(&self, uid: &str, mail: &str, cn: &str, password: &str) -> String {
+        let id = Uuid::new_v4().to_string();
+        self.directory.lock().unwrap().push(LdapEntry {
+            dn: user_dn(uid),
+            attrs: vec![
+                ("objectClass".to_string(), vec!["person".to_string()]),
+                ("entryUUID".to_string(), vec![id.clone()]),
+                ("uid".to_string(), vec![uid.to_string()]),
+                ("mail".to_string(), vec![mail.to_string()]),
+                ("cn".to_string(), vec![cn.to_string()]),
+            ],
+            password: Some(password.to_string()),
+        });
+        id
+    }
+
+    /// Add a group whose members are the given uids
+    pub fn add_group(&self, cn: &str, member_uids: &[&str]) {
+        self.directory.lock().unwrap().push(LdapEntry {
+            dn: group_dn(cn),
+            attrs: vec![
+                ("objectClass".to_string(), vec!["groupOfNames".to_string()]),
+                ("cn".to_string(), vec![cn.to_string()]),
+                (
+                    "member".to_string(),
+                    member_uids.iter().map(|uid| user_dn(uid)).collect(),
+                ),
+            ],
+            password: None,
+        });
+    }
+
+    pub fn remove(&self, dn: &str) {
+        self.directory
+            .lock()
+            .unwrap()
+            .retain(|entry| !entry.dn.eq_ignore_ascii_case(dn));
+    }
+}
+
+pub async fn spawn_mock_ldap() -> MockLdap {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let directory = Arc::new(Mutex::new(vec![LdapEntry {
+        dn: BIND_DN.to_string(),
+        attrs: vec![(
+            "objectClass".to_string(),
+            vec!["organizationalRole".to_string()],
+        )],
+        password: Some(BIND_PASSWORD.to_string()),
+    }]));
+
+    let shared = directory.clone();
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                return;
+            };
+            tokio::spawn(serve(stream, shared.clone()));
+        }
+    });
+
+    MockLdap {
+        url: format!("ldap://127.0.0.1:{}", port),
+        directory,
+    }
+}
+
+async fn serve(mut stream: TcpStream, directory: Arc<Mutex<Vec<LdapEntry>>>) {
+    let mut buffer = Vec::new();
+    let mut bound_dn: Option<String> = None;
+
+    loop {
+        let mut chunk = [0u8; 4096];
+        match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+        }
+
+        while let Ok((rest, message)) = parse_tag(&buffer) {
+            let consumed = buffer.len() - rest.len();
+            let replies = handle(message, &directory, &mut bound_dn);
+            buffer.drain(..consumed);
+
+            let Some(replies) = replies else {
+                return;
+            };
+            for reply in replies {
+                if stream.write_all(&encode(&reply)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Replies to one message, or `None` to close the connection
+fn handle(
+    message: StructureTag,
+    directory: &Mutex<Vec<LdapEntry>>,
+    bound_dn: &mut Option<String>,
+) -> Option<Vec<StructureTag>> {
+    let mut parts = message.expect_constructed()?.into_iter();
+    let message_id = parts.next()?.expect_primitive()?;
+    let op = parts.next()?;
+    let envelope = |op: StructureTag| {
+        constructed(
+            TagClass::Universal,
+            16,
+            vec![primitive(TagClass::Universal, 2, message_id.clone()), op],
+        )
+    };
+
+    match (op.class, op.id) {
+        // BindRequest
+        (TagClass::Application, 0) => {
+            let mut fields = op.expect_constructed()?.into_iter().skip(1);
+            let name = text(fields.next()?)?;
+            let password = text(fields.next()?)?;
+            let ok = name.is_empty() && password.is_empty()
+                || directory.lock().unwrap().iter().any(|entry| {
+                    entry.dn.eq_ignore_ascii_case(&name)
+                        && entry.password.as_deref() == Some(password.as_str())
+                });
+            *bound_dn = ok.then_some(name);
+            let rc = if ok { SUCCESS } else { INVALID_CREDENTIALS };
+            Some(vec![envelope(result(1, rc))])
+        }
+        // UnbindRequest
+        (TagClass::Application, 2) => None,
+        // SearchRequest
+        (TagClass::Application, 3) => {
+            if !bound_dn
+                .as_deref()
+                .is_some_and(|dn| dn.eq_ignore_ascii_case(BIND_DN))
+            {
+                return Some(vec![envelope(result(5, INSUFFICIENT_ACCESS))]);
+            }
+
+            let fields = op.expect_constructed()?;
+            let base = text(fields[0].clone())?;
+            let scope = fields[1].clone().expect_primitive()?.first().copied()?;
+            let filter = &fields[6];
+
+            let entries = directory.lock().unwrap().clone();
+            if scope == 0 && !entries.iter().any(|e| e.dn.eq_ignore_ascii_case(&base)) {
+                return Some(vec![envelope(result(5, NO_SUCH_OBJECT))]);
+            }
+            let mut replies: Vec<StructureTag> = entries
+                .iter()
+                .filter(|entry| in_scope(&entry.dn, &base, scope) && matches(filter, entry))
+                .map(|entry| envelope(search_entry(entry)))
+                .collect();
+            replies.push(envelope(result(5, SUCCESS)));
+            Some(replies)
+        }
+        _ => Some(vec![]),
+    }
+}
+
+fn in_scope(dn: &str, base: &str, scope: u8) -> bool {
+    let dn = dn.to_lowercase();
+    let base = base.to_lowercase();
+    match scope {
+        0 => dn == base,
+        _ => dn == base || dn.ends_with(&format!(",{}", base)),
+    }
+}
+
+fn matches(filter: &StructureTag, entry: &LdapEntry) -> bool {
+    if filter.class != TagClass::Context {
+        return false;
+    }
+    match (filter.id, &filter.payload) {
+        (0, PL::C(filters)) => filters.iter().all(|f| matches(f, entry)),
+        (1, PL::C(filters)) => filters.iter().any(|f| matches(f, entry)),
+        (2, PL::C(filters)) => !filters.iter().all(|f| matches(f, entry)),
+        (3, PL::C(pair)) => {
+            let (Some(name), Some(value)) = (text(pair[0].clone()), text(pair[1].clone())) else {
+                return false;
+            };
+            entry
+                .values(&name)
+                .is_some_and(|values| values.iter().any(|v| v.eq_ignore_ascii_case(&value)))
+        }
+        (7, PL::P(name)) => entry.values(&String::from_utf8_lossy(name)).is_some(),
+        _ => false,
+    }
+}
+
+fn text(tag: StructureTag) -> Option<String> {
+    String::from_utf8(tag.expect_primitive()?).ok()
+}
+
+fn primitive(class: TagClass, id: u64, bytes: Vec<u8>) -> StructureTag {
+    StructureTag {
+        class,
+        id,
+        payload: PL::P(bytes),
+    }
+}
+
+fn constructed(class: TagClass, id: u64, children: Vec<StructureTag>) -> StructureTag {
+    StructureTag {
+        class,
+        id,
+        payload: PL::C(children),
+    }
+}
+
+fn octets(value: &str) -> StructureTag {
+    primitive(TagClass::Universal, 4, value.as_bytes().to_vec())
+}
+
+/// LDAPResult under the given application tag
+fn result(op: u64, rc: u8) -> StructureTag {
+    constructed(
+        TagClass::Application,
+        op,
+        vec![
+            primitive(TagClass::Universal, 10, vec![rc]),
+            octets(""),
+            octets(""),
+        ],
+    )
+}
+
+fn search_entry(entry: &LdapEntry) -> StructureTag {
+    let attributes = entry
+        .attrs
+        .iter()
+        .map(|(name, values)| {
+            constructed(
+                TagClass::Universal,
+                16,
+                vec![
+                    octets(name),
+                    constructed(
+                        TagClass::Universal,
+                        17,
+                        values.iter().map(|v| octets(v)).collect(),
+                    ),
+                ],
+            )
+        })
+        .collect();
+
+    constructed(
+        TagClass::Application,
+        4,
+        vec![
+            octets(&entry.dn),
+            constructed(TagClass::Universal, 16, attributes),
+        ],
+    )
+}
+
+/// BER encoding with definite lengths
+fn encode(tag: &StructureTag) -> Vec<u8> {
+    let class = match tag.class {
+        TagClass::Universal => 0x00,
+        TagClass::Application => 0x40,
+        TagClass::Context => 0x80,
+        TagClass::Private => 0xc0,
+    };
+    let (form, content) = match &tag.payload {
+        PL::P(bytes) => (0x00, bytes.clone()),
+        PL::C(children) => (0x20, children.iter().flat_map(encode).collect()),
+    };
+
+    let mut out = vec![class | form | tag.id as u8];
+    if content.len() < 0x80 {
+        out.push(content.len() as u8);
+    } else {
+        let length: Vec<u8> = content
+            .len()
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|b| *b == 0)
+            .collect();
+        out.push(0x80 | length.len() as u8);
+        out.extend(length);
+    }
+    out.extend(content);
+    out
+}
@@ -1,8 +1,23 @@
 use once_cell::sync::Lazy;
-use rustchat::{api, realtime::WsHub, storage::S3Client};
+use rustchat::{
+    api,
+    realtime::WsHub,
+    storage::{LocalStorage, S3Client, Storage},
+};
+use serde_json::Value;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use uuid::Uuid;
 
+#[allow(dead_code)]
+pub mod mock_clamd;
+#[allow(dead_code)]
+pub mod mock_idp;
+#[allow(dead_code)]
+pub mod mock_ldap;
+#[allow(dead_code)]
+pub mod mock_saml_idp;
+
 // Ensure tracing is initialized only once
 static TRACING: Lazy<()> = Lazy::new(|| {
     let log_level = "info";
@@ -15,10 +30,20 @@ pub struct TestApp {
     pub address: String,
     #[allow(dead_code)]
     pub db_pool: PgPool,
+    #[allow(dead_code)]
+    pub state: api::AppState,
     pub api_client: reqwest::Client,
+    /// Address `api_client` connects from
+    #[allow(dead_code)]
+    pub client_ip: IpAddr,
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|state| state).await
+}
+
+/// Start a server whose state is adjusted by `configure`
+pub async fn spawn_app_with(configure: impl FnOnce(api::AppState) -> api::AppState) -> TestApp {
     Lazy::force(&TRACING);
 
     let db_url = std::env::var("RUSTCHAT_DATABASE_URL")
@@ -47,6 +72,12 @@ pub async fn spawn_app() -> TestApp {
         "us-east-1".to_string(),
     );
 
+    // New files go to a scratch directory, so uploads work without MinIO
+    let encryption_key = Uuid::new_v4().to_string();
+    let storage_dir = std::env::temp_dir().join(format!("rustchat-test-{}", Uuid::new_v4()));
+    let storage =
+        Storage::new(LocalStorage::new(storage_dir, "", &encryption_key)).with_backend(s3_client);
+
     let jwt_secret = Uuid::new_v4().to_string();
     let jwt_expiry_hours = 1;
 
@@ -56,31 +87,54 @@ pub async fn spawn_app() -> TestApp {
         .create_pool(Some(deadpool_redis::Runtime::Tokio1))
         .expect("Failed to create Redis pool");
 
-    let app = api::router(
+    let state = configure(api::AppState::new(
         db_pool.clone(),
         redis_pool,
         jwt_secret,
         jwt_expiry_hours,
+        encryption_key,
         ws_hub,
-        s3_client,
-    );
+        storage,
+    ));
+    let app = api::router_with_state(state.clone());
 
-    let server = axum::serve(listener, app);
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    );
     tokio::spawn(async move {
         server.await.expect("Failed to run server");
     });
 
+    let client_ip = random_loopback_ip();
     TestApp {
         address,
         db_pool,
-        api_client: reqwest::Client::builder()
-            .redirect(reqwest::redirect::Policy::none())
-            .cookie_store(true)
-            .build()
-            .unwrap(),
+        state,
+        api_client: client_from(client_ip),
+        client_ip,
     }
 }
 
+/// A loopback address no other test is likely to use
+///
+/// Failed logins and rate limits count per client address, so tests each
+/// connect from their own.
+pub fn random_loopback_ip() -> IpAddr {
+    let bytes = Uuid::new_v4().into_bytes();
+    IpAddr::V4(Ipv4Addr::new(127, bytes[0], bytes[1], bytes[2].max(2)))
+}
+
+/// A client that connects from `ip`, which must be a local address
+pub fn client_from(ip: IpAddr) -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .cookie_store(true)
+        .local_address(ip)
+        .build()
+        .unwrap()
+}
+
 async fn configure_database(database_url: &str) -> PgPool {
     let random_db_name = Uuid::new_v4().to_string();
 
@@ -112,3 +166,223 @@ async fn configure_database(database_url: &str) -> PgPool {
 
     pool
 }
+
+/// A logged-in user who is a member of a fresh team and channel
+#[allow(dead_code)]
+pub struct Fixture {
+    pub token: String,
+    pub user_id: Uuid,
+    pub team_id: Uuid,
+    pub channel_id: Uuid,
+}
+
+#[allow(dead_code)]
+pub async fn setup_channel_member(app: &TestApp, username: &str) -> Fixture {
+    app.api_client
+        .post(format!("{}/api/v1/auth/register", &app.address))
+        .json(&serde_json::json!({
+            "username": username,
+            "email": format!("{}@example.com", username),
+            "password": "Password123!",
+            "display_name": username
+        }))
+        .send()
+        .await
+        .expect("Failed to register");
+
+    let login: Value = app
+        .api_client
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&serde_json::json!({
+            "email": format!("{}@example.com", username),
+            "password": "Password123!"
+        }))
+        .send()
+        .await
+        .expect("Failed to login")
+        .json()
+        .await
+        .unwrap();
+
+    let token = login["token"].as_str().unwrap().to_string();
+    let user_id = Uuid::parse_str(login["user"]["id"].as_str().unwrap()).unwrap();
+
+    let org_id = Uuid::new_v4();
+    sqlx::query("INSERT INTO organizations (id, name) VALUES ($1, 'Test Org')")
+        .bind(org_id)
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to create organization");
+
+    let team_id = Uuid::new_v4();
+    sqlx::query("INSERT INTO teams (id, org_id, name, display_name) VALUES ($1, $2, 'test-team', 'Test Team')")
+        .bind(team_id)
+        .bind(org_id)
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to insert team");
+
+    let channel_id = Uuid::new_v4();
+    sqlx::query("INSERT INTO channels (id, team_id, name, display_name, type, creator_id) VALUES ($1, $2, 'test-channel', 'Test Channel', 'public', $3)")
+        .bind(channel_id)
+        .bind(team_id)
+        .bind(user_id)
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to insert channel");
+
+    sqlx::query("INSERT INTO team_members (team_id, user_id) VALUES ($1, $2)")
+        .bind(team_id)
+        .bind(user_id)
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to add team member");
+
+    sqlx::query("INSERT INTO channel_members (channel_id, user_id) VALUES ($1, $2)")
+        .bind(channel_id)
+        .bind(user_id)
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to add channel member");
+
+    Fixture {
+        token,
+        user_id,
+        team_id,
+        channel_id,
+    }
+}
+
+/// Register and log in another member of the fixture's team and channel
+#[allow(dead_code)]
+pub async fn add_channel_member(app: &TestApp, fx: &Fixture, username: &str) -> (String, Uuid) {
+    app.api_client
+        .post(format!("{}/api/v1/auth/register", &app.address))
+        .json(&serde_json::json!({
+            "username": username,
+            "email": format!("{}@example.com", username),
+            "password": "Password123!",
+            "display_name": username
+        }))
+        .send()
+        .await
+        .expect("Failed to register");
+    let login: Value = app
+        .api_client
+        .post(format!("{}/api/v1/auth/login", &app.address))
+        .json(&serde_json::json!({
+            "email": format!("{}@example.com", username),
+            "password": "Password123!"
+        }))
+        .send()
+        .await
+        .expect("Failed to login")
+        .json()
+        .await
+        .unwrap();
+    let user_id = Uuid::parse_str(login["user"]["id"].as_str().unwrap()).unwrap();
+
+    sqlx::query("INSERT INTO team_members (team_id, user_id) VALUES ($1, $2)")
+        .bind(fx.team_id)
+        .bind(user_id)
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to add team member");
+    sqlx::query("INSERT INTO channel_members (channel_id, user_id) VALUES ($1, $2)")
+        .bind(fx.channel_id)
+        .bind(user_id)
+        .execute(&app.db_pool)
+        .await
+        .expect("Failed to add channel member");
+
+    (login["token"].as_str().unwrap().to_string(), user_id)
+}
+
+/// A message received by the SMTP sink
+#[allow(dead_code)]
+#[derive(Debug, Clone)]
+pub struct SinkMessage {
+    pub from: String,
+    pub to: Vec<String>,
+    pub data: String,
+    /// The `AUTH` command the client sent on this connection, if any
+    pub auth: Option<String>,
+}
+
+/// Start a plain-text SMTP server that accepts and records every message
+///
+/// Returns the port it listens on.
+#[allow(dead_code)]
+pub async fn spawn_smtp_sink() -> (u16, std::sync::Arc<std::sync::Mutex<Vec<SinkMessage>>>) {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let messages = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let sink = messages.clone();
+    tokio::spawn(async move {
+        loop {
+            let Ok((stream, _)) = listener.accept().await else {
+                return;
+            };
+            let sink = sink.clone();
+            tokio::spawn(async move {
+                let (read, mut write) = stream.into_split();
+                let mut lines = BufReader::new(read).lines();
+                let mut current = SinkMessage {
+                    from: String::new(),
+                    to: Vec::new(),
+                    data: String::new(),
+                    auth: None,
+                };
+
+                write.write_all(b"220 sink ESMTP\r\n").await.ok();
+                while let Ok(Some(line)) = lines.next_line().await {
+                    let upper = line.to_ascii_uppercase();
+                    let reply: &[u8] = if upper.starts_with("EHLO") || upper.starts_with("HELO") {
+                        b"250-sink\r\n250 AUTH PLAIN LOGIN\r\n"
+                    } else if upper.starts_with("AUTH") {
+                        current.auth = Some(line.clone());
+                        b"235 2.7.0 Authentication successful\r\n"
+                    } else if upper.starts_with("MAIL FROM:") {
+                        current.from = line[10..].trim().trim_matches(['<', '>']).to_string();
+                        b"250 OK\r\n"
+                    } else if upper.starts_with("RCPT TO:") {
+                        current
+                            .to
+                            .push(line[8..].trim().trim_matches(['<', '>']).to_string());
+                        b"250 OK\r\n"
+                    } else if upper == "DATA" {
+                        write
+                            .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                            .await
+                            .ok();
+                        let mut data = String::new();
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            if line == "." {
+                                break;
+                            }
+                            data.push_str(line.strip_prefix('.').unwrap_or(&line));
+                            data.push('\n');
+                        }
+                        current.data = data;
+                        sink.lock().unwrap().push(current.clone());
+                        current.to.clear();
+                        b"250 OK queued\r\n"
+                    } else if upper == "QUIT" {
+                        write.write_all(b"221 Bye\r\n").await.ok();
+                        return;
+                    } else {
+                        b"250 OK\r\n"
+                    };
+                    if write.write_all(reply).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    (port, messages)
+}
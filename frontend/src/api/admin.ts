@@ -16,6 +16,7 @@ export interface EmailConfig {
     smtp_username: string;
     smtp_password_encrypted: string;
     smtp_tls: boolean;
+    smtp_security?: 'tls' | 'starttls' | 'none' | null;
     from_address: string;
     from_name: string;
 }
@@ -41,6 +42,9 @@ export interface AuthConfig {
     password_require_number: boolean;
     password_require_symbol: boolean;
     session_length_hours: number;
+    require_email_verification: boolean;
+    enable_personal_access_tokens: boolean;
+    enable_guest_accounts: boolean;
 }
 
 export interface IntegrationsConfig {
@@ -171,6 +175,8 @@ export const adminApi = {
         api.patch<AdminUser>(`/admin/users/${id}`, data),
     deactivateUser: (id: string) => api.post(`/admin/users/${id}/deactivate`),
     reactivateUser: (id: string) => api.post(`/admin/users/${id}/reactivate`),
+    promoteUser: (id: string) => api.post<AdminUser>(`/admin/users/${id}/promote`),
+    demoteUser: (id: string) => api.post<AdminUser>(`/admin/users/${id}/demote`),
     resetPassword: (id: string) => api.post(`/admin/users/${id}/reset-password`),
 
     // Audit Logs
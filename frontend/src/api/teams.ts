@@ -8,6 +8,7 @@ export interface Team {
     invite_id?: string
     is_public?: boolean
     allow_open_invite?: boolean
+    allowed_domains?: string
     created_at: string
 }
 
@@ -28,6 +29,7 @@ export interface CreateTeamRequest {
     description?: string
     is_public?: boolean
     allow_open_invite?: boolean
+    allowed_domains?: string
 }
 
 export const teamsApi = {
@@ -43,5 +45,9 @@ export const teamsApi = {
     addMember: (teamId: string, userId: string) => api.post(`/teams/${teamId}/members`, { user_id: userId }),
     removeMember: (teamId: string, userId: string) => api.delete(`/teams/${teamId}/members/${userId}`),
     getChannels: (teamId: string) => api.get(`/teams/${teamId}/channels`),
+    getInviteId: (id: string) => api.get<{ invite_id: string }>(`/teams/${id}/invite`),
+    regenerateInviteId: (id: string) => api.post<{ invite_id: string }>(`/teams/${id}/invite/regenerate`),
+    inviteByEmail: (id: string, emails: string[]) => api.post(`/teams/${id}/invite/email`, { emails }),
+    acceptInvite: (invite: { token?: string; invite_id?: string }) => api.post<TeamMember>('/teams/invite/accept', invite),
 }
 